@@ -24,30 +24,6 @@ fn read_infile(infile: &str, buf: &mut Vec<u8>) -> io::Result<()> {
     Ok(())
 }
 
-macro_rules! benchmark_single {
-    ($name:ident, $name_str:expr, $infile:expr) => {
-        fn $name(c: &mut Criterion) {
-            let mut bytes = Vec::new();
-            read_infile($infile, &mut bytes).unwrap();
-
-            let mut collapser = $name::Folder::default();
-
-            let mut group = c.benchmark_group($name_str);
-
-            group
-                .bench_with_input("collapse", &bytes, move |b, data| {
-                    b.iter(|| {
-                        let _result = collapser.collapse(data.as_slice(), io::sink());
-                    })
-                })
-                .throughput(Throughput::Bytes(bytes.len() as u64))
-                .sample_size(SAMPLE_SIZE);
-
-            group.finish();
-        }
-    };
-}
-
 macro_rules! benchmark_multi {
     ($name:ident, $name_str:expr, $infile:expr) => {
         fn $name(c: &mut Criterion) {
@@ -97,7 +73,7 @@ macro_rules! benchmark_multi {
 
 benchmark_multi!(dtrace, "dtrace", INFILE_DTRACE);
 benchmark_multi!(perf, "perf", INFILE_PERF);
-benchmark_single!(sample, "sample", INFILE_SAMPLE);
+benchmark_multi!(sample, "sample", INFILE_SAMPLE);
 
 criterion_group!(benches, dtrace, perf, sample);
 
@@ -0,0 +1,67 @@
+/*
+ * C declarations for the functions of inferno's `ffi` feature. See the documentation of
+ * inferno::ffi for what they do.
+ *
+ * Outputs are written to new buffers in `out` and `out_len`, which must be freed with
+ * inferno_free.
+ */
+
+#ifndef INFERNO_H
+#define INFERNO_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef enum InfernoStatus {
+    INFERNO_OK = 0,
+    INFERNO_INVALID_ARGUMENT = 1,
+    INFERNO_INVALID_INPUT = 2,
+    INFERNO_ERROR = 3,
+} InfernoStatus;
+
+void inferno_free(uint8_t *buf, size_t len);
+
+/* Collapse the output of a profiler into folded stack lines. */
+InfernoStatus inferno_collapse_bpftrace(const uint8_t *input, size_t len, uint8_t **out, size_t *out_len);
+InfernoStatus inferno_collapse_callgrind(const uint8_t *input, size_t len, uint8_t **out, size_t *out_len);
+InfernoStatus inferno_collapse_cpuprofile(const uint8_t *input, size_t len, uint8_t **out, size_t *out_len);
+InfernoStatus inferno_collapse_d3(const uint8_t *input, size_t len, uint8_t **out, size_t *out_len);
+InfernoStatus inferno_collapse_dtrace(const uint8_t *input, size_t len, uint8_t **out, size_t *out_len);
+InfernoStatus inferno_collapse_gecko(const uint8_t *input, size_t len, uint8_t **out, size_t *out_len);
+InfernoStatus inferno_collapse_ghcprof(const uint8_t *input, size_t len, uint8_t **out, size_t *out_len);
+InfernoStatus inferno_collapse_guess(const uint8_t *input, size_t len, uint8_t **out, size_t *out_len);
+InfernoStatus inferno_collapse_heaptrack(const uint8_t *input, size_t len, uint8_t **out, size_t *out_len);
+InfernoStatus inferno_collapse_jfr(const uint8_t *input, size_t len, uint8_t **out, size_t *out_len);
+InfernoStatus inferno_collapse_massif(const uint8_t *input, size_t len, uint8_t **out, size_t *out_len);
+InfernoStatus inferno_collapse_nettrace(const uint8_t *input, size_t len, uint8_t **out, size_t *out_len);
+InfernoStatus inferno_collapse_perf(const uint8_t *input, size_t len, uint8_t **out, size_t *out_len);
+InfernoStatus inferno_collapse_pmp(const uint8_t *input, size_t len, uint8_t **out, size_t *out_len);
+InfernoStatus inferno_collapse_pprof(const uint8_t *input, size_t len, uint8_t **out, size_t *out_len);
+InfernoStatus inferno_collapse_pyspy(const uint8_t *input, size_t len, uint8_t **out, size_t *out_len);
+InfernoStatus inferno_collapse_recursive(const uint8_t *input, size_t len, uint8_t **out, size_t *out_len);
+InfernoStatus inferno_collapse_sample(const uint8_t *input, size_t len, uint8_t **out, size_t *out_len);
+InfernoStatus inferno_collapse_simpleperf(const uint8_t *input, size_t len, uint8_t **out, size_t *out_len);
+InfernoStatus inferno_collapse_speedscope(const uint8_t *input, size_t len, uint8_t **out, size_t *out_len);
+InfernoStatus inferno_collapse_stackprof(const uint8_t *input, size_t len, uint8_t **out, size_t *out_len);
+InfernoStatus inferno_collapse_svg(const uint8_t *input, size_t len, uint8_t **out, size_t *out_len);
+InfernoStatus inferno_collapse_vsprof(const uint8_t *input, size_t len, uint8_t **out, size_t *out_len);
+InfernoStatus inferno_collapse_vtune(const uint8_t *input, size_t len, uint8_t **out, size_t *out_len);
+InfernoStatus inferno_collapse_wpa(const uint8_t *input, size_t len, uint8_t **out, size_t *out_len);
+InfernoStatus inferno_collapse_xctrace(const uint8_t *input, size_t len, uint8_t **out, size_t *out_len);
+
+/*
+ * Plot folded stack lines as a flame graph. `title` and `format` ("svg", "html", "png" or
+ * "json") may be NULL for their defaults.
+ */
+InfernoStatus inferno_flamegraph(const uint8_t *input, size_t len, const char *title,
+                                 const char *format, uint8_t **out, size_t *out_len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* INFERNO_H */
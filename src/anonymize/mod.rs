@@ -0,0 +1,260 @@
+use std::borrow::Cow;
+use std::fs::File;
+use std::hash::Hasher;
+use std::io::{self, prelude::*};
+use std::path::PathBuf;
+
+use ahash::AHashMap;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use siphasher::sip::SipHasher24;
+
+use crate::folded::{self, Header};
+use crate::rewrite::split_counts;
+
+const READER_CAPACITY: usize = 128 * 1024;
+
+// Names of functions, modules, types and files. Numbers, like addresses and line numbers, don't
+// match since they don't start with a letter. A name with an extension, like `config.rs`, that's
+// followed by a line number, a closing bracket or the end of the frame is a file name, and only
+// its stem is anonymized.
+static IDENTIFIER: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"\b([A-Za-z_][A-Za-z0-9_$]*)(?:(\.[A-Za-z][A-Za-z0-9]*)(:\d|[)\]]|$))?").unwrap()
+});
+
+// The directories of paths, up to the file name, like `/usr/lib/` or `C:\src\`.
+static DIRECTORIES: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?:[A-Za-z]:)?[\\/]?(?:[^\s\\/()\[\]<>;:]+[\\/])+").unwrap());
+
+// The annotations that collapsers add to frame names, like `_[k]` for kernel frames.
+static ANNOTATION: Lazy<Regex> = Lazy::new(|| Regex::new(r"_\[\w+\]$").unwrap());
+
+/// Configure how frames are anonymized.
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// The secret key that names are hashed with.
+    ///
+    /// A name is always hashed to the same pseudonym with the same key, so profiles that are
+    /// anonymized with the same key can still be compared or diffed. Without a key, pseudonyms of
+    /// common names can be found by hashing guesses, so set one when profiles leave your hands.
+    ///
+    /// Default is empty.
+    pub key: String,
+
+    /// Frames that start with any of these prefixes are kept as they are, except for their paths.
+    ///
+    /// This is useful for keeping the frames of public code, like `std::` or `libc`, readable.
+    ///
+    /// Default is empty.
+    pub keep: Vec<String>,
+
+    /// Keep the directories of file paths in frames.
+    ///
+    /// By default, only the file names of paths like `/home/me/app/src/main.rs` are kept, and their
+    /// stems are anonymized while their extensions are kept.
+    ///
+    /// Default is `false`.
+    pub keep_paths: bool,
+}
+
+/// Pseudonymizes the names in frames with a keyed hash.
+///
+/// Each name in a frame, like a function, module, type or file name, is replaced by a pseudonym
+/// like `_3f9a0c1be27d`, while the punctuation between names and numbers are kept. So `app::db::
+/// query` becomes `_6b0e2d14c3f8::_d1a95e0273bc::_0e47f1a9c6d2`, and frames of the same module
+/// still share a prefix.
+#[derive(Debug)]
+pub struct Anonymizer {
+    opt: Options,
+    keys: (u64, u64),
+    cache: AHashMap<String, String>,
+}
+
+impl Anonymizer {
+    /// Make an anonymizer with the given options.
+    pub fn new(opt: Options) -> Self {
+        let derive = |salt: u64| {
+            let mut hasher = SipHasher24::new_with_keys(salt, !salt);
+            hasher.write(opt.key.as_bytes());
+            hasher.finish()
+        };
+        let keys = (derive(0), derive(1));
+        Self {
+            opt,
+            keys,
+            cache: AHashMap::default(),
+        }
+    }
+
+    /// Anonymize the name of a frame.
+    pub fn frame(&mut self, frame: &str) -> String {
+        if let Some(anonymized) = self.cache.get(frame) {
+            return anonymized.clone();
+        }
+
+        let (name, annotation) = match ANNOTATION.find(frame) {
+            Some(m) => frame.split_at(m.start()),
+            None => (frame, ""),
+        };
+        let name = if self.opt.keep_paths {
+            Cow::Borrowed(name)
+        } else {
+            DIRECTORIES.replace_all(name, "")
+        };
+        let anonymized = if self.opt.keep.iter().any(|prefix| name.starts_with(prefix)) {
+            format!("{}{}", name, annotation)
+        } else {
+            let keys = self.keys;
+            let name = IDENTIFIER.replace_all(&name, |caps: &regex::Captures<'_>| {
+                let mut hasher = SipHasher24::new_with_keys(keys.0, keys.1);
+                match (caps.get(2), caps.get(3)) {
+                    // File stems are hashed apart from other names, so that `config.rs` doesn't
+                    // give away that it holds the `config` module.
+                    (Some(extension), Some(end)) => {
+                        hasher.write(b"file:");
+                        hasher.write(caps[1].as_bytes());
+                        let stem = hasher.finish() >> 16;
+                        format!("_{:012x}{}{}", stem, extension.as_str(), end.as_str())
+                    }
+                    _ => {
+                        hasher.write(caps[1].as_bytes());
+                        format!("_{:012x}", hasher.finish() >> 16)
+                    }
+                }
+            });
+            format!("{}{}", name, annotation)
+        };
+
+        self.cache.insert(frame.to_string(), anonymized.clone());
+        anonymized
+    }
+
+    /// Anonymize the frames of a stack.
+    pub fn stack(&mut self, stack: &str) -> String {
+        let frames: Vec<String> = stack.split(';').map(|frame| self.frame(frame)).collect();
+        frames.join(";")
+    }
+}
+
+/// Anonymize the frames of folded stack lines, and write the result to `writer`.
+///
+/// Each line is expected to have the following whitespace-separated fields:
+///
+///  - A semicolon-separated list of frame names (e.g., `main;foo;bar;baz`).
+///  - A sample count for the given stack, or two for differential inputs.
+///
+/// Lines that don't end in a sample count are left out, since they could hold anything. So are
+/// comment lines, except for the known keys of a [`Header`] at the start.
+pub fn from_lines<'a, I, W>(opt: Options, lines: I, mut writer: W) -> io::Result<()>
+where
+    I: IntoIterator<Item = &'a str>,
+    W: Write,
+{
+    let mut lines = lines.into_iter().peekable();
+    let mut comments = Vec::new();
+    while let Some(line) = lines.next_if(|line| folded::is_comment(line)) {
+        comments.push(line);
+    }
+    if let Some(header) = Header::parse(comments) {
+        header.write(&mut writer)?;
+    }
+
+    let mut anonymizer = Anonymizer::new(opt);
+    for line in lines {
+        if let Some((stack, counts)) = split_counts(line.trim_end()) {
+            writeln!(writer, "{}{}", anonymizer.stack(stack), counts)?;
+        }
+    }
+    Ok(())
+}
+
+/// Anonymize the frames of folded stack lines from a reader.
+///
+/// See [`from_lines`] for the expected format of each line.
+pub fn from_reader<R, W>(opt: Options, mut reader: R, writer: W) -> io::Result<()>
+where
+    R: Read,
+    W: Write,
+{
+    let mut input = String::new();
+    reader.read_to_string(&mut input)?;
+    from_lines(opt, input.lines(), writer)
+}
+
+/// Anonymize the frames of folded stack lines from files, and write the result to the provided
+/// `writer`.
+///
+/// If files is empty, or is just `-`, STDIN will be used as input.
+pub fn from_files<W: Write>(opt: Options, files: &[PathBuf], writer: W) -> io::Result<()> {
+    let mut input = String::new();
+    if files.is_empty() {
+        io::stdin().lock().read_to_string(&mut input)?;
+    }
+    let mut stdin_added = false;
+    for infile in files {
+        if infile.to_str() == Some("-") {
+            if !stdin_added {
+                io::stdin().lock().read_to_string(&mut input)?;
+                stdin_added = true;
+            }
+        } else {
+            let mut r = io::BufReader::with_capacity(READER_CAPACITY, File::open(infile)?);
+            r.read_to_string(&mut input)?;
+        }
+    }
+    from_lines(opt, input.lines(), writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn anonymizer(key: &str) -> Anonymizer {
+        Anonymizer::new(Options {
+            key: key.to_string(),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn keeps_structure() {
+        let mut anonymizer = anonymizer("secret");
+        let app = anonymizer.frame("app");
+        let db = anonymizer.frame("db");
+        assert_eq!(
+            anonymizer.frame("app::db::query<u8>"),
+            format!(
+                "{}::{}::{}<{}>",
+                app,
+                db,
+                anonymizer.frame("query"),
+                anonymizer.frame("u8")
+            )
+        );
+        assert!(anonymizer.frame("do_irq_[k]").ends_with("_[k]"));
+        assert!(anonymizer.frame("main+0x1f").ends_with("+0x1f"));
+    }
+
+    #[test]
+    fn strips_paths() {
+        let mut anonymizer = anonymizer("secret");
+        let main = anonymizer.frame("main.rs");
+        assert_eq!(anonymizer.frame("/home/me/app/src/main.rs"), main);
+        assert_eq!(anonymizer.frame(r"C:\src\app\main.rs"), main);
+        assert!(main.ends_with(".rs"));
+        assert_ne!(main, format!("{}.rs", anonymizer.frame("main")));
+    }
+
+    #[test]
+    fn keys_change_pseudonyms() {
+        assert_eq!(
+            anonymizer("a").frame("query"),
+            anonymizer("a").frame("query")
+        );
+        assert_ne!(
+            anonymizer("a").frame("query"),
+            anonymizer("b").frame("query")
+        );
+    }
+}
@@ -0,0 +1,97 @@
+use std::io;
+use std::path::PathBuf;
+
+use clap::{ArgAction, Parser};
+use env_logger::Env;
+use inferno::anonymize::{self, Options};
+use is_terminal::IsTerminal;
+use log::warn;
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "inferno-anonymize",
+    about,
+    after_help = "\
+Replaces the names of functions, modules, types and files in folded stack
+profiles with pseudonyms, and strips the directories of file paths, so that
+profiles of proprietary code can be shared.
+
+  $ inferno-anonymize --key \"$SECRET\" --keep std:: stacks.folded | inferno-flamegraph > shared.svg
+
+Names get the same pseudonyms with the same key, so profiles that are
+anonymized with the same key can still be diffed with inferno-diff-folded."
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Keep the directories of file paths
+    #[clap(long = "keep-paths")]
+    keep_paths: bool,
+
+    /// Silence all log output
+    #[clap(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
+    verbose: u8,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// Secret key to hash names with [env: INFERNO_ANONYMIZE_KEY]
+    #[clap(long = "key", value_name = "STRING")]
+    key: Option<String>,
+
+    /// Keep frames that start with this prefix as they are (may be repeated)
+    #[clap(long = "keep", value_name = "STRING")]
+    keep: Vec<String>,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// Folded stack files. With no PATH, or PATH is -, read STDIN.
+    #[clap(name = "PATH", value_parser)]
+    infiles: Vec<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> (Vec<PathBuf>, Options) {
+        let mut options = Options::default();
+        options.key = self
+            .key
+            .or_else(|| std::env::var("INFERNO_ANONYMIZE_KEY").ok())
+            .unwrap_or_default();
+        options.keep = self.keep;
+        options.keep_paths = self.keep_paths;
+        (self.infiles, options)
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt = Opt::parse();
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let (infiles, options) = opt.into_parts();
+    if options.key.is_empty() {
+        warn!("No key given, so pseudonyms of common names can be guessed");
+    }
+
+    if std::io::stdout().is_terminal() {
+        anonymize::from_files(options, &infiles, io::stdout().lock())
+    } else {
+        anonymize::from_files(options, &infiles, io::BufWriter::new(io::stdout().lock()))
+    }
+}
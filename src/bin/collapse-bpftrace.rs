@@ -0,0 +1,75 @@
+use std::io;
+use std::path::PathBuf;
+
+use clap::{ArgAction, Parser};
+use env_logger::Env;
+use inferno::collapse::bpftrace::{Folder, Options};
+use inferno::collapse::Collapse;
+use inferno::config::Config;
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "inferno-collapse-bpftrace",
+    about,
+    after_help = "\
+[1] This processes the stacks printed by bpftrace maps or BCC tools, like:
+        bpftrace -e 'profile:hz:99 { @[kstack, ustack, comm] = count(); }' > out.stacks
+        profile -d 30 > out.stacks
+    "
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Annotate kernel functions with a `_[k]`
+    #[clap(long = "kernel")]
+    kernel: bool,
+
+    /// Include PID with the process names of BCC output
+    #[clap(long = "pid")]
+    pid: bool,
+
+    /// Silence all log output
+    #[clap(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
+    verbose: u8,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// bpftrace or BCC output file, or STDIN if not specified
+    #[clap(value_name = "PATH")]
+    infile: Option<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> (Option<PathBuf>, Options) {
+        let mut options = Options::default();
+        options.annotate_kernel = self.kernel;
+        options.include_pid = self.pid;
+        (self.infile, options)
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt = Opt::parse();
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let (infile, options) = opt.into_parts();
+    let options = Config::discover()?.collapse("bpftrace", options)?;
+    Folder::from(options).collapse_file_to_stdout(infile.as_ref())
+}
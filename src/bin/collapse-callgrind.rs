@@ -0,0 +1,77 @@
+use std::io;
+use std::path::PathBuf;
+
+use clap::{ArgAction, Parser};
+use env_logger::Env;
+use inferno::collapse::callgrind::{Folder, Options};
+use inferno::collapse::Collapse;
+use inferno::config::Config;
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "inferno-collapse-callgrind",
+    about,
+    after_help = "\
+[1] This processes the output of Valgrind's callgrind tool, created as follows:
+        valgrind --tool=callgrind --callgrind-out-file=callgrind.out <program-to-profile>
+    "
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Write a metadata header, like the units of the counts, before the folded stacks
+    #[clap(long = "header")]
+    header: bool,
+
+    /// Silence all log output
+    #[clap(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
+    verbose: u8,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// Event to weight stacks by, like "Ir" or "CEst" [default: the first event of the profile]
+    #[clap(long = "event", value_name = "EVENT")]
+    event: Option<String>,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// callgrind output file, or STDIN if not specified
+    #[clap(value_name = "PATH")]
+    infile: Option<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> (Option<PathBuf>, Options) {
+        let mut options = Options::default();
+        options.header = self.header;
+        options.event = self.event;
+        (self.infile, options)
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt = Opt::parse();
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let (infile, options) = opt.into_parts();
+    let options = Config::discover()?.collapse("callgrind", options)?;
+    Folder::from(options).collapse_file_to_stdout(infile.as_ref())
+}
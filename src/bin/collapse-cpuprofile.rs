@@ -0,0 +1,97 @@
+use std::io;
+use std::path::PathBuf;
+
+use clap::{ArgAction, Parser};
+use env_logger::Env;
+use inferno::collapse::cpuprofile::{Folder, Options};
+use inferno::collapse::Collapse;
+use inferno::config::Config;
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "inferno-collapse-cpuprofile",
+    about,
+    after_help = "\
+[1] This processes the .cpuprofile JSON of V8, created by Node as follows:
+        node --cpu-prof --cpu-prof-name=app.cpuprofile app.js
+    or saved from the Performance panel of Chrome DevTools.
+    "
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Count samples instead of weighting them by the microseconds they took
+    #[clap(long = "count-samples")]
+    count_samples: bool,
+
+    /// Add the script, line and column of each function to its frame
+    #[clap(long = "locations")]
+    locations: bool,
+
+    /// Leave out samples taken while the program was idle
+    #[clap(long = "skip-idle")]
+    skip_idle: bool,
+
+    /// Write a metadata header, like the units of the counts, before the folded stacks
+    #[clap(long = "header")]
+    header: bool,
+
+    /// Silence all log output
+    #[clap(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
+    verbose: u8,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// Source map, or directory of .map files, for minified JavaScript (may be repeated)
+    #[cfg(feature = "sourcemap")]
+    #[clap(long = "source-map", value_name = "PATH")]
+    source_maps: Vec<PathBuf>,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// cpuprofile JSON file, or STDIN if not specified
+    #[clap(value_name = "PATH")]
+    infile: Option<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> (Option<PathBuf>, Options) {
+        let mut options = Options::default();
+        options.count_samples = self.count_samples;
+        options.include_locations = self.locations;
+        options.skip_idle = self.skip_idle;
+        options.header = self.header;
+        #[cfg(feature = "sourcemap")]
+        {
+            options.source_maps = self.source_maps;
+        }
+        (self.infile, options)
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt = Opt::parse();
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let (infile, options) = opt.into_parts();
+    let options = Config::discover()?.collapse("cpuprofile", options)?;
+    Folder::from(options).collapse_file_to_stdout(infile.as_ref())
+}
@@ -0,0 +1,74 @@
+use std::io;
+use std::path::PathBuf;
+
+use clap::{ArgAction, Parser};
+use env_logger::Env;
+use inferno::collapse::d3::{Folder, Options};
+use inferno::collapse::Collapse;
+use inferno::config::Config;
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "inferno-collapse-d3",
+    about,
+    after_help = "\
+[1] This processes the JSON data of d3-flame-graph, a tree of nodes like
+        {\"name\": \"main\", \"value\": 7, \"children\": [...]}
+    "
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Keep the root node in every stack
+    #[clap(long = "keep-root")]
+    keep_root: bool,
+
+    /// The value of each node is its self value instead of its total value
+    #[clap(long = "self-value")]
+    self_value: bool,
+
+    /// Silence all log output
+    #[clap(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
+    verbose: u8,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// d3-flame-graph JSON file, or STDIN if not specified
+    #[clap(value_name = "PATH")]
+    infile: Option<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> (Option<PathBuf>, Options) {
+        let mut options = Options::default();
+        options.keep_root = self.keep_root;
+        options.self_value = self.self_value;
+        (self.infile, options)
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt = Opt::parse();
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let (infile, options) = opt.into_parts();
+    let options = Config::discover()?.collapse("d3", options)?;
+    Folder::from(options).collapse_file_to_stdout(infile.as_ref())
+}
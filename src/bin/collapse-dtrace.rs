@@ -1,10 +1,14 @@
 use std::io;
 use std::path::PathBuf;
 
+use clap::builder::TypedValueParser;
 use clap::{ArgAction, Parser};
 use env_logger::Env;
-use inferno::collapse::dtrace::{Folder, Options};
+use inferno::collapse::dtrace::{DistributionWeight, Folder, ModuleNames, Options, TimeUnit};
 use inferno::collapse::{Collapse, DEFAULT_NTHREADS};
+use inferno::config::Config;
+use inferno::symbols::demangle;
+use log::info;
 use once_cell::sync::Lazy;
 
 static NTHREADS: Lazy<String> = Lazy::new(|| DEFAULT_NTHREADS.to_string());
@@ -18,6 +22,8 @@ static NTHREADS: Lazy<String> = Lazy::new(|| DEFAULT_NTHREADS.to_string());
         dtrace -x ustackframes=100 -n 'profile-97 /pid == 12345 && arg1/ { @[ustack()] = count(); } tick-60s { exit(0); }'
     or including kernel time:
         dtrace -x ustackframes=100 -n 'profile-97 /pid == 12345/ { @[ustack()] = count(); } tick-60s { exit(0); }'
+    Time aggregations, like sum(timestamp - self->ts), and quantize() distributions of them are
+    also supported; see --time-unit and --distribution-weight.
     "
 )]
 struct Opt {
@@ -28,6 +34,30 @@ struct Opt {
     #[clap(long = "includeoffset")]
     includeoffset: bool,
 
+    /// Don't demangle mangled Rust, C++, MSVC and Swift symbols
+    #[clap(long = "no-demangle")]
+    no_demangle: bool,
+
+    /// Leave the parameters out of demangled C++ and MSVC functions
+    #[clap(long = "demangle-no-params")]
+    demangle_no_params: bool,
+
+    /// Keep the hash suffixes of demangled Rust symbols, like ::h0123456789abcdef
+    #[clap(long = "demangle-keep-hash")]
+    demangle_keep_hash: bool,
+
+    /// Use the process name from the aggregation key (e.g. @[execname, ustack()]) as root frame
+    #[clap(long = "pname")]
+    pname: bool,
+
+    /// Also include the remaining aggregation keys (e.g. pid and tid) in the root frame
+    #[clap(long = "tid")]
+    tid: bool,
+
+    /// Write a metadata header, like the units of the counts, before the folded stacks
+    #[clap(long = "header")]
+    header: bool,
+
     /// Silence all log output
     #[clap(short = 'q', long = "quiet")]
     quiet: bool,
@@ -39,6 +69,36 @@ struct Opt {
     // *************** //
     // *** OPTIONS *** //
     // *************** //
+    /// How to weigh quantize() distributions: by number of values or by their (approximate) sum
+    #[clap(
+        long = "distribution-weight",
+        default_value = "value",
+        value_parser = clap::builder::PossibleValuesParser::new(DistributionWeight::VARIANTS).map(|s| s.parse::<DistributionWeight>().unwrap()),
+        value_name = "STRING"
+    )]
+    distribution_weight: DistributionWeight,
+
+    /// How to keep the module of `module`function` frames
+    #[clap(
+        long = "modules",
+        default_value = "prefix",
+        value_parser = clap::builder::PossibleValuesParser::new(ModuleNames::VARIANTS).map(|s| s.parse::<ModuleNames>().unwrap()),
+        value_name = "STRING"
+    )]
+    modules: ModuleNames,
+
+    /// Treat aggregated values as nanoseconds (e.g. from sum(timestamp - self->ts)) and convert them to this unit
+    #[clap(
+        long = "time-unit",
+        value_parser = clap::builder::PossibleValuesParser::new(TimeUnit::VARIANTS).map(|s| s.parse::<TimeUnit>().unwrap()),
+        value_name = "UNIT"
+    )]
+    time_unit: Option<TimeUnit>,
+
+    /// What the aggregated values are in (e.g. bytes for sum(arg0)), for the --header
+    #[clap(long = "units", value_name = "STRING")]
+    units: Option<String>,
+
     /// Number of threads to use.
     #[clap(
         short = 'n',
@@ -59,8 +119,22 @@ struct Opt {
 impl Opt {
     fn into_parts(self) -> (Option<PathBuf>, Options) {
         let mut options = Options::default();
+        options.header = self.header;
+        options.distribution_weight = self.distribution_weight;
+        options.demangle = if self.no_demangle {
+            demangle::Options::none()
+        } else {
+            demangle::Options::default()
+        };
+        options.demangle.no_params = self.demangle_no_params;
+        options.demangle.strip_hash = !self.demangle_keep_hash;
         options.includeoffset = self.includeoffset;
+        options.include_pname = self.pname;
+        options.include_tid = self.tid;
+        options.module_names = self.modules;
         options.nthreads = self.nthreads;
+        options.time_unit = self.time_unit;
+        options.units = self.units;
         (self.infile, options)
     }
 }
@@ -81,5 +155,12 @@ fn main() -> io::Result<()> {
     }
 
     let (infile, options) = opt.into_parts();
+    let options = Config::discover()?.collapse("dtrace", options)?;
+    if let Some(unit) = options.time_unit {
+        info!(
+            "Stack weights are in {}; pass `--countname {}` to inferno-flamegraph to label them",
+            unit, unit
+        );
+    }
     Folder::from(options).collapse_file_to_stdout(infile.as_ref())
 }
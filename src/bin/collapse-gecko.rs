@@ -0,0 +1,83 @@
+use std::io;
+use std::path::PathBuf;
+
+use clap::{ArgAction, Parser};
+use env_logger::Env;
+use inferno::collapse::gecko::{Folder, Options};
+use inferno::collapse::Collapse;
+use inferno::config::Config;
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "inferno-collapse-gecko",
+    about,
+    after_help = "\
+[1] This processes Firefox Profiler JSON profiles, like those saved by Firefox or written by:
+        samply record --save-only -o profile.json <program-to-profile>
+    Compressed profiles must be decompressed first, for example with gunzip.
+    "
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Add the thread name of each sample as the root frame
+    #[clap(long = "threads")]
+    threads: bool,
+
+    /// Write a metadata header, like the units of the counts, before the folded stacks
+    #[clap(long = "header")]
+    header: bool,
+
+    /// Silence all log output
+    #[clap(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
+    verbose: u8,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// Name or thread id of a thread to fold; may be given more than once [default: all threads]
+    #[clap(long = "thread", value_name = "THREAD")]
+    thread: Vec<String>,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// Firefox Profiler JSON file, or STDIN if not specified
+    #[clap(value_name = "PATH")]
+    infile: Option<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> (Option<PathBuf>, Options) {
+        let mut options = Options::default();
+        options.include_threads = self.threads;
+        options.header = self.header;
+        options.threads = self.thread;
+        (self.infile, options)
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt = Opt::parse();
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let (infile, options) = opt.into_parts();
+    let options = Config::discover()?.collapse("gecko", options)?;
+    Folder::from(options).collapse_file_to_stdout(infile.as_ref())
+}
@@ -5,6 +5,7 @@ use clap::{ArgAction, ArgGroup, Parser};
 use env_logger::Env;
 use inferno::collapse::ghcprof::{Folder, Options, Source};
 use inferno::collapse::Collapse;
+use inferno::config::Config;
 
 #[derive(Debug, Parser)]
 #[clap(
@@ -34,6 +35,10 @@ struct Opt {
     #[clap(long = "ticks")]
     ticks: bool,
 
+    /// Write a metadata header, like the units of the counts, before the folded stacks
+    #[clap(long = "header")]
+    header: bool,
+
     /// Silence all log output
     #[clap(short = 'q', long = "quiet")]
     quiet: bool,
@@ -53,6 +58,7 @@ struct Opt {
 impl Opt {
     fn into_parts(self) -> (Option<PathBuf>, Options) {
         let mut options = Options::default();
+        options.header = self.header;
         options.source = if self.ticks {
             Source::Ticks
         } else if self.bytes {
@@ -80,5 +86,6 @@ fn main() -> io::Result<()> {
     }
 
     let (infile, options) = opt.into_parts();
+    let options = Config::discover()?.collapse("ghcprof", options)?;
     Folder::from(options).collapse_file_to_stdout(infile.as_ref())
 }
@@ -1,13 +1,25 @@
 use std::io;
 use std::path::PathBuf;
 
+use clap::builder::TypedValueParser;
 use clap::{ArgAction, Parser};
 use env_logger::Env;
-use inferno::collapse::guess::{Folder, Options};
+use inferno::collapse::guess::{self, Folder, Format, Options, DEFAULT_PROBE_LINES};
 use inferno::collapse::{Collapse, DEFAULT_NTHREADS};
+use inferno::config::Config;
+use inferno::symbols::demangle;
 use once_cell::sync::Lazy;
 
 static NTHREADS: Lazy<String> = Lazy::new(|| DEFAULT_NTHREADS.to_string());
+static PROBE_LINES: Lazy<String> = Lazy::new(|| DEFAULT_PROBE_LINES.to_string());
+
+static FORMATS: Lazy<Vec<&'static str>> = Lazy::new(|| {
+    Format::VARIANTS
+        .iter()
+        .chain(Format::REGISTERED)
+        .copied()
+        .collect()
+});
 
 #[derive(Debug, Parser)]
 #[clap(
@@ -15,12 +27,34 @@ static NTHREADS: Lazy<String> = Lazy::new(|| DEFAULT_NTHREADS.to_string());
     about,
     after_help = "\
 [1] Attempts to find an appropriate collapser to use based on the input.
+    Run with -v to see the chosen format and the runners-up.
+[2] When no built-in format matches, the collapser plugins given with --plugin and those found
+    in the directories listed in INFERNO_PLUGIN_PATH are tried in that order. A plugin is an
+    executable that exits successfully when run as `plugin --check` with the start of the input
+    on stdin if it understands the input, and that writes folded stacks to stdout when run
+    without arguments with the whole input on stdin.
                   "
 )]
 struct Opt {
     // ************* //
     // *** FLAGS *** //
     // ************* //
+    /// Don't demangle mangled Rust, C++, MSVC and Swift symbols
+    #[clap(long = "no-demangle")]
+    no_demangle: bool,
+
+    /// Leave the parameters out of demangled C++ and MSVC functions
+    #[clap(long = "demangle-no-params")]
+    demangle_no_params: bool,
+
+    /// Keep the hash suffixes of demangled Rust symbols, like ::h0123456789abcdef
+    #[clap(long = "demangle-keep-hash")]
+    demangle_keep_hash: bool,
+
+    /// Write a metadata header, like the units of the counts, before the folded stacks
+    #[clap(long = "header")]
+    header: bool,
+
     /// Silence all log output
     #[clap(short = 'q', long = "quiet")]
     quiet: bool,
@@ -32,6 +66,14 @@ struct Opt {
     // *************** //
     // *** OPTIONS *** //
     // *************** //
+    /// Skip detection and use the collapser for this input format
+    #[clap(
+        long = "assume",
+        value_parser = clap::builder::PossibleValuesParser::new(FORMATS.iter().copied()).map(|s| s.parse::<Format>().unwrap()),
+        value_name = "FORMAT"
+    )]
+    assume: Option<Format>,
+
     /// Number of threads to use
     #[clap(
         short = 'n',
@@ -41,6 +83,18 @@ struct Opt {
     )]
     nthreads: usize,
 
+    /// Number of lines to probe for other matching formats once one has been recognized
+    #[clap(
+        long = "probe-lines",
+        default_value = &**PROBE_LINES,
+        value_name = "UINT"
+    )]
+    probe_lines: usize,
+
+    /// Collapser plugin to try if no built-in format matches [2]
+    #[clap(long = "plugin", value_name = "PATH")]
+    plugins: Vec<PathBuf>,
+
     // ************ //
     // *** ARGS *** //
     // ************ //
@@ -52,7 +106,19 @@ struct Opt {
 impl Opt {
     fn into_parts(self) -> (Option<PathBuf>, Options) {
         let mut options = Options::default();
+        options.header = self.header;
+        options.assume = self.assume;
+        options.demangle = if self.no_demangle {
+            demangle::Options::none()
+        } else {
+            demangle::Options::default()
+        };
+        options.demangle.no_params = self.demangle_no_params;
+        options.demangle.strip_hash = !self.demangle_keep_hash;
         options.nthreads = self.nthreads;
+        options.probe_lines = self.probe_lines;
+        options.plugins = self.plugins;
+        options.plugins.extend(guess::discover_plugins());
         (self.infile, options)
     }
 }
@@ -73,5 +139,6 @@ fn main() -> io::Result<()> {
     }
 
     let (infile, options) = opt.into_parts();
+    let options = Config::discover()?.collapse("guess", options)?;
     Folder::from(options).collapse_file_to_stdout(infile.as_ref())
 }
@@ -0,0 +1,99 @@
+use std::io;
+use std::path::PathBuf;
+
+use clap::builder::TypedValueParser;
+use clap::{ArgAction, Parser};
+use env_logger::Env;
+use inferno::collapse::heaptrack::{Cost, Folder, Options};
+use inferno::collapse::Collapse;
+use inferno::config::Config;
+use inferno::symbols::demangle;
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "inferno-collapse-heaptrack",
+    about,
+    after_help = "\
+[1] This processes the decompressed data files of heaptrack, created as follows:
+        heaptrack <program-to-profile>
+        zstd -d heaptrack.<program>.<pid>.zst
+    "
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Don't demangle mangled Rust, C++, MSVC and Swift symbols
+    #[clap(long = "no-demangle")]
+    no_demangle: bool,
+
+    /// Keep the hash suffixes of demangled Rust symbols, like ::h0123456789abcdef
+    #[clap(long = "demangle-keep-hash")]
+    demangle_keep_hash: bool,
+
+    /// Write a metadata header, like the units of the counts, before the folded stacks
+    #[clap(long = "header")]
+    header: bool,
+
+    /// Silence all log output
+    #[clap(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
+    verbose: u8,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// What to weight stacks by: bytes allocated (allocated), bytes never freed (leaked), or the number of allocations (allocations)
+    #[clap(
+        long = "cost",
+        default_value = "allocated",
+        value_parser = clap::builder::PossibleValuesParser::new(Cost::VARIANTS).map(|s| s.parse::<Cost>().unwrap()),
+        value_name = "STRING"
+    )]
+    cost: Cost,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// Decompressed heaptrack data file, or STDIN if not specified
+    #[clap(value_name = "PATH")]
+    infile: Option<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> (Option<PathBuf>, Options) {
+        let mut options = Options::default();
+        options.cost = self.cost;
+        options.demangle = if self.no_demangle {
+            demangle::Options::none()
+        } else {
+            demangle::Options::default()
+        };
+        options.demangle.strip_hash = !self.demangle_keep_hash;
+        options.header = self.header;
+        (self.infile, options)
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt = Opt::parse();
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let (infile, options) = opt.into_parts();
+    let options = Config::discover()?.collapse("heaptrack", options)?;
+    Folder::from(options).collapse_file_to_stdout(infile.as_ref())
+}
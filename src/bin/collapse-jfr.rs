@@ -0,0 +1,95 @@
+use std::io;
+use std::path::PathBuf;
+
+use clap::builder::TypedValueParser;
+use clap::{ArgAction, Parser};
+use env_logger::Env;
+use inferno::collapse::jfr::{Event, Folder, Options};
+use inferno::collapse::Collapse;
+use inferno::config::Config;
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "inferno-collapse-jfr",
+    about,
+    after_help = "\
+[1] This processes Java Flight Recorder recordings, created as follows:
+        java -XX:StartFlightRecording=filename=recording.jfr <program-to-profile>
+    or by async-profiler:
+        asprof -e cpu -o jfr -f recording.jfr <pid>
+    "
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Add the thread name of each event as the root frame
+    #[clap(long = "threads")]
+    threads: bool,
+
+    /// Annotate JIT-compiled (_[j]), inlined (_[i]) and kernel (_[k]) frames
+    #[clap(long = "all")]
+    all: bool,
+
+    /// Write a metadata header, like the units of the counts, before the folded stacks
+    #[clap(long = "header")]
+    header: bool,
+
+    /// Silence all log output
+    #[clap(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
+    verbose: u8,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// Events to fold: execution samples (cpu), allocations weighted by bytes (alloc), or blocked threads weighted by nanoseconds (lock)
+    #[clap(
+        long = "event",
+        default_value = "cpu",
+        value_parser = clap::builder::PossibleValuesParser::new(Event::VARIANTS).map(|s| s.parse::<Event>().unwrap()),
+        value_name = "STRING"
+    )]
+    event: Event,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// JFR recording, or STDIN if not specified
+    #[clap(value_name = "PATH")]
+    infile: Option<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> (Option<PathBuf>, Options) {
+        let mut options = Options::default();
+        options.event = self.event;
+        options.include_threads = self.threads;
+        options.annotate = self.all;
+        options.header = self.header;
+        (self.infile, options)
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt = Opt::parse();
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let (infile, options) = opt.into_parts();
+    let options = Config::discover()?.collapse("jfr", options)?;
+    Folder::from(options).collapse_file_to_stdout(infile.as_ref())
+}
@@ -1,10 +1,15 @@
-use std::io;
-use std::path::PathBuf;
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
 
 use clap::{ArgAction, Parser};
 use env_logger::Env;
 use inferno::collapse::perf::{Folder, Options};
 use inferno::collapse::{Collapse, DEFAULT_NTHREADS};
+use inferno::config::Config;
+use inferno::split;
+use inferno::symbols::demangle;
 use once_cell::sync::Lazy;
 
 static NTHREADS: Lazy<String> = Lazy::new(|| DEFAULT_NTHREADS.to_string());
@@ -28,10 +33,23 @@ struct Opt {
     #[clap(long = "addrs")]
     addrs: bool,
 
+    /// Fold stacks in the order they were sampled, weighted by nanoseconds, for flamegraph --flamechart
+    #[clap(long = "chronological", conflicts_with_all = &["off_cpu", "output_dir"])]
+    chronological: bool,
+
+    /// Annotate each stack with its category (gc, jit, kernel or app) for flamegraph --category-band
+    #[clap(long = "categories")]
+    categories: bool,
+
     /// All annotations (--kernel --jit)
     #[clap(long = "all")]
     all: bool,
 
+    /// Expand frames into their inlined functions using DWARF debug info
+    #[cfg(feature = "inline")]
+    #[clap(long = "inline")]
+    inline: bool,
+
     /// Annotate jit functions with a `_[j]`
     #[clap(long = "jit")]
     jit: bool,
@@ -40,6 +58,26 @@ struct Opt {
     #[clap(long = "kernel")]
     kernel: bool,
 
+    /// Don't demangle mangled Rust, C++, MSVC and Swift symbols
+    #[clap(long = "no-demangle")]
+    no_demangle: bool,
+
+    /// Leave the parameters out of demangled C++ and MSVC functions
+    #[clap(long = "demangle-no-params")]
+    demangle_no_params: bool,
+
+    /// Keep the hash suffixes of demangled Rust symbols, like ::h0123456789abcdef
+    #[clap(long = "demangle-keep-hash")]
+    demangle_keep_hash: bool,
+
+    /// Fold sched:sched_switch events into off-CPU stacks weighted by microseconds blocked
+    #[clap(long = "off-cpu")]
+    off_cpu: bool,
+
+    /// Count every sample once instead of weighting it by its period
+    #[clap(long = "count-samples")]
+    count_samples: bool,
+
     /// Include PID with process names
     #[clap(long = "pid")]
     pid: bool,
@@ -48,6 +86,10 @@ struct Opt {
     #[clap(long = "tid")]
     tid: bool,
 
+    /// Write a metadata header, like the units of the counts, before the folded stacks
+    #[clap(long = "header")]
+    header: bool,
+
     /// Silence all log output
     #[clap(short = 'q', long = "quiet")]
     quiet: bool,
@@ -59,10 +101,40 @@ struct Opt {
     // *************** //
     // *** OPTIONS *** //
     // *************** //
+    /// Directory to search for binaries with debug info (may be repeated)
+    #[cfg(feature = "inline")]
+    #[clap(long = "debug-dir", value_name = "PATH")]
+    debug_dir: Vec<PathBuf>,
+
     /// Event filter [default: first encountered event]
     #[clap(long = "event-filter", value_name = "STRING")]
     event_filter: Option<String>,
 
+    /// With --chronological, fold gaps between samples longer than this as idle time [default: 0.01]
+    #[clap(long = "idle-gap", value_name = "SECONDS", requires = "chronological")]
+    idle_gap: Option<f64>,
+
+    /// Only fold samples taken at or after this time, in seconds
+    #[clap(long = "from", value_name = "FLOAT")]
+    from: Option<f64>,
+
+    /// Only fold samples taken before this time, in seconds
+    #[clap(long = "to", value_name = "FLOAT")]
+    to: Option<f64>,
+
+    /// Cut stacks deeper than this many frames, replacing the deeper frames with a `...` frame
+    #[clap(long = "max-depth", value_name = "UINT")]
+    max_depth: Option<usize>,
+
+    /// Perf map or jitdump file, or a directory of them, used to name unknown JIT frames (may
+    /// be repeated)
+    #[clap(long = "jit-map", value_name = "PATH")]
+    jit_map: Vec<PathBuf>,
+
+    /// Write the stacks of each process (or thread, with --pid/--tid) to its own file in DIR
+    #[clap(long = "output-dir", value_name = "DIR")]
+    output_dir: Option<PathBuf>,
+
     /// Number of threads to use
     #[clap(
         short = 'n',
@@ -87,17 +159,40 @@ struct Opt {
 }
 
 impl Opt {
-    fn into_parts(self) -> (Option<PathBuf>, Options) {
+    fn into_parts(self) -> (Option<PathBuf>, Option<PathBuf>, bool, Options) {
         let mut options = Options::default();
+        options.header = self.header;
         options.include_pid = self.pid;
         options.include_tid = self.tid;
         options.include_addrs = self.addrs;
         options.annotate_jit = self.jit || self.all;
         options.annotate_kernel = self.kernel || self.all;
+        options.annotate_categories = self.categories;
+        options.demangle = if self.no_demangle {
+            demangle::Options::none()
+        } else {
+            demangle::Options::default()
+        };
+        options.demangle.no_params = self.demangle_no_params;
+        options.demangle.strip_hash = !self.demangle_keep_hash;
         options.event_filter = self.event_filter;
+        options.from = self.from;
+        if let Some(idle_gap) = self.idle_gap {
+            options.idle_gap = idle_gap;
+        }
+        options.to = self.to;
+        options.jit_maps = self.jit_map;
+        options.max_depth = self.max_depth;
+        #[cfg(feature = "inline")]
+        {
+            options.expand_inlines = self.inline;
+            options.debug_dirs = self.debug_dir;
+        }
         options.nthreads = self.nthreads;
+        options.off_cpu = self.off_cpu;
+        options.period_weights = !self.count_samples;
         options.skip_after = self.skip_after;
-        (self.infile, options)
+        (self.infile, self.output_dir, self.chronological, options)
     }
 }
 
@@ -116,6 +211,49 @@ fn main() -> io::Result<()> {
         .init();
     }
 
-    let (infile, options) = opt.into_parts();
-    Folder::from(options).collapse_file_to_stdout(infile.as_ref())
+    let (infile, output_dir, chronological, options) = opt.into_parts();
+    let options = Config::discover()?.collapse("perf", options)?;
+    let mut folder = Folder::from(options);
+    if chronological {
+        let stdout = io::stdout();
+        let writer = BufWriter::new(stdout.lock());
+        return match infile {
+            Some(path) => {
+                folder.collapse_chronologically(BufReader::new(File::open(path)?), writer)
+            }
+            None => folder.collapse_chronologically(io::stdin().lock(), writer),
+        };
+    }
+    match output_dir {
+        Some(dir) => {
+            fs::create_dir_all(&dir)?;
+            let mut names = HashSet::new();
+            let writer_for = |root: &str| create_output_file(&dir, root, &mut names);
+            match infile {
+                Some(path) => {
+                    folder.collapse_per_thread(BufReader::new(File::open(path)?), writer_for)
+                }
+                None => folder.collapse_per_thread(io::stdin().lock(), writer_for),
+            }
+        }
+        None => folder.collapse_file_to_stdout(infile.as_ref()),
+    }
+}
+
+// Roots that only differ in characters that aren't safe in file names, like `Web Content-1/2`
+// and `Web_Content-1_2`, get a number added to the name of their file, so that one doesn't
+// overwrite the other.
+fn create_output_file(
+    dir: &Path,
+    root: &str,
+    names: &mut HashSet<String>,
+) -> io::Result<BufWriter<File>> {
+    let name = split::file_name(root);
+    let mut unique = name.clone();
+    let mut n = 1;
+    while !names.insert(unique.clone()) {
+        n += 1;
+        unique = format!("{}-{}", name, n);
+    }
+    File::create(dir.join(format!("{}.folded", unique))).map(BufWriter::new)
 }
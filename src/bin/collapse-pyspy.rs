@@ -0,0 +1,99 @@
+use std::io;
+use std::path::PathBuf;
+
+use clap::{ArgAction, Parser};
+use env_logger::Env;
+use inferno::collapse::pyspy::{Folder, Options};
+use inferno::collapse::Collapse;
+use inferno::config::Config;
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "inferno-collapse-pyspy",
+    about,
+    after_help = "\
+[1] This processes the raw output of py-spy, created as follows:
+        py-spy record --format raw -o stacks.txt -- python <program-to-profile>
+    "
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Leave line numbers out of frames
+    #[clap(long = "no-lines")]
+    no_lines: bool,
+
+    /// Leave file names and line numbers out of frames
+    #[clap(long = "no-files")]
+    no_files: bool,
+
+    /// Leave out the thread frames of profiles recorded with --threads
+    #[clap(long = "no-threads")]
+    no_threads: bool,
+
+    /// Leave out the process frames of profiles recorded with --subprocesses
+    #[clap(long = "no-processes")]
+    no_processes: bool,
+
+    /// Leave out stacks of idle threads
+    #[clap(long = "skip-idle")]
+    skip_idle: bool,
+
+    /// Annotate each stack with its thread status (idle, gil or active) for flamegraph --category-band
+    #[clap(long = "status")]
+    status: bool,
+
+    /// Write a metadata header, like the units of the counts, before the folded stacks
+    #[clap(long = "header")]
+    header: bool,
+
+    /// Silence all log output
+    #[clap(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
+    verbose: u8,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// py-spy raw output file, or STDIN if not specified
+    #[clap(value_name = "PATH")]
+    infile: Option<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> (Option<PathBuf>, Options) {
+        let mut options = Options::default();
+        options.no_lines = self.no_lines;
+        options.no_files = self.no_files;
+        options.no_threads = self.no_threads;
+        options.no_processes = self.no_processes;
+        options.skip_idle = self.skip_idle;
+        options.annotate_status = self.status;
+        options.header = self.header;
+        (self.infile, options)
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt = Opt::parse();
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let (infile, options) = opt.into_parts();
+    let options = Config::discover()?.collapse("pyspy", options)?;
+    Folder::from(options).collapse_file_to_stdout(infile.as_ref())
+}
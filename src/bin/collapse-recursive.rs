@@ -4,13 +4,31 @@ use std::path::PathBuf;
 use clap::Parser;
 use inferno::collapse::recursive::{Folder, Options};
 use inferno::collapse::{Collapse, DEFAULT_NTHREADS};
+use inferno::config::Config;
 use once_cell::sync::Lazy;
+use regex::Regex;
 
 static NTHREADS: Lazy<String> = Lazy::new(|| DEFAULT_NTHREADS.to_string());
 
 #[derive(Debug, Parser)]
 #[clap(name = "inferno-collapse-recursive", about)]
 struct Opt {
+    /// Annotate collapsed recursive frames with their depth, as in `foo (x3)`
+    #[clap(long = "annotate-depth")]
+    annotate_depth: bool,
+
+    /// Collapse runs of recursive frames to this many frames instead of one
+    #[clap(long = "max-repeats", default_value = "1", value_name = "UINT")]
+    max_repeats: usize,
+
+    /// Only collapse the recursion of functions matching this regular expression
+    #[clap(long = "include", value_parser = Regex::new, value_name = "REGEX")]
+    include: Option<Regex>,
+
+    /// Don't collapse the recursion of functions matching this regular expression
+    #[clap(long = "exclude", value_parser = Regex::new, value_name = "REGEX")]
+    exclude: Option<Regex>,
+
     /// Number of threads to use
     #[clap(
         short = 'n',
@@ -28,6 +46,10 @@ struct Opt {
 impl Opt {
     fn into_parts(self) -> (Option<PathBuf>, Options) {
         let mut options = Options::default();
+        options.annotate_depth = self.annotate_depth;
+        options.exclude = self.exclude;
+        options.include = self.include;
+        options.max_repeats = self.max_repeats;
         options.nthreads = self.nthreads;
         (self.infile, options)
     }
@@ -36,5 +58,6 @@ impl Opt {
 fn main() -> io::Result<()> {
     let opt = Opt::parse();
     let (infile, options) = opt.into_parts();
+    let options = Config::discover()?.collapse("recursive", options)?;
     Folder::from(options).collapse_file_to_stdout(infile.as_ref())
 }
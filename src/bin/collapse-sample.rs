@@ -1,10 +1,16 @@
 use std::io;
 use std::path::PathBuf;
 
+use clap::builder::TypedValueParser;
 use clap::{ArgAction, Parser};
 use env_logger::Env;
 use inferno::collapse::sample::{Folder, Options};
-use inferno::collapse::Collapse;
+use inferno::collapse::{Collapse, Prefix, DEFAULT_NTHREADS};
+use inferno::config::Config;
+use inferno::symbols::demangle;
+use once_cell::sync::Lazy;
+
+static NTHREADS: Lazy<String> = Lazy::new(|| DEFAULT_NTHREADS.to_string());
 
 #[derive(Debug, Parser)]
 #[clap(
@@ -22,6 +28,38 @@ struct Opt {
     #[clap(long = "no-modules")]
     no_modules: bool,
 
+    /// Use thread names, when present, as the root frames
+    #[clap(long = "thread-names")]
+    thread_names: bool,
+
+    /// Add the thread's dispatch queue, when present, as a root frame
+    #[clap(long = "dispatch-queues")]
+    dispatch_queues: bool,
+
+    /// Keep stacks of blocked threads, annotated with _[blocked]
+    #[clap(long = "annotate-states")]
+    annotate_states: bool,
+
+    /// Name unsymbolicated (???) frames after their binary and offset, like libfoo+0x1f3c
+    #[clap(long = "resolve-unknown")]
+    resolve_unknown: bool,
+
+    /// Don't demangle mangled Rust, C++, MSVC and Swift symbols
+    #[clap(long = "no-demangle")]
+    no_demangle: bool,
+
+    /// Leave the parameters out of demangled C++ and MSVC functions
+    #[clap(long = "demangle-no-params")]
+    demangle_no_params: bool,
+
+    /// Keep the hash suffixes of demangled Rust symbols, like ::h0123456789abcdef
+    #[clap(long = "demangle-keep-hash")]
+    demangle_keep_hash: bool,
+
+    /// Write a metadata header, like the units of the counts, before the folded stacks
+    #[clap(long = "header")]
+    header: bool,
+
     /// Silence all log output
     #[clap(short = 'q', long = "quiet")]
     quiet: bool,
@@ -30,6 +68,26 @@ struct Opt {
     #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
     verbose: u8,
 
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// Put this process or thread frame at the root of each stack instead of the thread line
+    #[clap(
+        long = "prefix",
+        value_parser = clap::builder::PossibleValuesParser::new(Prefix::VARIANTS).map(|s| s.parse::<Prefix>().unwrap()),
+        value_name = "STRING"
+    )]
+    prefix: Option<Prefix>,
+
+    /// Number of threads to use.
+    #[clap(
+        short = 'n',
+        long = "nthreads",
+        default_value = &**NTHREADS,
+        value_name = "UINT"
+    )]
+    nthreads: usize,
+
     // ************ //
     // *** ARGS *** //
     // ************ //
@@ -41,7 +99,21 @@ struct Opt {
 impl Opt {
     fn into_parts(self) -> (Option<PathBuf>, Options) {
         let mut options = Options::default();
+        options.header = self.header;
         options.no_modules = self.no_modules;
+        options.prefix = self.prefix;
+        options.thread_names = self.thread_names;
+        options.dispatch_queues = self.dispatch_queues;
+        options.annotate_states = self.annotate_states;
+        options.resolve_unknown = self.resolve_unknown;
+        options.demangle = if self.no_demangle {
+            demangle::Options::none()
+        } else {
+            demangle::Options::default()
+        };
+        options.demangle.no_params = self.demangle_no_params;
+        options.demangle.strip_hash = !self.demangle_keep_hash;
+        options.nthreads = self.nthreads;
         (self.infile, options)
     }
 }
@@ -62,5 +134,6 @@ fn main() -> io::Result<()> {
     }
 
     let (infile, options) = opt.into_parts();
+    let options = Config::discover()?.collapse("sample", options)?;
     Folder::from(options).collapse_file_to_stdout(infile.as_ref())
 }
@@ -0,0 +1,96 @@
+use std::io;
+use std::path::PathBuf;
+
+use clap::{ArgAction, Parser};
+use env_logger::Env;
+use inferno::collapse::simpleperf::{Folder, Options};
+use inferno::collapse::Collapse;
+use inferno::config::Config;
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "inferno-collapse-simpleperf",
+    about,
+    after_help = "\
+[1] This processes the text output of Android's simpleperf, created as follows:
+        simpleperf record -g -p <pid> -o perf.data
+        simpleperf report-sample --show-callchain -i perf.data > report-sample.txt
+    The perf script-like output of simpleperf's report_sample.py script can be processed with
+    inferno-collapse-perf instead.
+    "
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// All annotations (--kernel --jit)
+    #[clap(long = "all")]
+    all: bool,
+
+    /// Annotate Java and Kotlin functions run by ART with a `_[j]`
+    #[clap(long = "jit")]
+    jit: bool,
+
+    /// Annotate kernel functions with a `_[k]`
+    #[clap(long = "kernel")]
+    kernel: bool,
+
+    /// Include the process name of each sample as its root frame
+    #[clap(long = "process")]
+    process: bool,
+
+    /// Count every sample once instead of weighting it by its event count
+    #[clap(long = "count-samples")]
+    count_samples: bool,
+
+    /// Write a metadata header, like the units of the counts, before the folded stacks
+    #[clap(long = "header")]
+    header: bool,
+
+    /// Silence all log output
+    #[clap(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
+    verbose: u8,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// simpleperf report-sample output file, or STDIN if not specified
+    #[clap(value_name = "PATH")]
+    infile: Option<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> (Option<PathBuf>, Options) {
+        let mut options = Options::default();
+        options.annotate_jit = self.jit || self.all;
+        options.annotate_kernel = self.kernel || self.all;
+        options.include_process = self.process;
+        options.count_samples = self.count_samples;
+        options.header = self.header;
+        (self.infile, options)
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt = Opt::parse();
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let (infile, options) = opt.into_parts();
+    let options = Config::discover()?.collapse("simpleperf", options)?;
+    Folder::from(options).collapse_file_to_stdout(infile.as_ref())
+}
@@ -0,0 +1,84 @@
+use std::io;
+use std::path::PathBuf;
+
+use clap::{ArgAction, Parser};
+use env_logger::Env;
+use inferno::collapse::speedscope::{Folder, Options};
+use inferno::collapse::Collapse;
+use inferno::config::Config;
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "inferno-collapse-speedscope",
+    about,
+    after_help = "\
+[1] This processes speedscope JSON files, like those written by:
+        dotnet-trace convert --format Speedscope trace.nettrace
+        rbspy record --format speedscope -- <program-to-profile>
+        py-spy record --format speedscope -o profile.json -- <program-to-profile>
+    "
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Count every sample of sampled profiles once instead of weighting it
+    #[clap(long = "count-samples")]
+    count_samples: bool,
+
+    /// Write a metadata header, like the units of the counts, before the folded stacks
+    #[clap(long = "header")]
+    header: bool,
+
+    /// Silence all log output
+    #[clap(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
+    verbose: u8,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// Index or name of the profile to fold [default: the active profile of the file]
+    #[clap(long = "profile", value_name = "PROFILE")]
+    profile: Option<String>,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// speedscope JSON file, or STDIN if not specified
+    #[clap(value_name = "PATH")]
+    infile: Option<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> (Option<PathBuf>, Options) {
+        let mut options = Options::default();
+        options.count_samples = self.count_samples;
+        options.header = self.header;
+        options.profile = self.profile;
+        (self.infile, options)
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt = Opt::parse();
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let (infile, options) = opt.into_parts();
+    let options = Config::discover()?.collapse("speedscope", options)?;
+    Folder::from(options).collapse_file_to_stdout(infile.as_ref())
+}
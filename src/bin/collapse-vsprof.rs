@@ -1,22 +1,28 @@
 use std::io;
 use std::path::PathBuf;
 
+use clap::builder::TypedValueParser;
 use clap::{ArgAction, Parser};
 use env_logger::Env;
-use inferno::collapse::vsprof::Folder;
+use inferno::collapse::vsprof::{Folder, ModuleNames, Options, Weight};
 use inferno::collapse::Collapse;
+use inferno::config::Config;
 
 #[derive(Debug, Parser)]
 #[clap(
     name = "inferno-collapse-vsprof",
     about,
     after_help = "\
-[1] This processes the call tree summary of the built in Visual Studio profiler"
+[1] This processes the call tree or caller/callee summary of the built in Visual Studio profiler"
 )]
 struct Opt {
     // ************* //
     // *** FLAGS *** //
     // ************* //
+    /// Write a metadata header, like the units of the counts, before the folded stacks
+    #[clap(long = "header")]
+    header: bool,
+
     /// Silence all log output
     #[clap(short = 'q', long = "quiet")]
     quiet: bool,
@@ -25,14 +31,58 @@ struct Opt {
     #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
     verbose: u8,
 
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// How to include the module of each function
+    #[clap(
+        long = "modules",
+        default_value = "strip",
+        value_parser = clap::builder::PossibleValuesParser::new(ModuleNames::VARIANTS).map(|s| s.parse::<ModuleNames>().unwrap()),
+        value_name = "STRING"
+    )]
+    modules: ModuleNames,
+
+    /// What to weigh stacks by: the number of calls, or the elapsed time (in microseconds)
+    #[clap(
+        long = "weight",
+        default_value = "calls",
+        value_parser = clap::builder::PossibleValuesParser::new(Weight::VARIANTS).map(|s| s.parse::<Weight>().unwrap()),
+        value_name = "STRING"
+    )]
+    weight: Weight,
+
+    /// Positions of the level, function name and number of calls columns, counting from 0, for
+    /// exports whose header isn't recognized
+    #[clap(
+        long = "columns",
+        value_name = "LEVEL,FUNCTION,CALLS",
+        value_delimiter = ',',
+        num_args = 3
+    )]
+    columns: Option<Vec<usize>>,
+
     // ************ //
     // *** ARGS *** //
     // ************ //
     #[clap(value_name = "PATH")]
-    /// Call tree summary file from the built in Visual Studio profiler, or STDIN if not specified
+    /// Call tree or caller/callee summary file from the built in Visual Studio profiler, or STDIN if not specified
     infile: Option<PathBuf>,
 }
 
+impl Opt {
+    fn into_parts(self) -> (Option<PathBuf>, Options) {
+        let mut options = Options::default();
+        options.header = self.header;
+        options.module_names = self.modules;
+        options.weight = self.weight;
+        options.column_indices = self
+            .columns
+            .map(|columns| [columns[0], columns[1], columns[2]]);
+        (self.infile, options)
+    }
+}
+
 fn main() -> io::Result<()> {
     let opt = Opt::parse();
 
@@ -48,5 +98,7 @@ fn main() -> io::Result<()> {
         .init();
     }
 
-    Folder::default().collapse_file_to_stdout(opt.infile)
+    let (infile, options) = opt.into_parts();
+    let options = Config::discover()?.collapse("vsprof", options)?;
+    Folder::from(options).collapse_file_to_stdout(infile.as_ref())
 }
@@ -1,10 +1,15 @@
 use std::io;
 use std::path::PathBuf;
 
+use clap::builder::TypedValueParser;
 use clap::{ArgAction, Parser};
 use env_logger::Env;
-use inferno::collapse::vtune::{Folder, Options};
-use inferno::collapse::Collapse;
+use inferno::collapse::vtune::{Folder, ModuleNames, Options};
+use inferno::collapse::{Collapse, DEFAULT_NTHREADS};
+use inferno::config::Config;
+use once_cell::sync::Lazy;
+
+static NTHREADS: Lazy<String> = Lazy::new(|| DEFAULT_NTHREADS.to_string());
 
 #[derive(Debug, Parser)]
 #[clap(
@@ -24,6 +29,14 @@ struct Opt {
     #[clap(long = "no-modules")]
     no_modules: bool,
 
+    /// Keep process and thread grouping rows as root frames instead of merging all threads
+    #[clap(long = "group-roots")]
+    group_roots: bool,
+
+    /// Write a metadata header, like the units of the counts, before the folded stacks
+    #[clap(long = "header")]
+    header: bool,
+
     /// Silence all log output
     #[clap(short = 'q', long = "quiet")]
     quiet: bool,
@@ -32,6 +45,31 @@ struct Opt {
     #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
     verbose: u8,
 
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// How to keep the module of each function: as a prefix, an annotation or a frame, or by collapsing runs of functions from one module
+    #[clap(
+        long = "modules",
+        default_value = "prefix",
+        value_parser = clap::builder::PossibleValuesParser::new(ModuleNames::VARIANTS).map(|s| s.parse::<ModuleNames>().unwrap()),
+        value_name = "STRING"
+    )]
+    modules: ModuleNames,
+
+    /// Metric column to use as weights, like "CPU Time" or "Instructions Retired" [default: CPU Time:Self]
+    #[clap(long = "weight-column", value_name = "COLUMN")]
+    weight_column: Option<String>,
+
+    /// Number of threads to use.
+    #[clap(
+        short = 'n',
+        long = "nthreads",
+        default_value = &**NTHREADS,
+        value_name = "UINT"
+    )]
+    nthreads: usize,
+
     // ************ //
     // *** ARGS *** //
     // ************ //
@@ -43,7 +81,12 @@ struct Opt {
 impl Opt {
     fn into_parts(self) -> (Option<PathBuf>, Options) {
         let mut options = Options::default();
+        options.header = self.header;
         options.no_modules = self.no_modules;
+        options.module_names = self.modules;
+        options.weight_column = self.weight_column;
+        options.group_roots = self.group_roots;
+        options.nthreads = self.nthreads;
         (self.infile, options)
     }
 }
@@ -64,5 +107,6 @@ fn main() -> io::Result<()> {
     }
 
     let (infile, options) = opt.into_parts();
+    let options = Config::discover()?.collapse("vtune", options)?;
     Folder::from(options).collapse_file_to_stdout(infile.as_ref())
 }
@@ -0,0 +1,89 @@
+use std::io;
+use std::path::PathBuf;
+
+use clap::{ArgAction, Parser};
+use env_logger::Env;
+use inferno::collapse::wpa::{Folder, Options};
+use inferno::collapse::Collapse;
+use inferno::config::Config;
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "inferno-collapse-wpa",
+    about,
+    after_help = "\
+[1] This processes the \"CPU Usage (Sampled)\" table of Windows Performance Analyzer, exported
+    to CSV with a Stack column, with its stack tree expanded or as a flat table. It can also be
+    exported without the user interface:
+        wpaexporter -i trace.etl -profile cpu.wpaProfile
+    "
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Add the process of each stack as its root frame
+    #[clap(long = "process")]
+    process: bool,
+
+    /// Don't include modules with function names
+    #[clap(long = "no-modules")]
+    no_modules: bool,
+
+    /// Write a metadata header, like the units of the counts, before the folded stacks
+    #[clap(long = "header")]
+    header: bool,
+
+    /// Silence all log output
+    #[clap(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
+    verbose: u8,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// Column to weight stacks by, like "Weight (in view) (ms)" [default: Count]
+    #[clap(long = "weight-column", value_name = "COLUMN")]
+    weight_column: Option<String>,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// WPA CSV export, or STDIN if not specified
+    #[clap(value_name = "PATH")]
+    infile: Option<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> (Option<PathBuf>, Options) {
+        let mut options = Options::default();
+        options.include_process = self.process;
+        options.no_modules = self.no_modules;
+        options.header = self.header;
+        options.weight_column = self.weight_column;
+        (self.infile, options)
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt = Opt::parse();
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let (infile, options) = opt.into_parts();
+    let options = Config::discover()?.collapse("wpa", options)?;
+    Folder::from(options).collapse_file_to_stdout(infile.as_ref())
+}
@@ -0,0 +1,106 @@
+use std::io;
+use std::path::PathBuf;
+
+use clap::{ArgAction, Parser};
+use env_logger::Env;
+use inferno::collapse::xctrace::{Folder, Options};
+use inferno::collapse::Collapse;
+use inferno::config::Config;
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "inferno-collapse-xctrace",
+    about,
+    after_help = "\
+[1] This processes the XML that xctrace writes for a table of an Instruments trace, like the
+    samples of the Time Profiler, exported as follows:
+        xctrace record --template 'Time Profiler' --output app.trace --launch -- ./app
+        xctrace export --input app.trace \\
+            --xpath '/trace-toc/run[@number=\"1\"]/data/table[@schema=\"time-profile\"]' > app.xml
+    Tables of Allocations events and os_signpost intervals (schema os-signpost-interval) are
+    exported the same way. Signpost intervals are counted by their duration in nanoseconds.
+    "
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Write a metadata header, like the table and the units of the counts, before the folded
+    /// stacks
+    #[clap(long = "header")]
+    header: bool,
+
+    /// Only fold the allocations that weren't freed by the end of the trace
+    #[clap(long = "live")]
+    live: bool,
+
+    /// Put the stacks of each run of the trace under a root frame like "Run 2", rather than
+    /// adding the runs up
+    #[clap(long = "runs")]
+    runs: bool,
+
+    /// Add the state of the thread, like [Running] or [Blocked], as the leaf frame of each stack
+    #[clap(long = "thread-states")]
+    thread_states: bool,
+
+    /// Count samples by their weight, like CPU time in nanoseconds or allocated bytes, rather
+    /// than once
+    #[clap(long = "weights")]
+    weights: bool,
+
+    /// Silence all log output
+    #[clap(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
+    verbose: u8,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// Only fold the samples of threads whose name contains this, like "Main Thread" or a
+    /// thread id like 0x8f5e3 (may be repeated)
+    #[clap(long = "thread", value_name = "STRING")]
+    thread: Vec<String>,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// xctrace export XML file, or STDIN if not specified
+    #[clap(value_name = "PATH")]
+    infile: Option<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> (Option<PathBuf>, Options) {
+        let mut options = Options::default();
+        options.threads = self.thread;
+        options.weights = self.weights;
+        options.thread_states = self.thread_states;
+        options.live = self.live;
+        options.runs = self.runs;
+        options.header = self.header;
+        (self.infile, options)
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt = Opt::parse();
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let (infile, options) = opt.into_parts();
+    let options = Config::discover()?.collapse("xctrace", options)?;
+    Folder::from(options).collapse_file_to_stdout(infile.as_ref())
+}
@@ -3,7 +3,9 @@ use std::path::PathBuf;
 
 use clap::{ArgAction, Parser};
 use env_logger::Env;
+use inferno::config::Config;
 use inferno::differential::{self, Options};
+use inferno::rewrite::Rules;
 use is_terminal::IsTerminal;
 
 #[derive(Debug, Parser)]
@@ -25,16 +27,25 @@ and pass the --negate flag to inferno-flamegraph like this:
 
   $ inferno-diff-folded folded2 folded1 | inferno-flamegraph --negate > diff1.svg
 
+To see which stacks gained or lost the most of the profile between runs of
+different lengths, write a report of percentages instead:
+
+  $ inferno-diff-folded --percentages folded1 folded2 | head
+
 You can use the inferno-collapse-* tools to generate the folded files."
 )]
 struct Opt {
     // ************* //
     // *** FLAGS *** //
     // ************* //
-    /// Normalize sample counts
+    /// Normalize sample counts, scaling the first profile so its total matches the second
     #[clap(short = 'n', long = "normalize")]
     normalize: bool,
 
+    /// Write the percentage of each profile that each stack has, and the change in percentage points, instead of the counts
+    #[clap(short = 'p', long = "percentages")]
+    percentages: bool,
+
     /// Strip hex numbers (addresses)
     #[clap(short = 's', long = "strip-hex")]
     strip_hex: bool,
@@ -47,6 +58,14 @@ struct Opt {
     #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
     verbose: u8,
 
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// Rewrite the frames of both profiles with the rules in this file before matching stacks.
+    /// See the documentation of inferno::rewrite::Rules for the format of the file
+    #[clap(long = "rewrite-rules", value_name = "PATH")]
+    rewrite_rules: Option<PathBuf>,
+
     // ************ //
     // *** ARGS *** //
     // ************ //
@@ -60,15 +79,21 @@ struct Opt {
 }
 
 impl Opt {
-    fn into_parts(self) -> (PathBuf, PathBuf, Options) {
-        (
+    fn into_parts(self) -> io::Result<(PathBuf, PathBuf, Options)> {
+        let rules = match self.rewrite_rules {
+            Some(path) => Rules::from_file(path)?,
+            None => Rules::default(),
+        };
+        Ok((
             self.path1,
             self.path2,
             Options {
                 normalize: self.normalize,
                 strip_hex: self.strip_hex,
+                percentages: self.percentages,
+                rules,
             },
-        )
+        ))
     }
 }
 
@@ -87,7 +112,8 @@ fn main() -> io::Result<()> {
         .init();
     }
 
-    let (folded1, folded2, options) = opt.into_parts();
+    let (folded1, folded2, options) = opt.into_parts()?;
+    let options = Config::discover()?.differential(options)?;
 
     if std::io::stdout().is_terminal() {
         differential::from_files(options, folded1, folded2, io::stdout().lock())
@@ -0,0 +1,89 @@
+use std::io;
+use std::path::PathBuf;
+
+use clap::{ArgAction, Parser};
+use env_logger::Env;
+use inferno::downsample::{self, Options};
+use is_terminal::IsTerminal;
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "inferno-downsample",
+    about,
+    after_help = "\
+Reduces a folded stack profile to fewer samples or unique stacks, while
+keeping the proportions between stacks, so that huge profiles render quickly.
+
+  $ inferno-downsample --samples 100000 --stacks 20000 huge.folded | inferno-flamegraph > flamegraph.svg
+
+Each stack ends up within one sample of its exact share of the samples.
+Stacks are picked with weighted reservoir sampling, so heavy stacks are kept."
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Silence all log output
+    #[clap(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
+    verbose: u8,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// Scale the sample counts to add up to this many samples
+    #[clap(long = "samples", value_name = "UINT")]
+    samples: Option<usize>,
+
+    /// Keep at most this many unique stacks
+    #[clap(long = "stacks", value_name = "UINT")]
+    stacks: Option<usize>,
+
+    /// Seed of the random picks of stacks
+    #[clap(long = "seed", default_value = "0", value_name = "UINT")]
+    seed: u64,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// Folded stack files. With no PATH, or PATH is -, read STDIN.
+    #[clap(name = "PATH", value_parser)]
+    infiles: Vec<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> (Vec<PathBuf>, Options) {
+        let mut options = Options::default();
+        options.samples = self.samples;
+        options.stacks = self.stacks;
+        options.seed = self.seed;
+        (self.infiles, options)
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt = Opt::parse();
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let (infiles, options) = opt.into_parts();
+
+    if std::io::stdout().is_terminal() {
+        downsample::from_files(options, &infiles, io::stdout().lock())
+    } else {
+        downsample::from_files(options, &infiles, io::BufWriter::new(io::stdout().lock()))
+    }
+}
@@ -0,0 +1,204 @@
+use std::io;
+use std::path::PathBuf;
+
+use clap::builder::TypedValueParser;
+use clap::{ArgAction, Parser};
+use env_logger::Env;
+use inferno::export::{self, Format, Options};
+use is_terminal::IsTerminal;
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "inferno-export",
+    about,
+    after_help = "\
+Converts folded stack profiles to the formats of other profile viewers.
+
+  $ inferno-export --format pprof stacks.folded > profile.pb.gz
+  $ go tool pprof -http=:8080 profile.pb.gz
+
+You can use the inferno-collapse-* tools to generate the folded files."
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Write a row for each function, instead of for each frame of each stack, in table formats
+    #[clap(long = "per-function")]
+    per_function: bool,
+
+    /// Read the profile named by --profile-name from the --sqlite database, and write it out as
+    /// folded stacks
+    #[cfg(feature = "sqlite")]
+    #[clap(long = "read-sqlite", requires = "sqlite")]
+    read_sqlite: bool,
+
+    /// Treat the root frame of each stack as its thread, and give each thread its own track
+    #[clap(long = "thread-roots")]
+    thread_roots: bool,
+
+    /// Silence all log output
+    #[clap(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
+    verbose: u8,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// Format to export to
+    #[clap(
+        long = "format",
+        default_value = "pprof",
+        value_parser = clap::builder::PossibleValuesParser::new(Format::VARIANTS).map(|s| s.parse::<Format>().unwrap()),
+        value_name = "STRING"
+    )]
+    format: Format,
+
+    /// What the sample counts measure
+    #[clap(long = "sample-type", default_value = "samples", value_name = "STRING")]
+    sample_type: String,
+
+    /// Unit of the sample counts
+    #[clap(long = "sample-unit", default_value = "count", value_name = "STRING")]
+    sample_unit: String,
+
+    /// Leave functions and calls in less than this percentage of samples out of call graphs and
+    /// statistics
+    #[clap(long = "min-percent", default_value = "0", value_name = "FLOAT")]
+    min_percent: f64,
+
+    /// Push the stacks to the Pyroscope server at this URL instead of writing them out
+    #[cfg(feature = "push")]
+    #[clap(long = "push", value_name = "URL")]
+    push: Option<String>,
+
+    /// Application name to push the stacks as
+    #[cfg(feature = "push")]
+    #[clap(long = "app-name", default_value = "inferno", value_name = "STRING")]
+    app_name: String,
+
+    /// Label to push the stacks with (may be repeated)
+    #[cfg(feature = "push")]
+    #[clap(long = "label", value_parser = parse_label, value_name = "KEY=VALUE")]
+    labels: Vec<(String, String)>,
+
+    /// Bearer token to push the stacks with
+    #[cfg(feature = "push")]
+    #[clap(long = "auth-token", value_name = "STRING")]
+    auth_token: Option<String>,
+
+    /// Store the stacks as a profile in this SQLite database instead of writing them out
+    #[cfg(feature = "sqlite")]
+    #[clap(long = "sqlite", value_name = "PATH")]
+    sqlite: Option<PathBuf>,
+
+    /// Name to store the profile under in the SQLite database, or to read it by
+    #[cfg(feature = "sqlite")]
+    #[clap(
+        long = "profile-name",
+        default_value = "inferno",
+        value_name = "STRING"
+    )]
+    profile_name: String,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// Folded stack files. With no PATH, or PATH is -, read STDIN.
+    #[clap(name = "PATH", value_parser)]
+    infiles: Vec<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> (Vec<PathBuf>, Options) {
+        let mut options = Options::default();
+        options.format = self.format;
+        options.min_percent = self.min_percent;
+        options.per_function = self.per_function;
+        options.sample_type = self.sample_type;
+        options.sample_unit = self.sample_unit;
+        options.thread_roots = self.thread_roots;
+        (self.infiles, options)
+    }
+
+    #[cfg(feature = "push")]
+    fn push_options(&self) -> Option<export::push::Options> {
+        let mut options = export::push::Options::default();
+        options.url = self.push.clone()?;
+        options.app_name = self.app_name.clone();
+        options.labels = self.labels.clone();
+        options.auth_token = self.auth_token.clone();
+        options.units = match self.sample_unit.as_str() {
+            "bytes" => "bytes",
+            "objects" => "objects",
+            _ => "samples",
+        }
+        .to_string();
+        Some(options)
+    }
+
+    #[cfg(feature = "sqlite")]
+    fn sqlite_options(&self) -> export::sqlite::Options {
+        let mut options = export::sqlite::Options::default();
+        options.name = self.profile_name.clone();
+        options.metadata = vec![
+            ("sample_type".to_string(), self.sample_type.clone()),
+            ("sample_unit".to_string(), self.sample_unit.clone()),
+        ];
+        options
+    }
+}
+
+#[cfg(feature = "push")]
+fn parse_label(label: &str) -> Result<(String, String), String> {
+    match label.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("expected KEY=VALUE, got '{}'", label)),
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt = Opt::parse();
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    #[cfg(feature = "sqlite")]
+    if let Some(database) = &opt.sqlite {
+        if opt.read_sqlite {
+            return export::sqlite::to_folded(
+                database,
+                &opt.profile_name,
+                io::BufWriter::new(io::stdout().lock()),
+            );
+        }
+        return export::sqlite::from_files(&opt.sqlite_options(), &opt.infiles, database)
+            .map(|_| ());
+    }
+
+    #[cfg(feature = "push")]
+    let push = opt.push_options();
+    let (infiles, options) = opt.into_parts();
+    #[cfg(feature = "push")]
+    if let Some(push) = push {
+        return export::push::from_files(&push, &infiles);
+    }
+
+    if std::io::stdout().is_terminal() {
+        export::from_files(&options, &infiles, io::stdout().lock())
+    } else {
+        export::from_files(&options, &infiles, io::BufWriter::new(io::stdout().lock()))
+    }
+}
@@ -0,0 +1,69 @@
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::PathBuf;
+
+use clap::{ArgAction, Parser};
+use env_logger::Env;
+use inferno::flamegraph;
+use is_terminal::IsTerminal;
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "inferno-extract-folded",
+    about,
+    after_help = "\
+Recovers the folded stacks that were embedded in an SVG flame graph with
+inferno-flamegraph --embed-folded, so that it can be plotted again with other
+options, or compared with another profile.
+
+  $ inferno-flamegraph --embed-folded stacks.folded > flamegraph.svg
+  $ inferno-extract-folded flamegraph.svg | inferno-flamegraph --inverted > icicle.svg"
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Silence all log output
+    #[clap(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
+    verbose: u8,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// SVG flame graph. With no PATH, or PATH is -, read STDIN.
+    #[clap(name = "PATH", value_parser)]
+    infile: Option<PathBuf>,
+}
+
+fn main() -> io::Result<()> {
+    let opt = Opt::parse();
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let stdout = io::stdout();
+    let writer: Box<dyn io::Write> = if stdout.is_terminal() {
+        Box::new(stdout.lock())
+    } else {
+        Box::new(io::BufWriter::new(stdout.lock()))
+    };
+    match opt.infile {
+        Some(ref path) if path.to_str() != Some("-") => {
+            flamegraph::extract_folded(BufReader::new(File::open(path)?), writer)
+        }
+        _ => flamegraph::extract_folded(io::stdin().lock(), writer),
+    }
+}
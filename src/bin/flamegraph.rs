@@ -1,28 +1,62 @@
+use std::fs;
 use std::io;
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use clap::builder::TypedValueParser;
-use clap::{ArgAction, Parser};
+use clap::{ArgAction, ArgGroup, Parser};
 use env_logger::Env;
+use inferno::collapse::DEFAULT_NTHREADS;
+use inferno::config::Config;
 use inferno::flamegraph::color::{
     parse_hex_color, BackgroundColor, Color, PaletteMap, SearchColor, StrokeColor,
 };
-use inferno::flamegraph::{self, defaults, Direction, Options, Palette, TextTruncateDirection};
+use inferno::flamegraph::{
+    self, defaults, ColorMode, CountUnit, Direction, FoldedEncoding, FontMetrics, Format,
+    FrameLinks, MultiInput, Options, Palette, StreamOptions, TextTruncateDirection, Theme,
+};
+use inferno::leak::{self, Weight};
+use inferno::rewrite::{self, Rules};
 
 #[cfg(feature = "nameattr")]
 use inferno::flamegraph::FuncFrameAttrsMap;
 use is_terminal::IsTerminal;
+use log::{error, info};
+use once_cell::sync::Lazy;
+
+static NTHREADS: Lazy<String> = Lazy::new(|| DEFAULT_NTHREADS.to_string());
 
 #[derive(Debug, Parser)]
-#[clap(name = "inferno-flamegraph", about)]
+#[clap(
+    name = "inferno-flamegraph",
+    about,
+    group = ArgGroup::new("destination").args(["output", "open"]).multiple(true)
+)]
 struct Opt {
     // ************* //
     // *** FLAGS *** //
     // ************* //
+    /// Replace frames narrower than --minwidth with frames that say how many were omitted
+    #[clap(long = "aggregate-pruned")]
+    aggregate_pruned: bool,
+
+    /// Draw a band of the categories of stacks (annotated like `leaf_[c:gc]`) next to the roots
+    #[clap(long = "category-band")]
+    category_band: bool,
+
+    /// Make the SVG smaller for large profiles, with shared colors and names, less precise
+    /// coordinates, and titles and labels added by its script when they're needed
+    #[clap(long = "compact")]
+    compact: bool,
+
     /// Use consistent palette (palette.map)
     #[clap(long = "cp")]
     cp: bool,
 
+    /// Use a dark color scheme (same as --theme dark)
+    #[clap(long = "dark", conflicts_with = "theme")]
+    dark: bool,
+
     /// Colors are selected by hashing the function name, weighting earlier characters more
     /// heavily
     #[clap(long = "hash", conflicts_with = "deterministic")]
@@ -32,6 +66,15 @@ struct Opt {
     #[clap(long = "deterministic", conflicts_with = "hash")]
     deterministic: bool,
 
+    /// Write the flame graph to a temporary file instead of STDOUT, and open it in the browser
+    /// (the one in $BROWSER, or else the default one)
+    #[clap(long = "open")]
+    open: bool,
+
+    /// Keep the fractional part of sample counts instead of truncating them
+    #[clap(long = "fractional-samples")]
+    fractional_samples: bool,
+
     /// Plot the flame graph up-side-down
     #[clap(short = 'i', long = "inverted")]
     inverted: bool,
@@ -57,6 +100,15 @@ struct Opt {
     #[clap(name = "no-sort", long = "no-sort")]
     no_sort: bool,
 
+    /// Number of threads to sort the input lines and merge them into frames with
+    #[clap(
+        short = 'n',
+        long = "nthreads",
+        default_value = &**NTHREADS,
+        value_name = "UINT"
+    )]
+    nthreads: usize,
+
     /// Pretty print XML with newlines and indentation.
     #[clap(long = "pretty-xml")]
     pretty_xml: bool,
@@ -73,9 +125,18 @@ struct Opt {
     #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
     verbose: u8,
 
+    /// Write the flame graph again whenever the input files change, until interrupted
+    #[clap(long = "watch", requires = "destination", requires = "PATH")]
+    watch: bool,
+
     // *************** //
     // *** OPTIONS *** //
     // *************** //
+    /// Reload HTML flame graphs in the browser every this many seconds, like when they're
+    /// rewritten with --watch
+    #[clap(long = "auto-reload", value_name = "SECONDS")]
+    auto_reload: Option<u32>,
+
     /// Set background colors. Gradient choices are yellow (default), blue, green, grey; flat colors use "#rrggbb"
     #[clap(long = "bgcolors", value_name = "STRING")]
     bgcolors: Option<BackgroundColor>,
@@ -94,6 +155,16 @@ struct Opt {
     #[clap(long = "colordiffusion", conflicts_with = "colors")]
     color_diffusion: bool,
 
+    /// What frame colors show: their palette, or, with lines that end with a weight after their
+    /// count, their weight per count on a gradient from pale yellow to red
+    #[clap(
+        long = "colormode",
+        default_value = "palette",
+        value_parser = clap::builder::PossibleValuesParser::new(ColorMode::VARIANTS).map(|s| s.parse::<ColorMode>().unwrap()),
+        value_name = "STRING"
+    )]
+    colormode: ColorMode,
+
     /// Count type label
     #[clap(
         long = "countname",
@@ -102,6 +173,31 @@ struct Opt {
     )]
     countname: String,
 
+    /// Unit of the counts: samples, bytes, nanoseconds, or any other label. Bytes and
+    /// nanoseconds are shown in human-readable units (KiB, ms, ...)
+    #[clap(long = "countunit", default_value = "samples", value_name = "STRING")]
+    countunit: CountUnit,
+
+    /// Name of the weights of --colormode weight
+    #[clap(
+        long = "weightname",
+        default_value = defaults::WEIGHT_NAME,
+        value_name = "STRING"
+    )]
+    weightname: String,
+
+    /// Embed the folded stacks in SVG flame graphs, compressed with gzip or as plain text, so
+    /// that inferno-extract-folded can recover them
+    #[clap(
+        long = "embed-folded",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "gzip",
+        value_parser = clap::builder::PossibleValuesParser::new(FoldedEncoding::VARIANTS).map(|s| s.parse::<FoldedEncoding>().unwrap()),
+        value_name = "ENCODING"
+    )]
+    embed_folded: Option<FoldedEncoding>,
+
     /// Factor to scale sample counts by
     #[clap(
         long = "factor",
@@ -110,6 +206,32 @@ struct Opt {
     )]
     factor: f64,
 
+    /// Format of the flame graph: an SVG image, a self-contained HTML page with a search box,
+    /// zoom breadcrumbs, and per-frame statistics, a PNG image (with the `png` feature) as
+    /// wide as --width, with frames as high as --height, or a JSON tree of the merged frames
+    #[clap(
+        long = "format",
+        default_value = "svg",
+        value_parser = clap::builder::PossibleValuesParser::new(Format::VARIANTS).map(|s| s.parse::<Format>().unwrap()),
+        value_name = "STRING"
+    )]
+    format: Format,
+
+    /// Link frames to a URL, filling in {func} with the function name, and {1}, {2}, ... or
+    /// {name} with the groups captured by --frameurl-pattern
+    #[clap(long = "frameurl", value_name = "TEMPLATE")]
+    frameurl: Option<String>,
+
+    /// Regular expression that captures placeholders of --frameurl from function names. Frames
+    /// of functions it doesn't match aren't linked
+    #[clap(
+        long = "frameurl-pattern",
+        requires = "frameurl",
+        value_parser = |s: &str| regex::Regex::new(s).map(|_| s.to_string()),
+        value_name = "REGEX"
+    )]
+    frameurl_pattern: Option<String>,
+
     /// Font size
     #[clap(
         long = "fontsize",
@@ -134,6 +256,12 @@ struct Opt {
     )]
     fontwidth: f64,
 
+    /// Character widths to fit function names in frames with: the name of a common font (like
+    /// Arial, Verdana, or monospace), auto for those of --fonttype, or a file of lines of a
+    /// character and its width in font sizes (like `m 0.833`)
+    #[clap(long = "fontmetrics", value_name = "FONT|PATH")]
+    fontmetrics: Option<String>,
+
     /// Color of UI text such as the search and reset zoom buttons
     #[clap(
         long = "uicolor",
@@ -146,6 +274,31 @@ struct Opt {
     )]
     uicolor: Color,
 
+    /// How to combine several input files: merge them, give each its own root frame named
+    /// after the file (per-file), or make a differential flame graph of two files (diff)
+    #[clap(
+        long = "multi-input",
+        default_value = "merge",
+        conflicts_with_all = ["leaks", "rewrite_rules", "root"],
+        value_parser = clap::builder::PossibleValuesParser::new(MultiInput::VARIANTS).map(|s| s.parse::<MultiInput>().unwrap()),
+        value_name = "STRING"
+    )]
+    multi_input: MultiInput,
+
+    /// Write the flame graph to this file instead of STDOUT
+    #[clap(short = 'o', long = "output", value_name = "PATH")]
+    output: Option<PathBuf>,
+
+    /// Color scheme of the flame graph: light, dark, or auto, which is dark when the viewer
+    /// prefers a dark color scheme. The dark scheme replaces the background and UI colors
+    #[clap(
+        long = "theme",
+        default_value = "light",
+        value_parser = clap::builder::PossibleValuesParser::new(Theme::VARIANTS).map(|s| s.parse::<Theme>().unwrap()),
+        value_name = "STRING"
+    )]
+    theme: Theme,
+
     /// Height of each frame
     #[clap(
         long = "height",
@@ -154,6 +307,24 @@ struct Opt {
     )]
     height: usize,
 
+    /// Plot folded stacks with allocated and freed bytes as a leak flame graph, with frames
+    /// weighted by the outstanding or allocated bytes
+    #[clap(
+        long = "leaks",
+        value_parser = clap::builder::PossibleValuesParser::new(Weight::VARIANTS).map(|s| s.parse::<Weight>().unwrap()),
+        value_name = "WEIGHT"
+    )]
+    leaks: Option<Weight>,
+
+    /// Sort the stacks with about <MIB> MiB of memory, in runs written to temporary files, and
+    /// draw frames as soon as they're found, for SVG flame graphs of very large inputs
+    #[clap(long = "max-memory", value_name = "MIB")]
+    max_memory: Option<usize>,
+
+    /// Cut stacks deeper than <UINT> frames, replacing the deeper frames with a `...` frame
+    #[clap(long = "maxdepth", value_name = "UINT")]
+    maxdepth: Option<usize>,
+
     /// Omit functions smaller than <FLOAT> percent
     #[clap(
         long = "minwidth",
@@ -197,6 +368,16 @@ struct Opt {
     )]
     stroke_color: StrokeColor,
 
+    /// Rewrite frames with the rules in this file before plotting.
+    /// See the documentation of inferno::rewrite::Rules for the format of the file
+    #[clap(long = "rewrite-rules", value_name = "PATH")]
+    rewrite_rules: Option<PathBuf>,
+
+    /// Re-root stacks at their first frame matching this regular expression, leaving out
+    /// the stacks that never reach it (applied after --rewrite-rules)
+    #[clap(long = "root", value_name = "REGEX")]
+    root: Option<String>,
+
     /// Second level title (optional)
     #[clap(long = "subtitle", value_name = "STRING")]
     subtitle: Option<String>,
@@ -258,13 +439,20 @@ impl<'a> Opt {
         }
         options.negate_differentials = self.negate;
         options.factor = self.factor;
+        options.fractional_samples = self.fractional_samples;
+        options.format = self.format;
+        options.compact = self.compact;
+        options.auto_reload = self.auto_reload;
         options.pretty_xml = self.pretty_xml;
         options.no_sort = self.no_sort;
+        options.nthreads = self.nthreads;
         options.no_javascript = self.no_javascript;
         options.color_diffusion = self.color_diffusion;
         options.reverse_stack_order = self.reverse;
         options.flame_chart = self.flame_chart;
+        options.category_band = self.category_band;
         options.base = self.base;
+        options.embed_folded = self.embed_folded;
 
         if self.flame_chart && self.title == defaults::TITLE {
             options.title = defaults::CHART_TITLE.to_owned();
@@ -275,10 +463,35 @@ impl<'a> Opt {
         options.image_width = self.width;
         options.frame_height = self.height;
         options.min_width = self.minwidth;
-        options.font_type = self.fonttype;
+        options.aggregate_pruned = self.aggregate_pruned;
+        options.max_depth = self.maxdepth;
         options.font_size = self.fontsize;
         options.font_width = self.fontwidth;
+        if let Some(ref metrics) = self.fontmetrics {
+            let font = if metrics == "auto" {
+                &self.fonttype
+            } else {
+                metrics
+            };
+            options.font_metrics = Some(match FontMetrics::for_font(font) {
+                Some(metrics) => metrics,
+                None if metrics == "auto" => panic!("No font metrics for {}", font),
+                None => {
+                    let mut metrics = FontMetrics::monospace(self.fontwidth);
+                    if let Err(e) = fs::File::open(font)
+                        .and_then(|file| metrics.read_advances(io::BufReader::new(file)))
+                    {
+                        panic!("Error reading {}: {:?}", font, e);
+                    }
+                    metrics
+                }
+            });
+        }
+        options.font_type = self.fonttype;
         options.count_name = self.countname;
+        options.count_unit = self.countunit;
+        options.color_mode = self.colormode;
+        options.weight_name = self.weightname;
         options.name_type = self.nametype;
         if let Some(notes) = self.notes {
             options.notes = notes;
@@ -288,6 +501,17 @@ impl<'a> Opt {
         options.search_color = self.search_color;
         options.stroke_color = self.stroke_color;
         options.uicolor = self.uicolor;
+        options.multi_input = self.multi_input;
+        options.theme = if self.dark { Theme::Dark } else { self.theme };
+        if let Some(template) = self.frameurl {
+            let links = FrameLinks::new(template);
+            options.frame_links = Some(match self.frameurl_pattern {
+                Some(pattern) => links
+                    .with_pattern(&pattern)
+                    .expect("the pattern was checked when parsing arguments"),
+                None => links,
+            });
+        }
         (self.infiles, options)
     }
 
@@ -310,7 +534,7 @@ impl<'a> Opt {
 const PALETTE_MAP_FILE: &str = "palette.map"; // default name for the palette map file
 
 fn main() -> quick_xml::Result<()> {
-    let opt = Opt::parse();
+    let mut opt = Opt::parse();
 
     // Initialize logger
     if !opt.quiet {
@@ -329,23 +553,149 @@ fn main() -> quick_xml::Result<()> {
         Err(e) => panic!("Error reading {}: {:?}", PALETTE_MAP_FILE, e),
     };
 
-    let (infiles, mut options) = opt.into_parts();
+    let passes = Passes {
+        rewrite_rules: opt.rewrite_rules.take(),
+        root: opt.root.take(),
+        leak_weight: opt.leaks.take(),
+    };
+    let stream = opt.max_memory.take().map(|mib| {
+        let mut stream = StreamOptions::default();
+        stream.memory_limit = mib * 1024 * 1024;
+        stream
+    });
+    let output = opt.output.take();
+    let open = opt.open;
+    let watch = opt.watch;
+    let (infiles, options) = opt.into_parts();
+    let mut options = Config::discover()
+        .and_then(|config| config.flamegraph(options))
+        .map_err(quick_xml::Error::Io)?;
+    let output = match (output, open) {
+        (Some(output), _) => Some(output),
+        (None, true) => Some(flamegraph::temp_path(options.format)),
+        (None, false) => None,
+    };
 
     options.palette_map = palette_map.as_mut();
 
-    if std::io::stdout().is_terminal() {
-        flamegraph::from_files(&mut options, &infiles, io::stdout().lock())?;
-    } else {
-        flamegraph::from_files(
-            &mut options,
-            &infiles,
-            io::BufWriter::new(io::stdout().lock()),
-        )?;
+    match &output {
+        Some(path) => {
+            flamegraph::write_atomically(path, |writer| {
+                write_flamegraph(&mut options, &passes, stream.as_ref(), &infiles, writer)
+            })?;
+            info!("Wrote {}", path.display());
+            if open {
+                flamegraph::open_in_browser(path).map_err(quick_xml::Error::Io)?;
+            }
+        }
+        None if io::stdout().is_terminal() => {
+            write_flamegraph(
+                &mut options,
+                &passes,
+                stream.as_ref(),
+                &infiles,
+                io::stdout().lock(),
+            )?;
+        }
+        None => {
+            write_flamegraph(
+                &mut options,
+                &passes,
+                stream.as_ref(),
+                &infiles,
+                io::BufWriter::new(io::stdout().lock()),
+            )?;
+        }
+    }
+
+    if let (true, Some(path)) = (watch, &output) {
+        let mut inputs = infiles.clone();
+        inputs.extend(passes.rewrite_rules.clone());
+        flamegraph::watch(&inputs, WATCH_INTERVAL, || {
+            // The inputs may be half-written, so errors are only logged until the next change.
+            match flamegraph::write_atomically(path, |writer| {
+                write_flamegraph(&mut options, &passes, stream.as_ref(), &infiles, writer)
+            }) {
+                Ok(()) => info!("Wrote {}", path.display()),
+                Err(e) => error!("Error writing {}: {}", path.display(), e),
+            }
+            Ok(())
+        })
+        .map_err(quick_xml::Error::Io)?;
     }
 
+    // The options borrow the palette map.
+    drop(options);
     save_consistent_palette_if_needed(&palette_map, PALETTE_MAP_FILE).map_err(quick_xml::Error::Io)
 }
 
+const WATCH_INTERVAL: Duration = Duration::from_millis(500); // how often --watch checks the inputs
+
+// Passes over the folded stacks before they're plotted.
+struct Passes {
+    rewrite_rules: Option<PathBuf>,
+    root: Option<String>,
+    leak_weight: Option<Weight>,
+}
+
+impl Passes {
+    // Runs the passes over the input files, if there are any, which reads all of the input.
+    fn run(&self, infiles: &[PathBuf], options: &mut Options<'_>) -> io::Result<Option<Vec<u8>>> {
+        let mut folded = None;
+        if self.rewrite_rules.is_some() || self.root.is_some() {
+            let mut rules = match &self.rewrite_rules {
+                Some(path) => fs::read_to_string(path)?,
+                None => String::new(),
+            };
+            if let Some(root) = &self.root {
+                // Expressions of rules end at whitespace.
+                let root = root.replace(' ', "\\x20");
+                rules.push_str(&format!("\nroot {}\n", root));
+            }
+            let rules = Rules::parse(&rules)?;
+            let mut rewritten = Vec::new();
+            rewrite::from_files(&rules, infiles, &mut rewritten)?;
+            folded = Some(rewritten);
+        }
+        if let Some(weight) = self.leak_weight {
+            let mut leak_options = leak::Options::default();
+            leak_options.weight = weight;
+            let mut leaks = Vec::new();
+            match &folded {
+                Some(folded) => leak::from_reader(leak_options, &folded[..], &mut leaks),
+                None => leak::from_files(leak_options, infiles, &mut leaks),
+            }?;
+            folded = Some(leaks);
+            if options.count_name == defaults::COUNT_NAME {
+                options.count_name = "bytes".to_string();
+            }
+        }
+        Ok(folded)
+    }
+}
+
+// Plots the folded stacks from the passes, if there are any, or else those in the input files,
+// with bounded memory if `stream` is given.
+fn write_flamegraph<W: io::Write>(
+    options: &mut Options<'_>,
+    passes: &Passes,
+    stream: Option<&StreamOptions>,
+    infiles: &[PathBuf],
+    writer: W,
+) -> quick_xml::Result<()> {
+    match (
+        passes.run(infiles, options).map_err(quick_xml::Error::Io)?,
+        stream,
+    ) {
+        (Some(folded), None) => flamegraph::from_reader(options, &folded[..], writer),
+        (Some(folded), Some(stream)) => {
+            flamegraph::from_reader_streaming(options, stream, &folded[..], writer)
+        }
+        (None, None) => flamegraph::from_files(options, infiles, writer),
+        (None, Some(stream)) => flamegraph::from_files_streaming(options, stream, infiles, writer),
+    }
+}
+
 fn fetch_consistent_palette_if_needed(
     use_consistent_palette: bool,
     palette_file: &str,
@@ -376,7 +726,9 @@ fn save_consistent_palette_if_needed(
 mod tests {
     use super::Opt;
     use clap::Parser;
-    use inferno::flamegraph::{color, Direction, Options, Palette, TextTruncateDirection};
+    use inferno::flamegraph::{
+        color, CountUnit, Direction, Options, Palette, TextTruncateDirection,
+    };
     use pretty_assertions::assert_eq;
     use std::path::PathBuf;
     use std::str::FromStr;
@@ -421,6 +773,8 @@ mod tests {
             "10.5",
             "--countname",
             "test count name",
+            "--countunit",
+            "bytes",
             "--nametype",
             "test name type",
             "--notes",
@@ -448,6 +802,7 @@ mod tests {
         expected_options.font_width = 10.5;
         expected_options.text_truncate_direction = TextTruncateDirection::Right;
         expected_options.count_name = "test count name".to_string();
+        expected_options.count_unit = CountUnit::Bytes;
         expected_options.name_type = "test name type".to_string();
         expected_options.factor = 0.1;
         expected_options.notes = "Test notes".to_string();
@@ -0,0 +1,86 @@
+use std::io;
+use std::path::PathBuf;
+
+use clap::{ArgAction, Parser};
+use env_logger::Env;
+use inferno::heatmap::{self, Options};
+use is_terminal::IsTerminal;
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "inferno-heatmap",
+    about,
+    after_help = "\
+Renders a subsecond-offset heatmap of the samples in perf script output, with
+a column for each second and a row for each part of a second.
+
+  $ perf script | inferno-heatmap > heatmap.svg
+
+Click a cell and then another one in the heatmap to select a time range, and
+pass the --from and --to options it shows to inferno-collapse-perf:
+
+  $ perf script | inferno-collapse-perf --from 4794564.12 --to 4794566.5 | inferno-flamegraph > range.svg"
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Silence all log output
+    #[clap(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
+    verbose: u8,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// Number of rows to divide each second into
+    #[clap(long = "rows", default_value = "50", value_name = "UINT")]
+    rows: usize,
+
+    /// Change title text
+    #[clap(long = "title", default_value = "Heatmap", value_name = "STRING")]
+    title: String,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// Perf script output file, or STDIN if not specified
+    #[clap(value_name = "PATH")]
+    infile: Option<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> (Option<PathBuf>, Options) {
+        let mut options = Options::default();
+        options.rows = self.rows;
+        options.title = self.title;
+        (self.infile, options)
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt = Opt::parse();
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let (infile, options) = opt.into_parts();
+
+    if std::io::stdout().is_terminal() {
+        heatmap::from_file(&options, infile, io::stdout().lock())
+    } else {
+        heatmap::from_file(&options, infile, io::BufWriter::new(io::stdout().lock()))
+    }
+}
@@ -0,0 +1,95 @@
+use std::io;
+use std::path::PathBuf;
+
+use clap::{ArgAction, Parser};
+use env_logger::Env;
+use inferno::hotcold::{self, Options};
+use is_terminal::IsTerminal;
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "inferno-hotcold",
+    about,
+    after_help = "\
+Puts an on-CPU and an off-CPU folded stack profile together, counted in
+microseconds, so that a single flame graph shows where time was spent both
+running and blocked.
+
+  $ inferno-hotcold --frequency 99 on-cpu.folded off-cpu.folded \\
+      | inferno-flamegraph --colors hotcold --countname us > hotcold.svg
+
+Frames that only off-CPU stacks go through are annotated with _[o], which the
+hotcold palette colors blue, while on-CPU frames are colored warm."
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Silence all log output
+    #[clap(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
+    verbose: u8,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// Frequency in Hz that the on-CPU profile was sampled at
+    #[clap(long = "frequency", default_value = "99", value_name = "FLOAT")]
+    frequency: f64,
+
+    /// Microseconds that each count of the off-CPU profile stands for
+    #[clap(long = "off-cpu-scale", default_value = "1", value_name = "FLOAT")]
+    off_cpu_scale: f64,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// Path to the on-CPU folded stack profile
+    #[clap(value_name = "ON_CPU")]
+    on_cpu: PathBuf,
+
+    /// Path to the off-CPU folded stack profile
+    #[clap(value_name = "OFF_CPU")]
+    off_cpu: PathBuf,
+}
+
+impl Opt {
+    fn into_parts(self) -> (PathBuf, PathBuf, Options) {
+        let mut options = Options::default();
+        options.frequency = self.frequency;
+        options.off_cpu_scale = self.off_cpu_scale;
+        (self.on_cpu, self.off_cpu, options)
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt = Opt::parse();
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let (on_cpu, off_cpu, options) = opt.into_parts();
+
+    if std::io::stdout().is_terminal() {
+        hotcold::from_files(options, on_cpu, off_cpu, io::stdout().lock())
+    } else {
+        hotcold::from_files(
+            options,
+            on_cpu,
+            off_cpu,
+            io::BufWriter::new(io::stdout().lock()),
+        )
+    }
+}
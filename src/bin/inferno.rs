@@ -0,0 +1,503 @@
+use std::env;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+use std::time::Duration;
+
+use clap::builder::TypedValueParser;
+use clap::{ArgAction, Parser, Subcommand};
+use env_logger::Env;
+use inferno::collapse::{
+    bpftrace, callgrind, cpuprofile, d3, dtrace, gecko, ghcprof, guess, heaptrack, jfr, massif,
+    nettrace, perf, pmp, pprof, pyspy, recursive, sample, simpleperf, speedscope, stackprof, svg,
+    vsprof, vtune, wpa, xctrace, Collapse,
+};
+use inferno::config::Config;
+use inferno::differential;
+use inferno::flamegraph::{self, Direction, Options, Palette};
+use is_terminal::IsTerminal;
+use log::{error, info};
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "inferno",
+    about,
+    after_help = "\
+Runs the inferno tools as subcommands, so that they can be used from one binary:
+
+  $ inferno collapse perf perf.txt | inferno flamegraph > flamegraph.svg
+  $ inferno render perf.txt > flamegraph.svg
+
+The collapse, diff and flamegraph subcommands take the most common options of
+inferno-collapse-FORMAT, inferno-diff-folded and inferno-flamegraph. The others can be set in
+the tables of a .inferno.toml file, like [collapse.perf] or [flamegraph].
+
+Any other subcommand NAME runs the tool inferno-NAME, like `inferno merge-folded` for
+inferno-merge-folded. Tools are looked for next to this binary first, and then on the PATH."
+)]
+struct Opt {
+    #[clap(subcommand)]
+    command: Tool,
+}
+
+// The collapsers that `inferno collapse` can run, by the name of their module.
+const COLLAPSERS: &[&str] = &[
+    "bpftrace",
+    "callgrind",
+    "cpuprofile",
+    "d3",
+    "dtrace",
+    "gecko",
+    "ghcprof",
+    "guess",
+    "heaptrack",
+    "jfr",
+    "massif",
+    "nettrace",
+    "perf",
+    "pmp",
+    "pprof",
+    "pyspy",
+    "recursive",
+    "sample",
+    "simpleperf",
+    "speedscope",
+    "stackprof",
+    "svg",
+    "vsprof",
+    "vtune",
+    "wpa",
+    "xctrace",
+];
+
+#[derive(Debug, Subcommand)]
+enum Tool {
+    /// Collapse the output of a profiler into folded stacks, like inferno-collapse-FORMAT
+    Collapse {
+        // ************* //
+        // *** FLAGS *** //
+        // ************* //
+        /// Write a metadata header, like the units of the counts, before the folded stacks
+        #[clap(long = "header")]
+        header: bool,
+
+        /// Silence all log output
+        #[clap(short = 'q', long = "quiet")]
+        quiet: bool,
+
+        /// Verbose logging mode (-v, -vv, -vvv)
+        #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
+        verbose: u8,
+
+        // ************ //
+        // *** ARGS *** //
+        // ************ //
+        /// The format of the input, or guess to detect it
+        #[clap(
+            value_name = "FORMAT",
+            value_parser = clap::builder::PossibleValuesParser::new(COLLAPSERS)
+        )]
+        format: String,
+
+        /// Input file, or STDIN if not specified
+        #[clap(value_name = "PATH")]
+        infile: Option<PathBuf>,
+    },
+
+    /// Make a differential profile of two folded stack files, like inferno-diff-folded
+    Diff {
+        // ************* //
+        // *** FLAGS *** //
+        // ************* //
+        /// Normalize sample counts, scaling the first profile so its total matches the second
+        #[clap(short = 'n', long = "normalize")]
+        normalize: bool,
+
+        /// Write the percentage of each profile that each stack has, and the change in percentage points, instead of the counts
+        #[clap(short = 'p', long = "percentages")]
+        percentages: bool,
+
+        /// Strip hex numbers (addresses)
+        #[clap(short = 's', long = "strip-hex")]
+        strip_hex: bool,
+
+        /// Silence all log output
+        #[clap(short = 'q', long = "quiet")]
+        quiet: bool,
+
+        /// Verbose logging mode (-v, -vv, -vvv)
+        #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
+        verbose: u8,
+
+        // ************ //
+        // *** ARGS *** //
+        // ************ //
+        /// Path to folded stack profile 1
+        #[clap(value_name = "PATH1")]
+        path1: PathBuf,
+
+        /// Path to folded stack profile 2
+        #[clap(value_name = "PATH2")]
+        path2: PathBuf,
+    },
+
+    /// Plot folded stacks as an SVG flame graph, like inferno-flamegraph
+    Flamegraph {
+        // ************* //
+        // *** FLAGS *** //
+        // ************* //
+        /// Colors are selected by hashing the function name, weighting earlier characters more
+        /// heavily
+        #[clap(long = "hash")]
+        hash: bool,
+
+        /// Plot the flame graph up-side-down
+        #[clap(short = 'i', long = "inverted")]
+        inverted: bool,
+
+        /// Generate stack-reversed flame graph
+        #[clap(long = "reverse")]
+        reverse: bool,
+
+        /// Silence all log output
+        #[clap(short = 'q', long = "quiet")]
+        quiet: bool,
+
+        /// Verbose logging mode (-v, -vv, -vvv)
+        #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
+        verbose: u8,
+
+        // *************** //
+        // *** OPTIONS *** //
+        // *************** //
+        /// Set color palette
+        #[clap(
+            short = 'c',
+            long = "colors",
+            value_parser = clap::builder::PossibleValuesParser::new(Palette::VARIANTS).map(|s| s.parse::<Palette>().unwrap()),
+            value_name = "STRING"
+        )]
+        colors: Option<Palette>,
+
+        /// Count type label
+        #[clap(long = "countname", value_name = "STRING")]
+        countname: Option<String>,
+
+        /// Write the flame graph to this file instead of STDOUT
+        #[clap(short = 'o', long = "output", value_name = "PATH")]
+        output: Option<PathBuf>,
+
+        /// Title of the flame graph
+        #[clap(long = "title", value_name = "STRING")]
+        title: Option<String>,
+
+        // ************ //
+        // *** ARGS *** //
+        // ************ //
+        /// Collapsed perf output files. With no PATH, or PATH is -, read STDIN.
+        #[clap(value_name = "PATH")]
+        infiles: Vec<PathBuf>,
+    },
+
+    /// Guess the format of a profiler's output, collapse it, and plot it as an SVG flame graph
+    Render {
+        // ************* //
+        // *** FLAGS *** //
+        // ************* //
+        /// Silence all log output
+        #[clap(short = 'q', long = "quiet")]
+        quiet: bool,
+
+        /// Verbose logging mode (-v, -vv, -vvv)
+        #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
+        verbose: u8,
+
+        /// Render the flame graph again whenever the input file changes, until interrupted
+        #[clap(long = "watch", requires = "output", requires = "infile")]
+        watch: bool,
+
+        // *************** //
+        // *** OPTIONS *** //
+        // *************** //
+        /// Write the flame graph to this file instead of STDOUT
+        #[clap(short = 'o', long = "output", value_name = "PATH")]
+        output: Option<PathBuf>,
+
+        /// Title of the flame graph
+        #[clap(long = "title", value_name = "STRING")]
+        title: Option<String>,
+
+        // ************ //
+        // *** ARGS *** //
+        // ************ //
+        /// Profiler output file, or STDIN if not specified
+        #[clap(value_name = "PATH")]
+        infile: Option<PathBuf>,
+    },
+
+    #[clap(external_subcommand)]
+    Other(Vec<OsString>),
+}
+
+const WATCH_INTERVAL: Duration = Duration::from_millis(500); // how often --watch checks the input
+
+fn main() -> io::Result<()> {
+    let opt = Opt::parse();
+    match opt.command {
+        Tool::Collapse {
+            header,
+            quiet,
+            verbose,
+            format,
+            infile,
+        } => {
+            if !quiet {
+                init_logger(verbose);
+            }
+            collapse(&format, header, infile)
+        }
+        Tool::Diff {
+            normalize,
+            percentages,
+            strip_hex,
+            quiet,
+            verbose,
+            path1,
+            path2,
+        } => {
+            if !quiet {
+                init_logger(verbose);
+            }
+            let options = differential::Options {
+                normalize,
+                percentages,
+                strip_hex,
+                ..Default::default()
+            };
+            let options = Config::discover()?.differential(options)?;
+            if io::stdout().is_terminal() {
+                differential::from_files(options, path1, path2, io::stdout().lock())
+            } else {
+                differential::from_files(
+                    options,
+                    path1,
+                    path2,
+                    io::BufWriter::new(io::stdout().lock()),
+                )
+            }
+        }
+        Tool::Flamegraph {
+            hash,
+            inverted,
+            reverse,
+            quiet,
+            verbose,
+            colors,
+            countname,
+            output,
+            title,
+            infiles,
+        } => {
+            if !quiet {
+                init_logger(verbose);
+            }
+            let mut options = Options::default();
+            options.hash = hash;
+            if inverted {
+                options.direction = Direction::Inverted;
+            }
+            options.reverse_stack_order = reverse;
+            if let Some(colors) = colors {
+                options.colors = colors;
+            }
+            if let Some(countname) = countname {
+                options.count_name = countname;
+            }
+            if let Some(title) = title {
+                options.title = title;
+            }
+            let mut options = Config::discover()?.flamegraph(options)?;
+            let result = match &output {
+                Some(path) => flamegraph::write_atomically(path, |writer| {
+                    flamegraph::from_files(&mut options, &infiles, writer)
+                }),
+                None if io::stdout().is_terminal() => {
+                    flamegraph::from_files(&mut options, &infiles, io::stdout().lock())
+                }
+                None => flamegraph::from_files(
+                    &mut options,
+                    &infiles,
+                    io::BufWriter::new(io::stdout().lock()),
+                ),
+            };
+            result.map_err(into_io_error)
+        }
+        Tool::Render {
+            quiet,
+            verbose,
+            watch,
+            output,
+            title,
+            infile,
+        } => {
+            if !quiet {
+                init_logger(verbose);
+            }
+            render(infile, output, watch, title)
+        }
+        Tool::Other(args) => match args.split_first() {
+            Some((name, args)) => run_tool(&name.to_string_lossy(), args),
+            None => unreachable!("external subcommands have a name"),
+        },
+    }
+}
+
+fn init_logger(verbose: u8) {
+    env_logger::Builder::from_env(Env::default().default_filter_or(match verbose {
+        0 => "warn",
+        1 => "info",
+        2 => "debug",
+        _ => "trace",
+    }))
+    .format_timestamp(None)
+    .init();
+}
+
+fn render(
+    infile: Option<PathBuf>,
+    output: Option<PathBuf>,
+    watch: bool,
+    title: Option<String>,
+) -> io::Result<()> {
+    let mut options = Options::default();
+    if let Some(title) = title {
+        options.title = title;
+    }
+    let config = Config::discover()?;
+    let mut options = config.flamegraph(options)?;
+
+    let mut collapse_options = guess::Options::default();
+    // The header tells the flame graph what the counts are.
+    collapse_options.header = true;
+    collapse_options.plugins = guess::discover_plugins();
+    let collapse_options = config.collapse("guess", collapse_options)?;
+
+    let result = match &output {
+        Some(path) => flamegraph::write_atomically(path, |writer| {
+            render_file(infile.as_deref(), &collapse_options, &mut options, writer)
+        }),
+        None if io::stdout().is_terminal() => render_file(
+            infile.as_deref(),
+            &collapse_options,
+            &mut options,
+            io::stdout().lock(),
+        ),
+        None => render_file(
+            infile.as_deref(),
+            &collapse_options,
+            &mut options,
+            io::BufWriter::new(io::stdout().lock()),
+        ),
+    };
+    result.map_err(into_io_error)?;
+
+    if let (true, Some(infile), Some(path)) = (watch, &infile, &output) {
+        flamegraph::watch(&[infile], WATCH_INTERVAL, || {
+            // The input may be half-written, so errors are only logged until the next change.
+            match flamegraph::write_atomically(path, |writer| {
+                render_file(
+                    Some(infile.as_path()),
+                    &collapse_options,
+                    &mut options,
+                    writer,
+                )
+            }) {
+                Ok(()) => info!("Wrote {}", path.display()),
+                Err(e) => error!("Error writing {}: {}", path.display(), e),
+            }
+            Ok(())
+        })?;
+    }
+    Ok(())
+}
+
+// Collapses the input with the options of the `[collapse.<format>]` table and a metadata header,
+// for the collapsers that can write one.
+macro_rules! collapse {
+    ($format:expr, $infile:expr, $header:expr; $($name:ident),*; $($headerless:ident),*) => {
+        match $format {
+            $(
+                stringify!($name) => {
+                    let mut options = $name::Options::default();
+                    options.header = $header;
+                    let options = Config::discover()?.collapse(stringify!($name), options)?;
+                    $name::Folder::from(options).collapse_file_to_stdout($infile)
+                }
+            )*
+            $(
+                stringify!($headerless) => {
+                    let options = $headerless::Options::default();
+                    let options = Config::discover()?.collapse(stringify!($headerless), options)?;
+                    $headerless::Folder::from(options).collapse_file_to_stdout($infile)
+                }
+            )*
+            _ => unreachable!("possible values are collapsers"),
+        }
+    };
+}
+
+fn collapse(format: &str, header: bool, infile: Option<PathBuf>) -> io::Result<()> {
+    if format == "guess" {
+        let mut options = guess::Options::default();
+        options.header = header;
+        options.plugins = guess::discover_plugins();
+        let options = Config::discover()?.collapse("guess", options)?;
+        return guess::Folder::from(options).collapse_file_to_stdout(infile.as_ref());
+    }
+    collapse!(
+        format, infile.as_ref(), header;
+        callgrind, cpuprofile, dtrace, gecko, ghcprof, heaptrack, jfr, massif, nettrace, perf,
+        pprof, pyspy, sample, simpleperf, speedscope, stackprof, vsprof, vtune, wpa, xctrace;
+        bpftrace, d3, pmp, recursive, svg
+    )
+}
+
+// Collapses the input with the format it's guessed to be in, and plots the folded stacks.
+fn render_file<W: io::Write>(
+    infile: Option<&Path>,
+    collapse_options: &guess::Options,
+    options: &mut Options<'_>,
+    writer: W,
+) -> quick_xml::Result<()> {
+    let reader: Box<dyn io::BufRead> = match infile {
+        Some(path) => Box::new(io::BufReader::new(
+            File::open(path).map_err(quick_xml::Error::Io)?,
+        )),
+        None => Box::new(io::stdin().lock()),
+    };
+    inferno::render(reader, writer, collapse_options.clone(), options)
+}
+
+fn into_io_error(e: quick_xml::Error) -> io::Error {
+    match e {
+        quick_xml::Error::Io(e) => e,
+        e => io::Error::new(io::ErrorKind::Other, e),
+    }
+}
+
+// Runs the tool `inferno-NAME` with the given arguments, and exits with its exit code.
+fn run_tool(name: &str, args: &[OsString]) -> io::Result<()> {
+    let file_name = format!("inferno-{}{}", name, env::consts::EXE_SUFFIX);
+    let program = env::current_exe()
+        .ok()
+        .map(|exe| exe.with_file_name(&file_name))
+        .filter(|path| path.is_file())
+        .unwrap_or_else(|| PathBuf::from(&file_name));
+    let status = Command::new(&program)
+        .args(args)
+        .status()
+        .map_err(|e| io::Error::new(e.kind(), format!("failed to run {}: {}", file_name, e)))?;
+    process::exit(status.code().unwrap_or(1));
+}
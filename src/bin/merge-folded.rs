@@ -0,0 +1,110 @@
+use std::io;
+use std::path::PathBuf;
+
+use clap::{ArgAction, Parser};
+use env_logger::Env;
+use inferno::merge::{self, Input};
+use is_terminal::IsTerminal;
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "inferno-merge-folded",
+    about,
+    after_help = "\
+Merges several folded stack profiles into one, summing the counts of the
+stacks they share.
+
+  $ inferno-merge-folded --prefix host1 --prefix host2 host1.folded host2.folded \\
+      | inferno-flamegraph > merged.svg
+
+--scale and --prefix apply to the inputs in the order they are given; either
+give none of them, or one for every input."
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Silence all log output
+    #[clap(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
+    verbose: u8,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// Factor to multiply the counts of an input by
+    #[clap(long = "scale", value_name = "FLOAT")]
+    scales: Vec<f64>,
+
+    /// Frame to put at the root of every stack of an input
+    #[clap(long = "prefix", value_name = "STRING")]
+    prefixes: Vec<String>,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// Paths to the folded stack profiles
+    #[clap(value_name = "PATH", required = true)]
+    paths: Vec<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> io::Result<Vec<(PathBuf, Input)>> {
+        for (flag, n) in [
+            ("--scale", self.scales.len()),
+            ("--prefix", self.prefixes.len()),
+        ] {
+            if n != 0 && n != self.paths.len() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!(
+                        "{} was given {} times for {} inputs",
+                        flag,
+                        n,
+                        self.paths.len()
+                    ),
+                ));
+            }
+        }
+
+        let mut scales = self.scales.into_iter();
+        let mut prefixes = self.prefixes.into_iter();
+        Ok(self
+            .paths
+            .into_iter()
+            .map(|path| {
+                let mut input = Input::default();
+                input.scale = scales.next().unwrap_or(1.0);
+                input.prefix = prefixes.next();
+                (path, input)
+            })
+            .collect())
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt = Opt::parse();
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let inputs = opt.into_parts()?;
+
+    if std::io::stdout().is_terminal() {
+        merge::from_files(&inputs, io::stdout().lock())
+    } else {
+        merge::from_files(&inputs, io::BufWriter::new(io::stdout().lock()))
+    }
+}
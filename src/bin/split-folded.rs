@@ -0,0 +1,112 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+
+use clap::{ArgAction, Parser};
+use env_logger::Env;
+use inferno::flamegraph::{self, Options as FlamegraphOptions};
+use inferno::split::{self, Options};
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "inferno-split-folded",
+    about,
+    after_help = "\
+Splits a folded stack profile whose stacks begin with process or thread frames
+into a folded file for each of those roots.
+
+  $ inferno-split-folded --output-dir threads --depth 2 --svg stacks.folded
+
+writes threads/<root>.folded, and threads/<root>.svg with --svg, for each
+process;thread pair that stacks begin with."
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Leave the root frames that stacks are split by out of the split stacks
+    #[clap(long = "strip-roots")]
+    strip_roots: bool,
+
+    /// Also write a flame graph of each root
+    #[clap(long = "svg")]
+    svg: bool,
+
+    /// Silence all log output
+    #[clap(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
+    verbose: u8,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// Number of frames at the root of each stack to split by
+    #[clap(long = "depth", default_value = "1", value_name = "UINT")]
+    depth: usize,
+
+    /// Directory to write the files of each root to
+    #[clap(long = "output-dir", value_name = "DIR")]
+    output_dir: PathBuf,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// Folded stack files. With no PATH, or PATH is -, read STDIN.
+    #[clap(name = "PATH", value_parser)]
+    infiles: Vec<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> (Vec<PathBuf>, PathBuf, bool, Options) {
+        let mut options = Options::default();
+        options.depth = self.depth;
+        options.strip_roots = self.strip_roots;
+        (self.infiles, self.output_dir, self.svg, options)
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt = Opt::parse();
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let (infiles, dir, svg, options) = opt.into_parts();
+    fs::create_dir_all(&dir)?;
+
+    let mut roots = Vec::new();
+    split::from_files(options, &infiles, |root| {
+        roots.push(root.to_string());
+        File::create(folded_path(&dir, root)).map(BufWriter::new)
+    })?;
+
+    if svg {
+        for root in roots {
+            let mut options = FlamegraphOptions::default();
+            options.title = root.clone();
+            let folded = folded_path(&dir, &root);
+            let svg = BufWriter::new(File::create(folded.with_extension("svg"))?);
+            flamegraph::from_files(&mut options, &[folded], svg).map_err(|e| match e {
+                quick_xml::Error::Io(e) => e,
+                e => io::Error::new(io::ErrorKind::Other, e),
+            })?;
+        }
+    }
+    Ok(())
+}
+
+fn folded_path(dir: &Path, root: &str) -> PathBuf {
+    dir.join(format!("{}.folded", split::file_name(root)))
+}
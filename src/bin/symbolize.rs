@@ -0,0 +1,133 @@
+use std::io;
+use std::path::PathBuf;
+
+use clap::{ArgAction, Parser};
+use env_logger::Env;
+use inferno::symbolize::{self, Options};
+use is_terminal::IsTerminal;
+
+#[derive(Debug, Parser)]
+#[clap(
+    name = "inferno-symbolize",
+    about,
+    after_help = "\
+Rewrites frames like libfoo.so+0x1a2b in folded stack profiles to the names of
+the functions at those offsets, and of the functions inlined there.
+
+  $ inferno-symbolize --binary-dir ./build stacks.folded | inferno-flamegraph > out.svg
+
+Frames that can't be symbolized are left as they are."
+)]
+struct Opt {
+    // ************* //
+    // *** FLAGS *** //
+    // ************* //
+    /// Don't expand frames into the functions that were inlined into them
+    #[clap(long = "no-inline")]
+    no_inline: bool,
+
+    /// Silence all log output
+    #[clap(short = 'q', long = "quiet")]
+    quiet: bool,
+
+    /// Verbose logging mode (-v, -vv, -vvv)
+    #[clap(short = 'v', long = "verbose", action = ArgAction::Count)]
+    verbose: u8,
+
+    // *************** //
+    // *** OPTIONS *** //
+    // *************** //
+    /// Breakpad .sym file, or directory or symbol store of them (may be repeated)
+    #[clap(long = "breakpad-symbols", value_name = "PATH")]
+    breakpad_symbols: Vec<PathBuf>,
+
+    /// Directory to look for binaries in (may be repeated)
+    #[clap(long = "binary-dir", value_name = "PATH")]
+    binary_dirs: Vec<PathBuf>,
+
+    /// Binary that bare addresses like 0x4011d6 are offsets into
+    #[clap(long = "executable", value_name = "PATH")]
+    executable: Option<PathBuf>,
+
+    /// Source map, or directory of .map files, for minified JavaScript (may be repeated)
+    #[cfg(feature = "sourcemap")]
+    #[clap(long = "source-map", value_name = "PATH")]
+    source_maps: Vec<PathBuf>,
+
+    /// Where to find PDBs, like srv*C:\symbols*https://msdl.microsoft.com/download/symbols
+    /// [default: $_NT_SYMBOL_PATH]
+    #[cfg(feature = "pdb")]
+    #[clap(long = "symbol-path", value_name = "STRING")]
+    symbol_path: Option<String>,
+
+    /// debuginfod server to fetch debug info for stripped binaries from (may be repeated)
+    /// [default: $DEBUGINFOD_URLS]
+    #[cfg(feature = "debuginfod")]
+    #[clap(long = "debuginfod-url", value_name = "URL")]
+    debuginfod_urls: Vec<String>,
+
+    // ************ //
+    // *** ARGS *** //
+    // ************ //
+    /// Folded stack files. With no PATH, or PATH is -, read STDIN.
+    #[clap(name = "PATH", value_parser)]
+    infiles: Vec<PathBuf>,
+}
+
+impl Opt {
+    fn into_parts(self) -> (Vec<PathBuf>, Options) {
+        let mut options = Options::default();
+        options.binary_dirs = self.binary_dirs;
+        options.breakpad_symbols = self.breakpad_symbols;
+        options.executable = self.executable;
+        options.no_inline = self.no_inline;
+        #[cfg(feature = "sourcemap")]
+        {
+            options.source_maps = self.source_maps;
+        }
+        #[cfg(feature = "pdb")]
+        {
+            options.symbol_path = self
+                .symbol_path
+                .or_else(|| std::env::var("_NT_SYMBOL_PATH").ok())
+                .unwrap_or_default();
+        }
+        #[cfg(feature = "debuginfod")]
+        {
+            options.debuginfod_urls = if self.debuginfod_urls.is_empty() {
+                std::env::var("DEBUGINFOD_URLS")
+                    .unwrap_or_default()
+                    .split_whitespace()
+                    .map(String::from)
+                    .collect()
+            } else {
+                self.debuginfod_urls
+            };
+        }
+        (self.infiles, options)
+    }
+}
+
+fn main() -> io::Result<()> {
+    let opt = Opt::parse();
+
+    // Initialize logger
+    if !opt.quiet {
+        env_logger::Builder::from_env(Env::default().default_filter_or(match opt.verbose {
+            0 => "warn",
+            1 => "info",
+            2 => "debug",
+            _ => "trace",
+        }))
+        .format_timestamp(None)
+        .init();
+    }
+
+    let (infiles, options) = opt.into_parts();
+
+    if std::io::stdout().is_terminal() {
+        symbolize::from_files(&options, &infiles, io::stdout().lock())
+    } else {
+        symbolize::from_files(&options, &infiles, io::BufWriter::new(io::stdout().lock()))
+    }
+}
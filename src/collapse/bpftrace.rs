@@ -0,0 +1,355 @@
+use std::io::{self, BufRead};
+
+use log::warn;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+
+// Kernel addresses start here on 64-bit systems.
+const KERNEL_BASE: u64 = 0xffff_8000_0000_0000;
+
+// The functions that kernel stacks start at: system call and exception entries, kernel threads
+// and the idle loop. Frames up to the last one of these in a stack are kernel frames.
+const KERNEL_ENTRIES: &[&str] = &[
+    "entry_SYSCALL",
+    "entry_INT80",
+    "do_syscall_64",
+    "ret_from_fork",
+    "kthread",
+    "secondary_startup",
+    "start_secondary",
+    "common_startup",
+    "start_kernel",
+    "x86_64_start",
+    "cpu_startup_entry",
+    "asm_",
+    "el0t_",
+    "el0_svc",
+    "el1h_",
+];
+
+/// `bpftrace` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct Options {
+    /// Annotate kernel functions with a `_[k]` suffix.
+    ///
+    /// Default is `false`.
+    pub annotate_kernel: bool,
+
+    /// Include the PID in the process name root frame of BCC output.
+    ///
+    /// Default is `false`.
+    pub include_pid: bool,
+}
+
+/// A stack collapser for the stacks that [bpftrace] and the [BCC] tools print.
+///
+/// bpftrace prints maps keyed by stacks, like `@[kstack, ustack, comm] = count()`, as blocks of
+/// frames between `@[` and `]: <count>`. Every stack in a key becomes part of the folded stack,
+/// and other keys, like the process name, become root frames. BCC tools such as `profile`,
+/// `offcputime` and `stackcount` print the frames of each stack followed by a `-` line with the
+/// process name and pid, and a line with the count.
+///
+/// Both print frames leaf first. Kernel frames are placed above user frames: they are recognized
+/// by their addresses when those are printed, by coming before the `--` delimiter of BCC's `-d`
+/// flag, and otherwise by ending at a kernel entry point like `entry_SYSCALL_64`.
+///
+/// To construct one, either use `bpftrace::Folder::default()` or create an [`Options`] and use
+/// `bpftrace::Folder::from(options)`.
+///
+///   [bpftrace]: https://github.com/bpftrace/bpftrace
+///   [BCC]: https://github.com/iovisor/bcc
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+/// A part of the key of a stack.
+enum Part {
+    /// A value like the process name, which becomes a root frame.
+    Value(String),
+    /// Frames, leaf first, and whether they are known to be kernel or user frames.
+    Stack(Vec<String>, Option<bool>),
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, reader: R, writer: W) -> io::Result<()>
+    where
+        R: BufRead,
+        W: io::Write,
+    {
+        let mut occurrences = Occurrences::new(1);
+        let mut parts = Vec::new();
+        let mut frames = Vec::new();
+        let mut kernel = None;
+        // Whether we're in a bpftrace map key.
+        let mut in_key = false;
+        let mut invalid = 0;
+
+        for line in reader.lines() {
+            let line = line?;
+            let trimmed = line.trim();
+            if trimmed.is_empty() {
+                continue;
+            }
+
+            if in_key {
+                if trimmed.starts_with(',') || trimmed.starts_with(']') {
+                    // The end of a stack, followed by more keys or the value.
+                    parts.push(Part::Stack(std::mem::take(&mut frames), None));
+                    let rest = trimmed.strip_prefix(',').unwrap_or(trimmed);
+                    if let Some(value) = key_rest(rest, &mut parts) {
+                        in_key = false;
+                        match value {
+                            Some(count) => self.fold(&mut parts, count, &mut occurrences),
+                            None => invalid += 1,
+                        }
+                        parts.clear();
+                    }
+                } else {
+                    frames.push(trimmed.to_string());
+                }
+                continue;
+            }
+
+            if trimmed.starts_with('@') {
+                if let Some(start) = trimmed.find('[') {
+                    parts.clear();
+                    frames.clear();
+                    in_key = true;
+                    if let Some(value) = key_rest(&trimmed[start + 1..], &mut parts) {
+                        // A map key without stacks.
+                        in_key = false;
+                        match value {
+                            Some(count) => self.fold(&mut parts, count, &mut occurrences),
+                            None => invalid += 1,
+                        }
+                        parts.clear();
+                    }
+                }
+                continue;
+            }
+
+            // BCC output, whose frames are indented.
+            if !line.starts_with(char::is_whitespace) {
+                continue;
+            }
+            if trimmed == "--" {
+                parts.push(Part::Stack(std::mem::take(&mut frames), Some(true)));
+                kernel = Some(false);
+            } else if let Some(process) = trimmed.strip_prefix("- ") {
+                parts.push(Part::Stack(std::mem::take(&mut frames), kernel.take()));
+                parts.push(Part::Value(self.process_name(process.trim())));
+            } else if trimmed.bytes().all(|b| b.is_ascii_digit())
+                && !(frames.is_empty() && parts.is_empty())
+            {
+                if !frames.is_empty() {
+                    parts.push(Part::Stack(std::mem::take(&mut frames), kernel.take()));
+                }
+                match trimmed.parse() {
+                    Ok(count) => self.fold(&mut parts, count, &mut occurrences),
+                    Err(_) => invalid += 1,
+                }
+                parts.clear();
+            } else {
+                frames.push(trimmed.to_string());
+            }
+        }
+
+        if invalid != 0 {
+            warn!("Ignored {} stacks without a valid count", invalid);
+        }
+        occurrences.write_and_clear(writer)
+    }
+
+    /// Check for bpftrace map keys with stacks or the process lines of BCC tools
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        let mut in_key = false;
+        let mut banner = false;
+        for line in input.lines() {
+            let trimmed = line.trim();
+            if trimmed.is_empty() {
+                continue;
+            }
+            if trimmed.starts_with('@') && trimmed.ends_with('[') {
+                in_key = true;
+            } else if (in_key && (trimmed.starts_with(']') || trimmed.starts_with(',')))
+                || (trimmed.starts_with("- ") && trimmed.ends_with(')'))
+            {
+                return Some(true);
+            } else if !in_key && !trimmed.starts_with('@') && !line.starts_with(char::is_whitespace)
+            {
+                // Only a banner, like `Attaching 1 probe...`, comes before the stacks; the
+                // frames of BCC tools are indented.
+                if banner {
+                    return Some(false);
+                }
+                banner = true;
+            }
+        }
+        None
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+impl Folder {
+    fn process_name(&self, process: &str) -> String {
+        // BCC prints processes as `comm (pid)`.
+        let (comm, pid) = match process.rsplit_once(" (") {
+            Some((comm, pid)) => (comm.trim(), pid.trim_end_matches(')')),
+            None => (process, ""),
+        };
+        if self.opt.include_pid && !pid.is_empty() {
+            format!("{}-{}", comm, pid)
+        } else {
+            comm.to_string()
+        }
+    }
+
+    fn fold(&self, parts: &mut Vec<Part>, count: usize, occurrences: &mut Occurrences) {
+        if count == 0 {
+            return;
+        }
+        let mut roots = Vec::new();
+        let mut stacks = Vec::new();
+        // Stacks in a key are listed kernel first, like `@[kstack, ustack]`, so they are put
+        // together the other way around, with user stacks above the root frames.
+        for part in parts.drain(..).rev() {
+            match part {
+                Part::Value(value) => roots.insert(0, value),
+                Part::Stack(frames, kernel) => {
+                    let frames: Vec<_> = frames.iter().map(|frame| parse_frame(frame)).collect();
+                    let nkernel = match kernel {
+                        Some(true) => frames.len(),
+                        Some(false) => 0,
+                        None => kernel_frames(&frames),
+                    };
+                    let names: Vec<_> = frames
+                        .iter()
+                        .enumerate()
+                        .rev()
+                        .map(|(i, (_, name))| {
+                            if i < nkernel && self.opt.annotate_kernel {
+                                format!("{}_[k]", name)
+                            } else {
+                                name.clone()
+                            }
+                        })
+                        .collect();
+                    if !names.is_empty() {
+                        stacks.push((nkernel == frames.len(), names));
+                    }
+                }
+            }
+        }
+        stacks.sort_by_key(|&(kernel, _)| kernel);
+
+        let mut folded = roots;
+        for (_, names) in stacks {
+            folded.extend(names);
+        }
+        if !folded.is_empty() {
+            occurrences.insert_or_add(folded.join(";"), count);
+        }
+    }
+}
+
+// Reads the keys after a stack, like `, bash]: 12`. Returns the count if the map entry
+// ends, or `Some(None)` if it ends with an invalid count.
+fn key_rest(rest: &str, parts: &mut Vec<Part>) -> Option<Option<usize>> {
+    let (keys, value) = match rest.rsplit_once("]:") {
+        Some((keys, value)) => (keys, Some(value)),
+        None => (rest, None),
+    };
+    parts.extend(
+        keys.split(',')
+            .map(str::trim)
+            .filter(|key| !key.is_empty() && *key != "]")
+            .map(|key| Part::Value(key.to_string())),
+    );
+    value.map(|value| value.trim().parse().ok())
+}
+
+// Splits a frame like `ffffffff8100 do_syscall_64+114`, `main+20 (/bin/app)` or
+// `b'do_idle'` into its address and function name.
+fn parse_frame(frame: &str) -> (Option<u64>, String) {
+    let mut frame = frame.trim();
+    // Some versions of BCC print Python byte strings.
+    if let Some(inner) = frame.strip_prefix("b'").and_then(|f| f.strip_suffix('\'')) {
+        frame = inner;
+    }
+
+    let mut address = None;
+    if let Some((first, rest)) = frame.split_once(' ') {
+        if let Some(addr) = parse_address(first) {
+            address = Some(addr);
+            frame = rest.trim();
+        }
+    } else if let Some(addr) = parse_address(frame).filter(|_| frame.starts_with("0x")) {
+        return (Some(addr), frame.to_string());
+    }
+
+    // Leave out the module, unless the function is unknown.
+    let mut module = None;
+    if frame.ends_with(')') {
+        if let Some((function, m)) = frame.rsplit_once(" (") {
+            frame = function;
+            module = Some(m.trim_end_matches(')'));
+        }
+    }
+    if let Some((function, offset)) = frame.rsplit_once('+') {
+        let offset = offset.trim_start_matches("0x");
+        if !function.is_empty()
+            && !offset.is_empty()
+            && offset.bytes().all(|b| b.is_ascii_hexdigit())
+        {
+            frame = function;
+        }
+    }
+
+    let name = match module {
+        Some(module) if frame == "[unknown]" => {
+            format!("[{}]", module.rsplit('/').next().unwrap_or(module))
+        }
+        _ => frame.to_string(),
+    };
+    (address, name)
+}
+
+fn parse_address(address: &str) -> Option<u64> {
+    // Short words like `add` would look like addresses too.
+    let hex = match address.strip_prefix("0x") {
+        Some(hex) => hex,
+        None if address.len() >= 8 => address,
+        None => return None,
+    };
+    if hex.is_empty() || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    u64::from_str_radix(hex, 16).ok()
+}
+
+// Finds the number of kernel frames at the leaf end of a stack.
+fn kernel_frames(frames: &[(Option<u64>, String)]) -> usize {
+    if frames.iter().any(|(address, _)| address.is_some()) {
+        return frames
+            .iter()
+            .take_while(|(address, _)| address.is_some_and(|a| a >= KERNEL_BASE))
+            .count();
+    }
+    frames
+        .iter()
+        .rposition(|(_, name)| KERNEL_ENTRIES.iter().any(|entry| name.starts_with(entry)))
+        .map_or(0, |i| i + 1)
+}
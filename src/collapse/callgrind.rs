@@ -0,0 +1,310 @@
+use std::io::{self, BufRead};
+
+use ahash::{AHashMap, AHashSet};
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+use crate::folded::Header;
+use crate::Error;
+
+// Paths through the call graph that stand for less cost than this are left out, which keeps
+// graphs with many paths through them from taking forever.
+const MIN_PATH_COST: f64 = 0.5;
+
+/// `callgrind` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct Options {
+    /// The event to weight stacks by, like `Ir` for instructions, or `CEst` for the cycle
+    /// estimation of `--cache-sim=yes` runs.
+    ///
+    /// Default is `None`, which uses the first event of the profile.
+    pub event: Option<String>,
+
+    /// Write a [`Header`] before the folded stacks, with the event and its units.
+    ///
+    /// Default is `false`.
+    pub header: bool,
+}
+
+/// A stack collapser for the output of [Valgrind]'s `callgrind` tool.
+///
+/// Callgrind doesn't record stacks, but the cost of each function and the inclusive cost of each
+/// call from one function to another. Stacks are rebuilt from that call graph, starting at the
+/// functions that nothing calls: the cost of a function is split between the stacks it is
+/// called from in proportion to the inclusive costs of those calls. This is exact when a
+/// function costs the same no matter who calls it, and an estimate otherwise. Recursive calls
+/// end a stack.
+///
+/// To construct one, either use `callgrind::Folder::default()` or create an [`Options`] and use
+/// `callgrind::Folder::from(options)`.
+///
+///   [Valgrind]: https://valgrind.org/docs/manual/cl-format.html
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+/// The call graph of a profile, with costs of the chosen event.
+#[derive(Default)]
+struct CallGraph {
+    names: Vec<String>,
+    /// The cost of each function itself.
+    costs: Vec<u64>,
+    /// The inclusive cost of the calls of each function, by callee.
+    calls: Vec<AHashMap<usize, u64>>,
+}
+
+impl CallGraph {
+    fn function(&mut self, ids: &mut AHashMap<String, usize>, name: &str) -> usize {
+        if let Some(&id) = ids.get(name) {
+            return id;
+        }
+        let id = self.names.len();
+        ids.insert(name.to_string(), id);
+        self.names.push(name.to_string());
+        self.costs.push(0);
+        self.calls.push(AHashMap::default());
+        id
+    }
+}
+
+/// What the next cost line of the profile is the cost of.
+#[derive(Clone, Copy)]
+enum Cost {
+    Function(usize),
+    Call(usize, usize),
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, reader: R, mut writer: W) -> io::Result<()>
+    where
+        R: BufRead,
+        W: io::Write,
+    {
+        let mut graph = CallGraph::default();
+        let mut ids = AHashMap::default();
+        // Names are compressed to ids like `(12)` after they are first given.
+        let mut compressed = AHashMap::default();
+        let mut npositions = 1;
+        let mut event = None;
+        let mut function = None;
+        let mut callee = None;
+        let mut next_cost = None;
+
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some((key, value)) = line.split_once('=') {
+                match key {
+                    "fn" | "cfn" | "cfi" | "cfl" | "fl" | "fi" | "fe" | "ob" | "cob" => {}
+                    "calls" => {
+                        if let (Some(caller), Some(callee)) = (function, callee) {
+                            next_cost = Some(Cost::Call(caller, callee));
+                        }
+                        continue;
+                    }
+                    // Other specifications, like jumps, have no cost lines.
+                    _ => continue,
+                }
+                if key == "fn" || key == "cfn" {
+                    let name = decompress(&mut compressed, value.trim());
+                    let id = graph.function(&mut ids, name);
+                    if key == "fn" {
+                        function = Some(id);
+                        callee = None;
+                        next_cost = Some(Cost::Function(id));
+                    } else {
+                        callee = Some(id);
+                    }
+                }
+                continue;
+            }
+
+            if let Some((key, value)) = line.split_once(':') {
+                if key.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_') {
+                    match key {
+                        "positions" => npositions = value.split_whitespace().count().max(1),
+                        "events" => {
+                            let events: Vec<_> = value.split_whitespace().collect();
+                            let index = self.event_index(&events)?;
+                            event = Some((index, events[index].to_string()));
+                        }
+                        _ => {}
+                    }
+                    continue;
+                }
+            }
+
+            // A cost line: positions followed by the costs of the events.
+            let index = match event {
+                Some((index, _)) => index,
+                None => return Err(invalid_data("the profile has no events line")),
+            };
+            let cost = line
+                .split_whitespace()
+                .nth(npositions + index)
+                .map(|cost| cost.parse::<u64>())
+                .unwrap_or(Ok(0))
+                .map_err(|_| invalid_data(format!("invalid cost line: {}", line)))?;
+            match next_cost {
+                Some(Cost::Function(id)) => graph.costs[id] += cost,
+                Some(Cost::Call(caller, callee)) => {
+                    *graph.calls[caller].entry(callee).or_default() += cost;
+                    // The lines after the cost of a call are the function's own costs again.
+                    next_cost = Some(Cost::Function(caller));
+                }
+                None => {}
+            }
+        }
+
+        let mut occurrences = Occurrences::new(1);
+        for (stack, cost) in fold(&graph) {
+            let cost = cost.round();
+            if cost >= 1.0 {
+                occurrences.insert_or_add(stack, cost as usize);
+            }
+        }
+
+        if self.opt.header {
+            let mut header = Header::for_tool("inferno-collapse-callgrind");
+            if let Some((_, event)) = event {
+                header.event = Some(event.clone());
+                header.units = Some(event);
+            }
+            header.write(&mut writer)?;
+        }
+        occurrences.write_and_clear(writer)
+    }
+
+    /// Check for the `events:` line or the format comment of callgrind output
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        for line in input.lines() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if line == "# callgrind format" || line.starts_with("events:") {
+                return Some(true);
+            }
+            let header = line.split_once(':').is_some_and(|(key, _)| {
+                key.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_')
+            });
+            if !header {
+                return Some(false);
+            }
+        }
+        None
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+impl Folder {
+    fn event_index(&self, events: &[&str]) -> io::Result<usize> {
+        match self.opt.event {
+            Some(ref event) => events.iter().position(|e| e == event).ok_or_else(|| {
+                io::Error::from(Error::InvalidOptions(format!(
+                    "the profile has no {} event; its events are: {}",
+                    event,
+                    events.join(", ")
+                )))
+            }),
+            None if events.is_empty() => Err(invalid_data("the profile has no events")),
+            None => Ok(0),
+        }
+    }
+}
+
+// Resolves a name given as `(id) name` or `(id)`, remembering the names of ids.
+fn decompress<'a>(compressed: &'a mut AHashMap<String, String>, value: &'a str) -> &'a str {
+    if let Some(rest) = value.strip_prefix('(') {
+        if let Some((id, name)) = rest.split_once(')') {
+            let name = name.trim();
+            if name.is_empty() {
+                return compressed.get(id).map_or(value, String::as_str);
+            }
+            compressed.insert(id.to_string(), name.to_string());
+            return name;
+        }
+    }
+    value
+}
+
+// Rebuilds stacks from the call graph, with the cost of each.
+fn fold(graph: &CallGraph) -> AHashMap<String, f64> {
+    let n = graph.names.len();
+    let mut inclusive = vec![0u64; n];
+    for calls in &graph.calls {
+        for (&callee, &cost) in calls {
+            inclusive[callee] += cost;
+        }
+    }
+
+    let mut stacks = AHashMap::default();
+    let mut on_stack = AHashSet::default();
+    let mut frames = Vec::new();
+    for root in (0..n).filter(|&f| inclusive[f] == 0) {
+        visit(
+            graph,
+            &inclusive,
+            root,
+            1.0,
+            &mut frames,
+            &mut on_stack,
+            &mut stacks,
+        );
+    }
+    stacks
+}
+
+fn visit(
+    graph: &CallGraph,
+    inclusive: &[u64],
+    function: usize,
+    share: f64,
+    frames: &mut Vec<usize>,
+    on_stack: &mut AHashSet<usize>,
+    stacks: &mut AHashMap<String, f64>,
+) {
+    frames.push(function);
+    on_stack.insert(function);
+
+    let cost = graph.costs[function] as f64 * share;
+    if cost > 0.0 {
+        let stack: Vec<_> = frames.iter().map(|&f| graph.names[f].as_str()).collect();
+        *stacks.entry(stack.join(";")).or_default() += cost;
+    }
+    for (&callee, &call) in &graph.calls[function] {
+        let call = call as f64 * share;
+        if call < MIN_PATH_COST || on_stack.contains(&callee) {
+            continue;
+        }
+        let share = call / inclusive[callee] as f64;
+        visit(graph, inclusive, callee, share, frames, on_stack, stacks);
+    }
+
+    on_stack.remove(&function);
+    frames.pop();
+}
+
+fn invalid_data<E>(error: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let error: Box<dyn std::error::Error + Send + Sync> = error.into();
+    Error::parse("callgrind", error).into()
+}
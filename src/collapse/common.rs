@@ -1,8 +1,9 @@
-use std::borrow::Cow;
+use std::fs::File;
 use std::io;
 #[cfg(feature = "multithreaded")]
 use std::mem;
-#[cfg(feature = "multithreaded")]
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
 use ahash::AHashMap;
@@ -10,12 +11,21 @@ use ahash::AHashMap;
 use dashmap::DashMap;
 use once_cell::sync::Lazy;
 
+use crate::folded::Header;
+use crate::Error;
+
+// Makes an `Err` of input that couldn't be parsed, in the format of the collapser the macro is
+// used in. The number of the line the error is about can be given first, like
+// `invalid_data_error!(line = number; "...", ...)`; errors made without one are given theirs by
+// `Error::at_line` in the loop over the lines of the input.
 macro_rules! invalid_data_error {
+    (line = $line:expr; $($arg:tt)*) => {{
+        let format = module_path!().rsplit("::").next().unwrap_or_default();
+        Err(io::Error::from($crate::Error::parse_line(format, $line, format!($($arg)*))))
+    }};
     ($($arg:tt)*) => {{
-        Err(io::Error::new(
-            io::ErrorKind::InvalidData,
-            format!($($arg)*),
-        ))
+        let format = module_path!().rsplit("::").next().unwrap_or_default();
+        Err(io::Error::from($crate::Error::parse(format, format!($($arg)*))))
     }};
 }
 
@@ -23,6 +33,15 @@ const CAPACITY_HASHMAP: usize = 512;
 
 pub(crate) const CAPACITY_READER: usize = 128 * 1024;
 
+/// The number of bytes of input between calls to the progress callback of
+/// `Collapse::collapse_with_progress`.
+pub(crate) const PROGRESS_INTERVAL: u64 = 1024 * 1024;
+
+// The magic bytes that compressed inputs start with.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+const XZ_MAGIC: [u8; 6] = [0xfd, b'7', b'z', b'X', b'Z', 0x00];
+
 /// Internal parameter (not exposed to users) that determines how many stacks of
 /// input data make up a "chunk" (unit that is sent to the threadpool for
 /// processing). Chosen by benchmarking various values using the following tests:
@@ -36,16 +55,237 @@ pub(crate) const DEFAULT_NSTACKS_PER_JOB: usize = 100;
 #[cfg(feature = "multithreaded")]
 const NBYTES_PER_STACK_GUESS: usize = 1024;
 
-const RUST_HASH_LENGTH: usize = 17;
-
 #[cfg(feature = "multithreaded")]
 #[doc(hidden)]
-pub static DEFAULT_NTHREADS: Lazy<usize> =
-    Lazy::new(|| std::thread::available_parallelism().unwrap().into());
+pub static DEFAULT_NTHREADS: Lazy<usize> = Lazy::new(|| {
+    // Targets without threads, like wasm32-unknown-unknown, don't know their parallelism.
+    std::thread::available_parallelism().map_or(1, usize::from)
+});
 #[cfg(not(feature = "multithreaded"))]
 #[doc(hidden)]
 pub static DEFAULT_NTHREADS: Lazy<usize> = Lazy::new(|| 1);
 
+/// Wraps the reader of a collapser's input in a decoder if the input is compressed.
+///
+/// gzip is always supported, zstd with the `zstd` feature and xz with the `xz` feature. Other
+/// inputs are passed through as they are.
+pub(crate) fn decompress<'a, R>(mut reader: R) -> io::Result<Box<dyn io::BufRead + 'a>>
+where
+    R: io::BufRead + 'a,
+{
+    let (gzip, zstd, xz) = {
+        let magic = reader.fill_buf()?;
+        (
+            magic.starts_with(&GZIP_MAGIC),
+            magic.starts_with(&ZSTD_MAGIC),
+            magic.starts_with(&XZ_MAGIC),
+        )
+    };
+
+    if gzip {
+        // Concatenated gzip files, like those of `cat a.gz b.gz`, are one input.
+        let decoder = libflate::gzip::MultiDecoder::new(reader)?;
+        return Ok(Box::new(io::BufReader::with_capacity(
+            CAPACITY_READER,
+            decoder,
+        )));
+    }
+    if zstd {
+        #[cfg(feature = "zstd")]
+        {
+            let decoder = zstd::stream::read::Decoder::with_buffer(reader)?;
+            return Ok(Box::new(io::BufReader::with_capacity(
+                CAPACITY_READER,
+                decoder,
+            )));
+        }
+        #[cfg(not(feature = "zstd"))]
+        return Err(Error::UnsupportedFormat(
+            "Input is compressed with zstd, which needs inferno's `zstd` feature".to_string(),
+        )
+        .into());
+    }
+    if xz {
+        #[cfg(feature = "xz")]
+        {
+            let decoder = xz2::bufread::XzDecoder::new_multi_decoder(reader);
+            return Ok(Box::new(io::BufReader::with_capacity(
+                CAPACITY_READER,
+                decoder,
+            )));
+        }
+        #[cfg(not(feature = "xz"))]
+        return Err(Error::UnsupportedFormat(
+            "Input is compressed with xz, which needs inferno's `xz` feature".to_string(),
+        )
+        .into());
+    }
+    Ok(Box::new(reader))
+}
+
+/// Opens a file of stacks, decompressing it like [`decompress`] does.
+pub(crate) fn open(path: &Path) -> io::Result<Box<dyn io::BufRead>> {
+    let file = File::open(path)?;
+    decompress(io::BufReader::with_capacity(CAPACITY_READER, file))
+}
+
+/// Splits a line into its fields, keeping the double quotes around quoted fields, which may
+/// contain the delimiter.
+pub(crate) fn split_fields(line: &str, delimiter: char) -> Vec<&str> {
+    let mut fields = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        if c == '"' {
+            quoted = !quoted;
+        } else if c == delimiter && !quoted {
+            fields.push(&line[start..i]);
+            start = i + c.len_utf8();
+        }
+    }
+    fields.push(&line[start..]);
+    fields
+}
+
+/// A reader that counts the lines that are read from it, so that the line numbers of parse errors
+/// can count the lines of the header that `pre_process` consumes too.
+pub(crate) struct LineCounter<R> {
+    reader: R,
+    pub(crate) lines: usize,
+}
+
+impl<R> LineCounter<R> {
+    pub(crate) fn new(reader: R) -> Self {
+        LineCounter { reader, lines: 0 }
+    }
+}
+
+impl<R: io::Read> io::Read for LineCounter<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        self.lines += memchr::memchr_iter(b'\n', &buf[..n]).count();
+        Ok(n)
+    }
+}
+
+impl<R: io::BufRead> io::BufRead for LineCounter<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.reader.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        // The buffer is still filled from the call to `fill_buf` that `amt` is out of, so this
+        // doesn't read anything.
+        if let Ok(buf) = self.reader.fill_buf() {
+            self.lines += memchr::memchr_iter(b'\n', &buf[..amt.min(buf.len())]).count();
+        }
+        self.reader.consume(amt);
+    }
+}
+
+/// A token to cancel a collapse in progress, usually from another thread; see
+/// [`Collapse::collapse_with_progress`](crate::collapse::Collapse::collapse_with_progress).
+///
+/// Clones of a token share its state, so cancelling any of them cancels them all.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    /// Creates a token that hasn't been cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels the collapses using this token the next time they read input.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Returns whether this token has been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Fails with an error of kind `Other` once this token has been cancelled.
+    pub(crate) fn check(&self) -> io::Result<()> {
+        if self.is_cancelled() {
+            // Not `Interrupted`, which readers retry on.
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "Collapse was cancelled",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Wraps the reader of a collapser's input to report how much of it has been read, and to stop
+/// reading with an error once the collapse is cancelled.
+pub(crate) struct ProgressReader<'a, R, F> {
+    inner: R,
+    progress: F,
+    cancel: &'a CancelToken,
+    read: u64,
+    reported: u64,
+}
+
+impl<'a, R, F> ProgressReader<'a, R, F>
+where
+    F: FnMut(u64),
+{
+    pub(crate) fn new(inner: R, progress: F, cancel: &'a CancelToken) -> Self {
+        Self {
+            inner,
+            progress,
+            cancel,
+            read: 0,
+            reported: 0,
+        }
+    }
+
+    /// Reports the number of bytes read in the end.
+    pub(crate) fn finish(mut self) {
+        (self.progress)(self.read);
+    }
+
+    fn advance(&mut self, n: usize) {
+        self.read += n as u64;
+        if self.read - self.reported >= PROGRESS_INTERVAL {
+            self.reported = self.read;
+            (self.progress)(self.read);
+        }
+    }
+}
+
+impl<'a, R, F> io::Read for ProgressReader<'a, R, F>
+where
+    R: io::Read,
+    F: FnMut(u64),
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.cancel.check()?;
+        let n = self.inner.read(buf)?;
+        self.advance(n);
+        Ok(n)
+    }
+}
+
+impl<'a, R, F> io::BufRead for ProgressReader<'a, R, F>
+where
+    R: io::BufRead,
+    F: FnMut(u64),
+{
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.cancel.check()?;
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.inner.consume(amt);
+        self.advance(amt);
+    }
+}
+
 /// Sealed trait for internal library authors.
 ///
 /// If you implement this trait, your type will implement the public-facing
@@ -111,6 +351,31 @@ pub trait CollapsePrivate: Send + Sized {
     /// `pre_process` method).
     fn would_end_stack(&mut self, line: &[u8]) -> bool;
 
+    /// Determine the start of a stack.
+    ///
+    /// Some formats, such as `sample` and `vtune`, are call trees, where nothing marks the end
+    /// of a stack; it ends where the next one starts. For these, this method should return
+    /// `true` if the provided line starts a new stack; chunks sent to worker threads are then
+    /// cut right before such lines (see also `stack_context`).
+    ///
+    /// Like `would_end_stack`, this method will be called for every line of input data
+    /// (excluding those consumed by the `pre_process` method). Formats that do mark the end of
+    /// their stacks don't need to implement it.
+    fn would_start_stack(&mut self, _line: &[u8]) -> bool {
+        false
+    }
+
+    /// Returns the lines to put before a chunk that starts with the line `would_start_stack`
+    /// was last called with.
+    ///
+    /// Formats whose stacks are the rows of a call tree can cut chunks before any row, as long
+    /// as the worker thread gets the ancestors of the row first. This method should return
+    /// those ancestors, in a form that doesn't add to the count of any stack (e.g. with their
+    /// counts set to zero).
+    fn stack_context(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
     /// Creates a copy and prepares it to be sent to a different thread.
     ///
     /// This method creates a copy of `self` in order to send it to a different thread.
@@ -144,11 +409,27 @@ pub trait CollapsePrivate: Send + Sized {
     /// Sets the number of threads to use.
     fn set_nthreads(&mut self, n: usize);
 
+    /// Returns the metadata header to write before the folded stacks, if any.
+    ///
+    /// This method is called once all stacks have been collapsed, so the header can describe
+    /// what was seen of the input. Worker threads only work on copies of `self`, so anything
+    /// they see must be shared with the main thread for it to be in the header.
+    fn header(&self) -> Option<Header> {
+        None
+    }
+
     // *********************************************************** //
     // ******************** PROVIDED METHODS ********************* //
     // *********************************************************** //
 
-    fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
+    // The worker threads of a multithreaded collapse stop at the next chunk of stacks once
+    // `cancel` is cancelled, even if all of the input has already been read.
+    fn collapse<R, W>(
+        &mut self,
+        mut reader: R,
+        mut writer: W,
+        cancel: &CancelToken,
+    ) -> io::Result<()>
     where
         R: io::BufRead,
         W: io::Write,
@@ -157,21 +438,32 @@ pub trait CollapsePrivate: Send + Sized {
 
         // Consume the header, if any, and do any other pre-processing
         // that needs to occur.
-        self.pre_process(&mut reader, &mut occurrences)?;
+        let mut counter = LineCounter::new(&mut reader);
+        self.pre_process(&mut counter, &mut occurrences)?;
+        let header_lines = counter.lines;
 
-        // Do collapsing.
+        // Do collapsing. The line numbers of parse errors count from after the header.
         if occurrences.is_concurrent() {
-            self.collapse_multi_threaded(reader, &mut occurrences)?;
+            self.collapse_multi_threaded(reader, &mut occurrences, cancel)
         } else {
-            self.collapse_single_threaded(reader, &mut occurrences)?;
+            self.collapse_single_threaded(reader, &mut occurrences)
         }
+        .map_err(|e| Error::offset_line(e, header_lines))?;
 
         // Write results.
+        if let Some(header) = self.header() {
+            header.write(&mut writer)?;
+        }
         occurrences.write_and_clear(writer)
     }
 
     #[cfg(not(feature = "multithreaded"))]
-    fn collapse_multi_threaded<R>(&mut self, _: R, _: &mut Occurrences) -> io::Result<()>
+    fn collapse_multi_threaded<R>(
+        &mut self,
+        _: R,
+        _: &mut Occurrences,
+        _: &CancelToken,
+    ) -> io::Result<()>
     where
         R: io::BufRead,
     {
@@ -183,6 +475,7 @@ pub trait CollapsePrivate: Send + Sized {
         &mut self,
         mut reader: R,
         occurrences: &mut Occurrences,
+        cancel: &CancelToken,
     ) -> io::Result<()>
     where
         R: io::BufRead,
@@ -202,7 +495,14 @@ pub trait CollapsePrivate: Send + Sized {
             // Channel for sending input data from the main thread to the worker threads.
             // We choose `2 * nthreads` as the channel size here in order to limit memory
             // usage in the case of particularly large input files.
-            let (tx_input, rx_input) = crossbeam_channel::bounded::<Vec<u8>>(2 * nthreads);
+            //
+            // Every chunk is sent with the number of lines of input before it, not counting the
+            // lines that `stack_context` put in front of it, to give parse errors their line.
+            let (tx_input, rx_input) = crossbeam_channel::bounded::<(usize, Vec<u8>)>(2 * nthreads);
+
+            // Channel for handing the buffers of processed chunks back to the main thread, so
+            // that it can read the next chunks into them instead of allocating new ones.
+            let (tx_spare, rx_spare) = crossbeam_channel::bounded::<Vec<u8>>(2 * nthreads);
 
             // Channel for worker threads that have errored to signal to all the other
             // worker threads that they should stop work immediately and return.
@@ -212,6 +512,7 @@ pub trait CollapsePrivate: Send + Sized {
             for _ in 0..nthreads {
                 let tx_error = tx_error.clone();
                 let rx_input = rx_input.clone();
+                let tx_spare = tx_spare.clone();
                 let (tx_stop, rx_stop) = (tx_stop.clone(), rx_stop.clone());
 
                 let mut folder = self.clone_and_reset_stack_context();
@@ -222,15 +523,20 @@ pub trait CollapsePrivate: Send + Sized {
                     crossbeam_channel::select! {
                         recv(rx_input) -> input => {
                             // Receive input from the main thread.
-                            let data = match input {
-                                Ok(data) => data,
+                            let (offset, mut data) = match input {
+                                Ok(input) => input,
                                 // The main threads drops it's handle to the input sender once it's
                                 // finished sending data; so if we get an error here, it means
                                 // there is no more data to be sent and we should exit.
                                 Err(_) => return,
                             };
-                            // If there is input data, process it.
-                            if let Err(e) = folder.collapse_single_threaded(&data[..], &mut occurrences) {
+                            // If there is input data, process it, unless the collapse has been
+                            // cancelled in the meantime.
+                            let result = cancel.check().and_then(|()| {
+                                folder.collapse_single_threaded(&data[..], &mut occurrences)
+                            })
+                            .map_err(|e| Error::offset_line(e, offset));
+                            if let Err(e) = result {
                                 // In the event of an error...
                                 //
                                 // We notify all the threads about it here, rather than wait for the main input
@@ -254,8 +560,11 @@ pub trait CollapsePrivate: Send + Sized {
                                 // Finally, return.
                                 return;
                             }
-                            // If successful, return to the top of the loop and continue to poll
-                            // the input and stop channels.
+                            // If successful, hand the buffer back for reuse, unless the main
+                            // thread already has enough of them, and return to the top of the
+                            // loop and continue to poll the input and stop channels.
+                            data.clear();
+                            let _ = tx_spare.try_send(data);
                         },
                         recv(rx_stop) -> _ => {
                             // Received a signal from another worker thread that it has errored;
@@ -283,6 +592,8 @@ pub trait CollapsePrivate: Send + Sized {
             let buf_capacity = usize::next_power_of_two(NBYTES_PER_STACK_GUESS * nstacks_per_job);
             let mut buf = Vec::with_capacity(buf_capacity);
             let (mut index, mut nstacks) = (0, 0);
+            // The number of lines read so far, and the number of lines before the current chunk.
+            let (mut nlines, mut offset) = (0_usize, 0);
 
             loop {
                 let n = reader.read_until(b'\n', &mut buf)?;
@@ -292,9 +603,35 @@ pub trait CollapsePrivate: Send + Sized {
                     // be alive (depending on if one errored in between the sending of the last
                     // chunk and the sending of this one), but either way we should break the loop;
                     // so there's no need to check for a `SendError` here.
-                    let _ = tx_input.send(buf);
+                    let _ = tx_input.send((offset, buf));
                     break;
                 }
+                nlines += 1;
+                // Every line has to go through `would_start_stack`, so that it knows the
+                // ancestors of the lines that follow, even though a chunk is never cut before
+                // its first line.
+                if self.would_start_stack(&buf[index..index + n]) && index != 0 {
+                    // If this line starts a new stack, the previous one has ended; count it.
+                    nstacks += 1;
+                    if nstacks == nstacks_per_job {
+                        // If we've accumulated enough stacks to make up a chunk, try to send
+                        // it, keeping this line for the next chunk. As below, a `SendError`
+                        // means the worker threads have errored.
+                        let mut next = self.stack_context();
+                        let context = next.len();
+                        let context_lines = memchr::memchr_iter(b'\n', &next).count();
+                        next.reserve(buf.capacity());
+                        next.extend_from_slice(&buf[index..]);
+                        buf.truncate(index);
+                        let chunk = mem::replace(&mut buf, next);
+                        if tx_input.send((offset, chunk)).is_err() {
+                            break;
+                        }
+                        index = context;
+                        offset = (nlines - 1).saturating_sub(context_lines);
+                        nstacks = 0;
+                    }
+                }
                 let line = &buf[index..index + n];
                 index += n;
                 if self.would_end_stack(line) {
@@ -304,8 +641,11 @@ pub trait CollapsePrivate: Send + Sized {
                         // If we've accumulated enough stacks to make up a chunk to send to the
                         // worker threads, try to send it.
                         let buf_capacity = usize::next_power_of_two(buf.capacity());
-                        let chunk = mem::replace(&mut buf, Vec::with_capacity(buf_capacity));
-                        if tx_input.send(chunk).is_err() {
+                        let next = rx_spare
+                            .try_recv()
+                            .unwrap_or_else(|_| Vec::with_capacity(buf_capacity));
+                        let chunk = mem::replace(&mut buf, next);
+                        if tx_input.send((offset, chunk)).is_err() {
                             // If sending the chunk produces a `SendError`, this means that one
                             // of the worker threads has errored, sent a signal to all the other
                             // worker threads to shut down, and they have all shutdown, in which
@@ -314,9 +654,10 @@ pub trait CollapsePrivate: Send + Sized {
                             break;
                         }
                         index = 0;
+                        offset = nlines;
                         nstacks = 0;
                     }
-                    continue
+                    continue;
                 }
             }
 
@@ -353,15 +694,28 @@ pub trait CollapsePrivate: Send + Sized {
 /// * AHashMap if single-threaded
 /// * DashMap if multi-threaded
 ///
+/// Counts are kept as fixed-point numbers with a number of fractional digits, which is 0 unless
+/// the collapser keeps fractional weights (see [`Occurrences::fractional`]).
+///
 /// This is public because it is part of the sealed `CollapsePrivate` trait's API, but it
 /// is in a crate-private module so is not nameable by downstream library users.
 #[derive(Clone, Debug)]
-pub enum Occurrences {
+pub struct Occurrences {
+    counts: Counts,
+    digits: usize,
+}
+
+#[derive(Clone, Debug)]
+enum Counts {
     SingleThreaded(AHashMap<String, usize>),
     #[cfg(feature = "multithreaded")]
     MultiThreaded(Arc<DashMap<String, usize, ahash::RandomState>>),
 }
 
+/// The fractional digits that [`Occurrences::fractional`] keeps of weights, like
+/// `flamegraph::Options::fractional_samples` does.
+const FRACTION_DIGITS: usize = 6;
+
 impl Occurrences {
     #[cfg(feature = "multithreaded")]
     pub(crate) fn new(nthreads: usize) -> Self {
@@ -382,34 +736,76 @@ impl Occurrences {
     fn new_single_threaded() -> Self {
         let map =
             AHashMap::with_capacity_and_hasher(CAPACITY_HASHMAP, ahash::RandomState::default());
-        Occurrences::SingleThreaded(map)
+        Occurrences {
+            counts: Counts::SingleThreaded(map),
+            digits: 0,
+        }
     }
 
     #[cfg(feature = "multithreaded")]
     fn new_multi_threaded() -> Self {
         let map =
             DashMap::with_capacity_and_hasher(CAPACITY_HASHMAP, ahash::RandomState::default());
-        Occurrences::MultiThreaded(Arc::new(map))
+        Occurrences {
+            counts: Counts::MultiThreaded(Arc::new(map)),
+            digits: 0,
+        }
+    }
+
+    /// Keeps the fractions of weights added with [`insert_or_add_weight`], up to six digits,
+    /// instead of rounding them. Counts without fractions are still written as whole numbers.
+    ///
+    ///   [`insert_or_add_weight`]: Occurrences::insert_or_add_weight
+    pub(crate) fn fractional(mut self) -> Self {
+        self.digits = FRACTION_DIGITS;
+        self
+    }
+
+    // The fixed-point count of a whole count.
+    fn scaled(&self, count: usize) -> usize {
+        if self.digits == 0 {
+            count
+        } else {
+            count.saturating_mul(10usize.pow(self.digits as u32))
+        }
     }
 
     /// Inserts a key-count pair into the map. If the map did not have this key
     /// present, `None` is returned. If the map did have this key present, the
     /// value is updated, and the old value is returned.
     pub(crate) fn insert(&mut self, key: String, count: usize) -> Option<usize> {
-        use self::Occurrences::*;
-        match self {
+        use self::Counts::*;
+        let count = self.scaled(count);
+        let old = match &mut self.counts {
             SingleThreaded(map) => map.insert(key, count),
             #[cfg(feature = "multithreaded")]
             MultiThreaded(arc) => arc.insert(key, count),
-        }
+        };
+        let scale = self.scaled(1);
+        old.map(|old| old / scale)
     }
 
     /// Inserts a key-count pair into the map if the key does not already exist.
     /// If the key does already exist, adds count to the current value of the
     /// existing key.
     pub(crate) fn insert_or_add(&mut self, key: String, count: usize) {
-        use self::Occurrences::*;
-        match self {
+        let count = self.scaled(count);
+        self.add(key, count);
+    }
+
+    /// Like [`insert_or_add`](Occurrences::insert_or_add), for a weight that may have a
+    /// fraction. The weight is rounded to the nearest whole number unless the map is
+    /// [`fractional`](Occurrences::fractional), and left out if it rounds to zero.
+    pub(crate) fn insert_or_add_weight(&mut self, key: String, weight: f64) {
+        let count = (weight * self.scaled(1) as f64).round() as usize;
+        if count != 0 {
+            self.add(key, count);
+        }
+    }
+
+    fn add(&mut self, key: String, count: usize) {
+        use self::Counts::*;
+        match &mut self.counts {
             SingleThreaded(map) => *map.entry(key).or_insert(0) += count,
             #[cfg(feature = "multithreaded")]
             MultiThreaded(arc) => *arc.entry(key).or_insert(0) += count,
@@ -417,8 +813,8 @@ impl Occurrences {
     }
 
     pub(crate) fn is_concurrent(&self) -> bool {
-        use self::Occurrences::*;
-        match self {
+        use self::Counts::*;
+        match self.counts {
             SingleThreaded(_) => false,
             #[cfg(feature = "multithreaded")]
             MultiThreaded(_) => true,
@@ -429,13 +825,14 @@ impl Occurrences {
     where
         W: io::Write,
     {
-        use self::Occurrences::*;
-        match self {
+        use self::Counts::*;
+        let digits = self.digits;
+        match &mut self.counts {
             SingleThreaded(ref mut map) => {
                 let mut contents: Vec<_> = map.drain().collect();
                 contents.sort();
                 for (key, value) in contents {
-                    writeln!(writer, "{} {}", key, value)?;
+                    write_line(&mut writer, &key, value, digits)?;
                 }
             }
             #[cfg(feature = "multithreaded")]
@@ -459,7 +856,7 @@ impl Occurrences {
                 let mut pairs = contents.iter().map(|pair| pair.pair()).collect::<Vec<_>>();
                 pairs.sort();
                 for (key, value) in pairs {
-                    writeln!(writer, "{} {}", key, value)?;
+                    write_line(&mut writer, key, *value, digits)?;
                 }
             }
         }
@@ -468,99 +865,30 @@ impl Occurrences {
     }
 }
 
-/// Demangles partially demangled Rust symbols that were demangled incorrectly by profilers like
-/// `sample` and `DTrace`.
-///
-/// For example:
-///     `_$LT$grep_searcher..searcher..glue..ReadByLine$LT$$u27$s$C$$u20$M$C$$u20$R$C$$u20$S$GT$$GT$::run::h30ecedc997ad7e32`
-/// becomes
-///     `<grep_searcher::searcher::glue::ReadByLine<'s, M, R, S>>::run`
-///
-/// Non-Rust symobols, or Rust symbols that are already demangled, will be returned unchanged.
-///
-/// Based on code in https://github.com/alexcrichton/rustc-demangle/blob/master/src/legacy.rs
-#[allow(clippy::cognitive_complexity)]
-pub(crate) fn fix_partially_demangled_rust_symbol(symbol: &str) -> Cow<str> {
-    // Rust hashes are hex digits with an `h` prepended.
-    let is_rust_hash =
-        |s: &str| s.starts_with('h') && s[1..].chars().all(|c| c.is_ascii_hexdigit());
-
-    // If there's no trailing Rust hash just return the symbol as is.
-    if symbol.len() < RUST_HASH_LENGTH || !is_rust_hash(&symbol[symbol.len() - RUST_HASH_LENGTH..])
-    {
-        return Cow::Borrowed(symbol);
-    }
-
-    // Strip off trailing hash.
-    let mut rest = &symbol[..symbol.len() - RUST_HASH_LENGTH];
-
-    if rest.ends_with("::") {
-        rest = &rest[..rest.len() - 2];
+// Writes a folded line, with a count of `digits` fractional digits, leaving out the trailing
+// zeros of its fraction.
+fn write_line<W: io::Write>(
+    writer: &mut W,
+    key: &str,
+    count: usize,
+    digits: usize,
+) -> io::Result<()> {
+    if digits == 0 {
+        return writeln!(writer, "{} {}", key, count);
     }
-
-    if rest.starts_with("_$") {
-        rest = &rest[1..];
+    let scale = 10usize.pow(digits as u32);
+    let (whole, fraction) = (count / scale, count % scale);
+    if fraction == 0 {
+        return writeln!(writer, "{} {}", key, whole);
     }
-
-    let mut demangled = String::new();
-
-    while !rest.is_empty() {
-        if rest.starts_with('.') {
-            if let Some('.') = rest[1..].chars().next() {
-                demangled.push_str("::");
-                rest = &rest[2..];
-            } else {
-                demangled.push('.');
-                rest = &rest[1..];
-            }
-        } else if rest.starts_with('$') {
-            macro_rules! demangle {
-                ($($pat:expr => $demangled:expr,)*) => ({
-                    $(if rest.starts_with($pat) {
-                        demangled.push_str($demangled);
-                        rest = &rest[$pat.len()..];
-                        } else)*
-                    {
-                        demangled.push_str(rest);
-                        break;
-                    }
-
-                })
-            }
-
-            demangle! {
-                "$SP$" => "@",
-                "$BP$" => "*",
-                "$RF$" => "&",
-                "$LT$" => "<",
-                "$GT$" => ">",
-                "$LP$" => "(",
-                "$RP$" => ")",
-                "$C$" => ",",
-                "$u7e$" => "~",
-                "$u20$" => " ",
-                "$u27$" => "'",
-                "$u3d$" => "=",
-                "$u5b$" => "[",
-                "$u5d$" => "]",
-                "$u7b$" => "{",
-                "$u7d$" => "}",
-                "$u3b$" => ";",
-                "$u2b$" => "+",
-                "$u21$" => "!",
-                "$u22$" => "\"",
-            }
-        } else {
-            let idx = match rest.char_indices().find(|&(_, c)| c == '$' || c == '.') {
-                None => rest.len(),
-                Some((i, _)) => i,
-            };
-            demangled.push_str(&rest[..idx]);
-            rest = &rest[idx..];
-        }
-    }
-
-    Cow::Owned(demangled)
+    let fraction = format!("{:0width$}", fraction, width = digits);
+    writeln!(
+        writer,
+        "{} {}.{}",
+        key,
+        whole,
+        fraction.trim_end_matches('0')
+    )
 }
 
 #[cfg(test)]
@@ -682,7 +1010,7 @@ pub(crate) mod testing {
                     let mut durations = Vec::new();
                     for _ in 0..NSAMPLES {
                         let now = Instant::now();
-                        folder.collapse(bytes, io::sink())?;
+                        folder.collapse(bytes, io::sink(), &CancelToken::new())?;
                         durations.push(now.elapsed().as_nanos());
                     }
                     let avg_duration =
@@ -759,7 +1087,7 @@ pub(crate) mod testing {
         stdout.flush()?;
         while now.elapsed() < std::time::Duration::from_secs(WARMUP_SECS as u64) {
             for (_, bytes) in inputs.iter() {
-                folder.collapse(&bytes[..], io::sink())?;
+                folder.collapse(&bytes[..], io::sink(), &CancelToken::new())?;
             }
         }
 
@@ -794,86 +1122,3 @@ Initialise it with `git submodule update --init flamegraph`."
         }
     }
 }
-
-#[cfg(test)]
-mod tests {
-    macro_rules! t {
-        ($a:expr, $b:expr) => {
-            assert!(ok($a, $b))
-        };
-    }
-
-    macro_rules! t_unchanged {
-        ($a:expr) => {
-            assert!(ok_unchanged($a))
-        };
-    }
-
-    fn ok(sym: &str, expected: &str) -> bool {
-        let result = super::fix_partially_demangled_rust_symbol(sym);
-        if result == expected {
-            true
-        } else {
-            println!("\n{}\n!=\n{}\n", result, expected);
-            false
-        }
-    }
-
-    fn ok_unchanged(sym: &str) -> bool {
-        let result = super::fix_partially_demangled_rust_symbol(sym);
-        if result == sym {
-            true
-        } else {
-            println!("{} should have been unchanged, but got {}", sym, result);
-            false
-        }
-    }
-
-    #[test]
-    fn fix_partially_demangled_rust_symbols() {
-        t!(
-            "std::sys::unix::fs::File::open::hb90e1c1c787080f0",
-            "std::sys::unix::fs::File::open"
-        );
-        t!("_$LT$std..fs..ReadDir$u20$as$u20$core..iter..traits..iterator..Iterator$GT$::next::hc14f1750ca79129b", "<std::fs::ReadDir as core::iter::traits::iterator::Iterator>::next");
-        t!("rg::search_parallel::_$u7b$$u7b$closure$u7d$$u7d$::_$u7b$$u7b$closure$u7d$$u7d$::h6e849b55a66fcd85", "rg::search_parallel::_{{closure}}::_{{closure}}");
-        t!(
-            "_$LT$F$u20$as$u20$alloc..boxed..FnBox$LT$A$GT$$GT$::call_box::h8612a2a83552fc2d",
-            "<F as alloc::boxed::FnBox<A>>::call_box"
-        );
-        t!(
-            "_$LT$$RF$std..fs..File$u20$as$u20$std..io..Read$GT$::read::h5d84059cf335c8e6",
-            "<&std::fs::File as std::io::Read>::read"
-        );
-        t!(
-            "_$LT$std..thread..JoinHandle$LT$T$GT$$GT$::join::hca6aa63e512626da",
-            "<std::thread::JoinHandle<T>>::join"
-        );
-        t!(
-            "std::sync::mpsc::shared::Packet$LT$T$GT$::recv::hfde2d9e28d13fd56",
-            "std::sync::mpsc::shared::Packet<T>::recv"
-        );
-        t!("crossbeam_utils::thread::ScopedThreadBuilder::spawn::_$u7b$$u7b$closure$u7d$$u7d$::h8fdc7d4f74c0da05", "crossbeam_utils::thread::ScopedThreadBuilder::spawn::_{{closure}}");
-    }
-
-    #[test]
-    fn fix_partially_demangled_rust_symbol_on_fully_mangled_symbols() {
-        t_unchanged!("_ZN4testE");
-        t_unchanged!("_ZN4test1a2bcE");
-        t_unchanged!("_ZN7inferno10flamegraph5merge6frames17hacfe2d67301633c2E");
-        t_unchanged!("_ZN3std2rt19lang_start_internal17h540c897fe52ba9c5E");
-        t_unchanged!("_ZN116_$LT$core..str..pattern..CharSearcher$LT$$u27$a$GT$$u20$as$u20$core..str..pattern..ReverseSearcher$LT$$u27$a$GT$$GT$15next_match_back17h09d544049dd719bbE");
-        t_unchanged!("_ZN3std5panic12catch_unwind17h0562757d03ff60b3E");
-        t_unchanged!("_ZN3std9panicking3try17h9c1cbc5599e1efbfE");
-    }
-
-    #[test]
-    fn fix_partially_demangled_rust_symbol_on_fully_demangled_symbols() {
-        t_unchanged!("std::sys::unix::fs::File::open");
-        t_unchanged!("<F as alloc::boxed::FnBox<A>>::call_box");
-        t_unchanged!("<std::fs::ReadDir as core::iter::traits::iterator::Iterator>::next");
-        t_unchanged!("<rg::search::SearchWorker<W>>::search_impl");
-        t_unchanged!("<grep_searcher::searcher::glue::ReadByLine<'s, M, R, S>>::run");
-        t_unchanged!("<alloc::raw_vec::RawVec<T, A>>::reserve_internal");
-    }
-}
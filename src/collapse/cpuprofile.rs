@@ -0,0 +1,340 @@
+use std::io;
+#[cfg(feature = "sourcemap")]
+use std::path::PathBuf;
+#[cfg(feature = "sourcemap")]
+use std::sync::Arc;
+
+use ahash::AHashMap;
+use log::warn;
+use serde_json::Value;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+use crate::folded::Header;
+#[cfg(feature = "sourcemap")]
+use crate::symbolize::source_map::SourceMaps;
+use crate::Error;
+
+/// `cpuprofile` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct Options {
+    /// Count every sample once instead of weighting it by the microseconds until the next
+    /// sample.
+    ///
+    /// Profiles without samples are always weighted by the hit counts of their nodes.
+    ///
+    /// Default is `false`.
+    pub count_samples: bool,
+
+    /// Add the script, line and column of each function to its frame, like
+    /// `render (app.js:12:5)`.
+    ///
+    /// Default is `false`.
+    pub include_locations: bool,
+
+    /// Source maps of minified JavaScript, or directories of `.map` files.
+    ///
+    /// Functions of a script that there's a source map for, matched by its file name, are named
+    /// by their original name, and located in the original source with `include_locations`.
+    /// Only available with the `sourcemap` feature.
+    ///
+    /// Default is no source maps.
+    #[cfg(feature = "sourcemap")]
+    pub source_maps: Vec<PathBuf>,
+
+    /// Leave out the samples taken while the program was idle.
+    ///
+    /// Default is `false`.
+    pub skip_idle: bool,
+
+    /// Write a [`Header`] before the folded stacks.
+    ///
+    /// Default is `false`.
+    pub header: bool,
+}
+
+/// A stack collapser for the `.cpuprofile` JSON of V8, as written by `node --cpu-prof`, Chrome
+/// DevTools and the `Profiler` domain of the Chrome DevTools Protocol.
+///
+/// A profile is a tree of `nodes` that each have a `callFrame`, and the `samples` that were
+/// taken, as the ids of the nodes they were taken in. Samples are weighted by their
+/// `timeDeltas`, in microseconds: every sample lasts until the next one, and the last one lasts
+/// until the `endTime` of the profile.
+///
+/// The `(root)` node is left out of stacks, while V8's other special nodes, like `(program)`,
+/// `(idle)` and `(garbage collector)`, are kept as root frames.
+///
+/// To construct one, either use `cpuprofile::Folder::default()` or create an [`Options`] and use
+/// `cpuprofile::Folder::from(options)`.
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+
+    /// The source maps of `opt.source_maps`, once they're loaded.
+    #[cfg(feature = "sourcemap")]
+    source_maps: Option<Arc<SourceMaps>>,
+}
+
+/// A node of the profile tree.
+struct Node {
+    name: String,
+    parent: Option<u64>,
+    hit_count: u64,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, mut reader: R, mut writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut input = String::new();
+        reader.read_to_string(&mut input)?;
+
+        let profile: Value =
+            serde_json::from_str(&input).map_err(|e| Error::parse("cpuprofile", e))?;
+        #[cfg(feature = "sourcemap")]
+        if self.source_maps.is_none() && !self.opt.source_maps.is_empty() {
+            self.source_maps = Some(Arc::new(SourceMaps::load(&self.opt.source_maps)?));
+        }
+        let nodes = self.nodes(&profile)?;
+
+        // The weight of each node, in microseconds or samples.
+        let mut weights: AHashMap<u64, u64> = AHashMap::default();
+        let samples = u64_array(&profile, "samples");
+        let time_deltas = i64_array(&profile, "timeDeltas");
+        let timed = !samples.is_empty() && !self.opt.count_samples;
+        if samples.is_empty() {
+            for (&id, node) in &nodes {
+                *weights.entry(id).or_default() += node.hit_count;
+            }
+        } else if timed {
+            if time_deltas.len() != samples.len() {
+                return Err(Error::parse(
+                    "cpuprofile",
+                    "the profile doesn't have a time delta for every sample",
+                )
+                .into());
+            }
+            let end_time = profile.get("endTime").and_then(Value::as_i64);
+            let mut time = profile
+                .get("startTime")
+                .and_then(Value::as_i64)
+                .unwrap_or_default();
+            let mut times = Vec::with_capacity(samples.len());
+            for delta in &time_deltas {
+                time += delta;
+                times.push(time);
+            }
+            for (i, &id) in samples.iter().enumerate() {
+                let next = times.get(i + 1).copied().or(end_time).unwrap_or(times[i]);
+                // Chrome may write samples slightly out of order.
+                let duration = next.saturating_sub(times[i]).max(0) as u64;
+                *weights.entry(id).or_default() += duration;
+            }
+        } else {
+            for &id in &samples {
+                *weights.entry(id).or_default() += 1;
+            }
+        }
+
+        let mut occurrences = Occurrences::new(1);
+        let mut unknown = 0;
+        let mut stacks: AHashMap<u64, Option<String>> = AHashMap::default();
+        for (id, weight) in weights {
+            if weight == 0 {
+                continue;
+            }
+            if !nodes.contains_key(&id) {
+                unknown += 1;
+                continue;
+            }
+            let stack = stacks.entry(id).or_insert_with(|| self.stack(&nodes, id));
+            if let Some(stack) = stack {
+                occurrences.insert_or_add(stack.clone(), weight as usize);
+            }
+        }
+
+        if unknown != 0 {
+            warn!("Ignored {} samples of unknown nodes", unknown);
+        }
+        if self.opt.header {
+            let mut header = Header::for_tool("inferno-collapse-cpuprofile");
+            header.units = Some(if timed { "microseconds" } else { "samples" }.to_string());
+            let time = |key| profile.get(key).and_then(Value::as_f64).map(|t| t / 1e6);
+            header.start = time("startTime");
+            header.end = time("endTime");
+            header.write(&mut writer)?;
+        }
+        occurrences.write_and_clear(writer)
+    }
+
+    /// Check for a JSON object with the keys of a cpuprofile
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        let input = input.trim_start();
+        if !input.starts_with('{') {
+            return Some(false);
+        }
+        if input.contains("\"nodes\"") && input.contains("\"callFrame\"") {
+            Some(true)
+        } else {
+            None
+        }
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder {
+            opt,
+            #[cfg(feature = "sourcemap")]
+            source_maps: None,
+        }
+    }
+}
+
+impl Folder {
+    // Reads the nodes of the profile, by their id.
+    fn nodes(&self, profile: &Value) -> io::Result<AHashMap<u64, Node>> {
+        let nodes = profile
+            .get("nodes")
+            .and_then(Value::as_array)
+            .ok_or_else(|| Error::parse("cpuprofile", "the profile has no nodes"))?;
+
+        let mut parents = AHashMap::default();
+        for node in nodes {
+            if let Some(id) = node.get("id").and_then(Value::as_u64) {
+                for child in u64_array(node, "children") {
+                    parents.insert(child, id);
+                }
+            }
+        }
+
+        let mut by_id = AHashMap::default();
+        let mut invalid = 0;
+        for node in nodes {
+            let (id, call_frame) = match (
+                node.get("id").and_then(Value::as_u64),
+                node.get("callFrame"),
+            ) {
+                (Some(id), Some(call_frame)) => (id, call_frame),
+                _ => {
+                    invalid += 1;
+                    continue;
+                }
+            };
+            // Nodes of trace events refer to their parent instead of their children.
+            let parent = node
+                .get("parent")
+                .and_then(Value::as_u64)
+                .or_else(|| parents.get(&id).copied());
+            let hit_count = node
+                .get("hitCount")
+                .and_then(Value::as_u64)
+                .unwrap_or_default();
+            let name = self.frame_name(call_frame);
+            by_id.insert(
+                id,
+                Node {
+                    name,
+                    parent,
+                    hit_count,
+                },
+            );
+        }
+
+        if invalid != 0 {
+            warn!("Ignored {} nodes without an id or call frame", invalid);
+        }
+        Ok(by_id)
+    }
+
+    fn frame_name(&self, call_frame: &Value) -> String {
+        let name = call_frame
+            .get("functionName")
+            .and_then(Value::as_str)
+            .filter(|name| !name.is_empty())
+            .unwrap_or("(anonymous)");
+        let url = call_frame
+            .get("url")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        if url.is_empty() {
+            return name.to_string();
+        }
+        // Lines and columns are zero-based.
+        let position = |key| {
+            call_frame
+                .get(key)
+                .and_then(Value::as_i64)
+                .and_then(|n| u32::try_from(n + 1).ok())
+                .filter(|&n| n != 0)
+        };
+        let (line, column) = (position("lineNumber"), position("columnNumber"));
+
+        #[cfg(feature = "sourcemap")]
+        if let (Some(source_maps), Some(line), Some(column)) = (&self.source_maps, line, column) {
+            if let Some((name, source)) = source_maps.lookup(name, url, line, column) {
+                return if self.opt.include_locations {
+                    format!("{} ({})", name, source)
+                } else {
+                    name.to_string()
+                };
+            }
+        }
+
+        if !self.opt.include_locations {
+            return name.to_string();
+        }
+        match (line, column) {
+            (Some(line), Some(column)) => format!("{} ({}:{}:{})", name, url, line, column),
+            (Some(line), None) => format!("{} ({}:{})", name, url, line),
+            _ => format!("{} ({})", name, url),
+        }
+    }
+
+    // Builds the folded stack of a node, or `None` if its samples should be left out.
+    fn stack(&self, nodes: &AHashMap<u64, Node>, id: u64) -> Option<String> {
+        let mut frames = Vec::new();
+        let mut next = Some(id);
+        while let Some(node) = next.and_then(|id| nodes.get(&id)) {
+            // The root node has no parent.
+            if node.parent.is_none() {
+                break;
+            }
+            // Guard against cycles in malformed profiles.
+            if frames.len() > nodes.len() {
+                return None;
+            }
+            frames.push(node.name.as_str());
+            next = node.parent;
+        }
+        if frames.is_empty() || (self.opt.skip_idle && frames == ["(idle)"]) {
+            return None;
+        }
+        frames.reverse();
+        Some(frames.join(";"))
+    }
+}
+
+fn u64_array(value: &Value, key: &str) -> Vec<u64> {
+    value
+        .get(key)
+        .and_then(Value::as_array)
+        .map(|values| values.iter().filter_map(Value::as_u64).collect())
+        .unwrap_or_default()
+}
+
+fn i64_array(value: &Value, key: &str) -> Vec<i64> {
+    value
+        .get(key)
+        .and_then(Value::as_array)
+        .map(|values| values.iter().filter_map(Value::as_i64).collect())
+        .unwrap_or_default()
+}
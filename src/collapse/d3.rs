@@ -0,0 +1,130 @@
+use std::io;
+
+use log::warn;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+use crate::Error;
+
+/// `d3` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct Options {
+    /// Keep the root node as the root frame of every stack.
+    ///
+    /// The root of d3-flame-graph data is usually a node that stands for the whole profile, like
+    /// `root` or `all`, so it's left out by default.
+    ///
+    /// Default is `false`.
+    pub keep_root: bool,
+
+    /// The `value` of each node is its self value instead of its total value, like with
+    /// d3-flame-graph's `selfValue(true)`.
+    ///
+    /// Default is `false`.
+    pub self_value: bool,
+}
+
+/// A stack collapser for the JSON data of [d3-flame-graph], a tree of nodes that each have a
+/// `name`, a `value` and `children`.
+///
+/// To construct one, either use `d3::Folder::default()` or create an [`Options`] and use
+/// `d3::Folder::from(options)`.
+///
+///   [d3-flame-graph]: https://github.com/spiermar/d3-flame-graph
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut input = String::new();
+        reader.read_to_string(&mut input)?;
+
+        // Every level of the tree is two levels of JSON, so deep stacks are past serde_json's
+        // default recursion limit.
+        let mut deserializer = serde_json::Deserializer::from_str(&input);
+        deserializer.disable_recursion_limit();
+        let root = Value::deserialize(&mut deserializer).map_err(|e| Error::parse("d3", e))?;
+
+        let mut occurrences = Occurrences::new(1);
+        let mut invalid = 0;
+        // Nodes to visit, with the depth of the node and the stack of its parent.
+        let mut pending = vec![(&root, 0usize)];
+        let mut stack: Vec<&str> = Vec::new();
+        while let Some((node, depth)) = pending.pop() {
+            let name = node.get("name").and_then(Value::as_str);
+            let value = node.get("value").and_then(Value::as_f64);
+            let (name, value) = match (name, value) {
+                (Some(name), Some(value)) => (name, value),
+                _ => {
+                    invalid += 1;
+                    continue;
+                }
+            };
+            let children = node
+                .get("children")
+                .and_then(Value::as_array)
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+
+            let keep = depth > 0 || self.opt.keep_root;
+            stack.truncate(depth.saturating_sub(usize::from(!self.opt.keep_root)));
+            if keep {
+                stack.push(name);
+            }
+
+            let own = if self.opt.self_value {
+                value
+            } else {
+                let children: f64 = children
+                    .iter()
+                    .filter_map(|child| child.get("value").and_then(Value::as_f64))
+                    .sum();
+                value - children
+            };
+            let own = own.round();
+            if own >= 1.0 && !stack.is_empty() {
+                occurrences.insert_or_add(stack.join(";"), own as usize);
+            }
+
+            pending.extend(children.iter().rev().map(|child| (child, depth + 1)));
+        }
+
+        if invalid != 0 {
+            warn!("Ignored {} nodes without a name or value", invalid);
+        }
+        occurrences.write_and_clear(writer)
+    }
+
+    /// Check for a JSON object with d3-flame-graph keys
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        let input = input.trim_start();
+        if !input.starts_with('{') {
+            return Some(false);
+        }
+        if input.contains("\"children\"") && input.contains("\"value\"") {
+            Some(true)
+        } else {
+            None
+        }
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
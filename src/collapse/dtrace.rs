@@ -1,31 +1,242 @@
 use std::borrow::Cow;
 use std::collections::VecDeque;
+use std::fmt;
 use std::io::{self, prelude::*};
+use std::str::FromStr;
 
 use log::warn;
 
 use crate::collapse::common::{self, CollapsePrivate, Occurrences};
+use crate::folded::Header;
+use crate::symbols::demangle;
+use crate::Error;
+
+/// How the buckets of a `quantize()`, `lquantize()` or `llquantize()` aggregation are folded
+/// into a single weight for their stack.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum DistributionWeight {
+    /// Use the number of values that were aggregated, that is, the sum of the bucket counts.
+    Count,
+
+    /// Use the (approximate) sum of the values that were aggregated, that is, the sum of each
+    /// bucket's lower bound multiplied by its count. Useful when the aggregated values are
+    /// durations, as in `quantize(timestamp - self->ts)`.
+    #[default]
+    Value,
+}
+
+impl DistributionWeight {
+    /// The valid set of distribution weights (via `FromStr`).
+    pub const VARIANTS: &'static [&'static str] = &["count", "value"];
+}
+
+impl FromStr for DistributionWeight {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "count" => Ok(DistributionWeight::Count),
+            "value" => Ok(DistributionWeight::Value),
+            unknown => Err(format!("unknown distribution weight: {}", unknown)),
+        }
+    }
+}
+
+/// The unit to convert nanosecond aggregation values to, for aggregations like
+/// `@[stack()] = sum(timestamp - self->ts)`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeUnit {
+    /// Nanoseconds (the values are left as they are).
+    Nanoseconds,
+    /// Microseconds.
+    Microseconds,
+    /// Milliseconds.
+    Milliseconds,
+    /// Seconds.
+    Seconds,
+}
+
+impl TimeUnit {
+    /// The valid set of time units (via `FromStr`).
+    pub const VARIANTS: &'static [&'static str] = &["ns", "us", "ms", "s"];
+
+    fn nanoseconds(self) -> usize {
+        match self {
+            TimeUnit::Nanoseconds => 1,
+            TimeUnit::Microseconds => 1_000,
+            TimeUnit::Milliseconds => 1_000_000,
+            TimeUnit::Seconds => 1_000_000_000,
+        }
+    }
+}
+
+impl FromStr for TimeUnit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ns" => Ok(TimeUnit::Nanoseconds),
+            "us" => Ok(TimeUnit::Microseconds),
+            "ms" => Ok(TimeUnit::Milliseconds),
+            "s" => Ok(TimeUnit::Seconds),
+            unknown => Err(format!("unknown time unit: {}", unknown)),
+        }
+    }
+}
+
+impl fmt::Display for TimeUnit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TimeUnit::Nanoseconds => "ns",
+            TimeUnit::Microseconds => "us",
+            TimeUnit::Milliseconds => "ms",
+            TimeUnit::Seconds => "s",
+        })
+    }
+}
+
+#[cfg(feature = "config")]
+impl serde::Serialize for TimeUnit {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "config")]
+impl<'de> serde::Deserialize<'de> for TimeUnit {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        crate::config::deserialize_from_str(deserializer)
+    }
+}
+
+/// How the module of `module`function` frames is kept in the folded stacks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum ModuleNames {
+    /// Keep the module as a prefix of the frame, as printed by DTrace (`libc.so.1`_read`).
+    #[default]
+    Prefix,
+
+    /// Drop the module (`_read`).
+    Strip,
+
+    /// Keep the module as an annotation after the function (`_read [libc.so.1]`).
+    Annotate,
+
+    /// Keep the module as a frame of its own, right above the function (`libc.so.1;_read`).
+    Frame,
+}
+
+impl ModuleNames {
+    /// The valid set of module name modes (via `FromStr`).
+    pub const VARIANTS: &'static [&'static str] = &["prefix", "strip", "annotate", "frame"];
+}
+
+impl FromStr for ModuleNames {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "prefix" => Ok(ModuleNames::Prefix),
+            "strip" => Ok(ModuleNames::Strip),
+            "annotate" => Ok(ModuleNames::Annotate),
+            "frame" => Ok(ModuleNames::Frame),
+            unknown => Err(format!("unknown module name mode: {}", unknown)),
+        }
+    }
+}
 
 /// `dtrace` folder configuration options.
 #[derive(Clone, Debug)]
 #[non_exhaustive]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
 pub struct Options {
+    /// How to weigh stacks whose aggregation is a distribution (`quantize()` and friends).
+    ///
+    /// Default is `DistributionWeight::Value`.
+    pub distribution_weight: DistributionWeight,
+
+    /// Which mangled symbols to demangle. DTrace leaves C++ symbols mangled on some systems, and
+    /// only partially demangles Rust symbols.
+    ///
+    /// Default is `demangle::Options::default()`, which demangles all supported manglings.
+    pub demangle: demangle::Options,
+
+    /// Write a [`Header`] before the folded stacks, with the units of the values if `units` or
+    /// `time_unit` is set.
+    ///
+    /// Default is `false`.
+    pub header: bool,
+
     /// Include function offset (except leafs).
     ///
     /// Default is `false`.
     pub includeoffset: bool,
 
+    /// Use the process name from the aggregation key as the root frame of each stack, as with
+    /// `@[execname, ustack()] = count();`. DTrace prints the scalar keys on the line before the
+    /// stack. Stacks of aggregations without keys are left as they are.
+    ///
+    /// Default is `false`.
+    pub include_pname: bool,
+
+    /// Also include the remaining scalar keys (typically the pid and/or tid, as with
+    /// `@[execname, pid, tid, ustack()]`) in the root frame, like `bash-1234/1235`. Implies
+    /// `include_pname`.
+    ///
+    /// Default is `false`.
+    pub include_tid: bool,
+
+    /// How to present the module of each frame.
+    ///
+    /// Default is `ModuleNames::Prefix`.
+    pub module_names: ModuleNames,
+
     /// The number of threads to use.
     ///
     /// Default is the number of logical cores on your machine.
     pub nthreads: usize,
+
+    /// If set, the aggregated values are taken to be nanoseconds (as with
+    /// `sum(timestamp - self->ts)`) and are converted to the given unit, rounded to the nearest
+    /// one.
+    ///
+    /// Default is `None`, which leaves the values as they are.
+    pub time_unit: Option<TimeUnit>,
+
+    /// What the aggregated values are in, like `bytes` for `@[ustack()] = sum(arg0);`, for the
+    /// [`Header`]. Overrides the unit of `time_unit`.
+    ///
+    /// Default is `None`.
+    pub units: Option<String>,
 }
 
 impl Default for Options {
     fn default() -> Self {
         Self {
+            distribution_weight: DistributionWeight::default(),
+            demangle: demangle::Options::default(),
+            header: false,
             includeoffset: false,
+            include_pname: false,
+            include_tid: false,
+            module_names: ModuleNames::default(),
             nthreads: *common::DEFAULT_NTHREADS,
+            time_unit: None,
+            units: None,
         }
     }
 }
@@ -38,9 +249,18 @@ pub struct Folder {
     /// Vector for processing java stuff
     cache_inlines: Vec<String>,
 
+    /// The weight of the distribution we're in the middle of, if any.
+    distribution: Option<usize>,
+
+    /// Whether `would_end_stack` is in the middle of a distribution.
+    in_distribution: bool,
+
     /// The number of stacks per job to send to the threadpool.
     nstacks_per_job: usize,
 
+    /// The root frame built from the aggregation key of the current stack.
+    pname: Option<String>,
+
     /// Function entries on the stack in this entry thus far.
     stack: VecDeque<String>,
 
@@ -55,9 +275,13 @@ impl From<Options> for Folder {
         if opt.nthreads == 0 {
             opt.nthreads = 1;
         }
+        opt.include_pname = opt.include_pname || opt.include_tid;
         Self {
             cache_inlines: Vec::new(),
+            distribution: None,
+            in_distribution: false,
             nstacks_per_job: common::DEFAULT_NSTACKS_PER_JOB,
+            pname: None,
             stack: VecDeque::default(),
             stack_str_size: 0,
             opt,
@@ -108,23 +332,30 @@ impl CollapsePrivate for Folder {
             let s = String::from_utf8_lossy(&line);
             let line = s.trim();
             if line.is_empty() {
+                if let Some(weight) = self.distribution.take() {
+                    self.on_stack_end(weight, occurrences);
+                }
                 continue;
+            } else if self.distribution.is_some() {
+                self.on_distribution_line(line);
+            } else if is_distribution_header(line.as_bytes()) {
+                self.distribution = Some(0);
             } else if let Ok(count) = line.parse::<usize>() {
                 self.on_stack_end(count, occurrences);
             } else {
                 self.on_stack_line(line);
             }
         }
+        if let Some(weight) = self.distribution.take() {
+            self.on_stack_end(weight, occurrences);
+        }
         // If we reach this point in the code and there's still something in our
         // state (`self.stack` and `self.stack_str_size`), it means the input
         // did not terminate at the end of a stack; rather, it terminated in
         // the middle of a stack. In this case, we consider the input data
         // invalid and return an io::Error to the user.
         if !self.stack.is_empty() || self.stack_str_size != 0 {
-            return Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                "Input data ends in the middle of a stack.",
-            ));
+            return Err(Error::parse("dtrace", "Input data ends in the middle of a stack.").into());
         }
         Ok(())
     }
@@ -148,11 +379,9 @@ impl CollapsePrivate for Folder {
             if line.is_empty() {
                 found_empty_line = true;
             } else if found_empty_line {
-                if line.parse::<usize>().is_ok() {
+                if line.parse::<usize>().is_ok() || is_distribution_header(line.as_bytes()) {
                     return Some(found_stack_line);
-                } else if line.contains('`')
-                    || (line.starts_with("0x") && usize::from_str_radix(&line[2..], 16).is_ok())
-                {
+                } else if line.contains('`') || Self::helper_label(line).is_some() {
                     found_stack_line = true;
                 } else {
                     // This is not a stack or count line
@@ -187,6 +416,19 @@ impl CollapsePrivate for Folder {
     // * Using the method below: 437 MiB/s
     //
     fn would_end_stack(&mut self, line: &[u8]) -> bool {
+        // Distributions (from `quantize()` and friends) end at the first empty line after their
+        // header instead.
+        if self.in_distribution {
+            if line.iter().all(|b| (*b as char).is_whitespace()) {
+                self.in_distribution = false;
+                return true;
+            }
+            return false;
+        } else if is_distribution_header(line) {
+            self.in_distribution = true;
+            return false;
+        }
+
         // In order to return `true`, as we iterate over the provided bytes, we need to progress
         // through each of the follow states, in order; if we can't, immediately return `false`.
         #[allow(clippy::enum_variant_names)]
@@ -232,7 +474,10 @@ impl CollapsePrivate for Folder {
     fn clone_and_reset_stack_context(&self) -> Self {
         Self {
             cache_inlines: self.cache_inlines.clone(),
+            distribution: None,
+            in_distribution: false,
             nstacks_per_job: self.nstacks_per_job,
+            pname: None,
             stack: VecDeque::default(),
             stack_str_size: 0,
             opt: self.opt.clone(),
@@ -254,6 +499,25 @@ impl CollapsePrivate for Folder {
     fn set_nthreads(&mut self, n: usize) {
         self.opt.nthreads = n;
     }
+
+    fn header(&self) -> Option<Header> {
+        if !self.opt.header {
+            return None;
+        }
+        let mut header = Header::for_tool("inferno-collapse-dtrace");
+        header.units = self.opt.units.clone().or_else(|| {
+            self.opt.time_unit.map(|unit| {
+                match unit {
+                    TimeUnit::Nanoseconds => "nanoseconds",
+                    TimeUnit::Microseconds => "microseconds",
+                    TimeUnit::Milliseconds => "milliseconds",
+                    TimeUnit::Seconds => "seconds",
+                }
+                .to_string()
+            })
+        });
+        Some(header)
+    }
 }
 
 impl Folder {
@@ -297,15 +561,15 @@ impl Folder {
         )
     }
 
-    // DTrace doesn't properly demangle Rust function names, so fix those.
-    fn fix_rust_symbol<'a>(&self, frame: &'a str) -> Cow<'a, str> {
+    // DTrace doesn't always demangle function names, or demangle them properly, so fix those.
+    fn demangle_frame<'a>(&self, frame: &'a str) -> Cow<'a, str> {
         let mut parts = frame.splitn(2, '`');
         if let (Some(pname), Some(func)) = (parts.next(), parts.next()) {
             if self.opt.includeoffset {
                 let mut parts = func.rsplitn(2, '+');
                 if let (Some(offset), Some(func)) = (parts.next(), parts.next()) {
                     if let Cow::Owned(func) =
-                        common::fix_partially_demangled_rust_symbol(func.trim_end())
+                        demangle::demangle(func.trim_end(), &self.opt.demangle)
                     {
                         return Cow::Owned(format!("{}`{}+{}", pname, func, offset));
                     } else {
@@ -314,7 +578,7 @@ impl Folder {
                 }
             }
 
-            if let Cow::Owned(func) = common::fix_partially_demangled_rust_symbol(func.trim_end()) {
+            if let Cow::Owned(func) = demangle::demangle(func.trim_end(), &self.opt.demangle) {
                 return Cow::Owned(format!("{}`{}", pname, func));
             }
         }
@@ -330,6 +594,20 @@ impl Folder {
     //     unix`sys_syscall+0x10e
     //       1
     fn on_stack_line(&mut self, line: &str) {
+        if self.opt.include_pname
+            && self.pname.is_none()
+            && self.stack.is_empty()
+            && Self::is_key_line(line)
+        {
+            self.on_key_line(line);
+            return;
+        }
+
+        let line = match Self::helper_label(line) {
+            Some(label) if !label.is_empty() => label,
+            _ => line,
+        };
+
         let (has_inlines, could_be_cpp, has_semicolon, mut frame) = if self.opt.includeoffset {
             (true, true, true, line)
         } else {
@@ -343,7 +621,15 @@ impl Folder {
         let frame = if frame.is_empty() {
             Cow::Borrowed("-")
         } else {
-            self.fix_rust_symbol(frame)
+            self.demangle_frame(frame)
+        };
+
+        let (module, frame) = match self.opt.module_names {
+            ModuleNames::Prefix => (None, frame),
+            _ => match frame.split_once('`') {
+                Some((module, func)) => (Some(module.to_string()), Cow::Owned(func.to_string())),
+                None => (None, frame),
+            },
         };
 
         if has_inlines {
@@ -369,9 +655,101 @@ impl Folder {
         } else {
             self.stack.push_front(frame.to_string())
         }
+
+        if let Some(module) = module {
+            match self.opt.module_names {
+                ModuleNames::Annotate => {
+                    if let Some(func) = self.stack.front_mut() {
+                        func.push_str(" [");
+                        func.push_str(&module);
+                        func.push(']');
+                    }
+                }
+                ModuleNames::Frame => {
+                    self.stack_str_size += module.len() + 1;
+                    self.stack.push_front(module);
+                }
+                ModuleNames::Prefix | ModuleNames::Strip => {}
+            }
+        }
+    }
+
+    // Stacks of aggregations without scalar keys, like `@[ustack()] = count();`, start right away
+    // with a frame, which is either `module`function` or a bare address.
+    fn is_key_line(line: &str) -> bool {
+        !line.contains('`') && Self::helper_label(line).is_none()
+    }
+
+    // we have the scalar keys of the aggregation, printed before the stack, like:
+    //
+    //     bash                                                1234
+    fn on_key_line(&mut self, line: &str) {
+        let mut keys = line.split_whitespace();
+        let mut pname = keys.next().unwrap_or("-").to_string();
+        if self.opt.include_tid {
+            for (i, key) in keys.enumerate() {
+                pname.push(if i == 0 { '-' } else { '/' });
+                pname.push_str(key);
+            }
+        }
+        self.stack_str_size += pname.len() + 1;
+        self.pname = Some(pname);
+    }
+
+    // Frames without a symbol are printed as a bare address. For JIT-compiled code, a ustack
+    // helper (see `ustack(nframes, strsize)`) may add a label after the address, like:
+    //
+    //     0x104d08831
+    //     0x2d9c2d8e2f4d LazyCompile:*fib /app/fib.js:1
+    //
+    // Returns the (possibly empty) label if this is such a frame.
+    fn helper_label(line: &str) -> Option<&str> {
+        let (addr, label) = line.split_once(' ').unwrap_or((line, ""));
+        let addr = addr.strip_prefix("0x")?;
+        if addr.is_empty() || !addr.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        Some(label.trim())
+    }
+
+    // we have a bucket of a distribution, like:
+    //
+    //            value  ------------- Distribution ------------- count
+    //             1024 |                                         0
+    //             2048 |@@@@@@@@@@@@@@@@@@@@@@@@@@               2
+    //             4096 |@@@@@@@@@@@@@                            1
+    //
+    // or, for `lquantize()`, with open-ended buckets:
+    //
+    //              < 0 |                                         0
+    //           >= 100 |@@@@                                     1
+    fn on_distribution_line(&mut self, line: &str) {
+        let bucket = line.split_once('|').and_then(|(value, bar)| {
+            let value = value.trim().trim_start_matches(&['<', '>', '='][..]).trim();
+            let count = bar.rsplit(' ').next()?;
+            Some((value.parse::<i64>().ok()?, count.parse::<usize>().ok()?))
+        });
+        let (value, count) = match bucket {
+            Some(bucket) => bucket,
+            None => {
+                warn!("Weird distribution line: {}", line);
+                return;
+            }
+        };
+        let weight = match self.opt.distribution_weight {
+            DistributionWeight::Count => count,
+            DistributionWeight::Value => usize::try_from(value).unwrap_or(0) * count,
+        };
+        if let Some(total) = self.distribution.as_mut() {
+            *total += weight;
+        }
     }
 
     fn on_stack_end(&mut self, count: usize, occurrences: &mut Occurrences) {
+        if let Some(pname) = self.pname.take() {
+            self.stack.push_front(pname);
+        }
+
         // allocate a string that is long enough to hold the entire stack string
         let mut stack_str = String::with_capacity(self.stack_str_size);
 
@@ -392,7 +770,12 @@ impl Folder {
             }
         }
 
-        // count it!
+        // count it! Times are rounded to the nearest unit, so that stacks that take a little
+        // less than a unit aren't cut down to nothing.
+        let count = match self.opt.time_unit {
+            Some(unit) => (count + unit.nanoseconds() / 2) / unit.nanoseconds(),
+            None => count,
+        };
         occurrences.insert_or_add(stack_str, count);
 
         // reset for the next event
@@ -401,6 +784,23 @@ impl Folder {
     }
 }
 
+// Matches the header of a distribution, like:
+//
+//            value  ------------- Distribution ------------- count
+//
+// This is called for every line by `would_end_stack`, so it works on bytes.
+fn is_distribution_header(line: &[u8]) -> bool {
+    let start = line.iter().position(|b| !b.is_ascii_whitespace());
+    let end = line.iter().rposition(|b| !b.is_ascii_whitespace());
+    let line = match (start, end) {
+        (Some(start), Some(end)) => &line[start..=end],
+        _ => return false,
+    };
+    line.starts_with(b"value")
+        && line.ends_with(b"count")
+        && line.windows(14).any(|w| w == b" Distribution ")
+}
+
 #[cfg(test)]
 mod tests {
     use std::fs;
@@ -566,8 +966,16 @@ mod tests {
         loop {
             let nstacks_per_job = rng.gen_range(1..=500);
             let options = Options {
+                distribution_weight: DistributionWeight::default(),
+                demangle: demangle::Options::default(),
+                header: rng.gen(),
                 includeoffset: rng.gen(),
+                include_pname: false,
+                include_tid: false,
+                module_names: ModuleNames::default(),
                 nthreads: rng.gen_range(2..=32),
+                time_unit: None,
+                units: None,
             };
 
             for (path, input) in inputs.iter() {
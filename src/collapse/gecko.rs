@@ -0,0 +1,305 @@
+use std::io;
+
+use ahash::AHashMap;
+use log::warn;
+use serde_json::Value;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+use crate::folded::Header;
+use crate::Error;
+
+/// `gecko` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct Options {
+    /// The threads to fold, by their name or thread id.
+    ///
+    /// Default is an empty list, which folds all threads.
+    pub threads: Vec<String>,
+
+    /// Add the name of the thread of each sample as the root frame of its stack, instead of
+    /// merging the stacks of all threads.
+    ///
+    /// Default is `false`.
+    pub include_threads: bool,
+
+    /// Write a [`Header`] before the folded stacks, with the units of the samples.
+    ///
+    /// Default is `false`.
+    pub header: bool,
+}
+
+/// A stack collapser for the JSON profiles of the [Firefox Profiler], as written by Firefox
+/// itself, by `samply`, and by the other profilers that export to it.
+///
+/// Both the processed format, whose tables are objects of columns, and the raw Gecko format,
+/// whose tables have a `schema` and rows of `data`, are read. The threads of subprocesses in the
+/// raw format are folded too. Samples are weighted by their `weight` column if they have one,
+/// keeping up to six fractional digits of weights like `tracing-ms`, and counted otherwise.
+///
+/// To construct one, either use `gecko::Folder::default()` or create an [`Options`] and use
+/// `gecko::Folder::from(options)`.
+///
+///   [Firefox Profiler]: https://github.com/firefox-devtools/profiler/blob/main/docs-developer/gecko-profile-format.md
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, mut reader: R, mut writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut input = String::new();
+        reader.read_to_string(&mut input)?;
+        let profile: Value = serde_json::from_str(&input).map_err(invalid_data)?;
+
+        let mut threads = Vec::new();
+        collect_threads(&profile, &mut threads);
+        if threads.is_empty() {
+            return Err(invalid_data("the profile has no threads"));
+        }
+        // Newer processed profiles share one string table between their threads.
+        let shared_strings = profile
+            .pointer("/shared/stringArray")
+            .and_then(Value::as_array);
+
+        let selected: Vec<_> = threads
+            .iter()
+            .filter(|thread| self.is_selected(thread))
+            .collect();
+        if selected.is_empty() {
+            let names: Vec<_> = threads.iter().map(|thread| thread_name(thread)).collect();
+            return Err(io::Error::from(Error::InvalidOptions(format!(
+                "the profile has no thread {}; its threads are: {}",
+                self.opt.threads.join(", "),
+                names.join(", ")
+            ))));
+        }
+
+        let mut occurrences = Occurrences::new(1).fractional();
+        let mut units = None;
+        for thread in selected {
+            let strings = thread
+                .get("stringArray")
+                .or_else(|| thread.get("stringTable"))
+                .and_then(Value::as_array)
+                .or(shared_strings)
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+            let thread_units = self.fold_thread(thread, strings, &mut occurrences);
+            units = units.or(thread_units);
+        }
+
+        if self.opt.header {
+            let mut header = Header::for_tool("inferno-collapse-gecko");
+            header.units = Some(units.unwrap_or("samples").to_string());
+            header.write(&mut writer)?;
+        }
+        occurrences.write_and_clear(writer)
+    }
+
+    /// Check for a JSON object with the keys of a Firefox Profiler profile
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        let input = input.trim_start();
+        if !input.starts_with('{') {
+            return Some(false);
+        }
+        if input.contains("\"meta\"")
+            && input.contains("\"threads\"")
+            && input.contains("\"stackTable\"")
+        {
+            Some(true)
+        } else {
+            None
+        }
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+impl Folder {
+    fn is_selected(&self, thread: &Value) -> bool {
+        if self.opt.threads.is_empty() {
+            return true;
+        }
+        let name = thread.get("name").and_then(Value::as_str);
+        let tid = thread.get("tid").map(|tid| match tid {
+            Value::String(tid) => tid.clone(),
+            tid => tid.to_string(),
+        });
+        self.opt
+            .threads
+            .iter()
+            .any(|wanted| name == Some(wanted.as_str()) || tid.as_ref() == Some(wanted))
+    }
+
+    // Folds the samples of one thread, and returns their units if they aren't sample counts.
+    fn fold_thread(
+        &self,
+        thread: &Value,
+        strings: &[Value],
+        occurrences: &mut Occurrences,
+    ) -> Option<&'static str> {
+        let string = |index: Option<&Value>| {
+            index
+                .and_then(Value::as_u64)
+                .and_then(|index| strings.get(index as usize))
+                .and_then(Value::as_str)
+        };
+
+        // Frames name their function in the processed format, and their location in the raw
+        // format.
+        let frame_table = thread.get("frameTable").unwrap_or(&Value::Null);
+        let frame_names: Vec<&str> = match column(frame_table, "func") {
+            Some(funcs) => {
+                let func_table = thread.get("funcTable").unwrap_or(&Value::Null);
+                let func_names = column(func_table, "name").unwrap_or_default();
+                funcs
+                    .iter()
+                    .map(|func| {
+                        func.and_then(Value::as_u64)
+                            .and_then(|func| func_names.get(func as usize).copied())
+                            .and_then(string)
+                            .unwrap_or("[unknown]")
+                    })
+                    .collect()
+            }
+            None => column(frame_table, "location")
+                .unwrap_or_default()
+                .into_iter()
+                .map(|location| string(location).unwrap_or("[unknown]"))
+                .collect(),
+        };
+
+        let stack_table = thread.get("stackTable").unwrap_or(&Value::Null);
+        let prefixes = column(stack_table, "prefix").unwrap_or_default();
+        let frames = column(stack_table, "frame").unwrap_or_default();
+
+        let samples = thread.get("samples").unwrap_or(&Value::Null);
+        let sample_stacks = column(samples, "stack").unwrap_or_default();
+        let weights = column(samples, "weight");
+        let units = match samples.get("weightType").and_then(Value::as_str) {
+            Some("tracing-ms") => Some("milliseconds"),
+            Some("bytes") => Some("bytes"),
+            _ => None,
+        };
+
+        let thread_name = thread_name(thread);
+        let mut stacks: AHashMap<usize, Option<String>> = AHashMap::default();
+        let mut invalid = 0;
+        for (i, stack) in sample_stacks.iter().enumerate() {
+            // Samples without a stack were taken while the thread wasn't running any code.
+            let stack = match stack.and_then(Value::as_u64) {
+                Some(stack) => stack as usize,
+                None => continue,
+            };
+            let weight = weights
+                .as_ref()
+                .and_then(|weights| weights.get(i).copied().flatten())
+                .and_then(Value::as_f64)
+                .unwrap_or(1.0);
+            if weight <= 0.0 {
+                continue;
+            }
+
+            let folded = stacks.entry(stack).or_insert_with(|| {
+                let mut names = Vec::new();
+                let mut next = Some(stack);
+                while let Some(index) = next {
+                    // Guard against cycles in malformed profiles.
+                    if names.len() > prefixes.len() {
+                        return None;
+                    }
+                    let frame = frames.get(index).copied().flatten()?.as_u64()?;
+                    names.push(*frame_names.get(frame as usize)?);
+                    next = prefixes
+                        .get(index)
+                        .copied()
+                        .flatten()
+                        .and_then(Value::as_u64)
+                        .map(|prefix| prefix as usize);
+                }
+                if self.opt.include_threads {
+                    names.push(thread_name);
+                }
+                names.reverse();
+                Some(names.join(";"))
+            });
+            match folded {
+                Some(folded) => occurrences.insert_or_add_weight(folded.clone(), weight),
+                None => invalid += 1,
+            }
+        }
+
+        if invalid != 0 {
+            warn!(
+                "Ignored {} samples of thread {} with invalid stacks",
+                invalid, thread_name
+            );
+        }
+        units
+    }
+}
+
+// Finds the threads of a profile and of the subprocess profiles of the raw format.
+fn collect_threads<'a>(profile: &'a Value, threads: &mut Vec<&'a Value>) {
+    if let Some(own) = profile.get("threads").and_then(Value::as_array) {
+        threads.extend(own.iter().filter(|thread| thread.is_object()));
+    }
+    if let Some(processes) = profile.get("processes").and_then(Value::as_array) {
+        for process in processes {
+            collect_threads(process, threads);
+        }
+    }
+}
+
+fn thread_name(thread: &Value) -> &str {
+    thread
+        .get("name")
+        .and_then(Value::as_str)
+        .unwrap_or("[unknown]")
+}
+
+// Reads a column of a table, which is either an object of columns, or a `schema` that maps
+// column names to indices into the rows of `data`.
+fn column<'a>(table: &'a Value, key: &str) -> Option<Vec<Option<&'a Value>>> {
+    if let (Some(schema), Some(data)) = (
+        table.get("schema"),
+        table.get("data").and_then(Value::as_array),
+    ) {
+        let index = schema.get(key)?.as_u64()? as usize;
+        return Some(
+            data.iter()
+                .map(|row| row.get(index).filter(|value| !value.is_null()))
+                .collect(),
+        );
+    }
+    let values = table.get(key)?.as_array()?;
+    Some(
+        values
+            .iter()
+            .map(|value| Some(value).filter(|value| !value.is_null()))
+            .collect(),
+    )
+}
+
+fn invalid_data<E>(error: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let error: Box<dyn std::error::Error + Send + Sync> = error.into();
+    Error::parse("gecko", error).into()
+}
@@ -4,6 +4,8 @@ use log::warn;
 
 use crate::collapse::common::Occurrences;
 use crate::collapse::Collapse;
+use crate::folded::Header;
+use crate::Error;
 
 // These are the identifying words of the callgraph table, note that ticks and bytes columns are optional so not present
 static START_LINE: &[&str] = &[
@@ -13,14 +15,29 @@ static START_LINE: &[&str] = &[
 /// `ghcprof` folder configuration options.
 #[derive(Clone, Debug, Default)]
 #[non_exhaustive]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
 pub struct Options {
     /// Column to source associated value from, default is `Source::PercentTime`.
     pub source: Source,
+
+    /// Write a [`Header`] with the units of the chosen column before the folded stacks.
+    ///
+    /// Default is `false`.
+    pub header: bool,
 }
 
 /// Which prof column to use as the cost centre of the output stacks
 #[derive(Clone, Debug, Default)]
 #[non_exhaustive]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
 pub enum Source {
     #[default]
     /// The indivial %time column representing individual time as a percent of the total
@@ -55,15 +72,17 @@ struct Cols {
 }
 
 impl Collapse for Folder {
-    fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
+    fn collapse<R, W>(&mut self, mut reader: R, mut writer: W) -> io::Result<()>
     where
         R: io::BufRead,
         W: io::Write,
     {
         // Consume the header...
         let mut line = Vec::new();
+        let mut number = 0;
         let cols = loop {
             line.clear();
+            number += 1;
             if reader.read_until(b'\n', &mut line)? == 0 {
                 warn!("File ended before start of call graph");
                 return Ok(());
@@ -88,8 +107,10 @@ impl Collapse for Folder {
                         .find("%time")
                         .expect("%time is present from matching START_LINE"),
                     // See note above about ticks and bytes columns
-                    Source::Ticks => one_off_end_of_col_before(l.as_ref(), "ticks")?,
-                    Source::Bytes => one_off_end_of_col_before(l.as_ref(), "bytes")?,
+                    Source::Ticks => one_off_end_of_col_before(l.as_ref(), "ticks")
+                        .map_err(|e| Error::at_line(e, number))?,
+                    Source::Bytes => one_off_end_of_col_before(l.as_ref(), "bytes")
+                        .map_err(|e| Error::at_line(e, number))?,
                 };
                 break Cols {
                     cost_centre,
@@ -100,11 +121,13 @@ impl Collapse for Folder {
         };
         // Skip one line
         reader.read_until(b'\n', &mut line)?;
+        number += 1;
 
         // Process the data...
         let mut occurrences = Occurrences::new(1);
         loop {
             line.clear();
+            number += 1;
             if reader.read_until(b'\n', &mut line)? == 0 {
                 // The format is not expected to contain any blank lines within the callgraph
                 break;
@@ -114,11 +137,25 @@ impl Collapse for Folder {
             if line.is_empty() {
                 break;
             } else {
-                self.on_line(line, &mut occurrences, &cols)?;
+                self.on_line(line, &mut occurrences, &cols)
+                    .map_err(|e| Error::at_line(e, number))?;
             }
         }
 
         // Write the results...
+        if self.opt.header {
+            let mut header = Header::for_tool("inferno-collapse-ghcprof");
+            header.units = Some(
+                match self.opt.source {
+                    // The percentages are scaled up to integers.
+                    Source::PercentTime => "permille of time",
+                    Source::Ticks => "ticks",
+                    Source::Bytes => "bytes",
+                }
+                .to_string(),
+            );
+            header.write(&mut writer)?;
+        }
         occurrences.write_and_clear(writer)?;
 
         // Reset the state...
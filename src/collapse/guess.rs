@@ -1,30 +1,255 @@
+use std::env;
+use std::fmt;
+use std::fs;
 use std::io::prelude::*;
 use std::io::{self, Cursor};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::thread;
 
-use log::{error, info};
+use log::{debug, error, info, warn};
 
-use crate::collapse::{self, dtrace, ghcprof, perf, sample, vsprof, vtune, Collapse};
+use crate::collapse::{
+    self, callgrind, cpuprofile, dtrace, gecko, ghcprof, heaptrack, massif, perf, sample,
+    simpleperf, speedscope, stackprof, vsprof, vtune, wpa, xctrace, Collapse,
+};
+use crate::symbols::demangle;
+use crate::Error;
 
 const LINES_PER_ITERATION: usize = 10;
 
+/// The default number of lines to probe once a format has been recognized, to find other formats
+/// the input may also be.
+pub const DEFAULT_PROBE_LINES: usize = 100;
+
+/// The environment variable holding the directories that [`discover_plugins`] looks for collapser
+/// plugins in, separated like `PATH`.
+pub const PLUGIN_PATH_VAR: &str = "INFERNO_PLUGIN_PATH";
+
 /// Folder configuration options.
 #[derive(Clone, Debug)]
 #[non_exhaustive]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
 pub struct Options {
     /// The number of threads to use.
     ///
     /// Default is the number of logical cores on your machine.
     pub nthreads: usize,
+
+    /// The number of lines of input to probe before settling on a format.
+    ///
+    /// Probing stops early once every format has either been recognized or ruled out, and goes
+    /// on past this number of lines for as long as no format has been recognized. When several
+    /// formats are recognized, the one that needed the fewest lines is used.
+    ///
+    /// Default is `DEFAULT_PROBE_LINES`.
+    pub probe_lines: usize,
+
+    /// Skip detection and use the collapser for this format.
+    ///
+    /// Default is `None`.
+    pub assume: Option<Format>,
+
+    /// Which mangled symbols the collapsers of `perf`, DTrace and `sample` output demangle.
+    ///
+    /// Default is `demangle::Options::default()`, which demangles all supported manglings.
+    pub demangle: demangle::Options,
+
+    /// Have the collapser of the detected format write a
+    /// [`Header`](crate::folded::Header) before the folded stacks. Plugins are left to decide on
+    /// their own.
+    ///
+    /// Default is `false`.
+    pub header: bool,
+
+    /// External collapser executables to try, in order, when no built-in format matches.
+    ///
+    /// A plugin is first run with a `--check` argument and the probed input on stdin, and should
+    /// exit successfully only if it understands the input. The first plugin that does is then run
+    /// without arguments, with the whole input on stdin, and should write folded stacks to
+    /// stdout.
+    ///
+    /// Default is no plugins; see [`discover_plugins`].
+    pub plugins: Vec<PathBuf>,
 }
 
 impl Default for Options {
     fn default() -> Self {
         Self {
             nthreads: *collapse::DEFAULT_NTHREADS,
+            probe_lines: DEFAULT_PROBE_LINES,
+            assume: None,
+            demangle: demangle::Options::default(),
+            header: false,
+            plugins: Vec::new(),
+        }
+    }
+}
+
+/// The input formats that can be detected.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// `perf script` output.
+    Perf,
+    /// DTrace `ustack()` aggregations.
+    Dtrace,
+    /// macOS `sample` output.
+    Sample,
+    /// VTune `amplxe-cl` CSV call graphs.
+    Vtune,
+    /// Visual Studio profiler CSV exports.
+    Vsprof,
+    /// GHC `.prof` files.
+    Ghcprof,
+    /// V8 `.cpuprofile` JSON of Node and Chrome.
+    Cpuprofile,
+    /// Windows Performance Analyzer CSV exports.
+    Wpa,
+    /// Ruby stackprof JSON dumps.
+    Stackprof,
+    /// A format registered with [`Folder::register`], by its name.
+    Custom(&'static str),
+}
+
+impl Format {
+    /// The valid set of formats (via `FromStr`), in the order they are tried in.
+    pub const VARIANTS: &'static [&'static str] = &[
+        "perf",
+        "dtrace",
+        "sample",
+        "vtune",
+        "vsprof",
+        "ghcprof",
+        "cpuprofile",
+        "wpa",
+        "stackprof",
+    ];
+
+    /// The formats of the other collapsers that every [`Folder`] registers, after the formats
+    /// above and with priority -1. They are valid (via `FromStr`) as `Format::Custom(name)`.
+    pub const REGISTERED: &'static [&'static str] = &[
+        "callgrind",
+        "simpleperf",
+        "heaptrack",
+        "massif",
+        "speedscope",
+        "gecko",
+        "xctrace",
+    ];
+
+    const ALL: [Format; 9] = [
+        Format::Perf,
+        Format::Dtrace,
+        Format::Sample,
+        Format::Vtune,
+        Format::Vsprof,
+        Format::Ghcprof,
+        Format::Cpuprofile,
+        Format::Wpa,
+        Format::Stackprof,
+    ];
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "perf" => Ok(Format::Perf),
+            "dtrace" => Ok(Format::Dtrace),
+            "sample" => Ok(Format::Sample),
+            "vtune" => Ok(Format::Vtune),
+            "vsprof" => Ok(Format::Vsprof),
+            "ghcprof" => Ok(Format::Ghcprof),
+            "cpuprofile" => Ok(Format::Cpuprofile),
+            "wpa" => Ok(Format::Wpa),
+            "stackprof" => Ok(Format::Stackprof),
+            registered => match Format::REGISTERED.iter().find(|&&name| name == registered) {
+                Some(name) => Ok(Format::Custom(name)),
+                None => Err(format!("unknown format: {}", registered)),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Format::Perf => "perf",
+            Format::Dtrace => "dtrace",
+            Format::Sample => "sample",
+            Format::Vtune => "vtune",
+            Format::Vsprof => "vsprof",
+            Format::Ghcprof => "ghcprof",
+            Format::Cpuprofile => "cpuprofile",
+            Format::Wpa => "wpa",
+            Format::Stackprof => "stackprof",
+            Format::Custom(name) => name,
+        })
+    }
+}
+
+#[cfg(feature = "config")]
+impl serde::Serialize for Format {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "config")]
+impl<'de> serde::Deserialize<'de> for Format {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        crate::config::deserialize_from_str(deserializer)
+    }
+}
+
+/// How sure detection is that the input is in a given format.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Confidence {
+    /// The input was recognized as this format after the given number of lines.
+    Matched(usize),
+    /// The probed input wasn't enough to tell.
+    Possible,
+    /// The input can't be in this format.
+    RuledOut,
+}
+
+impl Confidence {
+    // Candidates are ranked by this key; formats recognized sooner come first.
+    fn rank(self) -> (usize, usize) {
+        match self {
+            Confidence::Matched(lines) => (0, lines),
+            Confidence::Possible => (1, 0),
+            Confidence::RuledOut => (2, 0),
+        }
+    }
+}
+
+impl fmt::Display for Confidence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Confidence::Matched(lines) => write!(f, "matched after {} lines", lines),
+            Confidence::Possible => f.write_str("undecided"),
+            Confidence::RuledOut => f.write_str("ruled out"),
         }
     }
 }
 
+/// A format the input may be in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Candidate {
+    /// The format.
+    pub format: Format,
+    /// How sure detection is that the input is in this format.
+    pub confidence: Confidence,
+}
+
 /// A collapser that tries to find an appropriate implementation of `Collapse`
 /// based on the input, then delegates to that collapser if one is found.
 ///
@@ -33,11 +258,68 @@ impl Default for Options {
 #[derive(Clone)]
 pub struct Folder {
     opt: Options,
+    custom: Vec<Registered>,
+}
+
+// A format registered with `Folder::register`.
+#[derive(Clone)]
+struct Registered {
+    name: &'static str,
+    priority: i32,
+    new: Arc<dyn Fn() -> Box<dyn DynCollapse> + Send + Sync>,
+}
+
+/// An object-safe version of [`Collapse`], implemented for every collapser, that
+/// [`Folder::register`] boxes registered collapsers as.
+pub trait DynCollapse {
+    /// Collapses the given input, like [`Collapse::collapse`].
+    fn collapse_dyn(
+        &mut self,
+        reader: &mut dyn io::BufRead,
+        writer: &mut dyn io::Write,
+    ) -> io::Result<()>;
+
+    /// Returns whether this collapser is appropriate for the given input, like
+    /// [`Collapse::is_applicable`].
+    fn is_applicable_dyn(&mut self, input: &str) -> Option<bool>;
+}
+
+impl<T: Collapse> DynCollapse for T {
+    fn collapse_dyn(
+        &mut self,
+        reader: &mut dyn io::BufRead,
+        writer: &mut dyn io::Write,
+    ) -> io::Result<()> {
+        self.collapse(reader, writer)
+    }
+
+    fn is_applicable_dyn(&mut self, input: &str) -> Option<bool> {
+        self.is_applicable(input)
+    }
+}
+
+// Registers the collapsers of `Format::REGISTERED`, with the options they share with this folder.
+macro_rules! register {
+    ($folder:expr, $($format:ident),*) => {
+        $(
+            let header = $folder.opt.header;
+            $folder.register(stringify!($format), -1, move || {
+                let mut options = $format::Options::default();
+                options.header = header;
+                $format::Folder::from(options)
+            });
+        )*
+    };
 }
 
 impl From<Options> for Folder {
     fn from(opt: Options) -> Self {
-        Self { opt }
+        let mut folder = Self {
+            opt,
+            custom: Vec::new(),
+        };
+        register!(folder, callgrind, simpleperf, heaptrack, massif, speedscope, gecko, xctrace);
+        folder
     }
 }
 
@@ -47,81 +329,401 @@ impl Default for Folder {
     }
 }
 
+impl Folder {
+    /// Registers a collapser for another format under the given name, so that it is detected
+    /// like the built-in formats and can be assumed as `Format::Custom(name)`.
+    ///
+    /// `new` is called for a fresh collapser every time input is probed or collapsed. Formats are
+    /// tried, and ties between formats recognized after the same number of lines are broken, by
+    /// priority, highest first. The built-in formats have priority 0 and are tried before
+    /// registered formats of the same priority, which are tried in the order they were registered.
+    pub fn register<F, C>(&mut self, name: &'static str, priority: i32, new: F)
+    where
+        F: Fn() -> C + Send + Sync + 'static,
+        C: Collapse + 'static,
+    {
+        self.custom.push(Registered {
+            name,
+            priority,
+            new: Arc::new(move || -> Box<dyn DynCollapse> { Box::new(new()) }),
+        });
+    }
+
+    /// Returns the formats that can be detected, in the order they are tried in.
+    pub fn formats(&self) -> Vec<Format> {
+        let mut formats: Vec<_> = Format::ALL.iter().map(|&format| (format, 0)).collect();
+        formats.extend(
+            self.custom
+                .iter()
+                .map(|custom| (Format::Custom(custom.name), custom.priority)),
+        );
+        // The sort is stable, so built-in formats go first among those of the same priority.
+        formats.sort_by_key(|&(_, priority)| std::cmp::Reverse(priority));
+        formats.into_iter().map(|(format, _)| format).collect()
+    }
+
+    /// Ranks the formats the given input may be in, best first, leaving out the formats it
+    /// can't be in.
+    pub fn rank(&self, input: &str) -> Vec<Candidate> {
+        let mut collapsers = Collapsers::new(&self.opt, &self.custom);
+        let mut buffer = String::new();
+        let mut reader = input.as_bytes();
+        match self.probe(&mut collapsers, &mut reader, &mut buffer) {
+            Ok(probe) => probe.candidates(),
+            // Reading from a string can't fail.
+            Err(_) => Vec::new(),
+        }
+    }
+
+    // Reads input into `buffer` until a format can be settled on.
+    fn probe<R: io::BufRead>(
+        &self,
+        collapsers: &mut Collapsers,
+        reader: &mut R,
+        buffer: &mut String,
+    ) -> io::Result<Probe> {
+        let formats = self.formats();
+        let mut probe = Probe {
+            confidence: vec![Confidence::Possible; formats.len()],
+            formats,
+        };
+        let mut lines = 0;
+        loop {
+            let mut eof = false;
+            for _ in 0..LINES_PER_ITERATION {
+                if reader.read_line(buffer)? == 0 {
+                    eof = true;
+                    break;
+                }
+                lines += 1;
+            }
+
+            probe.update(collapsers, buffer, lines);
+            if eof || probe.is_settled(lines, self.opt.probe_lines) {
+                return Ok(probe);
+            }
+        }
+    }
+}
+
 impl Collapse for Folder {
     fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
     where
         R: io::BufRead,
         W: io::Write,
     {
-        let mut dtrace = {
+        let mut collapsers = Collapsers::new(&self.opt, &self.custom);
+
+        if let Some(format) = self.opt.assume {
+            info!("Using {} collapser", format);
+            return collapsers.collapse(format, reader, writer);
+        }
+
+        let mut buffer = String::new();
+        let probe = self.probe(&mut collapsers, &mut reader, &mut buffer)?;
+        let candidates = probe.candidates();
+        let (format, confidence) = match candidates.first() {
+            Some(&Candidate {
+                format,
+                confidence: confidence @ Confidence::Matched(_),
+            }) => (format, confidence),
+            _ => {
+                for plugin in &self.opt.plugins {
+                    if plugin_is_applicable(plugin, &buffer) {
+                        info!("Using collapser plugin {}", plugin.display());
+                        let cursor = Cursor::new(buffer).chain(reader);
+                        return run_plugin(plugin, cursor, writer);
+                    }
+                }
+
+                error!("No applicable collapse implementation found for input");
+                return Ok(());
+            }
+        };
+
+        info!("Using {} collapser ({})", format, confidence);
+        for runner_up in &candidates[1..] {
+            info!(
+                "Runner-up: {} collapser ({})",
+                runner_up.format, runner_up.confidence
+            );
+        }
+        let also_matched: Vec<_> = candidates[1..]
+            .iter()
+            .filter(|candidate| matches!(candidate.confidence, Confidence::Matched(_)))
+            .map(|candidate| candidate.format.to_string())
+            .collect();
+        if !also_matched.is_empty() {
+            warn!(
+                "Input also looks like {} input; assume a format if {} is wrong",
+                also_matched.join(", "),
+                format
+            );
+        }
+
+        let cursor = Cursor::new(buffer).chain(reader);
+        collapsers.collapse(format, cursor, writer)
+    }
+
+    fn is_applicable(&mut self, _line: &str) -> Option<bool> {
+        unreachable!()
+    }
+}
+
+/// Finds the collapser plugins in the directories listed in the `INFERNO_PLUGIN_PATH` environment
+/// variable, in order. Every file in those directories is taken to be a plugin.
+pub fn discover_plugins() -> Vec<PathBuf> {
+    let mut plugins = Vec::new();
+    let dirs = match env::var_os(PLUGIN_PATH_VAR) {
+        Some(dirs) => dirs,
+        None => return plugins,
+    };
+    for dir in env::split_paths(&dirs) {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!("Unable to read plugin directory {}: {}", dir.display(), e);
+                continue;
+            }
+        };
+        let mut files: Vec<_> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file())
+            .collect();
+        files.sort();
+        plugins.extend(files);
+    }
+    plugins
+}
+
+fn plugin_is_applicable(plugin: &Path, probe: &str) -> bool {
+    let child = Command::new(plugin)
+        .arg("--check")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            warn!("Unable to run collapser plugin {}: {}", plugin.display(), e);
+            return false;
+        }
+    };
+
+    // The plugin may decide without reading all of the probe, and exit before we're done writing.
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(probe.as_bytes());
+    }
+    match child.wait() {
+        Ok(status) => {
+            debug!("Collapser plugin {} check: {}", plugin.display(), status);
+            status.success()
+        }
+        Err(e) => {
+            warn!("Unable to run collapser plugin {}: {}", plugin.display(), e);
+            false
+        }
+    }
+}
+
+fn run_plugin<R, W>(plugin: &Path, mut reader: R, mut writer: W) -> io::Result<()>
+where
+    R: io::BufRead,
+    W: io::Write,
+{
+    let mut child = Command::new(plugin)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    // Read the output on another thread, so the plugin can't block on a full stdout pipe while we
+    // are still writing its input.
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let output = thread::spawn(move || {
+        let mut output = Vec::new();
+        stdout.read_to_end(&mut output).map(|_| output)
+    });
+    {
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        io::copy(&mut reader, &mut stdin)?;
+    }
+    let output = output
+        .join()
+        .expect("thread reading plugin output panicked")?;
+
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("Collapser plugin {} failed: {}", plugin.display(), status),
+        ));
+    }
+    writer.write_all(&output)
+}
+
+// The state of detection, with the confidence in each of the formats tried.
+struct Probe {
+    formats: Vec<Format>,
+    confidence: Vec<Confidence>,
+}
+
+impl Probe {
+    fn update(&mut self, collapsers: &mut Collapsers, input: &str, lines: usize) {
+        for (&format, confidence) in self.formats.iter().zip(self.confidence.iter_mut()) {
+            if *confidence == Confidence::Possible {
+                match collapsers.is_applicable(format, input) {
+                    Some(false) => *confidence = Confidence::RuledOut,
+                    Some(true) => *confidence = Confidence::Matched(lines),
+                    None => (), // We're not yet sure if this collapser is appropriate
+                }
+            }
+        }
+    }
+
+    fn is_settled(&self, lines: usize, probe_lines: usize) -> bool {
+        let matched = self
+            .confidence
+            .iter()
+            .any(|confidence| matches!(confidence, Confidence::Matched(_)));
+        let possible = self.confidence.contains(&Confidence::Possible);
+        matched && (!possible || lines >= probe_lines)
+    }
+
+    fn candidates(&self) -> Vec<Candidate> {
+        let mut candidates: Vec<_> = self
+            .formats
+            .iter()
+            .zip(self.confidence.iter())
+            .filter(|(_, &confidence)| confidence != Confidence::RuledOut)
+            .map(|(&format, &confidence)| Candidate { format, confidence })
+            .collect();
+        // The sort is stable, so ties keep the order formats are tried in.
+        candidates.sort_by_key(|candidate| candidate.confidence.rank());
+        candidates
+    }
+}
+
+struct Collapsers {
+    perf: perf::Folder,
+    dtrace: dtrace::Folder,
+    sample: sample::Folder,
+    vtune: vtune::Folder,
+    vsprof: vsprof::Folder,
+    ghcprof: ghcprof::Folder,
+    cpuprofile: cpuprofile::Folder,
+    wpa: wpa::Folder,
+    stackprof: stackprof::Folder,
+    custom: Vec<(&'static str, Box<dyn DynCollapse>)>,
+}
+
+impl Collapsers {
+    fn new(opt: &Options, custom: &[Registered]) -> Self {
+        let dtrace = {
             let options = dtrace::Options {
-                nthreads: self.opt.nthreads,
+                demangle: opt.demangle,
+                header: opt.header,
+                nthreads: opt.nthreads,
                 ..Default::default()
             };
             dtrace::Folder::from(options)
         };
-        let mut perf = {
+        let perf = {
             let options = perf::Options {
-                nthreads: self.opt.nthreads,
+                demangle: opt.demangle,
+                header: opt.header,
+                nthreads: opt.nthreads,
                 ..Default::default()
             };
             perf::Folder::from(options)
         };
-        let mut sample = sample::Folder::default();
-        let mut vtune = vtune::Folder::default();
-        let mut vsprof = vsprof::Folder::default();
-        let mut ghcprof = ghcprof::Folder::default();
-
-        // Each Collapse impl gets its own flag in this array.
-        // It gets set to true when the impl has been ruled out.
-        let mut not_applicable = [false; 6];
-
-        let mut buffer = String::new();
-        loop {
-            let mut eof = false;
-            for _ in 0..LINES_PER_ITERATION {
-                if reader.read_line(&mut buffer)? == 0 {
-                    eof = true;
-                }
-            }
-
-            macro_rules! try_collapse_impl {
-                ($collapse:ident, $index:expr) => {
-                    if !not_applicable[$index] {
-                        match $collapse.is_applicable(&buffer) {
-                            Some(false) => {
-                                // We can rule this collapser out.
-                                not_applicable[$index] = true;
-                            }
-                            Some(true) => {
-                                // We found a collapser that works! Let's use it.
-                                info!("Using {} collapser", stringify!($collapse));
-                                let cursor = Cursor::new(buffer).chain(reader);
-                                return $collapse.collapse(cursor, writer);
-                            }
-                            None => (), // We're not yet sure if this collapser is appropriate
-                        }
-                    }
-                };
-            }
-            try_collapse_impl!(perf, 0);
-            try_collapse_impl!(dtrace, 1);
-            try_collapse_impl!(sample, 2);
-            try_collapse_impl!(vtune, 3);
-            try_collapse_impl!(vsprof, 4);
-            try_collapse_impl!(ghcprof, 5);
-
-            if eof {
-                break;
-            }
+        Collapsers {
+            perf,
+            dtrace,
+            sample: sample::Folder::from(sample::Options {
+                demangle: opt.demangle,
+                header: opt.header,
+                nthreads: opt.nthreads,
+                ..Default::default()
+            }),
+            vtune: vtune::Folder::from(vtune::Options {
+                header: opt.header,
+                nthreads: opt.nthreads,
+                ..Default::default()
+            }),
+            vsprof: vsprof::Folder::from(vsprof::Options {
+                header: opt.header,
+                ..Default::default()
+            }),
+            ghcprof: ghcprof::Folder::from(ghcprof::Options {
+                header: opt.header,
+                ..Default::default()
+            }),
+            cpuprofile: cpuprofile::Folder::from(cpuprofile::Options {
+                header: opt.header,
+                ..Default::default()
+            }),
+            wpa: wpa::Folder::from(wpa::Options {
+                header: opt.header,
+                ..Default::default()
+            }),
+            stackprof: stackprof::Folder::from(stackprof::Options {
+                header: opt.header,
+                ..Default::default()
+            }),
+            custom: custom
+                .iter()
+                .map(|custom| (custom.name, (custom.new)()))
+                .collect(),
         }
+    }
 
-        error!("No applicable collapse implementation found for input");
+    fn custom(&mut self, name: &str) -> Option<&mut Box<dyn DynCollapse>> {
+        self.custom
+            .iter_mut()
+            .find(|(custom, _)| *custom == name)
+            .map(|(_, collapser)| collapser)
+    }
 
-        Ok(())
+    fn is_applicable(&mut self, format: Format, input: &str) -> Option<bool> {
+        match format {
+            Format::Perf => self.perf.is_applicable(input),
+            Format::Dtrace => self.dtrace.is_applicable(input),
+            Format::Sample => self.sample.is_applicable(input),
+            Format::Vtune => self.vtune.is_applicable(input),
+            Format::Vsprof => self.vsprof.is_applicable(input),
+            Format::Ghcprof => self.ghcprof.is_applicable(input),
+            Format::Cpuprofile => self.cpuprofile.is_applicable(input),
+            Format::Wpa => self.wpa.is_applicable(input),
+            Format::Stackprof => self.stackprof.is_applicable(input),
+            Format::Custom(name) => self
+                .custom(name)
+                .and_then(|collapser| collapser.is_applicable_dyn(input)),
+        }
     }
 
-    fn is_applicable(&mut self, _line: &str) -> Option<bool> {
-        unreachable!()
+    fn collapse<R, W>(&mut self, format: Format, mut reader: R, mut writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        match format {
+            Format::Perf => self.perf.collapse(reader, writer),
+            Format::Dtrace => self.dtrace.collapse(reader, writer),
+            Format::Sample => self.sample.collapse(reader, writer),
+            Format::Vtune => self.vtune.collapse(reader, writer),
+            Format::Vsprof => self.vsprof.collapse(reader, writer),
+            Format::Ghcprof => self.ghcprof.collapse(reader, writer),
+            Format::Cpuprofile => self.cpuprofile.collapse(reader, writer),
+            Format::Wpa => self.wpa.collapse(reader, writer),
+            Format::Stackprof => self.stackprof.collapse(reader, writer),
+            Format::Custom(name) => match self.custom(name) {
+                Some(collapser) => collapser.collapse_dyn(&mut reader, &mut writer),
+                None => Err(io::Error::from(Error::UnsupportedFormat(format!(
+                    "No collapser registered for format {}",
+                    name
+                )))),
+            },
+        }
     }
 }
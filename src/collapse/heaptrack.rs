@@ -0,0 +1,281 @@
+use std::fmt;
+use std::io::{self, BufRead};
+use std::str::FromStr;
+
+use log::warn;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+use crate::folded::Header;
+use crate::symbols::demangle;
+
+/// What the stacks of a heaptrack profile are weighted by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum Cost {
+    /// The bytes allocated, whether they were freed or not.
+    #[default]
+    Allocated,
+
+    /// The bytes that were never freed.
+    Leaked,
+
+    /// The number of allocations.
+    Allocations,
+}
+
+impl Cost {
+    /// The valid set of costs (via `FromStr`).
+    pub const VARIANTS: &'static [&'static str] = &["allocated", "leaked", "allocations"];
+}
+
+impl FromStr for Cost {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allocated" => Ok(Cost::Allocated),
+            "leaked" => Ok(Cost::Leaked),
+            "allocations" => Ok(Cost::Allocations),
+            unknown => Err(format!("unknown heaptrack cost: {}", unknown)),
+        }
+    }
+}
+
+impl fmt::Display for Cost {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Cost::Allocated => "allocated",
+            Cost::Leaked => "leaked",
+            Cost::Allocations => "allocations",
+        })
+    }
+}
+
+/// `heaptrack` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct Options {
+    /// What stacks are weighted by.
+    ///
+    /// Default is [`Cost::Allocated`].
+    pub cost: Cost,
+
+    /// Which mangled symbols to demangle.
+    ///
+    /// Default is `demangle::Options::default()`, which demangles all supported manglings.
+    pub demangle: demangle::Options,
+
+    /// Write a [`Header`] before the folded stacks, with units of `bytes` or `allocations`, so
+    /// that flame graphs count bytes instead of samples.
+    ///
+    /// Default is `false`.
+    pub header: bool,
+}
+
+/// A stack collapser for the data files of the [heaptrack] memory profiler.
+///
+/// heaptrack writes `heaptrack.<program>.<pid>.zst` (or `.gz`) files, which have to be
+/// decompressed first. They are text, with a line for every string, instruction pointer, stack
+/// trace and allocation site, and for every allocation and free. Stacks are weighted by the bytes
+/// allocated at them, the bytes that were never freed, or the number of allocations.
+///
+/// To construct one, either use `heaptrack::Folder::default()` or create an [`Options`] and use
+/// `heaptrack::Folder::from(options)`.
+///
+///   [heaptrack]: https://github.com/KDE/heaptrack
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+/// An instruction pointer, with the functions at it, innermost first.
+#[derive(Default)]
+struct InstructionPointer {
+    address: u64,
+    module: usize,
+    functions: Vec<usize>,
+}
+
+/// The data of a profile. Strings, instruction pointers and traces are numbered from 1.
+#[derive(Default)]
+struct Data {
+    strings: Vec<String>,
+    ips: Vec<InstructionPointer>,
+    /// The instruction pointer and the parent trace of each trace.
+    traces: Vec<(usize, usize)>,
+    /// The size and trace of each allocation site, numbered from 0.
+    allocations: Vec<(u64, usize)>,
+    /// The number of allocations and frees of each allocation site.
+    counts: Vec<(u64, u64)>,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, reader: R, mut writer: W) -> io::Result<()>
+    where
+        R: BufRead,
+        W: io::Write,
+    {
+        let mut data = Data::default();
+        let mut file_version = 0;
+        let mut invalid = 0;
+        for line in reader.lines() {
+            let line = line?;
+            let (kind, rest) = match line.split_once(' ') {
+                Some((kind, rest)) => (kind, rest),
+                None => continue,
+            };
+            let mut numbers = rest.split_whitespace().map(|n| u64::from_str_radix(n, 16));
+            let mut next = || numbers.next().and_then(Result::ok);
+            match kind {
+                "v" => {
+                    next();
+                    file_version = next().unwrap_or_default();
+                }
+                "s" => {
+                    // Strings are prefixed with their length since version 3 of the format.
+                    let string = if file_version >= 3 {
+                        rest.split_once(' ').map_or("", |(_, string)| string)
+                    } else {
+                        rest
+                    };
+                    data.strings.push(string.to_string());
+                }
+                "i" => match (next(), next()) {
+                    (Some(address), Some(module)) => {
+                        let mut functions = Vec::new();
+                        // Each function comes with its file and line.
+                        while let Some(function) = next() {
+                            functions.push(function as usize);
+                            next();
+                            next();
+                        }
+                        data.ips.push(InstructionPointer {
+                            address,
+                            module: module as usize,
+                            functions,
+                        });
+                    }
+                    _ => invalid += 1,
+                },
+                "t" => match (next(), next()) {
+                    (Some(ip), Some(parent)) => data.traces.push((ip as usize, parent as usize)),
+                    _ => invalid += 1,
+                },
+                "a" => match (next(), next()) {
+                    (Some(size), Some(trace)) => {
+                        data.allocations.push((size, trace as usize));
+                        data.counts.push((0, 0));
+                    }
+                    _ => invalid += 1,
+                },
+                "+" | "-" => match next().and_then(|index| data.counts.get_mut(index as usize)) {
+                    Some((allocated, _)) if kind == "+" => *allocated += 1,
+                    Some((_, freed)) => *freed += 1,
+                    None => invalid += 1,
+                },
+                _ => {}
+            }
+        }
+
+        let mut occurrences = Occurrences::new(1);
+        for (&(size, trace), &(allocated, freed)) in data.allocations.iter().zip(&data.counts) {
+            let weight = match self.opt.cost {
+                Cost::Allocated => size * allocated,
+                Cost::Leaked => size * allocated.saturating_sub(freed),
+                Cost::Allocations => allocated,
+            };
+            if weight != 0 {
+                occurrences.insert_or_add(self.stack(&data, trace), weight as usize);
+            }
+        }
+
+        if invalid != 0 {
+            warn!("Ignored {} invalid lines", invalid);
+        }
+        if self.opt.header {
+            let mut header = Header::for_tool("inferno-collapse-heaptrack");
+            header.event = Some(self.opt.cost.to_string());
+            header.units = Some(
+                match self.opt.cost {
+                    Cost::Allocations => "allocations",
+                    _ => "bytes",
+                }
+                .to_string(),
+            );
+            header.write(&mut writer)?;
+        }
+        occurrences.write_and_clear(writer)
+    }
+
+    /// Check for the version line that heaptrack data starts with
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        let first = input.lines().next()?;
+        let mut fields = first.split(' ');
+        let is_version = fields.next() == Some("v")
+            && fields.all(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_hexdigit()));
+        Some(is_version)
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+impl Folder {
+    // Builds the folded stack of a trace.
+    fn stack(&self, data: &Data, trace: usize) -> String {
+        let mut frames = Vec::new();
+        let mut next = trace;
+        while next != 0 {
+            let (ip, parent) = match data.traces.get(next - 1) {
+                Some(&trace) => trace,
+                None => break,
+            };
+            // Guard against cycles in malformed files.
+            if frames.len() > data.traces.len() {
+                break;
+            }
+            match ip.checked_sub(1).and_then(|ip| data.ips.get(ip)) {
+                Some(ip) if !ip.functions.is_empty() => {
+                    for &function in &ip.functions {
+                        frames.push(self.function(data, function));
+                    }
+                }
+                Some(ip) => frames.push(unknown_function(data, ip)),
+                None => frames.push("[unknown]".to_string()),
+            }
+            next = parent;
+        }
+        frames.reverse();
+        frames.join(";")
+    }
+
+    fn function(&self, data: &Data, function: usize) -> String {
+        match function.checked_sub(1).and_then(|f| data.strings.get(f)) {
+            Some(name) => demangle::demangle(name, &self.opt.demangle).into_owned(),
+            None => "[unknown]".to_string(),
+        }
+    }
+}
+
+// Names an instruction pointer without symbols after its module, like perf does.
+fn unknown_function(data: &Data, ip: &InstructionPointer) -> String {
+    match ip.module.checked_sub(1).and_then(|m| data.strings.get(m)) {
+        Some(module) if !module.is_empty() => {
+            format!("[{}]", module.rsplit('/').next().unwrap_or(module))
+        }
+        _ => format!("{:#x}", ip.address),
+    }
+}
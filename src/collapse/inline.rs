@@ -0,0 +1,123 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use log::warn;
+
+use crate::dwarf::Binary;
+
+/// Expands perf stack frames into the functions that were inlined into them, using the DWARF
+/// debug info of the binaries named in the stacks.
+///
+/// Binaries are loaded lazily (and at most once) the first time one of their frames is seen.
+pub(crate) struct InlineResolver {
+    debug_dirs: Vec<PathBuf>,
+    binaries: Mutex<HashMap<String, Option<Arc<Binary>>>>,
+}
+
+impl InlineResolver {
+    /// Creates a resolver that looks for binaries in `debug_dirs` before trying the path perf
+    /// recorded.
+    pub(crate) fn new(debug_dirs: Vec<PathBuf>) -> Self {
+        Self {
+            debug_dirs,
+            binaries: Mutex::default(),
+        }
+    }
+
+    /// Returns the functions that were inlined at the frame `pc symbol+offset (module)`,
+    /// outermost first. The function perf reported itself is not included.
+    ///
+    /// Returns an empty list if the binary or its debug info can't be found, or if nothing was
+    /// inlined at that address.
+    pub(crate) fn inlined_functions(&self, pc: &str, symbol: &str, module: &str) -> Vec<String> {
+        let binary = match self.binary(module) {
+            Some(binary) => binary,
+            None => return Vec::new(),
+        };
+
+        // perf prints runtime addresses, which for position-independent code don't match the
+        // addresses in the file. The symbol's offset is reliable though, so prefer that.
+        let addr = split_symbol_offset(symbol)
+            .and_then(|(name, offset)| Some(binary.symbol_address(name)? + offset))
+            .or_else(|| u64::from_str_radix(pc, 16).ok());
+        let addr = match addr {
+            Some(addr) => addr,
+            None => return Vec::new(),
+        };
+
+        let mut functions = binary.functions(addr);
+        // The last function is the one that was actually called, which perf already knows about.
+        functions.pop();
+        functions.reverse();
+        functions
+    }
+
+    fn binary(&self, module: &str) -> Option<Arc<Binary>> {
+        // Skip pseudo-modules like [kernel.kallsyms], [vdso] and [unknown].
+        if !module.starts_with('/') {
+            return None;
+        }
+
+        let mut binaries = self.binaries.lock().ok()?;
+        binaries
+            .entry(module.to_string())
+            .or_insert_with(|| {
+                let path = self.find(module)?;
+                match Binary::load(&path) {
+                    Ok(binary) => Some(Arc::new(binary)),
+                    Err(e) => {
+                        warn!("Could not load debug info from {}: {}", path.display(), e);
+                        None
+                    }
+                }
+            })
+            .clone()
+    }
+
+    // Looks for `module` in each debug directory, first as a path relative to the directory (like
+    // a sysroot) and then by file name, before falling back to the path perf recorded.
+    fn find(&self, module: &str) -> Option<PathBuf> {
+        let relative = module.trim_start_matches('/');
+        let file_name = Path::new(module).file_name()?;
+        self.debug_dirs
+            .iter()
+            .flat_map(|dir| [dir.join(relative), dir.join(file_name)])
+            .chain(std::iter::once(PathBuf::from(module)))
+            .find(|path| path.is_file())
+    }
+}
+
+// Splits `func+0x1f` into `("func", 0x1f)`.
+fn split_symbol_offset(symbol: &str) -> Option<(&str, u64)> {
+    let offset = symbol.rfind("+0x")?;
+    let value = u64::from_str_radix(&symbol[(offset + 3)..], 16).ok()?;
+    Some((&symbol[..offset], value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn symbol_offsets() {
+        assert_eq!(split_symbol_offset("main+0x14"), Some(("main", 0x14)));
+        assert_eq!(
+            split_symbol_offset("std::rt::lang_start+0x0"),
+            Some(("std::rt::lang_start", 0))
+        );
+        assert_eq!(split_symbol_offset("main"), None);
+        assert_eq!(split_symbol_offset("[unknown]"), None);
+    }
+
+    #[test]
+    fn pseudo_modules_are_skipped() {
+        let resolver = InlineResolver::new(Vec::new());
+        assert!(resolver
+            .inlined_functions("ffffffff8103ce3b", "native_safe_halt", "[kernel.kallsyms]")
+            .is_empty());
+        assert!(resolver
+            .inlined_functions("401234", "main+0x14", "/does/not/exist")
+            .is_empty());
+    }
+}
@@ -0,0 +1,760 @@
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+use ahash::AHashMap;
+use log::warn;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+use crate::folded::Header;
+use crate::Error;
+
+// The first bytes of every chunk of a recording.
+const MAGIC: &[u8; 4] = b"FLR\0";
+
+// The size of the header of a chunk, which is followed by its events.
+const CHUNK_HEADER_SIZE: usize = 68;
+
+// The chunk header flag that says integers are LEB128-compressed.
+const COMPRESSED_INTS: i32 = 1;
+
+// The types of the events that describe the recording rather than the program.
+const METADATA_EVENT: u64 = 0;
+const CONSTANT_POOL_EVENT: u64 = 1;
+
+// The encodings of strings.
+const STRING_NULL: u8 = 0;
+const STRING_EMPTY: u8 = 1;
+const STRING_CONSTANT_POOL: u8 = 2;
+const STRING_UTF8: u8 = 3;
+const STRING_CHAR_ARRAY: u8 = 4;
+const STRING_LATIN1: u8 = 5;
+
+// Classes and fields nest, but never this deep in recordings of the JVM or async-profiler.
+const MAX_DEPTH: usize = 32;
+
+/// The events of a recording to fold stacks from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum Event {
+    /// `jdk.ExecutionSample` events of threads running Java code, counted as samples.
+    #[default]
+    Cpu,
+
+    /// `jdk.ObjectAllocationSample`, `jdk.ObjectAllocationInNewTLAB` and
+    /// `jdk.ObjectAllocationOutsideTLAB` events, weighted by the bytes they stand for.
+    Alloc,
+
+    /// `jdk.JavaMonitorEnter` and `jdk.ThreadPark` events of blocked threads, weighted by the
+    /// nanoseconds they were blocked for.
+    Lock,
+}
+
+impl Event {
+    /// The valid set of events (via `FromStr`).
+    pub const VARIANTS: &'static [&'static str] = &["cpu", "alloc", "lock"];
+
+    fn matches(self, name: &str) -> bool {
+        match self {
+            Event::Cpu => name == "jdk.ExecutionSample",
+            Event::Alloc => matches!(
+                name,
+                "jdk.ObjectAllocationSample"
+                    | "jdk.ObjectAllocationInNewTLAB"
+                    | "jdk.ObjectAllocationOutsideTLAB"
+            ),
+            Event::Lock => matches!(name, "jdk.JavaMonitorEnter" | "jdk.ThreadPark"),
+        }
+    }
+
+    fn units(self) -> &'static str {
+        match self {
+            Event::Cpu => "samples",
+            Event::Alloc => "bytes",
+            Event::Lock => "nanoseconds",
+        }
+    }
+}
+
+impl FromStr for Event {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cpu" => Ok(Event::Cpu),
+            "alloc" => Ok(Event::Alloc),
+            "lock" => Ok(Event::Lock),
+            unknown => Err(format!("unknown JFR event: {}", unknown)),
+        }
+    }
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Event::Cpu => "cpu",
+            Event::Alloc => "alloc",
+            Event::Lock => "lock",
+        })
+    }
+}
+
+/// `jfr` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct Options {
+    /// The events to fold stacks from.
+    ///
+    /// Default is [`Event::Cpu`].
+    pub event: Event,
+
+    /// Add the name of the thread of each event as the root frame of its stack.
+    ///
+    /// Default is `false`.
+    pub include_threads: bool,
+
+    /// Annotate JIT-compiled frames with a `_[j]` suffix, inlined frames with `_[i]` and kernel
+    /// frames with `_[k]`, like `inferno-collapse-perf --all` does.
+    ///
+    /// Default is `false`.
+    pub annotate: bool,
+
+    /// Write a [`Header`] before the folded stacks.
+    ///
+    /// Default is `false`.
+    pub header: bool,
+}
+
+/// A stack collapser for Java Flight Recorder recordings, as written by the JVM with
+/// `-XX:StartFlightRecording` or `jcmd <pid> JFR.start`, and by async-profiler with
+/// `-o jfr`.
+///
+/// Recordings are read the way the JVM describes them in their metadata, so the events of any
+/// JDK version are understood. Frames are named like `com.example.App.main`.
+///
+/// To construct one, either use `jfr::Folder::default()` or create an [`Options`] and use
+/// `jfr::Folder::from(options)`.
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, mut reader: R, mut writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut input = Vec::new();
+        reader.read_to_end(&mut input)?;
+
+        let mut occurrences = Occurrences::new(1);
+        let mut header = Header::for_tool("inferno-collapse-jfr");
+        header.event = Some(self.opt.event.to_string());
+        header.units = Some(self.opt.event.units().to_string());
+
+        let mut rest = &input[..];
+        while !rest.is_empty() {
+            let chunk = Chunk::parse(rest)?;
+            chunk.fold(&self.opt, &mut occurrences)?;
+
+            let start = chunk.start_nanos as f64 / 1e9;
+            let end = start + chunk.duration_nanos as f64 / 1e9;
+            header.start = Some(header.start.map_or(start, |s| s.min(start)));
+            header.end = Some(header.end.map_or(end, |e| e.max(end)));
+            rest = &rest[chunk.size..];
+        }
+
+        if self.opt.header {
+            header.write(&mut writer)?;
+        }
+        occurrences.write_and_clear(writer)
+    }
+
+    /// JFR recordings are binary, so they can't be recognized from text
+    fn is_applicable(&mut self, _input: &str) -> Option<bool> {
+        Some(false)
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+/// A class that the metadata of a chunk describes.
+#[derive(Debug, Default)]
+struct Class {
+    name: String,
+    fields: Vec<Field>,
+}
+
+#[derive(Debug)]
+struct Field {
+    name: String,
+    class: u64,
+    constant_pool: bool,
+    array: bool,
+}
+
+/// A value, decoded as its class describes it.
+#[derive(Debug, Clone)]
+enum Value {
+    Int(i64),
+    /// A value that stacks aren't made from, like a floating point number.
+    Other,
+    Str(Option<String>),
+    /// A reference to a constant of the given class.
+    Ref(u64, u64),
+    /// An object of the given class, with the values of its fields.
+    Object(u64, Vec<Value>),
+    Array(Vec<Value>),
+}
+
+/// A chunk of a recording: a self-contained part with its own metadata and constants.
+struct Chunk<'a> {
+    buf: &'a [u8],
+    size: usize,
+    compressed: bool,
+    start_nanos: i64,
+    duration_nanos: i64,
+    ticks_per_second: i64,
+    classes: AHashMap<u64, Class>,
+    constants: AHashMap<(u64, u64), Value>,
+}
+
+impl<'a> Chunk<'a> {
+    fn parse(buf: &'a [u8]) -> io::Result<Self> {
+        if buf.len() < CHUNK_HEADER_SIZE || &buf[..4] != MAGIC {
+            return Err(invalid_data("the input isn't a JFR recording"));
+        }
+        let major = u16::from_be_bytes([buf[4], buf[5]]);
+        if major != 2 {
+            return Err(invalid_data(format!(
+                "JFR format version {} isn't supported",
+                major
+            )));
+        }
+        let long_at = |pos: usize| {
+            let mut bytes = [0; 8];
+            bytes.copy_from_slice(&buf[pos..pos + 8]);
+            i64::from_be_bytes(bytes)
+        };
+        let size = long_at(8) as usize;
+        let constant_pool_offset = long_at(16) as usize;
+        let metadata_offset = long_at(24) as usize;
+        if size < CHUNK_HEADER_SIZE
+            || size > buf.len()
+            || constant_pool_offset >= size
+            || metadata_offset >= size
+        {
+            return Err(invalid_data("a chunk of the recording is truncated"));
+        }
+        let features = i32::from_be_bytes([buf[64], buf[65], buf[66], buf[67]]);
+
+        let mut chunk = Chunk {
+            buf: &buf[..size],
+            size,
+            compressed: features & COMPRESSED_INTS != 0,
+            start_nanos: long_at(32),
+            duration_nanos: long_at(40),
+            ticks_per_second: long_at(56),
+            classes: AHashMap::default(),
+            constants: AHashMap::default(),
+        };
+        chunk.read_metadata(metadata_offset)?;
+        chunk.read_constant_pools(constant_pool_offset)?;
+        Ok(chunk)
+    }
+
+    fn reader(&self, pos: usize) -> Reader<'a> {
+        Reader {
+            buf: self.buf,
+            pos,
+            compressed: self.compressed,
+        }
+    }
+
+    fn read_metadata(&mut self, offset: usize) -> io::Result<()> {
+        let mut r = self.reader(offset);
+        r.int()?; // size
+        r.long()?; // type
+        r.long()?; // start time
+        r.long()?; // duration
+        r.long()?; // metadata id
+        let nstrings = r.int()? as usize;
+        let mut strings = Vec::with_capacity(nstrings.min(r.remaining()));
+        for _ in 0..nstrings {
+            strings.push(r.string(None)?.unwrap_or_default());
+        }
+        let root = Element::read(&mut r, &strings, 0)?;
+
+        let mut classes = Vec::new();
+        root.find("class", &mut classes);
+        for class in classes {
+            let id = match class.attribute("id").and_then(|id| id.parse().ok()) {
+                Some(id) => id,
+                None => continue,
+            };
+            let fields = class
+                .children
+                .iter()
+                .filter(|child| child.name == "field")
+                .filter_map(|field| {
+                    Some(Field {
+                        name: field.attribute("name")?.to_string(),
+                        class: field.attribute("class")?.parse().ok()?,
+                        constant_pool: field.attribute("constantPool") == Some("true"),
+                        array: field.attribute("dimension") == Some("1"),
+                    })
+                })
+                .collect();
+            let name = class.attribute("name").unwrap_or_default().to_string();
+            self.classes.insert(id, Class { name, fields });
+        }
+        Ok(())
+    }
+
+    fn read_constant_pools(&mut self, mut offset: usize) -> io::Result<()> {
+        loop {
+            let mut r = self.reader(offset);
+            r.int()?; // size
+            r.long()?; // type
+            r.long()?; // start time
+            r.long()?; // duration
+            let delta = r.long()? as i64;
+            r.byte()?; // flush
+            let npools = r.int()?;
+            for _ in 0..npools {
+                let class = r.long()?;
+                let is_chunk_header = self.class(class)?.name == "jdk.types.ChunkHeader";
+                if is_chunk_header {
+                    // The chunk header is written as a constant whose class doesn't describe it.
+                    r.skip(CHUNK_HEADER_SIZE + 3)?;
+                    continue;
+                }
+                let nconstants = r.int()?;
+                for _ in 0..nconstants {
+                    let key = r.long()?;
+                    let value = r.value(&self.classes, class, 0)?;
+                    self.constants.insert((class, key), value);
+                }
+            }
+
+            if delta == 0 {
+                return Ok(());
+            }
+            offset = offset
+                .checked_add_signed(delta as isize)
+                .filter(|&offset| offset < self.size)
+                .ok_or_else(|| invalid_data("a constant pool of the recording is out of bounds"))?;
+        }
+    }
+
+    fn class(&self, id: u64) -> io::Result<&Class> {
+        self.classes
+            .get(&id)
+            .ok_or_else(|| invalid_data(format!("class {} of the recording is unknown", id)))
+    }
+
+    fn fold(&self, opt: &Options, occurrences: &mut Occurrences) -> io::Result<()> {
+        let mut pos = CHUNK_HEADER_SIZE;
+        let mut stack = String::new();
+        let mut missing = 0;
+        while pos < self.size {
+            let mut r = self.reader(pos);
+            let size = r.int()? as usize;
+            if size == 0 {
+                return Err(invalid_data("an event of the recording has no size"));
+            }
+            pos += size;
+
+            let class = r.long()?;
+            if class == METADATA_EVENT || class == CONSTANT_POOL_EVENT {
+                continue;
+            }
+            match self.classes.get(&class) {
+                Some(c) if opt.event.matches(&c.name) => {}
+                _ => continue,
+            }
+            let event = r.value(&self.classes, class, 0)?;
+
+            let weight = self.weight(opt.event, &event);
+            if weight == 0 {
+                continue;
+            }
+            stack.clear();
+            if opt.include_threads {
+                let thread = self
+                    .field(&event, "sampledThread")
+                    .or_else(|| self.field(&event, "eventThread"));
+                let name = thread.and_then(|thread| {
+                    self.text(self.field(thread, "javaName")?)
+                        .or_else(|| self.text(self.field(thread, "osName")?))
+                });
+                stack.push_str(name.unwrap_or("[unknown]"));
+            }
+            if !self.push_frames(opt, &event, &mut stack) {
+                missing += 1;
+                continue;
+            }
+            if !stack.is_empty() {
+                occurrences.insert_or_add(stack.clone(), weight);
+            }
+        }
+
+        if missing != 0 {
+            warn!("Ignored {} events without a stack trace", missing);
+        }
+        Ok(())
+    }
+
+    fn weight(&self, event: Event, value: &Value) -> usize {
+        let int = |name: &str| match self.field(value, name) {
+            Some(&Value::Int(v)) if v > 0 => Some(v as u64),
+            _ => None,
+        };
+        match event {
+            Event::Cpu => 1,
+            Event::Alloc => int("weight").or_else(|| int("allocationSize")).unwrap_or(0) as usize,
+            Event::Lock => {
+                let ticks = int("duration").unwrap_or(0);
+                if self.ticks_per_second > 0 {
+                    (ticks as f64 * 1e9 / self.ticks_per_second as f64) as usize
+                } else {
+                    ticks as usize
+                }
+            }
+        }
+    }
+
+    // Pushes the frames of the stack trace of an event, from the root to the leaf. Returns
+    // whether the event had a stack trace.
+    fn push_frames(&self, opt: &Options, event: &Value, stack: &mut String) -> bool {
+        let frames = match self
+            .field(event, "stackTrace")
+            .and_then(|trace| self.field(trace, "frames"))
+        {
+            Some(Value::Array(frames)) => frames,
+            _ => return false,
+        };
+        // Stack traces list their frames from the leaf to the root.
+        for frame in frames.iter().rev() {
+            if !stack.is_empty() {
+                stack.push(';');
+            }
+            let method = self.field(frame, "method");
+            let class_name = method
+                .and_then(|method| self.field(method, "type"))
+                .and_then(|class| self.field(class, "name"))
+                .and_then(|name| self.text(name));
+            let method_name = method
+                .and_then(|method| self.field(method, "name"))
+                .and_then(|name| self.text(name));
+            match (class_name, method_name) {
+                (Some(class), Some(method)) if !class.is_empty() => {
+                    stack.extend(class.chars().map(|c| if c == '/' { '.' } else { c }));
+                    stack.push('.');
+                    stack.push_str(method);
+                }
+                (_, Some(method)) => stack.push_str(method),
+                _ => stack.push_str("[unknown]"),
+            }
+
+            if opt.annotate {
+                let frame_type = self
+                    .field(frame, "type")
+                    .and_then(|frame_type| self.field(frame_type, "description"))
+                    .and_then(|description| self.text(description));
+                match frame_type {
+                    Some("JIT compiled") => stack.push_str("_[j]"),
+                    Some("Inlined") => stack.push_str("_[i]"),
+                    Some("Kernel") => stack.push_str("_[k]"),
+                    _ => {}
+                }
+            }
+        }
+        true
+    }
+
+    // Follows references to constants.
+    fn resolve<'v>(&'v self, mut value: &'v Value) -> Option<&'v Value> {
+        for _ in 0..MAX_DEPTH {
+            match *value {
+                Value::Ref(class, key) => value = self.constants.get(&(class, key))?,
+                _ => return Some(value),
+            }
+        }
+        None
+    }
+
+    // Gets the field of an object with the given name.
+    fn field<'v>(&'v self, value: &'v Value, name: &str) -> Option<&'v Value> {
+        match self.resolve(value)? {
+            Value::Object(class, fields) => {
+                let index = self
+                    .classes
+                    .get(class)?
+                    .fields
+                    .iter()
+                    .position(|field| field.name == name)?;
+                fields.get(index)
+            }
+            _ => None,
+        }
+    }
+
+    // Gets the text of a string, or of a symbol that holds a string.
+    fn text<'v>(&'v self, value: &'v Value) -> Option<&'v str> {
+        match self.resolve(value)? {
+            Value::Str(s) => s.as_deref(),
+            object @ Value::Object(..) => self.text(self.field(object, "string")?),
+            _ => None,
+        }
+    }
+}
+
+/// An element of the metadata of a chunk, like a class or a field.
+struct Element<'s> {
+    name: &'s str,
+    attributes: Vec<(&'s str, &'s str)>,
+    children: Vec<Element<'s>>,
+}
+
+impl<'s> Element<'s> {
+    fn read(r: &mut Reader<'_>, strings: &'s [String], depth: usize) -> io::Result<Self> {
+        if depth > MAX_DEPTH {
+            return Err(invalid_data("the metadata of the recording is too deep"));
+        }
+        let string = |i: u64| {
+            strings
+                .get(i as usize)
+                .map(String::as_str)
+                .ok_or_else(|| invalid_data("the metadata of the recording is invalid"))
+        };
+        let name = string(r.int()?)?;
+        let nattributes = r.int()?;
+        let mut attributes = Vec::new();
+        for _ in 0..nattributes {
+            attributes.push((string(r.int()?)?, string(r.int()?)?));
+        }
+        let nchildren = r.int()?;
+        let mut children = Vec::new();
+        for _ in 0..nchildren {
+            children.push(Element::read(r, strings, depth + 1)?);
+        }
+        Ok(Element {
+            name,
+            attributes,
+            children,
+        })
+    }
+
+    fn attribute(&self, key: &str) -> Option<&'s str> {
+        self.attributes
+            .iter()
+            .find(|&&(k, _)| k == key)
+            .map(|&(_, v)| v)
+    }
+
+    fn find<'e>(&'e self, name: &str, found: &mut Vec<&'e Element<'s>>) {
+        for child in &self.children {
+            if child.name == name {
+                found.push(child);
+            } else {
+                child.find(name, found);
+            }
+        }
+    }
+}
+
+/// Reads the values of a chunk.
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+    compressed: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn remaining(&self) -> usize {
+        self.buf.len().saturating_sub(self.pos)
+    }
+
+    fn bytes(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if n > self.remaining() {
+            return Err(invalid_data("the recording is truncated"));
+        }
+        let bytes = &self.buf[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, n: usize) -> io::Result<()> {
+        self.bytes(n).map(|_| ())
+    }
+
+    fn byte(&mut self) -> io::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    // Reads a LEB128-compressed integer, whose ninth byte has all eight bits.
+    fn varint(&mut self) -> io::Result<u64> {
+        let mut value = 0;
+        for shift in (0..56).step_by(7) {
+            let byte = self.byte()?;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Ok(value | u64::from(self.byte()?) << 56)
+    }
+
+    fn fixed<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        let mut bytes = [0; N];
+        bytes.copy_from_slice(self.bytes(N)?);
+        Ok(bytes)
+    }
+
+    fn short(&mut self) -> io::Result<u64> {
+        if self.compressed {
+            self.varint()
+        } else {
+            Ok(u64::from(u16::from_be_bytes(self.fixed()?)))
+        }
+    }
+
+    fn int(&mut self) -> io::Result<u64> {
+        if self.compressed {
+            self.varint()
+        } else {
+            Ok(u64::from(u32::from_be_bytes(self.fixed()?)))
+        }
+    }
+
+    fn long(&mut self) -> io::Result<u64> {
+        if self.compressed {
+            self.varint()
+        } else {
+            Ok(u64::from_be_bytes(self.fixed()?))
+        }
+    }
+
+    // Reads a string, which may refer to a constant of the string class, if given.
+    fn string(&mut self, class: Option<u64>) -> io::Result<Option<String>> {
+        self.string_value(class).map(|value| match value {
+            Value::Str(s) => s,
+            _ => None,
+        })
+    }
+
+    fn string_value(&mut self, class: Option<u64>) -> io::Result<Value> {
+        let s = match self.byte()? {
+            STRING_NULL => None,
+            STRING_EMPTY => Some(String::new()),
+            STRING_CONSTANT_POOL => {
+                let key = self.long()?;
+                return Ok(match class {
+                    Some(class) => Value::Ref(class, key),
+                    None => Value::Str(None),
+                });
+            }
+            STRING_UTF8 => {
+                let len = self.int()? as usize;
+                Some(String::from_utf8_lossy(self.bytes(len)?).into_owned())
+            }
+            STRING_CHAR_ARRAY => {
+                let len = self.int()? as usize;
+                let mut chars = Vec::with_capacity(len.min(self.remaining()));
+                for _ in 0..len {
+                    chars.push(self.short()? as u16);
+                }
+                Some(String::from_utf16_lossy(&chars))
+            }
+            STRING_LATIN1 => {
+                let len = self.int()? as usize;
+                Some(self.bytes(len)?.iter().map(|&b| char::from(b)).collect())
+            }
+            encoding => {
+                return Err(invalid_data(format!(
+                    "unknown string encoding {} in the recording",
+                    encoding
+                )))
+            }
+        };
+        Ok(Value::Str(s))
+    }
+
+    // Reads a value of the given class.
+    fn value(
+        &mut self,
+        classes: &AHashMap<u64, Class>,
+        class: u64,
+        depth: usize,
+    ) -> io::Result<Value> {
+        if depth > MAX_DEPTH {
+            return Err(invalid_data("a value of the recording is too deep"));
+        }
+        let c = classes
+            .get(&class)
+            .ok_or_else(|| invalid_data(format!("class {} of the recording is unknown", class)))?;
+        Ok(match c.name.as_str() {
+            "boolean" | "byte" => Value::Int(i64::from(self.byte()? as i8)),
+            "short" | "char" => Value::Int(i64::from(self.short()? as i16)),
+            "int" => Value::Int(i64::from(self.int()? as i32)),
+            "long" => Value::Int(self.long()? as i64),
+            "float" => self.skip(4).map(|_| Value::Other)?,
+            "double" => self.skip(8).map(|_| Value::Other)?,
+            "java.lang.String" => self.string_value(Some(class))?,
+            _ => {
+                let mut fields = Vec::with_capacity(c.fields.len());
+                for field in &c.fields {
+                    let value = if field.array {
+                        let len = self.int()? as usize;
+                        let mut values = Vec::with_capacity(len.min(self.remaining()));
+                        for _ in 0..len {
+                            values.push(self.field(classes, field, depth)?);
+                        }
+                        Value::Array(values)
+                    } else {
+                        self.field(classes, field, depth)?
+                    };
+                    fields.push(value);
+                }
+                Value::Object(class, fields)
+            }
+        })
+    }
+
+    fn field(
+        &mut self,
+        classes: &AHashMap<u64, Class>,
+        field: &Field,
+        depth: usize,
+    ) -> io::Result<Value> {
+        if field.constant_pool {
+            Ok(Value::Ref(field.class, self.long()?))
+        } else {
+            self.value(classes, field.class, depth + 1)
+        }
+    }
+}
+
+fn invalid_data<E>(error: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let error: Box<dyn std::error::Error + Send + Sync> = error.into();
+    Error::parse("jfr", error).into()
+}
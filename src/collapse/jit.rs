@@ -0,0 +1,324 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use log::warn;
+
+use crate::Error;
+
+// "JiTD" in the byte order of the process that wrote the file.
+const JITDUMP_MAGIC: u32 = 0x4A69_5444;
+
+// The only jitdump record we care about; it carries the name and address range of freshly
+// compiled code. See tools/perf/Documentation/jitdump-specification.txt in the Linux tree.
+const JIT_CODE_LOAD: u32 = 0;
+
+// id + total_size + timestamp
+const JITDUMP_RECORD_HEADER_SIZE: usize = 16;
+
+// pid + tid + vma + code_addr + code_size + code_index
+const JITDUMP_CODE_LOAD_FIXED_SIZE: usize = 40;
+
+#[derive(Clone, Debug)]
+struct Symbol {
+    start: u64,
+    end: u64,
+    name: String,
+}
+
+/// Symbols emitted by JIT runtimes, either through `/tmp/perf-<pid>.map` files or through
+/// `jit-<pid>.dump` files, grouped by the process they belong to.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct JitSymbols {
+    /// Symbol tables sorted by start address, keyed by the pid of the process that produced
+    /// them (if we could tell).
+    tables: BTreeMap<Option<u32>, Vec<Symbol>>,
+}
+
+impl JitSymbols {
+    /// Loads symbols from the given perf map and jitdump files, and from the `perf-<pid>.map`
+    /// and `jit-<pid>.dump` files in the given directories.
+    ///
+    /// The file type is detected from the jitdump magic number, so the file names don't matter,
+    /// except that the pid of a perf map file is taken from a `perf-<pid>.map` file name.
+    pub(crate) fn from_files<P>(paths: &[P]) -> io::Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let mut symbols = Self::default();
+        for path in paths {
+            let path = path.as_ref();
+            if path.is_dir() {
+                symbols.load_dir(path)?;
+            } else {
+                symbols.load_file(path)?;
+            }
+        }
+        symbols.sort();
+        Ok(symbols)
+    }
+
+    // Loads the perf map and jitdump files in `dir`, like `/tmp`, in the order of their names,
+    // so that the same files always give the same symbols.
+    fn load_dir(&mut self, dir: &Path) -> io::Result<()> {
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let is_jit_file = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| {
+                    is_pid_file(name, "perf-", ".map") || is_pid_file(name, "jit-", ".dump")
+                });
+            if is_jit_file && path.is_file() {
+                paths.push(path);
+            }
+        }
+        if paths.is_empty() {
+            warn!("No perf map or jitdump files in {}", dir.display());
+        }
+        paths.sort();
+        for path in paths {
+            self.load_file(&path)?;
+        }
+        Ok(())
+    }
+
+    fn load_file(&mut self, path: &Path) -> io::Result<()> {
+        let bytes = fs::read(path)?;
+        if bytes.len() >= 4 && is_jitdump_magic(&bytes[..4]) {
+            self.load_jitdump(&bytes)
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+        } else {
+            let pid = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_prefix("perf-"))
+                .and_then(|name| name.strip_suffix(".map"))
+                .and_then(|pid| pid.parse().ok());
+            self.load_perf_map(pid, &String::from_utf8_lossy(&bytes));
+            Ok(())
+        }
+    }
+
+    // Each line of a perf map file looks like:
+    //
+    //     7f83c4d3f000 1e0 LazyCompile:~main /app/index.js:10
+    //
+    // That is, a hexadecimal start address, a hexadecimal code size, and the symbol name (which
+    // may contain spaces).
+    fn load_perf_map(&mut self, pid: Option<u32>, contents: &str) {
+        let table = self.tables.entry(pid).or_default();
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            match perf_map_line_parts(line) {
+                Some((start, size, name)) if !name.is_empty() => table.push(Symbol {
+                    start,
+                    end: start.saturating_add(size),
+                    name: name.to_string(),
+                }),
+                _ => warn!("Weird perf map line: {}", line),
+            }
+        }
+    }
+
+    fn load_jitdump(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let little_endian = bytes[..4] == JITDUMP_MAGIC.to_le_bytes();
+        let read_u32 = |offset: usize| -> io::Result<u32> {
+            let raw = bytes
+                .get(offset..offset + 4)
+                .ok_or_else(truncated_jitdump)?;
+            let raw = [raw[0], raw[1], raw[2], raw[3]];
+            Ok(if little_endian {
+                u32::from_le_bytes(raw)
+            } else {
+                u32::from_be_bytes(raw)
+            })
+        };
+        let read_u64 = |offset: usize| -> io::Result<u64> {
+            let lo = u64::from(read_u32(offset)?);
+            let hi = u64::from(read_u32(offset + 4)?);
+            Ok(if little_endian {
+                lo | (hi << 32)
+            } else {
+                (lo << 32) | hi
+            })
+        };
+
+        // The file header is: magic, version, total_size, elf_mach, pad1, pid, timestamp, flags.
+        let header_size = read_u32(8)? as usize;
+        let pid = read_u32(20)?;
+        let table = self.tables.entry(Some(pid)).or_default();
+
+        let mut offset = header_size;
+        while offset + JITDUMP_RECORD_HEADER_SIZE <= bytes.len() {
+            let id = read_u32(offset)?;
+            let record_size = read_u32(offset + 4)? as usize;
+            if record_size < JITDUMP_RECORD_HEADER_SIZE || offset + record_size > bytes.len() {
+                // The runtime may have been killed while writing the last record.
+                warn!("Ignoring truncated record at end of jitdump file");
+                break;
+            }
+
+            if id == JIT_CODE_LOAD {
+                let body = offset + JITDUMP_RECORD_HEADER_SIZE;
+                let code_addr = read_u64(body + 16)?;
+                let code_size = read_u64(body + 24)?;
+                let name_start = body + JITDUMP_CODE_LOAD_FIXED_SIZE;
+                let record_end = offset + record_size;
+                if name_start <= record_end {
+                    let name = &bytes[name_start..record_end];
+                    let name = match name.iter().position(|b| *b == 0) {
+                        Some(nul) => &name[..nul],
+                        None => name,
+                    };
+                    table.push(Symbol {
+                        start: code_addr,
+                        end: code_addr.saturating_add(code_size),
+                        name: String::from_utf8_lossy(name).into_owned(),
+                    });
+                }
+            }
+
+            offset += record_size;
+        }
+
+        Ok(())
+    }
+
+    fn sort(&mut self) {
+        for table in self.tables.values_mut() {
+            // Stable sort so that, for code that was compiled into the same address more than
+            // once, the last entry (which is the most recent one) is the one we find.
+            table.sort_by_key(|symbol| symbol.start);
+        }
+    }
+
+    /// Finds the name of the JIT symbol that contains `addr` in the process `pid`.
+    ///
+    /// Symbols whose pid is unknown are considered to belong to every process. If `pid` is
+    /// `None`, or there is no symbol table for that process, all symbol tables are searched.
+    pub(crate) fn lookup(&self, pid: Option<u32>, addr: u64) -> Option<&str> {
+        match pid.and_then(|pid| self.tables.get(&Some(pid))) {
+            Some(table) => lookup_in(table, addr).or_else(|| {
+                self.tables
+                    .get(&None)
+                    .and_then(|table| lookup_in(table, addr))
+            }),
+            None => self
+                .tables
+                .values()
+                .find_map(|table| lookup_in(table, addr)),
+        }
+    }
+}
+
+fn lookup_in(table: &[Symbol], addr: u64) -> Option<&str> {
+    let idx = table.partition_point(|symbol| symbol.start <= addr);
+    let symbol = table.get(idx.checked_sub(1)?)?;
+    if addr < symbol.end {
+        Some(&symbol.name)
+    } else {
+        None
+    }
+}
+
+fn perf_map_line_parts(line: &str) -> Option<(u64, u64, &str)> {
+    let mut parts = line.splitn(3, ' ');
+    let start = parse_hex(parts.next()?)?;
+    let size = parse_hex(parts.next()?)?;
+    let name = parts.next()?.trim();
+    Some((start, size, name))
+}
+
+// Whether a file is called `<prefix><pid><suffix>`, like `perf-1234.map`.
+fn is_pid_file(name: &str, prefix: &str, suffix: &str) -> bool {
+    name.strip_prefix(prefix)
+        .and_then(|name| name.strip_suffix(suffix))
+        .is_some_and(|pid| !pid.is_empty() && pid.bytes().all(|b| b.is_ascii_digit()))
+}
+
+fn is_jitdump_magic(bytes: &[u8]) -> bool {
+    bytes == JITDUMP_MAGIC.to_le_bytes() || bytes == JITDUMP_MAGIC.to_be_bytes()
+}
+
+fn truncated_jitdump() -> io::Error {
+    Error::parse("jitdump", "truncated jitdump file").into()
+}
+
+fn parse_hex(s: &str) -> Option<u64> {
+    let s = s.strip_prefix("0x").unwrap_or(s);
+    u64::from_str_radix(s, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn perf_map_lookup() {
+        let mut symbols = JitSymbols::default();
+        symbols.load_perf_map(
+            Some(42),
+            "7f0000001000 100 LazyCompile:*fib /app/fib.js:1\n\
+             7f0000002000 40 LazyCompile:~main /app/index.js:10\n",
+        );
+        symbols.sort();
+
+        assert_eq!(
+            symbols.lookup(Some(42), 0x7f00_0000_1010),
+            Some("LazyCompile:*fib /app/fib.js:1")
+        );
+        assert_eq!(
+            symbols.lookup(None, 0x7f00_0000_203f),
+            Some("LazyCompile:~main /app/index.js:10")
+        );
+        assert_eq!(symbols.lookup(Some(42), 0x7f00_0000_2040), None);
+        assert_eq!(symbols.lookup(Some(42), 0x7f00_0000_0fff), None);
+    }
+
+    #[test]
+    fn jitdump_lookup() {
+        fn record(id: u32, body: &[u8]) -> Vec<u8> {
+            let mut record = Vec::new();
+            record.extend_from_slice(&id.to_le_bytes());
+            record.extend_from_slice(&((16 + body.len()) as u32).to_le_bytes());
+            record.extend_from_slice(&0u64.to_le_bytes());
+            record.extend_from_slice(body);
+            record
+        }
+
+        let mut dump = Vec::new();
+        dump.extend_from_slice(&JITDUMP_MAGIC.to_le_bytes());
+        dump.extend_from_slice(&1u32.to_le_bytes()); // version
+        dump.extend_from_slice(&40u32.to_le_bytes()); // total_size
+        dump.extend_from_slice(&62u32.to_le_bytes()); // elf_mach
+        dump.extend_from_slice(&0u32.to_le_bytes()); // pad1
+        dump.extend_from_slice(&1234u32.to_le_bytes()); // pid
+        dump.extend_from_slice(&0u64.to_le_bytes()); // timestamp
+        dump.extend_from_slice(&0u64.to_le_bytes()); // flags
+
+        let mut load = Vec::new();
+        load.extend_from_slice(&1234u32.to_le_bytes()); // pid
+        load.extend_from_slice(&1235u32.to_le_bytes()); // tid
+        load.extend_from_slice(&0x5000u64.to_le_bytes()); // vma
+        load.extend_from_slice(&0x5000u64.to_le_bytes()); // code_addr
+        load.extend_from_slice(&0x20u64.to_le_bytes()); // code_size
+        load.extend_from_slice(&0u64.to_le_bytes()); // code_index
+        load.extend_from_slice(b"Interpreted:foo\0");
+        dump.extend(record(JIT_CODE_LOAD, &load));
+        // an unrelated record (JIT_CODE_CLOSE) which should be skipped
+        dump.extend(record(3, &[]));
+
+        let mut symbols = JitSymbols::default();
+        symbols.load_jitdump(&dump).unwrap();
+        symbols.sort();
+
+        assert_eq!(symbols.lookup(Some(1234), 0x5010), Some("Interpreted:foo"));
+        assert_eq!(symbols.lookup(Some(1234), 0x5020), None);
+    }
+}
@@ -0,0 +1,230 @@
+use std::io::{self, BufRead};
+
+use log::warn;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+use crate::folded::Header;
+use crate::Error;
+
+/// `massif` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct Options {
+    /// The number of the snapshot to fold.
+    ///
+    /// Only detailed snapshots have stacks: massif takes one every `--detailed-freq` snapshots,
+    /// and one at the peak.
+    ///
+    /// Default is `None`, which folds the peak snapshot, or the last detailed one if there is no
+    /// peak.
+    pub snapshot: Option<usize>,
+
+    /// Keep the source location of each function, like `compute (app.c:12)`.
+    ///
+    /// Default is `false`.
+    pub include_locations: bool,
+
+    /// Write a [`Header`] before the folded stacks, with units of `bytes`, so that flame graphs
+    /// count bytes instead of samples.
+    ///
+    /// Default is `false`.
+    pub header: bool,
+}
+
+/// A stack collapser for the `massif.out.<pid>` files of [Valgrind]'s `massif` heap profiler.
+///
+/// Massif takes snapshots of the heap as the program runs, and records the stacks that the heap
+/// was allocated at in detailed snapshots, as a tree that starts at the allocation functions and
+/// branches out to their callers. The stacks of one snapshot are folded, weighted by the bytes
+/// they hold. Allocations too small to be listed on their own are folded into a
+/// `[below threshold]` frame.
+///
+/// To construct one, either use `massif::Folder::default()` or create an [`Options`] and use
+/// `massif::Folder::from(options)`.
+///
+///   [Valgrind]: https://valgrind.org/docs/manual/ms-manual.html
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+/// A node of a heap tree: the bytes allocated under it, and its callers.
+struct Node {
+    bytes: u64,
+    name: String,
+    children: Vec<Node>,
+}
+
+/// A detailed snapshot.
+struct Snapshot {
+    number: usize,
+    peak: bool,
+    tree: Node,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, reader: R, mut writer: W) -> io::Result<()>
+    where
+        R: BufRead,
+        W: io::Write,
+    {
+        let mut snapshots = Vec::new();
+        let mut number = 0;
+        let mut lines = reader.lines();
+        while let Some(line) = lines.next() {
+            let line = line?;
+            if let Some(n) = line.strip_prefix("snapshot=") {
+                number = n.trim().parse().unwrap_or_default();
+            } else if let Some(kind) = line.strip_prefix("heap_tree=") {
+                let peak = kind.trim() == "peak";
+                if peak || kind.trim() == "detailed" {
+                    let tree = self.read_tree(&mut lines)?;
+                    snapshots.push(Snapshot { number, peak, tree });
+                }
+            }
+        }
+
+        let snapshot = match self.opt.snapshot {
+            Some(wanted) => snapshots.iter().find(|s| s.number == wanted),
+            None => snapshots
+                .iter()
+                .find(|s| s.peak)
+                .or_else(|| snapshots.last()),
+        };
+        let snapshot = match snapshot {
+            Some(snapshot) => snapshot,
+            None if self.opt.snapshot.is_some() => {
+                let numbers: Vec<_> = snapshots.iter().map(|s| s.number.to_string()).collect();
+                return Err(io::Error::from(Error::InvalidOptions(format!(
+                    "snapshot {} isn't a detailed snapshot; the detailed snapshots are: {}",
+                    self.opt.snapshot.unwrap_or_default(),
+                    numbers.join(", ")
+                ))));
+            }
+            None => {
+                warn!("The profile has no detailed snapshots");
+                return Ok(());
+            }
+        };
+
+        let mut occurrences = Occurrences::new(1);
+        let mut callers = Vec::new();
+        // The root of the tree stands for all the allocation functions.
+        for child in &snapshot.tree.children {
+            fold(child, &mut callers, &mut occurrences);
+        }
+
+        if self.opt.header {
+            let mut header = Header::for_tool("inferno-collapse-massif");
+            header.event = Some(format!("snapshot {}", snapshot.number));
+            header.units = Some("bytes".to_string());
+            header.write(&mut writer)?;
+        }
+        occurrences.write_and_clear(writer)
+    }
+
+    /// Check for the `desc:` and `cmd:` lines that massif output starts with
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        let mut lines = input.lines();
+        let first = lines.next()?;
+        if !first.starts_with("desc:") {
+            return Some(false);
+        }
+        lines.next().map(|second| second.starts_with("cmd:"))
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+impl Folder {
+    // Reads a heap tree, whose nodes are written like `n2: 1000 0x4005A4: compute (app.c:12)`,
+    // with the number of children after the `n`, and are indented by their depth.
+    fn read_tree<I>(&self, lines: &mut I) -> io::Result<Node>
+    where
+        I: Iterator<Item = io::Result<String>>,
+    {
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => return Err(invalid_data("the file ended in a heap tree")),
+        };
+        let trimmed = line.trim_start();
+        let (nchildren, rest) = trimmed
+            .strip_prefix('n')
+            .and_then(|node| node.split_once(": "))
+            .ok_or_else(|| invalid_data(format!("invalid heap tree node: {}", trimmed)))?;
+        let nchildren: usize = nchildren
+            .parse()
+            .map_err(|_| invalid_data(format!("invalid heap tree node: {}", trimmed)))?;
+        let (bytes, name) = rest.split_once(' ').unwrap_or((rest, ""));
+        let bytes = bytes
+            .parse()
+            .map_err(|_| invalid_data(format!("invalid heap tree node: {}", trimmed)))?;
+
+        let mut children = Vec::new();
+        for _ in 0..nchildren {
+            children.push(self.read_tree(lines)?);
+        }
+        Ok(Node {
+            bytes,
+            name: self.frame(name),
+            children,
+        })
+    }
+
+    // Turns a node like `0x4005A4: compute (app.c:12)` into a frame.
+    fn frame(&self, node: &str) -> String {
+        if node.starts_with("in ") && node.contains("below massif's threshold") {
+            return "[below threshold]".to_string();
+        }
+        let function = match node.split_once(": ") {
+            Some((address, function)) if address.starts_with("0x") => function,
+            _ => node,
+        };
+        if self.opt.include_locations {
+            return function.to_string();
+        }
+        match function.rsplit_once(" (") {
+            // Functions without symbols are named after their module, like perf does.
+            Some(("???", location)) => {
+                let module = location.trim_end_matches(')').trim_start_matches("in ");
+                format!("[{}]", module.rsplit('/').next().unwrap_or(module))
+            }
+            Some((function, _)) => function.to_string(),
+            None => function.to_string(),
+        }
+    }
+}
+
+// Folds a node and its callers; `callers` holds the frames from the allocation function to it.
+fn fold<'a>(node: &'a Node, callers: &mut Vec<&'a str>, occurrences: &mut Occurrences) {
+    callers.push(&node.name);
+    // Bytes that aren't allocated from any of the listed callers, because the stack ended.
+    let children: u64 = node.children.iter().map(|child| child.bytes).sum();
+    let bytes = node.bytes.saturating_sub(children);
+    if bytes != 0 {
+        let stack: Vec<_> = callers.iter().rev().copied().collect();
+        occurrences.insert_or_add(stack.join(";"), bytes as usize);
+    }
+    for child in &node.children {
+        fold(child, callers, occurrences);
+    }
+    callers.pop();
+}
+
+fn invalid_data<E>(error: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let error: Box<dyn std::error::Error + Send + Sync> = error.into();
+    Error::parse("massif", error).into()
+}
@@ -21,6 +21,13 @@ pub mod perf;
 /// Internal string match helper functions for perf
 pub(crate) mod matcher;
 
+/// Internal symbol tables for JIT-compiled code (perf map and jitdump files)
+pub(crate) mod jit;
+
+/// Internal DWARF-based inline frame expansion for perf
+#[cfg(feature = "inline")]
+pub(crate) mod inline;
+
 /// Stack collapsing for the output of [`sample`](https://gist.github.com/loderunner/36724cc9ee8db66db305#profiling-with-sample) on macOS.
 ///
 /// See the [crate-level documentation] for details.
@@ -67,18 +74,196 @@ pub mod vsprof;
 ///   [crate-level documentation]: ../../index.html
 pub mod ghcprof;
 
+/// Stack collapsing for the XML that Apple's `xctrace export` writes for the tables of
+/// Instruments traces, like those of the Time Profiler and Allocations instruments.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod xctrace;
+
+/// Stack collapsing for flame graph SVGs made by [`flamegraph.pl`](https://github.com/brendangregg/FlameGraph)
+/// or inferno, to get back the stacks of flame graphs whose folded files are gone.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod svg;
+
+/// Stack collapsing for the JSON data of [`d3-flame-graph`](https://github.com/spiermar/d3-flame-graph).
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod d3;
+
+/// Stack collapsing for the raw output of [`py-spy`](https://github.com/benfred/py-spy).
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod pyspy;
+
+/// Stack collapsing for the [pprof](https://github.com/google/pprof) protobuf profiles of Go and
+/// gperftools.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod pprof;
+
+/// Stack collapsing for the `.cpuprofile` JSON of [V8](https://v8.dev/docs/profile), as written
+/// by `node --cpu-prof` and Chrome DevTools.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod cpuprofile;
+
+/// Stack collapsing for [Java Flight Recorder](https://docs.oracle.com/en/java/java-components/jdk-mission-control/)
+/// recordings of the JVM and async-profiler.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod jfr;
+
+/// Stack collapsing for the output of [Valgrind](https://valgrind.org/)'s `callgrind` tool.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod callgrind;
+
+/// Stack collapsing for [speedscope](https://www.speedscope.app/)'s JSON file format.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod speedscope;
+
+/// Stack collapsing for the JSON profiles of the
+/// [Firefox Profiler](https://profiler.firefox.com/).
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod gecko;
+
+/// Stack collapsing for the stacks printed by [bpftrace](https://github.com/bpftrace/bpftrace)
+/// and the [BCC](https://github.com/iovisor/bcc) tools.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod bpftrace;
+
+/// Stack collapsing for the CSV exports of the Windows Performance Analyzer.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod wpa;
+
+/// Stack collapsing for the data files of the [heaptrack](https://github.com/KDE/heaptrack)
+/// memory profiler.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod heaptrack;
+
+/// Stack collapsing for the output of [Valgrind](https://valgrind.org/)'s `massif` heap profiler.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod massif;
+
+/// Stack collapsing for the `.nettrace` files of .NET's EventPipe, as written by `dotnet-trace`.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod nettrace;
+
+/// Stack collapsing for "poor man's profiler" backtraces taken with `gdb` or `eu-stack`.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod pmp;
+
+/// Stack collapsing for the JSON dumps of Ruby's [stackprof](https://github.com/tmm1/stackprof).
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod stackprof;
+
+/// Stack collapsing for the `report-sample` output of Android's
+/// [simpleperf](https://android.googlesource.com/platform/system/extras/+/main/simpleperf/doc/README.md).
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../../index.html
+pub mod simpleperf;
+
 use is_terminal::IsTerminal;
 
 // DEFAULT_NTHREADS is public because we use it in the help text of the binaries,
 // but it doesn't need to be exposed to library users, hence #[doc(hidden)].
 #[doc(hidden)]
-pub use self::common::DEFAULT_NTHREADS;
+pub use self::common::{CancelToken, DEFAULT_NTHREADS};
 
 use std::fs::File;
 use std::io;
 use std::path::Path;
+use std::str::FromStr;
+
+use self::common::{decompress, CollapsePrivate, ProgressReader, CAPACITY_READER};
+
+/// Which process or thread to put at the root of each stack, for the collapsers whose input
+/// records them, so that the stacks of different processes or threads are kept apart.
+///
+/// The frames are those that the `include_pid` and `include_tid` options of [`perf`] write: the
+/// name of the process and its id, like `rg-64751`, for processes, and then the id of the
+/// thread, like `rg-64751/15758523`, for threads.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum Prefix {
+    /// Merge the stacks of all processes and threads.
+    #[default]
+    None,
 
-use self::common::{CollapsePrivate, CAPACITY_READER};
+    /// Put the process at the root of each stack.
+    Process,
+
+    /// Put the process and the thread at the root of each stack.
+    Thread,
+}
+
+impl Prefix {
+    /// The valid set of prefixes (via `FromStr`).
+    pub const VARIANTS: &'static [&'static str] = &["none", "process", "thread"];
+}
+
+impl FromStr for Prefix {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Prefix::None),
+            "process" => Ok(Prefix::Process),
+            "thread" => Ok(Prefix::Thread),
+            unknown => Err(format!("unknown prefix: {}", unknown)),
+        }
+    }
+}
 
 /// The abstract behavior of stack collapsing.
 ///
@@ -99,8 +284,37 @@ pub trait Collapse {
         R: io::BufRead,
         W: io::Write;
 
+    /// Collapses the contents of the provided `reader` like [`collapse`](Collapse::collapse),
+    /// calling `progress` with the number of bytes read so far about every megabyte of input, and
+    /// once more with the total at the end.
+    ///
+    /// Once `cancel` is cancelled, reading more input fails, and so does the collapse, with an
+    /// error of kind `Other`. Some collapsers read all of their input before they fold it, and
+    /// only notice the cancellation while reading, except for the multithreaded ones, whose
+    /// threads also check for it before each chunk of stacks they fold.
+    fn collapse_with_progress<R, W, F>(
+        &mut self,
+        reader: R,
+        writer: W,
+        progress: F,
+        cancel: &CancelToken,
+    ) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+        F: FnMut(u64),
+    {
+        let mut reader = ProgressReader::new(reader, progress, cancel);
+        self.collapse(&mut reader, writer)?;
+        reader.finish();
+        Ok(())
+    }
+
     /// Collapses the contents of the provided file (or of STDIN if `infile` is `None`) and
     /// writes folded stack lines to provided `writer`.
+    ///
+    /// Files compressed with gzip, or with zstd or xz if the `zstd` or `xz` features are
+    /// enabled, are decompressed first.
     fn collapse_file<P, W>(&mut self, infile: Option<P>, writer: W) -> io::Result<()>
     where
         P: AsRef<Path>,
@@ -110,17 +324,27 @@ pub trait Collapse {
             Some(ref path) => {
                 let file = File::open(path)?;
                 let reader = io::BufReader::with_capacity(CAPACITY_READER, file);
-                self.collapse(reader, writer)
+                self.collapse(decompress(reader)?, writer)
             }
             None => {
                 let stdin = io::stdin();
                 let stdin_guard = stdin.lock();
                 let reader = io::BufReader::with_capacity(CAPACITY_READER, stdin_guard);
-                self.collapse(reader, writer)
+                self.collapse(decompress(reader)?, writer)
             }
         }
     }
 
+    /// Collapses `input` and returns the folded stack lines, without files or a writer, like in a
+    /// WebAssembly module that gets its input from JavaScript.
+    ///
+    /// Input compressed like for [`collapse_file`](Collapse::collapse_file) is decompressed first.
+    fn collapse_bytes(&mut self, input: &[u8]) -> io::Result<String> {
+        let mut folded = Vec::new();
+        self.collapse(decompress(input)?, &mut folded)?;
+        String::from_utf8(folded).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
     /// Collapses the contents of the provided file (or of STDIN if `infile` is `None`) and
     /// writes folded stack lines to STDOUT.
     fn collapse_file_to_stdout<P>(&mut self, infile: Option<P>) -> io::Result<()>
@@ -152,7 +376,25 @@ where
         R: io::BufRead,
         W: io::Write,
     {
-        <Self as CollapsePrivate>::collapse(self, reader, writer)
+        <Self as CollapsePrivate>::collapse(self, reader, writer, &CancelToken::new())
+    }
+
+    fn collapse_with_progress<R, W, F>(
+        &mut self,
+        reader: R,
+        writer: W,
+        progress: F,
+        cancel: &CancelToken,
+    ) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+        F: FnMut(u64),
+    {
+        let mut reader = ProgressReader::new(reader, progress, cancel);
+        <Self as CollapsePrivate>::collapse(self, &mut reader, writer, cancel)?;
+        reader.finish();
+        Ok(())
     }
 
     fn is_applicable(&mut self, input: &str) -> Option<bool> {
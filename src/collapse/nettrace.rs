@@ -0,0 +1,499 @@
+use std::io;
+
+use ahash::AHashMap;
+use log::warn;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+use crate::folded::Header;
+use crate::Error;
+
+// A nettrace file starts with this, followed by the name of its serialization format.
+const MAGIC: &[u8] = b"Nettrace";
+const SERIALIZATION: &[u8] = b"!FastSerialization.1";
+
+// The tags of the FastSerialization format that nettrace files use.
+const TAG_NULL_REFERENCE: u8 = 1;
+const TAG_BEGIN_PRIVATE_OBJECT: u8 = 5;
+const TAG_END_OBJECT: u8 = 6;
+
+// The flags of compressed event headers, which say what fields follow.
+const FLAG_METADATA_ID: u8 = 1;
+const FLAG_CAPTURE_THREAD_AND_SEQUENCE: u8 = 2;
+const FLAG_THREAD_ID: u8 = 4;
+const FLAG_STACK_ID: u8 = 8;
+const FLAG_ACTIVITY_ID: u8 = 16;
+const FLAG_RELATED_ACTIVITY_ID: u8 = 32;
+const FLAG_DATA_LENGTH: u8 = 128;
+
+// The provider of the samples of `dotnet-trace collect`.
+const SAMPLE_PROVIDER: &str = "Microsoft-DotNETCore-SampleProfiler";
+
+// The providers of the events that give the names and addresses of JIT-compiled methods, and the
+// ids of those events: MethodLoadVerbose and MethodUnloadVerbose of the runtime, and
+// MethodDCStartVerbose and MethodDCEndVerbose of its rundown at the end of a trace.
+const RUNTIME_PROVIDERS: &[&str] = &[
+    "Microsoft-Windows-DotNETRuntime",
+    "Microsoft-Windows-DotNETRuntimeRundown",
+];
+const METHOD_EVENTS: &[u32] = &[143, 144];
+
+/// `nettrace` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct Options {
+    /// Add the thread of each sample, like `Thread (1234)`, as the root frame of its stack.
+    ///
+    /// Default is `false`.
+    pub include_threads: bool,
+
+    /// Write a [`Header`] before the folded stacks.
+    ///
+    /// Default is `false`.
+    pub header: bool,
+}
+
+/// A stack collapser for the `.nettrace` files of .NET's EventPipe, as written by
+/// `dotnet-trace collect`.
+///
+/// The stacks of the CPU samples of the trace are folded, with managed frames named after the
+/// methods that the runtime reports JIT-compiling, like `MyApp.Program.Compute`. Native frames
+/// have no names in nettrace files, so every run of native frames becomes one `[unknown]` frame.
+///
+/// Only the format of .NET 5 and later is read. Older traces can be converted with
+/// `dotnet-trace convert --format Speedscope` and folded with `speedscope::Folder` instead.
+///
+/// To construct one, either use `nettrace::Folder::default()` or create an [`Options`] and use
+/// `nettrace::Folder::from(options)`.
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+/// What the events of a metadata id are.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Sample,
+    Method,
+    Other,
+}
+
+/// The fields of an event header that are used.
+#[derive(Default)]
+struct EventHeader {
+    metadata_id: u64,
+    thread_id: u64,
+    stack_id: u64,
+    payload_size: usize,
+}
+
+/// A JIT-compiled method.
+struct Method {
+    start: u64,
+    end: u64,
+    name: String,
+}
+
+/// What has been read from a trace.
+#[derive(Default)]
+struct Trace {
+    pointer_size: usize,
+    kinds: AHashMap<u64, Kind>,
+    stacks: AHashMap<u64, Vec<u64>>,
+    /// The number of samples of each thread and stack of instruction pointers.
+    samples: AHashMap<(u64, Vec<u64>), usize>,
+    /// The number of samples whose stacks weren't in the trace.
+    unknown_stacks: usize,
+    methods: Vec<Method>,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, mut reader: R, mut writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut input = Vec::new();
+        reader.read_to_end(&mut input)?;
+
+        let mut trace = Trace {
+            pointer_size: 8,
+            ..Default::default()
+        };
+        trace.read(&input)?;
+        trace.methods.sort_by_key(|method| method.start);
+
+        let mut occurrences = Occurrences::new(1);
+        let mut frames = Vec::new();
+        for ((thread, ips), &count) in &trace.samples {
+            frames.clear();
+            for &ip in ips.iter().rev() {
+                let name = trace.method(ip).unwrap_or("[unknown]");
+                // Runs of native frames, which have no names, merge into one.
+                if name == "[unknown]" && frames.last() == Some(&"[unknown]") {
+                    continue;
+                }
+                frames.push(name);
+            }
+            let mut stack = if self.opt.include_threads {
+                format!("Thread ({})", thread)
+            } else {
+                String::new()
+            };
+            for frame in &frames {
+                if !stack.is_empty() {
+                    stack.push(';');
+                }
+                stack.push_str(frame);
+            }
+            if !stack.is_empty() {
+                occurrences.insert_or_add(stack, count);
+            }
+        }
+
+        if trace.unknown_stacks != 0 {
+            warn!(
+                "Ignored {} samples with unknown stacks",
+                trace.unknown_stacks
+            );
+        }
+        if self.opt.header {
+            let mut header = Header::for_tool("inferno-collapse-nettrace");
+            header.units = Some("samples".to_string());
+            header.write(&mut writer)?;
+        }
+        occurrences.write_and_clear(writer)
+    }
+
+    /// Check for the `Nettrace` magic that the files start with
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        Some(input.as_bytes().starts_with(MAGIC))
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+impl Trace {
+    fn read(&mut self, buf: &[u8]) -> io::Result<()> {
+        let mut r = Reader { buf, pos: 0 };
+        if !buf.starts_with(MAGIC) {
+            return Err(invalid_data(
+                "not a nettrace file of .NET 5 or later; convert older traces to speedscope",
+            ));
+        }
+        r.skip(MAGIC.len())?;
+        let len = r.u32()? as usize;
+        if r.bytes(len)? != SERIALIZATION {
+            return Err(invalid_data("unknown nettrace serialization format"));
+        }
+
+        loop {
+            match r.byte()? {
+                TAG_NULL_REFERENCE => return Ok(()),
+                TAG_BEGIN_PRIVATE_OBJECT => {}
+                tag => return Err(invalid_data(format!("unexpected nettrace tag {}", tag))),
+            }
+            let name = r.type_name()?;
+            match name {
+                "Trace" => {
+                    // The start time, the timestamp frequency and synchronization come first.
+                    r.skip(16 + 8 + 8)?;
+                    self.pointer_size = r.u32()? as usize;
+                    // The process id, number of processors and sampling rate come last.
+                    r.skip(4 + 4 + 4)?;
+                }
+                "EventBlock" | "MetadataBlock" | "StackBlock" | "SPBlock" => {
+                    let size = r.u32()? as usize;
+                    // Blocks are aligned to four bytes in the file.
+                    r.skip((4 - r.pos % 4) % 4)?;
+                    let block = r.bytes(size)?;
+                    match name {
+                        "EventBlock" => self.read_events(block, false)?,
+                        "MetadataBlock" => self.read_events(block, true)?,
+                        "StackBlock" => self.read_stacks(block)?,
+                        // Stack ids start over after sequence points.
+                        _ => self.stacks.clear(),
+                    }
+                }
+                name => {
+                    return Err(invalid_data(format!(
+                        "unknown nettrace object type {}",
+                        name
+                    )))
+                }
+            }
+            if r.byte()? != TAG_END_OBJECT {
+                return Err(invalid_data(format!("{} object doesn't end", name)));
+            }
+        }
+    }
+
+    fn read_events(&mut self, block: &[u8], metadata: bool) -> io::Result<()> {
+        let mut r = Reader { buf: block, pos: 0 };
+        let header_size = r.u16()? as usize;
+        let flags = r.u16()?;
+        r.skip(header_size.saturating_sub(4))?;
+        let compressed = flags & 1 != 0;
+
+        // Compressed headers only have the fields that differ from the previous event's.
+        let mut header = EventHeader::default();
+        while r.remaining() > 0 {
+            if compressed {
+                let flags = r.byte()?;
+                if flags & FLAG_METADATA_ID != 0 {
+                    header.metadata_id = r.varint()?;
+                }
+                if flags & FLAG_CAPTURE_THREAD_AND_SEQUENCE != 0 {
+                    // The sequence number, capture thread and processor.
+                    r.varint()?;
+                    r.varint()?;
+                    r.varint()?;
+                }
+                if flags & FLAG_THREAD_ID != 0 {
+                    header.thread_id = r.varint()?;
+                }
+                if flags & FLAG_STACK_ID != 0 {
+                    header.stack_id = r.varint()?;
+                }
+                // The timestamp.
+                r.varint()?;
+                if flags & FLAG_ACTIVITY_ID != 0 {
+                    r.skip(16)?;
+                }
+                if flags & FLAG_RELATED_ACTIVITY_ID != 0 {
+                    r.skip(16)?;
+                }
+                if flags & FLAG_DATA_LENGTH != 0 {
+                    header.payload_size = r.varint()? as usize;
+                }
+            } else {
+                // The event size, then the metadata id, whose high bit says if events are sorted.
+                r.skip(4)?;
+                header.metadata_id = u64::from(r.u32()? & 0x7fff_ffff);
+                // The sequence number.
+                r.skip(4)?;
+                header.thread_id = r.u64()?;
+                // The capture thread and processor.
+                r.skip(8 + 4)?;
+                header.stack_id = u64::from(r.u32()?);
+                // The timestamp and activity ids.
+                r.skip(8 + 16 + 16)?;
+                header.payload_size = r.u32()? as usize;
+            }
+
+            let payload = r.bytes(header.payload_size)?;
+            if !compressed {
+                r.skip((4 - r.pos % 4) % 4)?;
+            }
+            if metadata {
+                self.read_metadata(payload)?;
+            } else {
+                self.on_event(&header, payload)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_metadata(&mut self, payload: &[u8]) -> io::Result<()> {
+        let mut r = Reader {
+            buf: payload,
+            pos: 0,
+        };
+        let id = u64::from(r.u32()?);
+        let provider = r.utf16()?;
+        let event = r.u32()?;
+        let kind = if provider == SAMPLE_PROVIDER {
+            Kind::Sample
+        } else if RUNTIME_PROVIDERS.contains(&provider.as_str()) && METHOD_EVENTS.contains(&event) {
+            Kind::Method
+        } else {
+            Kind::Other
+        };
+        self.kinds.insert(id, kind);
+        Ok(())
+    }
+
+    fn on_event(&mut self, header: &EventHeader, payload: &[u8]) -> io::Result<()> {
+        match self.kinds.get(&header.metadata_id) {
+            Some(Kind::Sample) => {
+                match self.stacks.get(&header.stack_id) {
+                    Some(ips) if !ips.is_empty() => {
+                        *self
+                            .samples
+                            .entry((header.thread_id, ips.clone()))
+                            .or_default() += 1;
+                    }
+                    _ => self.unknown_stacks += 1,
+                }
+                Ok(())
+            }
+            Some(Kind::Method) => {
+                let mut r = Reader {
+                    buf: payload,
+                    pos: 0,
+                };
+                // The method and module ids come first.
+                r.skip(8 + 8)?;
+                let start = r.u64()?;
+                let size = u64::from(r.u32()?);
+                // The method token and flags.
+                r.skip(4 + 4)?;
+                let namespace = r.utf16()?;
+                let name = r.utf16()?;
+                let name = if namespace.is_empty() {
+                    name
+                } else {
+                    format!("{}.{}", namespace, name)
+                };
+                self.methods.push(Method {
+                    start,
+                    end: start + size,
+                    name,
+                });
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn read_stacks(&mut self, block: &[u8]) -> io::Result<()> {
+        let mut r = Reader { buf: block, pos: 0 };
+        let first = u64::from(r.u32()?);
+        let count = u64::from(r.u32()?);
+        for id in first..first + count {
+            let size = r.u32()? as usize;
+            let ips = r.bytes(size)?;
+            let ips = match self.pointer_size {
+                4 => ips
+                    .chunks_exact(4)
+                    .map(|ip| u64::from(u32::from_le_bytes([ip[0], ip[1], ip[2], ip[3]])))
+                    .collect(),
+                _ => ips
+                    .chunks_exact(8)
+                    .map(|ip| {
+                        let mut bytes = [0; 8];
+                        bytes.copy_from_slice(ip);
+                        u64::from_le_bytes(bytes)
+                    })
+                    .collect(),
+            };
+            self.stacks.insert(id, ips);
+        }
+        Ok(())
+    }
+
+    // Finds the method that an instruction pointer is in.
+    fn method(&self, ip: u64) -> Option<&str> {
+        let i = self.methods.partition_point(|method| method.start <= ip);
+        let method = &self.methods[i.checked_sub(1)?];
+        if ip < method.end {
+            Some(&method.name)
+        } else {
+            None
+        }
+    }
+}
+
+/// Reads the little-endian values of a trace.
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn remaining(&self) -> usize {
+        self.buf.len().saturating_sub(self.pos)
+    }
+
+    fn bytes(&mut self, n: usize) -> io::Result<&'a [u8]> {
+        if n > self.remaining() {
+            return Err(invalid_data("the trace is truncated"));
+        }
+        let bytes = &self.buf[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn skip(&mut self, n: usize) -> io::Result<()> {
+        self.bytes(n).map(|_| ())
+    }
+
+    fn byte(&mut self) -> io::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn fixed<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        let mut bytes = [0; N];
+        bytes.copy_from_slice(self.bytes(N)?);
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        Ok(u16::from_le_bytes(self.fixed()?))
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.fixed()?))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_le_bytes(self.fixed()?))
+    }
+
+    // Reads a LEB128-compressed integer.
+    fn varint(&mut self) -> io::Result<u64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(invalid_data("invalid compressed integer"))
+    }
+
+    // Reads a null-terminated UTF-16 string.
+    fn utf16(&mut self) -> io::Result<String> {
+        let mut units = Vec::new();
+        loop {
+            match self.u16()? {
+                0 => return Ok(String::from_utf16_lossy(&units)),
+                unit => units.push(unit),
+            }
+        }
+    }
+
+    // Reads the type of an object, which is itself an object with a version and a name.
+    fn type_name(&mut self) -> io::Result<&'a str> {
+        if self.byte()? != TAG_BEGIN_PRIVATE_OBJECT || self.byte()? != TAG_NULL_REFERENCE {
+            return Err(invalid_data("invalid nettrace object type"));
+        }
+        // The version and the minimum version of readers.
+        self.skip(4 + 4)?;
+        let len = self.u32()? as usize;
+        let name = std::str::from_utf8(self.bytes(len)?)
+            .map_err(|_| invalid_data("invalid nettrace object type"))?;
+        if self.byte()? != TAG_END_OBJECT {
+            return Err(invalid_data("invalid nettrace object type"));
+        }
+        Ok(name)
+    }
+}
+
+fn invalid_data<E>(error: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let error: Box<dyn std::error::Error + Send + Sync> = error.into();
+    Error::parse("nettrace", error).into()
+}
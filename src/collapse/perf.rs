@@ -1,8 +1,18 @@
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 use std::io::{self, BufRead};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 
 use crate::collapse::common::{self, CollapsePrivate, Occurrences};
+#[cfg(feature = "inline")]
+use crate::collapse::inline::InlineResolver;
+use crate::collapse::jit::JitSymbols;
 use crate::collapse::matcher::is_kernel;
+use crate::collapse::Collapse;
+use crate::folded::{self, Header};
+use crate::split;
+use crate::symbols::demangle;
+use crate::Error;
 
 const TIDY_GENERIC: bool = true;
 const TIDY_JAVA: bool = true;
@@ -33,6 +43,11 @@ enum StackFilter {
 /// `perf` folder configuration options.
 #[derive(Clone, Debug)]
 #[non_exhaustive]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
 pub struct Options {
     /// Annotate JIT functions with a `_[j]` suffix.
     ///
@@ -44,12 +59,62 @@ pub struct Options {
     /// Default is `false`.
     pub annotate_kernel: bool,
 
+    /// Annotate each stack with the category of its leaf frame, like `gc`, `jit`, `kernel` or
+    /// `app`, using [`crate::folded::annotate_category`].
+    ///
+    /// Stacks of the garbage collector and JIT compiler threads of the JVM and .NET are found by
+    /// their thread names, and kernel stacks by the module of their leaf frame.
+    ///
+    /// Default is `false`.
+    pub annotate_categories: bool,
+
+    /// Which mangled symbols to demangle, for example when `perf` was built without demangling
+    /// support or run with `--no-demangle`. Rust symbols that `perf` only partially demangles are
+    /// fixed too.
+    ///
+    /// Default is `demangle::Options::default()`, which demangles all supported manglings.
+    pub demangle: demangle::Options,
+
     /// Only consider samples of the given event type (see `perf list`). If this option is
     /// set to `None`, it will be set to the first encountered event type.
     ///
     /// Default is `None`.
     pub event_filter: Option<String>,
 
+    /// Only fold samples taken at or after this time, in seconds, as `perf script` prints the
+    /// time of each event. Together with `to`, this picks a time range of the profile, like the
+    /// ranges that can be selected in `inferno-heatmap`.
+    ///
+    /// Samples without a time are always folded, and so are `off_cpu` stacks.
+    ///
+    /// Default is `None`.
+    pub from: Option<f64>,
+
+    /// Write a [`Header`] with the event, units and time range of the profile before the
+    /// folded stacks.
+    ///
+    /// Default is `false`.
+    pub header: bool,
+
+    /// When folding stacks with [`Folder::collapse_chronologically`], a gap of more than this
+    /// many seconds between two samples is taken to be idle time: the first sample lasts this
+    /// long, and the rest of the gap is folded as an `[idle]` stack.
+    ///
+    /// Default is `0.01`.
+    pub idle_gap: f64,
+
+    /// Cut stacks deeper than this many frames, counting the root frame of the process name,
+    /// while folding them, with [`folded::truncate_depth`]. Very deep stacks, like those of
+    /// async runtimes or interpreters, are then folded into fewer and shorter lines.
+    ///
+    /// Default is `None`, which keeps all frames.
+    pub max_depth: Option<usize>,
+
+    /// Only fold samples taken before this time, in seconds. See `from`.
+    ///
+    /// Default is `None`.
+    pub to: Option<f64>,
+
     /// Include raw addresses (e.g., `0xbfff0836`) where symbols can't be found.
     ///
     /// Default is `false`.
@@ -66,6 +131,57 @@ pub struct Options {
     /// Default is `false`.
     pub include_tid: bool,
 
+    /// Expand each frame into the chain of functions that were inlined into it (annotated with
+    /// `_[i]`), using the DWARF debug info of the binaries named in the stacks.
+    ///
+    /// This is similar to `perf script --inline`, but works even if the host that recorded the
+    /// profile didn't have the debug info installed.
+    ///
+    /// Default is `false`.
+    #[cfg(feature = "inline")]
+    pub expand_inlines: bool,
+
+    /// Directories searched for binaries with debug info when `expand_inlines` is set, before
+    /// trying the path recorded by perf. A binary is looked for both at its full recorded path
+    /// relative to the directory and by file name alone.
+    ///
+    /// Default is empty.
+    #[cfg(feature = "inline")]
+    pub debug_dirs: Vec<PathBuf>,
+
+    /// Perf map files (`/tmp/perf-<pid>.map`) and jitdump files (`jit-<pid>.dump`) used to
+    /// name `[unknown]` frames, or frames that are only an address, whose address falls within
+    /// JIT-compiled code. Directories, like `/tmp`, stand for the perf map and jitdump files in
+    /// them.
+    ///
+    /// Frames named this way are annotated with `_[j]` when `annotate_jit` is set.
+    ///
+    /// This is useful when `perf script` was run without access to the files written by the
+    /// JIT runtime (Node.js with `--perf-basic-prof`, the JVM with perf-map-agent, .NET with
+    /// `DOTNET_PerfMapEnabled`, ...).
+    ///
+    /// Default is empty.
+    pub jit_maps: Vec<PathBuf>,
+
+    /// Weight each sample by the event period that `perf script` prints before the event name
+    /// (included by default, or with `-F +period`), rather than counting every sample once.
+    ///
+    /// This gives correctly proportioned flamegraphs for recordings with adaptive sampling
+    /// frequency or `--count` events. Samples without a period count once either way.
+    ///
+    /// Default is `true`.
+    pub period_weights: bool,
+
+    /// Fold `sched:sched_switch` events into off-CPU stacks, weighted by the time (in
+    /// microseconds) each thread spent switched out before it was scheduled again.
+    ///
+    /// Use with the output of `perf record -e sched:sched_switch -g` followed by `perf script`.
+    /// Other events are ignored, as are threads that never get switched back in. Since this needs
+    /// to follow threads across events, the input is always processed on a single thread.
+    ///
+    /// Default is `false`.
+    pub off_cpu: bool,
+
     /// The number of threads to use.
     ///
     /// Default is the number of logical cores on your machine.
@@ -83,11 +199,25 @@ impl Default for Options {
         Self {
             annotate_jit: false,
             annotate_kernel: false,
+            annotate_categories: false,
+            demangle: demangle::Options::default(),
             event_filter: None,
+            from: None,
+            header: false,
+            idle_gap: 0.01,
+            max_depth: None,
+            to: None,
             include_addrs: false,
             include_pid: false,
             include_tid: false,
+            #[cfg(feature = "inline")]
+            expand_inlines: false,
+            #[cfg(feature = "inline")]
+            debug_dirs: Vec::default(),
+            jit_maps: Vec::default(),
             nthreads: *common::DEFAULT_NTHREADS,
+            off_cpu: false,
+            period_weights: true,
             skip_after: Vec::default(),
         }
     }
@@ -113,9 +243,22 @@ pub struct Folder {
     ///   different event types, such as instructions and cycles, would produce misleading results.
     event_filter: Option<String>,
 
+    /// The pid (or, if perf didn't record it, the tid) of the current event.
+    event_pid: Option<u32>,
+
+    /// The time (in nanoseconds) of the current event, when folding stacks chronologically.
+    event_time: Option<u64>,
+
     /// All lines until the next empty line are stack lines.
     in_event: bool,
 
+    /// Expands frames into their inlined functions, shared between threads.
+    #[cfg(feature = "inline")]
+    inline_resolver: Option<Arc<InlineResolver>>,
+
+    /// Symbols loaded from `Options::jit_maps`, shared between threads.
+    jit_symbols: Option<Arc<JitSymbols>>,
+
     /// The number of stacks per job to send to the threadpool.
     nstacks_per_job: usize,
 
@@ -124,15 +267,39 @@ pub struct Folder {
     /// Called pname after original stackcollapse-perf source.
     pname: String,
 
+    /// The `sched:sched_switch` data of the current event, when folding off-CPU stacks.
+    sched_switch: Option<SchedSwitch>,
+
     /// Whether to skip stack lines in this event.
     stack_filter: StackFilter,
 
     /// Function entries on the stack in this entry thus far.
     stack: VecDeque<String>,
 
+    /// The strings of the frames of earlier stacks, which are reused for the frames of the next
+    /// ones so that frames don't need allocations of their own.
+    spare_frames: Vec<String>,
+
+    /// Whether the leaf frame of the stack in this entry is in the kernel.
+    leaf_in_kernel: bool,
+
     /// period of current event
     period: Option<usize>,
 
+    /// What this thread has seen of the profile since it was last added to `seen_total`.
+    seen: Seen,
+
+    /// What all threads have seen of the profile, for its header.
+    seen_total: Arc<Mutex<Seen>>,
+
+    /// When (in nanoseconds) each thread that is currently off-CPU was switched out, and its
+    /// folded stack at that point.
+    switched_out: HashMap<u32, (u64, String)>,
+
+    /// The time (in nanoseconds) and folded stack of every sample so far, when folding stacks
+    /// chronologically.
+    timeline: Option<Vec<(u64, String)>>,
+
     // Options...
     opt: Options,
 }
@@ -146,12 +313,28 @@ impl From<Options> for Folder {
         Self {
             cache_line: Vec::default(),
             event_filter: opt.event_filter.clone(),
+            event_pid: None,
+            event_time: None,
             in_event: false,
+            #[cfg(feature = "inline")]
+            inline_resolver: if opt.expand_inlines {
+                Some(Arc::new(InlineResolver::new(opt.debug_dirs.clone())))
+            } else {
+                None
+            },
+            jit_symbols: None,
             nstacks_per_job: common::DEFAULT_NSTACKS_PER_JOB,
             pname: String::default(),
+            sched_switch: None,
             stack_filter: StackFilter::Keep,
             stack: VecDeque::default(),
+            spare_frames: Vec::new(),
+            leaf_in_kernel: false,
             period: None,
+            seen: Seen::default(),
+            seen_total: Arc::default(),
+            switched_out: HashMap::default(),
+            timeline: None,
             opt,
         }
     }
@@ -168,8 +351,17 @@ impl CollapsePrivate for Folder {
     where
         R: io::BufRead,
     {
-        // If user has provided an event filter, do nothing...
-        if self.event_filter.is_some() {
+        self.seen = Seen::default();
+        *self.seen_total.lock().unwrap() = Seen::default();
+
+        // Load the JIT symbols up front so all worker threads can share them.
+        if !self.opt.jit_maps.is_empty() && self.jit_symbols.is_none() {
+            self.jit_symbols = Some(Arc::new(JitSymbols::from_files(&self.opt.jit_maps)?));
+        }
+
+        // If user has provided an event filter, or we only look at sched_switch events, do
+        // nothing...
+        if self.event_filter.is_some() || self.opt.off_cpu {
             return Ok(());
         }
 
@@ -207,11 +399,39 @@ impl CollapsePrivate for Folder {
 
         // Reset state...
         self.in_event = false;
+        self.sched_switch = None;
         self.stack_filter = StackFilter::Keep;
         self.stack.clear();
+        self.switched_out.clear();
+        self.seen_total.lock().unwrap().add(self.seen);
+        self.seen = Seen::default();
         Ok(())
     }
 
+    fn header(&self) -> Option<Header> {
+        if !self.opt.header {
+            return None;
+        }
+        let mut seen = *self.seen_total.lock().unwrap();
+        seen.add(self.seen);
+
+        let mut header = Header::for_tool("inferno-collapse-perf");
+        if self.opt.off_cpu {
+            header.event = Some("sched:sched_switch".to_string());
+            header.units = Some("microseconds".to_string());
+        } else {
+            header.event = self.event_filter.clone();
+            header.units = if seen.periods {
+                Some(self.event_filter.as_deref().unwrap_or("events").to_string())
+            } else {
+                Some("samples".to_string())
+            };
+        }
+        header.start = seen.start;
+        header.end = seen.end;
+        Some(header)
+    }
+
     fn is_applicable(&mut self, input: &str) -> Option<bool> {
         // Check if the input has an event line followed by a stack line.
 
@@ -261,12 +481,24 @@ impl CollapsePrivate for Folder {
         Self {
             cache_line: self.cache_line.clone(),
             event_filter: self.event_filter.clone(),
+            event_pid: None,
+            event_time: None,
             in_event: false,
+            #[cfg(feature = "inline")]
+            inline_resolver: self.inline_resolver.clone(),
+            jit_symbols: self.jit_symbols.clone(),
             nstacks_per_job: self.nstacks_per_job,
             pname: String::new(),
+            sched_switch: None,
             stack_filter: StackFilter::Keep,
             stack: VecDeque::default(),
+            spare_frames: Vec::new(),
+            leaf_in_kernel: false,
             period: None,
+            seen: Seen::default(),
+            seen_total: Arc::clone(&self.seen_total),
+            switched_out: HashMap::default(),
+            timeline: None,
             opt: self.opt.clone(),
         }
     }
@@ -280,7 +512,13 @@ impl CollapsePrivate for Folder {
     }
 
     fn nthreads(&self) -> usize {
-        self.opt.nthreads
+        // Off-CPU time is only known once a thread is switched back in, which may happen in any
+        // later event, so events can't be processed independently.
+        if self.opt.off_cpu {
+            1
+        } else {
+            self.opt.nthreads
+        }
     }
 
     fn set_nthreads(&mut self, n: usize) {
@@ -289,6 +527,101 @@ impl CollapsePrivate for Folder {
 }
 
 impl Folder {
+    /// Collapses the contents of the provided `reader`, but instead of writing all folded stack
+    /// lines to a single writer, writes the stacks of each process (or thread, see
+    /// [`Options::include_pid`] and [`Options::include_tid`]) to a writer of its own.
+    ///
+    /// `writer_for` is called with the root frame of a stack (the comm, followed by the pid and
+    /// tid if they are included) the first time a stack of that process or thread is seen.
+    pub fn collapse_per_thread<R, W, F>(&mut self, reader: R, writer_for: F) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+        F: FnMut(&str) -> io::Result<W>,
+    {
+        let mut folded = Vec::new();
+        Collapse::collapse(self, reader, &mut folded)?;
+        split::from_lines(
+            split::Options::default(),
+            String::from_utf8_lossy(&folded).lines(),
+            writer_for,
+        )
+    }
+
+    /// Collapses the contents of the provided `reader` into stacks in the order they were
+    /// sampled, for flame charts (see `flamegraph::Options::flame_chart`), instead of merging
+    /// the samples of each stack.
+    ///
+    /// Each sample lasts until the next one, and consecutive samples of the same stack are
+    /// folded together, so the counts are in nanoseconds. Gaps of more than
+    /// [`Options::idle_gap`] between samples are folded as `[idle]` stacks, and the last sample
+    /// lasts as long as the one before it. Times are most precise with `perf script --ns`.
+    ///
+    /// Samples without a time are left out. The input is always processed on a single thread,
+    /// and [`Options::off_cpu`] isn't supported.
+    pub fn collapse_chronologically<R, W>(&mut self, mut reader: R, mut writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        if self.opt.off_cpu {
+            return Err(io::Error::from(Error::InvalidOptions(
+                "off-CPU stacks can't be folded chronologically".to_string(),
+            )));
+        }
+
+        let mut occurrences = Occurrences::new(1);
+        self.timeline = Some(Vec::new());
+        let result = self
+            .pre_process(&mut reader, &mut occurrences)
+            .and_then(|()| self.collapse_single_threaded(reader, &mut occurrences));
+        let mut timeline = self.timeline.take().unwrap_or_default();
+        result?;
+
+        if let Some(mut header) = self.header() {
+            header.units = Some("nanoseconds".to_string());
+            header.write(&mut writer)?;
+        }
+
+        // perf script sorts events by time, but only if it recorded the events of every CPU.
+        timeline.sort_by_key(|&(time, _)| time);
+        let idle_gap = (self.opt.idle_gap.max(0.0) * 1e9) as u64;
+        let mut run: Option<(&str, u64)> = None;
+        let mut last_duration = idle_gap;
+        for (i, (time, stack)) in timeline.iter().enumerate() {
+            let (duration, idle) = match timeline.get(i + 1) {
+                Some((next, _)) => {
+                    let gap = next - time;
+                    (gap.min(idle_gap), gap.saturating_sub(idle_gap))
+                }
+                None => (last_duration, 0),
+            };
+            last_duration = duration;
+
+            match run {
+                Some((run_stack, ref mut run_duration)) if run_stack == stack.as_str() => {
+                    *run_duration += duration;
+                }
+                _ => {
+                    if let Some((run_stack, run_duration)) = run {
+                        writeln!(writer, "{} {}", run_stack, run_duration)?;
+                    }
+                    run = Some((stack.as_str(), duration));
+                }
+            }
+            if idle > 0 {
+                if let Some((run_stack, run_duration)) = run.take() {
+                    writeln!(writer, "{} {}", run_stack, run_duration)?;
+                }
+                writeln!(writer, "[idle] {}", idle)?;
+            }
+        }
+        if let Some((run_stack, run_duration)) = run {
+            writeln!(writer, "{} {}", run_stack, run_duration)?;
+        }
+        Ok(())
+    }
+
     /// Processes a stack. On success, returns `true` if at end of data; `false` otherwise.
     fn process_single_stack<R>(
         &mut self,
@@ -302,7 +635,7 @@ impl Folder {
         loop {
             line_buffer.clear();
             if reader.read_until(0x0A, line_buffer)? == 0 {
-                if !self.stack.is_empty() {
+                if !self.stack.is_empty() || self.sched_switch.is_some() {
                     self.after_event(occurrences);
                 }
                 return Ok(true);
@@ -333,8 +666,9 @@ impl Folder {
         let mut all_digits = false;
         let mut last_was_space = false;
         let mut contains_slash_at = None;
-        for (idx, c) in line.char_indices() {
-            if c == ' ' {
+        // the bytes that matter are all ASCII, so this doesn't need to decode characters
+        for (idx, &c) in line.as_bytes().iter().enumerate() {
+            if c == b' ' {
                 if all_digits && !last_was_space {
                     // found an all-digit word
                     let (pid, tid) = if let Some(slash) = contains_slash_at {
@@ -350,7 +684,7 @@ impl Folder {
                 }
                 word_start = idx + 1;
                 all_digits = true;
-            } else if c == '/' {
+            } else if c == b'/' {
                 if all_digits {
                     contains_slash_at = Some(idx);
                 }
@@ -360,7 +694,7 @@ impl Folder {
                 all_digits = false;
                 contains_slash_at = None;
             }
-            last_was_space = c == ' ';
+            last_was_space = c == b' ';
         }
         None
     }
@@ -379,6 +713,16 @@ impl Folder {
         self.in_event = true;
 
         if let Some((comm, pid, tid, end)) = Self::event_line_parts(line) {
+            if self.opt.off_cpu {
+                self.sched_switch = SchedSwitch::from_event(&line[end..], tid);
+                if self.sched_switch.is_none() {
+                    self.stack_filter = StackFilter::Skip;
+                }
+                self.set_pname(comm, pid, tid);
+                self.see_time(&line[end..]);
+                return;
+            }
+
             let mut by_colons = line[end..].splitn(3, ':').skip(1);
             let event = by_colons.next().and_then(|period_and_event| {
                 let mut it = period_and_event.rsplit(' ');
@@ -401,6 +745,15 @@ impl Folder {
                 }
             }
 
+            if !self.in_time_range(&line[end..]) {
+                self.stack_filter = StackFilter::Skip;
+            } else {
+                self.see_time(&line[end..]);
+            }
+            if self.timeline.is_some() {
+                self.event_time = Self::event_time_str(&line[end..]).and_then(parse_timestamp);
+            }
+
             // some event lines _include_ a stack line if the stack only has one frame.
             // in that case, the event will be followed by the stack.
             let single_stack = if let Some(post_event) = by_colons.next() {
@@ -428,17 +781,7 @@ impl Folder {
                 None
             };
 
-            // XXX: re-use existing memory in pname if possible
-            self.pname = comm.replace(' ', "_");
-            if self.opt.include_tid {
-                self.pname.push('-');
-                self.pname.push_str(pid);
-                self.pname.push('/');
-                self.pname.push_str(tid);
-            } else if self.opt.include_pid {
-                self.pname.push('-');
-                self.pname.push_str(pid);
-            }
+            self.set_pname(comm, pid, tid);
 
             if let Some(stack_line) = single_stack {
                 self.on_stack_line(stack_line);
@@ -450,11 +793,65 @@ impl Folder {
         }
     }
 
+    // checks the time of an event against `from` and `to`, given what follows its pid/tid, like:
+    //
+    //     [002] 6544038.708352: cpu-clock:
+    //     4794564.109216: cycles:
+    fn in_time_range(&self, event: &str) -> bool {
+        if self.opt.from.is_none() && self.opt.to.is_none() {
+            return true;
+        }
+        match Self::event_time(event) {
+            Some(time) => {
+                self.opt.from.map_or(true, |from| time >= from)
+                    && self.opt.to.map_or(true, |to| time < to)
+            }
+            None => true,
+        }
+    }
+
+    // records the time of a folded event for the header, if there is one.
+    fn see_time(&mut self, event: &str) {
+        if self.opt.header {
+            if let Some(time) = Self::event_time(event) {
+                self.seen.time(time);
+            }
+        }
+    }
+
+    fn event_time(event: &str) -> Option<f64> {
+        Self::event_time_str(event).and_then(|time| time.parse::<f64>().ok())
+    }
+
+    fn event_time_str(event: &str) -> Option<&str> {
+        event
+            .split_once(':')
+            .and_then(|(head, _)| head.rsplit(' ').next())
+    }
+
+    fn set_pname(&mut self, comm: &str, pid: &str, tid: &str) {
+        self.event_pid = pid.parse().or_else(|_| tid.parse()).ok();
+
+        // XXX: re-use existing memory in pname if possible
+        self.pname = comm.replace(' ', "_");
+        if self.opt.include_tid {
+            self.pname.push('-');
+            self.pname.push_str(pid);
+            self.pname.push('/');
+            self.pname.push_str(tid);
+        } else if self.opt.include_pid {
+            self.pname.push('-');
+            self.pname.push_str(pid);
+        }
+    }
+
     fn stack_line_parts(line: &str) -> Option<(&str, &str, &str)> {
-        let mut line = line.trim_start().splitn(2, ' ');
-        let pc = line.next()?.trim_end();
-        let mut line = line.next()?.rsplitn(2, ' ');
-        let mut module = line.next()?;
+        let line = line.trim_start();
+        let pc_end = memchr::memchr(b' ', line.as_bytes())?;
+        let pc = line[..pc_end].trim_end();
+        let line = &line[pc_end + 1..];
+        let module_start = memchr::memrchr(b' ', line.as_bytes())?;
+        let mut module = &line[module_start + 1..];
 
         // Module should always be wrapped in (), so remove those if they exist.
         // We first check for their existence because it's possible this is being
@@ -466,7 +863,7 @@ impl Folder {
         }
         module = &module[1..(module.len() - 1)];
 
-        let rawfunc = match line.next()?.trim() {
+        let rawfunc = match line[..module_start].trim() {
             // Sometimes there are two spaces between the pc and the (, like:
             //     7f1e2215d058  (/lib/x86_64-linux-gnu/libc-2.15.so)
             // In order to match the perl version, the rawfunc should be " ", and not "".
@@ -496,6 +893,12 @@ impl Folder {
         }
 
         if let Some((pc, mut rawfunc, module)) = Self::stack_line_parts(line) {
+            // Look up inlined functions while we still have the symbol offset
+            #[cfg(feature = "inline")]
+            let inlined = self.inlined_functions(pc, rawfunc, module);
+            #[cfg(not(feature = "inline"))]
+            let inlined: [String; 0] = [];
+
             // Strip off symbol offsets
             if let Some(offset) = rawfunc.rfind("+0x") {
                 let end = &rawfunc[(offset + 3)..];
@@ -511,21 +914,38 @@ impl Folder {
                 return;
             }
 
-            // perf mostly demangles Rust symbols,
-            // but this will fix the things it gets wrong
-            let rawfunc = common::fix_partially_demangled_rust_symbol(rawfunc);
+            // perf couldn't resolve this frame; maybe it's JIT-compiled code we know about
+            let jit_func = if is_unresolved(rawfunc) {
+                self.jit_symbol(pc)
+            } else {
+                None
+            };
+            let rawfunc = jit_func.as_deref().unwrap_or(rawfunc);
+
+            if self.stack.is_empty() {
+                self.leaf_in_kernel = is_kernel(module);
+            }
+
+            // perf mostly demangles symbols, but this also fixes the Rust symbols it gets wrong
+            let rawfunc = demangle::demangle(rawfunc, &self.opt.demangle);
 
             // Support Java inlining by splitting on "->". After the first func, the
             // rest are annotated with "_[i]" to mark them as inlined.
             // See https://github.com/brendangregg/FlameGraph/pull/89.
-            for func in rawfunc.split("->") {
-                let mut func = with_module_fallback(module, func, pc, self.opt.include_addrs);
+            // Functions found in the debug info are annotated the same way.
+            let funcs = rawfunc
+                .split("->")
+                .chain(inlined.iter().map(String::as_str));
+            for name in funcs {
+                let mut func = self.spare_frames.pop().unwrap_or_default();
+                func.clear();
+                with_module_fallback(&mut func, module, name, pc, self.opt.include_addrs);
                 if TIDY_GENERIC {
-                    func = tidy_generic(func);
+                    tidy_generic(&mut func);
                 }
 
                 if TIDY_JAVA && self.pname.starts_with("java") {
-                    func = tidy_java(func);
+                    tidy_java(&mut func);
                 }
 
                 // Annotations
@@ -547,7 +967,8 @@ impl Folder {
                 } else if self.opt.annotate_kernel && is_kernel(module) {
                     func.push_str("_[k]"); // kernel
                 } else if self.opt.annotate_jit
-                    && ((module.starts_with("/tmp/perf-") && module.ends_with(".map"))
+                    && (jit_func.is_some()
+                        || (module.starts_with("/tmp/perf-") && module.ends_with(".map"))
                         || (module.contains("/jitted-") && module.ends_with(".so")))
                     && !func.contains("_[j]")
                 {
@@ -574,7 +995,36 @@ impl Folder {
         }
     }
 
+    #[cfg(feature = "inline")]
+    fn inlined_functions(&self, pc: &str, symbol: &str, module: &str) -> Vec<String> {
+        match &self.inline_resolver {
+            Some(resolver) => resolver.inlined_functions(pc, symbol, module),
+            None => Vec::new(),
+        }
+    }
+
+    fn jit_symbol(&self, pc: &str) -> Option<String> {
+        let symbols = self.jit_symbols.as_ref()?;
+        let addr = u64::from_str_radix(pc, 16).ok()?;
+        symbols.lookup(self.event_pid, addr).map(str::to_string)
+    }
+
     fn after_event(&mut self, occurrences: &mut Occurrences) {
+        // the thread being switched in is done waiting, so count its off-CPU time
+        if let Some(SchedSwitch {
+            time,
+            next: Some(next),
+            ..
+        }) = self.sched_switch
+        {
+            if let Some((since, stack_str)) = self.switched_out.remove(&next) {
+                let micros = time.saturating_sub(since) / 1000;
+                if micros > 0 {
+                    occurrences.insert_or_add(stack_str, micros as usize);
+                }
+            }
+        }
+
         // end of stack, so emit stack entry
         if !self.stack.is_empty() {
             // allocate a string that is long enough to hold the entire stack string
@@ -591,28 +1041,189 @@ impl Folder {
             for e in self.stack.drain(..) {
                 stack_str.push_str(&e);
                 stack_str.push(';');
+                self.spare_frames.push(e);
             }
 
             // self.stack is not empty, therefore stack_str has at least one frame followed by ';'
             stack_str.pop();
 
-            // count it!
-            occurrences.insert_or_add(stack_str, self.period.unwrap_or(1));
+            if let Some(max_depth) = self.opt.max_depth {
+                folded::truncate_depth(&mut stack_str, max_depth);
+            }
+
+            if self.opt.annotate_categories {
+                let category = thread_category(&self.pname).unwrap_or(if self.leaf_in_kernel {
+                    "kernel"
+                } else {
+                    "app"
+                });
+                folded::annotate_category(&mut stack_str, category);
+            }
+
+            if let Some(SchedSwitch {
+                time,
+                prev: Some(prev),
+                ..
+            }) = self.sched_switch
+            {
+                // we'll know how long it was off-CPU once it's switched back in
+                self.switched_out.insert(prev, (time, stack_str));
+            } else if let Some(ref mut timeline) = self.timeline {
+                // samples last until the next one, so their periods don't matter
+                if let Some(time) = self.event_time {
+                    timeline.push((time, stack_str));
+                }
+            } else if !self.opt.off_cpu {
+                // count it!
+                let weight = match self.period {
+                    Some(period) if self.opt.period_weights => {
+                        self.seen.periods = true;
+                        period
+                    }
+                    _ => 1,
+                };
+                occurrences.insert_or_add(stack_str, weight);
+            }
         }
 
         // reset for the next event
         self.in_event = false;
+        self.sched_switch = None;
         self.stack_filter = StackFilter::Keep;
         self.stack.clear();
+        self.leaf_in_kernel = false;
         self.period = None;
+        self.event_time = None;
+    }
+}
+
+/// The category of the stacks of a thread that is known by its name, like the garbage collector
+/// and JIT compiler threads of the JVM and .NET.
+///
+/// Thread names are cut to 15 bytes by Linux, and spaces in them are replaced by underscores in
+/// `pname`, so the names are matched by their starts.
+fn thread_category(pname: &str) -> Option<&'static str> {
+    const GC: &[&str] = &[
+        "GC_Thread",
+        "G1_",
+        "ZGC",
+        "Shenandoah",
+        ".NET_BGC",
+        ".NET_Server_GC",
+    ];
+    const JIT: &[&str] = &["C1_CompilerThre", "C2_CompilerThre", ".NET_Tiered_Com"];
+    if GC.iter().any(|prefix| pname.starts_with(prefix)) {
+        Some("gc")
+    } else if JIT.iter().any(|prefix| pname.starts_with(prefix)) {
+        Some("jit")
+    } else {
+        None
+    }
+}
+
+/// What was seen of a profile while folding it, for its [`Header`].
+#[derive(Clone, Copy, Debug, Default)]
+struct Seen {
+    /// The time of the first folded event.
+    start: Option<f64>,
+
+    /// The time of the last folded event.
+    end: Option<f64>,
+
+    /// Whether any sample was weighted by its period.
+    periods: bool,
+}
+
+impl Seen {
+    fn time(&mut self, time: f64) {
+        self.start = Some(self.start.map_or(time, |start| start.min(time)));
+        self.end = Some(self.end.map_or(time, |end| end.max(time)));
+    }
+
+    fn add(&mut self, other: Seen) {
+        if let Some(start) = other.start {
+            self.time(start);
+        }
+        if let Some(end) = other.end {
+            self.time(end);
+        }
+        self.periods = self.periods || other.periods;
+    }
+}
+
+/// The parts of a `sched:sched_switch` event needed to compute off-CPU time.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct SchedSwitch {
+    /// When the switch happened, in nanoseconds.
+    time: u64,
+
+    /// The thread being switched out.
+    prev: Option<u32>,
+
+    /// The thread being switched in.
+    next: Option<u32>,
+}
+
+impl SchedSwitch {
+    // parses what follows the pid/tid of an event line, like:
+    //
+    //     [000] 100.000000: sched:sched_switch: prev_comm=app prev_pid=1001 prev_prio=120 prev_state=S ==> next_comm=swapper/0 next_pid=0 next_prio=120
+    //     [000] 100.000000: sched:sched_switch: app:1001 [120] S ==> swapper/0:0 [120]
+    //
+    // the latter being how newer versions of perf print the event.
+    fn from_event(line: &str, tid: &str) -> Option<Self> {
+        let (head, args) = line.split_once(" sched:sched_switch:")?;
+        let time = head.trim_end().strip_suffix(':')?.rsplit(' ').next()?;
+        let time = parse_timestamp(time)?;
+        let (prev, next) = args.split_once("==>")?;
+        Some(Self {
+            time,
+            prev: sched_switch_tid(prev, "prev_pid=").or_else(|| tid.parse().ok()),
+            next: sched_switch_tid(next, "next_pid="),
+        })
+    }
+}
+
+fn sched_switch_tid(task: &str, key: &str) -> Option<u32> {
+    if let Some(i) = task.find(key) {
+        return task[(i + key.len())..].split(' ').next()?.parse().ok();
     }
+    // comm:tid [prio] state
+    let task = task.trim();
+    let task = &task[..task.rfind(" [")?];
+    task.rsplit(':').next()?.parse().ok()
+}
+
+// parses a perf timestamp (seconds with microsecond or nanosecond precision) into nanoseconds
+fn parse_timestamp(s: &str) -> Option<u64> {
+    let (secs, frac) = s.split_once('.').unwrap_or((s, ""));
+    let mut nanos = secs.parse::<u64>().ok()?;
+    for i in 0..9 {
+        let digit = match frac.as_bytes().get(i) {
+            Some(c) => u64::from(char::from(*c).to_digit(10)?),
+            None => 0,
+        };
+        nanos = nanos * 10 + digit;
+    }
+    Some(nanos)
 }
 
 // massage function name to be nicer
 // NOTE: ignoring https://github.com/jvm-profiling-tools/perf-map-agent/pull/35
-fn with_module_fallback(module: &str, func: &str, pc: &str, include_addrs: bool) -> String {
+// writes the name into `res`, which is reused between frames
+// Whether perf couldn't name the function of a frame: it's `[unknown]`, empty, or just the
+// address, like `0x7f0000001010`.
+fn is_unresolved(rawfunc: &str) -> bool {
+    match rawfunc.strip_prefix("0x") {
+        Some(addr) => !addr.is_empty() && addr.bytes().all(|b| b.is_ascii_hexdigit()),
+        None => rawfunc == "[unknown]" || rawfunc == " ",
+    }
+}
+
+fn with_module_fallback(res: &mut String, module: &str, func: &str, pc: &str, include_addrs: bool) {
     if func != "[unknown]" {
-        return func.to_string();
+        res.push_str(func);
+        return;
     }
 
     // try to use part of module name as function if unknown
@@ -620,17 +1231,16 @@ fn with_module_fallback(module: &str, func: &str, pc: &str, include_addrs: bool)
         ("[unknown]", true) => "unknown",
         ("[unknown]", false) => {
             // no need to process this further
-            return func.to_string();
+            res.push_str(func);
+            return;
         }
         (module, _) => {
             // use everything following last / of module as function name
-            &module[module.rfind('/').map(|i| i + 1).unwrap_or(0)..]
+            let bytes = module.as_bytes();
+            &module[memchr::memrchr(b'/', bytes).map(|i| i + 1).unwrap_or(0)..]
         }
     };
 
-    // output string is a bit longer than rawfunc but not much
-    let mut res = String::with_capacity(func.len() + 12);
-
     res.push('[');
     res.push_str(func);
     if include_addrs {
@@ -640,12 +1250,13 @@ fn with_module_fallback(module: &str, func: &str, pc: &str, include_addrs: bool)
     } else {
         res.push(']');
     }
-
-    res
 }
 
-fn tidy_generic(mut func: String) -> String {
-    func = func.replace(';', ":");
+fn tidy_generic(func: &mut String) {
+    // most names have no semicolons, so only those that do are copied
+    if memchr::memchr(b';', func.as_bytes()).is_some() {
+        *func = func.replace(';', ":");
+    }
     // remove argument list from function name, but _don't_ remove:
     //
     //  - Go method names like "net/http.(*Client).Do".
@@ -690,10 +1301,9 @@ fn tidy_generic(mut func: String) -> String {
 
     // The perl version here strips ' and "; we don't do that.
     // see https://github.com/brendangregg/FlameGraph/commit/817c6ea3b92417349605e5715fe6a7cb8cbc9776
-    func
 }
 
-fn tidy_java(mut func: String) -> String {
+fn tidy_java(func: &mut String) {
     // along with tidy_generic converts the following:
     //     Lorg/mozilla/javascript/ContextFactory;.call(Lorg/mozilla/javascript/ContextAction;)Ljava/lang/Object;
     //     Lorg/mozilla/javascript/ContextFactory;.call(Lorg/mozilla/javascript/C
@@ -702,11 +1312,9 @@ fn tidy_java(mut func: String) -> String {
     //     org/mozilla/javascript/ContextFactory:.call
     //     org/mozilla/javascript/ContextFactory:.call
     //     org/mozilla/javascript/MemberBox:.init
-    if func.starts_with('L') && func.contains('/') {
+    if func.starts_with('L') && memchr::memchr(b'/', func.as_bytes()).is_some() {
         func.remove(0);
     }
-
-    func
 }
 
 #[cfg(test)]
@@ -751,7 +1359,9 @@ mod tests {
         ];
 
         for (input, expected) in test_expectations.iter() {
-            assert_eq!(&tidy_generic(input.to_string()), expected);
+            let mut func = input.to_string();
+            tidy_generic(&mut func);
+            assert_eq!(&func, expected);
         }
     }
 
@@ -888,11 +1498,29 @@ mod tests {
             let options = Options {
                 annotate_jit: rng.gen(),
                 annotate_kernel: rng.gen(),
+                annotate_categories: rng.gen(),
+                demangle: if rng.gen() {
+                    demangle::Options::default()
+                } else {
+                    demangle::Options::none()
+                },
                 event_filter: None,
+                from: None,
+                header: rng.gen(),
+                idle_gap: 0.01,
+                max_depth: None,
+                to: None,
                 include_addrs: rng.gen(),
                 include_pid: rng.gen(),
                 include_tid: rng.gen(),
+                #[cfg(feature = "inline")]
+                expand_inlines: false,
+                #[cfg(feature = "inline")]
+                debug_dirs: Vec::default(),
+                jit_maps: Vec::default(),
                 nthreads: rng.gen_range(2..=32),
+                off_cpu: false,
+                period_weights: rng.gen(),
                 skip_after: Vec::default(),
             };
 
@@ -0,0 +1,205 @@
+use std::io::{self, BufRead};
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+
+/// `pmp` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct Options {
+    /// Add the thread of each stack as its root frame: its name if the backtrace has one, and
+    /// otherwise its id, like `Thread (1234)`.
+    ///
+    /// Default is `false`.
+    pub include_threads: bool,
+}
+
+/// A stack collapser for "poor man's profiler" backtraces: repeated snapshots of the threads of
+/// a process taken with `gdb -batch -ex "thread apply all bt"` or `eu-stack -p`.
+///
+/// The snapshots are simply concatenated, and every backtrace in them counts as one sample of its
+/// stack. Function arguments and source locations are left out, and functions without symbols
+/// are named after their module, like `[libc.so.6]`, if the backtrace has it.
+///
+/// To construct one, either use `pmp::Folder::default()` or create an [`Options`] and use
+/// `pmp::Folder::from(options)`.
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, reader: R, writer: W) -> io::Result<()>
+    where
+        R: BufRead,
+        W: io::Write,
+    {
+        let mut occurrences = Occurrences::new(1);
+        let mut thread = None;
+        // The frames of the current backtrace, leaf first.
+        let mut frames = Vec::new();
+
+        for line in reader.lines() {
+            let line = line?;
+            let trimmed = line.trim();
+            if let Some(frame) = frame_line(trimmed) {
+                // A backtrace without a thread header may follow another one right away.
+                if trimmed.starts_with("#0 ") {
+                    self.fold(thread.as_deref(), &mut frames, &mut occurrences);
+                }
+                frames.push(frame_name(frame));
+                continue;
+            }
+
+            // Source lines of `eu-stack -s` are indented under their frames.
+            if !frames.is_empty() && line.starts_with(char::is_whitespace) && !trimmed.is_empty() {
+                continue;
+            }
+            self.fold(thread.as_deref(), &mut frames, &mut occurrences);
+            if let Some(name) = thread_header(trimmed) {
+                thread = Some(name);
+            } else if trimmed.starts_with("PID ") {
+                // Each `eu-stack` snapshot starts with its process.
+                thread = None;
+            }
+        }
+        self.fold(thread.as_deref(), &mut frames, &mut occurrences);
+
+        occurrences.write_and_clear(writer)
+    }
+
+    /// Check for the thread headers and frames of gdb and eu-stack backtraces
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        // gdb prints other things when it attaches, like the current frame and its source line.
+        let found = input.lines().map(str::trim).any(|line| {
+            thread_header(line).is_some() || (line.starts_with("#0 ") && frame_line(line).is_some())
+        });
+        if found {
+            Some(true)
+        } else {
+            None
+        }
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+impl Folder {
+    fn fold(&self, thread: Option<&str>, frames: &mut Vec<String>, occurrences: &mut Occurrences) {
+        if frames.is_empty() {
+            return;
+        }
+        let mut stack = Vec::with_capacity(frames.len() + 1);
+        if self.opt.include_threads {
+            if let Some(thread) = thread {
+                stack.push(thread.to_string());
+            }
+        }
+        stack.extend(frames.drain(..).rev());
+        occurrences.insert_or_add(stack.join(";"), 1);
+    }
+}
+
+// Returns what follows the number of a frame line like `#3  0x00007f... in main () at app.c:5`.
+fn frame_line(line: &str) -> Option<&str> {
+    let rest = line.strip_prefix('#')?;
+    let (number, frame) = rest.split_once(char::is_whitespace)?;
+    if number.is_empty() || !number.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some(frame.trim())
+}
+
+// Names a frame like `0x00007f... in poll (fds=0x1, nfds=1) at poll.c:29` from gdb, or
+// `0x00007f... __poll - /lib64/libc.so.6` from eu-stack.
+fn frame_name(frame: &str) -> String {
+    let (address, rest) = match frame.split_once(char::is_whitespace) {
+        Some((address, rest)) if address.starts_with("0x") => (Some(address), rest.trim()),
+        _ if frame.starts_with("0x") => (Some(frame), ""),
+        _ => (None, frame),
+    };
+
+    // gdb frames have the function after `in`, except for inlined frames that have no address.
+    if let Some(function) = rest.strip_prefix("in ") {
+        let (name, location) = split_arguments(function);
+        if name != "??" {
+            return name.to_string();
+        }
+        return match location.rsplit_once(" from ") {
+            Some((_, module)) => module_frame(module),
+            None => "[unknown]".to_string(),
+        };
+    }
+    if address.is_none() {
+        let (name, _) = split_arguments(rest);
+        return name.to_string();
+    }
+
+    // eu-stack frames have the function, if it is known, and with `-m` the module after ` - `.
+    let (function, module) = match rest.split_once(" - ") {
+        Some((function, module)) => (function.trim(), Some(module.trim())),
+        None if rest.starts_with("- ") => ("", Some(rest[2..].trim())),
+        None => (rest, None),
+    };
+    match (function, module) {
+        ("" | "??", Some(module)) => module_frame(module),
+        ("" | "??", None) => "[unknown]".to_string(),
+        (function, _) => function.to_string(),
+    }
+}
+
+// Splits a gdb function like `std::vector<int>::push_back (this=0x1) at vector.h:3` at the
+// parenthesis that starts its arguments, which isn't in a template.
+fn split_arguments(function: &str) -> (&str, &str) {
+    let mut depth = 0usize;
+    let bytes = function.as_bytes();
+    for (i, &b) in bytes.iter().enumerate() {
+        match b {
+            b'<' => depth += 1,
+            b'>' => depth = depth.saturating_sub(1),
+            b'(' if depth == 0 && i > 0 && bytes[i - 1] == b' ' => {
+                return (function[..i].trim_end(), &function[i..]);
+            }
+            _ => {}
+        }
+    }
+    (function, "")
+}
+
+// Names a frame without symbols after its module, like perf does.
+fn module_frame(module: &str) -> String {
+    format!("[{}]", module.rsplit('/').next().unwrap_or(module))
+}
+
+// Names the thread of a header like `Thread 2 (Thread 0x7f... (LWP 1235) "worker"):`,
+// `Thread 1 (process 1234):` or `TID 1235:`.
+fn thread_header(line: &str) -> Option<String> {
+    if let Some(tid) = line.strip_prefix("TID ").and_then(|l| l.strip_suffix(':')) {
+        return Some(format!("Thread ({})", tid.trim()));
+    }
+    let header = line.strip_prefix("Thread ")?.strip_suffix("):")?;
+    let (_, description) = header.split_once(" (")?;
+    if let Some(name) = description
+        .strip_suffix('"')
+        .and_then(|d| d.rsplit_once(" \""))
+        .map(|(_, name)| name)
+    {
+        return Some(name.to_string());
+    }
+    let id = description
+        .split_once("(LWP ")
+        .map(|(_, lwp)| lwp.trim_end_matches(')'))
+        .or_else(|| description.strip_prefix("process "))
+        .or_else(|| description.strip_prefix("Thread "))
+        .unwrap_or(description);
+    Some(format!("Thread ({})", id.trim()))
+}
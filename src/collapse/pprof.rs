@@ -0,0 +1,434 @@
+use std::io::{self, Read};
+
+use ahash::AHashMap;
+use libflate::gzip::Decoder;
+use log::warn;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+use crate::folded::Header;
+use crate::Error;
+
+// Field numbers of the messages in profile.proto.
+const PROFILE_SAMPLE_TYPE: u32 = 1;
+const PROFILE_SAMPLE: u32 = 2;
+const PROFILE_LOCATION: u32 = 4;
+const PROFILE_FUNCTION: u32 = 5;
+const PROFILE_STRING_TABLE: u32 = 6;
+const PROFILE_TIME_NANOS: u32 = 9;
+const PROFILE_DURATION_NANOS: u32 = 10;
+const PROFILE_PERIOD: u32 = 12;
+const PROFILE_DEFAULT_SAMPLE_TYPE: u32 = 14;
+const VALUE_TYPE_TYPE: u32 = 1;
+const VALUE_TYPE_UNIT: u32 = 2;
+const SAMPLE_LOCATION_ID: u32 = 1;
+const SAMPLE_VALUE: u32 = 2;
+const LOCATION_ID: u32 = 1;
+const LOCATION_ADDRESS: u32 = 3;
+const LOCATION_LINE: u32 = 4;
+const LINE_FUNCTION_ID: u32 = 1;
+const FUNCTION_ID: u32 = 1;
+const FUNCTION_NAME: u32 = 2;
+const FUNCTION_SYSTEM_NAME: u32 = 3;
+
+// Protobuf wire types.
+const VARINT: u8 = 0;
+const FIXED64: u8 = 1;
+const LENGTH_DELIMITED: u8 = 2;
+const FIXED32: u8 = 5;
+
+// The first bytes of gzipped data.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// `pprof` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct Options {
+    /// The type of the sample values to weight stacks by, like `cpu` or `samples` for CPU
+    /// profiles, or `alloc_space`, `alloc_objects`, `inuse_space` or `inuse_objects` for heap
+    /// profiles.
+    ///
+    /// Default is `None`, which uses the default sample type of the profile, or its last sample
+    /// type if it has no default, like `go tool pprof` does.
+    pub sample_type: Option<String>,
+
+    /// Write a [`Header`] before the folded stacks, with the sample type and unit of the
+    /// profile.
+    ///
+    /// Default is `false`.
+    pub header: bool,
+}
+
+/// A stack collapser for the gzipped [`profile.proto`] format of pprof, as written by Go's
+/// `runtime/pprof` and `net/http/pprof`, and by gperftools' `pprof --proto`.
+///
+/// Uncompressed profiles are read too. Functions that were inlined into a location become frames
+/// of their own, and locations without functions are named by their address.
+///
+/// To construct one, either use `pprof::Folder::default()` or create an [`Options`] and use
+/// `pprof::Folder::from(options)`.
+///
+///   [`profile.proto`]: https://github.com/google/pprof/blob/main/proto/profile.proto
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, mut reader: R, mut writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut input = Vec::new();
+        reader.read_to_end(&mut input)?;
+        if input.starts_with(&GZIP_MAGIC) {
+            let mut decompressed = Vec::new();
+            Decoder::new(&input[..])?.read_to_end(&mut decompressed)?;
+            input = decompressed;
+        }
+
+        let profile = Profile::decode(&input)?;
+        let value = profile.value_index(self.opt.sample_type.as_deref())?;
+
+        let mut occurrences = Occurrences::new(1);
+        let mut unknown = 0;
+        let mut stack = String::new();
+        for sample in &profile.samples {
+            let weight = match sample.values.get(value) {
+                Some(&weight) if weight > 0 => weight as usize,
+                _ => continue,
+            };
+
+            stack.clear();
+            // pprof lists the locations of a sample from the leaf to the root, and the lines of a
+            // location from the innermost inlined function to the function it was inlined into.
+            for id in sample.location_ids.iter().rev() {
+                let location = match profile.locations.get(id) {
+                    Some(location) => location,
+                    None => {
+                        unknown += 1;
+                        continue;
+                    }
+                };
+                if location.function_ids.is_empty() {
+                    push_frame(&mut stack, &format!("0x{:x}", location.address));
+                }
+                for function_id in location.function_ids.iter().rev() {
+                    match profile.function_name(*function_id) {
+                        Some(name) => push_frame(&mut stack, name),
+                        None => unknown += 1,
+                    }
+                }
+            }
+            if !stack.is_empty() {
+                occurrences.insert_or_add(stack.clone(), weight);
+            }
+        }
+
+        if unknown != 0 {
+            warn!(
+                "Ignored {} frames with unknown locations or functions",
+                unknown
+            );
+        }
+        if self.opt.header {
+            let (event, units) = profile.sample_types[value];
+            let mut header = Header::for_tool("inferno-collapse-pprof");
+            header.event = Some(profile.string(event).to_string());
+            header.units = Some(profile.string(units).to_string());
+            header.period = Some(profile.period as f64).filter(|&period| period > 0.0);
+            if profile.time_nanos > 0 {
+                let start = profile.time_nanos as f64 / 1e9;
+                header.start = Some(start);
+                header.end = Some(start + profile.duration_nanos as f64 / 1e9);
+            }
+            header.write(&mut writer)?;
+        }
+        occurrences.write_and_clear(writer)
+    }
+
+    /// pprof profiles are binary, so they can't be recognized from text
+    fn is_applicable(&mut self, _input: &str) -> Option<bool> {
+        Some(false)
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+fn push_frame(stack: &mut String, frame: &str) {
+    if !stack.is_empty() {
+        stack.push(';');
+    }
+    stack.push_str(frame);
+}
+
+/// The parts of a decoded profile that stacks are made from.
+#[derive(Default)]
+struct Profile<'a> {
+    /// The type and unit of each sample value, as indices into `strings`.
+    sample_types: Vec<(u64, u64)>,
+    samples: Vec<Sample>,
+    locations: AHashMap<u64, Location>,
+    /// The name of each function, as an index into `strings`.
+    functions: AHashMap<u64, u64>,
+    strings: Vec<&'a str>,
+    time_nanos: i64,
+    duration_nanos: i64,
+    period: i64,
+    default_sample_type: u64,
+}
+
+#[derive(Default)]
+struct Sample {
+    location_ids: Vec<u64>,
+    values: Vec<i64>,
+}
+
+#[derive(Default)]
+struct Location {
+    address: u64,
+    function_ids: Vec<u64>,
+}
+
+impl<'a> Profile<'a> {
+    fn decode(buf: &'a [u8]) -> io::Result<Self> {
+        let mut profile = Profile::default();
+        let mut fields = Fields::new(buf);
+        while let Some((field, value)) = fields.next_field()? {
+            match (field, value) {
+                (PROFILE_SAMPLE_TYPE, Value::Bytes(bytes)) => {
+                    let (mut kind, mut unit) = (0, 0);
+                    let mut fields = Fields::new(bytes);
+                    while let Some((field, value)) = fields.next_field()? {
+                        match (field, value) {
+                            (VALUE_TYPE_TYPE, Value::Varint(v)) => kind = v,
+                            (VALUE_TYPE_UNIT, Value::Varint(v)) => unit = v,
+                            _ => {}
+                        }
+                    }
+                    profile.sample_types.push((kind, unit));
+                }
+                (PROFILE_SAMPLE, Value::Bytes(bytes)) => {
+                    let mut sample = Sample::default();
+                    let mut fields = Fields::new(bytes);
+                    while let Some((field, value)) = fields.next_field()? {
+                        match field {
+                            SAMPLE_LOCATION_ID => value.repeated(&mut sample.location_ids)?,
+                            SAMPLE_VALUE => {
+                                let mut values = Vec::new();
+                                value.repeated(&mut values)?;
+                                sample.values.extend(values.into_iter().map(|v| v as i64));
+                            }
+                            _ => {}
+                        }
+                    }
+                    profile.samples.push(sample);
+                }
+                (PROFILE_LOCATION, Value::Bytes(bytes)) => {
+                    let (mut id, mut location) = (0, Location::default());
+                    let mut fields = Fields::new(bytes);
+                    while let Some((field, value)) = fields.next_field()? {
+                        match (field, value) {
+                            (LOCATION_ID, Value::Varint(v)) => id = v,
+                            (LOCATION_ADDRESS, Value::Varint(v)) => location.address = v,
+                            (LOCATION_LINE, Value::Bytes(bytes)) => {
+                                let mut fields = Fields::new(bytes);
+                                while let Some((field, value)) = fields.next_field()? {
+                                    if let (LINE_FUNCTION_ID, Value::Varint(v)) = (field, value) {
+                                        location.function_ids.push(v);
+                                    }
+                                }
+                            }
+                            _ => {}
+                        }
+                    }
+                    profile.locations.insert(id, location);
+                }
+                (PROFILE_FUNCTION, Value::Bytes(bytes)) => {
+                    let (mut id, mut name, mut system_name) = (0, 0, 0);
+                    let mut fields = Fields::new(bytes);
+                    while let Some((field, value)) = fields.next_field()? {
+                        match (field, value) {
+                            (FUNCTION_ID, Value::Varint(v)) => id = v,
+                            (FUNCTION_NAME, Value::Varint(v)) => name = v,
+                            (FUNCTION_SYSTEM_NAME, Value::Varint(v)) => system_name = v,
+                            _ => {}
+                        }
+                    }
+                    profile
+                        .functions
+                        .insert(id, if name != 0 { name } else { system_name });
+                }
+                (PROFILE_STRING_TABLE, Value::Bytes(bytes)) => {
+                    let string = std::str::from_utf8(bytes).map_err(invalid_data)?;
+                    profile.strings.push(string);
+                }
+                (PROFILE_TIME_NANOS, Value::Varint(v)) => profile.time_nanos = v as i64,
+                (PROFILE_DURATION_NANOS, Value::Varint(v)) => profile.duration_nanos = v as i64,
+                (PROFILE_PERIOD, Value::Varint(v)) => profile.period = v as i64,
+                (PROFILE_DEFAULT_SAMPLE_TYPE, Value::Varint(v)) => profile.default_sample_type = v,
+                _ => {}
+            }
+        }
+
+        if profile.strings.first() != Some(&"") {
+            return Err(invalid_data(
+                "the string table of the profile doesn't start with the empty string",
+            ));
+        }
+        Ok(profile)
+    }
+
+    fn string(&self, index: u64) -> &'a str {
+        self.strings
+            .get(index as usize)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn function_name(&self, id: u64) -> Option<&'a str> {
+        self.functions.get(&id).map(|&name| self.string(name))
+    }
+
+    // Finds the index of the sample values of the given type, or of the default type.
+    fn value_index(&self, sample_type: Option<&str>) -> io::Result<usize> {
+        let index = match sample_type {
+            Some(sample_type) => self
+                .sample_types
+                .iter()
+                .position(|&(kind, _)| self.string(kind) == sample_type),
+            None if self.default_sample_type != 0 => self
+                .sample_types
+                .iter()
+                .position(|&(kind, _)| kind == self.default_sample_type),
+            None => self.sample_types.len().checked_sub(1),
+        };
+        index.ok_or_else(|| {
+            let types: Vec<_> = self
+                .sample_types
+                .iter()
+                .map(|&(kind, _)| self.string(kind))
+                .collect();
+            io::Error::from(Error::InvalidOptions(format!(
+                "the profile has no {} sample type; its sample types are: {}",
+                sample_type.unwrap_or("default"),
+                types.join(", ")
+            )))
+        })
+    }
+}
+
+/// A decoded protobuf field value.
+#[derive(Clone, Copy)]
+enum Value<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+impl<'a> Value<'a> {
+    // Adds the values of a repeated varint field, which may be packed.
+    fn repeated(self, values: &mut Vec<u64>) -> io::Result<()> {
+        match self {
+            Value::Varint(v) => values.push(v),
+            Value::Bytes(bytes) => {
+                let mut bytes = bytes;
+                while !bytes.is_empty() {
+                    values.push(varint(&mut bytes)?);
+                }
+            }
+            Value::Fixed => {}
+        }
+        Ok(())
+    }
+}
+
+/// The fields of an encoded protobuf message.
+struct Fields<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Fields<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Fields { buf }
+    }
+
+    fn next_field(&mut self) -> io::Result<Option<(u32, Value<'a>)>> {
+        if self.buf.is_empty() {
+            return Ok(None);
+        }
+        let key = varint(&mut self.buf)?;
+        let field = (key >> 3) as u32;
+        let value = match (key & 0x7) as u8 {
+            VARINT => Value::Varint(varint(&mut self.buf)?),
+            LENGTH_DELIMITED => {
+                let len = varint(&mut self.buf)? as usize;
+                if len > self.buf.len() {
+                    return Err(invalid_data("a field of the profile is truncated"));
+                }
+                let (bytes, rest) = self.buf.split_at(len);
+                self.buf = rest;
+                Value::Bytes(bytes)
+            }
+            wire_type @ (FIXED64 | FIXED32) => {
+                let len = if wire_type == FIXED64 { 8 } else { 4 };
+                if len > self.buf.len() {
+                    return Err(invalid_data("a field of the profile is truncated"));
+                }
+                self.buf = &self.buf[len..];
+                Value::Fixed
+            }
+            wire_type => {
+                return Err(invalid_data(format!(
+                    "unsupported protobuf wire type {}",
+                    wire_type
+                )))
+            }
+        };
+        Ok(Some((field, value)))
+    }
+}
+
+fn varint(buf: &mut &[u8]) -> io::Result<u64> {
+    let mut value = 0;
+    for (i, &byte) in buf.iter().enumerate().take(10) {
+        value |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            *buf = &buf[i + 1..];
+            return Ok(value);
+        }
+    }
+    Err(invalid_data("a varint of the profile is truncated"))
+}
+
+fn invalid_data<E>(error: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let error: Box<dyn std::error::Error + Send + Sync> = error.into();
+    Error::parse("pprof", error).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn varint_decoding() {
+        let mut buf: &[u8] = &[0x01, 0xac, 0x02];
+        assert_eq!(varint(&mut buf).unwrap(), 1);
+        assert_eq!(varint(&mut buf).unwrap(), 300);
+        assert!(buf.is_empty());
+        assert!(varint(&mut &[0x80][..]).is_err());
+    }
+}
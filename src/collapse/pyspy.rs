@@ -0,0 +1,280 @@
+use std::io::{self, BufRead};
+
+use log::warn;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+use crate::folded::{self, Header};
+
+/// `py-spy` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct Options {
+    /// Leave the line numbers out of frames, so that all the lines of a function are merged into
+    /// one frame, like `py-spy record --nolineno` does.
+    ///
+    /// Default is `false`.
+    pub no_lines: bool,
+
+    /// Leave the file names (and line numbers) out of frames, keeping just the function names.
+    ///
+    /// Default is `false`.
+    pub no_files: bool,
+
+    /// Leave out the thread frames that `py-spy record --threads` puts at the root of stacks.
+    ///
+    /// Default is `false`.
+    pub no_threads: bool,
+
+    /// Leave out the process frames that `py-spy record --subprocesses` puts at the root of
+    /// stacks.
+    ///
+    /// Default is `false`.
+    pub no_processes: bool,
+
+    /// Leave out stacks of threads that were idle, for profiles recorded with `--idle`.
+    ///
+    /// Default is `false`.
+    pub skip_idle: bool,
+
+    /// Annotate each stack with the status of its thread, `idle`, `gil` or `active`, using
+    /// [`crate::folded::annotate_category`], so that `inferno-flamegraph --category-band` shows
+    /// where the GIL was held.
+    ///
+    /// Default is `false`.
+    pub annotate_status: bool,
+
+    /// Write a [`Header`] before the folded stacks.
+    ///
+    /// Default is `false`.
+    pub header: bool,
+}
+
+/// A stack collapser for the raw output of [`py-spy`](https://github.com/benfred/py-spy), as
+/// written by `py-spy record --format raw`.
+///
+/// The raw output is already made of folded stacks, with frames like `work (app.py:12)`, but
+/// can have roots and thread statuses that other inferno tools don't know about:
+///
+/// ```text
+/// process 4242:"python app.py";thread (0x7F3E3C6B4740) (active+gil);<module> (app.py:20);work (app.py:12) 8
+/// ```
+///
+/// Thread frames may end in the status of the thread, which is one of `idle`, `gil`, `active`
+/// or `active+gil`. See the [`Options`] for what is done with them.
+///
+/// To construct one, either use `pyspy::Folder::default()` or create an [`Options`] and use
+/// `pyspy::Folder::from(options)`.
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+/// The status of a sampled thread.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Status {
+    Idle,
+    Gil,
+    Active,
+}
+
+impl Status {
+    fn from_marker(marker: &str) -> Option<Self> {
+        match marker {
+            "idle" => Some(Status::Idle),
+            "gil" | "active+gil" => Some(Status::Gil),
+            "active" => Some(Status::Active),
+            _ => None,
+        }
+    }
+
+    fn category(self) -> &'static str {
+        match self {
+            Status::Idle => "idle",
+            Status::Gil => "gil",
+            Status::Active => "active",
+        }
+    }
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, reader: R, mut writer: W) -> io::Result<()>
+    where
+        R: BufRead,
+        W: io::Write,
+    {
+        let mut occurrences = Occurrences::new(1);
+        let mut invalid = 0;
+        let mut stack = String::new();
+        for line in reader.lines() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || folded::is_comment(line) {
+                continue;
+            }
+
+            let parsed = line.rsplit_once(' ').and_then(|(frames, count)| {
+                Some((frames.trim_end(), count.parse::<usize>().ok()?))
+            });
+            let (frames, count) = match parsed {
+                Some(parsed) => parsed,
+                None => {
+                    invalid += 1;
+                    continue;
+                }
+            };
+
+            stack.clear();
+            let mut status = None;
+            for frame in frames.split(';') {
+                if !stack.is_empty() {
+                    stack.push(';');
+                }
+
+                // The thread and process frames have no location to tidy up.
+                if is_thread(frame) {
+                    let (thread, marker) = split_status(frame);
+                    status = marker.or(status);
+                    if !self.opt.no_threads {
+                        stack.push_str(thread);
+                    }
+                } else if is_process(frame) {
+                    if !self.opt.no_processes {
+                        stack.push_str(frame);
+                    }
+                } else {
+                    self.push_frame(&mut stack, frame);
+                }
+                if stack.ends_with(';') {
+                    stack.pop();
+                }
+            }
+
+            if status == Some(Status::Idle) && self.opt.skip_idle {
+                continue;
+            }
+            if stack.is_empty() {
+                invalid += 1;
+                continue;
+            }
+            if let Some(status) = status.filter(|_| self.opt.annotate_status) {
+                folded::annotate_category(&mut stack, status.category());
+            }
+            occurrences.insert_or_add(stack.clone(), count);
+        }
+
+        if invalid != 0 {
+            warn!("Ignored {} lines with invalid format", invalid);
+        }
+        if self.opt.header {
+            let mut header = Header::for_tool("inferno-collapse-pyspy");
+            header.units = Some("samples".to_string());
+            header.write(&mut writer)?;
+        }
+        occurrences.write_and_clear(writer)
+    }
+
+    /// Check for a folded stack line with py-spy's `function (file.py:line)` frames
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        for line in input.lines() {
+            let line = line.trim();
+            if line.is_empty() || folded::is_comment(line) {
+                continue;
+            }
+            let frames = match line.rsplit_once(' ') {
+                Some((frames, count)) if count.parse::<usize>().is_ok() => frames,
+                _ => return Some(false),
+            };
+            let python = frames.split(';').any(|frame| {
+                split_location(frame)
+                    .1
+                    .is_some_and(|location| location.contains(".py"))
+            });
+            if python {
+                return Some(true);
+            }
+        }
+        None
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+impl Folder {
+    fn push_frame(&self, stack: &mut String, frame: &str) {
+        let (name, location) = split_location(frame);
+        match location {
+            Some(_) if self.opt.no_files => stack.push_str(name),
+            Some(location) if self.opt.no_lines => {
+                let file = match location.rsplit_once(':') {
+                    Some((file, line)) if line.bytes().all(|b| b.is_ascii_digit()) => file,
+                    _ => location,
+                };
+                stack.push_str(name);
+                stack.push_str(" (");
+                stack.push_str(file);
+                stack.push(')');
+            }
+            _ => stack.push_str(frame),
+        }
+    }
+}
+
+// Splits a frame like `work (app.py:12)` into its function and its location.
+fn split_location(frame: &str) -> (&str, Option<&str>) {
+    if let Some(inner) = frame.strip_suffix(')') {
+        if let Some(i) = inner.rfind(" (") {
+            return (&frame[..i], Some(&inner[i + 2..]));
+        }
+    }
+    (frame, None)
+}
+
+fn is_thread(frame: &str) -> bool {
+    frame.starts_with("thread (")
+}
+
+fn is_process(frame: &str) -> bool {
+    frame.starts_with("process ") && frame.contains(":\"")
+}
+
+// Splits the status off a thread frame like `thread (0x7F3E3C6B4740) (idle)`.
+fn split_status(frame: &str) -> (&str, Option<Status>) {
+    match split_location(frame) {
+        (thread, Some(marker)) => match Status::from_marker(marker) {
+            Some(status) => (thread, Some(status)),
+            None => (frame, None),
+        },
+        (thread, None) => (thread, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn thread_status() {
+        assert_eq!(
+            split_status("thread (0x7F3E3C6B4740) (active+gil)"),
+            ("thread (0x7F3E3C6B4740)", Some(Status::Gil))
+        );
+        assert_eq!(
+            split_status("thread (0x7F3E3C6B4740) (idle)"),
+            ("thread (0x7F3E3C6B4740)", Some(Status::Idle))
+        );
+        assert_eq!(
+            split_status("thread (0x7F3E3C6B4740)"),
+            ("thread (0x7F3E3C6B4740)", None)
+        );
+    }
+}
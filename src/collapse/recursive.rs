@@ -1,20 +1,57 @@
 use super::common::{self, CollapsePrivate};
+use crate::Error;
+use regex::Regex;
 use std::{borrow::Cow, io};
 
 /// Recursive backtrace folder configuration options.
 #[derive(Clone, Debug)]
 #[non_exhaustive]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
 pub struct Options {
     /// The number of threads to use.
     ///
     /// Default is the number of logical cores on your machine.
     pub nthreads: usize,
+
+    /// Annotate each collapsed run of recursive frames with the number of frames in it, as in
+    /// `foo (x3)`, so deep recursion still shows in the flame graph.
+    ///
+    /// Default is `false`.
+    pub annotate_depth: bool,
+
+    /// The number of frames each run of recursive frames is collapsed to, so that shallow
+    /// recursion is kept as it is and only deeper recursion is cut down. With
+    /// [`Options::annotate_depth`], the last frame kept is annotated, as in `foo;foo;foo (x7)`.
+    ///
+    /// Default is `1`, and `0` counts as `1`.
+    pub max_repeats: usize,
+
+    /// Only collapse the recursion of functions that match this pattern, like interpreter eval
+    /// loops or async poll functions.
+    ///
+    /// Default is `None`, which collapses the recursion of all functions.
+    #[cfg_attr(feature = "config", serde(with = "crate::config::optional_regex"))]
+    pub include: Option<Regex>,
+
+    /// Don't collapse the recursion of functions that match this pattern.
+    ///
+    /// Default is `None`.
+    #[cfg_attr(feature = "config", serde(with = "crate::config::optional_regex"))]
+    pub exclude: Option<Regex>,
 }
 
 impl Default for Options {
     fn default() -> Self {
         Self {
             nthreads: *common::DEFAULT_NTHREADS,
+            annotate_depth: false,
+            max_repeats: 1,
+            include: None,
+            exclude: None,
         }
     }
 }
@@ -36,6 +73,9 @@ impl From<Options> for Folder {
         if opt.nthreads == 0 {
             opt.nthreads = 1;
         }
+        if opt.max_repeats == 0 {
+            opt.max_repeats = 1;
+        }
         Self {
             nstacks_per_job: common::DEFAULT_NSTACKS_PER_JOB,
             opt,
@@ -70,12 +110,17 @@ impl CollapsePrivate for Folder {
     where
         R: std::io::BufRead,
     {
-        for line in reader.lines() {
+        for (i, line) in reader.lines().enumerate() {
             let line = line?;
-            let (stack, count) = Self::line_parts(&line)
-                .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidData))?;
+            let (stack, count) = Self::line_parts(&line).ok_or_else(|| {
+                io::Error::from(Error::Parse {
+                    format: "folded",
+                    line: Some(i + 1),
+                    message: format!("invalid folded stack line: {}", line),
+                })
+            })?;
 
-            occurrences.insert_or_add(Self::collapse_stack(stack.into()).into_owned(), count);
+            occurrences.insert_or_add(self.collapse_stack(stack.into()).into_owned(), count);
         }
         Ok(())
     }
@@ -118,7 +163,7 @@ impl Folder {
             .and_then(|(stack, count)| Some((stack, count.parse().ok()?)))
     }
 
-    fn collapse_stack(stack: Cow<str>) -> Cow<str> {
+    fn collapse_stack<'a>(&self, stack: Cow<'a, str>) -> Cow<'a, str> {
         // First, determine whether we can avoid allocation by just returning
         // the original stack (in the case that there is no recursion, which is
         // likely the mainline case).
@@ -129,13 +174,29 @@ impl Folder {
         // There is recursion, so we can't get away without allocating a new
         // String.
         let mut result = String::with_capacity(stack.len());
-        let mut last = None;
-        for frame in stack.split(';') {
-            if last.map_or(true, |l| l != frame) {
+        let mut frames = stack.split(';').peekable();
+        while let Some(frame) = frames.next() {
+            let mut depth = 1;
+            while frames.peek() == Some(&frame) {
+                frames.next();
+                depth += 1;
+            }
+
+            let kept = if depth > 1 && self.should_collapse(frame) {
+                std::cmp::min(depth, self.opt.max_repeats)
+            } else {
+                depth
+            };
+            for _ in 1..kept {
                 result.push_str(frame);
-                result.push(';')
+                result.push(';');
+            }
+
+            result.push_str(frame);
+            if self.opt.annotate_depth && kept < depth {
+                result.push_str(&format!(" (x{})", depth));
             }
-            last = Some(frame);
+            result.push(';')
         }
 
         // Remove the trailing semicolon
@@ -144,6 +205,19 @@ impl Folder {
         result.into()
     }
 
+    /// Determine whether the recursion of a function should be collapsed.
+    fn should_collapse(&self, frame: &str) -> bool {
+        self.opt
+            .include
+            .as_ref()
+            .map_or(true, |include| include.is_match(frame))
+            && !self
+                .opt
+                .exclude
+                .as_ref()
+                .is_some_and(|exclude| exclude.is_match(frame))
+    }
+
     /// Determine whether or not a stack contains direct recursion.
     fn is_recursive(stack: &str) -> bool {
         let mut last = None;
@@ -173,22 +247,80 @@ mod test {
 
     #[test]
     fn test_collapse_stack() {
-        assert_eq!(Folder::collapse_stack("".into()), "");
-        assert_eq!(Folder::collapse_stack("single".into()), "single");
+        let folder = Folder::default();
+        assert_eq!(folder.collapse_stack("".into()), "");
+        assert_eq!(folder.collapse_stack("single".into()), "single");
         assert_eq!(
-            Folder::collapse_stack("not;recursive".into()),
+            folder.collapse_stack("not;recursive".into()),
             "not;recursive"
         );
         assert_eq!(
-            Folder::collapse_stack("has;some;some;recursion;recursion".into()),
+            folder.collapse_stack("has;some;some;recursion;recursion".into()),
             "has;some;recursion"
         );
         assert_eq!(
-            Folder::collapse_stack("co;recursive;co;recursive".into()),
+            folder.collapse_stack("co;recursive;co;recursive".into()),
+            "co;recursive;co;recursive"
+        );
+    }
+
+    #[test]
+    fn test_collapse_stack_annotate_depth() {
+        let folder = Folder::from(Options {
+            annotate_depth: true,
+            ..Default::default()
+        });
+        assert_eq!(
+            folder.collapse_stack("not;recursive".into()),
+            "not;recursive"
+        );
+        assert_eq!(
+            folder.collapse_stack("has;some;some;recursion;recursion;recursion".into()),
+            "has;some (x2);recursion (x3)"
+        );
+        assert_eq!(
+            folder.collapse_stack("co;recursive;co;recursive".into()),
             "co;recursive;co;recursive"
         );
     }
 
+    #[test]
+    fn test_collapse_stack_selective() {
+        let folder = Folder::from(Options {
+            include: Some(Regex::new("^eval").unwrap()),
+            exclude: Some(Regex::new("^eval_inner$").unwrap()),
+            ..Default::default()
+        });
+        assert_eq!(
+            folder.collapse_stack("main;eval;eval;fib;fib".into()),
+            "main;eval;fib;fib"
+        );
+        assert_eq!(
+            folder.collapse_stack("main;eval_inner;eval_inner;eval_outer;eval_outer".into()),
+            "main;eval_inner;eval_inner;eval_outer"
+        );
+    }
+
+    #[test]
+    fn test_collapse_stack_max_repeats() {
+        let mut options = Options {
+            max_repeats: 2,
+            ..Default::default()
+        };
+        let folder = Folder::from(options.clone());
+        assert_eq!(
+            folder.collapse_stack("main;fib;fib;eval;eval;eval;eval".into()),
+            "main;fib;fib;eval;eval"
+        );
+
+        options.annotate_depth = true;
+        let folder = Folder::from(options);
+        assert_eq!(
+            folder.collapse_stack("main;fib;fib;eval;eval;eval;eval".into()),
+            "main;fib;fib;eval;eval (x4)"
+        );
+    }
+
     #[test]
     fn test_line_parts() {
         assert_eq!(
@@ -2,8 +2,11 @@ use std::io::{self, BufRead};
 
 use log::warn;
 
-use crate::collapse::common::{self, Occurrences};
-use crate::collapse::Collapse;
+use crate::collapse::common::{self, CancelToken, CollapsePrivate, Occurrences};
+use crate::collapse::Prefix;
+use crate::folded::Header;
+use crate::symbols::demangle;
+use crate::Error;
 
 // The set of symbols to ignore for 'waiting' threads, for ease of use.
 // This will hide waiting threads from the view, making it easier to
@@ -21,6 +24,9 @@ static IGNORE_SYMBOLS: &[&str] = &[
     "semaphore_wait_trap",
 ];
 
+// The header line with the name and id of the sampled process, like `Process:  rg [64751]`.
+static PROCESS_LINE: &str = "Process:";
+
 // The call graph begins after this line.
 static START_LINE: &str = "Call graph:";
 
@@ -28,21 +34,99 @@ static START_LINE: &str = "Call graph:";
 // We know we're done when we get to this line.
 static END_LINE: &str = "Total number in stack";
 
+// The list of loaded binaries, used to resolve unsymbolicated frames, begins after this line.
+static BINARY_IMAGES_LINE: &str = "Binary Images:";
+
 /// `sample` folder configuration options.
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug)]
 #[non_exhaustive]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
 pub struct Options {
+    /// Which mangled symbols to demangle. `sample` leaves symbols mangled when it can't
+    /// demangle them, and only partially demangles Rust symbols.
+    ///
+    /// Default is `demangle::Options::default()`, which demangles all supported manglings.
+    pub demangle: demangle::Options,
+
+    /// Write a [`Header`] before the folded stacks.
+    ///
+    /// Default is `false`.
+    pub header: bool,
+
     /// Don't include modules with function names.
     ///
     /// Default is `false`.
     pub no_modules: bool,
+
+    /// Which process or thread to put at the root of each stack, in place of the thread line of
+    /// the report, like the other collapsers do. `Prefix::None` merges the stacks of all
+    /// threads. `thread_names` and `dispatch_queues` only apply to the thread line.
+    ///
+    /// Default is `None`, which keeps the thread line of the report as the root frame.
+    pub prefix: Option<Prefix>,
+
+    /// Use the thread's name (like `Main Thread`), when the report has one, as the root frame
+    /// instead of the full thread line.
+    ///
+    /// Default is `false`.
+    pub thread_names: bool,
+
+    /// Add the label of the dispatch queue a thread was serving (like `com.apple.main-thread`), when
+    /// the report has one, as a root frame above the thread, so stacks can be analyzed per queue.
+    ///
+    /// Default is `false`.
+    pub dispatch_queues: bool,
+
+    /// Keep the stacks of blocked threads, with their leaf frame annotated with `_[blocked]`,
+    /// instead of leaving them out.
+    ///
+    /// `sample` doesn't record thread states, so a thread is considered blocked when its leaf
+    /// frame is one of the well-known functions that wait (like `__psynch_cvwait` or
+    /// `mach_msg_trap`).
+    ///
+    /// Default is `false`.
+    pub annotate_states: bool,
+
+    /// Name frames `sample` couldn't symbolicate (`???`) after the binary they're in and their
+    /// offset into it, like `libfoo+0x1f3c`, instead of leaving them as `???`.
+    ///
+    /// The binary is found from the load address printed for the frame, or else from the "Binary
+    /// Images" section at the end of the report. Since that section comes after the call graph,
+    /// the whole report is kept in memory when this is enabled.
+    ///
+    /// Default is `false`.
+    pub resolve_unknown: bool,
+
+    /// The number of threads to use.
+    ///
+    /// Default is the number of logical cores on your machine.
+    pub nthreads: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            demangle: demangle::Options::default(),
+            header: false,
+            no_modules: false,
+            prefix: None,
+            thread_names: false,
+            dispatch_queues: false,
+            annotate_states: false,
+            resolve_unknown: false,
+            nthreads: *common::DEFAULT_NTHREADS,
+        }
+    }
 }
 
 /// A stack collapser for the output of `sample` on macOS.
 ///
 /// To construct one, either use `sample::Folder::default()` or create an [`Options`] and use
 /// `sample::Folder::from(options)`.
-#[derive(Clone, Default)]
 pub struct Folder {
     /// Number of samples for the current stack frame.
     current_samples: usize,
@@ -50,14 +134,115 @@ pub struct Folder {
     /// Function on the stack in this entry thus far.
     stack: Vec<String>,
 
+    /// Binaries loaded into the sampled process, sorted by start address.
+    images: Vec<BinaryImage>,
+
+    /// The name and id of the sampled process, like `rg-64751`, from the header of the report.
+    process: Option<String>,
+
+    /// Whether the start of the call graph was found.
+    started: bool,
+
+    /// Whether the end of the call graph was found.
+    ended: bool,
+
+    /// The lines from the thread down to the last line seen by `would_start_stack`.
+    context: Vec<Vec<u8>>,
+
+    /// The number of stacks per job to send to the threadpool.
+    nstacks_per_job: usize,
+
     opt: Options,
 }
 
-impl Collapse for Folder {
-    fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
+/// A binary from the "Binary Images" section of the report.
+#[derive(Clone, Debug, PartialEq)]
+struct BinaryImage {
+    start: u64,
+    end: u64,
+    name: String,
+}
+
+impl BinaryImage {
+    // Parses lines of the form:
+    //
+    //        0x103746000 -        0x103943fff +rg (0) <7F1B5D6D-...> /usr/local/bin/rg
+    //     0x7fff737b6000 -     0x7fff737cfff7  libdyld.dylib (655.1.1) <...> /usr/lib/system/libdyld.dylib
+    fn parse(line: &str) -> Option<Self> {
+        let (start, rest) = line.trim_start().split_once('-')?;
+        let rest = rest.trim_start();
+        let (end, rest) = rest.split_once(char::is_whitespace)?;
+
+        // Name the image after its file, like the "(in libfoo.dylib)" annotations of frames do.
+        let path = match rest.rfind('>') {
+            Some(close) => &rest[close + 1..],
+            None => rest.split_whitespace().last()?,
+        };
+        let name = path.trim().rsplit('/').next()?;
+        if name.is_empty() {
+            return None;
+        }
+
+        Some(BinaryImage {
+            start: parse_hex(start.trim())?,
+            end: parse_hex(end)?,
+            name: name.trim_end_matches(".dylib").to_string(),
+        })
+    }
+}
+
+// Parses a hexadecimal address like `0x7fff737cf3d5`.
+fn parse_hex(s: &str) -> Option<u64> {
+    u64::from_str_radix(s.strip_prefix("0x")?, 16).ok()
+}
+
+impl From<Options> for Folder {
+    fn from(mut opt: Options) -> Self {
+        if opt.nthreads == 0 {
+            opt.nthreads = 1;
+        }
+        Self {
+            current_samples: 0,
+            stack: Vec::new(),
+            images: Vec::new(),
+            process: None,
+            started: false,
+            ended: false,
+            context: Vec::new(),
+            nstacks_per_job: common::DEFAULT_NSTACKS_PER_JOB,
+            opt,
+        }
+    }
+}
+
+impl Default for Folder {
+    fn default() -> Self {
+        Options::default().into()
+    }
+}
+
+impl CollapsePrivate for Folder {
+    fn collapse<R, W>(&mut self, mut reader: R, writer: W, cancel: &CancelToken) -> io::Result<()>
     where
         R: io::BufRead,
         W: io::Write,
+    {
+        if self.opt.resolve_unknown {
+            // The binary images are listed after the call graph, so we have to read the whole
+            // report before we can resolve any frames.
+            let mut report = Vec::new();
+            reader.read_to_end(&mut report)?;
+            self.images = Self::binary_images(&report);
+            let result = self.collapse_call_graph(&report[..], writer, cancel);
+            self.images.clear();
+            return result;
+        }
+        self.collapse_call_graph(reader, writer, cancel)
+    }
+
+    fn pre_process<R>(&mut self, reader: &mut R, _: &mut Occurrences) -> io::Result<()>
+    where
+        R: io::BufRead,
     {
         // Consume the header...
         let mut line = Vec::new();
@@ -69,34 +254,58 @@ impl Collapse for Folder {
             };
             let l = String::from_utf8_lossy(&line);
             if l.starts_with(START_LINE) {
-                break;
+                self.started = true;
+                return Ok(());
+            }
+            if let Some(process) = l.strip_prefix(PROCESS_LINE) {
+                // Process:         rg [64751]
+                let process = process.trim();
+                self.process = Some(match process.rsplit_once(" [") {
+                    Some((name, pid)) => format!("{}-{}", name.trim(), pid.trim_end_matches(']')),
+                    None => process.to_string(),
+                });
             }
         }
+    }
 
-        // Process the data...
-        let mut occurrences = Occurrences::new(1);
+    fn collapse_single_threaded<R>(
+        &mut self,
+        mut reader: R,
+        occurrences: &mut Occurrences,
+    ) -> io::Result<()>
+    where
+        R: io::BufRead,
+    {
+        let mut line = Vec::new();
+        let mut number = 0;
         loop {
             line.clear();
+            number += 1;
             if reader.read_until(0x0A, &mut line)? == 0 {
-                return invalid_data_error!("File ended before end of call graph");
+                // A chunk of the call graph ends after a leaf.
+                self.write_stack(occurrences);
+                break;
             }
             let l = String::from_utf8_lossy(&line);
             let line = l.trim_end();
             if line.is_empty() {
                 continue;
             } else if line.starts_with("    ") {
-                self.on_line(line, &mut occurrences)?;
+                self.on_line(line, occurrences)
+                    .map_err(|e| Error::at_line(e, number))?;
             } else if line.starts_with(END_LINE) {
-                self.write_stack(&mut occurrences);
+                self.write_stack(occurrences);
+                self.ended = true;
                 break;
             } else {
-                return invalid_data_error!("Stack line doesn't start with 4 spaces:\n{}", line);
+                return invalid_data_error!(
+                    line = number;
+                    "Stack line doesn't start with 4 spaces:\n{}",
+                    line
+                );
             }
         }
 
-        // Write the results...
-        occurrences.write_and_clear(writer)?;
-
         // Reset the state...
         self.current_samples = 0;
         self.stack.clear();
@@ -127,18 +336,185 @@ impl Collapse for Folder {
         }
         None
     }
-}
 
-impl From<Options> for Folder {
-    fn from(opt: Options) -> Self {
-        Folder {
-            opt,
-            ..Default::default()
+    // Every leaf of the call graph is a stack of its own, so chunks can be cut before any line
+    // that follows a leaf, given the lines above it.
+    fn would_end_stack(&mut self, _line: &[u8]) -> bool {
+        false
+    }
+
+    fn would_start_stack(&mut self, line: &[u8]) -> bool {
+        if self.ended {
+            return false;
         }
+        let line = String::from_utf8_lossy(line);
+        if line.starts_with(END_LINE) {
+            // Whatever follows the call graph goes with its last chunk.
+            self.ended = true;
+            return false;
+        }
+        let indent_chars = match line.strip_prefix("    ") {
+            Some(line) if !line.trim().is_empty() => line.find(|c| !Self::is_indent_char(c)),
+            _ => None,
+        };
+        let depth = match indent_chars {
+            Some(indent_chars) if indent_chars % 2 == 0 => indent_chars / 2 + 1,
+            // Leave invalid lines to the worker threads to report.
+            _ => return false,
+        };
+
+        // The previous line was a leaf unless this one is deeper.
+        let after_leaf = depth <= self.context.len();
+        self.context.truncate(depth - 1);
+        self.context.push(line.into_owned().into_bytes());
+        after_leaf
+    }
+
+    fn stack_context(&self) -> Vec<u8> {
+        match self.context.split_last() {
+            Some((_, ancestors)) => ancestors.concat(),
+            None => Vec::new(),
+        }
+    }
+
+    fn clone_and_reset_stack_context(&self) -> Self {
+        Self {
+            current_samples: 0,
+            stack: Vec::new(),
+            images: self.images.clone(),
+            process: self.process.clone(),
+            started: self.started,
+            ended: false,
+            context: Vec::new(),
+            nstacks_per_job: self.nstacks_per_job,
+            opt: self.opt.clone(),
+        }
+    }
+
+    fn nstacks_per_job(&self) -> usize {
+        self.nstacks_per_job
+    }
+
+    fn set_nstacks_per_job(&mut self, n: usize) {
+        self.nstacks_per_job = n;
+    }
+
+    fn nthreads(&self) -> usize {
+        self.opt.nthreads
+    }
+
+    fn set_nthreads(&mut self, n: usize) {
+        self.opt.nthreads = n;
+    }
+
+    fn header(&self) -> Option<Header> {
+        if !self.opt.header {
+            return None;
+        }
+        let mut header = Header::for_tool("inferno-collapse-sample");
+        header.units = Some("samples".to_string());
+        Some(header)
     }
 }
 
 impl Folder {
+    fn collapse_call_graph<R, W>(
+        &mut self,
+        mut reader: R,
+        mut writer: W,
+        cancel: &CancelToken,
+    ) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut occurrences = Occurrences::new(self.opt.nthreads);
+        self.started = false;
+        self.ended = false;
+        self.context.clear();
+
+        // Consume the header...
+        let mut counter = common::LineCounter::new(&mut reader);
+        self.pre_process(&mut counter, &mut occurrences)?;
+        let header_lines = counter.lines;
+        if !self.started {
+            return Ok(());
+        }
+
+        // Process the data...
+        if occurrences.is_concurrent() {
+            self.collapse_multi_threaded(reader, &mut occurrences, cancel)
+        } else {
+            self.collapse_single_threaded(reader, &mut occurrences)
+        }
+        .map_err(|e| Error::offset_line(e, header_lines))?;
+        if !self.ended {
+            return invalid_data_error!("File ended before end of call graph");
+        }
+
+        // Write the results...
+        if let Some(header) = self.header() {
+            header.write(&mut writer)?;
+        }
+        occurrences.write_and_clear(writer)
+    }
+
+    fn binary_images(report: &[u8]) -> Vec<BinaryImage> {
+        let report = String::from_utf8_lossy(report);
+        let mut images: Vec<_> = report
+            .lines()
+            .skip_while(|line| !line.starts_with(BINARY_IMAGES_LINE))
+            .skip(1)
+            .filter_map(BinaryImage::parse)
+            .collect();
+        images.sort_unstable_by_key(|image| image.start);
+        images
+    }
+
+    fn binary_image(&self, address: u64) -> Option<&BinaryImage> {
+        let i = self.images.partition_point(|image| image.start <= address);
+        let image = self.images.get(i.checked_sub(1)?)?;
+        if address <= image.end {
+            Some(image)
+        } else {
+            None
+        }
+    }
+
+    // Handle unsymbolicated frames of the form:
+    //
+    // ???  (in rg)  load address 0x103746000 + 0x1f3c  [0x103747f3c]
+    // ???  (in <unknown binary>)  [0x11d3c4b8e]
+    // ???  [0x103747f3c]
+    //
+    // Returns `module+0xoffset` if the binary containing the frame is known.
+    fn resolve_unknown(&self, line: &str) -> Option<String> {
+        if let Some(start) = line.find("load address ") {
+            // The module is shown with "(in libfoo.dylib)".
+            let module = line[..start]
+                .rsplit_once("(in ")
+                .and_then(|(_, module)| module.split_once(')'))
+                .map(|(module, _)| module.trim_end_matches(".dylib"));
+            let mut address = line[start + "load address ".len()..].split_whitespace();
+            let load_address = parse_hex(address.next()?)?;
+            let offset = match (address.next(), address.next()) {
+                (Some("+"), Some(offset)) => parse_hex(offset)?,
+                _ => return None,
+            };
+            let module = match module {
+                Some(module) => module,
+                None => self.binary_image(load_address)?.name.as_str(),
+            };
+            return Some(format!("{}+0x{:x}", module, offset));
+        }
+
+        let open = line.rfind("[0x")?;
+        let close = open + line[open..].find(']')?;
+        let address = parse_hex(&line[open + 1..close])?;
+        let image = self.binary_image(address)?;
+        Some(format!("{}+0x{:x}", image.name, address - image.start))
+    }
+
     fn line_parts<'a>(&self, line: &'a str) -> Option<(&'a str, &'a str, &'a str)> {
         let mut line = line.trim_start().splitn(2, ' ');
         let time = line.next()?.trim_end();
@@ -173,6 +549,51 @@ impl Folder {
         c == ' ' || c == '+' || c == '|' || c == ':' || c == '!'
     }
 
+    // Handle the first line of a thread's call graph, of the form:
+    //
+    // 825 Thread_15758523   DispatchQueue_1: com.apple.main-thread  (serial)
+    // 825 Thread_2719398: Main Thread   DispatchQueue_1: com.apple.main-thread  (serial)
+    // 825 Thread_15758535
+    //
+    // `thread` is everything up to the first parenthesis. Returns the root frame(s) to use.
+    fn thread_frames(&self, thread: &str) -> String {
+        if let Some(prefix) = self.opt.prefix {
+            let process = self.process.as_deref().unwrap_or("unknown");
+            return match prefix {
+                Prefix::None => String::new(),
+                Prefix::Process => process.to_string(),
+                Prefix::Thread => {
+                    let id = thread.split([' ', ':']).next().unwrap_or(thread);
+                    let tid = id.strip_prefix("Thread_").unwrap_or(id);
+                    format!("{}/{}", process, tid)
+                }
+            };
+        }
+        if !self.opt.thread_names && !self.opt.dispatch_queues {
+            return thread.to_string();
+        }
+
+        let (thread, queue) = match thread.find("DispatchQueue_") {
+            Some(start) => {
+                // DispatchQueue_1: com.apple.main-thread
+                let queue = &thread[start..];
+                let label = queue.split_once(": ").map_or(queue, |(_, label)| label);
+                (thread[..start].trim_end(), Some(label.trim()))
+            }
+            None => (thread, None),
+        };
+        let thread = match thread.split_once(": ") {
+            Some((_, name)) if self.opt.thread_names && !name.trim().is_empty() => name.trim(),
+            Some((id, _)) => id,
+            None => thread,
+        };
+
+        match queue {
+            Some(queue) if self.opt.dispatch_queues => format!("{};{}", queue, thread),
+            _ => thread.to_string(),
+        }
+    }
+
     // Handle call graph lines of the form:
     //
     // 5130 Thread_8749954
@@ -213,9 +634,19 @@ impl Folder {
                     // add up to that node's sample count so we only need to keep track of the
                     // sample count at the top of the stack.
                     self.current_samples = samples;
-                    // sample doesn't properly demangle Rust symbols, so fix those.
-                    let func = common::fix_partially_demangled_rust_symbol(func);
-                    if module.is_empty() {
+                    // sample doesn't always demangle symbols properly, so fix those.
+                    let func = demangle::demangle(func, &self.opt.demangle);
+                    let resolved = if self.opt.resolve_unknown && func.starts_with("???") {
+                        self.resolve_unknown(&line[4 + indent_chars..])
+                    } else {
+                        None
+                    };
+                    if depth == 1 {
+                        // the root of the call graph is the thread
+                        self.stack.push(self.thread_frames(&func));
+                    } else if let Some(resolved) = resolved {
+                        self.stack.push(resolved);
+                    } else if module.is_empty() {
                         self.stack.push(func.to_string());
                     } else {
                         self.stack.push(format!("{}`{}", module, func));
@@ -237,11 +668,118 @@ impl Folder {
         if let Some(func) = self.stack.last() {
             for symbol in IGNORE_SYMBOLS {
                 if func.ends_with(symbol) {
-                    // Don't write out stacks with ignored symbols
+                    // Don't write out stacks with ignored symbols, unless asked to keep them as
+                    // blocked threads
+                    if self.opt.annotate_states {
+                        let mut stack = self.joined_stack();
+                        stack.push_str("_[blocked]");
+                        occurrences.insert_or_add(stack, self.current_samples);
+                    }
                     return;
                 }
             }
         }
-        occurrences.insert(self.stack.join(";"), self.current_samples);
+        // A function can be listed more than once under the same caller (at different offsets),
+        // so the counts of stacks that end up the same add up.
+        let stack = self.joined_stack();
+        if !stack.is_empty() {
+            occurrences.insert_or_add(stack, self.current_samples);
+        }
+    }
+
+    // The frames of the stack, without the root frame of the thread if it's left out.
+    fn joined_stack(&self) -> String {
+        match self.stack.split_first() {
+            Some((root, frames)) if root.is_empty() => frames.join(";"),
+            _ => self.stack.join(";"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::path::PathBuf;
+
+    use once_cell::sync::Lazy;
+
+    use super::*;
+    use crate::collapse::common;
+
+    static INPUT: Lazy<Vec<PathBuf>> = Lazy::new(|| {
+        [
+            "./tests/data/collapse-sample/end-before-call-graph-start.txt",
+            "./tests/data/collapse-sample/large.txt.gz",
+            "./tests/data/collapse-sample/named-threads.txt",
+            "./tests/data/collapse-sample/sample.txt",
+            "./tests/data/collapse-sample/threads.txt",
+            "./tests/data/collapse-sample/unknown-frames.txt",
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect::<Vec<_>>()
+    });
+
+    #[test]
+    fn test_collapse_multi_sample() -> io::Result<()> {
+        let mut folder = Folder::default();
+        common::testing::test_collapse_multi(&mut folder, &INPUT)?;
+
+        // Most of the reports are small, so also cut them into a chunk per leaf.
+        folder.set_nstacks_per_job(1);
+        common::testing::test_collapse_multi(&mut folder, &INPUT)
+    }
+
+    #[test]
+    fn test_collapse_sample_stack_context() {
+        let mut folder = Folder::default();
+        assert!(!folder.would_start_stack(b"    5 Thread_101\n"));
+        assert!(!folder.would_start_stack(b"    + 5 thread_start  (in libsystem_pthread.dylib)\n"));
+        assert!(!folder.would_start_stack(b"    +   3 work  (in app)\n"));
+        assert!(folder.would_start_stack(b"    +   2 idle  (in app)\n"));
+        assert_eq!(
+            folder.stack_context(),
+            b"    5 Thread_101\n    + 5 thread_start  (in libsystem_pthread.dylib)\n".to_vec()
+        );
+        assert!(folder.would_start_stack(b"    10 Thread_102\n"));
+        assert_eq!(folder.stack_context(), b"".to_vec());
+
+        assert!(!folder.would_start_stack(b"Total number in stack (recursive counted multiple):\n"));
+        assert!(!folder.would_start_stack(b"    10 Thread_103\n"));
+    }
+
+    #[test]
+    fn test_collapse_sample_error_line() {
+        let report = std::fs::read_to_string("./tests/data/collapse-sample/sample.txt").unwrap();
+        let mut lines: Vec<&str> = report.lines().collect();
+        lines[149] = "    + BAD";
+        let input = lines.join("\n");
+
+        for nthreads in [1, 2] {
+            let mut folder = Folder::from(Options {
+                nthreads,
+                ..Default::default()
+            });
+            // Cut the call graph into a chunk per leaf, so that the error is in a chunk that
+            // starts with the context of its stack.
+            folder.set_nstacks_per_job(1);
+            let error = folder
+                .collapse(input.as_bytes(), io::sink(), &CancelToken::new())
+                .unwrap_err();
+            match Error::from(error) {
+                Error::Parse { line, .. } => assert_eq!(line, Some(150)),
+                e => panic!("expected a parse error, got {:?}", e),
+            }
+        }
+    }
+
+    /// Varies the nstacks_per_job parameter and outputs the 10 fastests configurations by file.
+    ///
+    /// Command: `cargo test bench_nstacks_sample --release -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_nstacks_sample() -> io::Result<()> {
+        let mut folder = Folder::default();
+        common::testing::bench_nstacks(&mut folder, &INPUT)
     }
 }
@@ -0,0 +1,303 @@
+use std::io::{self, BufRead};
+
+use ahash::AHashMap;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+use crate::folded::Header;
+
+/// `simpleperf` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct Options {
+    /// Annotate Java and Kotlin frames, whether JIT-compiled, compiled ahead of time by ART or
+    /// interpreted, with a `_[j]` suffix.
+    ///
+    /// Default is `false`.
+    pub annotate_jit: bool,
+
+    /// Annotate kernel functions with a `_[k]` suffix.
+    ///
+    /// Default is `false`.
+    pub annotate_kernel: bool,
+
+    /// Add the name of the process of each sample as the root frame of its stack.
+    ///
+    /// Default is `false`.
+    pub include_process: bool,
+
+    /// Count every sample once instead of weighting it by its `event_count`.
+    ///
+    /// Default is `false`.
+    pub count_samples: bool,
+
+    /// Write a [`Header`] before the folded stacks, with the event of the samples.
+    ///
+    /// Default is `false`.
+    pub header: bool,
+}
+
+/// A stack collapser for the text output of Android's [simpleperf] `report-sample` command, as
+/// created by `simpleperf report-sample --show-callchain -i perf.data`.
+///
+/// Every `sample:` record has the frame it was taken in, followed by the frames of its
+/// `callchain:`, each with a `file` and a `symbol`. Frames without symbols are named after their
+/// file, like `[libc.so]`.
+///
+/// The output of simpleperf's `report_sample.py` script mimics `perf script`, and is folded by
+/// `perf::Folder` instead.
+///
+/// To construct one, either use `simpleperf::Folder::default()` or create an [`Options`] and use
+/// `simpleperf::Folder::from(options)`.
+///
+///   [simpleperf]: https://android.googlesource.com/platform/system/extras/+/main/simpleperf/doc/README.md
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+/// The record that the current lines belong to.
+enum Record {
+    Sample(Sample),
+    Thread(Thread),
+    Other,
+}
+
+#[derive(Default)]
+struct Sample {
+    event_type: Option<String>,
+    event_count: Option<u64>,
+    thread_id: Option<u64>,
+    process_id: Option<u64>,
+    thread_name: String,
+    /// The frames of the sample, leaf first.
+    frames: Vec<Frame>,
+}
+
+#[derive(Default)]
+struct Thread {
+    thread_id: Option<u64>,
+    process_id: Option<u64>,
+    thread_name: String,
+}
+
+#[derive(Default)]
+struct Frame {
+    vaddr_in_file: bool,
+    file: Option<String>,
+    symbol: Option<String>,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, reader: R, mut writer: W) -> io::Result<()>
+    where
+        R: BufRead,
+        W: io::Write,
+    {
+        let mut samples = Vec::new();
+        // The process and name of each thread, from `thread:` records.
+        let mut threads: AHashMap<u64, (Option<u64>, String)> = AHashMap::default();
+        let mut record = Record::Other;
+
+        for line in reader.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            if !line.starts_with(char::is_whitespace) {
+                finish(record, &mut samples, &mut threads);
+                record = match line.trim_end() {
+                    "sample:" => Record::Sample(Sample::default()),
+                    "thread:" => Record::Thread(Thread::default()),
+                    _ => Record::Other,
+                };
+                continue;
+            }
+
+            let (key, value) = match line.trim().split_once(':') {
+                Some((key, value)) => (key, value.trim()),
+                None => continue,
+            };
+            match &mut record {
+                Record::Sample(sample) => match key {
+                    "event_type" => sample.event_type = Some(value.to_string()),
+                    "event_count" => sample.event_count = value.parse().ok(),
+                    "thread_id" => sample.thread_id = value.parse().ok(),
+                    "process_id" => sample.process_id = value.parse().ok(),
+                    "thread_name" => sample.thread_name = value.to_string(),
+                    // Every frame has these, so one that is already set starts the next frame.
+                    "vaddr_in_file" | "file" | "symbol" => {
+                        let next = match sample.frames.last() {
+                            Some(frame) => match key {
+                                "vaddr_in_file" => frame.vaddr_in_file,
+                                "file" => frame.file.is_some(),
+                                _ => frame.symbol.is_some(),
+                            },
+                            None => true,
+                        };
+                        if next {
+                            sample.frames.push(Frame::default());
+                        }
+                        let frame = sample.frames.last_mut().expect("a frame was just pushed");
+                        match key {
+                            "vaddr_in_file" => frame.vaddr_in_file = true,
+                            "file" => frame.file = Some(value.to_string()),
+                            _ => frame.symbol = Some(value.to_string()),
+                        }
+                    }
+                    _ => {}
+                },
+                Record::Thread(thread) => match key {
+                    "thread_id" => thread.thread_id = value.parse().ok(),
+                    "process_id" => thread.process_id = value.parse().ok(),
+                    "thread_name" => thread.thread_name = value.to_string(),
+                    _ => {}
+                },
+                Record::Other => {}
+            }
+        }
+        finish(record, &mut samples, &mut threads);
+
+        let mut occurrences = Occurrences::new(1);
+        let mut event = None;
+        let mut weighted = false;
+        for sample in &samples {
+            if sample.frames.is_empty() {
+                continue;
+            }
+            let mut stack = Vec::with_capacity(sample.frames.len() + 1);
+            if self.opt.include_process {
+                stack.push(process_name(sample, &threads).to_string());
+            }
+            stack.extend(sample.frames.iter().rev().map(|frame| self.frame(frame)));
+
+            let weight = match sample.event_count {
+                Some(count) if !self.opt.count_samples => {
+                    weighted = true;
+                    count
+                }
+                _ => 1,
+            };
+            if weight != 0 {
+                occurrences.insert_or_add(stack.join(";"), weight as usize);
+            }
+            if event.is_none() {
+                event = sample.event_type.clone();
+            }
+        }
+
+        if self.opt.header {
+            let mut header = Header::for_tool("inferno-collapse-simpleperf");
+            header.units = if weighted {
+                Some(event.as_deref().unwrap_or("events").to_string())
+            } else {
+                Some("samples".to_string())
+            };
+            header.event = event;
+            header.write(&mut writer)?;
+        }
+        occurrences.write_and_clear(writer)
+    }
+
+    /// Check for the fields of the `sample:` records of `simpleperf report-sample`
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        let mut in_sample = false;
+        for line in input.lines() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            if !line.starts_with(char::is_whitespace) {
+                in_sample = line.trim_end() == "sample:";
+                if !in_sample && !line.ends_with(':') {
+                    return Some(false);
+                }
+            } else if in_sample {
+                let key = line.trim().split(':').next().unwrap_or_default();
+                if matches!(key, "event_count" | "thread_id" | "vaddr_in_file") {
+                    return Some(true);
+                }
+            }
+        }
+        None
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+impl Folder {
+    fn frame(&self, frame: &Frame) -> String {
+        let file = frame.file.as_deref().unwrap_or_default();
+        let mut name = match frame.symbol.as_deref() {
+            Some(symbol) if !symbol.is_empty() && symbol != "unknown" => symbol.to_string(),
+            // Functions without symbols are named after their file, like perf does.
+            _ if !file.is_empty() => {
+                let file = file.trim_end_matches(" (deleted)");
+                format!("[{}]", file.rsplit('/').next().unwrap_or(file))
+            }
+            _ => "[unknown]".to_string(),
+        };
+        if self.opt.annotate_kernel && is_kernel(file) {
+            name.push_str("_[k]");
+        } else if self.opt.annotate_jit && is_java(file) {
+            name.push_str("_[j]");
+        }
+        name
+    }
+}
+
+fn finish(
+    record: Record,
+    samples: &mut Vec<Sample>,
+    threads: &mut AHashMap<u64, (Option<u64>, String)>,
+) {
+    match record {
+        Record::Sample(sample) => samples.push(sample),
+        Record::Thread(thread) => {
+            if let Some(tid) = thread.thread_id {
+                threads.insert(tid, (thread.process_id, thread.thread_name));
+            }
+        }
+        Record::Other => {}
+    }
+}
+
+// Names the process of a sample after its main thread, whose id is that of the process.
+fn process_name<'a>(
+    sample: &'a Sample,
+    threads: &'a AHashMap<u64, (Option<u64>, String)>,
+) -> &'a str {
+    let pid = sample.process_id.or_else(|| {
+        sample
+            .thread_id
+            .and_then(|tid| threads.get(&tid))
+            .and_then(|(pid, _)| *pid)
+    });
+    match pid.and_then(|pid| threads.get(&pid)) {
+        Some((_, name)) if !name.is_empty() => name,
+        _ => &sample.thread_name,
+    }
+}
+
+fn is_kernel(file: &str) -> bool {
+    file == "[kernel.kallsyms]" || file.ends_with(".ko")
+}
+
+// Whether a file holds Java or Kotlin code: ART's JIT code cache, or a dex, oat or app file.
+fn is_java(file: &str) -> bool {
+    if file.contains("jit-cache") || file.contains("jit-code-cache") {
+        return true;
+    }
+    let file = file.split('!').next().unwrap_or(file);
+    [".dex", ".vdex", ".odex", ".oat", ".art", ".apk", ".jar"]
+        .iter()
+        .any(|extension| file.ends_with(extension))
+}
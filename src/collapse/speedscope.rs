@@ -0,0 +1,287 @@
+use std::io;
+
+use ahash::AHashMap;
+use log::warn;
+use serde_json::Value;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+use crate::folded::Header;
+use crate::Error;
+
+/// `speedscope` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct Options {
+    /// The profile to fold, by its index in the file or by its name.
+    ///
+    /// Default is `None`, which folds the `activeProfileIndex` profile, or the first one.
+    pub profile: Option<String>,
+
+    /// Count every sample of a sampled profile once instead of weighting it by its weight.
+    ///
+    /// Evented profiles are always weighted by the time between their events.
+    ///
+    /// Default is `false`.
+    pub count_samples: bool,
+
+    /// Write a [`Header`] before the folded stacks, with the units of the profile.
+    ///
+    /// Default is `false`.
+    pub header: bool,
+}
+
+/// A stack collapser for the [speedscope] JSON file format, as written by `dotnet-trace`,
+/// Hermes, pyinstrument, rbspy and `inferno-export`.
+///
+/// Both `sampled` profiles, whose samples are lists of frames with weights, and `evented`
+/// profiles, whose frames are opened and closed at given times, are read. Weights in time units
+/// are converted to microseconds, and fractional weights keep up to six fractional digits.
+///
+/// To construct one, either use `speedscope::Folder::default()` or create an [`Options`] and use
+/// `speedscope::Folder::from(options)`.
+///
+///   [speedscope]: https://github.com/jlfwong/speedscope/wiki/Importing-from-custom-sources
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, mut reader: R, mut writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut input = String::new();
+        reader.read_to_string(&mut input)?;
+        let file: Value = serde_json::from_str(&input).map_err(invalid_data)?;
+
+        let frames: Vec<&str> = file
+            .pointer("/shared/frames")
+            .and_then(Value::as_array)
+            .map(|frames| {
+                frames
+                    .iter()
+                    .map(|frame| {
+                        frame
+                            .get("name")
+                            .and_then(Value::as_str)
+                            .unwrap_or("[unknown]")
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let profile = self.profile(&file)?;
+
+        let unit = profile
+            .get("unit")
+            .and_then(Value::as_str)
+            .unwrap_or("none");
+        let (scale, units) = match unit {
+            "nanoseconds" => (1e-3, "microseconds"),
+            "microseconds" => (1.0, "microseconds"),
+            "milliseconds" => (1e3, "microseconds"),
+            "seconds" => (1e6, "microseconds"),
+            "bytes" => (1.0, "bytes"),
+            _ => (1.0, "samples"),
+        };
+
+        // The weight of each stack of frame indices, in the units of the output.
+        let mut weights: AHashMap<Vec<usize>, f64> = AHashMap::default();
+        let mut counted = false;
+        match profile.get("type").and_then(Value::as_str) {
+            Some("sampled") => {
+                let samples = profile
+                    .get("samples")
+                    .and_then(Value::as_array)
+                    .map(Vec::as_slice)
+                    .unwrap_or_default();
+                let sample_weights = profile
+                    .get("weights")
+                    .and_then(Value::as_array)
+                    .map(Vec::as_slice)
+                    .unwrap_or_default();
+                counted = self.opt.count_samples || sample_weights.len() != samples.len();
+                for (i, sample) in samples.iter().enumerate() {
+                    let stack = indices(sample);
+                    let weight = if counted {
+                        1.0
+                    } else {
+                        sample_weights[i].as_f64().unwrap_or_default() * scale
+                    };
+                    *weights.entry(stack).or_default() += weight;
+                }
+            }
+            Some("evented") => {
+                let events = profile
+                    .get("events")
+                    .and_then(Value::as_array)
+                    .map(Vec::as_slice)
+                    .unwrap_or_default();
+                let mut stack = Vec::new();
+                let mut last = None;
+                let mut unbalanced = 0;
+                for event in events {
+                    let at = event.get("at").and_then(Value::as_f64).unwrap_or_default();
+                    if let Some(last) = last {
+                        if !stack.is_empty() && at > last {
+                            *weights.entry(stack.clone()).or_default() += (at - last) * scale;
+                        }
+                    }
+                    last = Some(at);
+
+                    let frame = event
+                        .get("frame")
+                        .and_then(Value::as_u64)
+                        .map(|frame| frame as usize);
+                    match (event.get("type").and_then(Value::as_str), frame) {
+                        (Some("O"), Some(frame)) => stack.push(frame),
+                        (Some("C"), Some(frame)) => {
+                            if stack.last() == Some(&frame) {
+                                stack.pop();
+                            } else {
+                                unbalanced += 1;
+                            }
+                        }
+                        _ => unbalanced += 1,
+                    }
+                }
+                if unbalanced != 0 {
+                    warn!(
+                        "Ignored {} events that don't open or close a frame",
+                        unbalanced
+                    );
+                }
+            }
+            other => {
+                return Err(invalid_data(format!(
+                    "unknown speedscope profile type: {}",
+                    other.unwrap_or("none")
+                )))
+            }
+        }
+
+        let mut occurrences = Occurrences::new(1).fractional();
+        let mut unknown = 0;
+        for (stack, weight) in weights {
+            if weight <= 0.0 || stack.is_empty() {
+                continue;
+            }
+            let names: Option<Vec<&str>> = stack
+                .iter()
+                .map(|&frame| frames.get(frame).copied())
+                .collect();
+            match names {
+                Some(names) => occurrences.insert_or_add_weight(names.join(";"), weight),
+                None => unknown += 1,
+            }
+        }
+
+        if unknown != 0 {
+            warn!("Ignored {} stacks with unknown frames", unknown);
+        }
+        if self.opt.header {
+            let mut header = Header::for_tool("inferno-collapse-speedscope");
+            header.event = profile
+                .get("name")
+                .and_then(Value::as_str)
+                .filter(|name| !name.is_empty())
+                .map(str::to_string);
+            header.units = Some(if counted { "samples" } else { units }.to_string());
+            header.write(&mut writer)?;
+        }
+        occurrences.write_and_clear(writer)
+    }
+
+    /// Check for a JSON object with the speedscope schema or its keys
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        let input = input.trim_start();
+        if !input.starts_with('{') {
+            return Some(false);
+        }
+        if input.contains("speedscope.app/file-format-schema.json")
+            || (input.contains("\"shared\"") && input.contains("\"profiles\""))
+        {
+            Some(true)
+        } else {
+            None
+        }
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+impl Folder {
+    // Finds the profile to fold.
+    fn profile<'a>(&self, file: &'a Value) -> io::Result<&'a Value> {
+        let profiles = file
+            .get("profiles")
+            .and_then(Value::as_array)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let profile = match self.opt.profile {
+            Some(ref wanted) => match wanted.parse::<usize>() {
+                Ok(index) => profiles.get(index),
+                Err(_) => profiles.iter().find(|profile| {
+                    profile.get("name").and_then(Value::as_str) == Some(wanted.as_str())
+                }),
+            },
+            None => {
+                let active = file
+                    .get("activeProfileIndex")
+                    .and_then(Value::as_u64)
+                    .unwrap_or_default();
+                profiles.get(active as usize).or_else(|| profiles.first())
+            }
+        };
+        profile.ok_or_else(|| {
+            let names: Vec<_> = profiles
+                .iter()
+                .enumerate()
+                .map(
+                    |(i, profile)| match profile.get("name").and_then(Value::as_str) {
+                        Some(name) => format!("{} ({})", i, name),
+                        None => i.to_string(),
+                    },
+                )
+                .collect();
+            io::Error::from(Error::InvalidOptions(format!(
+                "the file has no {} profile; its profiles are: {}",
+                self.opt.profile.as_deref().unwrap_or("active"),
+                names.join(", ")
+            )))
+        })
+    }
+}
+
+// Reads the frame indices of a sample.
+fn indices(sample: &Value) -> Vec<usize> {
+    sample
+        .as_array()
+        .map(|frames| {
+            frames
+                .iter()
+                .filter_map(Value::as_u64)
+                .map(|frame| frame as usize)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn invalid_data<E>(error: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let error: Box<dyn std::error::Error + Send + Sync> = error.into();
+    Error::parse("speedscope", error).into()
+}
@@ -0,0 +1,165 @@
+use std::io;
+
+use log::warn;
+use serde_json::Value;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+use crate::folded::Header;
+use crate::Error;
+
+/// `stackprof` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct Options {
+    /// Add the file and line of each method to its frame, like `Foo#bar (app/foo.rb:12)`.
+    ///
+    /// Default is `false`.
+    pub include_locations: bool,
+
+    /// Write a [`Header`] before the folded stacks, with the mode of the profile as its event.
+    ///
+    /// Default is `false`.
+    pub header: bool,
+}
+
+/// A stack collapser for the JSON dumps of Ruby's [stackprof], as written by
+/// `StackProf.run(raw: true, ...)` followed by `JSON.generate`, or `stackprof --json`.
+///
+/// The `raw` array of a dump holds every distinct stack, as the number of its frames, their ids
+/// from the root down, and the number of samples of the stack. Frames are named after the
+/// `name` of their entry in `frames`. Dumps recorded without `raw: true` only have the callers
+/// and callees of each method, which aren't enough to rebuild stacks, and are rejected.
+///
+/// To construct one, either use `stackprof::Folder::default()` or create an [`Options`] and use
+/// `stackprof::Folder::from(options)`.
+///
+///   [stackprof]: https://github.com/tmm1/stackprof
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, mut reader: R, mut writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut input = String::new();
+        reader.read_to_string(&mut input)?;
+        let profile: Value = serde_json::from_str(&input).map_err(invalid_data)?;
+
+        let frames = profile
+            .get("frames")
+            .and_then(Value::as_object)
+            .ok_or_else(|| invalid_data("the profile has no frames"))?;
+        let raw = profile
+            .get("raw")
+            .and_then(Value::as_array)
+            .ok_or_else(|| {
+                invalid_data("the profile has no raw samples; record it with `raw: true`")
+            })?;
+
+        let mut occurrences = Occurrences::new(1);
+        let mut unknown = 0;
+        let mut raw = raw.iter().map(Value::as_u64);
+        while let Some(len) = raw.next() {
+            let len = len.ok_or_else(|| invalid_data("invalid raw samples"))? as usize;
+            let mut stack = Vec::new();
+            for _ in 0..len {
+                let id = raw
+                    .next()
+                    .flatten()
+                    .ok_or_else(|| invalid_data("invalid raw samples"))?;
+                match frames.get(&id.to_string()) {
+                    Some(frame) => stack.push(self.frame(frame)),
+                    None => {
+                        unknown += 1;
+                        stack.push("[unknown]".to_string());
+                    }
+                }
+            }
+            let count = raw
+                .next()
+                .flatten()
+                .ok_or_else(|| invalid_data("invalid raw samples"))?;
+            if count != 0 && !stack.is_empty() {
+                occurrences.insert_or_add(stack.join(";"), count as usize);
+            }
+        }
+
+        if unknown != 0 {
+            warn!("{} frames weren't in the profile's frames", unknown);
+        }
+        if self.opt.header {
+            let mode = profile.get("mode").and_then(Value::as_str);
+            let mut header = Header::for_tool("inferno-collapse-stackprof");
+            header.event = mode.map(str::to_string);
+            header.units = Some(
+                match mode {
+                    Some("object") => "allocations",
+                    _ => "samples",
+                }
+                .to_string(),
+            );
+            header.write(&mut writer)?;
+        }
+        occurrences.write_and_clear(writer)
+    }
+
+    /// Check for the `mode`, `interval` and `frames` keys of stackprof dumps
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        let input = input.trim_start();
+        if !input.starts_with('{') {
+            return Some(false);
+        }
+        if input.contains("\"mode\"")
+            && input.contains("\"interval\"")
+            && input.contains("\"frames\"")
+        {
+            Some(true)
+        } else {
+            None
+        }
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+impl Folder {
+    fn frame(&self, frame: &Value) -> String {
+        let name = frame
+            .get("name")
+            .and_then(Value::as_str)
+            .unwrap_or("[unknown]");
+        if !self.opt.include_locations {
+            return name.to_string();
+        }
+        match (
+            frame.get("file").and_then(Value::as_str),
+            frame.get("line").and_then(Value::as_u64),
+        ) {
+            (Some(file), Some(line)) => format!("{} ({}:{})", name, file, line),
+            (Some(file), None) => format!("{} ({})", name, file),
+            _ => name.to_string(),
+        }
+    }
+}
+
+fn invalid_data<E>(error: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let error: Box<dyn std::error::Error + Send + Sync> = error.into();
+    Error::parse("stackprof", error).into()
+}
@@ -0,0 +1,235 @@
+use std::io;
+
+use log::warn;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+use crate::Error;
+
+/// `svg` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct Options {
+    /// Keep the root frame of the flame graph, usually `all`, as the root frame of every stack.
+    ///
+    /// Default is `false`.
+    pub keep_root: bool,
+}
+
+/// A stack collapser for flame graph SVGs made by `flamegraph.pl` or inferno.
+///
+/// Each frame of those is a `<g>` with a `<title>` like `main (1,234 samples, 5.00%)` and a
+/// `<rect>` laying it out. The sample count comes from the title, and the stack of each frame
+/// from which frame its `<rect>` sits on. Flame graphs, icicle graphs and differential flame
+/// graphs all work, though the differences of differential ones are lost.
+///
+/// To construct one, either use `svg::Folder::default()` or create an [`Options`] and use
+/// `svg::Folder::from(options)`.
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+/// A frame of a flame graph, as laid out in the SVG.
+#[derive(Clone, Debug)]
+struct Frame {
+    name: String,
+    samples: f64,
+    x: f64,
+    y: f64,
+    width: f64,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
+    where
+        R: io::BufRead,
+        W: io::Write,
+    {
+        let mut input = String::new();
+        reader.read_to_string(&mut input)?;
+        let frames = parse_frames(&input)?;
+
+        let mut occurrences = Occurrences::new(1);
+        if frames.is_empty() {
+            warn!("No flame graph frames found");
+            return occurrences.write_and_clear(writer);
+        }
+
+        // The root is the widest frame, and depths grow away from it: upwards in flame graphs
+        // and downwards in icicle graphs.
+        let root = (0..frames.len())
+            .max_by(|&a, &b| frames[a].width.total_cmp(&frames[b].width))
+            .expect("there are frames");
+        let mut levels: Vec<f64> = frames.iter().map(|frame| frame.y).collect();
+        levels.sort_by(f64::total_cmp);
+        levels.dedup();
+        let root_y = frames[root].y;
+        if root_y - levels[0] > levels[levels.len() - 1] - root_y {
+            levels.reverse();
+        }
+        let depth = |frame: &Frame| {
+            levels
+                .iter()
+                .position(|&y| y == frame.y)
+                .expect("every frame has a level")
+        };
+
+        // The frames of each depth, sorted by where they start.
+        let mut by_depth: Vec<Vec<usize>> = vec![Vec::new(); levels.len()];
+        for (i, frame) in frames.iter().enumerate() {
+            by_depth[depth(frame)].push(i);
+        }
+        for level in &mut by_depth {
+            level.sort_by(|&a, &b| frames[a].x.total_cmp(&frames[b].x));
+        }
+
+        // Each frame sits on the frame below it that covers its middle.
+        let mut parents = vec![None; frames.len()];
+        let mut children_samples = vec![0.0; frames.len()];
+        for d in 1..by_depth.len() {
+            for &i in &by_depth[d] {
+                let middle = frames[i].x + frames[i].width / 2.0;
+                let below = &by_depth[d - 1];
+                let j = below.partition_point(|&j| frames[j].x <= middle);
+                if let Some(&parent) = j.checked_sub(1).map(|j| &below[j]) {
+                    if middle <= frames[parent].x + frames[parent].width {
+                        parents[i] = Some(parent);
+                        children_samples[parent] += frames[i].samples;
+                    }
+                }
+            }
+        }
+
+        let mut orphans = 0;
+        let mut stack = Vec::new();
+        for (i, frame) in frames.iter().enumerate() {
+            let own = (frame.samples - children_samples[i]).round();
+            if own < 1.0 {
+                continue;
+            }
+
+            stack.clear();
+            let mut node = Some(i);
+            while let Some(j) = node {
+                stack.push(frames[j].name.as_str());
+                node = parents[j];
+                if node.is_none() && depth(&frames[j]) != 0 {
+                    orphans += 1;
+                }
+            }
+            if !self.opt.keep_root && stack.len() > 1 {
+                stack.pop();
+            }
+            stack.reverse();
+            occurrences.insert_or_add(stack.join(";"), own as usize);
+        }
+
+        if orphans != 0 {
+            warn!("Found {} frames that don't sit on another frame", orphans);
+        }
+        occurrences.write_and_clear(writer)
+    }
+
+    /// Check for an SVG document
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        let input = input.trim_start();
+        if input.contains("<svg") {
+            Some(true)
+        } else if input.starts_with('<') {
+            None
+        } else {
+            Some(false)
+        }
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+// Finds the frames of a flame graph: the `<rect>`s that follow a `<title>` in the same `<g>`.
+fn parse_frames(input: &str) -> io::Result<Vec<Frame>> {
+    let mut reader = Reader::from_str(input);
+    reader.trim_text(true);
+
+    let mut frames = Vec::new();
+    let mut in_title = false;
+    let mut title: Option<String> = None;
+    loop {
+        match reader.read_event().map_err(invalid_data)? {
+            Event::Start(e) if e.name().as_ref() == b"title" => in_title = true,
+            Event::End(e) if e.name().as_ref() == b"title" => in_title = false,
+            Event::End(e) if e.name().as_ref() == b"g" => title = None,
+            Event::Text(e) if in_title => {
+                title = Some(e.unescape().map_err(invalid_data)?.into_owned());
+            }
+            Event::Start(e) | Event::Empty(e) if e.name().as_ref() == b"rect" => {
+                if let Some(title) = title.take() {
+                    if let Some(frame) = frame(&title, &e)? {
+                        frames.push(frame);
+                    }
+                }
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(frames)
+}
+
+// Makes a frame from a title like `main (1,234 samples, 5.00%)` and the rect that lays it out.
+fn frame(title: &str, rect: &BytesStart<'_>) -> io::Result<Option<Frame>> {
+    let (name, details) = match title.rsplit_once(" (") {
+        Some(parts) => parts,
+        None => return Ok(None),
+    };
+    let samples = details
+        .split(' ')
+        .next()
+        .and_then(|samples| samples.replace(',', "").parse::<f64>().ok());
+    let samples = match samples {
+        Some(samples) => samples,
+        None => return Ok(None),
+    };
+
+    let (mut x, mut y, mut width) = (None, None, None);
+    for attribute in rect.attributes() {
+        let attribute = attribute.map_err(invalid_data)?;
+        let value = attribute.unescape_value().map_err(invalid_data)?;
+        // Inferno lays frames out in percentages, flamegraph.pl in pixels.
+        let value = value.trim_end_matches('%').parse::<f64>().ok();
+        match attribute.key.as_ref() {
+            b"x" => x = value,
+            b"y" => y = value,
+            b"width" => width = value,
+            _ => {}
+        }
+    }
+    Ok(match (x, y, width) {
+        (Some(x), Some(y), Some(width)) => Some(Frame {
+            name: name.to_string(),
+            samples,
+            x,
+            y,
+            width,
+        }),
+        _ => None,
+    })
+}
+
+fn invalid_data<E>(e: E) -> io::Error
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    Error::parse("svg", e).into()
+}
@@ -1,24 +1,273 @@
-use std::{cmp::Ordering, io};
+use std::{cmp::Ordering, collections::HashMap, io, str::FromStr};
 
 use log::warn;
 
-use crate::collapse::{common::Occurrences, Collapse};
+use crate::collapse::common::{split_fields, Occurrences};
+use crate::collapse::Collapse;
+use crate::folded::Header;
+use crate::Error;
 
+// The header of the "Call Tree" view. Only the columns the folder uses need to be there, in any
+// order.
 static START_LINE: &str = "Level,Function Name,Number of Calls,Elapsed Inclusive Time %,Elapsed Exclusive Time %,Avg Elapsed Inclusive Time,Avg Elapsed Exclusive Time,Module Name,";
 
+// The header of the "Caller/Callee" view, which lists each function along with the functions that
+// call it and the functions it calls, instead of the call tree.
+static CALLER_CALLEE_START_LINE: &str = "Type,Function Name,Number of Calls,Elapsed Inclusive Time %,Elapsed Exclusive Time %,Avg Elapsed Inclusive Time,Avg Elapsed Exclusive Time,Module Name,";
+
+// The most stacks that are rebuilt from the call graph of a "Caller/Callee" export. Functions
+// whose callers share callees, like a chain of functions that each call the next one through two
+// others, are reached along exponentially many paths.
+const MAX_CALL_GRAPH_STACKS: usize = 100_000;
+
+// The header of the call tree of the sampling "CPU Usage" tool of the Diagnostics Hub, whose
+// function names are indented by their depth in the tree.
+static CPU_USAGE_START_LINE: &str = "Function Name,Total CPU [unit, %],Self CPU [unit, %],Module";
+
+/// What to weigh the stacks of a Visual Studio profiler export by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum Weight {
+    /// Use the "Number of Calls" column.
+    #[default]
+    Calls,
+
+    /// Use the "Avg Elapsed Inclusive Time" column, multiplied by the number of calls. The time
+    /// spent in a function's callees is subtracted from the function itself.
+    InclusiveTime,
+
+    /// Use the "Avg Elapsed Exclusive Time" column, multiplied by the number of calls.
+    ExclusiveTime,
+}
+
+impl Weight {
+    /// The valid set of weights (via `FromStr`).
+    pub const VARIANTS: &'static [&'static str] = &["calls", "inclusive-time", "exclusive-time"];
+}
+
+impl FromStr for Weight {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "calls" => Ok(Weight::Calls),
+            "inclusive-time" => Ok(Weight::InclusiveTime),
+            "exclusive-time" => Ok(Weight::ExclusiveTime),
+            unknown => Err(format!("unknown weight: {}", unknown)),
+        }
+    }
+}
+
+/// How the "Module Name" column is included in the folded stacks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum ModuleNames {
+    /// Leave the module out (`System.String.IsNullOrEmpty(string)`).
+    #[default]
+    Strip,
+
+    /// Add the module as a prefix of the frame (`mscorlib.dll`System.String.IsNullOrEmpty(string)`).
+    Prefix,
+
+    /// Add the module as an annotation after the function
+    /// (`System.String.IsNullOrEmpty(string) [mscorlib.dll]`).
+    Annotate,
+
+    /// Add the module as a frame of its own, right above the function
+    /// (`mscorlib.dll;System.String.IsNullOrEmpty(string)`).
+    Frame,
+}
+
+impl ModuleNames {
+    /// The valid set of module name modes (via `FromStr`).
+    pub const VARIANTS: &'static [&'static str] = &["strip", "prefix", "annotate", "frame"];
+}
+
+impl FromStr for ModuleNames {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "strip" => Ok(ModuleNames::Strip),
+            "prefix" => Ok(ModuleNames::Prefix),
+            "annotate" => Ok(ModuleNames::Annotate),
+            "frame" => Ok(ModuleNames::Frame),
+            unknown => Err(format!("unknown module name mode: {}", unknown)),
+        }
+    }
+}
+
+/// `vsprof` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct Options {
+    /// What to weigh the stacks by.
+    ///
+    /// Call counts can wildly misrepresent where time goes for fast functions that are called
+    /// very often, so the elapsed time columns can be used instead. Times are weighed in
+    /// microseconds. For "Caller/Callee" exports, both time weights use the exclusive time.
+    /// "CPU Usage" exports are always weighed by their self CPU.
+    ///
+    /// Default is `Weight::Calls`.
+    pub weight: Weight,
+
+    /// How to include the module of each function, so that functions with the same name from
+    /// different assemblies aren't merged.
+    ///
+    /// Default is `ModuleNames::Strip`.
+    pub module_names: ModuleNames,
+
+    /// The positions of the level (or type), function name and number of calls columns, in that
+    /// order and counting from 0, for exports whose columns can't be found from their header.
+    ///
+    /// Default is `None`, which finds the columns by their names in the header. Headers that
+    /// aren't recognized, like translated ones with other columns than usual, have their columns
+    /// found from the first row instead: the function name is the first quoted text, the level
+    /// the first whole number or word, and the number of calls the next whole number.
+    pub column_indices: Option<[usize; 3]>,
+
+    /// Write a [`Header`] with the units of the weights before the folded stacks.
+    ///
+    /// Default is `false`.
+    pub header: bool,
+}
+
 /// A stack collapser for the output of the Visual Studio built in profiler.
+///
+/// Both the "Call Tree" and the "Caller/Callee" CSV exports are supported. Since the latter only
+/// records which functions call each other, and not along which paths, the stacks are rebuilt by
+/// following callees from the functions that have no callers, and the calls of a function are
+/// split among the paths leading to it in proportion to how often each path calls it.
+///
+/// The call tree exports of the sampling "CPU Usage" tool of the Diagnostics Hub are supported
+/// too. Their functions are indented by their depth in the tree, and each function is weighed
+/// by its "Self CPU" column.
+///
+/// Exports of localized Visual Studio installs, with translated column names, `;` delimiters and
+/// `,` decimal separators, are supported too. The function types of localized "Caller/Callee"
+/// exports aren't recognized though.
 #[derive(Default)]
 pub struct Folder {
     /// Function entries on the stack in this entry thus far.
     stack: Vec<(String, usize)>,
+
+    /// Functions of a "Caller/Callee" export, by name.
+    functions: HashMap<String, Function>,
+
+    /// The function the callees of a "Caller/Callee" export currently belong to.
+    current_function: Option<String>,
+
+    /// The field delimiter of the export, which is `;` for some localized exports.
+    delimiter: char,
+
+    /// The positions of the columns of the export.
+    columns: Columns,
+
+    /// Whether this is a "CPU Usage" export, whose stack holds the indentation of each function
+    /// instead of its weight.
+    cpu_usage: bool,
+
+    /// The units of the "Self CPU" column of a "CPU Usage" export.
+    cpu_units: &'static str,
+
+    opt: Options,
+}
+
+/// A function of a "Caller/Callee" export.
+#[derive(Debug, Default)]
+struct Function {
+    /// The number of times the function was called in total.
+    number_of_calls: usize,
+
+    /// The functions this function calls, and how many times it calls them.
+    callees: Vec<(String, usize)>,
+
+    /// Whether any other function calls this function.
+    has_callers: bool,
+
+    /// The time spent in the function itself in total, in microseconds.
+    exclusive_time: usize,
+}
+
+/// The positions of the columns the folder uses, as found in the header of an export.
+#[derive(Clone, Debug, Default)]
+struct Columns {
+    /// "Level" in the call tree, and "Type" in the caller/callee view.
+    level: usize,
+    function_name: usize,
+    number_of_calls: usize,
+    inclusive_time: Option<usize>,
+    exclusive_time: Option<usize>,
+    module_name: Option<usize>,
+    /// "Self CPU" in "CPU Usage" exports.
+    self_cpu: Option<usize>,
+}
+
+impl Columns {
+    /// The columns of an export whose columns haven't been customized, which is what we assume
+    /// for localized exports, since their column names are translated.
+    fn positional() -> Self {
+        Columns {
+            level: 0,
+            function_name: 1,
+            number_of_calls: 2,
+            inclusive_time: Some(5),
+            exclusive_time: Some(6),
+            module_name: Some(7),
+            self_cpu: None,
+        }
+    }
+}
+
+/// The header of an export.
+struct ExportHeader {
+    delimiter: char,
+
+    /// Whether this is a "Caller/Callee" export, if that can be told from the header.
+    caller_callee: Option<bool>,
+
+    /// Whether this is a "CPU Usage" export.
+    cpu_usage: bool,
+
+    /// The units of the "Self CPU" column of a "CPU Usage" export.
+    cpu_units: &'static str,
+
+    columns: Columns,
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder {
+            opt,
+            ..Default::default()
+        }
+    }
 }
 
 impl Collapse for Folder {
-    fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
+    fn collapse<R, W>(&mut self, mut reader: R, mut writer: W) -> io::Result<()>
     where
         R: std::io::BufRead,
         W: std::io::Write,
     {
+        // Visual Studio often writes its reports as UTF-16, which is decoded up front.
+        if let Some(input) = decode_utf16(&mut reader)? {
+            return self.collapse(io::Cursor::new(input), writer);
+        }
+
         // Skip the header
         let mut line = Vec::new();
         if reader.read_until(b'\n', &mut line)? == 0 {
@@ -27,18 +276,68 @@ impl Collapse for Folder {
         };
 
         let header = String::from_utf8_lossy(&line).to_string();
-        if !line_matches_start_line(&header) {
-            return invalid_data_error!(
-                "Expected first line to be header line\n    {}\nbut instead got\n    {}",
-                START_LINE,
-                header
-            );
+        let mut infer_columns = false;
+        let mut caller_callee = match parse_header(&header) {
+            Some(header) => {
+                self.delimiter = header.delimiter;
+                self.columns = header.columns;
+                self.cpu_usage = header.cpu_usage;
+                self.cpu_units = header.cpu_units;
+                header.caller_callee
+            }
+            None => {
+                // A header we don't know, whose columns are found from the first row instead.
+                let line = header.trim().trim_start_matches('\u{feff}');
+                let delimiter = header_delimiter(line);
+                let names = split_fields(line, delimiter);
+                let names = names.iter().filter(|name| !name.trim().is_empty()).count();
+                if names < 3 && self.opt.column_indices.is_none() {
+                    return invalid_data_error!(
+                        line = 1;
+                        "Expected first line to be header line\n    {}\nor\n    {}\nor\n    {}\nbut instead got\n    {}",
+                        START_LINE,
+                        CALLER_CALLEE_START_LINE,
+                        CPU_USAGE_START_LINE,
+                        header
+                    );
+                }
+                self.delimiter = delimiter;
+                self.columns = Columns::default();
+                self.cpu_usage = false;
+                infer_columns = true;
+                None
+            }
+        };
+        if let Some([level, function_name, number_of_calls]) = self.opt.column_indices {
+            self.columns.level = level;
+            self.columns.function_name = function_name;
+            self.columns.number_of_calls = number_of_calls;
+            self.cpu_usage = false;
+            infer_columns = false;
+        }
+
+        if self.opt.weight != Weight::Calls && !self.cpu_usage {
+            let (column, name) = if self.opt.weight == Weight::InclusiveTime {
+                (self.columns.inclusive_time, "Avg Elapsed Inclusive Time")
+            } else {
+                (self.columns.exclusive_time, "Avg Elapsed Exclusive Time")
+            };
+            if column.is_none() {
+                return invalid_data_error!(
+                    line = 1;
+                    "Missing column '{}' in header:\n{}",
+                    name,
+                    header
+                );
+            }
         }
 
         // Process the data
         let mut occurences = Occurrences::new(1);
+        let mut number = 1;
         loop {
             line.clear();
+            number += 1;
             if reader.read_until(b'\n', &mut line)? == 0 {
                 break;
             }
@@ -46,124 +345,466 @@ impl Collapse for Folder {
             let line = l.trim_end();
             if line.is_empty() {
                 continue;
+            }
+
+            // The header of a localized export doesn't tell which view it is, but the first field
+            // does: it's the level in the call tree, and the type of function otherwise.
+            let fields = split_fields(line, self.delimiter);
+            if infer_columns {
+                self.columns = match columns_from_row(&fields, self.delimiter) {
+                    Some(columns) => columns,
+                    None => {
+                        return invalid_data_error!(
+                            line = number;
+                            "Unable to find the level, function name and number of calls columns in line:\n{}",
+                            line
+                        )
+                    }
+                };
+                infer_columns = false;
+            }
+            let caller_callee = *caller_callee.get_or_insert_with(|| {
+                let level = fields.get(self.columns.level).copied().unwrap_or_default();
+                !level.trim_matches('"').chars().all(|c| c.is_ascii_digit())
+            });
+            if self.cpu_usage {
+                self.on_cpu_usage_line(line, &fields, &mut occurences)
+            } else if caller_callee {
+                self.on_caller_callee_line(line, &fields)
             } else {
-                self.on_line(line, &mut occurences)?;
+                self.on_line(line, &fields, &mut occurences)
             }
+            .map_err(|e| Error::at_line(e, number))?;
         }
 
-        self.write_stack(&mut occurences);
+        if self.cpu_usage {
+            // Every function is written as soon as its line is read.
+        } else if caller_callee == Some(true) {
+            self.write_call_graph(&mut occurences);
+        } else if self.opt.weight == Weight::Calls {
+            self.write_stack(&mut occurences);
+        } else {
+            while !self.stack.is_empty() {
+                self.write_stack(&mut occurences);
+                self.stack.pop();
+            }
+        }
 
         // Write the results
+        if self.opt.header {
+            let mut header = Header::for_tool("inferno-collapse-vsprof");
+            header.units = Some(
+                if self.cpu_usage {
+                    self.cpu_units
+                } else if self.opt.weight == Weight::Calls {
+                    "calls"
+                } else {
+                    "microseconds"
+                }
+                .to_string(),
+            );
+            header.write(&mut writer)?;
+        }
         occurences.write_and_clear(writer)?;
 
         // Reset the state
         self.stack.clear();
+        self.functions.clear();
+        self.current_function = None;
         Ok(())
     }
 
     fn is_applicable(&mut self, input: &str) -> Option<bool> {
-        let line = input
-            .lines()
+        let mut lines = input.lines();
+        let line = lines
             .next()
             .expect("there is always at least one line (even if empty)");
 
-        Some(line_matches_start_line(line))
+        match parse_header(line) {
+            Some(ExportHeader {
+                caller_callee: Some(_),
+                ..
+            }) => Some(true),
+            // A translated header only tells us how many columns there are, so also check that
+            // the function names are where we expect them.
+            Some(header) => lines.next().map(|line| {
+                split_fields(line, header.delimiter)
+                    .get(header.columns.function_name)
+                    .is_some_and(|field| field.starts_with('"'))
+            }),
+            None => Some(false),
+        }
     }
 }
 
 impl Folder {
-    // Parse lines with values in the order as specified by `START_LINE`, comma delimited.
+    // Parse lines with values in the columns as specified by the header, comma delimited.
     // Level,Function Name,Number of Calls,...
     // 6,"System.String.IsNullOrEmpty(string)",4,0.00,0.00,0.00,0.00,"mscorlib.dll",
-    fn on_line(&mut self, line: &str, occurences: &mut Occurrences) -> io::Result<()> {
-        let (depth, remainder) = get_next_number(line)?;
+    fn on_line(
+        &mut self,
+        line: &str,
+        fields: &[&str],
+        occurences: &mut Occurrences,
+    ) -> io::Result<()> {
+        let (depth, _) = get_next_number(self.field(fields, self.columns.level), self.delimiter)?;
+        let function_name = self.function_name(line, fields)?;
+        let number_of_calls = self.field(fields, self.columns.number_of_calls);
+        let (number_of_calls, _) = get_next_number(number_of_calls, self.delimiter)?;
+        let function_name = self.frame(function_name, fields);
+
+        if self.opt.weight != Weight::Calls {
+            let weight = self.time_weight(number_of_calls, fields)?;
+            return self.on_timed_line(line, depth, &function_name, weight, occurences);
+        }
 
-        if remainder.is_empty() {
+        let prev_depth = self.stack.len();
+        // The level of a function is the number of functions above it, starting at 0. Levels
+        // that skip ahead also come from columns that were guessed wrong.
+        if depth > prev_depth + 1 {
+            return invalid_data_error!("Skipped indentation level at line:\n{}", line);
+        }
+
+        // There are 3 separate cases to handle regarding the depth:
+        // 1. prev_depth + 1 == depth -> a new function is called, we only need to
+        //    store the function name and the number of times it is called from the
+        //    outer function
+        // 2. prev_depth == depth -> the previous function call was a leaf node, so we
+        //    need to save the current stack and replace the top node with our node
+        //    call
+        // 3. prev_depth > depth -> the previous function call was a leaf node, so we
+        //    need to save the current stack and than we need to pop the top nodes
+        //    until the top node is our parent (i.e. the function which called us)
+        match prev_depth.cmp(&depth) {
+            // Case 1
+            Ordering::Less => {
+                self.stack
+                    .push((function_name.to_string(), number_of_calls));
+            }
+            // Case 2
+            Ordering::Equal => {
+                self.write_stack(occurences);
+                self.stack.pop();
+                self.stack
+                    .push((function_name.to_string(), number_of_calls));
+            }
+            // Case 3
+            Ordering::Greater => {
+                // The Visual Studio profiler outputs the number of times a function is called.
+                //
+                // Let's say we have a function `A()` which is called 500 times, and which
+                // calls a function `B()` 300 times. If we didn't do anything special here,
+                // this would result in `A()` being assigned 800 samples, giving the impression
+                // that `A()` only calls `B()` less than 50% of the time, while in fact it is
+                // called more than 50% of the time.
+                //
+                // To prevent this from happening, we instead subtract the number of calls from
+                // the previous node (in this case `B()`) from the current node (in this case
+                // `A()`. This leaves `A()` with the correct number of 500 samples.
+                //
+                // The top node is always written, but while walking down the stack, if the
+                // previous number of calls is equal to the current number of calls, we don't
+                // want to write the current top node, because that would duplicate the number
+                // of samples for the current node.
+                let mut prev_number_of_calls = 0;
+                for _ in 0..(prev_depth - depth + 1) {
+                    if prev_number_of_calls != self.stack.last().unwrap().1 {
+                        self.write_stack(occurences);
+                    }
+                    prev_number_of_calls = self.stack.pop().unwrap().1;
+
+                    if self.stack.is_empty() {
+                        break;
+                    }
+
+                    let last = self.stack.len() - 1;
+                    let number_of_calls = &self.stack[last].1;
+                    if prev_number_of_calls < *number_of_calls {
+                        self.stack[last].1 -= prev_number_of_calls;
+                    }
+                }
+
+                self.stack
+                    .push((function_name.to_string(), number_of_calls));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Get the field in the given column, or an empty field if the line doesn't have that column.
+    fn field<'a>(&self, fields: &[&'a str], column: usize) -> &'a str {
+        fields.get(column).copied().unwrap_or_default()
+    }
+
+    // Get the function name of a line. Function names are always wrapped in quotes.
+    fn function_name<'a>(&self, line: &str, fields: &[&'a str]) -> io::Result<&'a str> {
+        let field = self.field(fields, self.columns.function_name);
+        if field.is_empty() {
             return invalid_data_error!("Missing function name in line:\n{}", line);
         }
 
-        // Function names are always wrapped in quotes. By trimming the leading double quote, we
-        // know that the next double quote is the double quote closing the function name. Splitting
-        // on this double quote, we get the function name and the remainder of the line.
-        let split = if let Some(remainder) = remainder.strip_prefix('"') {
-            remainder.split_once('"')
+        match field
+            .strip_prefix('"')
+            .and_then(|field| field.strip_suffix('"'))
+        {
+            Some(function_name) => Ok(function_name),
+            None => invalid_data_error!("Unable to parse function name from line:\n{}", line),
+        }
+    }
+
+    // Handle call tree lines when weighing by time. Unlike the number of calls, the time of each
+    // function is known up front, so each stack is written when its function is popped.
+    fn on_timed_line(
+        &mut self,
+        line: &str,
+        depth: usize,
+        function_name: &str,
+        weight: usize,
+        occurrences: &mut Occurrences,
+    ) -> io::Result<()> {
+        // The level of a function is the number of functions above it, starting at 0.
+        if depth > self.stack.len() {
+            return invalid_data_error!("Skipped indentation level at line:\n{}", line);
+        }
+
+        while self.stack.len() > depth {
+            self.write_stack(occurrences);
+            self.stack.pop();
+        }
+
+        if self.opt.weight == Weight::InclusiveTime {
+            if let Some((_, parent_weight)) = self.stack.last_mut() {
+                *parent_weight = parent_weight.saturating_sub(weight);
+            }
+        }
+        self.stack.push((function_name.to_string(), weight));
+
+        Ok(())
+    }
+
+    // Get the frame to use for a function, given the fields of its line.
+    fn frame(&self, function_name: &str, fields: &[&str]) -> String {
+        let module = match self.columns.module_name {
+            Some(column) => self.field(fields, column).trim_matches('"'),
+            None => "",
+        };
+
+        match self.opt.module_names {
+            _ if module.is_empty() => function_name.to_string(),
+            ModuleNames::Strip => function_name.to_string(),
+            ModuleNames::Prefix => format!("{}`{}", module, function_name),
+            ModuleNames::Annotate => format!("{} [{}]", function_name, module),
+            ModuleNames::Frame => format!("{};{}", module, function_name),
+        }
+    }
+
+    // Get the total time of a function, in microseconds, given the fields of its line.
+    fn time_weight(&self, number_of_calls: usize, fields: &[&str]) -> io::Result<usize> {
+        let column = if self.opt.weight == Weight::InclusiveTime {
+            self.columns.inclusive_time
         } else {
-            return invalid_data_error!("Unable to parse function name from line:\n{}", line);
+            self.columns.exclusive_time
+        };
+        let average = match column {
+            Some(column) => get_next_float(self.field(fields, column), self.delimiter)?.0,
+            None => 0.0,
         };
 
-        if let Some((function_name, remainder)) = split {
-            let (number_of_calls, _) = get_next_number(remainder)?;
-
-            let prev_depth = self.stack.len();
-            // There are 3 separate cases to handle regarding the depth:
-            // 1. prev_depth + 1 == depth -> a new function is called, we only need to
-            //    store the function name and the number of times it is called from the
-            //    outer function
-            // 2. prev_depth == depth -> the previous function call was a leaf node, so we
-            //    need to save the current stack and replace the top node with our node
-            //    call
-            // 3. prev_depth > depth -> the previous function call was a leaf node, so we
-            //    need to save the current stack and than we need to pop the top nodes
-            //    until the top node is our parent (i.e. the function which called us)
-            match prev_depth.cmp(&depth) {
-                // Case 1
-                Ordering::Less => {
-                    assert_eq!(prev_depth + 1, depth);
-                    self.stack
-                        .push((function_name.to_string(), number_of_calls));
+        // The times are in milliseconds.
+        Ok((average * number_of_calls as f64 * 1000.0).round() as usize)
+    }
+
+    // Parse lines of the "Caller/Callee" view, where each function is listed as a "Root", preceded
+    // by its callers and followed by its callees.
+    // Type,Function Name,Number of Calls,...
+    // Caller,"Raytracer.Program.Main(string[])",1,0.00,0.00,0.00,0.00,"Raytracer.exe",
+    // Root,"Raytracer.Raytracer.Render()",1,0.00,0.00,0.00,0.00,"Raytracer.exe",
+    // Callee,"Raytracer.Raytracer.Tracer.TraceImage()","2,400",0.00,0.00,0.00,0.00,"Raytracer.exe",
+    fn on_caller_callee_line(&mut self, line: &str, fields: &[&str]) -> io::Result<()> {
+        let kind = self.field(fields, self.columns.level).trim_matches('"');
+        let function_name = self.function_name(line, fields)?;
+        let number_of_calls = self.field(fields, self.columns.number_of_calls);
+        let (number_of_calls, _) = get_next_number(number_of_calls, self.delimiter)?;
+        let function_name = self.frame(function_name, fields);
+
+        match kind {
+            "Root" => {
+                let exclusive_time = match (self.opt.weight, self.columns.exclusive_time) {
+                    (Weight::Calls, _) | (_, None) => 0,
+                    (_, Some(column)) => {
+                        let (exclusive, _) =
+                            get_next_float(self.field(fields, column), self.delimiter)?;
+                        (exclusive * number_of_calls as f64 * 1000.0).round() as usize
+                    }
+                };
+                let function = self.functions.entry(function_name.clone()).or_default();
+                function.number_of_calls += number_of_calls;
+                function.exclusive_time += exclusive_time;
+                self.current_function = Some(function_name);
+            }
+            "Callee" => {
+                let caller = match self.current_function {
+                    Some(ref caller) => caller.clone(),
+                    None => {
+                        return invalid_data_error!("Found callee before its caller:\n{}", line)
+                    }
+                };
+                if caller != function_name {
+                    self.functions
+                        .entry(function_name.to_string())
+                        .or_default()
+                        .has_callers = true;
                 }
-                // Case 2
-                Ordering::Equal => {
-                    self.write_stack(occurences);
-                    self.stack.pop();
-                    self.stack
-                        .push((function_name.to_string(), number_of_calls));
+                self.functions
+                    .entry(caller)
+                    .or_default()
+                    .callees
+                    .push((function_name.to_string(), number_of_calls));
+            }
+            // The callers of a function are also listed as its callees elsewhere.
+            "Caller" => {}
+            _ => return invalid_data_error!("Unknown function type '{}' in line:\n{}", kind, line),
+        }
+
+        Ok(())
+    }
+
+    // Parse lines of the "CPU Usage" call tree, whose function names are indented by their depth,
+    // and whose CPU columns have the CPU along with its share of the total.
+    // Function Name,Total CPU [unit, %],Self CPU [unit, %],Module
+    // "  | - Raytracer.Program.Main(string[])","1,248 (98.27%)","12 (0.94%)","Raytracer.exe"
+    fn on_cpu_usage_line(
+        &mut self,
+        line: &str,
+        fields: &[&str],
+        occurrences: &mut Occurrences,
+    ) -> io::Result<()> {
+        let field = self.field(fields, self.columns.function_name);
+        let field = field
+            .strip_prefix('"')
+            .and_then(|field| field.strip_suffix('"'))
+            .unwrap_or(field);
+        let name_start = field
+            .find(|c: char| !c.is_whitespace() && c != '|' && c != '-' && c != '+')
+            .unwrap_or(field.len());
+        let (indentation, function_name) = field.split_at(name_start);
+        let function_name = function_name.trim_end();
+        if function_name.is_empty() {
+            return invalid_data_error!("Missing function name in line:\n{}", line);
+        }
+
+        let self_cpu = match self.columns.self_cpu {
+            Some(column) => self.field(fields, column).trim_matches('"'),
+            None => "",
+        };
+        // Like `1,248 (98.27%)`, or just the CPU in some versions.
+        let self_cpu = self_cpu.split('(').next().unwrap_or_default().trim();
+        let self_cpu = match parse_float(self_cpu) {
+            Some(self_cpu) => self_cpu.round() as usize,
+            None => return invalid_data_error!("Unable to parse Self CPU from line:\n{}", line),
+        };
+
+        // A function is a child of the last function that is indented less.
+        let depth = indentation.chars().count();
+        while self.stack.last().is_some_and(|(_, d)| *d >= depth) {
+            self.stack.pop();
+        }
+        let frame = self.frame(function_name, fields);
+        self.stack.push((frame, depth));
+
+        if self_cpu > 0 {
+            let functions: Vec<_> = self.stack.iter().map(|(f, _)| &f[..]).collect();
+            occurrences.insert_or_add(functions.join(";"), self_cpu);
+        }
+        Ok(())
+    }
+
+    // Store the stacks of a "Caller/Callee" export in `occurrences`, starting from the functions
+    // that aren't called by any other function.
+    fn write_call_graph(&self, occurrences: &mut Occurrences) {
+        let mut stack = Vec::new();
+        let mut budget = MAX_CALL_GRAPH_STACKS;
+        for (function_name, function) in &self.functions {
+            if !function.has_callers {
+                self.write_callees(
+                    function_name,
+                    function.number_of_calls,
+                    &mut stack,
+                    &mut budget,
+                    occurrences,
+                );
+            }
+        }
+        if budget == 0 {
+            warn!(
+                "The call graph has too many paths, so only {} stacks were rebuilt; the calls \
+                 of the functions beyond them are left with their callers",
+                MAX_CALL_GRAPH_STACKS
+            );
+        }
+    }
+
+    // Once `budget` stacks have been rebuilt, the callees of the remaining functions aren't
+    // followed anymore.
+    fn write_callees<'a>(
+        &'a self,
+        function_name: &'a str,
+        number_of_calls: usize,
+        stack: &mut Vec<&'a str>,
+        budget: &mut usize,
+        occurrences: &mut Occurrences,
+    ) {
+        stack.push(function_name);
+        *budget = budget.saturating_sub(1);
+
+        let mut callee_calls = 0;
+        let function = self.functions.get(function_name);
+        if let Some(function) = function {
+            for (callee, calls) in &function.callees {
+                if *budget == 0 {
+                    break;
+                }
+                // Recursive calls would never end, and are already part of the current function.
+                if stack.contains(&&callee[..]) {
+                    continue;
                 }
-                // Case 3
-                Ordering::Greater => {
-                    // The Visual Studio profiler outputs the number of times a function is called.
-                    //
-                    // Let's say we have a function `A()` which is called 500 times, and which
-                    // calls a function `B()` 300 times. If we didn't do anything special here,
-                    // this would result in `A()` being assigned 800 samples, giving the impression
-                    // that `A()` only calls `B()` less than 50% of the time, while in fact it is
-                    // called more than 50% of the time.
-                    //
-                    // To prevent this from happening, we instead subtract the number of calls from
-                    // the previous node (in this case `B()`) from the current node (in this case
-                    // `A()`. This leaves `A()` with the correct number of 500 samples.
-                    //
-                    // The top node is always written, but while walking down the stack, if the
-                    // previous number of calls is equal to the current number of calls, we don't
-                    // want to write the current top node, because that would duplicate the number
-                    // of samples for the current node.
-                    let mut prev_number_of_calls = 0;
-                    for _ in 0..(prev_depth - depth + 1) {
-                        if prev_number_of_calls != self.stack.last().unwrap().1 {
-                            self.write_stack(occurences);
-                        }
-                        prev_number_of_calls = self.stack.pop().unwrap().1;
-
-                        if self.stack.is_empty() {
-                            break;
-                        }
-
-                        let last = self.stack.len() - 1;
-                        let number_of_calls = &self.stack[last].1;
-                        if prev_number_of_calls < *number_of_calls {
-                            self.stack[last].1 -= prev_number_of_calls;
-                        }
-                    }
 
-                    self.stack
-                        .push((function_name.to_string(), number_of_calls));
+                // Only the share of the callee's calls that comes from the calls along this path.
+                let calls = if number_of_calls < function.number_of_calls {
+                    (*calls as u128 * number_of_calls as u128 / function.number_of_calls as u128)
+                        as usize
+                } else {
+                    *calls
+                };
+                if calls > 0 {
+                    callee_calls += calls;
+                    self.write_callees(callee, calls, stack, budget, occurrences);
                 }
             }
-        } else {
-            return invalid_data_error!("Unable to parse function name from line:\n{}", line);
         }
 
-        Ok(())
+        let nsamples = match function {
+            // The share of the function's own time that comes from the calls along this path.
+            Some(function) if self.opt.weight != Weight::Calls => {
+                if number_of_calls < function.number_of_calls {
+                    (function.exclusive_time as u128 * number_of_calls as u128
+                        / function.number_of_calls as u128) as usize
+                } else {
+                    function.exclusive_time
+                }
+            }
+            // Callees without a row of their own have no known time.
+            None if self.opt.weight != Weight::Calls => 0,
+            // Like with the call tree, the calls of the callees are subtracted from the calls of
+            // the function itself.
+            _ => number_of_calls.saturating_sub(callee_calls),
+        };
+        if nsamples > 0 {
+            occurrences.insert_or_add(stack.join(";"), nsamples);
+        }
+
+        stack.pop();
     }
 
     // Store the current stack in `occurences`
@@ -175,17 +816,44 @@ impl Folder {
     }
 }
 
+/// Reads the whole input if it starts with the byte order mark of UTF-16, in either byte order,
+/// and decodes it.
+fn decode_utf16<R: io::BufRead>(reader: &mut R) -> io::Result<Option<String>> {
+    let big_endian = match reader.fill_buf()? {
+        [0xff, 0xfe, ..] => false,
+        [0xfe, 0xff, ..] => true,
+        _ => return Ok(None),
+    };
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    let units: Vec<u16> = bytes[2..]
+        .chunks_exact(2)
+        .map(|unit| {
+            if big_endian {
+                u16::from_be_bytes([unit[0], unit[1]])
+            } else {
+                u16::from_le_bytes([unit[0], unit[1]])
+            }
+        })
+        .collect();
+    Ok(Some(String::from_utf16_lossy(&units)))
+}
+
 /// Gets the number from the start of the line. This can either be a number <1000, in which case the
 /// line doesn't contain double quotes, or the number can be >1000, in which case the line does
-/// contain double quotes. In both cases `line` may start with a leading comma, which will be
+/// contain double quotes. In both cases `line` may start with a leading `delimiter`, which will be
 /// ignored.
 ///
+/// Localized exports may use `.` or (non-breaking) spaces as thousands separators, and `;` as the
+/// delimiter.
+///
 /// ### Example inputs
 /// - Number <1000: `471,91.25,18.39,401.92,81.02,"Raytracer.exe",`
 /// - Number >1000: `"2,893,824",54.37,4.21,0.04,0.00,"Raytracer.exe",`
-fn get_next_number(line: &str) -> io::Result<(usize, &str)> {
-    // Trim the leading comma, if any
-    let line = line.strip_prefix(',').unwrap_or(line);
+/// - Localized: `2.893.824;54,37;4,21;0,04;0,00;"Raytracer.exe";`
+fn get_next_number(line: &str, delimiter: char) -> io::Result<(usize, &str)> {
+    // Trim the leading delimiter, if any
+    let line = line.strip_prefix(delimiter).unwrap_or(line);
 
     // If the number is >1000, it is wrapped in quotes, so we need to remove those, and make sure
     // that we also remove the leading comma from the remainder after we are done parsing the
@@ -197,7 +865,7 @@ fn get_next_number(line: &str) -> io::Result<(usize, &str)> {
         remove_leading_comma = true;
         line.split_once('"')
     } else {
-        line.split_once(',').or(Some((line, "")))
+        line.split_once(delimiter).or(Some((line, "")))
     };
 
     // Parse the number
@@ -219,7 +887,7 @@ fn get_next_number(line: &str) -> io::Result<(usize, &str)> {
                 continue;
             }
 
-            if c == ',' || c == '.' || c == ' ' {
+            if c == ',' || c == '.' || c == ' ' || c == '\u{a0}' || c == '\u{202f}' {
                 if !initial && current_group_count < 3 {
                     return invalid_data_error!("Missing thousands separator in number '{}'", num);
                 }
@@ -247,7 +915,7 @@ fn get_next_number(line: &str) -> io::Result<(usize, &str)> {
             // `remainder` still has a leading comma, because the number is >1000. We need to
             // remove it so we are consistent regardless of whether the number was wrapped in
             // double quotes or not.
-            remainder = remainder.strip_prefix(',').unwrap_or(remainder);
+            remainder = remainder.strip_prefix(delimiter).unwrap_or(remainder);
         }
 
         return Ok((n as usize, remainder));
@@ -256,22 +924,200 @@ fn get_next_number(line: &str) -> io::Result<(usize, &str)> {
     invalid_data_error!("Invalid number in line:\n{}", line)
 }
 
-/// Some files may start with the <U+FEFF> character (zero width no-break space). This
-/// causes the call to `starts_with` to return false, which in this case isn't what we want.
-/// As this character has no influence on the rest of the file, we can safely ignore it.
-fn line_matches_start_line(line: &str) -> bool {
-    line.trim()
-        .trim_start_matches('\u{feff}')
-        .starts_with(START_LINE)
+/// Gets the floating point number from the start of the line, like `91.25`, `"1,000.25"` or
+/// (in localized exports) `91,25`. The line may start with a leading `delimiter`, which will be
+/// ignored.
+fn get_next_float(line: &str, delimiter: char) -> io::Result<(f64, &str)> {
+    // Trim the leading delimiter, if any
+    let line = line.strip_prefix(delimiter).unwrap_or(line);
+
+    // Numbers >1000 are wrapped in quotes and contain thousands separators.
+    let (num, remainder) = if let Some(line) = line.strip_prefix('"') {
+        match line.split_once('"') {
+            Some((num, remainder)) => (num, remainder.strip_prefix(delimiter).unwrap_or(remainder)),
+            None => return invalid_data_error!("Invalid number in line:\n{}", line),
+        }
+    } else {
+        line.split_once(delimiter).unwrap_or((line, ""))
+    };
+
+    match parse_float(num) {
+        Some(n) => Ok((n, remainder)),
+        None => invalid_data_error!("Unable to parse number from '{}'", num),
+    }
+}
+
+/// Parses a floating point number that may use either `.` or `,` as its decimal separator, and
+/// any of `.`, `,` or (non-breaking) spaces as thousands separators.
+///
+/// The last `.` or `,` is the decimal separator, unless it's used more than once or is followed
+/// by exactly three digits with no other separator before it, in which case it separates
+/// thousands. Visual Studio writes times and percentages with two decimals, so this is only
+/// ambiguous for values it doesn't produce.
+fn parse_float(num: &str) -> Option<f64> {
+    let num: String = num.chars().filter(|c| !c.is_whitespace()).collect();
+    let decimal = num.rfind(&['.', ','][..]).filter(|&i| {
+        let separator = &num[i..=i];
+        let other = if separator == "." { ',' } else { '.' };
+        num.matches(separator).count() == 1 && (num.len() - i - 1 != 3 || num[..i].contains(other))
+    });
+
+    let mut normalized = String::with_capacity(num.len());
+    for (i, c) in num.char_indices() {
+        match c {
+            _ if Some(i) == decimal => normalized.push('.'),
+            '.' | ',' => {}
+            c => normalized.push(c),
+        }
+    }
+    normalized.parse().ok()
+}
+
+/// Finds the field delimiter of an export from its header line.
+fn header_delimiter(line: &str) -> char {
+    if line.contains('\t') {
+        '\t'
+    } else if line.contains(';') {
+        ';'
+    } else {
+        ','
+    }
+}
+
+/// Finds the columns of an export with an unknown header from the fields of its first row. The
+/// function name is the first quoted text, the level (or type) is the first whole number or word
+/// that isn't quoted, and the number of calls is the next whole number. The module name is the
+/// last quoted text after the function name, if there is one.
+fn columns_from_row(fields: &[&str], delimiter: char) -> Option<Columns> {
+    let is_text = |field: &str| {
+        field.len() > 1
+            && field.starts_with('"')
+            && field.ends_with('"')
+            && get_next_number(field, delimiter).is_err()
+            && parse_float(field.trim_matches('"')).is_none()
+    };
+    let is_number = |field: &str| !field.is_empty() && get_next_number(field, delimiter).is_ok();
+    let is_word = |field: &str| !field.is_empty() && field.chars().all(char::is_alphabetic);
+
+    let function_name = fields.iter().position(|field| is_text(field))?;
+    let level = (0..fields.len())
+        .find(|&i| i != function_name && (is_number(fields[i]) || is_word(fields[i])))?;
+    let number_of_calls =
+        (level + 1..fields.len()).find(|&i| i != function_name && is_number(fields[i]))?;
+    let module_name = (function_name + 1..fields.len())
+        .rev()
+        .find(|&i| is_text(fields[i]) || fields[i] == "\"\"");
+    Some(Columns {
+        level,
+        function_name,
+        number_of_calls,
+        module_name,
+        ..Default::default()
+    })
+}
+
+/// Parses the header line of an export, finding the columns by name, so extra and reordered
+/// columns are fine.
+///
+/// Localized exports translate the column names, so if the header doesn't have the English ones,
+/// it's only checked to have the expected number of columns, which are then assumed to be in the
+/// default order.
+fn parse_header(line: &str) -> Option<ExportHeader> {
+    // Some files may start with the <U+FEFF> character (zero width no-break space). As this
+    // character has no influence on the rest of the file, we can safely ignore it.
+    let line = line.trim().trim_start_matches('\u{feff}');
+    let delimiter = header_delimiter(line);
+    // The CPU columns of "CPU Usage" exports, like `Self CPU [unit, %]`, aren't always quoted.
+    let mut names: Vec<String> = Vec::new();
+    for name in split_fields(line, delimiter) {
+        let name = name.trim().trim_matches('"');
+        match names.last_mut() {
+            Some(last) if last.contains('[') && !last.contains(']') => {
+                last.push(delimiter);
+                last.push_str(name);
+            }
+            _ => names.push(name.to_string()),
+        }
+    }
+    let position = |name: &str| names.iter().position(|column| *column == name);
+    let position_prefix = |prefix: &str| names.iter().position(|column| column.starts_with(prefix));
+
+    if let (Some(function_name), Some(self_cpu), Some(_)) = (
+        position("Function Name"),
+        position_prefix("Self CPU"),
+        position_prefix("Total CPU"),
+    ) {
+        return Some(ExportHeader {
+            delimiter,
+            caller_callee: Some(false),
+            cpu_usage: true,
+            // The units are named in brackets, like `Self CPU [ms, %]`, or left as `[unit, %]`
+            // for samples.
+            cpu_units: if names[self_cpu].contains("[ms") {
+                "milliseconds"
+            } else {
+                "samples"
+            },
+            columns: Columns {
+                function_name,
+                self_cpu: Some(self_cpu),
+                module_name: position("Module").or_else(|| position("Module Name")),
+                ..Default::default()
+            },
+        });
+    }
+
+    let caller_callee = match (position("Level"), position("Type")) {
+        (Some(level), _) => Some((level, false)),
+        (None, Some(level)) => Some((level, true)),
+        (None, None) => None,
+    };
+    if let (Some((level, caller_callee)), Some(function_name), Some(number_of_calls)) = (
+        caller_callee,
+        position("Function Name"),
+        position("Number of Calls"),
+    ) {
+        return Some(ExportHeader {
+            delimiter,
+            caller_callee: Some(caller_callee),
+            cpu_usage: false,
+            cpu_units: "",
+            columns: Columns {
+                level,
+                function_name,
+                number_of_calls,
+                inclusive_time: position("Avg Elapsed Inclusive Time"),
+                exclusive_time: position("Avg Elapsed Exclusive Time"),
+                module_name: position("Module Name"),
+                self_cpu: None,
+            },
+        });
+    }
+
+    let default_columns = START_LINE
+        .split(',')
+        .filter(|name| !name.is_empty())
+        .count();
+    if names.iter().filter(|name| !name.is_empty()).count() == default_columns {
+        Some(ExportHeader {
+            delimiter,
+            caller_callee: None,
+            cpu_usage: false,
+            cpu_units: "",
+            columns: Columns::positional(),
+        })
+    } else {
+        None
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::get_next_number;
+    use super::{get_next_float, get_next_number};
 
     #[test]
     fn get_next_number_default() {
-        let result = get_next_number(r#"471,91.25,18.39,401.92,81.02,"Raytracer.exe","#);
+        let result = get_next_number(r#"471,91.25,18.39,401.92,81.02,"Raytracer.exe","#, ',');
         assert!(result.is_ok());
 
         let (result, _) = result.unwrap();
@@ -280,7 +1126,7 @@ mod tests {
 
     #[test]
     fn get_next_number_with_leading_comma() {
-        let result = get_next_number(r#",471,91.25,18.39,401.92,81.02,"Raytracer.exe","#);
+        let result = get_next_number(r#",471,91.25,18.39,401.92,81.02,"Raytracer.exe","#, ',');
         assert!(result.is_ok());
 
         let (result, _) = result.unwrap();
@@ -289,7 +1135,7 @@ mod tests {
 
     #[test]
     fn get_next_number_with_thousands_sep() {
-        let result = get_next_number(r#""2,893,824",54.37,4.21,0.04,0.00,"Raytracer.exe","#);
+        let result = get_next_number(r#""2,893,824",54.37,4.21,0.04,0.00,"Raytracer.exe","#, ',');
         assert!(result.is_ok());
 
         let (result, _) = result.unwrap();
@@ -298,21 +1144,71 @@ mod tests {
 
     #[test]
     fn get_next_number_missing_thousands_seps() {
-        assert!(get_next_number(r#""2893824",54.37,4.21,0.04,0.00,"Raytracer.exe","#).is_err());
+        assert!(
+            get_next_number(r#""2893824",54.37,4.21,0.04,0.00,"Raytracer.exe","#, ',').is_err()
+        );
     }
 
     #[test]
     fn get_next_number_missing_thousands_sep() {
-        assert!(get_next_number(r#""28,93824",54.37,4.21,0.04,0.00,"Raytracer.exe","#).is_err());
+        assert!(
+            get_next_number(r#""28,93824",54.37,4.21,0.04,0.00,"Raytracer.exe","#, ',').is_err()
+        );
     }
 
     #[test]
     fn get_next_number_with_float() {
-        assert!(get_next_number(r#""2,893.82",54.37,4.21,0.04,0.00,"Raytracer.exe","#).is_err());
+        assert!(
+            get_next_number(r#""2,893.82",54.37,4.21,0.04,0.00,"Raytracer.exe","#, ',').is_err()
+        );
     }
 
     #[test]
     fn get_next_number_with_text_input() {
-        assert!(get_next_number(r#""text",54.37,4.21,0.04,0.00,"Raytracer.exe","#).is_err());
+        assert!(get_next_number(r#""text",54.37,4.21,0.04,0.00,"Raytracer.exe","#, ',').is_err());
+    }
+
+    #[test]
+    fn get_next_float_default() {
+        let (result, remainder) = get_next_float(r#"91.25,18.39,"Raytracer.exe","#, ',').unwrap();
+        assert_eq!(result, 91.25);
+        assert_eq!(remainder, r#"18.39,"Raytracer.exe","#);
+    }
+
+    #[test]
+    fn get_next_float_with_thousands_sep() {
+        let (result, remainder) =
+            get_next_float(r#","1,000.25",81.02,"Raytracer.exe","#, ',').unwrap();
+        assert_eq!(result, 1000.25);
+        assert_eq!(remainder, r#"81.02,"Raytracer.exe","#);
+    }
+
+    #[test]
+    fn get_next_float_with_text_input() {
+        assert!(get_next_float(r#""text",54.37,"Raytracer.exe","#, ',').is_err());
+    }
+
+    #[test]
+    fn get_next_number_localized() {
+        let (result, remainder) =
+            get_next_number(r#"2.893.824;54,37;"Raytracer.exe";"#, ';').unwrap();
+        assert_eq!(result, 2_893_824);
+        assert_eq!(remainder, r#"54,37;"Raytracer.exe";"#);
+
+        let (result, _) = get_next_number("2\u{a0}893\u{a0}824;54,37", ';').unwrap();
+        assert_eq!(result, 2_893_824);
+    }
+
+    #[test]
+    fn get_next_float_localized() {
+        let (result, remainder) = get_next_float(r#";54,37;"Raytracer.exe";"#, ';').unwrap();
+        assert_eq!(result, 54.37);
+        assert_eq!(remainder, r#""Raytracer.exe";"#);
+
+        let (result, _) = get_next_float(r#""1.000,25";81,02"#, ';').unwrap();
+        assert_eq!(result, 1000.25);
+
+        let (result, _) = get_next_float(r#""1 000,25",81.02"#, ',').unwrap();
+        assert_eq!(result, 1000.25);
     }
 }
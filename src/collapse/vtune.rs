@@ -1,59 +1,228 @@
 use std::io::{self, BufRead};
+use std::str::FromStr;
 
 use log::warn;
 
-use crate::collapse::common::Occurrences;
-use crate::collapse::Collapse;
+use crate::collapse::common::{self, CollapsePrivate, Occurrences};
+use crate::folded::Header;
+use crate::Error;
 
-// The call graph begins after this line.
-static HEADER: &str = "Function Stack,CPU Time:Self,Module";
+// The call graph begins after the header line, which starts with this.
+static HEADER: &str = "Function Stack,";
+
+// The column used for weights if none is selected, and the one `amplxe-cl` is usually told to
+// export.
+static DEFAULT_WEIGHT_COLUMN: &str = "CPU Time:Self";
+
+// The column holding the module of each function.
+static MODULE_COLUMN: &str = "Module";
+
+/// How the module of each function is kept in the folded stacks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum ModuleNames {
+    /// Keep the module as a prefix of the frame (`libstdc++.so.6`std::sort<int*>`).
+    #[default]
+    Prefix,
+
+    /// Drop the module (`std::sort<int*>`).
+    Strip,
+
+    /// Keep the module as an annotation after the function (`std::sort<int*> [libstdc++.so.6]`).
+    Annotate,
+
+    /// Keep the module as a frame of its own, right above the function
+    /// (`libstdc++.so.6;std::sort<int*>`).
+    Frame,
+
+    /// Replace every run of functions from the same module with a single frame named after the
+    /// module (`libstdc++.so.6`), to see which modules the time goes to.
+    Collapse,
+}
+
+impl ModuleNames {
+    /// The valid set of module name modes (via `FromStr`).
+    pub const VARIANTS: &'static [&'static str] =
+        &["prefix", "strip", "annotate", "frame", "collapse"];
+}
+
+impl FromStr for ModuleNames {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "prefix" => Ok(ModuleNames::Prefix),
+            "strip" => Ok(ModuleNames::Strip),
+            "annotate" => Ok(ModuleNames::Annotate),
+            "frame" => Ok(ModuleNames::Frame),
+            "collapse" => Ok(ModuleNames::Collapse),
+            unknown => Err(format!("unknown module name mode: {}", unknown)),
+        }
+    }
+}
 
 /// `vtune` folder configuration options.
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug)]
 #[non_exhaustive]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
 pub struct Options {
-    /// Don't include modules with function names.
+    /// Write a [`Header`] with the metric of the weights before the folded stacks.
+    ///
+    /// Default is `false`.
+    pub header: bool,
+
+    /// Don't include modules with function names. Same as `ModuleNames::Strip`, and overrides
+    /// `module_names`.
     ///
     /// Default is `false`.
     pub no_modules: bool,
+
+    /// How to keep the module of each function.
+    ///
+    /// Default is `ModuleNames::Prefix`.
+    pub module_names: ModuleNames,
+
+    /// The metric column to use as the weight of each stack, like `CPU Time`, `Instructions
+    /// Retired` or `Clockticks`, for exports with several metric columns.
+    ///
+    /// The column's `:Self` variant is used if the export has one, since the total values
+    /// include the weight of callees. Times are in seconds and are converted to milliseconds;
+    /// any other metric is used as is.
+    ///
+    /// Default is `None`, which uses `CPU Time:Self`, or the first metric column if the export
+    /// doesn't have that one.
+    pub weight_column: Option<String>,
+
+    /// Keep the process and thread grouping rows of exports grouped by thread (like
+    /// `app (PID: 1234)` and `worker (TID: 1235)`) as root frames, so each thread gets its own
+    /// part of the flame graph.
+    ///
+    /// Otherwise, the grouping rows are left out and the stacks of all threads are merged.
+    ///
+    /// Default is `false`.
+    pub group_roots: bool,
+
+    /// The number of threads to use.
+    ///
+    /// Default is the number of logical cores on your machine.
+    pub nthreads: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            header: false,
+            no_modules: false,
+            module_names: ModuleNames::default(),
+            weight_column: None,
+            group_roots: false,
+            nthreads: *common::DEFAULT_NTHREADS,
+        }
+    }
 }
 
 /// A stack collapser for CSV call graphs created with the VTune `amplxe-cl` tool.
 ///
 /// To construct one, either use `vtune::Folder::default()` or create an [`Options`] and use
 /// `vtune::Folder::from(options)`.
-#[derive(Clone, Default)]
 pub struct Folder {
-    /// Function on the stack in this entry thus far.
-    stack: Vec<String>,
+    /// Frames of the rows on the stack in this entry thus far, with the module of each row.
+    stack: Vec<(String, String)>,
+
+    /// Number of process and thread grouping rows at the root of the stack.
+    groups: usize,
+
+    /// The columns after `Function Stack` that hold the weight and module of each function.
+    columns: Columns,
+
+    /// The rows from the root down to the last row seen by `would_start_stack`, with their
+    /// weights set to zero.
+    context: Vec<Vec<u8>>,
+
+    /// The number of stacks per job to send to the threadpool.
+    nstacks_per_job: usize,
 
     opt: Options,
 }
 
-impl Collapse for Folder {
-    fn collapse<R, W>(&mut self, mut reader: R, writer: W) -> io::Result<()>
+#[derive(Clone, Debug, Default)]
+struct Columns {
+    weight: usize,
+    weight_name: String,
+    weight_scale: f64,
+    module: Option<usize>,
+}
+
+impl From<Options> for Folder {
+    fn from(mut opt: Options) -> Self {
+        if opt.nthreads == 0 {
+            opt.nthreads = 1;
+        }
+        Self {
+            stack: Vec::new(),
+            groups: 0,
+            columns: Columns::default(),
+            context: Vec::new(),
+            nstacks_per_job: common::DEFAULT_NSTACKS_PER_JOB,
+            opt,
+        }
+    }
+}
+
+impl Default for Folder {
+    fn default() -> Self {
+        Options::default().into()
+    }
+}
+
+impl CollapsePrivate for Folder {
+    fn pre_process<R>(&mut self, reader: &mut R, _: &mut Occurrences) -> io::Result<()>
     where
         R: io::BufRead,
-        W: io::Write,
     {
         // Consume the header...
+        self.columns = Columns::default();
+        self.context.clear();
         let mut line = Vec::new();
+        let mut number = 0;
         loop {
             line.clear();
+            number += 1;
             if reader.read_until(0x0A, &mut line)? == 0 {
                 warn!("File ended before header");
                 return Ok(());
             };
             let l = String::from_utf8_lossy(&line);
             if l.starts_with(HEADER) {
-                break;
+                self.columns = self
+                    .columns(l.trim_end())
+                    .map_err(|e| Error::at_line(e, number))?;
+                return Ok(());
             }
         }
+    }
 
-        // Process the data...
-        let mut occurrences = Occurrences::new(1);
+    fn collapse_single_threaded<R>(
+        &mut self,
+        mut reader: R,
+        occurrences: &mut Occurrences,
+    ) -> io::Result<()>
+    where
+        R: io::BufRead,
+    {
+        let mut line = Vec::new();
+        let mut number = 0;
         loop {
             line.clear();
+            number += 1;
             if reader.read_until(0x0A, &mut line)? == 0 {
                 break;
             }
@@ -62,15 +231,14 @@ impl Collapse for Folder {
             if line.is_empty() {
                 continue;
             } else {
-                self.on_line(line, &mut occurrences)?;
+                self.on_line(line, occurrences)
+                    .map_err(|e| Error::at_line(e, number))?;
             }
         }
 
-        // Write the results...
-        occurrences.write_and_clear(writer)?;
-
         // Reset the state...
         self.stack.clear();
+        self.groups = 0;
         Ok(())
     }
 
@@ -94,38 +262,147 @@ impl Collapse for Folder {
         }
         None
     }
-}
 
-impl From<Options> for Folder {
-    fn from(opt: Options) -> Self {
-        Folder {
-            opt,
-            ..Default::default()
+    // Every row of the call graph is a stack of its own, whose weight is the row's self weight,
+    // so chunks can be cut before any row, given the rows above it.
+    fn would_end_stack(&mut self, _line: &[u8]) -> bool {
+        false
+    }
+
+    fn would_start_stack(&mut self, line: &[u8]) -> bool {
+        let line = String::from_utf8_lossy(line);
+        let line = line.trim_end();
+        if line.is_empty() {
+            return false;
+        }
+        let depth = line.len() - line.trim_start_matches(' ').len();
+        self.context.truncate(depth);
+        self.context.push(self.without_weight(line));
+        true
+    }
+
+    fn stack_context(&self) -> Vec<u8> {
+        match self.context.split_last() {
+            Some((_, ancestors)) => ancestors.concat(),
+            None => Vec::new(),
+        }
+    }
+
+    fn clone_and_reset_stack_context(&self) -> Self {
+        Self {
+            stack: Vec::new(),
+            groups: 0,
+            columns: self.columns.clone(),
+            context: Vec::new(),
+            nstacks_per_job: self.nstacks_per_job,
+            opt: self.opt.clone(),
         }
     }
+
+    fn nstacks_per_job(&self) -> usize {
+        self.nstacks_per_job
+    }
+
+    fn set_nstacks_per_job(&mut self, n: usize) {
+        self.nstacks_per_job = n;
+    }
+
+    fn nthreads(&self) -> usize {
+        self.opt.nthreads
+    }
+
+    fn set_nthreads(&mut self, n: usize) {
+        self.opt.nthreads = n;
+    }
+
+    fn header(&self) -> Option<Header> {
+        // There are no weights to describe if the export didn't have a header.
+        if !self.opt.header || self.columns.weight_name.is_empty() {
+            return None;
+        }
+        let mut header = Header::for_tool("inferno-collapse-vtune");
+        let metric = self.columns.weight_name.trim_end_matches(":Self");
+        header.event = Some(metric.to_string());
+        header.units = Some(if self.columns.weight_scale == 1.0 {
+            metric.to_string()
+        } else {
+            "milliseconds".to_string()
+        });
+        Some(header)
+    }
 }
 
 impl Folder {
-    fn line_parts<'a>(&self, line: &'a str) -> Option<(&'a str, &'a str, &'a str)> {
-        let mut line = if let Some(line) = line.strip_prefix('"') {
-            // The function name will be in quotes if it contains spaces.
-            line.splitn(2, "\",")
-        } else {
-            // We split on a string because we need to match the type of the other if branch.
-            #[allow(clippy::single_char_pattern)]
-            line.splitn(2, ",")
+    // Handle header lines of the form:
+    //
+    // Function Stack,CPU Time:Self,Module
+    // Function Stack,CPU Time:Total,CPU Time:Self,Instructions Retired:Self,Module
+    fn columns(&self, header: &str) -> io::Result<Columns> {
+        let names: Vec<_> = header[HEADER.len()..].split(',').map(str::trim).collect();
+        let find = |name: &str| names.iter().position(|column| column == &name);
+
+        let weight = match self.opt.weight_column {
+            Some(ref name) => {
+                find(format!("{}:Self", name).as_str()).or_else(|| find(name.as_str()))
+            }
+            None => find(DEFAULT_WEIGHT_COLUMN)
+                .or_else(|| names.iter().position(|column| column != &MODULE_COLUMN)),
+        };
+        let weight = match weight {
+            Some(weight) => weight,
+            None => {
+                return invalid_data_error!(
+                    "Weight column `{}` not found in header:\n{}",
+                    self.opt
+                        .weight_column
+                        .as_deref()
+                        .unwrap_or(DEFAULT_WEIGHT_COLUMN),
+                    header
+                );
+            }
         };
 
-        let func = line.next()?;
-        let mut line = line.next()?.splitn(2, ',');
-        let time = line.next()?;
-        let module = if self.opt.no_modules {
-            ""
+        let weight_name = names[weight].to_string();
+        // Times are exported in seconds, but we want integer weights, so use milliseconds.
+        let weight_scale = if weight_name.contains("Time") {
+            1000.0
         } else {
-            line.next()?
+            1.0
         };
 
-        Some((func, time, module))
+        Ok(Columns {
+            weight,
+            weight_name,
+            weight_scale,
+            module: find(MODULE_COLUMN),
+        })
+    }
+
+    fn line_parts<'a>(&self, line: &'a str) -> Option<(&'a str, &'a str, &'a str)> {
+        // Fields are in quotes if they contain spaces or commas, like the parameters of a
+        // function or the path of a module.
+        let fields = common::split_fields(line, ',');
+        let (func, fields) = fields.split_first()?;
+        let weight = fields.get(self.columns.weight)?;
+        let module = match self.columns.module {
+            Some(i) if !self.opt.no_modules => fields.get(i).copied().unwrap_or(""),
+            _ => "",
+        };
+        Some((unquote(func), unquote(weight), unquote(module)))
+    }
+
+    // Returns a row like `  "foo",0,app` for the row `  foo,0.5,app`, which puts `foo` on the
+    // stack without adding to its weight.
+    fn without_weight(&self, line: &str) -> Vec<u8> {
+        let spaces = line.len() - line.trim_start_matches(' ').len();
+        let (indent, row) = line.split_at(spaces);
+        let mut fields = common::split_fields(row, ',');
+        let func = format!("\"{}\"", unquote(fields[0]));
+        fields[0] = &func;
+        if let Some(weight) = fields.get_mut(self.columns.weight + 1) {
+            *weight = "0";
+        }
+        format!("{}{}\n", indent, fields.join(",")).into_bytes()
     }
 
     fn on_line(&mut self, line: &str, occurrences: &mut Occurrences) -> io::Result<()> {
@@ -140,23 +417,31 @@ impl Folder {
                 for _ in 0..=prev_depth - depth {
                     self.stack.pop();
                 }
+                self.groups = self.groups.min(self.stack.len());
             } else if depth > prev_depth + 1 {
                 return invalid_data_error!("Skipped indentation level at line:\n{}", line);
             }
 
-            if let Some((func, time, module)) = self.line_parts(&line[spaces..]) {
-                if let Ok(time) = time.parse::<f64>() {
-                    let time_ms = (time * 1000.0).round() as usize;
-                    if module.is_empty() {
-                        self.stack.push(func.to_string());
+            if let Some((func, weight, module)) = self.line_parts(&line[spaces..]) {
+                if let Ok(weight) = weight.parse::<f64>() {
+                    let weight = (weight * self.columns.weight_scale).round() as usize;
+                    if self.groups == self.stack.len() && is_group_row(func) {
+                        // Grouping rows only ever come before the functions.
+                        self.groups += 1;
+                        self.stack.push((func.to_string(), String::new()));
                     } else {
-                        self.stack.push(format!("{}`{}", module, func));
+                        let frame = self.frame(func, module);
+                        self.stack.push((frame, module.to_string()));
                     }
-                    if time_ms > 0 {
-                        self.write_stack(occurrences, time_ms);
+                    if weight > 0 {
+                        self.write_stack(occurrences, weight);
                     }
                 } else {
-                    return invalid_data_error!("Invalid `CPU Time:Self` field: {}", time);
+                    return invalid_data_error!(
+                        "Invalid `{}` field: {}",
+                        self.columns.weight_name,
+                        weight
+                    );
                 }
             } else {
                 return invalid_data_error!("Unable to parse stack line:\n{}", line);
@@ -166,7 +451,121 @@ impl Folder {
         Ok(())
     }
 
+    // Names the frame of a function, which is empty for functions that are collapsed into the
+    // frame of their caller's module.
+    fn frame(&self, func: &str, module: &str) -> String {
+        if module.is_empty() {
+            return func.to_string();
+        }
+        match self.opt.module_names {
+            ModuleNames::Prefix => format!("{}`{}", module, func),
+            ModuleNames::Strip => func.to_string(),
+            ModuleNames::Annotate => format!("{} [{}]", func, module),
+            ModuleNames::Frame => format!("{};{}", module, func),
+            ModuleNames::Collapse => match self.stack.last() {
+                Some((_, caller)) if caller == module => String::new(),
+                _ => module.to_string(),
+            },
+        }
+    }
+
     fn write_stack(&self, occurrences: &mut Occurrences, time: usize) {
-        occurrences.insert(self.stack.join(";"), time);
+        let stack = if self.opt.group_roots {
+            &self.stack[..]
+        } else {
+            &self.stack[self.groups..]
+        };
+        let frames: Vec<_> = stack
+            .iter()
+            .map(|(frame, _)| frame.as_str())
+            .filter(|frame| !frame.is_empty())
+            .collect();
+        if !frames.is_empty() {
+            // Without the grouping rows, different threads can have the same stacks.
+            occurrences.insert_or_add(frames.join(";"), time);
+        }
+    }
+}
+
+// Strips the double quotes around a quoted field.
+fn unquote(field: &str) -> &str {
+    field
+        .strip_prefix('"')
+        .and_then(|field| field.strip_suffix('"'))
+        .unwrap_or(field)
+}
+
+// Process and thread grouping rows look like `app (PID: 1234)` and `worker (TID: 1235)`.
+fn is_group_row(func: &str) -> bool {
+    func.ends_with(')') && (func.contains(" (PID: ") || func.contains(" (TID: "))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::path::PathBuf;
+
+    use once_cell::sync::Lazy;
+
+    use super::*;
+    use crate::collapse::common;
+
+    static INPUT: Lazy<Vec<PathBuf>> = Lazy::new(|| {
+        [
+            "./tests/data/collapse-vtune/events.csv",
+            "./tests/data/collapse-vtune/end-before-header.csv",
+            "./tests/data/collapse-vtune/modules.csv",
+            "./tests/data/collapse-vtune/quoted-fields.csv",
+            "./tests/data/collapse-vtune/threads.csv",
+            "./tests/data/collapse-vtune/vtune.csv",
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect::<Vec<_>>()
+    });
+
+    #[test]
+    fn test_collapse_multi_vtune() -> io::Result<()> {
+        let mut folder = Folder::default();
+        common::testing::test_collapse_multi(&mut folder, &INPUT)?;
+
+        // The exports are small, so also cut them into a chunk per root function.
+        folder.set_nstacks_per_job(1);
+        common::testing::test_collapse_multi(&mut folder, &INPUT)
+    }
+
+    #[test]
+    fn test_collapse_vtune_stack_context() {
+        let mut folder = Folder::default();
+        folder.columns = folder
+            .columns("Function Stack,CPU Time:Total,CPU Time:Self,Module")
+            .unwrap();
+
+        assert!(folder.would_start_stack(b"Total,0.030,0.0,[Unknown]\n"));
+        assert!(folder.would_start_stack(b" main,0.030,0.010,app\n"));
+        assert!(folder.would_start_stack(b"  \"work<u8, u16>\",0.020,0.020,app\n"));
+        assert_eq!(
+            folder.stack_context(),
+            b"\"Total\",0.030,0,[Unknown]\n \"main\",0.030,0,app\n".to_vec()
+        );
+
+        assert!(folder.would_start_stack(b" helper,0.005,0.005,app\n"));
+        assert_eq!(
+            folder.stack_context(),
+            b"\"Total\",0.030,0,[Unknown]\n".to_vec()
+        );
+
+        assert!(!folder.would_start_stack(b"\n"));
+        assert!(!folder.would_start_stack(b""));
+    }
+
+    /// Varies the nstacks_per_job parameter and outputs the 10 fastests configurations by file.
+    ///
+    /// Command: `cargo test bench_nstacks_vtune --release -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_nstacks_vtune() -> io::Result<()> {
+        let mut folder = Folder::default();
+        common::testing::bench_nstacks(&mut folder, &INPUT)
     }
 }
@@ -0,0 +1,332 @@
+use std::io::{self, BufRead};
+
+use log::warn;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+use crate::folded::Header;
+use crate::Error;
+
+// The columns of the stack and the process of each row.
+static STACK_COLUMN: &str = "Stack";
+static PROCESS_COLUMN: &str = "Process";
+
+// The column used for weights if none is selected: the number of samples.
+static DEFAULT_WEIGHT_COLUMN: &str = "Count";
+
+// The node at the root of the stack trees of WPA, which isn't a function.
+static ROOT_FRAME: &str = "[Root]";
+
+/// `wpa` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct Options {
+    /// The column to use as the weight of each stack, like `Weight (in view) (ms)`.
+    ///
+    /// Default is `None`, which uses the sample `Count`.
+    pub weight_column: Option<String>,
+
+    /// Don't include the modules of functions, like the `ntdll.dll!` of
+    /// `ntdll.dll!RtlUserThreadStart`.
+    ///
+    /// Default is `false`.
+    pub no_modules: bool,
+
+    /// Add the process of each stack, like `app.exe (1234)`, as its root frame, for exports
+    /// with a `Process` column.
+    ///
+    /// Default is `false`.
+    pub include_process: bool,
+
+    /// Write a [`Header`] with the units of the weights before the folded stacks.
+    ///
+    /// Default is `false`.
+    pub header: bool,
+}
+
+/// A stack collapser for the "CPU Usage (Sampled)" tables that [Windows Performance Analyzer]
+/// exports to CSV or TSV, with a `Stack` column.
+///
+/// Exports of expanded stack trees have a row for every node of the tree, with its inclusive
+/// count. WPA indents a node under its parent, and marks it with `|-` if it has siblings, while
+/// a node that is the only child of its parent is listed right after it without a marker. The
+/// self weight of every node is its count less the counts of its children. Exports of flat
+/// tables instead have the whole stack of each row in the `Stack` column, with its frames
+/// separated by `/` from the root.
+///
+/// To construct one, either use `wpa::Folder::default()` or create an [`Options`] and use
+/// `wpa::Folder::from(options)`.
+///
+///   [Windows Performance Analyzer]: https://learn.microsoft.com/en-us/windows-hardware/test/wpt/windows-performance-analyzer
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+/// The columns of an export.
+struct Columns {
+    delimiter: char,
+    stack: usize,
+    weight: usize,
+    weight_name: String,
+    process: Option<usize>,
+}
+
+/// A node of the stack tree that is being read.
+struct Node {
+    /// The indentation of the node's name.
+    column: usize,
+    name: String,
+    total: f64,
+    children: f64,
+}
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, reader: R, mut writer: W) -> io::Result<()>
+    where
+        R: BufRead,
+        W: io::Write,
+    {
+        let mut lines = reader.lines();
+        let columns = loop {
+            match lines.next() {
+                Some(line) => {
+                    if let Some(columns) = self.columns(&line?)? {
+                        break columns;
+                    }
+                }
+                None => {
+                    warn!("File ended before header");
+                    return Ok(());
+                }
+            }
+        };
+
+        let mut occurrences = Occurrences::new(1);
+        let mut tree: Vec<Node> = Vec::new();
+        let mut process = String::new();
+        let mut invalid = 0;
+        for line in lines {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let fields = split_fields(&line, columns.delimiter);
+            let field = |column: usize| fields.get(column).map_or("", String::as_str);
+
+            // Rows of process groups may have no stack, and start new stack trees.
+            if let Some(column) = columns.process {
+                let row_process = field(column).trim();
+                if !row_process.is_empty() && row_process != process {
+                    self.pop_nodes(&mut tree, 0, &process, &mut occurrences);
+                    process = row_process.to_string();
+                }
+            }
+
+            let stack = field(columns.stack);
+            if stack.trim().is_empty() {
+                continue;
+            }
+            let weight = match parse_number(field(columns.weight)) {
+                Some(weight) => weight,
+                None => {
+                    invalid += 1;
+                    continue;
+                }
+            };
+
+            let name_start = stack
+                .find(|c: char| !c.is_whitespace() && c != '|' && c != '-')
+                .unwrap_or(stack.len());
+            let (prefix, name) = stack.split_at(name_start);
+            let name = name.trim_end();
+            let is_tree = !prefix.is_empty() || name == ROOT_FRAME;
+            if !is_tree {
+                // A flat row, with the whole stack.
+                let frames: Vec<_> = name
+                    .split('/')
+                    .map(|frame| self.frame(frame.trim()))
+                    .collect();
+                self.insert(&process, frames, weight, &mut occurrences);
+                continue;
+            }
+
+            if name == ROOT_FRAME {
+                self.pop_nodes(&mut tree, 0, &process, &mut occurrences);
+                continue;
+            }
+            let column = prefix.chars().count();
+            // A node with siblings is a child of the last node that is indented less, and one
+            // without is a child of the row before it.
+            if prefix.trim_end().ends_with('-') {
+                self.pop_nodes(&mut tree, column, &process, &mut occurrences);
+            }
+            if let Some(parent) = tree.last_mut() {
+                parent.children += weight;
+            }
+            tree.push(Node {
+                column,
+                name: self.frame(name),
+                total: weight,
+                children: 0.0,
+            });
+        }
+        self.pop_nodes(&mut tree, 0, &process, &mut occurrences);
+
+        if invalid != 0 {
+            warn!(
+                "Ignored {} rows without a valid {}",
+                invalid, columns.weight_name
+            );
+        }
+        if self.opt.header {
+            let mut header = Header::for_tool("inferno-collapse-wpa");
+            header.units = Some(if columns.weight_name == DEFAULT_WEIGHT_COLUMN {
+                "samples".to_string()
+            } else {
+                columns.weight_name.clone()
+            });
+            header.write(&mut writer)?;
+        }
+        occurrences.write_and_clear(writer)
+    }
+
+    /// Check for a header with a `Stack` column
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        input
+            .lines()
+            .find(|line| !line.trim().is_empty())
+            .map(|line| matches!(self.columns(line), Ok(Some(_))))
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+impl Folder {
+    // Handle header lines of the form:
+    //
+    // Line #,Process,Stack,Count,Weight (in view) (ms),% Weight
+    fn columns(&self, header: &str) -> io::Result<Option<Columns>> {
+        // Some files may start with the <U+FEFF> character (zero width no-break space).
+        let header = header.trim().trim_start_matches('\u{feff}');
+        let delimiter = if header.contains('\t') { '\t' } else { ',' };
+        let names = split_fields(header, delimiter);
+        let find = |name: &str| names.iter().position(|column| column.trim() == name);
+
+        let stack = match find(STACK_COLUMN) {
+            Some(stack) => stack,
+            None => return Ok(None),
+        };
+        let weight_name = self
+            .opt
+            .weight_column
+            .as_deref()
+            .unwrap_or(DEFAULT_WEIGHT_COLUMN);
+        let weight = match find(weight_name) {
+            Some(weight) => weight,
+            None if self.opt.weight_column.is_none() => return Ok(None),
+            None => {
+                return Err(io::Error::from(Error::InvalidOptions(format!(
+                    "the export has no {} column; its columns are: {}",
+                    weight_name,
+                    names.join(", ")
+                ))))
+            }
+        };
+        Ok(Some(Columns {
+            delimiter,
+            stack,
+            weight,
+            weight_name: weight_name.to_string(),
+            process: find(PROCESS_COLUMN),
+        }))
+    }
+
+    fn frame(&self, frame: &str) -> String {
+        match frame.split_once('!') {
+            Some((_, function)) if self.opt.no_modules && !function.is_empty() => {
+                function.to_string()
+            }
+            _ => frame.to_string(),
+        }
+    }
+
+    // Removes the nodes of the tree indented at least `column`, deepest first, writing their
+    // self weights.
+    fn pop_nodes(
+        &self,
+        tree: &mut Vec<Node>,
+        column: usize,
+        process: &str,
+        occurrences: &mut Occurrences,
+    ) {
+        while tree.last().is_some_and(|node| node.column >= column) {
+            let node = &tree[tree.len() - 1];
+            let weight = node.total - node.children;
+            let frames = tree.iter().map(|node| node.name.clone()).collect();
+            self.insert(process, frames, weight, occurrences);
+            tree.pop();
+        }
+    }
+
+    fn insert(
+        &self,
+        process: &str,
+        mut frames: Vec<String>,
+        weight: f64,
+        occurrences: &mut Occurrences,
+    ) {
+        let weight = weight.round();
+        if weight < 1.0 || frames.is_empty() {
+            return;
+        }
+        if self.opt.include_process && !process.is_empty() {
+            frames.insert(0, process.to_string());
+        }
+        occurrences.insert_or_add(frames.join(";"), weight as usize);
+    }
+}
+
+/// Splits a line into its fields, removing the double quotes around quoted fields, which may
+/// contain the delimiter.
+fn split_fields(line: &str, delimiter: char) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '"' {
+            // Quotes in quoted fields are doubled.
+            if quoted && chars.peek() == Some(&'"') {
+                field.push('"');
+                chars.next();
+            } else {
+                quoted = !quoted;
+            }
+        } else if c == delimiter && !quoted {
+            fields.push(std::mem::take(&mut field));
+        } else {
+            field.push(c);
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+/// Parses a number like `1234`, `1,234` or `12.5`.
+fn parse_number(field: &str) -> Option<f64> {
+    let number: String = field
+        .chars()
+        .filter(|&c| c != ',' && !c.is_whitespace())
+        .collect();
+    number.parse().ok()
+}
@@ -0,0 +1,553 @@
+use std::collections::{HashMap, HashSet};
+use std::io::{self, BufRead};
+
+use log::{info, warn};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+
+use crate::collapse::common::Occurrences;
+use crate::collapse::Collapse;
+use crate::folded::Header;
+use crate::Error;
+
+/// `xctrace` folder configuration options.
+#[derive(Clone, Debug, Default)]
+#[non_exhaustive]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct Options {
+    /// Only fold the samples of threads whose name contains one of these, like `Main Thread` or
+    /// the thread id `0x8f5e3`.
+    ///
+    /// The name of a thread is what Instruments shows for it, like
+    /// `Main Thread 0x8f5e3 (app, pid: 1234)`.
+    ///
+    /// Default is empty, which folds the samples of all threads.
+    pub threads: Vec<String>,
+
+    /// Count each sample by its weight, rather than once: the CPU time of Time Profiler samples
+    /// in nanoseconds, the cycles of CPU Profiler samples, or the size of allocations in bytes.
+    ///
+    /// Samples without a weight count once either way.
+    ///
+    /// Default is `false`.
+    pub weights: bool,
+
+    /// Add the state the thread was in when it was sampled, like `[Running]` or `[Blocked]`, as
+    /// the leaf frame of each stack.
+    ///
+    /// Time Profiler only samples threads that aren't running if it records waiting threads.
+    ///
+    /// Default is `false`.
+    pub thread_states: bool,
+
+    /// Only fold the allocations of the Allocations instrument that weren't freed by the end of
+    /// the trace, like its persistent allocations. Frees are matched to allocations by address.
+    ///
+    /// Default is `false`, which folds all allocations.
+    pub live: bool,
+
+    /// Put the stacks of each run of the trace under a root frame like `Run 2`, when the export
+    /// has the tables of several runs.
+    ///
+    /// Default is `false`, which adds the runs up.
+    pub runs: bool,
+
+    /// Write a [`Header`] before the folded stacks, with the schema of the table as the event,
+    /// and with the units of the weights when `weights` is set.
+    ///
+    /// Default is `false`.
+    pub header: bool,
+}
+
+/// A stack collapser for the XML that `xctrace export` writes for the tables of an Instruments
+/// trace, like those of the Time Profiler and Allocations instruments.
+///
+/// Export a table with something like:
+///
+/// ```text
+/// xctrace export --input app.trace \
+///     --xpath '/trace-toc/run[@number="1"]/data/table[@schema="time-profile"]'
+/// ```
+///
+/// Every row of the table that has a backtrace is a sample. Values that repeat, like threads and
+/// backtraces, are only written out the first time, and referred to by id after that.
+///
+/// The rows of os_signpost interval tables are intervals rather than samples. Intervals that
+/// start and end within another interval of the same thread are nested in it, and each interval
+/// is counted by the nanoseconds of its duration that aren't spent in nested intervals.
+///
+/// The rows of the Allocations instrument are events. Allocations are counted once, or by their
+/// size with [`Options::weights`], while frees and reference counting events are left out.
+///
+/// To construct one, either use `xctrace::Folder::default()` or create an [`Options`] and use
+/// `xctrace::Folder::from(options)`.
+#[derive(Clone, Default)]
+pub struct Folder {
+    opt: Options,
+}
+
+/// A value of a table, which later rows can refer to by its id.
+#[derive(Clone, Debug)]
+enum Value {
+    /// A frame of a backtrace, by the name of its function.
+    Frame(String),
+
+    /// A backtrace, as a stack of frames from the root to the leaf.
+    Backtrace(String),
+
+    /// Any other element, like `<weight fmt="1.00 ms">1000000</weight>`.
+    Element { fmt: Option<String>, text: String },
+}
+
+/// An element that hasn't been closed yet.
+#[derive(Debug, Default)]
+struct Open {
+    name: String,
+    id: Option<String>,
+    reference: Option<String>,
+    fmt: Option<String>,
+
+    /// The `name` attribute, or the `addr` of frames without a name, or the `xpath` of nodes.
+    label: Option<String>,
+    text: String,
+
+    /// The frames of a backtrace, from the leaf to the root.
+    frames: Vec<String>,
+
+    /// The backtrace of a tagged backtrace.
+    backtrace: Option<String>,
+}
+
+/// The columns of a row that samples are folded with.
+#[derive(Debug, Default)]
+struct Row {
+    thread: Option<String>,
+    state: Option<String>,
+    weight: Option<(&'static str, u64)>,
+    backtrace: Option<String>,
+
+    /// The event type of the rows of the Allocations instrument, like `Malloc` or `Free`.
+    event: Option<String>,
+    address: Option<String>,
+
+    /// The name, start and duration of signpost intervals, in nanoseconds.
+    name: Option<String>,
+    start: Option<u64>,
+    duration: Option<u64>,
+
+    /// The number of columns filled in so far.
+    columns: usize,
+}
+
+/// A signpost interval.
+#[derive(Debug)]
+struct Interval {
+    run: Option<String>,
+    thread: Option<String>,
+    name: String,
+    start: u64,
+    duration: u64,
+}
+
+impl Interval {
+    fn end(&self) -> u64 {
+        self.start + self.duration
+    }
+}
+
+// The events of the Allocations instrument that free memory, and those that only count references.
+const FREE_EVENTS: &[&str] = &["Free", "Destroy"];
+const REFERENCE_EVENTS: &[&str] = &["Retain", "Release", "Autorelease"];
+
+impl Collapse for Folder {
+    fn collapse<R, W>(&mut self, reader: R, mut writer: W) -> io::Result<()>
+    where
+        R: BufRead,
+        W: io::Write,
+    {
+        let mut reader = Reader::from_reader(reader);
+        reader.trim_text(true);
+
+        let mut occurrences = Occurrences::new(1);
+        // The stacks and counts of allocations that haven't been freed yet, by run and address.
+        let mut live: HashMap<(Option<String>, String), (String, usize)> = HashMap::new();
+        let mut intervals = Vec::new();
+        let mut values: HashMap<String, Value> = HashMap::new();
+        let mut open: Vec<Open> = Vec::new();
+        let mut row: Option<Row> = None;
+        let mut schema = None;
+        // The number of the run that the current table is of, and all runs seen.
+        let mut run: Option<String> = None;
+        let mut runs = HashSet::new();
+        // The mnemonics of the columns of the table, like `start` or `duration`.
+        let mut columns: Vec<String> = Vec::new();
+        let mut units = None;
+        let mut without_backtrace = 0;
+        let mut buf = Vec::new();
+        loop {
+            let (start, end) = match reader.read_event_into(&mut buf).map_err(invalid_data)? {
+                Event::Start(e) => (Some(element(&e)?), false),
+                Event::Empty(e) => (Some(element(&e)?), true),
+                Event::End(_) => (None, true),
+                Event::Text(e) => {
+                    if let Some(top) = open.last_mut() {
+                        top.text.push_str(&e.unescape().map_err(invalid_data)?);
+                    }
+                    (None, false)
+                }
+                Event::Eof => break,
+                _ => (None, false),
+            };
+            buf.clear();
+
+            if let Some(start) = start {
+                if start.name == "row" {
+                    row = Some(Row::default());
+                } else if start.name == "node" {
+                    run = start
+                        .label
+                        .as_deref()
+                        .and_then(run_number)
+                        .map(str::to_string);
+                    runs.extend(run.clone());
+                } else if start.name == "schema" {
+                    if schema.is_none() {
+                        schema = start.label.clone();
+                    }
+                    columns.clear();
+                }
+                open.push(start);
+            }
+            if !end {
+                continue;
+            }
+
+            let closed = match open.pop() {
+                Some(closed) => closed,
+                None => continue,
+            };
+            if closed.name == "row" {
+                let mut row = row.take().unwrap_or_default();
+                match row.event.as_deref() {
+                    Some(event) if FREE_EVENTS.contains(&event) => {
+                        if let Some(address) = row.address {
+                            live.remove(&(run.clone(), address));
+                        }
+                    }
+                    Some(event) if REFERENCE_EVENTS.contains(&event) => {}
+                    _ if row.backtrace.is_none() && row.duration.is_some() => {
+                        if let (Some(name), Some(start), Some(duration)) =
+                            (row.name, row.start, row.duration)
+                        {
+                            if self.folds_thread(row.thread.as_deref()) {
+                                intervals.push(Interval {
+                                    run: run.clone(),
+                                    thread: row.thread,
+                                    name: name.replace(';', ":"),
+                                    start,
+                                    duration,
+                                });
+                            }
+                        }
+                    }
+                    _ if row.backtrace.is_none() => without_backtrace += 1,
+                    _ => {
+                        let address = row.address.take();
+                        if let Some((stack, count)) = self.sample(row, &mut units) {
+                            let stack = self.rooted(run.as_deref(), stack);
+                            match address {
+                                Some(address) if self.opt.live => {
+                                    live.insert((run.clone(), address), (stack, count));
+                                }
+                                _ => occurrences.insert_or_add(stack, count),
+                            }
+                        }
+                    }
+                }
+                continue;
+            }
+
+            let value = match closed.reference {
+                Some(ref reference) => match values.get(reference) {
+                    Some(value) => value.clone(),
+                    None => {
+                        return Err(Error::parse(
+                            "xctrace",
+                            format!("reference to unknown id {}", reference),
+                        )
+                        .into())
+                    }
+                },
+                None => value_of(&closed),
+            };
+            if let Some(id) = closed.id {
+                values.insert(id, value.clone());
+            }
+
+            match (open.last_mut(), value) {
+                (Some(parent), Value::Frame(name)) if parent.name == "backtrace" => {
+                    parent.frames.push(name);
+                }
+                (Some(parent), Value::Backtrace(stack)) if parent.name == "tagged-backtrace" => {
+                    parent.backtrace = Some(stack);
+                }
+                (Some(parent), Value::Element { text, .. })
+                    if parent.name == "col" && closed.name == "mnemonic" =>
+                {
+                    columns.push(text);
+                }
+                (Some(parent), value) if parent.name == "row" => {
+                    if let Some(ref mut row) = row {
+                        let mnemonic = columns.get(row.columns).map(String::as_str);
+                        row.columns += 1;
+                        fill(row, &closed.name, mnemonic, value);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if without_backtrace != 0 {
+            warn!("Ignored {} rows without a backtrace", without_backtrace);
+        }
+        if runs.len() > 1 && !self.opt.runs {
+            info!(
+                "Added up the {} runs of the trace; pass --runs to keep them apart",
+                runs.len()
+            );
+        }
+        for (stack, count) in live.into_values() {
+            occurrences.insert_or_add(stack, count);
+        }
+        if !intervals.is_empty() {
+            units = Some("nanoseconds");
+            self.fold_intervals(intervals, &mut occurrences);
+        }
+        if self.opt.header {
+            let mut header = Header::for_tool("inferno-collapse-xctrace");
+            header.event = schema;
+            header.units = units.map(str::to_string);
+            header.write(&mut writer)?;
+        }
+        occurrences.write_and_clear(writer)
+    }
+
+    /// Check for the `<trace-query-result>` root element of `xctrace export`
+    fn is_applicable(&mut self, input: &str) -> Option<bool> {
+        let input = input.trim_start();
+        if input.contains("<trace-query-result") {
+            Some(true)
+        } else if input.starts_with('<') {
+            None
+        } else {
+            Some(false)
+        }
+    }
+}
+
+impl From<Options> for Folder {
+    fn from(opt: Options) -> Self {
+        Folder { opt }
+    }
+}
+
+impl Folder {
+    // Returns the stack and count of the sample of a row, if it's of one of the threads to fold,
+    // and sets `units` to those of the weight it was counted by.
+    fn sample(&self, row: Row, units: &mut Option<&'static str>) -> Option<(String, usize)> {
+        if !self.folds_thread(row.thread.as_deref()) {
+            return None;
+        }
+
+        let mut stack = row.backtrace.unwrap_or_default();
+        if self.opt.thread_states {
+            if let Some(state) = row.state {
+                if !stack.is_empty() {
+                    stack.push(';');
+                }
+                stack.push('[');
+                stack.push_str(&state);
+                stack.push(']');
+            }
+        }
+        if stack.is_empty() {
+            return None;
+        }
+
+        let count = match row.weight {
+            Some((weight_units, weight)) if self.opt.weights => {
+                *units = Some(weight_units);
+                weight as usize
+            }
+            _ => 1,
+        };
+        if count == 0 {
+            return None;
+        }
+        Some((stack, count))
+    }
+
+    // Whether the rows of the given thread are folded.
+    fn folds_thread(&self, thread: Option<&str>) -> bool {
+        let thread = thread.unwrap_or_default();
+        self.opt.threads.is_empty()
+            || self
+                .opt
+                .threads
+                .iter()
+                .any(|wanted| thread.contains(&**wanted))
+    }
+
+    // Folds signpost intervals into the stacks of the intervals of the same thread they're nested
+    // in, each counted by the part of its duration that isn't spent in nested intervals.
+    fn fold_intervals(&self, mut intervals: Vec<Interval>, occurrences: &mut Occurrences) {
+        // Intervals go after those they're nested in: by start, and longest first.
+        intervals.sort_by(|a, b| {
+            (&a.run, &a.thread, a.start, b.duration).cmp(&(&b.run, &b.thread, b.start, a.duration))
+        });
+
+        let mut stacks: Vec<String> = Vec::with_capacity(intervals.len());
+        let mut own: Vec<u64> = Vec::with_capacity(intervals.len());
+        // The intervals that the current one may be nested in, innermost last.
+        let mut open: Vec<usize> = Vec::new();
+        for (i, interval) in intervals.iter().enumerate() {
+            while let Some(&parent) = open.last() {
+                let parent = &intervals[parent];
+                if (&parent.run, &parent.thread) == (&interval.run, &interval.thread)
+                    && interval.start < parent.end()
+                {
+                    break;
+                }
+                open.pop();
+            }
+            let stack = match open.last() {
+                Some(&parent) => {
+                    let nested = interval.end().min(intervals[parent].end()) - interval.start;
+                    own[parent] = own[parent].saturating_sub(nested);
+                    format!("{};{}", stacks[parent], interval.name)
+                }
+                None => self.rooted(interval.run.as_deref(), interval.name.clone()),
+            };
+            stacks.push(stack);
+            own.push(interval.duration);
+            open.push(i);
+        }
+
+        for (stack, own) in stacks.into_iter().zip(own) {
+            if own != 0 {
+                occurrences.insert_or_add(stack, own as usize);
+            }
+        }
+    }
+
+    // Puts a stack under the root frame of its run, if the runs are kept apart.
+    fn rooted(&self, run: Option<&str>, stack: String) -> String {
+        match run {
+            Some(run) if self.opt.runs => format!("Run {};{}", run, stack),
+            _ => stack,
+        }
+    }
+}
+
+// Reads the attributes of an element that we care about.
+fn element(e: &BytesStart<'_>) -> io::Result<Open> {
+    let attribute = |name: &str| -> io::Result<Option<String>> {
+        match e.try_get_attribute(name).map_err(invalid_data)? {
+            Some(attribute) => Ok(Some(
+                attribute
+                    .unescape_value()
+                    .map_err(invalid_data)?
+                    .into_owned(),
+            )),
+            None => Ok(None),
+        }
+    };
+    let name = String::from_utf8_lossy(e.local_name().as_ref()).into_owned();
+    let label = match attribute("name")? {
+        None if name == "frame" => attribute("addr")?,
+        None if name == "node" => attribute("xpath")?,
+        label => label,
+    };
+    Ok(Open {
+        id: attribute("id")?,
+        reference: attribute("ref")?,
+        fmt: attribute("fmt")?,
+        label,
+        name,
+        ..Open::default()
+    })
+}
+
+// The number of the run that a table is of, from the xpath of its node, like
+// `//trace-toc[1]/run[2]/data[1]/table[4]`.
+fn run_number(xpath: &str) -> Option<&str> {
+    let start = xpath.find("/run[")? + "/run[".len();
+    let len = xpath[start..].find(']')?;
+    Some(&xpath[start..start + len])
+}
+
+// The value of an element that isn't a reference to another one.
+fn value_of(closed: &Open) -> Value {
+    match &*closed.name {
+        "frame" => {
+            let name = closed.label.as_deref().unwrap_or("[unknown]");
+            Value::Frame(name.replace(';', ":"))
+        }
+        "backtrace" => {
+            let frames: Vec<&str> = closed.frames.iter().rev().map(String::as_str).collect();
+            Value::Backtrace(frames.join(";"))
+        }
+        "tagged-backtrace" => Value::Backtrace(closed.backtrace.clone().unwrap_or_default()),
+        _ => Value::Element {
+            fmt: closed.fmt.clone(),
+            text: closed.text.clone(),
+        },
+    }
+}
+
+// Fills in the column of a row that `value` is the value of. Columns are told apart by the name of
+// their element, or by their mnemonic in the schema where elements share a name, like the `string`
+// elements of signpost names, categories and subsystems.
+fn fill(row: &mut Row, column: &str, mnemonic: Option<&str>, value: Value) {
+    match (mnemonic, &value) {
+        (Some("name"), Value::Element { fmt, text }) => {
+            row.name = Some(fmt.clone().unwrap_or_else(|| text.clone()));
+            return;
+        }
+        (Some("start"), Value::Element { text, .. }) => {
+            row.start = text.trim().parse().ok();
+            return;
+        }
+        (Some("duration"), Value::Element { text, .. }) => {
+            row.duration = text.trim().parse().ok();
+            return;
+        }
+        _ => {}
+    }
+    match (column, value) {
+        ("backtrace" | "tagged-backtrace", Value::Backtrace(stack)) => row.backtrace = Some(stack),
+        ("thread", Value::Element { fmt, text }) => row.thread = Some(fmt.unwrap_or(text)),
+        ("thread-state", Value::Element { fmt, text }) => row.state = Some(fmt.unwrap_or(text)),
+        ("event-type", Value::Element { fmt, text }) => row.event = Some(fmt.unwrap_or(text)),
+        ("address", Value::Element { fmt, text }) => row.address = Some(fmt.unwrap_or(text)),
+        ("weight" | "cycle-weight" | "size-in-bytes", Value::Element { text, .. }) => {
+            let units = match column {
+                "weight" => "nanoseconds",
+                "cycle-weight" => "cycles",
+                _ => "bytes",
+            };
+            if let Ok(weight) = text.trim().parse() {
+                row.weight = Some((units, weight));
+            }
+        }
+        _ => {}
+    }
+}
+
+fn invalid_data<E: std::error::Error + Send + Sync + 'static>(e: E) -> io::Error {
+    Error::parse("xctrace", e).into()
+}
@@ -0,0 +1,222 @@
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use regex::Regex;
+use serde::de::{self, DeserializeOwned, Deserializer};
+use serde::ser::Serializer;
+use serde::{Deserialize, Serialize};
+
+use crate::Error;
+use crate::{differential, flamegraph};
+
+/// The name of the config file that [`Config::discover`] looks for.
+pub const FILE_NAME: &str = ".inferno.toml";
+
+/// Options for the tools of Inferno, read from a TOML file.
+///
+/// The options of each tool are in a table of their own: `[flamegraph]` for
+/// [`flamegraph::Options`], `[differential]` for [`differential::Options`], and
+/// `[collapse.<format>]`, like `[collapse.perf]`, for the options of each collapser. The keys of
+/// a table are the names of the fields of the options, and their values are written like on the
+/// command line:
+///
+/// ```toml
+/// [flamegraph]
+/// colors = "java"
+/// title = "Checkout service"
+/// min_width = 0.5
+///
+/// [collapse.perf]
+/// include_addrs = true
+/// ```
+///
+/// Options given on the command line take precedence over the config file. Options that are
+/// left at their defaults on the command line don't, so the config file can only be overruled by
+/// options that differ from the defaults.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Config {
+    table: toml::Table,
+}
+
+impl FromStr for Config {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse::<toml::Table>() {
+            Ok(table) => Ok(Config { table }),
+            Err(e) => Err(Error::Parse {
+                format: "toml",
+                line: e
+                    .span()
+                    .map(|span| s[..span.start].matches('\n').count() + 1),
+                message: e.message().to_string(),
+            }),
+        }
+    }
+}
+
+impl Config {
+    /// Read the config file at `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(fs::read_to_string(path)?.parse()?)
+    }
+
+    /// Find the `.inferno.toml` in the current directory, or else in the closest of its parent
+    /// directories that has one.
+    pub fn find() -> io::Result<Option<PathBuf>> {
+        let dir = env::current_dir()?;
+        Ok(dir
+            .ancestors()
+            .map(|dir| dir.join(FILE_NAME))
+            .find(|path| path.is_file()))
+    }
+
+    /// Read the config file found by [`Config::find`], or return an empty config if there is
+    /// none.
+    pub fn discover() -> io::Result<Self> {
+        match Self::find()? {
+            Some(path) => Self::load(path),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Fill in the options of the `[flamegraph]` table that are at their defaults in `options`.
+    ///
+    /// Options that can't be written in a config file, like [`flamegraph::Options::palette_map`],
+    /// are kept as they are.
+    pub fn flamegraph<'a>(
+        &self,
+        mut options: flamegraph::Options<'a>,
+    ) -> io::Result<flamegraph::Options<'a>> {
+        let mut merged: flamegraph::Options<'a> = match self.merge(&["flamegraph"], &options)? {
+            Some(merged) => merged,
+            None => return Ok(options),
+        };
+        merged.palette_map = options.palette_map.take();
+        merged.frame_links = options.frame_links.take();
+        merged.font_metrics = options.font_metrics.take();
+        merged.color_fn = options.color_fn.take();
+        #[cfg(feature = "nameattr")]
+        {
+            merged.func_frameattrs = options.func_frameattrs;
+        }
+        Ok(merged)
+    }
+
+    /// Fill in the options of the `[differential]` table that are at their defaults in
+    /// `options`.
+    ///
+    /// The rewrite rules of [`differential::Options::rules`] are kept as they are.
+    pub fn differential(
+        &self,
+        options: differential::Options,
+    ) -> io::Result<differential::Options> {
+        let mut merged: differential::Options = match self.merge(&["differential"], &options)? {
+            Some(merged) => merged,
+            None => return Ok(options),
+        };
+        merged.rules = options.rules;
+        Ok(merged)
+    }
+
+    /// Fill in the options of the `[collapse.<format>]` table that are at their defaults in
+    /// `options`, where `format` is the name of the collapser's module, like `perf`.
+    pub fn collapse<T>(&self, format: &str, options: T) -> io::Result<T>
+    where
+        T: Serialize + DeserializeOwned + Default,
+    {
+        Ok(self
+            .merge(&["collapse", format], &options)?
+            .unwrap_or(options))
+    }
+
+    // Merges the table at `path` under `options`, and reads the result back as options, unless
+    // there is no such table.
+    fn merge<T>(&self, path: &[&str], options: &T) -> io::Result<Option<T>>
+    where
+        T: Serialize + DeserializeOwned + Default,
+    {
+        let mut section = &self.table;
+        for key in path {
+            section = match section.get(*key) {
+                Some(toml::Value::Table(table)) => table,
+                Some(_) => return Err(invalid_options(path, "not a table").into()),
+                None => return Ok(None),
+            };
+        }
+
+        let mut merged = to_table(options, path)?;
+        let defaults = to_table(&T::default(), path)?;
+        merge_tables(&mut merged, &defaults, section);
+        match toml::Value::Table(merged).try_into() {
+            Ok(merged) => Ok(Some(merged)),
+            Err(e) => Err(invalid_options(path, e).into()),
+        }
+    }
+}
+
+// Puts the values of `config` into `merged` wherever `merged` has the value of `defaults`.
+fn merge_tables(merged: &mut toml::Table, defaults: &toml::Table, config: &toml::Table) {
+    for (key, value) in config {
+        match (merged.get_mut(key), defaults.get(key), value) {
+            (
+                Some(toml::Value::Table(merged)),
+                Some(toml::Value::Table(defaults)),
+                toml::Value::Table(config),
+            ) => merge_tables(merged, defaults, config),
+            _ => {
+                if merged.get(key) == defaults.get(key) {
+                    merged.insert(key.clone(), value.clone());
+                }
+            }
+        }
+    }
+}
+
+fn to_table<T: Serialize>(options: &T, path: &[&str]) -> Result<toml::Table, Error> {
+    match toml::Value::try_from(options) {
+        Ok(toml::Value::Table(table)) => Ok(table),
+        Ok(_) => Err(invalid_options(path, "not a table")),
+        Err(e) => Err(invalid_options(path, e)),
+    }
+}
+
+fn invalid_options<M: fmt::Display>(path: &[&str], message: M) -> Error {
+    Error::InvalidOptions(format!("invalid [{}] options: {}", path.join("."), message))
+}
+
+// Deserializes a value from a string with its `FromStr`, for the types that are written as they
+// are on the command line.
+pub(crate) fn deserialize_from_str<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr<Err = String>,
+{
+    String::deserialize(deserializer)?
+        .parse()
+        .map_err(de::Error::custom)
+}
+
+// Regular expressions are written as their patterns.
+pub(crate) mod optional_regex {
+    use super::*;
+
+    pub(crate) fn serialize<S: Serializer>(
+        regex: &Option<Regex>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        regex.as_ref().map(Regex::as_str).serialize(serializer)
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Regex>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|pattern| Regex::new(&pattern).map_err(de::Error::custom))
+            .transpose()
+    }
+}
@@ -1,11 +1,12 @@
-use std::fs::File;
 use std::io::{self, prelude::*};
 use std::path::Path;
 
 use ahash::AHashMap;
 use log::warn;
 
-const READER_CAPACITY: usize = 128 * 1024;
+use crate::collapse::common;
+use crate::folded::{self, Header};
+use crate::rewrite::Rules;
 
 #[derive(Debug, Clone, Copy, Default)]
 struct Counts {
@@ -16,7 +17,12 @@ struct Counts {
 /// Configure the generated output.
 ///
 /// All options default to off.
-#[derive(Debug, Clone, Copy, Default)]
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
 pub struct Options {
     /// Normalize the first profile count to match the second.
     ///
@@ -28,6 +34,23 @@ pub struct Options {
 
     /// Strip hex numbers (addresses) of the form "0x45ef2173" and replace with "0x...".
     pub strip_hex: bool,
+
+    /// Rules to rewrite the frames of both profiles with before their stacks are matched up, like
+    /// `rename ::h[0-9a-f]{16}$` to drop the hashes of Rust symbols, or `rename _\[i\]$` to
+    /// ignore inlining annotations, so that frames that changed superficially between builds
+    /// still match.
+    ///
+    /// Stacks whose frames are all dropped are left out.
+    #[cfg_attr(feature = "config", serde(skip))]
+    pub rules: Rules,
+
+    /// Instead of the counts of each stack, write its share of the total of each profile, in
+    /// percent, and the difference between the two shares, in percentage points.
+    ///
+    /// Stacks are written biggest difference first, so that the stacks that gained or lost the
+    /// most of the profile between runs of different lengths come out on top. The output is a
+    /// report to read, and no header is written before it.
+    pub percentages: bool,
 }
 
 /// Produce an output that can be used to generate a differential flame graph.
@@ -40,27 +63,59 @@ pub struct Options {
 ///
 /// The output written to the `writer` will be similar to the inputs, except there will be two
 /// sample count columns -- one for each profile.
-pub fn from_readers<R1, R2, W>(opt: Options, before: R1, after: R2, writer: W) -> io::Result<()>
+///
+/// If both profiles start with a [`Header`] that gives their sampling period, and the periods
+/// differ, the counts of the first profile are scaled to the period of the second, so that the
+/// counts of both stand for the same number of events. The header of the second profile is
+/// written before the output.
+pub fn from_readers<R1, R2, W>(opt: Options, before: R1, after: R2, mut writer: W) -> io::Result<()>
 where
     R1: BufRead,
     R2: BufRead,
     W: Write,
 {
     let mut stack_counts = AHashMap::default();
-    let total1 = parse_stack_counts(opt, &mut stack_counts, before, true)?;
-    let total2 = parse_stack_counts(opt, &mut stack_counts, after, false)?;
+    let (mut total1, header1) = parse_stack_counts(&opt, &mut stack_counts, before, true)?;
+    let (total2, header2) = parse_stack_counts(&opt, &mut stack_counts, after, false)?;
+
+    if let (Some(header1), Some(header2)) = (&header1, &header2) {
+        if header1.units != header2.units {
+            warn!(
+                "The profiles are counted in different units ({} and {})",
+                header1.units.as_deref().unwrap_or("unknown"),
+                header2.units.as_deref().unwrap_or("unknown")
+            );
+        }
+        if let (Some(period1), Some(period2)) = (header1.period, header2.period) {
+            if period1 != period2 && period2 > 0.0 {
+                for counts in stack_counts.values_mut() {
+                    counts.first = (counts.first as f64 * period1 / period2).round() as usize;
+                }
+                total1 = stack_counts.values().map(|counts| counts.first).sum();
+            }
+        }
+    }
+
+    if opt.percentages {
+        return write_percentages(&stack_counts, total1, total2, writer);
+    }
     if opt.normalize && total1 != total2 {
         for counts in stack_counts.values_mut() {
             counts.first = (counts.first as f64 * total2 as f64 / total1 as f64) as usize;
         }
     }
+    if let Some(mut header) = header2 {
+        header.tool = Header::for_tool("inferno-diff-folded").tool;
+        header.write(&mut writer)?;
+    }
     write_stacks(&stack_counts, writer)
 }
 
 /// Produce an output that can be used to generate a differential flame graph from
 /// a before and an after profile.
 ///
-/// See [`from_readers`] for the input and output formats.
+/// See [`from_readers`] for the input and output formats. Files compressed with gzip, or with
+/// zstd or xz if the `zstd` or `xz` features are enabled, are decompressed first.
 pub fn from_files<P1, P2, W>(
     opt: Options,
     file_before: P1,
@@ -72,23 +127,23 @@ where
     P2: AsRef<Path>,
     W: Write,
 {
-    let file1 = File::open(file_before)?;
-    let reader1 = io::BufReader::with_capacity(READER_CAPACITY, file1);
-    let file2 = File::open(file_after)?;
-    let reader2 = io::BufReader::with_capacity(READER_CAPACITY, file2);
+    let reader1 = common::open(file_before.as_ref())?;
+    let reader2 = common::open(file_after.as_ref())?;
     from_readers(opt, reader1, reader2, writer)
 }
 
-// Populate stack_counts based on lines from the reader and returns the sum of the sample counts.
+// Populate stack_counts based on lines from the reader and returns the sum of the sample counts,
+// along with the header of the profile, if it has one.
 fn parse_stack_counts<R>(
-    opt: Options,
+    opt: &Options,
     stack_counts: &mut AHashMap<String, Counts>,
     mut reader: R,
     is_first: bool,
-) -> io::Result<usize>
+) -> io::Result<(usize, Option<Header>)>
 where
     R: BufRead,
 {
+    let mut comments = Vec::new();
     let mut total = 0;
     let mut line = Vec::new();
     let mut stripped_fractional_samples = false;
@@ -100,9 +155,22 @@ where
         }
 
         let l = String::from_utf8_lossy(&line);
-        if let Some((stack, count)) =
+        if folded::is_comment(&l) {
+            if total == 0 {
+                comments.push(l.into_owned());
+            }
+            continue;
+        }
+        if let Some((mut stack, count)) =
             parse_line(&l, opt.strip_hex, &mut stripped_fractional_samples)
         {
+            if !opt.rules.is_empty() {
+                let frames = opt.rules.rewrite(&stack);
+                if frames.is_empty() {
+                    continue;
+                }
+                stack = frames.join(";");
+            }
             let counts = stack_counts.entry(stack).or_default();
             if is_first {
                 counts.first += count;
@@ -115,7 +183,7 @@ where
         }
     }
 
-    Ok(total)
+    Ok((total, Header::parse(comments.iter().map(String::as_str))))
 }
 
 // Write three-column lines with the folded stack trace and two value columns,
@@ -130,6 +198,53 @@ where
     Ok(())
 }
 
+// Write lines with the folded stack trace, its share of each profile and the difference between
+// the two, biggest difference first.
+fn write_percentages<W>(
+    stack_counts: &AHashMap<String, Counts>,
+    total1: usize,
+    total2: usize,
+    mut writer: W,
+) -> io::Result<()>
+where
+    W: Write,
+{
+    let share = |count: usize, total: usize| {
+        if total == 0 {
+            0.0
+        } else {
+            count as f64 * 100.0 / total as f64
+        }
+    };
+    let mut shares: Vec<_> = stack_counts
+        .iter()
+        .map(|(stack, counts)| {
+            (
+                stack,
+                share(counts.first, total1),
+                share(counts.second, total2),
+            )
+        })
+        .collect();
+    shares.sort_by(|(stack1, before1, after1), (stack2, before2, after2)| {
+        (after2 - before2)
+            .abs()
+            .total_cmp(&(after1 - before1).abs())
+            .then_with(|| stack1.cmp(stack2))
+    });
+    for (stack, before, after) in shares {
+        writeln!(
+            writer,
+            "{} {:.4} {:.4} {:+.4}",
+            stack,
+            before,
+            after,
+            after - before
+        )?;
+    }
+    Ok(())
+}
+
 // Parse stack and sample count from line.
 fn parse_line(
     line: &str,
@@ -0,0 +1,271 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::io::{self, prelude::*};
+use std::path::PathBuf;
+
+use ahash::AHashMap;
+use log::warn;
+
+use crate::folded;
+
+const READER_CAPACITY: usize = 128 * 1024;
+
+/// Configure how far folded stacks are downsampled.
+///
+/// With neither `samples` nor `stacks` set, stacks are only merged.
+#[derive(Debug, Clone, Copy, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// Scale the sample counts so that they add up to this many samples.
+    ///
+    /// Counts are rounded so that each stack ends up within one sample of its exact share of the
+    /// target, and so that they add up to the target exactly. Stacks whose share is less than a
+    /// sample can round down to nothing, and are left out then.
+    ///
+    /// Default is `None`.
+    pub samples: Option<usize>,
+
+    /// Keep at most this many unique stacks.
+    ///
+    /// The stacks are picked with weighted reservoir sampling, so each stack's chance of being
+    /// kept grows with its sample count, and the counts of the stacks that are kept are scaled
+    /// back up to the total of the input. The proportions between the stacks that are kept are
+    /// preserved to within one sample.
+    ///
+    /// Default is `None`.
+    pub stacks: Option<usize>,
+
+    /// The seed of the random numbers that pick stacks with `stacks`.
+    ///
+    /// The same input, options and seed always give the same output.
+    ///
+    /// Default is `0`.
+    pub seed: u64,
+}
+
+/// Downsample folded stack lines, and write the result to `writer`.
+///
+/// Each line is expected to have the following whitespace-separated fields:
+///
+///  - A semicolon-separated list of frame names (e.g., `main;foo;bar;baz`).
+///  - A sample count for the given stack.
+///
+/// Identical stacks are merged, and stacks are written in the order they first appear in.
+pub fn from_lines<'a, I, W>(opt: Options, lines: I, mut writer: W) -> io::Result<()>
+where
+    I: IntoIterator<Item = &'a str>,
+    W: Write,
+{
+    let mut stacks: Vec<(&str, usize)> = Vec::new();
+    let mut index: AHashMap<&str, usize> = AHashMap::default();
+    let mut ignored = 0;
+    for line in lines {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if folded::is_comment(line) {
+            writeln!(writer, "{}", line)?;
+            continue;
+        }
+        let parsed = line
+            .rsplit_once(' ')
+            .and_then(|(stack, count)| Some((stack.trim_end(), count.parse::<usize>().ok()?)));
+        let (stack, count) = match parsed {
+            Some(parsed) => parsed,
+            None => {
+                ignored += 1;
+                continue;
+            }
+        };
+        match index.get(stack) {
+            Some(&i) => stacks[i].1 += count,
+            None => {
+                index.insert(stack, stacks.len());
+                stacks.push((stack, count));
+            }
+        }
+    }
+    if ignored != 0 {
+        warn!("Ignored {} lines with invalid format", ignored);
+    }
+
+    let total: usize = stacks.iter().map(|&(_, count)| count).sum();
+    if let Some(max) = opt.stacks {
+        stacks = reservoir(stacks, max, opt.seed);
+    }
+    let counts = scale(&stacks, opt.samples.unwrap_or(total));
+
+    for (&(stack, _), count) in stacks.iter().zip(counts) {
+        if count != 0 {
+            writeln!(writer, "{} {}", stack, count)?;
+        }
+    }
+    Ok(())
+}
+
+/// Downsample folded stack lines from a reader.
+///
+/// See [`from_lines`] for the expected format of each line.
+pub fn from_reader<R, W>(opt: Options, mut reader: R, writer: W) -> io::Result<()>
+where
+    R: Read,
+    W: Write,
+{
+    let mut input = String::new();
+    reader.read_to_string(&mut input)?;
+    from_lines(opt, input.lines(), writer)
+}
+
+/// Downsample folded stack lines from files, and write the result to the provided `writer`.
+///
+/// If files is empty, or is just `-`, STDIN will be used as input.
+pub fn from_files<W: Write>(opt: Options, files: &[PathBuf], writer: W) -> io::Result<()> {
+    let mut input = String::new();
+    if files.is_empty() {
+        io::stdin().lock().read_to_string(&mut input)?;
+    }
+    let mut stdin_added = false;
+    for infile in files {
+        if infile.to_str() == Some("-") {
+            if !stdin_added {
+                io::stdin().lock().read_to_string(&mut input)?;
+                stdin_added = true;
+            }
+        } else {
+            let mut r = io::BufReader::with_capacity(READER_CAPACITY, File::open(infile)?);
+            r.read_to_string(&mut input)?;
+        }
+    }
+    from_lines(opt, input.lines(), writer)
+}
+
+// A stack in the reservoir, ordered so that the stack with the lowest key is at the top of the
+// heap.
+struct Keyed {
+    key: f64,
+    index: usize,
+}
+
+impl PartialEq for Keyed {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Keyed {}
+
+impl PartialOrd for Keyed {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Keyed {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.key.total_cmp(&self.key)
+    }
+}
+
+// Picks at most `max` stacks, weighted by their counts, with the A-Res algorithm of Efraimidis
+// and Spirakis: every stack gets the key `u^(1/count)` for a uniform random `u`, and the stacks
+// with the highest keys are kept. The keys are compared as `ln(u) / count`, which orders them the
+// same way without underflowing for large counts. The stacks that are kept stay in their order.
+fn reservoir(stacks: Vec<(&str, usize)>, max: usize, seed: u64) -> Vec<(&str, usize)> {
+    if stacks.len() <= max {
+        return stacks;
+    }
+    let mut rng = SplitMix64(seed);
+    let mut heap = BinaryHeap::with_capacity(max + 1);
+    for (index, &(_, count)) in stacks.iter().enumerate() {
+        if count == 0 {
+            continue;
+        }
+        let key = rng.next_f64().ln() / count as f64;
+        if heap.len() < max {
+            heap.push(Keyed { key, index });
+        } else if heap.peek().is_some_and(|lowest| key > lowest.key) {
+            heap.pop();
+            heap.push(Keyed { key, index });
+        }
+    }
+    let mut kept: Vec<usize> = heap.into_iter().map(|keyed| keyed.index).collect();
+    kept.sort_unstable();
+    kept.into_iter().map(|index| stacks[index]).collect()
+}
+
+// Scales counts to add up to `target`, with the largest remainder method: every count is rounded
+// down, and the samples that are left go to the counts that lost the most to rounding, so each
+// count is within one of its exact share.
+fn scale(stacks: &[(&str, usize)], target: usize) -> Vec<usize> {
+    let total: usize = stacks.iter().map(|&(_, count)| count).sum();
+    if total == 0 || total == target {
+        return stacks.iter().map(|&(_, count)| count).collect();
+    }
+
+    let mut counts = Vec::with_capacity(stacks.len());
+    let mut remainders = Vec::with_capacity(stacks.len());
+    for (i, &(_, count)) in stacks.iter().enumerate() {
+        // Exact in u128, since both factors fit in a u64.
+        let exact = count as u128 * target as u128;
+        counts.push((exact / total as u128) as usize);
+        remainders.push((exact % total as u128, i));
+    }
+    let left = target - counts.iter().sum::<usize>();
+    remainders.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+    for &(_, i) in &remainders[..left] {
+        counts[i] += 1;
+    }
+    counts
+}
+
+// A small and fast generator, so the picks only depend on the seed.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // A uniform number in (0, 1], so that its logarithm is finite.
+    fn next_f64(&mut self) -> f64 {
+        ((self.next_u64() >> 11) + 1) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scale_is_within_one_sample() {
+        let stacks = [("a", 7), ("b", 13), ("c", 1), ("d", 979)];
+        let counts = scale(&stacks, 100);
+        assert_eq!(counts.iter().sum::<usize>(), 100);
+        for (&(_, count), scaled) in stacks.iter().zip(counts) {
+            let exact = count as f64 * 100.0 / 1000.0;
+            assert!(
+                (scaled as f64 - exact).abs() < 1.0,
+                "{} vs {}",
+                scaled,
+                exact
+            );
+        }
+    }
+
+    #[test]
+    fn reservoir_keeps_heavy_stacks() {
+        let mut stacks = vec![("heavy", 1_000_000)];
+        stacks.extend((0..100).map(|_| ("light", 1)));
+        for seed in 0..20 {
+            let kept = reservoir(stacks.clone(), 10, seed);
+            assert_eq!(kept.len(), 10);
+            assert_eq!(kept[0], ("heavy", 1_000_000));
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use addr2line::gimli::{self, EndianArcSlice, RunTimeEndian};
+use addr2line::object::{self, Object, ObjectSection, ObjectSegment, ObjectSymbol};
+
+type Reader = EndianArcSlice<RunTimeEndian>;
+
+/// A binary (executable or shared library) along with its DWARF debug info.
+pub(crate) struct Binary {
+    // `addr2line::Context` lazily parses units and isn't `Sync`, so lookups are serialized.
+    context: Mutex<addr2line::Context<Reader>>,
+
+    /// Symbol addresses keyed by both mangled and demangled name, so `symbol+offset` frames can
+    /// be mapped back to file addresses regardless of where the binary was loaded.
+    #[cfg_attr(not(feature = "inline"), allow(dead_code))]
+    symbols: HashMap<String, u64>,
+
+    /// Demangled function symbols as their start and end addresses, sorted by address, for
+    /// binaries without debug info.
+    #[cfg_attr(not(feature = "symbolize"), allow(dead_code))]
+    functions: Vec<(u64, u64, String)>,
+
+    /// The address the binary expects to be loaded at, which `module+offset` offsets are
+    /// relative to.
+    #[cfg_attr(not(feature = "symbolize"), allow(dead_code))]
+    base: u64,
+}
+
+impl Binary {
+    pub(crate) fn load(path: &Path) -> io::Result<Self> {
+        let data = fs::read(path)?;
+        let file = object::File::parse(&*data).map_err(invalid_data)?;
+        let endian = if file.is_little_endian() {
+            RunTimeEndian::Little
+        } else {
+            RunTimeEndian::Big
+        };
+
+        let dwarf = gimli::Dwarf::load(|id| -> Result<Reader, gimli::Error> {
+            let data = file
+                .section_by_name(id.name())
+                .and_then(|section| section.uncompressed_data().ok())
+                .unwrap_or(Cow::Borrowed(&[]));
+            Ok(Reader::new(Arc::from(&*data), endian))
+        })
+        .map_err(invalid_data)?;
+        let context = addr2line::Context::from_dwarf(dwarf).map_err(invalid_data)?;
+
+        let mut symbols = HashMap::new();
+        let mut functions = Vec::new();
+        for symbol in file.symbols() {
+            let name = match symbol.name() {
+                Ok(name) if !name.is_empty() => name,
+                _ => continue,
+            };
+            let demangled = addr2line::demangle_auto(Cow::Borrowed(name), None);
+            let demangled = strip_rust_hash(&demangled);
+            if demangled != name {
+                symbols.insert(demangled.to_string(), symbol.address());
+            }
+            symbols.insert(name.to_string(), symbol.address());
+            if symbol.kind() == object::SymbolKind::Text {
+                let end = symbol.address() + symbol.size().max(1);
+                functions.push((symbol.address(), end, demangled.to_string()));
+            }
+        }
+        functions.sort_unstable();
+
+        // PE offsets are relative to the image base. Other formats are relative to the lowest
+        // segment that is mapped from the file, which skips Mach-O's `__PAGEZERO`.
+        let base = match file.format() {
+            object::BinaryFormat::Pe => file.relative_address_base(),
+            _ => file
+                .segments()
+                .filter(|segment| segment.file_range().1 > 0)
+                .map(|segment| segment.address())
+                .min()
+                .unwrap_or(0),
+        };
+
+        Ok(Self {
+            context: Mutex::new(context),
+            symbols,
+            functions,
+            base,
+        })
+    }
+
+    /// Returns the address of the symbol with the given mangled or demangled name.
+    #[cfg_attr(not(feature = "inline"), allow(dead_code))]
+    pub(crate) fn symbol_address(&self, name: &str) -> Option<u64> {
+        self.symbols.get(name).copied()
+    }
+
+    /// Returns the names of the functions covering `addr`, innermost first.
+    pub(crate) fn functions(&self, addr: u64) -> Vec<String> {
+        let mut names = Vec::new();
+        let context = match self.context.lock() {
+            Ok(context) => context,
+            Err(_) => return names,
+        };
+        let mut frames = match context.find_frames(addr).skip_all_loads() {
+            Ok(frames) => frames,
+            Err(_) => return names,
+        };
+        while let Ok(Some(frame)) = frames.next() {
+            if let Some(name) = frame
+                .function
+                .and_then(|function| function.demangle().ok().map(Cow::into_owned))
+            {
+                names.push(strip_rust_hash(&name).to_string());
+            }
+        }
+        names
+    }
+
+    /// Returns the names of the functions at `offset` from the start of the binary, outermost
+    /// first, using the symbol table if there's no debug info for it.
+    #[cfg_attr(not(feature = "symbolize"), allow(dead_code))]
+    pub(crate) fn functions_at_offset(&self, offset: u64) -> Vec<String> {
+        let addr = self.base + offset;
+        let mut names = self.functions(addr);
+        if names.is_empty() {
+            let i = self
+                .functions
+                .partition_point(|(start, _, _)| *start <= addr);
+            if let Some((_, end, name)) = i.checked_sub(1).map(|i| &self.functions[i]) {
+                if addr < *end {
+                    names.push(name.clone());
+                }
+            }
+        }
+        names.reverse();
+        names
+    }
+}
+
+/// Strips the trailing hash from legacy Rust symbols, like perf does.
+pub(crate) fn strip_rust_hash(name: &str) -> &str {
+    match name.rfind("::h") {
+        Some(i)
+            if name.len() - i == 19 && name[(i + 3)..].chars().all(|c| c.is_ascii_hexdigit()) =>
+        {
+            &name[..i]
+        }
+        _ => name,
+    }
+}
+
+fn invalid_data<E>(e: E) -> io::Error
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rust_hashes() {
+        assert_eq!(
+            strip_rust_hash("std::rt::lang_start::h3ae3d1d5a2e6fa5c"),
+            "std::rt::lang_start"
+        );
+        assert_eq!(
+            strip_rust_hash("std::rt::lang_start"),
+            "std::rt::lang_start"
+        );
+        assert_eq!(strip_rust_hash("foo::hello"), "foo::hello");
+    }
+}
@@ -0,0 +1,156 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+
+/// Why collapsing, diffing or plotting stacks failed.
+///
+/// The functions of this crate return [`io::Error`]s (or [`quick_xml::Error::Io`]s), which carry
+/// these errors inside. To match on the cause of a failure, convert the `io::Error` back with
+/// [`From`]:
+///
+/// ```
+/// use inferno::collapse::vtune::Folder;
+/// use inferno::collapse::Collapse;
+///
+/// let input = "Function Stack,CPU Time:Self,Module\nmain,oops,app\n";
+/// let error = Folder::default().collapse(input.as_bytes(), Vec::new()).unwrap_err();
+/// match inferno::Error::from(error) {
+///     inferno::Error::Parse { format, .. } => assert_eq!(format, "vtune"),
+///     e => panic!("unexpected error: {}", e),
+/// }
+/// ```
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The input couldn't be parsed.
+    Parse {
+        /// The name of the format the input was read as, like `perf` for the output of
+        /// `perf script`, or `folded` for folded stack lines.
+        format: &'static str,
+
+        /// The number of the line that couldn't be parsed, counting from 1, if it's known.
+        line: Option<usize>,
+
+        /// What was wrong with the input.
+        message: String,
+    },
+
+    /// The input or output is in a format that isn't supported, like PNG flame graphs without the
+    /// `png` feature.
+    UnsupportedFormat(String),
+
+    /// Options that can't be used together, or with the given input.
+    InvalidOptions(String),
+
+    /// Reading the input or writing the output failed.
+    Io(io::Error),
+}
+
+impl Error {
+    // Makes an error for input in `format` that couldn't be parsed.
+    pub(crate) fn parse<M: fmt::Display>(format: &'static str, message: M) -> Self {
+        Error::Parse {
+            format,
+            line: None,
+            message: message.to_string(),
+        }
+    }
+
+    // Makes an error for the given line of input in `format`, counting from 1, that couldn't be
+    // parsed.
+    pub(crate) fn parse_line<M: fmt::Display>(
+        format: &'static str,
+        line: usize,
+        message: M,
+    ) -> Self {
+        Error::Parse {
+            format,
+            line: Some(line),
+            message: message.to_string(),
+        }
+    }
+
+    // Gives the parse error that `e` carries, if any, the line of input it's about, unless it
+    // already knows its line.
+    pub(crate) fn at_line(mut e: io::Error, line: usize) -> io::Error {
+        if let Some(Error::Parse { line: l, .. }) = e.get_mut().and_then(|e| e.downcast_mut()) {
+            l.get_or_insert(line);
+        }
+        e
+    }
+
+    // Moves the line of the parse error that `e` carries, if any, down by `offset` lines, for
+    // errors about a part of the input that starts after line `offset`.
+    pub(crate) fn offset_line(mut e: io::Error, offset: usize) -> io::Error {
+        if let Some(Error::Parse { line: Some(l), .. }) = e.get_mut().and_then(|e| e.downcast_mut())
+        {
+            *l += offset;
+        }
+        e
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Parse {
+                line: Some(line),
+                message,
+                ..
+            } => write!(f, "line {}: {}", line, message),
+            Error::Parse { message, .. }
+            | Error::UnsupportedFormat(message)
+            | Error::InvalidOptions(message) => f.write_str(message),
+            Error::Io(e) => e.fmt(f),
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    /// Get the error out of an `io::Error` returned by this crate, or else wrap it as
+    /// [`Error::Io`].
+    fn from(e: io::Error) -> Self {
+        if e.get_ref().is_some_and(|inner| inner.is::<Error>()) {
+            let inner = e.into_inner().expect("checked above");
+            return *inner.downcast::<Error>().expect("checked above");
+        }
+        Error::Io(e)
+    }
+}
+
+impl From<quick_xml::Error> for Error {
+    fn from(e: quick_xml::Error) -> Self {
+        match e {
+            quick_xml::Error::Io(e) => Error::from(e),
+            e => Error::Io(io::Error::new(io::ErrorKind::Other, e)),
+        }
+    }
+}
+
+impl From<Error> for io::Error {
+    /// Wrap the error in an `io::Error` of the closest kind, or unwrap an [`Error::Io`].
+    fn from(e: Error) -> Self {
+        let kind = match e {
+            Error::Io(e) => return e,
+            Error::Parse { .. } => io::ErrorKind::InvalidData,
+            Error::UnsupportedFormat(_) => io::ErrorKind::Unsupported,
+            Error::InvalidOptions(_) => io::ErrorKind::InvalidInput,
+        };
+        io::Error::new(kind, e)
+    }
+}
+
+impl From<Error> for quick_xml::Error {
+    fn from(e: Error) -> Self {
+        quick_xml::Error::Io(e.into())
+    }
+}
@@ -0,0 +1,96 @@
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use arrow_array::{ArrayRef, Int64Array, RecordBatch, StringArray};
+use arrow_ipc::writer::{FileWriter, StreamWriter};
+use arrow_schema::{ArrowError, DataType, Field, Schema};
+
+use super::table::{Column, Table};
+use super::{Options, Stacks};
+
+// Rows are written in batches of this many, so that readers of streams can start on the first
+// batches before the last ones are written.
+const BATCH_ROWS: usize = 64 * 1024;
+
+/// Writes the table as an Arrow IPC stream.
+pub(super) fn write_stream<W: Write>(
+    opt: &Options,
+    stacks: &Stacks<'_>,
+    writer: W,
+) -> io::Result<()> {
+    let table = Table::new(opt, stacks);
+    let schema = schema(&table);
+    let encode = || -> Result<(), ArrowError> {
+        let mut writer = StreamWriter::try_new(writer, &schema)?;
+        for batch in batches(&table, &schema) {
+            writer.write(&batch?)?;
+        }
+        writer.finish()
+    };
+    encode().map_err(arrow_error)
+}
+
+/// Writes the table as an Arrow IPC file, also known as Feather version 2.
+pub(super) fn write_file<W: Write>(
+    opt: &Options,
+    stacks: &Stacks<'_>,
+    writer: W,
+) -> io::Result<()> {
+    let table = Table::new(opt, stacks);
+    let schema = schema(&table);
+    let encode = || -> Result<(), ArrowError> {
+        let mut writer = FileWriter::try_new(writer, &schema)?;
+        for batch in batches(&table, &schema) {
+            writer.write(&batch?)?;
+        }
+        writer.finish()
+    };
+    encode().map_err(arrow_error)
+}
+
+fn schema(table: &Table<'_>) -> Arc<Schema> {
+    let fields: Vec<_> = table
+        .columns
+        .iter()
+        .map(|(name, column)| {
+            let data_type = match column {
+                Column::Int(_) => DataType::Int64,
+                Column::Text(_) => DataType::Utf8,
+            };
+            Field::new(*name, data_type, false)
+        })
+        .collect();
+    Arc::new(Schema::new(fields))
+}
+
+fn batches<'t>(
+    table: &'t Table<'_>,
+    schema: &'t Arc<Schema>,
+) -> impl Iterator<Item = Result<RecordBatch, ArrowError>> + 't {
+    let rows = table.rows();
+    (0..rows).step_by(BATCH_ROWS).map(move |start| {
+        let end = rows.min(start + BATCH_ROWS);
+        let columns: Vec<ArrayRef> = table
+            .columns
+            .iter()
+            .map(|(_, column)| -> ArrayRef {
+                match column {
+                    Column::Int(values) => Arc::new(Int64Array::from_iter_values(
+                        values[start..end].iter().map(|&value| value as i64),
+                    )),
+                    Column::Text(values) => {
+                        Arc::new(StringArray::from_iter_values(&values[start..end]))
+                    }
+                }
+            })
+            .collect();
+        RecordBatch::try_new(Arc::clone(schema), columns)
+    })
+}
+
+fn arrow_error(e: ArrowError) -> io::Error {
+    match e {
+        ArrowError::IoError(_, e) => e,
+        e => io::Error::new(io::ErrorKind::Other, e),
+    }
+}
@@ -0,0 +1,39 @@
+use std::io::{self, Write};
+
+use super::table::{Column, Table};
+use super::{Options, Stacks};
+
+pub(super) fn write<W: Write>(opt: &Options, stacks: &Stacks<'_>, mut writer: W) -> io::Result<()> {
+    let table = Table::new(opt, stacks);
+
+    for (i, (name, _)) in table.columns.iter().enumerate() {
+        if i > 0 {
+            writer.write_all(b",")?;
+        }
+        writer.write_all(name.as_bytes())?;
+    }
+    writer.write_all(b"\n")?;
+
+    for row in 0..table.rows() {
+        for (i, (_, column)) in table.columns.iter().enumerate() {
+            if i > 0 {
+                writer.write_all(b",")?;
+            }
+            match column {
+                Column::Int(values) => write!(writer, "{}", values[row])?,
+                Column::Text(values) => write_field(&mut writer, values[row])?,
+            }
+        }
+        writer.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+// Writes a field, quoting it if it has commas, quotes or line breaks in it.
+fn write_field<W: Write>(writer: &mut W, field: &str) -> io::Result<()> {
+    if field.contains(&[',', '"', '\n', '\r'][..]) {
+        write!(writer, "\"{}\"", field.replace('"', "\"\""))
+    } else {
+        writer.write_all(field.as_bytes())
+    }
+}
@@ -0,0 +1,104 @@
+use std::io::{self, Write};
+
+use ahash::{AHashMap, AHashSet};
+
+use super::{Options, Stacks};
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Node {
+    // Samples in stacks that have this function in them.
+    total: usize,
+    // Samples in stacks that end in this function.
+    own: usize,
+}
+
+pub(super) fn write<W: Write>(opt: &Options, stacks: &Stacks<'_>, mut writer: W) -> io::Result<()> {
+    let mut nodes = vec![Node::default(); stacks.frames.len()];
+    let mut edges: Vec<((usize, usize), usize)> = Vec::new();
+    let mut edge_ids = AHashMap::default();
+    let mut total = 0;
+
+    let mut seen_frames = AHashSet::default();
+    let mut seen_edges = AHashSet::default();
+    for (frames, count) in &stacks.stacks {
+        total += count;
+        if let Some(&leaf) = frames.last() {
+            nodes[leaf].own += count;
+        }
+
+        // Recursive functions and calls only count once per stack.
+        seen_frames.clear();
+        seen_edges.clear();
+        for &frame in frames {
+            if seen_frames.insert(frame) {
+                nodes[frame].total += count;
+            }
+        }
+        for edge in frames.windows(2).map(|pair| (pair[0], pair[1])) {
+            if !seen_edges.insert(edge) {
+                continue;
+            }
+            let i = *edge_ids.entry(edge).or_insert_with(|| {
+                edges.push((edge, 0));
+                edges.len() - 1
+            });
+            edges[i].1 += count;
+        }
+    }
+
+    let percent = |samples: usize| {
+        if total == 0 {
+            0.0
+        } else {
+            samples as f64 * 100.0 / total as f64
+        }
+    };
+    let kept = |samples: usize| percent(samples) >= opt.min_percent;
+
+    writeln!(writer, "digraph {} {{", dot_string(&opt.sample_type))?;
+    writeln!(writer, "  node [shape=box];")?;
+    for (i, (node, name)) in nodes.iter().zip(&stacks.frames).enumerate() {
+        if !kept(node.total) {
+            continue;
+        }
+        let label = format!(
+            "{}\ntotal: {} ({:.2}%)\nself: {} ({:.2}%)",
+            name,
+            node.total,
+            percent(node.total),
+            node.own,
+            percent(node.own)
+        );
+        writeln!(writer, "  n{} [label={}];", i, dot_string(&label))?;
+    }
+    for &((from, to), samples) in &edges {
+        if !kept(samples) || !kept(nodes[from].total) || !kept(nodes[to].total) {
+            continue;
+        }
+        writeln!(
+            writer,
+            "  n{} -> n{} [label=\"{}\", penwidth={:.2}];",
+            from,
+            to,
+            samples,
+            1.0 + 4.0 * percent(samples) / 100.0
+        )?;
+    }
+    writeln!(writer, "}}")
+}
+
+// Quotes a string for DOT, where newlines are written as `\n`.
+fn dot_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
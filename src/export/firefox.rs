@@ -0,0 +1,149 @@
+use std::io::{self, Write};
+
+use ahash::AHashMap;
+
+use super::{write_json_string, Options, Stacks};
+
+// The version of the Gecko profile format that is written.
+const GECKO_PROFILE_VERSION: usize = 24;
+
+/// The tables of one thread of the profile.
+#[derive(Default)]
+struct Thread<'a> {
+    name: &'a str,
+    strings: Vec<&'a str>,
+    // Frame table rows, as indices into `strings`.
+    frames: Vec<usize>,
+    frame_ids: AHashMap<usize, usize>,
+    // Stack table rows, as the stack of the caller and the frame.
+    stack_table: Vec<(Option<usize>, usize)>,
+    stack_ids: AHashMap<(Option<usize>, usize), usize>,
+    // Samples, as the stack, the time and the weight.
+    samples: Vec<(usize, usize, usize)>,
+    now: usize,
+}
+
+impl<'a> Thread<'a> {
+    fn add_stack(&mut self, stacks: &Stacks<'a>, frames: &[usize], count: usize) {
+        let mut stack = None;
+        for &frame in frames {
+            let strings = &mut self.strings;
+            let frame_table = &mut self.frames;
+            let frame = *self.frame_ids.entry(frame).or_insert_with(|| {
+                strings.push(stacks.frames[frame]);
+                frame_table.push(strings.len() - 1);
+                frame_table.len() - 1
+            });
+
+            let prefix = stack;
+            let stack_table = &mut self.stack_table;
+            stack = Some(*self.stack_ids.entry((prefix, frame)).or_insert_with(|| {
+                stack_table.push((prefix, frame));
+                stack_table.len() - 1
+            }));
+        }
+
+        if let Some(stack) = stack {
+            self.samples.push((stack, self.now, count));
+            self.now += count;
+        }
+    }
+}
+
+pub(super) fn write<W: Write>(opt: &Options, stacks: &Stacks<'_>, mut writer: W) -> io::Result<()> {
+    let mut threads: Vec<(Option<usize>, Thread<'_>)> = Vec::new();
+    for (frames, count) in &stacks.stacks {
+        let (root, frames) = match frames.split_first() {
+            Some((&root, rest)) if opt.thread_roots => (Some(root), rest),
+            _ => (None, &frames[..]),
+        };
+        let i = match threads.iter().position(|(r, _)| *r == root) {
+            Some(i) => i,
+            None => {
+                let thread = Thread {
+                    name: root.map_or("inferno", |root| stacks.frames[root]),
+                    ..Default::default()
+                };
+                threads.push((root, thread));
+                threads.len() - 1
+            }
+        };
+        threads[i].1.add_stack(stacks, frames, *count);
+    }
+
+    write!(
+        writer,
+        r#"{{"meta":{{"interval":1,"startTime":0,"shutdownTime":null,"processType":0,"product":"inferno","stackwalk":0,"debug":false,"version":{},"categories":[{{"name":"Other","color":"grey","subcategories":["Other"]}}],"markerSchema":[]}},"libs":[],"threads":["#,
+        GECKO_PROFILE_VERSION
+    )?;
+    for (i, (_, thread)) in threads.iter().enumerate() {
+        if i > 0 {
+            writer.write_all(b",")?;
+        }
+        write_thread(&mut writer, opt, thread, i + 1)?;
+    }
+    writeln!(writer, r#"],"processes":[],"pausedRanges":[]}}"#)
+}
+
+fn write_thread<W: Write>(
+    writer: &mut W,
+    opt: &Options,
+    thread: &Thread<'_>,
+    tid: usize,
+) -> io::Result<()> {
+    writer.write_all(br#"{"name":"#)?;
+    write_json_string(writer, thread.name)?;
+    write!(
+        writer,
+        r#","processType":"default","pid":1,"tid":{},"registerTime":0,"unregisterTime":null,"#,
+        tid
+    )?;
+
+    writer.write_all(
+        br#""samples":{"schema":{"stack":0,"time":1,"eventDelay":2,"weight":3},"weightType":"#,
+    )?;
+    // These are the only weight types the Firefox Profiler knows.
+    let weight_type = match opt.sample_unit.as_str() {
+        "bytes" => "bytes",
+        "milliseconds" => "tracing-ms",
+        _ => "samples",
+    };
+    write_json_string(writer, weight_type)?;
+    writer.write_all(br#","data":["#)?;
+    for (i, (stack, time, weight)) in thread.samples.iter().enumerate() {
+        if i > 0 {
+            writer.write_all(b",")?;
+        }
+        write!(writer, "[{},{},0,{}]", stack, time, weight)?;
+    }
+
+    writer.write_all(br#"]},"markers":{"schema":{"name":0,"startTime":1,"endTime":2,"phase":3,"category":4,"data":5},"data":[]},"#)?;
+
+    writer.write_all(br#""stackTable":{"schema":{"prefix":0,"frame":1},"data":["#)?;
+    for (i, (prefix, frame)) in thread.stack_table.iter().enumerate() {
+        if i > 0 {
+            writer.write_all(b",")?;
+        }
+        match prefix {
+            Some(prefix) => write!(writer, "[{},{}]", prefix, frame)?,
+            None => write!(writer, "[null,{}]", frame)?,
+        }
+    }
+
+    writer.write_all(br#"]},"frameTable":{"schema":{"location":0,"relevantForJS":1,"innerWindowID":2,"implementation":3,"line":4,"column":5,"category":6,"subcategory":7},"data":["#)?;
+    for (i, location) in thread.frames.iter().enumerate() {
+        if i > 0 {
+            writer.write_all(b",")?;
+        }
+        write!(writer, "[{},false,0,null,null,null,0,0]", location)?;
+    }
+
+    writer.write_all(br#"]},"stringTable":["#)?;
+    for (i, string) in thread.strings.iter().enumerate() {
+        if i > 0 {
+            writer.write_all(b",")?;
+        }
+        write_json_string(writer, string)?;
+    }
+    writer.write_all(b"]}")
+}
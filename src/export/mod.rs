@@ -0,0 +1,445 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, prelude::*};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use ahash::AHashMap;
+use log::warn;
+
+/// Export to the gzipped [`profile.proto`] format of pprof.
+///
+/// Every distinct frame gets a function, and a location with the same id that only has a line
+/// pointing at that function, since folded stacks don't have addresses or line numbers.
+///
+///   [`profile.proto`]: https://github.com/google/pprof/blob/main/proto/profile.proto
+pub mod pprof;
+
+/// Export to the [speedscope] JSON file format, as a single sampled profile.
+///
+///   [speedscope]: https://www.speedscope.app/
+pub mod speedscope;
+
+/// Export to the [Trace Event] JSON format of `chrome://tracing` and the [Perfetto UI].
+///
+/// The stacks are laid out one after the other, like in a flame chart, with each one lasting as
+/// long as its sample count. Sample counts in time units (see [`Options::sample_unit`]) are
+/// converted to the microseconds of trace events; any other sample counts last a microsecond
+/// each.
+///
+///   [Trace Event]: https://docs.google.com/document/d/1CvAClvFfyA5R-PhYUmn5OOQtYMH4h6I0nSsKchNAySU
+///   [Perfetto UI]: https://ui.perfetto.dev/
+pub mod trace_event;
+
+/// Export to a [Graphviz] DOT call graph, with a node for each function and an edge for each
+/// call, weighted by the samples they appear in.
+///
+///   [Graphviz]: https://graphviz.org/
+pub mod dot;
+
+/// Export to the Gecko profile JSON format, which can be opened at [profiler.firefox.com].
+///
+/// Each distinct stack becomes one sample, weighted by its sample count.
+///
+///   [profiler.firefox.com]: https://profiler.firefox.com/
+pub mod firefox;
+
+/// Export to CSV, as a table for analysis in spreadsheets or dataframe libraries.
+///
+/// By default, there's a row for each frame of each stack, with the columns `stack` (the index
+/// of the stack), `depth`, `function`, `module`, `self` and `total`. With
+/// [`Options::per_function`], there's a row for each function instead, with the columns
+/// `function`, `module`, `self` and `total`. The module of frames like ``libc.so.1`_read`` is
+/// the part before the backtick, and is empty for other frames.
+pub mod csv;
+
+/// Export to [Apache Parquet], with the same columns as [`csv`].
+///
+/// Only available with the `parquet` feature.
+///
+///   [Apache Parquet]: https://parquet.apache.org/
+#[cfg(feature = "parquet")]
+pub mod parquet;
+
+/// Export to the [Arrow IPC] streaming and file (Feather) formats, with the same columns as
+/// [`csv`], for loading into Polars, DataFusion, pandas and the like without parsing.
+///
+/// Rows are written in batches of 65536. Only available with the `arrow` feature.
+///
+///   [Arrow IPC]: https://arrow.apache.org/docs/format/Columnar.html#serialization-and-interprocess-communication-ipc
+#[cfg(feature = "arrow")]
+pub mod arrow;
+
+/// Export per-function statistics as JSON, for CI checks like "`parse` must stay under 5% of
+/// the self samples" that shouldn't have to parse folded stacks themselves.
+///
+/// The output has the `sample_type`, `sample_unit` and `total` samples of the profile, and its
+/// `functions`, most self samples first. Each function has its `name`, `self` and `total`
+/// samples, the same as percentages of all samples (`self_percent` and `total_percent`), and the
+/// `callers` and `callees` it has, with the `samples` of each call. Recursive functions and calls
+/// count once per stack. Functions in less than [`Options::min_percent`] of the samples are left
+/// out.
+pub mod stats;
+
+/// Push folded stacks to a [Pyroscope] server, or anything else that takes its `/ingest` API,
+/// like Grafana Alloy's `pyroscope.receive_http`.
+///
+/// Only available with the `push` feature.
+///
+///   [Pyroscope]: https://grafana.com/oss/pyroscope/
+#[cfg(feature = "push")]
+pub mod push;
+
+/// Store folded stacks in a SQLite database, and read them back out.
+///
+/// A database can hold many profiles, which share the `frames` they call and the `stacks` they
+/// have in common. Each stack is a node with a `parent` stack (null for roots) and a `frame`, and
+/// the `weights` table holds the sample count of each stack in each of the `profiles`. The
+/// `metadata` table holds key-value pairs for each profile. For example, the self samples of
+/// each function in the last profile can be queried with:
+///
+/// ```sql
+/// SELECT frames.name, SUM(weights.weight) FROM weights
+/// JOIN stacks ON stacks.id = weights.stack
+/// JOIN frames ON frames.id = stacks.frame
+/// WHERE weights.profile = (SELECT MAX(id) FROM profiles)
+/// GROUP BY frames.name;
+/// ```
+///
+/// Only available with the `sqlite` feature.
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+
+mod table;
+
+const READER_CAPACITY: usize = 128 * 1024;
+
+/// The formats folded stacks can be exported to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Format {
+    /// A gzipped pprof `profile.proto`, as read by `go tool pprof`, Parca and Grafana Phlare.
+    #[default]
+    Pprof,
+    /// A speedscope JSON file, for https://www.speedscope.app/.
+    Speedscope,
+    /// Trace Event JSON, for the Perfetto UI and `chrome://tracing`.
+    TraceEvent,
+    /// A Graphviz DOT call graph.
+    Dot,
+    /// Gecko profile JSON, for the Firefox Profiler.
+    Firefox,
+    /// A CSV table.
+    Csv,
+    /// An Apache Parquet table. Exporting to it fails unless the `parquet` feature is enabled.
+    Parquet,
+    /// An Arrow IPC stream. Exporting to it fails unless the `arrow` feature is enabled.
+    Arrow,
+    /// An Arrow IPC file, also known as Feather. Exporting to it fails unless the `arrow` feature
+    /// is enabled.
+    Feather,
+    /// Per-function statistics JSON.
+    Stats,
+}
+
+impl Format {
+    /// The valid set of formats (via `FromStr`).
+    pub const VARIANTS: &'static [&'static str] = &[
+        "pprof",
+        "speedscope",
+        "trace-event",
+        "dot",
+        "firefox",
+        "csv",
+        "parquet",
+        "arrow",
+        "feather",
+        "stats",
+    ];
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pprof" => Ok(Format::Pprof),
+            "speedscope" => Ok(Format::Speedscope),
+            "trace-event" => Ok(Format::TraceEvent),
+            "dot" => Ok(Format::Dot),
+            "firefox" => Ok(Format::Firefox),
+            "csv" => Ok(Format::Csv),
+            "parquet" => Ok(Format::Parquet),
+            "arrow" => Ok(Format::Arrow),
+            "feather" => Ok(Format::Feather),
+            "stats" => Ok(Format::Stats),
+            unknown => Err(format!("unknown format: {}", unknown)),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Format::Pprof => "pprof",
+            Format::Speedscope => "speedscope",
+            Format::TraceEvent => "trace-event",
+            Format::Dot => "dot",
+            Format::Firefox => "firefox",
+            Format::Csv => "csv",
+            Format::Parquet => "parquet",
+            Format::Arrow => "arrow",
+            Format::Feather => "feather",
+            Format::Stats => "stats",
+        })
+    }
+}
+
+/// Configure the exported output.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct Options {
+    /// The format to export to.
+    ///
+    /// Default is `Format::Pprof`.
+    pub format: Format,
+
+    /// What the sample counts measure, like `samples` or `cpu`.
+    ///
+    /// Default is `"samples"`.
+    pub sample_type: String,
+
+    /// The unit of the sample counts, like `count` or `nanoseconds`.
+    ///
+    /// Default is `"count"`.
+    pub sample_unit: String,
+
+    /// Treat the root frame of each stack as the thread it ran on, like the ones written by
+    /// `collapse-perf --tid`, and give each thread a track of its own in formats that have them.
+    ///
+    /// Default is `false`.
+    pub thread_roots: bool,
+
+    /// Leave out the functions and calls of call graphs, and the functions of statistics, that
+    /// are in less than this percentage of the samples.
+    ///
+    /// Default is `0.0`, which keeps everything.
+    pub min_percent: f64,
+
+    /// Write a row for each function, instead of for each frame of each stack, in table
+    /// formats (CSV, Parquet and Arrow).
+    ///
+    /// Default is `false`.
+    pub per_function: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            format: Format::default(),
+            sample_type: "samples".to_string(),
+            sample_unit: "count".to_string(),
+            thread_roots: false,
+            min_percent: 0.0,
+            per_function: false,
+        }
+    }
+}
+
+/// Export folded stack lines to another profile format, and write the result to `writer`.
+///
+/// Each line is expected to have the following whitespace-separated fields, as produced by the
+/// `collapse` tools:
+///
+///  - A semicolon-separated list of frame names (e.g., `main;foo;bar;baz`).
+///  - A sample count for the given stack.
+///
+/// Lines that don't end in a sample count are ignored, with a warning.
+pub fn from_lines<'a, I, W>(opt: &Options, lines: I, writer: W) -> io::Result<()>
+where
+    I: IntoIterator<Item = &'a str>,
+    W: Write,
+{
+    let stacks = Stacks::from_lines(lines);
+    match opt.format {
+        Format::Pprof => pprof::write(opt, &stacks, writer),
+        Format::Speedscope => speedscope::write(opt, &stacks, writer),
+        Format::TraceEvent => trace_event::write(opt, &stacks, writer),
+        Format::Dot => dot::write(opt, &stacks, writer),
+        Format::Firefox => firefox::write(opt, &stacks, writer),
+        Format::Csv => csv::write(opt, &stacks, writer),
+        #[cfg(feature = "parquet")]
+        Format::Parquet => parquet::write(opt, &stacks, writer),
+        #[cfg(not(feature = "parquet"))]
+        Format::Parquet => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "exporting to Parquet needs inferno to be built with the `parquet` feature",
+        )),
+        #[cfg(feature = "arrow")]
+        Format::Arrow => arrow::write_stream(opt, &stacks, writer),
+        #[cfg(feature = "arrow")]
+        Format::Feather => arrow::write_file(opt, &stacks, writer),
+        #[cfg(not(feature = "arrow"))]
+        Format::Arrow | Format::Feather => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "exporting to Arrow needs inferno to be built with the `arrow` feature",
+        )),
+        Format::Stats => stats::write(opt, &stacks, writer),
+    }
+}
+
+/// Export folded stack lines from a reader.
+///
+/// See [`from_lines`] for the expected format of each line.
+pub fn from_reader<R, W>(opt: &Options, mut reader: R, writer: W) -> io::Result<()>
+where
+    R: Read,
+    W: Write,
+{
+    let mut input = String::new();
+    reader.read_to_string(&mut input)?;
+    from_lines(opt, input.lines(), writer)
+}
+
+/// Export folded stack lines from files, and write the result to the provided `writer`.
+///
+/// If files is empty, or is just `-`, STDIN will be used as input.
+pub fn from_files<W: Write>(opt: &Options, files: &[PathBuf], writer: W) -> io::Result<()> {
+    let input = read_files(files)?;
+    from_lines(opt, input.lines(), writer)
+}
+
+// Reads all of the files, or STDIN if files is empty or just `-`, into one string.
+fn read_files(files: &[PathBuf]) -> io::Result<String> {
+    let mut input = String::new();
+    if files.is_empty() {
+        io::stdin().lock().read_to_string(&mut input)?;
+    }
+    let mut stdin_added = false;
+    for infile in files {
+        if infile.to_str() == Some("-") {
+            if !stdin_added {
+                io::stdin().lock().read_to_string(&mut input)?;
+                stdin_added = true;
+            }
+        } else {
+            let mut r = io::BufReader::with_capacity(READER_CAPACITY, File::open(infile)?);
+            r.read_to_string(&mut input)?;
+        }
+    }
+    Ok(input)
+}
+
+/// Folded stacks with their frames interned, in the order they first appear in the input.
+#[derive(Debug, Default)]
+struct Stacks<'a> {
+    /// The distinct frame names; frames are referred to by their index in here.
+    frames: Vec<&'a str>,
+
+    /// The distinct stacks, from the root to the leaf, with their total sample counts.
+    stacks: Vec<(Vec<usize>, usize)>,
+}
+
+impl<'a> Stacks<'a> {
+    fn from_lines<I>(lines: I) -> Self
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let mut stacks = Stacks::default();
+        let mut frame_ids = AHashMap::default();
+        let mut stack_ids: AHashMap<Vec<usize>, usize> = AHashMap::default();
+        let mut ignored = 0;
+        for line in lines {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let (stack, count) = match parse_line(line) {
+                Some(parts) => parts,
+                None => {
+                    ignored += 1;
+                    continue;
+                }
+            };
+            let frames: Vec<_> = stack
+                .split(';')
+                .map(|frame| {
+                    *frame_ids.entry(frame).or_insert_with(|| {
+                        stacks.frames.push(frame);
+                        stacks.frames.len() - 1
+                    })
+                })
+                .collect();
+            match stack_ids.get(&frames) {
+                Some(&i) => stacks.stacks[i].1 += count,
+                None => {
+                    stack_ids.insert(frames.clone(), stacks.stacks.len());
+                    stacks.stacks.push((frames, count));
+                }
+            }
+        }
+
+        if ignored != 0 {
+            warn!("Ignored {} lines with invalid format", ignored);
+        }
+        stacks
+    }
+}
+
+// Splits a folded line into its stack and sample count. Fractional counts are truncated.
+fn parse_line(line: &str) -> Option<(&str, usize)> {
+    let (stack, count) = line.rsplit_once(' ')?;
+    let count = match count.parse() {
+        Ok(count) => count,
+        Err(_) => count.parse::<f64>().ok().filter(|count| *count >= 0.0)? as usize,
+    };
+    Some((stack.trim_end(), count))
+}
+
+// Writes a string as a JSON string literal, with quotes.
+fn write_json_string<W: Write>(writer: &mut W, s: &str) -> io::Result<()> {
+    writer.write_all(b"\"")?;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        let escaped = match c {
+            '"' => "\\\"",
+            '\\' => "\\\\",
+            '\n' => "\\n",
+            '\r' => "\\r",
+            '\t' => "\\t",
+            c if (c as u32) < 0x20 => "",
+            _ => continue,
+        };
+        writer.write_all(&s.as_bytes()[start..i])?;
+        if escaped.is_empty() {
+            write!(writer, "\\u{:04x}", c as u32)?;
+        } else {
+            writer.write_all(escaped.as_bytes())?;
+        }
+        start = i + c.len_utf8();
+    }
+    writer.write_all(&s.as_bytes()[start..])?;
+    writer.write_all(b"\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_string_escaping() {
+        let mut json = Vec::new();
+        write_json_string(&mut json, "a \"quoted\" \\path\n\u{1}é").unwrap();
+        assert_eq!(
+            String::from_utf8(json).unwrap(),
+            r#""a \"quoted\" \\path\n\u0001é""#
+        );
+    }
+
+    #[test]
+    fn parse_line_counts() {
+        assert_eq!(parse_line("main;foo 3"), Some(("main;foo", 3)));
+        assert_eq!(parse_line("main;foo 3.7"), Some(("main;foo", 3)));
+        assert_eq!(parse_line("main;foo"), None);
+    }
+}
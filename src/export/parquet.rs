@@ -0,0 +1,57 @@
+use std::io::{self, Write};
+use std::sync::Arc;
+
+use ::parquet::data_type::{ByteArray, ByteArrayType, Int64Type};
+use ::parquet::errors::ParquetError;
+use ::parquet::file::properties::WriterProperties;
+use ::parquet::file::writer::SerializedFileWriter;
+use ::parquet::schema::parser::parse_message_type;
+
+use super::table::{Column, Table};
+use super::{Options, Stacks};
+
+pub(super) fn write<W: Write>(opt: &Options, stacks: &Stacks<'_>, mut writer: W) -> io::Result<()> {
+    let table = Table::new(opt, stacks);
+    // The file writer needs a `Send` writer, so write the file to memory first.
+    let file = encode(&table).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    writer.write_all(&file)
+}
+
+fn encode(table: &Table<'_>) -> Result<Vec<u8>, ParquetError> {
+    let fields: Vec<_> = table
+        .columns
+        .iter()
+        .map(|(name, column)| match column {
+            Column::Int(_) => format!("REQUIRED INT64 {};", name),
+            Column::Text(_) => format!("REQUIRED BYTE_ARRAY {} (UTF8);", name),
+        })
+        .collect();
+    let schema = parse_message_type(&format!("message stacks {{ {} }}", fields.join(" ")))?;
+
+    let mut file = Vec::new();
+    let properties = WriterProperties::builder().build();
+    let mut writer = SerializedFileWriter::new(&mut file, Arc::new(schema), Arc::new(properties))?;
+    let mut row_group = writer.next_row_group()?;
+    let mut columns = table.columns.iter();
+    while let Some(mut column_writer) = row_group.next_column()? {
+        match columns.next() {
+            Some((_, Column::Int(values))) => {
+                let values: Vec<_> = values.iter().map(|&value| value as i64).collect();
+                column_writer
+                    .typed::<Int64Type>()
+                    .write_batch(&values, None, None)?;
+            }
+            Some((_, Column::Text(values))) => {
+                let values: Vec<_> = values.iter().map(|&value| ByteArray::from(value)).collect();
+                column_writer
+                    .typed::<ByteArrayType>()
+                    .write_batch(&values, None, None)?;
+            }
+            None => unreachable!("the schema has a column for each column of the table"),
+        }
+        column_writer.close()?;
+    }
+    row_group.close()?;
+    writer.close()?;
+    Ok(file)
+}
@@ -0,0 +1,184 @@
+use std::io::{self, Write};
+
+use ahash::AHashMap;
+use libflate::gzip::Encoder;
+
+use super::{Options, Stacks};
+
+// Field numbers of the messages in profile.proto.
+const PROFILE_SAMPLE_TYPE: u32 = 1;
+const PROFILE_SAMPLE: u32 = 2;
+const PROFILE_LOCATION: u32 = 4;
+const PROFILE_FUNCTION: u32 = 5;
+const PROFILE_STRING_TABLE: u32 = 6;
+const PROFILE_PERIOD_TYPE: u32 = 11;
+const PROFILE_PERIOD: u32 = 12;
+const VALUE_TYPE_TYPE: u32 = 1;
+const VALUE_TYPE_UNIT: u32 = 2;
+const SAMPLE_LOCATION_ID: u32 = 1;
+const SAMPLE_VALUE: u32 = 2;
+const LOCATION_ID: u32 = 1;
+const LOCATION_LINE: u32 = 4;
+const LINE_FUNCTION_ID: u32 = 1;
+const FUNCTION_ID: u32 = 1;
+const FUNCTION_NAME: u32 = 2;
+const FUNCTION_SYSTEM_NAME: u32 = 3;
+
+// Protobuf wire types.
+const VARINT: u8 = 0;
+const LENGTH_DELIMITED: u8 = 2;
+
+pub(super) fn write<W: Write>(opt: &Options, stacks: &Stacks<'_>, writer: W) -> io::Result<()> {
+    let profile = encode(opt, stacks);
+    let mut encoder = Encoder::new(writer)?;
+    encoder.write_all(&profile)?;
+    encoder.finish().into_result()?;
+    Ok(())
+}
+
+fn encode(opt: &Options, stacks: &Stacks<'_>) -> Vec<u8> {
+    let mut strings = StringTable::default();
+    let mut profile = Vec::new();
+
+    let mut value_type = Vec::new();
+    varint_field(
+        &mut value_type,
+        VALUE_TYPE_TYPE,
+        strings.index(&opt.sample_type),
+    );
+    varint_field(
+        &mut value_type,
+        VALUE_TYPE_UNIT,
+        strings.index(&opt.sample_unit),
+    );
+    bytes_field(&mut profile, PROFILE_SAMPLE_TYPE, &value_type);
+    bytes_field(&mut profile, PROFILE_PERIOD_TYPE, &value_type);
+    varint_field(&mut profile, PROFILE_PERIOD, 1);
+
+    for (frames, count) in &stacks.stacks {
+        let mut sample = Vec::new();
+        // pprof lists the locations of a sample from the leaf to the root.
+        packed_field(
+            &mut sample,
+            SAMPLE_LOCATION_ID,
+            frames.iter().rev().map(|&frame| frame as u64 + 1),
+        );
+        packed_field(&mut sample, SAMPLE_VALUE, Some(*count as u64));
+        bytes_field(&mut profile, PROFILE_SAMPLE, &sample);
+    }
+
+    for (i, &name) in stacks.frames.iter().enumerate() {
+        // Ids must be non-zero.
+        let id = i as u64 + 1;
+
+        let mut line = Vec::new();
+        varint_field(&mut line, LINE_FUNCTION_ID, id);
+        let mut location = Vec::new();
+        varint_field(&mut location, LOCATION_ID, id);
+        bytes_field(&mut location, LOCATION_LINE, &line);
+        bytes_field(&mut profile, PROFILE_LOCATION, &location);
+
+        let name = strings.index(name);
+        let mut function = Vec::new();
+        varint_field(&mut function, FUNCTION_ID, id);
+        varint_field(&mut function, FUNCTION_NAME, name);
+        varint_field(&mut function, FUNCTION_SYSTEM_NAME, name);
+        bytes_field(&mut profile, PROFILE_FUNCTION, &function);
+    }
+
+    for string in &strings.strings {
+        bytes_field(&mut profile, PROFILE_STRING_TABLE, string.as_bytes());
+    }
+
+    profile
+}
+
+/// The strings of a profile, which messages refer to by their index.
+struct StringTable<'a> {
+    strings: Vec<&'a str>,
+    indices: AHashMap<&'a str, u64>,
+}
+
+impl Default for StringTable<'_> {
+    fn default() -> Self {
+        // The first string must be the empty string.
+        let mut indices = AHashMap::default();
+        indices.insert("", 0);
+        StringTable {
+            strings: vec![""],
+            indices,
+        }
+    }
+}
+
+impl<'a> StringTable<'a> {
+    fn index(&mut self, string: &'a str) -> u64 {
+        let strings = &mut self.strings;
+        *self.indices.entry(string).or_insert_with(|| {
+            strings.push(string);
+            strings.len() as u64 - 1
+        })
+    }
+}
+
+fn varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn key(buf: &mut Vec<u8>, field: u32, wire_type: u8) {
+    varint(buf, (u64::from(field) << 3) | u64::from(wire_type));
+}
+
+fn varint_field(buf: &mut Vec<u8>, field: u32, value: u64) {
+    key(buf, field, VARINT);
+    varint(buf, value);
+}
+
+fn bytes_field(buf: &mut Vec<u8>, field: u32, bytes: &[u8]) {
+    key(buf, field, LENGTH_DELIMITED);
+    varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+fn packed_field<I>(buf: &mut Vec<u8>, field: u32, values: I)
+where
+    I: IntoIterator<Item = u64>,
+{
+    let mut packed = Vec::new();
+    for value in values {
+        varint(&mut packed, value);
+    }
+    bytes_field(buf, field, &packed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn varint_encoding() {
+        let mut buf = Vec::new();
+        varint(&mut buf, 1);
+        varint(&mut buf, 300);
+        assert_eq!(buf, [0x01, 0xac, 0x02]);
+    }
+
+    #[test]
+    fn encodes_profile() {
+        let stacks = Stacks::from_lines(vec!["main;foo 3", "main;bar 2"]);
+        let profile = encode(&Options::default(), &stacks);
+
+        // The first sample is main;foo, with locations 2 (foo) and 1 (main).
+        let sample = [0x12, 0x07, 0x0a, 0x02, 0x02, 0x01, 0x12, 0x01, 0x03];
+        assert!(profile.windows(sample.len()).any(|window| window == sample));
+        // The string table starts with the empty string.
+        let strings = [0x32, 0x00, 0x32, 0x07];
+        assert!(profile
+            .windows(strings.len())
+            .any(|window| window == strings));
+    }
+}
@@ -0,0 +1,148 @@
+use std::fmt::Write as _;
+use std::io;
+use std::path::PathBuf;
+
+use log::info;
+
+use super::{read_files, Stacks};
+
+/// Configure where and how folded stacks are pushed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Options {
+    /// The base URL of the server, like `http://localhost:4040`.
+    ///
+    /// Default is `"http://localhost:4040"`.
+    pub url: String,
+
+    /// The name of the application the stacks were collected from.
+    ///
+    /// Default is `"inferno"`.
+    pub app_name: String,
+
+    /// Labels to attach to the profile, as key-value pairs.
+    ///
+    /// Default is no labels.
+    pub labels: Vec<(String, String)>,
+
+    /// The unit of the sample counts: `samples`, `objects` or `bytes`.
+    ///
+    /// Default is `"samples"`.
+    pub units: String,
+
+    /// How many samples were collected per second, if the sample counts are samples.
+    ///
+    /// Default is `100`.
+    pub sample_rate: u32,
+
+    /// The start of the profile, in seconds since the Unix epoch.
+    ///
+    /// Default is `None`, which lets the server use the time it receives the profile.
+    pub from: Option<u64>,
+
+    /// The end of the profile, in seconds since the Unix epoch.
+    ///
+    /// Default is `None`, which lets the server use the time it receives the profile.
+    pub until: Option<u64>,
+
+    /// A token to send as a bearer token in the `Authorization` header.
+    ///
+    /// Default is `None`.
+    pub auth_token: Option<String>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            url: "http://localhost:4040".to_string(),
+            app_name: "inferno".to_string(),
+            labels: Vec::new(),
+            units: "samples".to_string(),
+            sample_rate: 100,
+            from: None,
+            until: None,
+            auth_token: None,
+        }
+    }
+}
+
+impl Options {
+    // The application name with the labels, like `app{env=prod,region=eu}`.
+    fn name(&self) -> String {
+        let mut name = self.app_name.clone();
+        if !self.labels.is_empty() {
+            name.push('{');
+            for (i, (key, value)) in self.labels.iter().enumerate() {
+                if i > 0 {
+                    name.push(',');
+                }
+                let _ = write!(name, "{}={}", key, value);
+            }
+            name.push('}');
+        }
+        name
+    }
+}
+
+/// Push folded stack lines to the server.
+///
+/// See [`super::from_lines`] for the expected format of each line. Stacks that appear more than
+/// once are merged before they are sent.
+pub fn from_lines<'a, I>(opt: &Options, lines: I) -> io::Result<()>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let stacks = Stacks::from_lines(lines);
+    let mut body = String::new();
+    for (frames, count) in &stacks.stacks {
+        for (i, &frame) in frames.iter().enumerate() {
+            if i > 0 {
+                body.push(';');
+            }
+            body.push_str(stacks.frames[frame]);
+        }
+        let _ = writeln!(body, " {}", count);
+    }
+
+    let url = format!("{}/ingest", opt.url.trim_end_matches('/'));
+    let mut request = ureq::post(&url)
+        .query("name", &opt.name())
+        .query("format", "folded")
+        .query("units", &opt.units)
+        .query("aggregationType", "sum")
+        .query("sampleRate", &opt.sample_rate.to_string())
+        .query("spyName", "inferno")
+        .set("Content-Type", "text/plain");
+    if let Some(from) = opt.from {
+        request = request.query("from", &from.to_string());
+    }
+    if let Some(until) = opt.until {
+        request = request.query("until", &until.to_string());
+    }
+    if let Some(token) = &opt.auth_token {
+        request = request.set("Authorization", &format!("Bearer {}", token));
+    }
+
+    info!("Pushing {} stacks to {}", stacks.stacks.len(), url);
+    match request.send_string(&body) {
+        Ok(_) => Ok(()),
+        Err(ureq::Error::Status(status, response)) => Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!(
+                "{} responded with {}: {}",
+                url,
+                status,
+                response.into_string().unwrap_or_default().trim()
+            ),
+        )),
+        Err(e) => Err(io::Error::new(io::ErrorKind::Other, e.to_string())),
+    }
+}
+
+/// Push folded stack lines from files to the server.
+///
+/// If files is empty, or is just `-`, STDIN will be used as input.
+pub fn from_files(opt: &Options, files: &[PathBuf]) -> io::Result<()> {
+    let input = read_files(files)?;
+    from_lines(opt, input.lines())
+}
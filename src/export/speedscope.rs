@@ -0,0 +1,72 @@
+use std::io::{self, Write};
+
+use super::{write_json_string, Options, Stacks};
+
+// The units speedscope knows; anything else is shown as plain numbers.
+const UNITS: &[&str] = &[
+    "bytes",
+    "microseconds",
+    "milliseconds",
+    "nanoseconds",
+    "seconds",
+];
+
+pub(super) fn write<W: Write>(opt: &Options, stacks: &Stacks<'_>, mut writer: W) -> io::Result<()> {
+    let unit = if UNITS.contains(&opt.sample_unit.as_str()) {
+        opt.sample_unit.as_str()
+    } else {
+        "none"
+    };
+    let total: usize = stacks.stacks.iter().map(|(_, count)| count).sum();
+
+    writer.write_all(
+        br#"{"$schema":"https://www.speedscope.app/file-format-schema.json","shared":{"frames":["#,
+    )?;
+    for (i, frame) in stacks.frames.iter().enumerate() {
+        if i > 0 {
+            writer.write_all(b",")?;
+        }
+        writer.write_all(br#"{"name":"#)?;
+        write_json_string(&mut writer, frame)?;
+        writer.write_all(b"}")?;
+    }
+
+    writer.write_all(br#"]},"profiles":[{"type":"sampled","name":"#)?;
+    write_json_string(&mut writer, &opt.sample_type)?;
+    writer.write_all(br#","unit":"#)?;
+    write_json_string(&mut writer, unit)?;
+    write!(
+        writer,
+        r#","startValue":0,"endValue":{},"samples":["#,
+        total
+    )?;
+    for (i, (frames, _)) in stacks.stacks.iter().enumerate() {
+        if i > 0 {
+            writer.write_all(b",")?;
+        }
+        writer.write_all(b"[")?;
+        for (j, frame) in frames.iter().enumerate() {
+            if j > 0 {
+                writer.write_all(b",")?;
+            }
+            write!(writer, "{}", frame)?;
+        }
+        writer.write_all(b"]")?;
+    }
+
+    writer.write_all(br#"],"weights":["#)?;
+    for (i, (_, count)) in stacks.stacks.iter().enumerate() {
+        if i > 0 {
+            writer.write_all(b",")?;
+        }
+        write!(writer, "{}", count)?;
+    }
+
+    writer.write_all(br#"]}],"name":"#)?;
+    write_json_string(&mut writer, &opt.sample_type)?;
+    writeln!(
+        writer,
+        r#","activeProfileIndex":0,"exporter":"inferno@{}"}}"#,
+        env!("CARGO_PKG_VERSION")
+    )
+}
@@ -0,0 +1,232 @@
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use ahash::AHashMap;
+use log::info;
+use rusqlite::{params, Connection, OptionalExtension};
+
+use super::{read_files, Stacks};
+
+// Frames and stacks are shared by all the profiles in a database, with stacks stored as a tree
+// of (parent, frame) nodes, so runs of the same program take little more space than their
+// weights.
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS profiles (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS metadata (
+    profile INTEGER NOT NULL REFERENCES profiles (id),
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (profile, key)
+);
+CREATE TABLE IF NOT EXISTS frames (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL UNIQUE
+);
+CREATE TABLE IF NOT EXISTS stacks (
+    id INTEGER PRIMARY KEY,
+    parent INTEGER REFERENCES stacks (id),
+    frame INTEGER NOT NULL REFERENCES frames (id)
+);
+CREATE INDEX IF NOT EXISTS stacks_parent_frame ON stacks (parent, frame);
+CREATE TABLE IF NOT EXISTS weights (
+    profile INTEGER NOT NULL REFERENCES profiles (id),
+    stack INTEGER NOT NULL REFERENCES stacks (id),
+    weight INTEGER NOT NULL,
+    PRIMARY KEY (profile, stack)
+);
+";
+
+/// Configure the profile that is stored.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Options {
+    /// The name to store the profile under. Names don't have to be unique; reading a profile by
+    /// name reads the last one stored under that name.
+    ///
+    /// Default is `"inferno"`.
+    pub name: String,
+
+    /// Key-value pairs to store with the profile, like the command that was profiled.
+    ///
+    /// Default is no metadata.
+    pub metadata: Vec<(String, String)>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            name: "inferno".to_string(),
+            metadata: Vec::new(),
+        }
+    }
+}
+
+/// Store folded stack lines as a new profile in the SQLite database at `path`, creating the
+/// database if it doesn't exist yet. Returns the id of the new profile.
+///
+/// See [`super::from_lines`] for the expected format of each line.
+pub fn from_lines<'a, I>(opt: &Options, lines: I, path: &Path) -> io::Result<i64>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let stacks = Stacks::from_lines(lines);
+    let mut conn = Connection::open(path).map_err(sql_error)?;
+    conn.execute_batch(SCHEMA).map_err(sql_error)?;
+    let tx = conn.transaction().map_err(sql_error)?;
+    let profile = insert_profile(&tx, opt, &stacks).map_err(sql_error)?;
+    tx.commit().map_err(sql_error)?;
+    info!(
+        "Stored {} stacks as profile {} in {}",
+        stacks.stacks.len(),
+        profile,
+        path.display()
+    );
+    Ok(profile)
+}
+
+/// Store folded stack lines from files as a new profile in the SQLite database at `path`.
+///
+/// If files is empty, or is just `-`, STDIN will be used as input.
+pub fn from_files(opt: &Options, files: &[PathBuf], path: &Path) -> io::Result<i64> {
+    let input = read_files(files)?;
+    from_lines(opt, input.lines(), path)
+}
+
+/// Read the last profile stored under `name` in the SQLite database at `path` back out as
+/// folded stack lines, and write them to `writer`.
+pub fn to_folded<W: Write>(path: &Path, name: &str, mut writer: W) -> io::Result<()> {
+    let conn = Connection::open(path).map_err(sql_error)?;
+    let profile: Option<i64> = conn
+        .query_row(
+            "SELECT id FROM profiles WHERE name = ?1 ORDER BY id DESC LIMIT 1",
+            [name],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(sql_error)?;
+    let profile = profile.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No profile named '{}' in {}", name, path.display()),
+        )
+    })?;
+
+    let frames = conn
+        .prepare("SELECT id, name FROM frames")
+        .and_then(|mut statement| {
+            let rows = statement
+                .query_map([], |row| {
+                    Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+                })?
+                .collect::<Result<AHashMap<_, _>, _>>();
+            rows
+        })
+        .map_err(sql_error)?;
+    let stacks = conn
+        .prepare("SELECT id, parent, frame FROM stacks")
+        .and_then(|mut statement| {
+            let rows = statement
+                .query_map([], |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        (row.get::<_, Option<i64>>(1)?, row.get::<_, i64>(2)?),
+                    ))
+                })?
+                .collect::<Result<AHashMap<_, _>, _>>();
+            rows
+        })
+        .map_err(sql_error)?;
+    let weights = conn
+        .prepare("SELECT stack, weight FROM weights WHERE profile = ?1 ORDER BY stack")
+        .and_then(|mut statement| {
+            let rows = statement
+                .query_map([profile], |row| {
+                    Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?))
+                })?
+                .collect::<Result<Vec<_>, _>>();
+            rows
+        })
+        .map_err(sql_error)?;
+
+    let mut names = Vec::new();
+    for (stack, weight) in weights {
+        names.clear();
+        let mut node = Some(stack);
+        while let Some(id) = node {
+            let (parent, frame) = stacks.get(&id).ok_or_else(|| corrupt(path))?;
+            names.push(frames.get(frame).ok_or_else(|| corrupt(path))?.as_str());
+            node = *parent;
+        }
+        names.reverse();
+        writeln!(writer, "{} {}", names.join(";"), weight)?;
+    }
+    Ok(())
+}
+
+fn insert_profile(conn: &Connection, opt: &Options, stacks: &Stacks<'_>) -> rusqlite::Result<i64> {
+    conn.execute("INSERT INTO profiles (name) VALUES (?1)", [&opt.name])?;
+    let profile = conn.last_insert_rowid();
+    for (key, value) in &opt.metadata {
+        conn.execute(
+            "INSERT OR REPLACE INTO metadata (profile, key, value) VALUES (?1, ?2, ?3)",
+            params![profile, key, value],
+        )?;
+    }
+
+    let mut insert_frame = conn.prepare("INSERT OR IGNORE INTO frames (name) VALUES (?1)")?;
+    let mut select_frame = conn.prepare("SELECT id FROM frames WHERE name = ?1")?;
+    let mut frame_ids = Vec::with_capacity(stacks.frames.len());
+    for frame in &stacks.frames {
+        insert_frame.execute([frame])?;
+        frame_ids.push(select_frame.query_row([frame], |row| row.get::<_, i64>(0))?);
+    }
+
+    let mut select_stack =
+        conn.prepare("SELECT id FROM stacks WHERE parent IS ?1 AND frame = ?2")?;
+    let mut insert_stack = conn.prepare("INSERT INTO stacks (parent, frame) VALUES (?1, ?2)")?;
+    let mut insert_weight =
+        conn.prepare("INSERT INTO weights (profile, stack, weight) VALUES (?1, ?2, ?3)")?;
+    let mut stack_ids: AHashMap<(Option<i64>, i64), i64> = AHashMap::default();
+    for (frames, count) in &stacks.stacks {
+        let mut stack = None;
+        for &frame in frames {
+            let key = (stack, frame_ids[frame]);
+            let id = match stack_ids.get(&key) {
+                Some(&id) => id,
+                None => {
+                    let id = match select_stack
+                        .query_row(params![key.0, key.1], |row| row.get(0))
+                        .optional()?
+                    {
+                        Some(id) => id,
+                        None => {
+                            insert_stack.execute(params![key.0, key.1])?;
+                            conn.last_insert_rowid()
+                        }
+                    };
+                    stack_ids.insert(key, id);
+                    id
+                }
+            };
+            stack = Some(id);
+        }
+        if let Some(stack) = stack {
+            insert_weight.execute(params![profile, stack, *count as i64])?;
+        }
+    }
+    Ok(profile)
+}
+
+fn sql_error(e: rusqlite::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e)
+}
+
+fn corrupt(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{} has stacks that refer to missing rows", path.display()),
+    )
+}
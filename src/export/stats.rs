@@ -0,0 +1,117 @@
+use std::io::{self, Write};
+
+use ahash::{AHashMap, AHashSet};
+
+use super::{write_json_string, Options, Stacks};
+
+#[derive(Clone, Debug, Default)]
+struct Function {
+    // Samples in stacks that have this function in them.
+    total: usize,
+    // Samples in stacks that end in this function.
+    own: usize,
+    // The functions that call this one, and that it calls, with the samples of those calls.
+    callers: Vec<(usize, usize)>,
+    callees: Vec<(usize, usize)>,
+}
+
+pub(super) fn write<W: Write>(opt: &Options, stacks: &Stacks<'_>, mut writer: W) -> io::Result<()> {
+    let mut functions = vec![Function::default(); stacks.frames.len()];
+    let mut calls: AHashMap<(usize, usize), usize> = AHashMap::default();
+    let mut total = 0;
+
+    let mut seen_frames = AHashSet::default();
+    let mut seen_calls = AHashSet::default();
+    for (frames, count) in &stacks.stacks {
+        total += count;
+        if let Some(&leaf) = frames.last() {
+            functions[leaf].own += count;
+        }
+
+        // Recursive functions and calls only count once per stack.
+        seen_frames.clear();
+        seen_calls.clear();
+        for &frame in frames {
+            if seen_frames.insert(frame) {
+                functions[frame].total += count;
+            }
+        }
+        for call in frames.windows(2).map(|pair| (pair[0], pair[1])) {
+            if seen_calls.insert(call) {
+                *calls.entry(call).or_default() += count;
+            }
+        }
+    }
+    for (&(caller, callee), &samples) in &calls {
+        functions[caller].callees.push((callee, samples));
+        functions[callee].callers.push((caller, samples));
+    }
+
+    let percent = |samples: usize| {
+        if total == 0 {
+            0.0
+        } else {
+            samples as f64 * 100.0 / total as f64
+        }
+    };
+
+    // The functions with the most self samples come first, and the same goes for their callers
+    // and callees. Ties are kept in the order the functions first appear in.
+    let mut order: Vec<_> = (0..functions.len())
+        .filter(|&i| percent(functions[i].total) >= opt.min_percent)
+        .collect();
+    order.sort_by_key(|&i| {
+        (
+            std::cmp::Reverse(functions[i].own),
+            std::cmp::Reverse(functions[i].total),
+        )
+    });
+
+    writer.write_all(b"{\n  \"sample_type\": ")?;
+    write_json_string(&mut writer, &opt.sample_type)?;
+    writer.write_all(b",\n  \"sample_unit\": ")?;
+    write_json_string(&mut writer, &opt.sample_unit)?;
+    write!(writer, ",\n  \"total\": {},\n  \"functions\": [", total)?;
+    for (n, &i) in order.iter().enumerate() {
+        let function = &functions[i];
+        writer.write_all(if n == 0 { &b"\n"[..] } else { b",\n" })?;
+        writer.write_all(b"    {\n      \"name\": ")?;
+        write_json_string(&mut writer, stacks.frames[i])?;
+        write!(
+            writer,
+            ",\n      \"self\": {},\n      \"self_percent\": {:.2},\n      \"total\": {},\n      \"total_percent\": {:.2},\n",
+            function.own,
+            percent(function.own),
+            function.total,
+            percent(function.total)
+        )?;
+        writer.write_all(b"      \"callers\": ")?;
+        write_calls(&mut writer, stacks, &function.callers)?;
+        writer.write_all(b",\n      \"callees\": ")?;
+        write_calls(&mut writer, stacks, &function.callees)?;
+        writer.write_all(b"\n    }")?;
+    }
+    if !order.is_empty() {
+        writer.write_all(b"\n  ")?;
+    }
+    writer.write_all(b"]\n}\n")
+}
+
+fn write_calls<W: Write>(
+    writer: &mut W,
+    stacks: &Stacks<'_>,
+    calls: &[(usize, usize)],
+) -> io::Result<()> {
+    let mut calls = calls.to_vec();
+    calls.sort_by_key(|&(function, samples)| (std::cmp::Reverse(samples), function));
+    writer.write_all(b"[")?;
+    for (i, &(function, samples)) in calls.iter().enumerate() {
+        if i > 0 {
+            writer.write_all(b", ")?;
+        }
+        writer.write_all(b"{\"name\": ")?;
+        write_json_string(writer, stacks.frames[function])?;
+        write!(writer, ", \"samples\": {}}}", samples)?;
+    }
+    writer.write_all(b"]")
+}
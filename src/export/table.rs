@@ -0,0 +1,109 @@
+use ahash::AHashSet;
+
+use super::{Options, Stacks};
+
+/// A column of a table, with a value for each row.
+pub(super) enum Column<'a> {
+    Int(Vec<u64>),
+    Text(Vec<&'a str>),
+}
+
+/// Folded stacks as a table, with either a row for each frame of each stack, or a row for each
+/// function.
+pub(super) struct Table<'a> {
+    pub(super) columns: Vec<(&'static str, Column<'a>)>,
+}
+
+impl<'a> Table<'a> {
+    pub(super) fn new(opt: &Options, stacks: &Stacks<'a>) -> Self {
+        if opt.per_function {
+            Self::functions(stacks)
+        } else {
+            Self::frames(stacks)
+        }
+    }
+
+    pub(super) fn rows(&self) -> usize {
+        match self.columns.first() {
+            Some((_, Column::Int(values))) => values.len(),
+            Some((_, Column::Text(values))) => values.len(),
+            None => 0,
+        }
+    }
+
+    // A row for each frame of each stack, with the stack's samples as the frame's total, and as
+    // its self samples too if it's the leaf.
+    fn frames(stacks: &Stacks<'a>) -> Self {
+        let mut stack_ids = Vec::new();
+        let mut depths = Vec::new();
+        let mut functions = Vec::new();
+        let mut modules = Vec::new();
+        let mut own = Vec::new();
+        let mut total = Vec::new();
+        for (i, (frames, count)) in stacks.stacks.iter().enumerate() {
+            for (depth, &frame) in frames.iter().enumerate() {
+                let (module, function) = split_module(stacks.frames[frame]);
+                stack_ids.push(i as u64);
+                depths.push(depth as u64);
+                functions.push(function);
+                modules.push(module);
+                own.push(if depth + 1 == frames.len() {
+                    *count as u64
+                } else {
+                    0
+                });
+                total.push(*count as u64);
+            }
+        }
+
+        Table {
+            columns: vec![
+                ("stack", Column::Int(stack_ids)),
+                ("depth", Column::Int(depths)),
+                ("function", Column::Text(functions)),
+                ("module", Column::Text(modules)),
+                ("self", Column::Int(own)),
+                ("total", Column::Int(total)),
+            ],
+        }
+    }
+
+    // A row for each function, with the samples of the stacks it's in as its total, and the
+    // samples of the stacks it's the leaf of as its self samples.
+    fn functions(stacks: &Stacks<'a>) -> Self {
+        let mut own = vec![0; stacks.frames.len()];
+        let mut total = vec![0; stacks.frames.len()];
+        let mut seen = AHashSet::default();
+        for (frames, count) in &stacks.stacks {
+            if let Some(&leaf) = frames.last() {
+                own[leaf] += *count as u64;
+            }
+            // Recursive functions only count once per stack.
+            seen.clear();
+            for &frame in frames {
+                if seen.insert(frame) {
+                    total[frame] += *count as u64;
+                }
+            }
+        }
+
+        let (modules, functions) = stacks
+            .frames
+            .iter()
+            .map(|frame| split_module(frame))
+            .unzip();
+        Table {
+            columns: vec![
+                ("function", Column::Text(functions)),
+                ("module", Column::Text(modules)),
+                ("self", Column::Int(own)),
+                ("total", Column::Int(total)),
+            ],
+        }
+    }
+}
+
+// Splits frames like `libc.so.1`_read` into their module and function.
+fn split_module(frame: &str) -> (&str, &str) {
+    frame.split_once('`').unwrap_or(("", frame))
+}
@@ -0,0 +1,117 @@
+use std::io::{self, Write};
+
+use super::{write_json_string, Options, Stacks};
+
+// The process all tracks belong to.
+const PID: usize = 1;
+
+/// A thread's timeline, with the frames that are currently open on it.
+struct Track {
+    tid: usize,
+    now: f64,
+    open: Vec<usize>,
+}
+
+pub(super) fn write<W: Write>(opt: &Options, stacks: &Stacks<'_>, mut writer: W) -> io::Result<()> {
+    // Trace event timestamps are in microseconds.
+    let scale = match opt.sample_unit.as_str() {
+        "nanoseconds" => 0.001,
+        "milliseconds" => 1_000.0,
+        "seconds" => 1_000_000.0,
+        _ => 1.0,
+    };
+
+    writer.write_all(br#"{"traceEvents":["#)?;
+    let mut first = true;
+    let mut tracks: Vec<(Option<usize>, Track)> = Vec::new();
+    for (frames, count) in &stacks.stacks {
+        let (root, frames) = match frames.split_first() {
+            Some((&root, rest)) if opt.thread_roots => (Some(root), rest),
+            _ => (None, &frames[..]),
+        };
+
+        let i = match tracks.iter().position(|(r, _)| *r == root) {
+            Some(i) => i,
+            None => {
+                let tid = tracks.len() + 1;
+                if let Some(root) = root {
+                    separator(&mut writer, &mut first)?;
+                    write!(
+                        writer,
+                        r#"{{"name":"thread_name","ph":"M","pid":{},"tid":{},"args":{{"name":"#,
+                        PID, tid
+                    )?;
+                    write_json_string(&mut writer, stacks.frames[root])?;
+                    writer.write_all(b"}}")?;
+                }
+                let track = Track {
+                    tid,
+                    now: 0.0,
+                    open: Vec::new(),
+                };
+                tracks.push((root, track));
+                tracks.len() - 1
+            }
+        };
+        let track = &mut tracks[i].1;
+
+        // Frames shared with the previous stack on this track stay open.
+        let shared = track
+            .open
+            .iter()
+            .zip(frames)
+            .take_while(|(open, frame)| open == frame)
+            .count();
+        close(&mut writer, &mut first, stacks, track, shared)?;
+        for &frame in &frames[shared..] {
+            event(&mut writer, &mut first, stacks, track, frame, "B")?;
+            track.open.push(frame);
+        }
+        track.now += *count as f64 * scale;
+    }
+    for (_, track) in &mut tracks {
+        close(&mut writer, &mut first, stacks, track, 0)?;
+    }
+
+    writeln!(writer, r#"],"displayTimeUnit":"ms"}}"#)
+}
+
+// Ends the frames open on a track, down to the given depth.
+fn close<W: Write>(
+    writer: &mut W,
+    first: &mut bool,
+    stacks: &Stacks<'_>,
+    track: &mut Track,
+    depth: usize,
+) -> io::Result<()> {
+    while track.open.len() > depth {
+        let frame = track.open.pop().expect("the track has open frames");
+        event(writer, first, stacks, track, frame, "E")?;
+    }
+    Ok(())
+}
+
+fn event<W: Write>(
+    writer: &mut W,
+    first: &mut bool,
+    stacks: &Stacks<'_>,
+    track: &Track,
+    frame: usize,
+    phase: &str,
+) -> io::Result<()> {
+    separator(writer, first)?;
+    writer.write_all(br#"{"name":"#)?;
+    write_json_string(writer, stacks.frames[frame])?;
+    write!(
+        writer,
+        r#","ph":"{}","ts":{},"pid":{},"tid":{}}}"#,
+        phase, track.now, PID, track.tid
+    )
+}
+
+fn separator<W: Write>(writer: &mut W, first: &mut bool) -> io::Result<()> {
+    if !std::mem::take(first) {
+        writer.write_all(b",")?;
+    }
+    Ok(())
+}
@@ -0,0 +1,213 @@
+//! Every function takes its input as a pointer to bytes and their number, and on success points
+//! `out` and `out_len` to a new buffer with the output, which must be freed with [`inferno_free`].
+//! Nothing is written to `out` and `out_len` if the function fails.
+//!
+//! The declarations for C are in `include/inferno.h`. To build the shared library, run
+//!
+//! ```console
+//! $ cargo rustc --release --lib --features ffi --crate-type cdylib
+//! ```
+
+use std::ffi::{c_char, CStr};
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+use crate::collapse::Collapse;
+use crate::flamegraph::{self, Format};
+
+/// What happened in a call to one of the functions.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InfernoStatus {
+    /// The function succeeded, and its output is in `out`.
+    Ok = 0,
+
+    /// A pointer that must not be null was, or a string wasn't valid UTF-8 or had an unknown
+    /// value.
+    InvalidArgument = 1,
+
+    /// The input couldn't be read, like when it's not in the format of the function.
+    InvalidInput = 2,
+
+    /// The function failed in some other way.
+    Error = 3,
+}
+
+impl From<&io::Error> for InfernoStatus {
+    fn from(e: &io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => {
+                InfernoStatus::InvalidInput
+            }
+            io::ErrorKind::InvalidInput | io::ErrorKind::Unsupported => {
+                InfernoStatus::InvalidArgument
+            }
+            _ => InfernoStatus::Error,
+        }
+    }
+}
+
+/// Free a buffer that one of the functions put in `out`.
+///
+/// Freeing a null pointer does nothing.
+///
+/// # Safety
+///
+/// `buf` and `len` must be the `out` and `out_len` of one call of a function, and the buffer can
+/// only be freed once.
+#[no_mangle]
+pub unsafe extern "C" fn inferno_free(buf: *mut u8, len: usize) {
+    if !buf.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(buf, len)));
+    }
+}
+
+// Runs `f` on the input, and hands its output over to the caller. Panics stop here, since they
+// can't unwind into the caller.
+unsafe fn call<F>(
+    input: *const u8,
+    len: usize,
+    out: *mut *mut u8,
+    out_len: *mut usize,
+    f: F,
+) -> InfernoStatus
+where
+    F: FnOnce(&[u8]) -> Result<Vec<u8>, InfernoStatus>,
+{
+    if (input.is_null() && len != 0) || out.is_null() || out_len.is_null() {
+        return InfernoStatus::InvalidArgument;
+    }
+    let input = if len == 0 {
+        &[][..]
+    } else {
+        slice::from_raw_parts(input, len)
+    };
+    match panic::catch_unwind(AssertUnwindSafe(|| f(input))) {
+        Ok(Ok(output)) => {
+            let output = output.into_boxed_slice();
+            *out_len = output.len();
+            *out = Box::into_raw(output).cast();
+            InfernoStatus::Ok
+        }
+        Ok(Err(status)) => status,
+        Err(_) => InfernoStatus::Error,
+    }
+}
+
+// Reads a string argument that may be null.
+unsafe fn optional_str<'a>(s: *const c_char) -> Result<Option<&'a str>, InfernoStatus> {
+    if s.is_null() {
+        return Ok(None);
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map(Some)
+        .map_err(|_| InfernoStatus::InvalidArgument)
+}
+
+macro_rules! collapse_functions {
+    ($($name:ident => $format:ident),* $(,)?) => {
+        $(
+            #[doc = concat!(
+                "Collapse the output of a profiler with [`collapse::",
+                stringify!($format),
+                "`](crate::collapse::",
+                stringify!($format),
+                ") and its default options, into folded stack lines."
+            )]
+            ///
+            /// Compressed input is decompressed first.
+            ///
+            /// # Safety
+            ///
+            /// `input` must point to `len` bytes, or may be null if `len` is 0. `out` and `out_len`
+            /// must be valid to write to.
+            #[no_mangle]
+            pub unsafe extern "C" fn $name(
+                input: *const u8,
+                len: usize,
+                out: *mut *mut u8,
+                out_len: *mut usize,
+            ) -> InfernoStatus {
+                call(input, len, out, out_len, |input| {
+                    let options = crate::collapse::$format::Options::default();
+                    crate::collapse::$format::Folder::from(options)
+                        .collapse_bytes(input)
+                        .map(String::into_bytes)
+                        .map_err(|e| InfernoStatus::from(&e))
+                })
+            }
+        )*
+    };
+}
+
+collapse_functions! {
+    inferno_collapse_bpftrace => bpftrace,
+    inferno_collapse_callgrind => callgrind,
+    inferno_collapse_cpuprofile => cpuprofile,
+    inferno_collapse_d3 => d3,
+    inferno_collapse_dtrace => dtrace,
+    inferno_collapse_gecko => gecko,
+    inferno_collapse_ghcprof => ghcprof,
+    inferno_collapse_guess => guess,
+    inferno_collapse_heaptrack => heaptrack,
+    inferno_collapse_jfr => jfr,
+    inferno_collapse_massif => massif,
+    inferno_collapse_nettrace => nettrace,
+    inferno_collapse_perf => perf,
+    inferno_collapse_pmp => pmp,
+    inferno_collapse_pprof => pprof,
+    inferno_collapse_pyspy => pyspy,
+    inferno_collapse_recursive => recursive,
+    inferno_collapse_sample => sample,
+    inferno_collapse_simpleperf => simpleperf,
+    inferno_collapse_speedscope => speedscope,
+    inferno_collapse_stackprof => stackprof,
+    inferno_collapse_svg => svg,
+    inferno_collapse_vsprof => vsprof,
+    inferno_collapse_vtune => vtune,
+    inferno_collapse_wpa => wpa,
+    inferno_collapse_xctrace => xctrace,
+}
+
+/// Plot folded stack lines as a flame graph, with the default options of
+/// [`flamegraph::Options`] but for its title and format.
+///
+/// `title` is the title of the flame graph, and `format` is one of the names in
+/// [`Format::VARIANTS`]. Either may be null for its default.
+///
+/// # Safety
+///
+/// `input` must point to `len` bytes, or may be null if `len` is 0. `title` and `format` must be
+/// null or point to NUL-terminated strings. `out` and `out_len` must be valid to write to.
+#[no_mangle]
+pub unsafe extern "C" fn inferno_flamegraph(
+    input: *const u8,
+    len: usize,
+    title: *const c_char,
+    format: *const c_char,
+    out: *mut *mut u8,
+    out_len: *mut usize,
+) -> InfernoStatus {
+    let mut options = flamegraph::Options::default();
+    match optional_str(title) {
+        Ok(Some(title)) => options.title = title.to_string(),
+        Ok(None) => {}
+        Err(status) => return status,
+    }
+    match optional_str(format).map(|format| format.map(str::parse::<Format>)) {
+        Ok(Some(Ok(format))) => options.format = format,
+        Ok(None) => {}
+        Ok(Some(Err(_))) | Err(_) => return InfernoStatus::InvalidArgument,
+    }
+    call(input, len, out, out_len, |input| {
+        let mut output = Vec::new();
+        flamegraph::from_reader(&mut options, input, &mut output).map_err(|e| match e {
+            quick_xml::Error::Io(e) => InfernoStatus::from(&e),
+            _ => InfernoStatus::Error,
+        })?;
+        Ok(output)
+    })
+}
@@ -76,8 +76,8 @@ pub enum Palette {
 impl Palette {
     /// The valid set of palettes (via `FromStr`).
     pub const VARIANTS: &'static [&'static str] = &[
-        "aqua", "blue", "green", "hot", "io", "java", "js", "mem", "orange", "perl", "python",
-        "purple", "red", "rust", "wakeup", "yellow",
+        "aqua", "blue", "category", "green", "hot", "hotcold", "io", "java", "js", "mem", "module",
+        "orange", "perl", "python", "purple", "red", "rust", "wakeup", "yellow",
     ];
 }
 
@@ -133,6 +133,18 @@ pub enum MultiPalette {
     Rust,
     /// Equivalent to [`BasicPalette::Aqua`] with [`BackgroundColor::Blue`].
     Wakeup,
+    /// Color off-CPU frames (annotated with `_[o]`) blue, and all other frames with
+    /// [`BasicPalette::Hot`], for the profiles of [`crate::hotcold`].
+    HotCold,
+    /// Color the frames that stacks of a known category end in (annotated with `_[c:NAME]`, see
+    /// [`crate::folded::annotate_category`]) by that category, and all other frames with
+    /// [`BasicPalette::Hot`].
+    Category,
+    /// Pick the hue of frames by the module, crate or package of their function, like `tokio`
+    /// for `tokio::runtime::park` or `java.util` for `java.util.HashMap.get`, so that all frames
+    /// of a module have the same hue. Kernel frames are orange, and frames without a module use
+    /// [`BasicPalette::Hot`].
+    Module,
 }
 
 impl FromStr for BackgroundColor {
@@ -220,6 +232,8 @@ impl FromStr for Palette {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
             "hot" => Ok(Palette::Basic(BasicPalette::Hot)),
+            "hotcold" => Ok(Palette::Multi(MultiPalette::HotCold)),
+            "category" => Ok(Palette::Multi(MultiPalette::Category)),
             "mem" => Ok(Palette::Basic(BasicPalette::Mem)),
             "io" => Ok(Palette::Basic(BasicPalette::Io)),
             "wakeup" => Ok(Palette::Multi(MultiPalette::Wakeup)),
@@ -228,6 +242,7 @@ impl FromStr for Palette {
             "perl" => Ok(Palette::Multi(MultiPalette::Perl)),
             "python" => Ok(Palette::Multi(MultiPalette::Python)),
             "rust" => Ok(Palette::Multi(MultiPalette::Rust)),
+            "module" => Ok(Palette::Multi(MultiPalette::Module)),
             "red" => Ok(Palette::Basic(BasicPalette::Red)),
             "green" => Ok(Palette::Basic(BasicPalette::Green)),
             "blue" => Ok(Palette::Basic(BasicPalette::Blue)),
@@ -240,6 +255,182 @@ impl FromStr for Palette {
     }
 }
 
+// Colors and palettes are written in config files like on the command line.
+
+#[cfg(feature = "config")]
+impl serde::Serialize for Palette {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(match self {
+            Palette::Basic(BasicPalette::Hot) => "hot",
+            Palette::Basic(BasicPalette::Mem) => "mem",
+            Palette::Basic(BasicPalette::Io) => "io",
+            Palette::Basic(BasicPalette::Red) => "red",
+            Palette::Basic(BasicPalette::Green) => "green",
+            Palette::Basic(BasicPalette::Blue) => "blue",
+            Palette::Basic(BasicPalette::Aqua) => "aqua",
+            Palette::Basic(BasicPalette::Yellow) => "yellow",
+            Palette::Basic(BasicPalette::Purple) => "purple",
+            Palette::Basic(BasicPalette::Orange) => "orange",
+            Palette::Multi(MultiPalette::Java) => "java",
+            Palette::Multi(MultiPalette::Js) => "js",
+            Palette::Multi(MultiPalette::Perl) => "perl",
+            Palette::Multi(MultiPalette::Python) => "python",
+            Palette::Multi(MultiPalette::Rust) => "rust",
+            Palette::Multi(MultiPalette::Wakeup) => "wakeup",
+            Palette::Multi(MultiPalette::HotCold) => "hotcold",
+            Palette::Multi(MultiPalette::Category) => "category",
+            Palette::Multi(MultiPalette::Module) => "module",
+        })
+    }
+}
+
+#[cfg(feature = "config")]
+impl<'de> serde::Deserialize<'de> for Palette {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        crate::config::deserialize_from_str(deserializer)
+    }
+}
+
+#[cfg(feature = "config")]
+impl serde::Serialize for BackgroundColor {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            BackgroundColor::Yellow => serializer.serialize_str("yellow"),
+            BackgroundColor::Blue => serializer.serialize_str("blue"),
+            BackgroundColor::Green => serializer.serialize_str("green"),
+            BackgroundColor::Grey => serializer.serialize_str("grey"),
+            BackgroundColor::Flat(color) => hex::serialize(color, serializer),
+        }
+    }
+}
+
+#[cfg(feature = "config")]
+impl<'de> serde::Deserialize<'de> for BackgroundColor {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        crate::config::deserialize_from_str(deserializer)
+    }
+}
+
+#[cfg(feature = "config")]
+impl serde::Serialize for SearchColor {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        hex::serialize(&self.0, serializer)
+    }
+}
+
+#[cfg(feature = "config")]
+impl<'de> serde::Deserialize<'de> for SearchColor {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        crate::config::deserialize_from_str(deserializer)
+    }
+}
+
+#[cfg(feature = "config")]
+impl serde::Serialize for StrokeColor {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            StrokeColor::Color(color) => hex::serialize(color, serializer),
+            StrokeColor::None => serializer.serialize_str("none"),
+        }
+    }
+}
+
+#[cfg(feature = "config")]
+impl<'de> serde::Deserialize<'de> for StrokeColor {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        crate::config::deserialize_from_str(deserializer)
+    }
+}
+
+// Colors are written as `#RRGGBB`.
+#[cfg(feature = "config")]
+pub(super) mod hex {
+    use serde::de::{self, Deserialize};
+
+    use super::{parse_hex_color, Color};
+
+    pub(in super::super) fn serialize<S: serde::Serializer>(
+        color: &Color,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&format_args!(
+            "#{:02x}{:02x}{:02x}",
+            color.r, color.g, color.b
+        ))
+    }
+
+    pub(in super::super) fn deserialize<'de, D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Color, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        parse_hex_color(&s).ok_or_else(|| de::Error::custom(format!("unknown color: {}", s)))
+    }
+}
+
+/// A frame of a flame graph, as given to a [`ColorFn`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct FrameInfo<'a> {
+    /// The name of the function, without its annotations.
+    pub name: &'a str,
+
+    /// The annotation of the frame: `k` for kernel frames (`foo_[k]`), `j` for JIT-compiled
+    /// frames, `i` for inlined frames, and `w` for waker frames.
+    pub annotation: Option<&'a str>,
+
+    /// The category of the stacks the frame is the leaf of, like `gc` for `foo_[c:gc]` (see
+    /// [`crate::folded::annotate_category`]).
+    pub category: Option<&'a str>,
+
+    /// The number of frames between this one and the `all` frame, which has depth 0.
+    pub depth: usize,
+
+    /// For differentials, the change in the samples of this frame, which is negated if
+    /// [`super::Options::negate_differentials`] is set.
+    pub delta: Option<isize>,
+}
+
+/// A function that picks the colors of frames, like by the team that owns them, instead of the
+/// palette.
+///
+/// Frames it returns `None` for are colored as if there was no `ColorFn`, so with the palette
+/// map, or with the consistent hashing of [`super::Options::hash`] and
+/// [`super::Options::deterministic`].
+pub struct ColorFn<'a>(Box<PickColor<'a>>);
+
+// A function that picks the color of a frame, or `None` to leave it to the palette.
+type PickColor<'a> = dyn Fn(&FrameInfo<'_>) -> Option<Color> + 'a;
+
+impl<'a> ColorFn<'a> {
+    /// Wrap a function that picks the colors of frames.
+    pub fn new<F>(f: F) -> Self
+    where
+        F: Fn(&FrameInfo<'_>) -> Option<Color> + 'a,
+    {
+        ColorFn(Box::new(f))
+    }
+
+    pub(super) fn color(&self, frame: &FrameInfo<'_>) -> Option<Color> {
+        (self.0)(frame)
+    }
+}
+
+impl fmt::Debug for ColorFn<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ColorFn(..)")
+    }
+}
+
+// Color functions can't be compared, so the same one is only equal to itself.
+impl PartialEq for ColorFn<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(
+            &*self.0 as *const _ as *const u8,
+            &*other.0 as *const _ as *const u8,
+        )
+    }
+}
+
 struct NamehashVariables {
     vector: f32,
     weight: f32,
@@ -342,6 +533,9 @@ fn rgb_components_for_palette(palette: Palette, name: &str, v1: f32, v2: f32, v3
         Palette::Multi(MultiPalette::Js) => palettes::js::resolve(name),
         Palette::Multi(MultiPalette::Wakeup) => palettes::wakeup::resolve(name),
         Palette::Multi(MultiPalette::Rust) => palettes::rust::resolve(name),
+        Palette::Multi(MultiPalette::HotCold) => palettes::hotcold::resolve(name),
+        Palette::Multi(MultiPalette::Category) => palettes::category::resolve(name),
+        Palette::Multi(MultiPalette::Module) => palettes::module::resolve(name),
     };
 
     match basic_palette {
@@ -402,6 +596,12 @@ pub(super) fn color(
     rgb_components_for_palette(palette, name, v1, v2, v3)
 }
 
+/// The color of a stack category, which is the same in every flame graph.
+pub(super) fn category_color(category: &str) -> Color {
+    let palette = Palette::Basic(palettes::category::for_category(category));
+    rgb_components_for_palette(palette, category, 0.5, 0.5, 0.5)
+}
+
 pub(super) fn color_scale(value: isize, max: usize) -> Color {
     match value.cmp(&0) {
         Ordering::Equal => Color {
@@ -424,6 +624,21 @@ pub(super) fn color_scale(value: isize, max: usize) -> Color {
     }
 }
 
+/// Picks a color on a gradient from pale yellow, for no weight, to red, for the `max` weight.
+pub(super) fn weight_scale(value: f64, max: f64) -> Color {
+    let t = if max > 0.0 {
+        (value / max).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let mix = |low: f64, high: f64| (low + (high - low) * t).round() as u8;
+    Color {
+        r: mix(250.0, 215.0),
+        g: mix(250.0, 48.0),
+        b: mix(210.0, 39.0),
+    }
+}
+
 fn default_bg_color_for(palette: Palette) -> BackgroundColor {
     match palette {
         Palette::Basic(BasicPalette::Mem) => BackgroundColor::Green,
@@ -125,6 +125,55 @@ pub(super) mod wakeup {
     }
 }
 
+pub(super) mod hotcold {
+    use crate::flamegraph::color::BasicPalette;
+
+    pub(in super::super) fn resolve(name: &str) -> BasicPalette {
+        if name.ends_with("_[o]") {
+            BasicPalette::Blue
+        } else {
+            BasicPalette::Hot
+        }
+    }
+}
+
+pub(super) mod category {
+    use crate::flamegraph::color::BasicPalette;
+    use crate::folded;
+
+    pub(in super::super) fn resolve(name: &str) -> BasicPalette {
+        match folded::split_category(name) {
+            (_, Some(category)) => for_category(category),
+            (_, None) => BasicPalette::Hot,
+        }
+    }
+
+    // The well-known categories get the hues profilers usually give them, and others one picked
+    // by their name, so that a category has the same color in every flame graph.
+    pub(in super::super) fn for_category(category: &str) -> BasicPalette {
+        match category.to_ascii_lowercase().as_str() {
+            "app" | "user" => BasicPalette::Hot,
+            "gc" => BasicPalette::Yellow,
+            "jit" | "compiler" => BasicPalette::Green,
+            "kernel" | "system" => BasicPalette::Orange,
+            "io" | "i/o" | "network" => BasicPalette::Aqua,
+            "idle" => BasicPalette::Blue,
+            other => {
+                const OTHERS: [BasicPalette; 4] = [
+                    BasicPalette::Purple,
+                    BasicPalette::Mem,
+                    BasicPalette::Red,
+                    BasicPalette::Io,
+                ];
+                let hash = other.bytes().fold(0usize, |hash, b| {
+                    hash.wrapping_mul(31).wrapping_add(b as usize)
+                });
+                OTHERS[hash % OTHERS.len()]
+            }
+        }
+    }
+}
+
 pub(super) mod rust {
     use crate::flamegraph::color::BasicPalette;
 
@@ -156,6 +205,75 @@ pub(super) mod rust {
     }
 }
 
+pub(super) mod module {
+    use crate::flamegraph::color::BasicPalette;
+    use crate::folded;
+
+    // The hues of modules, leaving out orange for the kernel and hot for frames without a module.
+    const MODULES: [BasicPalette; 8] = [
+        BasicPalette::Aqua,
+        BasicPalette::Purple,
+        BasicPalette::Green,
+        BasicPalette::Yellow,
+        BasicPalette::Blue,
+        BasicPalette::Red,
+        BasicPalette::Mem,
+        BasicPalette::Io,
+    ];
+
+    pub(in super::super) fn resolve(name: &str) -> BasicPalette {
+        let (name, _) = folded::split_category(name);
+        if name.ends_with("_[k]") {
+            return BasicPalette::Orange;
+        }
+        match module(name) {
+            Some(module) => {
+                let hash = module.bytes().fold(0usize, |hash, b| {
+                    hash.wrapping_mul(31).wrapping_add(b as usize)
+                });
+                MODULES[hash % MODULES.len()]
+            }
+            None => BasicPalette::Hot,
+        }
+    }
+
+    // Finds the module, crate, package or library a function is in, like `tokio` for
+    // `tokio::runtime::park`, `libc.so.6` for ``libc.so.6`read``, or `java.util` for
+    // `java.util.HashMap.get`.
+    pub(in super::super) fn module(name: &str) -> Option<&str> {
+        let name = name.trim_end_matches("_[j]").trim_end_matches("_[i]");
+        if let Some((module, _)) = name.split_once('`') {
+            return Some(module);
+        }
+        // Python, as written by py-spy: `function (path/to/file.py:12)`.
+        if let Some(location) = name.strip_suffix(')').and_then(|n| n.rsplit_once(" (")) {
+            let file = location
+                .1
+                .rsplit_once(':')
+                .map_or(location.1, |(file, _)| file);
+            return Some(file);
+        }
+        // Rust and C++, including trait methods like `<tokio::net::TcpStream as Read>::read`.
+        let path = name.trim_start_matches(['<', '&', '*']);
+        if let Some(i) = path.find("::") {
+            return Some(&path[..i]).filter(|module| !module.is_empty());
+        }
+        // Java, Go and others that separate packages with `/` or `.`; the first two parts are
+        // the module, like `org/apache` or `java.util`.
+        if name.contains([' ', '(']) {
+            return None;
+        }
+        for separator in ['/', '.'] {
+            let mut parts = name.splitn(3, separator);
+            if let (Some(first), Some(second), Some(_)) = (parts.next(), parts.next(), parts.next())
+            {
+                return Some(&name[..first.len() + 1 + second.len()]);
+            }
+        }
+        None
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::flamegraph::color::BasicPalette;
@@ -616,4 +734,97 @@ mod tests {
             assert_eq!(result, elem.output);
         }
     }
+
+    #[test]
+    fn category_returns_correct() {
+        use super::category;
+
+        let test_names = [
+            TestData {
+                input: String::from("main"),
+                output: BasicPalette::Hot,
+            },
+            TestData {
+                input: String::from("collect_[c:gc]"),
+                output: BasicPalette::Yellow,
+            },
+            TestData {
+                input: String::from("read_[c:IO]"),
+                output: BasicPalette::Aqua,
+            },
+            TestData {
+                input: String::from("do_syscall_64_[k]"),
+                output: BasicPalette::Hot,
+            },
+        ];
+        for elem in test_names.iter() {
+            let result = category::resolve(&elem.input);
+            assert_eq!(result, elem.output);
+        }
+    }
+
+    #[test]
+    fn hotcold_returns_correct() {
+        use super::hotcold;
+
+        let test_names = [
+            TestData {
+                input: String::from("main"),
+                output: BasicPalette::Hot,
+            },
+            TestData {
+                input: String::from("futex_wait_[o]"),
+                output: BasicPalette::Blue,
+            },
+            TestData {
+                input: String::from("do_syscall_64_[k]"),
+                output: BasicPalette::Hot,
+            },
+        ];
+        for elem in test_names.iter() {
+            let result = hotcold::resolve(&elem.input);
+            assert_eq!(result, elem.output);
+        }
+    }
+
+    #[test]
+    fn module_returns_correct() {
+        use super::module;
+
+        let test_names = [
+            TestData {
+                input: String::from("main"),
+                output: BasicPalette::Hot,
+            },
+            TestData {
+                input: String::from("do_syscall_64_[k]"),
+                output: BasicPalette::Orange,
+            },
+        ];
+        for elem in test_names.iter() {
+            let result = module::resolve(&elem.input);
+            assert_eq!(result, elem.output);
+        }
+
+        let modules = [
+            ("tokio::runtime::park::Inner::park", Some("tokio")),
+            (
+                "<tokio::net::TcpStream as std::io::Read>::read",
+                Some("tokio"),
+            ),
+            ("libc.so.6`read", Some("libc.so.6")),
+            ("java.util.HashMap.get", Some("java.util")),
+            ("org/apache/Foo:.bar_[j]", Some("org/apache")),
+            ("process (app/worker.py:42)", Some("app/worker.py")),
+            ("main", None),
+            ("[unknown]", None),
+        ];
+        for (name, expected) in modules {
+            assert_eq!(module::module(name), expected, "{}", name);
+        }
+        assert_eq!(
+            module::resolve("tokio::spawn"),
+            module::resolve("tokio::runtime::park")
+        );
+    }
 }
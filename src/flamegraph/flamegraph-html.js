@@ -0,0 +1,328 @@
+"use strict";
+(function () {
+    var data = JSON.parse(document.getElementById("flamegraph-data").textContent);
+    var FRAME_HEIGHT = 16, FONT_SIZE = 12, FONT_WIDTH = 0.59, MIN_PIXELS = 0.5;
+
+    var depth = data.depth, start = data.start, width = data.width;
+    var n = depth.length, maxdepth = 0;
+    for (var i = 0; i < n; i++) maxdepth = Math.max(maxdepth, depth[i]);
+
+    // Frames are sorted by depth, then start, so the parent of a frame is the last frame one
+    // level shallower that starts at or before it.
+    var rows = [];
+    for (var i = 0; i < n; i++) (rows[depth[i]] = rows[depth[i]] || []).push(i);
+    var parent = new Int32Array(n).fill(-1), self = Float64Array.from(width);
+    for (var i = 0; i < n; i++) {
+        var above = rows[depth[i] - 1];
+        if (!above) continue;
+        var lo = 0, hi = above.length - 1;
+        while (lo < hi) {
+            var mid = (lo + hi + 1) >> 1;
+            if (start[above[mid]] <= start[i]) lo = mid; else hi = mid - 1;
+        }
+        parent[i] = above[lo];
+        self[above[lo]] -= width[i];
+    }
+    var root = rows[0] ? rows[0][0] : -1;
+
+    var canvas = document.getElementById("canvas");
+    var context = canvas.getContext("2d");
+    var chart = document.getElementById("chart");
+    var searchbox = document.getElementById("search");
+    var zoomed = root, selected = -1, hovered = -1, matches = null, regex = null;
+
+    document.getElementById("title").textContent = data.title;
+    document.getElementById("subtitle").textContent = data.subtitle;
+    document.getElementById("notes").textContent = data.notes;
+
+    function formatCount(value) {
+        var count = value * data.factor / Math.pow(10, data.digits);
+        var steps = { bytes: [1024, ["B", "KiB", "MiB", "GiB", "TiB"]], nanoseconds: [1000, ["ns", "µs", "ms", "s"]] }[data.unit];
+        if (steps && count >= steps[0]) {
+            var unit = 0;
+            while (count >= steps[0] && unit + 1 < steps[1].length) {
+                count /= steps[0];
+                unit++;
+            }
+            return count.toFixed(2) + " " + steps[1][unit];
+        }
+        return count.toLocaleString("en", { minimumFractionDigits: data.digits, maximumFractionDigits: data.digits }) + " " + data.label;
+    }
+
+    function percent(value, total) {
+        return (total ? 100 * value / total : 0).toFixed(2) + "%";
+    }
+
+    function frameName(i) {
+        // Category annotations (`_[c:...]`) aren't part of the name.
+        var name = data.names[data.name[i]].replace(/_\[c:[^\]]*\]$/, "");
+        return name === "" && depth[i] === 0 ? "all" : name;
+    }
+
+    function isAncestor(a, i) {
+        for (; i !== -1; i = parent[i]) if (i === a) return true;
+        return false;
+    }
+
+    // *** drawing *** //
+
+    function frameY(d) {
+        return data.inverted ? d * FRAME_HEIGHT : (maxdepth - d) * FRAME_HEIGHT;
+    }
+
+    function draw() {
+        var ratio = window.devicePixelRatio || 1;
+        var cssWidth = chart.clientWidth, cssHeight = (maxdepth + 1) * FRAME_HEIGHT;
+        canvas.width = cssWidth * ratio;
+        canvas.height = cssHeight * ratio;
+        canvas.style.width = cssWidth + "px";
+        canvas.style.height = cssHeight + "px";
+        context.setTransform(ratio, 0, 0, ratio, 0, 0);
+        context.clearRect(0, 0, cssWidth, cssHeight);
+        context.font = FONT_SIZE + "px monospace";
+        context.textBaseline = "middle";
+        if (zoomed === -1) return;
+
+        var zstart = start[zoomed], zend = start[zoomed] + width[zoomed], zdepth = depth[zoomed];
+        var scale = cssWidth / width[zoomed];
+        for (var i = 0; i < n; i++) {
+            var x, w;
+            if (depth[i] < zdepth) {
+                // Ancestors of the zoomed frame span the whole width, faded.
+                if (!isAncestor(i, zoomed)) continue;
+                x = 0;
+                w = cssWidth;
+                context.globalAlpha = 0.5;
+            } else {
+                if (start[i] >= zend || start[i] + width[i] <= zstart) continue;
+                x = (start[i] - zstart) * scale;
+                w = width[i] * scale;
+                if (w < MIN_PIXELS) continue;
+                context.globalAlpha = 1;
+            }
+            var y = frameY(depth[i]);
+            context.fillStyle = matches && matches[i] ? data.searchColor : data.colors[data.color[i]];
+            context.fillRect(x, y, Math.max(w - 1, MIN_PIXELS), FRAME_HEIGHT - 1);
+            if (i === selected) {
+                context.strokeStyle = "#000";
+                context.strokeRect(x + 0.5, y + 0.5, Math.max(w - 2, 0), FRAME_HEIGHT - 2);
+            }
+            var fit = Math.floor((w - 6) / (FONT_SIZE * FONT_WIDTH));
+            if (fit >= 3) {
+                var name = frameName(i);
+                if (name.length > fit) name = name.substring(0, fit - 2) + "..";
+                context.fillStyle = "#000";
+                context.fillText(name, x + 3, y + FRAME_HEIGHT / 2);
+            }
+        }
+        context.globalAlpha = 1;
+    }
+
+    function frameAt(event) {
+        var rect = canvas.getBoundingClientRect();
+        var x = event.clientX - rect.left, y = event.clientY - rect.top;
+        var d = data.inverted ? Math.floor(y / FRAME_HEIGHT) : maxdepth - Math.floor(y / FRAME_HEIGHT);
+        if (zoomed === -1 || !rows[d]) return -1;
+        if (d < depth[zoomed]) {
+            for (var i = zoomed; i !== -1; i = parent[i]) if (depth[i] === d) return i;
+            return -1;
+        }
+        var time = start[zoomed] + x / rect.width * width[zoomed];
+        var row = rows[d];
+        for (var j = 0; j < row.length; j++) {
+            var i = row[j];
+            if (start[i] <= time && time < start[i] + width[i]) return i;
+        }
+        return -1;
+    }
+
+    // *** sidebar *** //
+
+    function row(table, label, value) {
+        var tr = table.insertRow();
+        tr.insertCell().textContent = label;
+        tr.insertCell().textContent = value;
+    }
+
+    function showDetails(i) {
+        var details = document.getElementById("details");
+        details.textContent = "";
+        var heading = document.createElement("h2");
+        heading.textContent = i === -1 ? "Hover or click a frame" : data.nameType;
+        details.appendChild(heading);
+        if (i === -1) return;
+
+        var name = document.createElement("div");
+        name.className = "name";
+        name.textContent = frameName(i);
+        details.appendChild(name);
+        var table = document.createElement("table");
+        row(table, "Total", formatCount(width[i]));
+        row(table, "Total of all", percent(width[i], data.total));
+        if (parent[i] !== -1) row(table, "Total of parent", percent(width[i], width[parent[i]]));
+        row(table, "Self", formatCount(self[i]));
+        row(table, "Self of all", percent(self[i], data.total));
+        row(table, "Depth", depth[i]);
+        if (data.delta) {
+            row(table, "Difference", (data.delta[i] > 0 ? "+" : "") + percent(data.delta[i], data.total));
+        }
+        details.appendChild(table);
+    }
+
+    // *** zooming *** //
+
+    function showBreadcrumbs() {
+        var nav = document.getElementById("breadcrumbs");
+        nav.textContent = "";
+        var path = [];
+        for (var i = zoomed; i !== -1; i = parent[i]) path.unshift(i);
+        path.forEach(function (i, k) {
+            if (k > 0) {
+                var separator = document.createElement("span");
+                separator.className = "separator";
+                separator.textContent = "›";
+                nav.appendChild(separator);
+            }
+            var crumb = document.createElement("a");
+            crumb.textContent = frameName(i);
+            crumb.onclick = function () { zoom(i); };
+            nav.appendChild(crumb);
+        });
+    }
+
+    function zoom(i) {
+        zoomed = i;
+        showBreadcrumbs();
+        draw();
+        updateUrl();
+    }
+
+    // *** searching *** //
+
+    function search(term) {
+        searchbox.classList.remove("invalid");
+        matches = null;
+        regex = null;
+        if (term) {
+            try {
+                regex = new RegExp(term);
+            } catch (e) {
+                searchbox.classList.add("invalid");
+            }
+        }
+        var list = document.getElementById("matches");
+        list.textContent = "";
+        document.getElementById("matched").textContent = "";
+        if (regex) {
+            matches = new Uint8Array(n);
+            // Per function, the parts of the graph it's in, to not count recursion twice.
+            var byName = {};
+            for (var i = 0; i < n; i++) {
+                var name = frameName(i);
+                if (!regex.test(name)) continue;
+                matches[i] = 1;
+                (byName[name] = byName[name] || []).push(i);
+            }
+            var total = [], all = [];
+            Object.keys(byName).forEach(function (name) {
+                total.push([name, covered(byName[name])]);
+                all = all.concat(byName[name]);
+            });
+            total.sort(function (a, b) { return b[1] - a[1]; });
+            document.getElementById("matched").textContent = "Matched: " + percent(covered(all), data.total);
+
+            var heading = document.createElement("h2");
+            heading.textContent = total.length + " matching " + (total.length === 1 ? "function" : "functions");
+            list.appendChild(heading);
+            var table = document.createElement("table");
+            total.forEach(function (match) {
+                var tr = table.insertRow();
+                var cell = tr.insertCell();
+                cell.className = "function";
+                cell.textContent = match[0];
+                cell.onclick = function () {
+                    searchbox.value = "^" + match[0].replace(/[.*+?^${}()|[\]\\]/g, "\\$&") + "$";
+                    search(searchbox.value);
+                };
+                cell = tr.insertCell();
+                cell.className = "count";
+                cell.textContent = formatCount(match[1]) + " (" + percent(match[1], data.total) + ")";
+            });
+            list.appendChild(table);
+        }
+        draw();
+        updateUrl();
+    }
+
+    // The total width of frames, counting frames within other frames once.
+    function covered(frames) {
+        var spans = frames.map(function (i) { return [start[i], start[i] + width[i]]; });
+        spans.sort(function (a, b) { return a[0] - b[0] || b[1] - a[1]; });
+        var sum = 0, end = -1;
+        spans.forEach(function (span) {
+            if (span[1] <= end) return;
+            sum += span[1] - Math.max(span[0], end);
+            end = span[1];
+        });
+        return sum;
+    }
+
+    // *** state in the URL, so it survives reloads and can be shared *** //
+
+    function updateUrl() {
+        var params = new URLSearchParams();
+        if (zoomed !== root) params.set("z", zoomed);
+        if (searchbox.value) params.set("s", searchbox.value);
+        var hash = params.toString();
+        history.replaceState(null, "", hash ? "#" + hash : location.pathname + location.search);
+    }
+
+    function restoreUrl() {
+        var params = new URLSearchParams(location.hash.substring(1));
+        var z = parseInt(params.get("z"), 10);
+        zoomed = z >= 0 && z < n ? z : root;
+        searchbox.value = params.get("s") || "";
+    }
+
+    // *** events *** //
+
+    canvas.addEventListener("mousemove", function (event) {
+        var i = frameAt(event);
+        if (i !== hovered) {
+            hovered = i;
+            showDetails(i === -1 ? selected : i);
+        }
+    });
+    canvas.addEventListener("mouseleave", function () {
+        hovered = -1;
+        showDetails(selected);
+    });
+    canvas.addEventListener("click", function (event) {
+        var i = frameAt(event);
+        if (i === -1) return;
+        selected = i;
+        showDetails(i);
+        zoom(i);
+    });
+    var timer = null;
+    searchbox.addEventListener("input", function () {
+        clearTimeout(timer);
+        timer = setTimeout(function () { search(searchbox.value); }, 150);
+    });
+    window.addEventListener("keydown", function (event) {
+        if ((event.ctrlKey || event.metaKey) && event.key === "f") {
+            event.preventDefault();
+            searchbox.focus();
+            searchbox.select();
+        } else if (event.key === "Escape") {
+            searchbox.value = "";
+            search("");
+        }
+    });
+    window.addEventListener("resize", draw);
+
+    restoreUrl();
+    showBreadcrumbs();
+    showDetails(-1);
+    search(searchbox.value);
+})();
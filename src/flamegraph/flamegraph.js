@@ -1,21 +1,27 @@
 "use strict";
-var details, searchbtn, unzoombtn, matchedtxt, svg, searching, frames, known_font_width;
+var details, searchbtn, unzoombtn, matchedtxt, svg, searching, frames, known_font_width, total_samples, idprefix, compact, announcer;
 function init(evt) {
-    details = document.getElementById("details").firstChild;
-    searchbtn = document.getElementById("search");
-    unzoombtn = document.getElementById("unzoom");
-    matchedtxt = document.getElementById("matched");
-    svg = document.getElementsByTagName("svg")[0];
-    frames = document.getElementById("frames");
+    // Embedded flame graphs prefix their ids with the id of their root element.
+    svg = evt.target;
+    idprefix = svg.id ? svg.id + "-" : "";
+    details = document.getElementById(idprefix + "details").firstChild;
+    searchbtn = document.getElementById(idprefix + "search");
+    unzoombtn = document.getElementById(idprefix + "unzoom");
+    matchedtxt = document.getElementById(idprefix + "matched");
+    frames = document.getElementById(idprefix + "frames");
+    // Compact flame graphs keep the names of frames, and how to show their counts, in a table.
+    var compact_data = document.getElementById(idprefix + "compact");
+    compact = compact_data ? JSON.parse(compact_data.textContent) : null;
     known_font_width = get_monospace_width(frames);
     total_samples = parseInt(frames.attributes.total_samples.value);
     searching = 0;
+    init_accessibility();
 
     // Use GET parameters to restore a flamegraph's state.
     var restore_state = function() {
         var params = get_params();
         if (params.x && params.y)
-            zoom(find_group(document.querySelector('[*|x="' + params.x + '"][y="' + params.y + '"]')));
+            zoom(find_group(frames.querySelector('[*|x="' + params.x + '"][y="' + params.y + '"]')));
         if (params.s)
             search(params.s);
     };
@@ -65,34 +71,24 @@ window.addEventListener("click", function(e) {
             if (e.ctrlKey === false) return;
             e.preventDefault();
         }
-        if (target.classList.contains("parent")) unzoom();
-        zoom(target);
-
-        // set parameters for zoom state
-        var el = target.querySelector("rect");
-        if (el && el.attributes && el.attributes.y && el.attributes["fg:x"]) {
-            var params = get_params()
-            params.x = el.attributes["fg:x"].value;
-            params.y = el.attributes.y.value;
-            history.replaceState(null, null, parse_params(params));
-        }
+        zoom_to(target);
     }
-    else if (e.target.id == "unzoom") {
-        unzoom();
-
-        // remove zoom state
-        var params = get_params();
-        if (params.x) delete params.x;
-        if (params.y) delete params.y;
-        history.replaceState(null, null, parse_params(params));
-    }
-    else if (e.target.id == "search") search_prompt();
+    else if (e.target.id == idprefix + "unzoom") reset_zoom();
+    else if (e.target.id == idprefix + "search") search_prompt();
 }, false)
 // mouse-over for info
 // show
 window.addEventListener("mouseover", function(e) {
     var target = find_group(e.target);
-    if (target) details.nodeValue = nametype + " " + g_to_text(target);
+    if (target) {
+        details.nodeValue = nametype + " " + g_to_text(target);
+        // Compact flame graphs leave out titles, so they're added when they're needed.
+        if (!find_child(target, "title")) {
+            var title = document.createElementNS("http://www.w3.org/2000/svg", "title");
+            title.textContent = g_to_text(target);
+            target.insertBefore(title, target.firstChild);
+        }
+    }
 }, false)
 // clear
 window.addEventListener("mouseout", function(e) {
@@ -106,6 +102,52 @@ window.addEventListener("keydown",function (e) {
         search_prompt();
     }
 }, false)
+// arrow keys to move between frames, enter to zoom and escape to reset zoom
+window.addEventListener("keydown", function(e) {
+    if (e.ctrlKey || e.altKey || e.metaKey) return;
+    var target = find_group(e.target);
+    if (!target) {
+        // The buttons work with the keyboard too.
+        if (e.key !== "Enter" && e.key !== " ") return;
+        if (e.target.id == idprefix + "unzoom") reset_zoom();
+        else if (e.target.id == idprefix + "search") search_prompt();
+        else return;
+        e.preventDefault();
+        return;
+    }
+    var next;
+    switch (e.key) {
+        case "ArrowLeft": next = find_sibling(target, -1); break;
+        case "ArrowRight": next = find_sibling(target, 1); break;
+        case "ArrowUp": next = inverted ? find_parent(target) : find_first_child(target); break;
+        case "ArrowDown": next = inverted ? find_first_child(target) : find_parent(target); break;
+        case "Home": next = find_root(); break;
+        case "Enter":
+        case " ":
+            zoom_to(target);
+            break;
+        case "Escape":
+            reset_zoom();
+            break;
+        default:
+            return;
+    }
+    e.preventDefault();
+    if (next) next.focus();
+}, false)
+// focused frames show their details, and are where tabbing back into the flame graph lands
+window.addEventListener("focusin", function(e) {
+    var target = find_group(e.target);
+    if (!target) return;
+    var current = frames.querySelector('[tabindex="0"]');
+    if (current) current.setAttribute("tabindex", "-1");
+    target.setAttribute("tabindex", "0");
+    details.nodeValue = nametype + " " + g_to_text(target);
+}, false)
+window.addEventListener("focusout", function(e) {
+    var target = find_group(e.target);
+    if (target) details.nodeValue = ' ';
+}, false)
 // functions
 function get_params() {
     var params = {};
@@ -136,23 +178,41 @@ function find_child(node, selector) {
 function find_group(node) {
     var parent = node.parentElement;
     if (!parent) return;
-    if (parent.id == "frames") return node;
+    if (parent.id == idprefix + "frames") return node;
     return find_group(parent);
 }
-function orig_save(e, attr, val) {
-    if (e.attributes["fg:orig_" + attr] != undefined) return;
-    if (e.attributes[attr] == undefined) return;
-    if (val == undefined) val = e.attributes[attr].value;
-    e.setAttribute("fg:orig_" + attr, val);
+function g_to_text(e) {
+    var title = find_child(e, "title");
+    if (title) return title.firstChild.nodeValue;
+    var rect = find_child(e, "rect");
+    var w = parseInt(rect.attributes["fg:w"].value);
+    var name = compact.names[parseInt(rect.attributes["fg:n"].value)];
+    if (name === "") return "all (" + format_count(w) + ", 100%)";
+    return name + " (" + format_count(w) + ", " + (100 * w / total_samples).toFixed(2) + "%)";
 }
-function orig_load(e, attr) {
-    if (e.attributes["fg:orig_"+attr] == undefined) return;
-    e.attributes[attr].value = e.attributes["fg:orig_" + attr].value;
-    e.removeAttribute("fg:orig_" + attr);
+function format_count(w) {
+    var count = Math.round(w * compact.factor) / Math.pow(10, compact.digits);
+    var steps = { bytes: [1024, ["B", "KiB", "MiB", "GiB", "TiB"]], nanoseconds: [1000, ["ns", "\u00b5s", "ms", "s"]] }[compact.unit];
+    if (steps && count >= steps[0]) {
+        var unit = 0;
+        while (count >= steps[0] && unit + 1 < steps[1].length) {
+            count /= steps[0];
+            unit++;
+        }
+        return count.toFixed(2) + " " + steps[1][unit];
+    }
+    return count.toLocaleString("en", { minimumFractionDigits: compact.digits, maximumFractionDigits: compact.digits }) + " " + compact.label;
 }
-function g_to_text(e) {
-    var text = find_child(e, "title").firstChild.nodeValue;
-    return (text)
+function frame_text(e) {
+    var t = find_child(e, "text");
+    if (t) return t;
+    // Compact flame graphs leave out labels that don't fit, so they're added when they might.
+    var r = find_child(e, "rect");
+    t = document.createElementNS("http://www.w3.org/2000/svg", "text");
+    t.setAttribute("x", r.attributes.x.value);
+    t.setAttribute("y", (parseFloat(r.attributes.y.value) + parseFloat(r.attributes.height.value) / 2 + 3).toFixed(2));
+    e.appendChild(t);
+    return t;
 }
 function g_to_func(e) {
     var func = g_to_text(e);
@@ -163,7 +223,7 @@ function g_to_func(e) {
 function get_monospace_width(frames) {
     // Given the id="frames" element, return the width of text characters if
     // this is a monospace font, otherwise return 0.
-    text = find_child(frames.children[0], "text");
+    text = frame_text(frames.children[0]);
     originalContent = text.textContent;
     text.textContent = "!";
     bangWidth = text.getComputedTextLength();
@@ -194,9 +254,8 @@ function update_text_for_elements(elements) {
     for (var i = 0; i < elements.length; i++) {
         var e = elements[i];
         var r = find_child(e, "rect");
-        var t = find_child(e, "text");
         var w = parseFloat(r.attributes.width.value) * frames.attributes.width.value / 100 - 3;
-        var txt = find_child(e, "title").textContent.replace(/\([^(]*\)$/,"");
+        var txt = g_to_text(e).replace(/\([^(]*\)$/,"");
         var newX = format_percent((parseFloat(r.attributes.x.value) + (100 * 3 / frames.attributes.width.value)));
 
         // Smaller than this size won't fit anything
@@ -229,7 +288,8 @@ function update_text_for_elements(elements) {
     for (var i = 0; i < elements.length; i++) {
         var e = elements[i];
         var values = textElemNewAttributes[i];
-        var t = find_child(e, "text");
+        if (values[1] === "" && !find_child(e, "text")) continue;
+        var t = frame_text(e);
         t.attributes.x.value = values[0];
         t.textContent = values[1];
     }
@@ -237,9 +297,9 @@ function update_text_for_elements(elements) {
 
 function update_text(e) {
     var r = find_child(e, "rect");
-    var t = find_child(e, "text");
+    var t = frame_text(e);
     var w = parseFloat(r.attributes.width.value) * frames.attributes.width.value / 100 - 3;
-    var txt = find_child(e, "title").textContent.replace(/\([^(]*\)$/,"");
+    var txt = g_to_text(e).replace(/\([^(]*\)$/,"");
     t.attributes.x.value = format_percent((parseFloat(r.attributes.x.value) + (100 * 3 / frames.attributes.width.value)));
 
     // Smaller than this size won't fit anything
@@ -270,7 +330,160 @@ function update_text(e) {
     }
     t.textContent = "";
 }
+// accessibility
+function init_accessibility() {
+    // The frames are a tree for screen readers, labelled with their names and percentages, and
+    // only one of them is in the tab order at a time.
+    frames.setAttribute("role", "tree");
+    frames.setAttribute("aria-label", document.getElementById(idprefix + "title").textContent);
+    var el = frames.children;
+    var levels = frame_levels(el);
+    for (var i = 0; i < el.length; i++) {
+        var e = el[i];
+        e.setAttribute("role", "treeitem");
+        e.setAttribute("aria-level", levels[frame_box(e).y]);
+        e.setAttribute("aria-label", g_to_text(e));
+        e.setAttribute("tabindex", "-1");
+    }
+    var root = find_root();
+    if (root) root.setAttribute("tabindex", "0");
+
+    var buttons = [unzoombtn, searchbtn];
+    for (var i = 0; i < buttons.length; i++) {
+        buttons[i].setAttribute("role", "button");
+        buttons[i].setAttribute("tabindex", "0");
+    }
+    matchedtxt.setAttribute("aria-live", "polite");
+
+    // Zooming is announced by a live region that isn't shown.
+    announcer = document.createElementNS("http://www.w3.org/2000/svg", "text");
+    announcer.setAttribute("aria-live", "polite");
+    announcer.setAttribute("x", "0");
+    announcer.setAttribute("y", "0");
+    announcer.setAttribute("opacity", "0");
+    svg.appendChild(announcer);
+}
+function announce(message) {
+    if (announcer) announcer.textContent = message;
+}
+function frame_levels(el) {
+    // The level of the frames at each y, counting from the root frame.
+    var ys = [];
+    for (var i = 0; i < el.length; i++) {
+        var y = parseFloat(find_child(el[i], "rect").attributes.y.value);
+        if (ys.indexOf(y) === -1) ys.push(y);
+    }
+    ys.sort(function(a, b) {
+        return inverted ? a - b : b - a;
+    });
+    var levels = {};
+    for (var i = 0; i < ys.length; i++) {
+        levels[ys[i]] = i + 1;
+    }
+    return levels;
+}
+function frame_box(e) {
+    var a = find_child(e, "rect").attributes;
+    return { x: parseInt(a["fg:x"].value), w: parseInt(a["fg:w"].value), y: parseFloat(a.y.value) };
+}
+function frames_toward_leaves(from, to) {
+    // How many levels `to` is from `from`, toward the leaves.
+    return inverted ? to - from : from - to;
+}
+function visible_frames() {
+    var visible = [];
+    var el = frames.children;
+    for (var i = 0; i < el.length; i++) {
+        if (!el[i].classList.contains("hide")) visible.push(el[i]);
+    }
+    return visible;
+}
+function find_sibling(node, direction) {
+    // The nearest shown frame to the left or right of a frame on its level.
+    var b = frame_box(node);
+    var el = visible_frames();
+    var best, best_x;
+    for (var i = 0; i < el.length; i++) {
+        var o = frame_box(el[i]);
+        if (o.y !== b.y || (o.x - b.x) * direction <= 0) continue;
+        if (best === undefined || (o.x - best_x) * direction < 0) {
+            best = el[i];
+            best_x = o.x;
+        }
+    }
+    return best;
+}
+function find_parent(node) {
+    // The nearest shown frame toward the root that spans a frame.
+    var b = frame_box(node);
+    var el = visible_frames();
+    var best, best_step;
+    for (var i = 0; i < el.length; i++) {
+        var o = frame_box(el[i]);
+        var step = frames_toward_leaves(o.y, b.y);
+        if (step <= 0 || o.x > b.x || o.x + o.w < b.x + b.w) continue;
+        if (best === undefined || step < best_step) {
+            best = el[i];
+            best_step = step;
+        }
+    }
+    return best;
+}
+function find_first_child(node) {
+    // The leftmost shown frame on the nearest level toward the leaves within a frame.
+    var b = frame_box(node);
+    var el = visible_frames();
+    var best, best_box, best_step;
+    for (var i = 0; i < el.length; i++) {
+        var o = frame_box(el[i]);
+        var step = frames_toward_leaves(b.y, o.y);
+        if (step <= 0 || o.x < b.x || o.x >= b.x + b.w) continue;
+        if (best === undefined || step < best_step || (step === best_step && o.x < best_box.x)) {
+            best = el[i];
+            best_box = o;
+            best_step = step;
+        }
+    }
+    return best;
+}
+function find_root() {
+    // The widest frame, and of those the one nearest the base of the flame graph.
+    var el = frames.children;
+    var best, best_box;
+    for (var i = 0; i < el.length; i++) {
+        var o = frame_box(el[i]);
+        if (best === undefined || o.w > best_box.w || (o.w === best_box.w && frames_toward_leaves(best_box.y, o.y) < 0)) {
+            best = el[i];
+            best_box = o;
+        }
+    }
+    return best;
+}
 // zoom
+function zoom_to(target) {
+    if (target.classList.contains("parent")) unzoom();
+    zoom(target);
+
+    // set parameters for zoom state
+    var el = target.querySelector("rect");
+    if (el && el.attributes && el.attributes.y && el.attributes["fg:x"]) {
+        var params = get_params()
+        params.x = el.attributes["fg:x"].value;
+        params.y = el.attributes.y.value;
+        history.replaceState(null, null, parse_params(params));
+    }
+    announce("Zoomed to " + g_to_text(target));
+}
+function reset_zoom() {
+    unzoom();
+
+    // remove zoom state
+    var params = get_params();
+    if (params.x) delete params.x;
+    if (params.y) delete params.y;
+    history.replaceState(null, null, parse_params(params));
+    announce("Zoom reset");
+}
 function zoom_reset(e) {
     if (e.tagName == "rect") {
         e.attributes.x.value = format_percent(100 * parseInt(e.attributes["fg:x"].value) / total_samples);
@@ -365,9 +578,9 @@ function unzoom() {
 }
 // search
 function reset_search() {
-    var el = document.querySelectorAll("#frames rect");
+    var el = frames.querySelectorAll("rect");
     for (var i = 0; i < el.length; i++) {
-        orig_load(el[i], "fill")
+        el[i].style.fill = "";
     }
     var params = get_params();
     delete params.s;
@@ -411,8 +624,8 @@ function search(term) {
         if (func.match(re)) {
             // highlight
             var x = parseInt(rect.attributes["fg:x"].value);
-            orig_save(rect, "fill");
-            rect.attributes.fill.value = searchcolor;
+            // The style wins over both fill attributes and the classes of compact flame graphs.
+            rect.style.fill = searchcolor;
             // remember matches
             if (matches[x] == undefined) {
                 matches[x] = w;
@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+use std::io::{self, BufRead};
+
+// Advance widths of the printable ASCII characters (from ` ` to `~`), in thousandths of the font
+// size.
+const HELVETICA: [u16; 95] = [
+    // ` ` to `/`
+    278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278,
+    // `0` to `?`
+    556, 556, 556, 556, 556, 556, 556, 556, 556, 556, 278, 278, 584, 584, 584, 556,
+    // `@` to `O`
+    1015, 667, 667, 722, 722, 667, 611, 778, 722, 278, 500, 667, 556, 833, 722, 778,
+    // `P` to `_`
+    667, 778, 722, 667, 611, 722, 667, 944, 667, 667, 611, 278, 278, 278, 469, 556,
+    // `` ` `` to `o`
+    333, 556, 556, 500, 556, 556, 278, 556, 556, 222, 222, 500, 222, 833, 556, 556,
+    // `p` to `~`
+    556, 556, 333, 500, 278, 556, 500, 722, 500, 500, 500, 334, 260, 334, 584,
+];
+
+const VERDANA: [u16; 95] = [
+    // ` ` to `/`
+    352, 394, 459, 818, 636, 1076, 727, 269, 454, 454, 636, 818, 364, 454, 364, 454,
+    // `0` to `?`
+    636, 636, 636, 636, 636, 636, 636, 636, 636, 636, 454, 454, 818, 818, 818, 545,
+    // `@` to `O`
+    1000, 684, 686, 698, 771, 632, 575, 775, 751, 421, 455, 693, 557, 843, 748, 787,
+    // `P` to `_`
+    603, 787, 695, 684, 616, 732, 684, 989, 685, 615, 685, 454, 454, 454, 818, 636,
+    // `` ` `` to `o`
+    636, 601, 623, 521, 623, 596, 352, 623, 633, 274, 344, 592, 274, 973, 633, 607,
+    // `p` to `~`
+    623, 623, 427, 521, 394, 633, 592, 818, 592, 592, 525, 635, 454, 635, 818,
+];
+
+/// The advance widths of the characters of a font, which decide how much of a function name fits
+/// in a frame when [`Options::font_metrics`](super::Options::font_metrics) is set.
+///
+/// Widths are fractions of the font size, like [`Options::font_width`](super::Options::font_width).
+/// Wide characters, like those of Chinese, Japanese and Korean, are as wide as the font size in
+/// proportional fonts and take two cells in monospace fonts.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FontMetrics {
+    ascii: [f64; 95],
+    other: f64,
+    wide: f64,
+    advances: HashMap<char, f64>,
+}
+
+impl FontMetrics {
+    /// The metrics of a monospace font whose characters are `width` wide.
+    pub fn monospace(width: f64) -> Self {
+        FontMetrics {
+            ascii: [width; 95],
+            other: width,
+            wide: 2.0 * width,
+            advances: HashMap::new(),
+        }
+    }
+
+    /// The metrics of a well-known font, or of a generic font family (`monospace` or
+    /// `sans-serif`), if there are some for it.
+    ///
+    /// Arial, Helvetica, Liberation Sans and the generic sans-serif family get the widths of
+    /// Helvetica, Verdana and DejaVu Sans get those of Verdana, and monospace fonts get
+    /// characters 0.6 wide.
+    pub fn for_font(name: &str) -> Option<Self> {
+        let table = match name.to_ascii_lowercase().as_str() {
+            "arial" | "helvetica" | "liberation sans" | "sans-serif" => &HELVETICA,
+            "verdana" | "dejavu sans" => &VERDANA,
+            "monospace" | "courier" | "courier new" | "dejavu sans mono" | "menlo" | "consolas"
+            | "liberation mono" => return Some(FontMetrics::monospace(0.6)),
+            _ => return None,
+        };
+        let mut ascii = [0.0; 95];
+        for (width, &advance) in ascii.iter_mut().zip(table) {
+            *width = f64::from(advance) / 1000.0;
+        }
+        Some(FontMetrics {
+            // Characters without a width are assumed to be about as wide as `o`.
+            other: ascii[usize::from(b'o' - b' ')],
+            wide: 1.0,
+            ascii,
+            advances: HashMap::new(),
+        })
+    }
+
+    /// Sets the advance width of a character.
+    pub fn set_advance(&mut self, c: char, width: f64) {
+        match c {
+            ' '..='~' => self.ascii[c as usize - ' ' as usize] = width,
+            _ => {
+                self.advances.insert(c, width);
+            }
+        }
+    }
+
+    /// Sets the advance widths of characters from lines of a character and its width, like
+    /// `m 0.833`. Characters can also be written as their code points, like `U+4E2D`, which is
+    /// needed for whitespace. The words `other` and `wide` set the width of other characters
+    /// that don't have a width, and of wide ones. Empty lines and lines starting with `#` are
+    /// skipped.
+    pub fn read_advances<R: BufRead>(&mut self, reader: R) -> io::Result<()> {
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid font metrics on line {}: {}", i + 1, line),
+                )
+            };
+            let (key, width) = line.rsplit_once(char::is_whitespace).ok_or_else(invalid)?;
+            let width = width.parse::<f64>().map_err(|_| invalid())?;
+            match key.trim() {
+                "other" => self.other = width,
+                "wide" => self.wide = width,
+                key => {
+                    let c = parse_char(key).ok_or_else(invalid)?;
+                    self.set_advance(c, width);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The advance width of a character.
+    pub fn advance(&self, c: char) -> f64 {
+        match c {
+            ' '..='~' => self.ascii[c as usize - ' ' as usize],
+            _ => match self.advances.get(&c) {
+                Some(&width) => width,
+                None if is_wide(c) => self.wide,
+                None => self.other,
+            },
+        }
+    }
+
+    /// The width of a text.
+    pub fn width(&self, text: &str) -> f64 {
+        text.chars().map(|c| self.advance(c)).sum()
+    }
+
+    // The start of `text` that fits in `space`, with room for `..` after it unless it's all of
+    // `text`, or `None` if not even one character and `..` fit.
+    pub(super) fn fit<'t>(&self, text: &'t str, space: f64) -> Option<&'t str> {
+        if self.width(text) < space {
+            return Some(text);
+        }
+        let space = space - 2.0 * self.advance('.');
+        let mut width = 0.0;
+        let mut end = 0;
+        for (i, c) in text.char_indices() {
+            width += self.advance(c);
+            if width > space {
+                break;
+            }
+            end = i + c.len_utf8();
+        }
+        if end == 0 {
+            None
+        } else {
+            Some(&text[..end])
+        }
+    }
+}
+
+fn parse_char(key: &str) -> Option<char> {
+    if let Some(code) = key.strip_prefix("U+") {
+        return char::from_u32(u32::from_str_radix(code, 16).ok()?);
+    }
+    let mut chars = key.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c),
+        _ => None,
+    }
+}
+
+// Whether a character is East Asian wide or fullwidth, as most emoji are too.
+fn is_wide(c: char) -> bool {
+    matches!(
+        c as u32,
+        0x1100..=0x115F
+            | 0x2E80..=0x303E
+            | 0x3041..=0x33FF
+            | 0x3400..=0x4DBF
+            | 0x4E00..=0x9FFF
+            | 0xA000..=0xA4CF
+            | 0xAC00..=0xD7A3
+            | 0xF900..=0xFAFF
+            | 0xFE30..=0xFE4F
+            | 0xFF00..=0xFF60
+            | 0xFFE0..=0xFFE6
+            | 0x1F300..=0x1F64F
+            | 0x1F900..=0x1F9FF
+            | 0x20000..=0x3FFFD
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FontMetrics;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn for_font_knows_common_fonts() {
+        let arial = FontMetrics::for_font("Arial").unwrap();
+        assert_eq!(arial.advance('i'), 0.222);
+        assert_eq!(arial.advance('W'), 0.944);
+        assert_eq!(arial.advance('中'), 1.0);
+        assert_eq!(arial, FontMetrics::for_font("sans-serif").unwrap());
+
+        let verdana = FontMetrics::for_font("Verdana").unwrap();
+        assert_eq!(verdana.advance('m'), 0.973);
+
+        let mono = FontMetrics::for_font("monospace").unwrap();
+        assert_eq!(mono.width("ab中"), 2.4);
+
+        assert_eq!(FontMetrics::for_font("Comic Sans MS"), None);
+    }
+
+    #[test]
+    fn read_advances_sets_widths() {
+        let mut metrics = FontMetrics::monospace(0.5);
+        metrics
+            .read_advances("# comment\n\nm 0.9\nU+0020 0.25\nU+4E2D 1.5\nother 0.7\n".as_bytes())
+            .unwrap();
+        assert_eq!(metrics.advance('m'), 0.9);
+        assert_eq!(metrics.advance(' '), 0.25);
+        assert_eq!(metrics.advance('中'), 1.5);
+        assert_eq!(metrics.advance('é'), 0.7);
+        assert_eq!(metrics.advance('n'), 0.5);
+
+        assert!(metrics.read_advances("mm 0.5".as_bytes()).is_err());
+        assert!(metrics.read_advances("m wide".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn fit_truncates_to_the_space() {
+        let metrics = FontMetrics::monospace(1.0);
+        assert_eq!(metrics.fit("abc", 4.0), Some("abc"));
+        assert_eq!(metrics.fit("abcdef", 5.0), Some("abc"));
+        assert_eq!(metrics.fit("abcdef", 2.5), None);
+        // Wide characters take two cells.
+        assert_eq!(metrics.fit("中文字符", 6.0), Some("中文"));
+    }
+}
@@ -0,0 +1,200 @@
+use std::io::{self, Write};
+
+use ahash::AHashMap;
+
+use super::json::write_json_string;
+use super::merge::TimedFrame;
+use super::{count_unit_names, frame_color, rand, weight_max, Direction, Options};
+
+// The page around the data. The data goes in a JSON script element rather than in the script
+// itself, since browsers parse JSON much faster than JavaScript.
+const HEAD: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+"#;
+
+const BODY: &str = r#"<body>
+<header>
+<h1 id="title"></h1>
+<p id="subtitle"></p>
+<div id="controls">
+<input id="search" type="search" placeholder="Search (regular expression)" autocomplete="off">
+<span id="matched"></span>
+</div>
+<nav id="breadcrumbs"></nav>
+</header>
+<main>
+<div id="chart"><canvas id="canvas"></canvas></div>
+<aside id="sidebar">
+<section id="details"></section>
+<section id="matches"></section>
+</aside>
+</main>
+<p id="notes"></p>
+"#;
+
+pub(super) fn write<W: Write>(
+    opt: &mut Options<'_>,
+    mut frames: Vec<TimedFrame<'_>>,
+    time: usize,
+    delta_max: usize,
+    digits: usize,
+    mut writer: W,
+) -> io::Result<()> {
+    // The same frames are left out as in the SVG, so both are as detailed.
+    let minwidth_time = opt.min_width * time as f64 / 100.0;
+    frames.retain(|frame| ((frame.end_time - frame.start_time) as f64) >= minwidth_time);
+    frames.sort_unstable_by_key(|frame| (frame.location.depth, frame.start_time));
+
+    writer.write_all(HEAD.as_bytes())?;
+    if let Some(seconds) = opt.auto_reload {
+        writeln!(
+            writer,
+            r#"<meta http-equiv="refresh" content="{}">"#,
+            seconds
+        )?;
+    }
+    writer.write_all(b"<title>")?;
+    write_html_text(&mut writer, &opt.title)?;
+    writer.write_all(b"</title>\n")?;
+    if !opt.no_javascript {
+        writer.write_all(b"<style>\n")?;
+        writer.write_all(include_str!("flamegraph-html.css").as_bytes())?;
+        writer.write_all(b"</style>\n")?;
+    }
+    writer.write_all(b"</head>\n")?;
+    writer.write_all(BODY.as_bytes())?;
+
+    writer.write_all(br#"<script type="application/json" id="flamegraph-data">"#)?;
+    write_data(opt, &frames, time, delta_max, digits, &mut writer)?;
+    writer.write_all(b"</script>\n")?;
+    if !opt.no_javascript {
+        writer.write_all(b"<script>\n")?;
+        writer.write_all(include_str!("flamegraph-html.js").as_bytes())?;
+        writer.write_all(b"</script>\n")?;
+    }
+    writer.write_all(b"</body>\n</html>\n")
+}
+
+// Writes the frames as columns of numbers, with their names and colors in tables, which keeps
+// the data of large profiles small.
+fn write_data<W: Write>(
+    opt: &mut Options<'_>,
+    frames: &[TimedFrame<'_>],
+    time: usize,
+    delta_max: usize,
+    digits: usize,
+    writer: &mut W,
+) -> io::Result<()> {
+    let (unit, label) = count_unit_names(opt);
+    writer.write_all(br#"{"title":"#)?;
+    write_json_string(writer, &opt.title)?;
+    writer.write_all(br#","subtitle":"#)?;
+    write_json_string(writer, opt.subtitle.as_deref().unwrap_or(""))?;
+    writer.write_all(br#","notes":"#)?;
+    write_json_string(writer, &opt.notes)?;
+    writer.write_all(br#","nameType":"#)?;
+    write_json_string(writer, &opt.name_type)?;
+    writer.write_all(br#","unit":"#)?;
+    write_json_string(writer, unit)?;
+    writer.write_all(br#","label":"#)?;
+    write_json_string(writer, label)?;
+    write!(
+        writer,
+        r#","digits":{},"factor":{},"total":{},"inverted":{},"searchColor":"#,
+        digits,
+        opt.factor,
+        time,
+        opt.direction == Direction::Inverted,
+    )?;
+    write_json_string(writer, &opt.search_color.to_string())?;
+
+    let mut names = AHashMap::default();
+    let mut colors = AHashMap::default();
+    let mut thread_rng = rand::thread_rng();
+    let weight_max = weight_max(frames);
+    let mut columns = vec![String::new(); 5];
+    let mut deltas = String::new();
+    for frame in frames {
+        let width = frame.end_time - frame.start_time;
+        let width_pct = 100.0 * width as f64 / time as f64;
+        let color = frame_color(
+            opt,
+            frame,
+            width_pct,
+            delta_max,
+            weight_max,
+            &mut thread_rng,
+        );
+        let color = format!("rgb({},{},{})", color.r, color.g, color.b);
+        let nnames = names.len();
+        let name = *names.entry(frame.location.function).or_insert(nnames);
+        let ncolors = colors.len();
+        let color = *colors.entry(color).or_insert(ncolors);
+
+        let values = [frame.location.depth, frame.start_time, width, name, color];
+        for (column, value) in columns.iter_mut().zip(values) {
+            if !column.is_empty() {
+                column.push(',');
+            }
+            column.push_str(itoa::Buffer::new().format(value));
+        }
+        if let Some(mut delta) = frame.delta {
+            if opt.negate_differentials {
+                delta = -delta;
+            }
+            if !deltas.is_empty() {
+                deltas.push(',');
+            }
+            deltas.push_str(itoa::Buffer::new().format(delta));
+        }
+    }
+
+    for (key, column) in ["depth", "start", "width", "name", "color"]
+        .iter()
+        .zip(&columns)
+    {
+        write!(writer, r#","{}":[{}]"#, key, column)?;
+    }
+    if !deltas.is_empty() {
+        write!(writer, r#","delta":[{}]"#, deltas)?;
+    }
+
+    writer.write_all(br#","names":["#)?;
+    let mut names: Vec<_> = names.into_iter().collect();
+    names.sort_unstable_by_key(|&(_, i)| i);
+    for (i, (name, _)) in names.into_iter().enumerate() {
+        if i > 0 {
+            writer.write_all(b",")?;
+        }
+        write_json_string(writer, name)?;
+    }
+    writer.write_all(br#"],"colors":["#)?;
+    let mut colors: Vec<_> = colors.into_iter().collect();
+    colors.sort_unstable_by_key(|&(_, i)| i);
+    for (i, (color, _)) in colors.into_iter().enumerate() {
+        if i > 0 {
+            writer.write_all(b",")?;
+        }
+        write_json_string(writer, &color)?;
+    }
+    writer.write_all(b"]}")
+}
+
+fn write_html_text<W: Write>(writer: &mut W, s: &str) -> io::Result<()> {
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        let escaped = match c {
+            '&' => "&amp;",
+            '<' => "&lt;",
+            '>' => "&gt;",
+            _ => continue,
+        };
+        writer.write_all(&s.as_bytes()[start..i])?;
+        writer.write_all(escaped.as_bytes())?;
+        start = i + c.len_utf8();
+    }
+    writer.write_all(&s.as_bytes()[start..])
+}
@@ -0,0 +1,85 @@
+use std::io::{self, Write};
+
+use super::merge::TimedFrame;
+use super::Options;
+
+// Writes the merged frames as a tree of `{"name", "value", "delta", "children"}` objects, the
+// format d3-flame-graph reads.
+pub(super) fn write<W: Write>(
+    opt: &Options<'_>,
+    mut frames: Vec<TimedFrame<'_>>,
+    digits: usize,
+    mut writer: W,
+) -> io::Result<()> {
+    // In this order, every frame comes after its parent, and before its later siblings, so the
+    // tree can be written as the frames come without building it first.
+    frames.sort_unstable_by_key(|frame| (frame.start_time, frame.location.depth));
+
+    let scale = opt.factor / 10f64.powi(digits as i32);
+    let mut open = 0;
+    let mut needs_comma = false;
+    for frame in &frames {
+        // Close the frames this one isn't in.
+        while open > frame.location.depth {
+            writer.write_all(b"]}")?;
+            open -= 1;
+            needs_comma = true;
+        }
+        if needs_comma {
+            writer.write_all(b",")?;
+        }
+
+        let name = match frame.location.function {
+            "" if frame.location.depth == 0 => "all",
+            function => function,
+        };
+        writer.write_all(br#"{"name":"#)?;
+        write_json_string(&mut writer, name)?;
+        write!(
+            writer,
+            r#","value":{}"#,
+            (frame.end_time - frame.start_time) as f64 * scale
+        )?;
+        if let Some(mut delta) = frame.delta {
+            if opt.negate_differentials {
+                delta = -delta;
+            }
+            write!(writer, r#","delta":{}"#, delta as f64 * scale)?;
+        }
+        writer.write_all(br#","children":["#)?;
+        open += 1;
+        needs_comma = false;
+    }
+    for _ in 0..open {
+        writer.write_all(b"]}")?;
+    }
+    writer.write_all(b"\n")
+}
+
+// Writes a string as a JSON string literal, with quotes. `<`, `>` and `&` are escaped too, so
+// the string can't end the HTML script element it may be in.
+pub(super) fn write_json_string<W: Write>(writer: &mut W, s: &str) -> io::Result<()> {
+    writer.write_all(b"\"")?;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        let escaped = match c {
+            '"' => "\\\"",
+            '\\' => "\\\\",
+            '\n' => "\\n",
+            '\r' => "\\r",
+            '\t' => "\\t",
+            '<' | '>' | '&' => "",
+            c if (c as u32) < 0x20 => "",
+            _ => continue,
+        };
+        writer.write_all(&s.as_bytes()[start..i])?;
+        if escaped.is_empty() {
+            write!(writer, "\\u{:04x}", c as u32)?;
+        } else {
+            writer.write_all(escaped.as_bytes())?;
+        }
+        start = i + c.len_utf8();
+    }
+    writer.write_all(&s.as_bytes()[start..])?;
+    writer.write_all(b"\"")
+}
@@ -0,0 +1,149 @@
+use std::fmt::Write;
+
+use regex::{Captures, Regex};
+
+/// Links every frame of a flame graph to a URL made from its function name, like a code
+/// browser's search for it.
+///
+/// The URL is made by filling in the placeholders of a template:
+///
+///  - `{func}` is the name of the function, without annotations like `_[k]`.
+///  - `{0}`, `{1}`, ... and `{name}` are the groups captured from the function name by the
+///    pattern, if there is one. Frames whose function the pattern doesn't match aren't linked.
+///
+/// Values are percent-encoded, except for `/`, so they can go in both paths and queries. For
+/// example, with the pattern `^(?P<crate>[^:<]+)::` the template
+/// `https://docs.rs/{crate}/latest/{crate}/?search={func}` links frames of Rust functions to
+/// their crate's documentation.
+///
+/// Links made with frame attributes (see `FuncFrameAttrsMap`) take precedence.
+#[derive(Clone, Debug)]
+pub struct FrameLinks {
+    template: String,
+    pattern: Option<Regex>,
+}
+
+impl FrameLinks {
+    /// Link frames to the URL made from `template`.
+    pub fn new<S: Into<String>>(template: S) -> Self {
+        FrameLinks {
+            template: template.into(),
+            pattern: None,
+        }
+    }
+
+    /// Capture placeholders from function names with a regular expression, and only link the
+    /// frames of the functions it matches.
+    pub fn with_pattern(mut self, pattern: &str) -> Result<Self, regex::Error> {
+        self.pattern = Some(Regex::new(pattern)?);
+        Ok(self)
+    }
+
+    /// The URL of the frames of a function, unless the pattern doesn't match it.
+    pub fn url_for(&self, function: &str) -> Option<String> {
+        let captures = match self.pattern {
+            Some(ref pattern) => Some(pattern.captures(function)?),
+            None => None,
+        };
+
+        let mut url = String::with_capacity(self.template.len() + function.len());
+        let mut rest = self.template.as_str();
+        while let Some(open) = rest.find('{') {
+            url.push_str(&rest[..open]);
+            let Some(close) = rest[open..].find('}') else {
+                break;
+            };
+            let placeholder = &rest[open + 1..open + close];
+            let value = if placeholder == "func" {
+                Some(function)
+            } else {
+                self.capture(captures.as_ref(), placeholder)
+            };
+            match value {
+                Some(value) => percent_encode(&mut url, value),
+                // Leave anything that isn't a placeholder as it is.
+                None => url.push_str(&rest[open..=open + close]),
+            }
+            rest = &rest[open + close + 1..];
+        }
+        url.push_str(rest);
+        Some(url)
+    }
+
+    // The text a pattern captured for a placeholder, which is empty for groups that didn't
+    // participate in the match, or `None` if the pattern has no such group.
+    fn capture<'t>(&self, captures: Option<&Captures<'t>>, placeholder: &str) -> Option<&'t str> {
+        let captures = captures?;
+        let group = match placeholder.parse::<usize>() {
+            Ok(i) if i < captures.len() => captures.get(i),
+            Ok(_) => return None,
+            Err(_) => {
+                let pattern = self.pattern.as_ref()?;
+                if !pattern
+                    .capture_names()
+                    .flatten()
+                    .any(|name| name == placeholder)
+                {
+                    return None;
+                }
+                captures.name(placeholder)
+            }
+        };
+        Some(group.map_or("", |group| group.as_str()))
+    }
+}
+
+impl PartialEq for FrameLinks {
+    fn eq(&self, other: &Self) -> bool {
+        self.template == other.template
+            && self.pattern.as_ref().map(Regex::as_str) == other.pattern.as_ref().map(Regex::as_str)
+    }
+}
+
+impl Eq for FrameLinks {}
+
+fn percent_encode(url: &mut String, value: &str) {
+    for b in value.bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~' | b'/') {
+            url.push(b as char);
+        } else {
+            let _ = write!(url, "%{:02X}", b);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FrameLinks;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn url_for_fills_in_the_function() {
+        let links = FrameLinks::new("https://src.example/search?q={func}");
+        assert_eq!(
+            links.url_for("std::vec::Vec<T>::push").as_deref(),
+            Some("https://src.example/search?q=std%3A%3Avec%3A%3AVec%3CT%3E%3A%3Apush")
+        );
+    }
+
+    #[test]
+    fn url_for_fills_in_captures() {
+        let links = FrameLinks::new("https://src.example/{crate}/{2}?q={func}&x={unknown}{3}")
+            .with_pattern(r"^(?P<crate>[a-z_]+)::(\w+)(\.\w+)?")
+            .unwrap();
+        assert_eq!(
+            links.url_for("tokio::runtime::park").as_deref(),
+            Some("https://src.example/tokio/runtime?q=tokio%3A%3Aruntime%3A%3Apark&x={unknown}")
+        );
+        assert_eq!(links.url_for("main"), None);
+    }
+
+    #[test]
+    fn url_for_keeps_other_braces() {
+        let links = FrameLinks::new("https://src.example/{file}?q={func}");
+        assert_eq!(
+            links.url_for("a b").as_deref(),
+            Some("https://src.example/{file}?q=a%20b")
+        );
+    }
+}
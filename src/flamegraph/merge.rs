@@ -1,9 +1,11 @@
 use std::collections::HashMap;
-use std::io;
 use std::iter;
 
 use log::warn;
 
+use crate::folded;
+use crate::Error;
+
 #[derive(Debug, PartialEq, Eq, Hash)]
 pub(super) struct Frame<'a> {
     pub(super) function: &'a str,
@@ -16,6 +18,7 @@ pub(super) struct TimedFrame<'a> {
     pub(super) start_time: usize,
     pub(super) end_time: usize,
     pub(super) delta: Option<isize>,
+    pub(super) weight: Option<f64>,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -70,6 +73,7 @@ fn flow<'a, LI, TI>(
             start_time: frame_time.start_time,
             end_time: time,
             delta: frame_time.delta,
+            weight: None,
         };
         frames.push(frame);
     }
@@ -109,6 +113,39 @@ fn flow<'a, LI, TI>(
 pub(super) fn frames<'a, I>(
     lines: I,
     suppress_sort_check: bool,
+    digits: usize,
+    weighted: bool,
+    max_depth: Option<usize>,
+) -> quick_xml::Result<(Vec<TimedFrame<'a>>, usize, usize, usize)>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let mut stripped_fractional_samples = false;
+    let merged = frames_after(
+        lines,
+        None,
+        suppress_sort_check,
+        digits,
+        weighted,
+        max_depth,
+        &mut stripped_fractional_samples,
+    );
+    if stripped_fractional_samples {
+        warn_stripped_fractional_samples();
+    }
+    merged
+}
+
+// Merges lines into frames like `frames`, where `after` is where the lines start among all the
+// lines, if they don't start at the beginning.
+fn frames_after<'a, I>(
+    lines: I,
+    after: Option<&RunStart<'a>>,
+    suppress_sort_check: bool,
+    digits: usize,
+    weighted: bool,
+    max_depth: Option<usize>,
+    stripped_fractional_samples: &mut bool,
 ) -> quick_xml::Result<(Vec<TimedFrame<'a>>, usize, usize, usize)>
 where
     I: IntoIterator<Item = &'a str>,
@@ -118,20 +155,27 @@ where
     let mut last = "";
     let mut tmp = Default::default();
     let mut frames = Default::default();
-    let mut delta = None;
+    let mut delta = after.and_then(|after| after.delta);
     let mut delta_max = 1;
-    let mut stripped_fractional_samples = false;
-    let mut prev_line = None;
-    for line in lines {
+    let (first_line, mut prev_line) = match after {
+        Some(after) => (after.index, Some(after.prev_stack)),
+        None => (0, None),
+    };
+    // For weighted lines, the time each line starts at, and the total weight before each line.
+    let mut line_starts = Vec::new();
+    let mut line_weights = vec![0.0];
+    for (i, line) in lines.into_iter().enumerate() {
         let mut line = line.trim();
 
         if !suppress_sort_check {
             if let Some(prev_line) = prev_line {
                 if prev_line > line {
-                    return Err(quick_xml::Error::Io(io::Error::new(
-                        io::ErrorKind::InvalidData,
-                        "unsorted input lines detected",
-                    )));
+                    return Err(Error::Parse {
+                        format: "folded",
+                        line: Some(first_line + i + 1),
+                        message: "unsorted input lines detected".to_string(),
+                    }
+                    .into());
                 }
             }
         }
@@ -140,20 +184,41 @@ where
         // Usually there will only be one samples column at the end of a line,
         // but for differentials there will be two. When there are two we compute the
         // delta between them and use the second one.
-        let nsamples =
-            if let Some(samples) = parse_nsamples(&mut line, &mut stripped_fractional_samples) {
-                // See if there's also a differential column present
-                if let Some(original_samples) =
-                    parse_nsamples(&mut line, &mut stripped_fractional_samples)
-                {
-                    delta = Some(samples as isize - original_samples as isize);
-                    delta_max = std::cmp::max(delta.unwrap().unsigned_abs(), delta_max);
+        //
+        // Weighted lines instead end with the number of samples followed by their weight.
+        let weight = if weighted {
+            match parse_weight(&mut line) {
+                Some(weight) => Some(weight),
+                None => {
+                    ignored += 1;
+                    continue;
                 }
-                samples
-            } else {
-                ignored += 1;
-                continue;
-            };
+            }
+        } else {
+            None
+        };
+        let nsamples = if weighted {
+            match parse_nsamples(&mut line, digits, stripped_fractional_samples) {
+                Some(samples) => samples,
+                None => {
+                    ignored += 1;
+                    continue;
+                }
+            }
+        } else if let Some(samples) = parse_nsamples(&mut line, digits, stripped_fractional_samples)
+        {
+            // See if there's also a differential column present
+            if let Some(original_samples) =
+                parse_nsamples(&mut line, digits, stripped_fractional_samples)
+            {
+                delta = Some(samples as isize - original_samples as isize);
+                delta_max = std::cmp::max(delta.unwrap().unsigned_abs(), delta_max);
+            }
+            samples
+        } else {
+            ignored += 1;
+            continue;
+        };
 
         if line.is_empty() {
             ignored += 1;
@@ -161,8 +226,7 @@ where
         }
         let stack = line;
 
-        // inject empty first-level stack frame to capture "all"
-        let this = iter::once("").chain(stack.split(';'));
+        let this = stack_frames(stack, max_depth);
         if last.is_empty() {
             // need to special-case this, because otherwise iter("") + "".split(';') == ["", ""]
             //eprintln!("flow(_, {}, {})", stack, time);
@@ -172,13 +236,18 @@ where
             flow(
                 &mut tmp,
                 &mut frames,
-                iter::once("").chain(last.split(';')),
+                stack_frames(last, max_depth),
                 this,
                 time,
                 delta,
             );
         }
 
+        if let Some(weight) = weight {
+            line_starts.push(time);
+            line_weights.push(line_weights[line_weights.len() - 1] + weight);
+        }
+
         last = stack;
         time += nsamples;
         prev_line = Some(line);
@@ -189,41 +258,379 @@ where
         flow(
             &mut tmp,
             &mut frames,
-            iter::once("").chain(last.split(';')),
+            stack_frames(last, max_depth),
             None,
             time,
             delta,
         );
     }
 
+    if weighted {
+        // A frame weighs as much as the lines that start within it.
+        let weight_before = |time: usize| line_weights[line_starts.partition_point(|&t| t < time)];
+        for frame in &mut frames {
+            frame.weight = Some(weight_before(frame.end_time) - weight_before(frame.start_time));
+        }
+    }
+
     Ok((frames, time, ignored, delta_max))
 }
 
+// Lines are only split between threads when each gets at least this many of them, since fewer
+// are merged faster than threads are started.
+const MIN_LINES_PER_THREAD: usize = 10_000;
+
+// How many lines are sampled for each bucket of lines that is sorted on a thread of its own.
+const SAMPLES_PER_BUCKET: usize = 64;
+
+// How many threads to split `nlines` lines between.
+fn nshards(nlines: usize, nthreads: usize) -> usize {
+    std::cmp::min(nthreads, nlines / MIN_LINES_PER_THREAD).max(1)
+}
+
+// Sorts lines like `sort_unstable` does, with up to `nthreads` threads.
+pub(super) fn sort_lines(lines: &mut Vec<&str>, nthreads: usize) {
+    let nbuckets = nshards(lines.len(), nthreads);
+    if nbuckets == 1 {
+        lines.sort_unstable();
+        return;
+    }
+
+    // Split the lines into buckets by lines picked from an evenly spaced sample of them, so that
+    // each bucket only has lines that sort before those of the next one. Then the sorted buckets
+    // are sorted together.
+    let step = lines.len() / (nbuckets * SAMPLES_PER_BUCKET);
+    let mut sample: Vec<&str> = lines.iter().step_by(step).copied().collect();
+    sample.sort_unstable();
+    let splitters: Vec<&str> = (1..nbuckets)
+        .map(|bucket| sample[bucket * sample.len() / nbuckets])
+        .collect();
+    let mut buckets = vec![Vec::with_capacity(lines.len() / nbuckets); nbuckets];
+    for line in lines.drain(..) {
+        buckets[splitters.partition_point(|&splitter| splitter <= line)].push(line);
+    }
+    let buckets = in_parallel(buckets, |mut bucket| {
+        bucket.sort_unstable();
+        bucket
+    });
+    for bucket in buckets {
+        lines.extend(bucket);
+    }
+}
+
+// Merges lines into frames like `frames` does, splitting them between up to `nthreads` threads.
+pub(super) fn frames_parallel<'a>(
+    lines: &[&'a str],
+    suppress_sort_check: bool,
+    digits: usize,
+    weighted: bool,
+    max_depth: Option<usize>,
+    nthreads: usize,
+) -> quick_xml::Result<(Vec<TimedFrame<'a>>, usize, usize, usize)> {
+    // The weights of frames that go on from one run of lines into the next would be added up in
+    // another order than in `frames`, which could round them differently.
+    let nruns = if weighted {
+        1
+    } else {
+        nshards(lines.len(), nthreads)
+    };
+    if nruns == 1 {
+        return frames(
+            lines.iter().copied(),
+            suppress_sort_check,
+            digits,
+            weighted,
+            max_depth,
+        );
+    }
+    frames_in_runs(lines, nruns, suppress_sort_check, digits, max_depth)
+}
+
+// Where a run of lines starts, after the last line of the run before it.
+struct RunStart<'a> {
+    // The index of the first line of the run.
+    index: usize,
+    // The stack of the line before it.
+    prev_stack: &'a str,
+    // How many frames the stack of the line before it has.
+    prev_depth: usize,
+    // How many frames, from the root on, the stacks of the two lines have in common.
+    shared: usize,
+    // The delta of the last line before it with a differential column, which lines without one
+    // keep using.
+    delta: Option<isize>,
+}
+
+// A run of lines can start at the line at `index` if both it and the line before it are valid,
+// so that the frames that go on from one run into the next are known.
+fn run_start<'a>(
+    lines: &[&'a str],
+    index: usize,
+    digits: usize,
+    max_depth: Option<usize>,
+) -> Option<RunStart<'a>> {
+    let prev_stack = line_stack(lines[index - 1], digits)?;
+    let stack = line_stack(lines[index], digits)?;
+    let shared = stack_frames(prev_stack, max_depth)
+        .zip(stack_frames(stack, max_depth))
+        .take_while(|(prev, this)| prev == this)
+        .count();
+    Some(RunStart {
+        index,
+        prev_stack,
+        prev_depth: stack_frames(prev_stack, max_depth).count(),
+        shared,
+        delta: None,
+    })
+}
+
+// The stack of an unweighted line without its sample counts, or `None` if `frames` ignores it.
+fn line_stack(line: &str, digits: usize) -> Option<&str> {
+    let mut line = line.trim();
+    let mut stripped_fractional_samples = false;
+    parse_nsamples(&mut line, digits, &mut stripped_fractional_samples)?;
+    parse_nsamples(&mut line, digits, &mut stripped_fractional_samples);
+    Some(line).filter(|line| !line.is_empty())
+}
+
+// The delta of an unweighted line with a differential column, or `None` if it has none.
+fn line_delta(line: &str, digits: usize) -> Option<isize> {
+    let mut line = line.trim();
+    let mut stripped_fractional_samples = false;
+    let samples = parse_nsamples(&mut line, digits, &mut stripped_fractional_samples)?;
+    let original_samples = parse_nsamples(&mut line, digits, &mut stripped_fractional_samples)?;
+    Some(samples as isize - original_samples as isize)
+}
+
+// Merges unweighted lines into frames like `frames` does, splitting them into up to `nruns` runs
+// that are merged on threads of their own. The frames that go on from one run into the next are
+// then stitched together, and the frames come in the same order as from `frames`.
+fn frames_in_runs<'a>(
+    lines: &[&'a str],
+    nruns: usize,
+    suppress_sort_check: bool,
+    digits: usize,
+    max_depth: Option<usize>,
+) -> quick_xml::Result<(Vec<TimedFrame<'a>>, usize, usize, usize)> {
+    // Start runs at the first lines they can start at from evenly spaced lines on.
+    let mut starts: Vec<RunStart<'a>> = Vec::with_capacity(nruns - 1);
+    let mut from = 1;
+    for run in 1..nruns {
+        from = std::cmp::max(from, run * lines.len() / nruns);
+        match (from..lines.len()).find_map(|index| run_start(lines, index, digits, max_depth)) {
+            Some(start) => {
+                from = start.index + 1;
+                starts.push(start);
+            }
+            None => break,
+        }
+    }
+
+    // Lines without a differential column keep the delta of the last line before them with one,
+    // so find the delta each run starts with first.
+    let begins = iter::once(0).chain(starts.iter().map(|start| start.index));
+    let ranges: Vec<_> = begins.zip(starts.iter().map(|start| start.index)).collect();
+    let last_deltas = in_parallel(ranges, |(begin, end)| {
+        lines[begin..end]
+            .iter()
+            .rev()
+            .find_map(|line| line_delta(line, digits))
+    });
+    let mut delta = None;
+    for (start, last_delta) in starts.iter_mut().zip(last_deltas) {
+        delta = last_delta.or(delta);
+        start.delta = delta;
+    }
+
+    let ends = starts
+        .iter()
+        .map(|start| start.index)
+        .chain(iter::once(lines.len()));
+    let runs: Vec<_> = iter::once(None)
+        .chain(starts.iter().map(Some))
+        .zip(ends)
+        .collect();
+    let merged = in_parallel(runs, |(after, end)| {
+        let begin = after.map_or(0, |start| start.index);
+        let mut stripped_fractional_samples = false;
+        let merged = frames_after(
+            lines[begin..end].iter().copied(),
+            after,
+            suppress_sort_check,
+            digits,
+            false,
+            max_depth,
+            &mut stripped_fractional_samples,
+        );
+        (merged, stripped_fractional_samples)
+    });
+
+    let mut frames = Vec::new();
+    let mut time = 0;
+    let mut ignored = 0;
+    let mut delta_max = 1;
+    let mut stripped_fractional_samples = false;
+    // The frames of the runs so far that go on into the next run, by depth.
+    let mut open: Vec<Option<TimedFrame<'a>>> = Vec::new();
+    for (run, (merged, stripped)) in merged.into_iter().enumerate() {
+        let (mut run_frames, run_time, run_ignored, run_delta_max) = merged?;
+        stripped_fractional_samples |= stripped;
+
+        let mut nopen = open.len();
+        for frame in &mut run_frames {
+            frame.start_time += time;
+            frame.end_time += time;
+            // The first frame to end at a depth the runs have in common is the one that goes on.
+            if nopen != 0 {
+                if let Some(prev) = open.get_mut(frame.location.depth).and_then(Option::take) {
+                    frame.start_time = prev.start_time;
+                    frame.delta = prev.delta;
+                    nopen -= 1;
+                }
+            }
+        }
+
+        if let Some(next) = starts.get(run) {
+            // The frames of the last stack of a run end last, from the root on.
+            let last = run_frames.len() - next.prev_depth;
+            open = run_frames
+                .drain(last..last + next.shared)
+                .map(Some)
+                .collect();
+        }
+        frames.append(&mut run_frames);
+        time += run_time;
+        ignored += run_ignored;
+        delta_max = std::cmp::max(run_delta_max, delta_max);
+    }
+
+    if stripped_fractional_samples {
+        warn_stripped_fractional_samples();
+    }
+    Ok((frames, time, ignored, delta_max))
+}
+
+// Maps jobs to their results, each on a thread of its own.
+#[cfg(feature = "multithreaded")]
+fn in_parallel<J, T, F>(jobs: Vec<J>, f: F) -> Vec<T>
+where
+    J: Send,
+    T: Send,
+    F: Fn(J) -> T + Sync,
+{
+    let f = &f;
+    crossbeam_utils::thread::scope(|scope| {
+        let handles: Vec<_> = jobs
+            .into_iter()
+            .map(|job| scope.spawn(move |_| f(job)))
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect()
+    })
+    .unwrap()
+}
+
+#[cfg(not(feature = "multithreaded"))]
+fn in_parallel<J, T, F>(jobs: Vec<J>, f: F) -> Vec<T>
+where
+    F: Fn(J) -> T,
+{
+    jobs.into_iter().map(f).collect()
+}
+
+// The frames of a stack, after an empty frame to capture "all", with the frames deeper than
+// `max_depth` replaced by one frame.
+pub(super) fn stack_frames(stack: &str, max_depth: Option<usize>) -> impl Iterator<Item = &str> {
+    let cut = max_depth.and_then(|max_depth| folded::depth_cut(stack, max_depth));
+    let kept = match cut {
+        Some(cut) => &stack[..cut - 1],
+        None => stack,
+    };
+    iter::once("")
+        .chain(kept.split(';'))
+        .chain(cut.map(|_| folded::TRUNCATED_FRAMES))
+}
+
+// Parse and remove the weight from the end of a weighted line.
+fn parse_weight(line: &mut &str) -> Option<f64> {
+    let (weighti, _) = rfind_samples(line)?;
+    let weight = line[weighti..].parse::<f64>().ok()?;
+    *line = line[..weighti].trim_end();
+    Some(weight)
+}
+
+// The most fractional digits a sample count keeps when fractional samples are enabled.
+const MAX_FRACTION_DIGITS: usize = 6;
+
+// Finds how many fractional digits the sample counts of `lines` need to be kept exactly, up to
+// `MAX_FRACTION_DIGITS`.
+pub(super) fn fraction_digits(lines: &[&str]) -> usize {
+    let mut digits = 0;
+    for line in lines {
+        let mut line = line.trim();
+        if line.starts_with("# ") {
+            continue;
+        }
+        // Look at both sample counts of differentials.
+        for _ in 0..2 {
+            let (samplesi, doti) = match rfind_samples(line) {
+                Some(found) => found,
+                None => break,
+            };
+            let fraction = line[samplesi..].get(doti + 1..).unwrap_or("");
+            digits = std::cmp::max(digits, fraction.trim_end_matches('0').len());
+            line = line[..samplesi].trim_end();
+        }
+    }
+    std::cmp::min(digits, MAX_FRACTION_DIGITS)
+}
+
+// Warns that fractional sample counts were truncated, which `parse_nsamples` notes.
+pub(super) fn warn_stripped_fractional_samples() {
+    warn!(
+        "The input data has fractional sample counts that will be truncated to integers. \
+         If you need to retain the extra precision you can scale up the sample data and \
+         use the --factor option to scale it back down."
+    );
+}
+
 // Parse and remove the number of samples from the end of a line.
-fn parse_nsamples(line: &mut &str, stripped_fractional_samples: &mut bool) -> Option<usize> {
+//
+// The sample count is returned as a fixed-point number with `digits` fractional digits, and
+// `stripped_fractional_samples` is set if a non-zero fractional part beyond them was stripped.
+pub(super) fn parse_nsamples(
+    line: &mut &str,
+    digits: usize,
+    stripped_fractional_samples: &mut bool,
+) -> Option<usize> {
     if let Some((samplesi, doti)) = rfind_samples(line) {
-        let mut samples = &line[samplesi..];
-        // Strip fractional part (if any);
+        let samples = &line[samplesi..];
+        let fraction = samples.get(doti + 1..).unwrap_or("");
+        let (kept, stripped) = fraction.split_at(std::cmp::min(digits, fraction.len()));
+        // Strip the fractional part (if any) beyond `digits`;
         // foobar 1.klwdjlakdj
         //
         // The Perl version keeps the fractional part but this can be problematic
         // because of cumulative floating point errors. Instead we recommend to
         // use the --factor option. See https://github.com/brendangregg/FlameGraph/pull/18
         //
-        // Warn if we're stripping a non-zero fractional part, but only the first time.
-        if !*stripped_fractional_samples
-            && doti < samples.len() - 1
-            && !samples[doti + 1..].chars().all(|c| c == '0')
-        {
+        // Note if we're stripping a non-zero fractional part, to warn about it once.
+        if !stripped.chars().all(|c| c == '0') {
             *stripped_fractional_samples = true;
-            warn!(
-                "The input data has fractional sample counts that will be truncated to integers. \
-                 If you need to retain the extra precision you can scale up the sample data and \
-                 use the --factor option to scale it back down."
-            );
         }
-        samples = &samples[..doti];
-        let nsamples = samples.parse::<usize>().ok()?;
+        let mut nsamples = samples[..doti].parse::<usize>().ok()?;
+        if digits != 0 {
+            let kept_samples = if kept.is_empty() {
+                0
+            } else {
+                kept.parse::<usize>().ok()?
+            };
+            nsamples = nsamples
+                .checked_mul(10usize.pow(digits as u32))?
+                .checked_add(kept_samples * 10usize.pow((digits - kept.len()) as u32))?;
+        }
         // remove nsamples part we just parsed from line
         *line = line[..samplesi].trim_end();
         Some(nsamples)
@@ -260,3 +667,102 @@ pub(super) fn rfind_samples(line: &str) -> Option<(usize, usize)> {
         Some((samplesi, line.len() - samplesi))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use pretty_assertions::assert_eq;
+    use rand::prelude::*;
+
+    use super::*;
+
+    const INPUTS: &[&str] = &[
+        "./tests/data/flamegraph/flamechart/flames.txt",
+        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt",
+        "./tests/data/flamegraph/bad-lines/bad-lines.txt",
+        "./tests/data/flamegraph/fractional-samples/fractional.txt",
+        "./tests/data/flamegraph/multiple-inputs/perf-vertx-stacks-01-collapsed-all-unsorted-1.txt",
+    ];
+
+    fn read_lines(input: &str) -> Vec<String> {
+        fs::read_to_string(input)
+            .unwrap()
+            .lines()
+            .filter(|line| !line.starts_with("# "))
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn frames_in_runs_match_frames() {
+        for input in INPUTS {
+            let lines = read_lines(input);
+            let mut lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+            lines.sort_unstable();
+            for max_depth in [None, Some(1), Some(3)] {
+                let expected = frames(lines.iter().copied(), false, 0, false, max_depth).unwrap();
+                for nruns in 2..=std::cmp::min(lines.len(), 20) {
+                    let actual = frames_in_runs(&lines, nruns, false, 0, max_depth).unwrap();
+                    assert_eq!(actual, expected, "{} in {} runs", input, nruns);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn frames_keep_the_delta_of_lines_without_a_differential_column() {
+        let lines = ["a;b 3 1", "a;c 2", "a;d 1 4", "a;e 5"];
+        let expected = frames(lines.iter().copied(), false, 0, false, None).unwrap();
+        let deltas: Vec<_> = expected
+            .0
+            .iter()
+            .filter(|frame| frame.location.depth == 2)
+            .map(|frame| (frame.location.function, frame.delta))
+            .collect();
+        assert_eq!(
+            deltas,
+            [
+                ("b", Some(-2)),
+                ("c", Some(-2)),
+                ("d", Some(3)),
+                ("e", Some(3))
+            ]
+        );
+        for nruns in 2..=lines.len() {
+            let actual = frames_in_runs(&lines, nruns, false, 0, None).unwrap();
+            assert_eq!(actual, expected, "{} runs", nruns);
+        }
+    }
+
+    #[test]
+    fn frames_in_runs_detect_unsorted_lines() {
+        let input = "./tests/data/flamegraph/unsorted-input/perf-vertx-stacks-01-collapsed-all-unsorted.txt";
+        let lines = read_lines(input);
+        let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+        let expected = frames(lines.iter().copied(), false, 0, false, None).unwrap_err();
+        for nruns in 2..=20 {
+            let actual = frames_in_runs(&lines, nruns, false, 0, None).unwrap_err();
+            assert_eq!(format!("{:?}", actual), format!("{:?}", expected));
+        }
+    }
+
+    #[test]
+    fn sort_lines_in_parallel() {
+        let lines = read_lines(INPUTS[0]);
+        let mut rng = SmallRng::seed_from_u64(0);
+        let mut many: Vec<String> = (0..4 * MIN_LINES_PER_THREAD)
+            .map(|i| format!("thread-{};{}", i % 7, lines.choose(&mut rng).unwrap()))
+            .collect();
+        many.shuffle(&mut rng);
+        let mut actual: Vec<&str> = many.iter().map(String::as_str).collect();
+        let mut expected = actual.clone();
+        expected.sort_unstable();
+        sort_lines(&mut actual, 4);
+        assert_eq!(actual, expected);
+
+        let expected = frames(expected.iter().copied(), false, 0, false, None).unwrap();
+        let actual = frames_parallel(&actual, false, 0, false, None, 4).unwrap();
+        assert_eq!(actual, expected);
+    }
+}
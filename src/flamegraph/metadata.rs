@@ -0,0 +1,249 @@
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+
+use libflate::gzip::{Decoder, Encoder};
+use quick_xml::events::{BytesEnd, BytesStart, BytesText, Event};
+use quick_xml::{Reader, Writer};
+
+use super::{svg, EmbedOptions};
+use crate::Error;
+
+// The namespace of the element that holds the folded stacks in the `<metadata>` of a flame graph.
+const NAMESPACE: &str = "https://github.com/jonhoo/inferno";
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// Base64 is broken into lines this long, like in MIME.
+const BASE64_LINE: usize = 76;
+
+/// How [`Options::embed_folded`](super::Options::embed_folded) stores the folded stacks in a
+/// flame graph.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum FoldedEncoding {
+    /// The folded stack lines as they are, escaped as XML text.
+    Text,
+
+    /// The folded stack lines compressed with gzip and encoded as base64, which is usually much
+    /// smaller.
+    #[default]
+    Gzip,
+}
+
+impl FoldedEncoding {
+    /// The names of the encodings.
+    pub const VARIANTS: &'static [&'static str] = &["gzip", "text"];
+
+    // The value of the `encoding` attribute of the embedded stacks.
+    fn attribute(self) -> &'static str {
+        match self {
+            FoldedEncoding::Text => "text",
+            FoldedEncoding::Gzip => "gzip+base64",
+        }
+    }
+}
+
+impl FromStr for FoldedEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "gzip" => Ok(FoldedEncoding::Gzip),
+            "text" => Ok(FoldedEncoding::Text),
+            unknown => Err(format!("unknown encoding of folded stacks: {}", unknown)),
+        }
+    }
+}
+
+/// The folded stacks of a flame graph, encoded to be embedded in it.
+pub(super) struct EmbeddedFolded {
+    encoding: FoldedEncoding,
+    data: String,
+}
+
+impl EmbeddedFolded {
+    // Encodes folded stack `lines`, header comments included.
+    pub(super) fn new(lines: &[&str], encoding: FoldedEncoding) -> io::Result<Self> {
+        let mut text = String::new();
+        for line in lines {
+            text.push_str(line);
+            text.push('\n');
+        }
+        let data = match encoding {
+            FoldedEncoding::Text => text,
+            FoldedEncoding::Gzip => {
+                let mut encoder = Encoder::new(Vec::new())?;
+                encoder.write_all(text.as_bytes())?;
+                base64_encode(&encoder.finish().into_result()?)
+            }
+        };
+        Ok(EmbeddedFolded { encoding, data })
+    }
+
+    // Writes the stacks as the `<metadata>` of the flame graph.
+    pub(super) fn write<W: Write>(
+        &self,
+        svg: &mut Writer<W>,
+        embed: Option<&EmbedOptions>,
+    ) -> quick_xml::Result<()> {
+        svg.write_event(Event::Start(
+            BytesStart::new("metadata")
+                .with_attributes(vec![("id", &*svg::element_id(embed, "folded"))]),
+        ))?;
+        svg.write_event(Event::Start(
+            BytesStart::new("inferno:folded").with_attributes(vec![
+                ("xmlns:inferno", NAMESPACE),
+                ("encoding", self.encoding.attribute()),
+            ]),
+        ))?;
+        svg.write_event(Event::Text(BytesText::new(&self.data)))?;
+        svg.write_event(Event::End(BytesEnd::new("inferno:folded")))?;
+        svg.write_event(Event::End(BytesEnd::new("metadata")))
+    }
+}
+
+/// Recover the folded stacks that were embedded in an SVG flame graph with
+/// [`Options::embed_folded`](super::Options::embed_folded), and write them to `writer`.
+///
+/// The stacks are written as they were given to the flame graph, including header comments, so
+/// they can be plotted again with other options, or compared with another profile.
+///
+/// Flame graphs without embedded stacks are a [`Error::Parse`] error. Their stacks can still be
+/// approximated from their frames with [`crate::collapse::svg`].
+pub fn extract_folded<R, W>(mut reader: R, mut writer: W) -> io::Result<()>
+where
+    R: Read,
+    W: Write,
+{
+    let mut input = String::new();
+    reader.read_to_string(&mut input)?;
+
+    let mut reader = Reader::from_str(&input);
+    let mut encoding = None;
+    let mut data = String::new();
+    loop {
+        match reader.read_event().map_err(invalid_data)? {
+            Event::Start(e) if e.local_name().as_ref() == b"folded" => {
+                let attribute = e
+                    .try_get_attribute("encoding")
+                    .map_err(invalid_data)?
+                    .ok_or_else(|| Error::parse("svg", "embedded stacks have no encoding"))?;
+                encoding = Some(match &*attribute.value {
+                    b"text" => FoldedEncoding::Text,
+                    b"gzip+base64" => FoldedEncoding::Gzip,
+                    value => {
+                        return Err(Error::parse(
+                            "svg",
+                            format!(
+                                "unknown encoding of embedded stacks: {}",
+                                String::from_utf8_lossy(value)
+                            ),
+                        )
+                        .into())
+                    }
+                });
+            }
+            Event::Text(e) if encoding.is_some() => {
+                data.push_str(&e.unescape().map_err(invalid_data)?);
+            }
+            Event::CData(e) if encoding.is_some() => {
+                data.push_str(&String::from_utf8_lossy(&e));
+            }
+            Event::End(e) if e.local_name().as_ref() == b"folded" => break,
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+
+    match encoding {
+        None => Err(Error::parse("svg", "the flame graph has no embedded stacks").into()),
+        Some(FoldedEncoding::Text) => writer.write_all(data.as_bytes()),
+        Some(FoldedEncoding::Gzip) => {
+            let compressed = base64_decode(&data)
+                .ok_or_else(|| Error::parse("svg", "embedded stacks aren't valid base64"))?;
+            io::copy(&mut Decoder::new(&compressed[..])?, &mut writer)?;
+            Ok(())
+        }
+    }
+}
+
+fn invalid_data<E: std::error::Error + Send + Sync + 'static>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+fn base64_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity((bytes.len() + 2) / 3 * 4 + bytes.len() / 57 + 1);
+    let mut line = 0;
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(BASE64[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+        line += 4;
+        if line == BASE64_LINE {
+            encoded.push('\n');
+            line = 0;
+        }
+    }
+    if line != 0 {
+        encoded.push('\n');
+    }
+    encoded
+}
+
+// Decodes base64, ignoring whitespace, or returns `None` if it isn't valid base64.
+fn base64_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(encoded.len() / 4 * 3);
+    let mut n = 0u32;
+    let mut bits = 0;
+    let mut padding = 0;
+    for c in encoded.bytes().filter(|c| !c.is_ascii_whitespace()) {
+        if c == b'=' {
+            padding += 1;
+            continue;
+        }
+        if padding != 0 {
+            return None;
+        }
+        let value = BASE64.iter().position(|&b| b == c)? as u32;
+        n = n << 6 | value;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((n >> bits) as u8);
+            n &= (1 << bits) - 1;
+        }
+    }
+    if padding > 2 {
+        return None;
+    }
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64_round_trip() {
+        for len in 0..100 {
+            let bytes: Vec<u8> = (0..len).map(|i| (i * 37 + 11) as u8).collect();
+            let encoded = base64_encode(&bytes);
+            assert!(encoded.lines().all(|line| line.len() <= BASE64_LINE));
+            assert_eq!(base64_decode(&encoded), Some(bytes));
+        }
+        assert_eq!(base64_encode(b"inferno"), "aW5mZXJubw==\n");
+        assert_eq!(base64_decode("not base64!"), None);
+    }
+}
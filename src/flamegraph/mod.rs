@@ -8,17 +8,31 @@ macro_rules! args {
 mod attrs;
 
 pub mod color;
+mod fonts;
+mod html;
+mod json;
+mod links;
 mod merge;
+mod metadata;
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+mod open;
+mod png;
 mod rand;
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+mod stream;
 mod svg;
+mod tree;
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+mod watch;
 
-use std::fs::File;
 use std::io::prelude::*;
 use std::io::{self, BufReader};
 use std::iter;
+use std::mem;
 use std::path::PathBuf;
 use std::str::FromStr;
 
+use ahash::{AHashMap, AHashSet};
 use log::{error, warn};
 use num_format::Locale;
 use quick_xml::events::{BytesEnd, BytesStart, BytesText, Event};
@@ -27,16 +41,31 @@ use str_stack::StrStack;
 
 #[cfg(feature = "nameattr")]
 use self::attrs::FrameAttrs;
+use self::metadata::EmbeddedFolded;
 
 #[cfg(feature = "nameattr")]
 pub use self::attrs::FuncFrameAttrsMap;
 
 pub use self::color::Palette;
-use self::color::{Color, SearchColor, StrokeColor};
+use self::color::{Color, ColorFn, FrameInfo, SearchColor, StrokeColor};
+pub use self::fonts::FontMetrics;
+pub use self::links::FrameLinks;
+pub use self::metadata::{extract_folded, FoldedEncoding};
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub use self::open::{open_in_browser, temp_path};
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub use self::stream::{from_files_streaming, from_reader_streaming, StreamOptions};
 use self::svg::{Dimension, StyleOptions};
+pub use self::tree::{build_tree, FrameNode, FrameTree};
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub use self::watch::{watch, write_atomically};
+use crate::collapse::common;
+use crate::folded::{self, Header};
+use crate::Error;
 
 const XPAD: usize = 10; // pad left and right
 const FRAMEPAD: usize = 1; // vertical padding for frames
+const CATEGORY_BAND: usize = 6; // height of the band of stack categories
 
 // If no image width is given, this will be the initial width, but the embedded JavaScript will set
 // the width to 100% when it loads to make the width "fluid". The reason we give an initial width
@@ -87,13 +116,19 @@ pub mod defaults {
         FONT_WIDTH: f64 = 0.59,
         COUNT_NAME: &str = "samples",
         NAME_TYPE: &str = "Function:",
-        FACTOR: f64 = 1.0
+        FACTOR: f64 = 1.0,
+        WEIGHT_NAME: &str = "weight"
     }
 }
 
 /// Configure the flame graph.
 #[derive(Debug, PartialEq)]
 #[non_exhaustive]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
 pub struct Options<'a> {
     /// The color palette to use when plotting.
     pub colors: color::Palette,
@@ -104,8 +139,20 @@ pub struct Options<'a> {
     pub bgcolors: Option<color::BackgroundColor>,
 
     /// The color of UI text such as the search and reset view button. Defaults to black
+    #[cfg_attr(feature = "config", serde(with = "color::hex"))]
     pub uicolor: color::Color,
 
+    /// The color scheme of SVG flame graphs, which can make them dark, or follow the viewer's
+    /// preference with [`Theme::Auto`].
+    ///
+    /// Default is [`Theme::Light`].
+    pub theme: Theme,
+
+    /// How [`from_files`] combines the stacks of several input files.
+    ///
+    /// Default is [`MultiInput::Merge`].
+    pub multi_input: MultiInput,
+
     /// Choose names based on the hashes of function names.
     ///
     /// This will cause similar functions to be colored similarly.
@@ -125,6 +172,7 @@ pub struct Options<'a> {
     ///
     /// This feature was first implemented [by Shawn
     /// Sterling](https://github.com/brendangregg/FlameGraph/pull/25).
+    #[cfg_attr(feature = "config", serde(skip))]
     pub palette_map: Option<&'a mut color::PaletteMap>,
 
     /// Assign extra attributes to particular functions.
@@ -132,8 +180,16 @@ pub struct Options<'a> {
     /// In particular, if a function appears in the given map, it will have extra attributes set in
     /// the resulting SVG based on its value in the map.
     #[cfg(feature = "nameattr")]
+    #[cfg_attr(feature = "config", serde(skip))]
     pub func_frameattrs: FuncFrameAttrsMap,
 
+    /// Link frames to URLs made from their function names, like a search for them in a code
+    /// browser.
+    ///
+    /// Default is `None`.
+    #[cfg_attr(feature = "config", serde(skip))]
+    pub frame_links: Option<FrameLinks>,
+
     /// Whether to plot a plot that grows top-to-bottom or bottom-up (the default).
     pub direction: Direction,
 
@@ -172,6 +228,25 @@ pub struct Options<'a> {
     /// [Default value](defaults::MIN_WIDTH).
     pub min_width: f64,
 
+    /// Replace the frames left out for being narrower than `min_width` with a frame named
+    /// `[N frames omitted]` for each group of them that are next to each other under the same
+    /// frame, as wide as all of them together. This shows how much of the profile is in frames
+    /// too narrow to draw, which can add up when there are many of them.
+    ///
+    /// The new frames are left out too if they're narrower than `min_width`. JSON output, which
+    /// has all frames, is unaffected.
+    ///
+    /// Default is `false`.
+    pub aggregate_pruned: bool,
+
+    /// Cut stacks deeper than this many frames, replacing their deeper frames with one `...`
+    /// frame as wide as all of them (see [`crate::folded::truncate_depth`]). This keeps flame
+    /// graphs of very deep stacks, like those of recursive code or interpreters, short and
+    /// quick to render.
+    ///
+    /// Default is `None`, which keeps all frames.
+    pub max_depth: Option<usize>,
+
     /// The font type for the flame graph.
     ///
     /// [Default value](defaults::FONT_TYPE).
@@ -187,6 +262,15 @@ pub struct Options<'a> {
     /// [Default value](defaults::FONT_WIDTH).
     pub font_width: f64,
 
+    /// The widths of the characters of the font, to fit the names of functions in their frames
+    /// exactly. Without them, every byte of a name is assumed to be `font_width` wide, which
+    /// cuts off too little of the names of frames in proportional fonts and too much of those
+    /// with characters beyond ASCII.
+    ///
+    /// Default is `None`.
+    #[cfg_attr(feature = "config", serde(skip))]
+    pub font_metrics: Option<FontMetrics>,
+
     /// When text doesn't fit in a frame, should we cut off left side (the default) or right side?
     pub text_truncate_direction: TextTruncateDirection,
 
@@ -195,6 +279,15 @@ pub struct Options<'a> {
     /// [Default value](defaults::COUNT_NAME).
     pub count_name: String,
 
+    /// The unit of the sample counts, which decides how they are shown in frame titles and
+    /// details.
+    ///
+    /// Unless this is [`CountUnit::Samples`], counts are labelled with the unit instead of with
+    /// `count_name`.
+    ///
+    /// Default is [`CountUnit::Samples`].
+    pub count_unit: CountUnit,
+
     /// Name type label for the flame graph.
     ///
     /// [Default value](defaults::NAME_TYPE).
@@ -222,6 +315,42 @@ pub struct Options<'a> {
     /// [Default value](defaults::FACTOR).
     pub factor: f64,
 
+    /// Keep the fractional part of sample counts, instead of truncating them to integers.
+    ///
+    /// Sample counts are kept with as many fractional digits as the most precise count in the
+    /// input has, up to six, so that they still add up exactly. Counts with more digits than
+    /// that are truncated, with a warning. The speedscope and Gecko collapsers write fractional
+    /// weights like that too.
+    ///
+    /// Default is `false`.
+    pub fractional_samples: bool,
+
+    /// The format to produce the flame graph in.
+    ///
+    /// [`render_embeddable`] always produces SVG.
+    ///
+    /// Default is [`Format::Svg`].
+    pub format: Format,
+
+    /// Make SVG flame graphs smaller, for large profiles: frames refer to shared colors and a
+    /// table of names instead of having their own, their coordinates are less precise, and
+    /// frames without room for their names have no labels.
+    ///
+    /// Frames have no titles either. Their script adds them when they're hovered over, so they
+    /// don't show differential changes or weights, and compact flame graphs can't be read back
+    /// by `collapse-svg`.
+    ///
+    /// Default is `false`.
+    pub compact: bool,
+
+    /// Reload HTML flame graphs in the browser every this many seconds, so that a page that's
+    /// left open shows the latest flame graph while it's being rewritten, like with [`watch`].
+    ///
+    /// Reloading resets zooming and searching. Other formats aren't reloaded.
+    ///
+    /// Default is `None`.
+    pub auto_reload: Option<u32>,
+
     /// Pretty print XML with newlines and indentation.
     pub pretty_xml: bool,
 
@@ -241,11 +370,38 @@ pub struct Options<'a> {
     /// option will be ignored.
     pub reverse_stack_order: bool,
 
+    /// The number of threads to sort the input lines and merge them into frames with.
+    ///
+    /// Large inputs are split between the threads, and the flame graph is the same for any
+    /// number of them.
+    ///
+    /// Default is the number of logical cores on your machine.
+    pub nthreads: usize,
+
     /// Don't include static JavaScript in flame graph.
     /// This is only meant to be used in tests.
     #[doc(hidden)]
     pub no_javascript: bool,
 
+    /// Pick the colors of frames with a function, overriding the palette for the frames it
+    /// returns a color for.
+    ///
+    /// Default is `None`.
+    #[cfg_attr(feature = "config", serde(skip))]
+    pub color_fn: Option<ColorFn<'a>>,
+
+    /// What the colors of the frames show.
+    ///
+    /// With [`ColorMode::Weight`], lines without a weight are ignored.
+    ///
+    /// Default is [`ColorMode::Palette`].
+    pub color_mode: ColorMode,
+
+    /// The name of the weights of [`ColorMode::Weight`], used in frame titles and the legend.
+    ///
+    /// [Default value](defaults::WEIGHT_NAME).
+    pub weight_name: String,
+
     /// Diffusion-based color: the wider the frame, the more red it is. This
     /// helps visually draw the eye towards frames that are wider, and therefore
     /// more likely to need to be optimized. This is redundant information,
@@ -259,8 +415,26 @@ pub struct Options<'a> {
     /// Note that stack is not sorted and will be reversed
     pub flame_chart: bool,
 
+    /// Draw a thin band next to the root frames, colored by the category of the stacks above
+    /// each part of it, for stacks whose leaf frame has a category annotation (see
+    /// [`crate::folded::annotate_category`]).
+    ///
+    /// The category annotations are hidden from frame names either way.
+    ///
+    /// Default is `false`.
+    pub category_band: bool,
+
     /// Base symbols
     pub base: Vec<String>,
+
+    /// Embed the folded stacks that the flame graph is made from in the `<metadata>` of SVG flame
+    /// graphs, encoded like this, so that [`extract_folded`] can recover them later.
+    ///
+    /// Header comments are kept, but stacks that were rebased with [`Options::base`] are embedded
+    /// as they were before. Other formats ignore this.
+    ///
+    /// Default is `None`.
+    pub embed_folded: Option<FoldedEncoding>,
 }
 
 impl<'a> Options<'a> {
@@ -277,14 +451,23 @@ impl<'a> Options<'a> {
             // Inverted (icicle) mode, put the details on top. The +4 is to add
             // a little bit more space between the title (or subtitle if there
             // is one) and the details.
-            self.font_size * 4 + subtitle_height + 4
+            self.font_size * 4 + subtitle_height + 4 + self.category_band_height()
+        }
+    }
+
+    /// Height of the band of stack categories, which goes between the root frames and the details
+    pub(super) fn category_band_height(&self) -> usize {
+        if self.category_band {
+            CATEGORY_BAND
+        } else {
+            0
         }
     }
 
     /// Calculate pad bottom, including labels
     pub(super) fn ypad2(&self) -> usize {
         if self.direction == Direction::Straight {
-            self.font_size * 2 + 10
+            self.font_size * 2 + 10 + self.category_band_height()
         } else {
             // Inverted (icicle) mode, put the details on top, so don't need
             // room at the bottom.
@@ -302,30 +485,47 @@ impl<'a> Default for Options<'a> {
             title: defaults::TITLE.to_string(),
             frame_height: defaults::FRAME_HEIGHT,
             min_width: defaults::MIN_WIDTH,
+            aggregate_pruned: Default::default(),
+            max_depth: Default::default(),
             font_type: defaults::FONT_TYPE.to_string(),
             font_size: defaults::FONT_SIZE,
             font_width: defaults::FONT_WIDTH,
+            font_metrics: Default::default(),
             text_truncate_direction: Default::default(),
             count_name: defaults::COUNT_NAME.to_string(),
+            count_unit: Default::default(),
             name_type: defaults::NAME_TYPE.to_string(),
             factor: defaults::FACTOR,
+            fractional_samples: Default::default(),
             image_width: Default::default(),
             notes: Default::default(),
             subtitle: Default::default(),
             bgcolors: Default::default(),
             uicolor: Default::default(),
+            theme: Default::default(),
+            multi_input: Default::default(),
             hash: Default::default(),
             deterministic: Default::default(),
             palette_map: Default::default(),
             direction: Default::default(),
             negate_differentials: Default::default(),
+            format: Default::default(),
+            compact: Default::default(),
+            auto_reload: Default::default(),
             pretty_xml: Default::default(),
             no_sort: Default::default(),
             reverse_stack_order: Default::default(),
+            nthreads: *crate::collapse::DEFAULT_NTHREADS,
             no_javascript: Default::default(),
+            color_fn: Default::default(),
+            color_mode: Default::default(),
+            weight_name: defaults::WEIGHT_NAME.to_string(),
             color_diffusion: Default::default(),
             flame_chart: Default::default(),
+            category_band: Default::default(),
             base: Default::default(),
+            embed_folded: Default::default(),
+            frame_links: Default::default(),
 
             #[cfg(feature = "nameattr")]
             func_frameattrs: Default::default(),
@@ -335,6 +535,11 @@ impl<'a> Default for Options<'a> {
 
 /// The direction the plot should grow.
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
 pub enum Direction {
     /// Stacks grow from the bottom to the top.
     ///
@@ -350,6 +555,11 @@ pub enum Direction {
 
 /// The direction text is truncated when it's too long.
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
 pub enum TextTruncateDirection {
     /// Truncate text on the left.
     #[default]
@@ -359,6 +569,242 @@ pub enum TextTruncateDirection {
     Right,
 }
 
+/// The format of a flame graph.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum Format {
+    /// An SVG image, with a script to zoom into and search it.
+    #[default]
+    Svg,
+
+    /// A self-contained HTML page, with a persistent search box and a list of the functions it
+    /// matches, zoom breadcrumbs, and a sidebar with the statistics of each frame.
+    ///
+    /// The frames are stored as compact JSON in the page and drawn on a canvas, which keeps very
+    /// large profiles responsive.
+    Html,
+
+    /// A PNG image of the SVG, as wide as [`Options::image_width`], with frames as high as
+    /// [`Options::frame_height`].
+    ///
+    /// Only available with the `png` feature.
+    Png,
+
+    /// The merged frames as a JSON tree of `{"name", "value", "children"}` objects, as read by
+    /// d3-flame-graph. Frames of differential flame graphs also have a `delta`.
+    ///
+    /// Values are scaled by [`Options::factor`], and the root frame is called `all`.
+    Json,
+}
+
+impl Format {
+    /// The names of the formats.
+    pub const VARIANTS: &'static [&'static str] = &["svg", "html", "png", "json"];
+
+    /// The file extension of flame graphs in this format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Svg => "svg",
+            Format::Html => "html",
+            Format::Png => "png",
+            Format::Json => "json",
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "svg" => Ok(Format::Svg),
+            "html" => Ok(Format::Html),
+            "png" => Ok(Format::Png),
+            "json" => Ok(Format::Json),
+            unknown => Err(format!("unknown flame graph format: {}", unknown)),
+        }
+    }
+}
+
+/// The color scheme of an SVG flame graph.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum Theme {
+    /// The background and text colors picked by the other options.
+    #[default]
+    Light,
+
+    /// A dark background with light text, and slightly dimmed frames, for dark pages and
+    /// dashboards. This replaces the background and UI colors.
+    Dark,
+
+    /// [`Theme::Light`], or [`Theme::Dark`] when the viewer prefers a dark color scheme (with
+    /// the `prefers-color-scheme` media query).
+    Auto,
+}
+
+impl Theme {
+    /// The names of the themes.
+    pub const VARIANTS: &'static [&'static str] = &["light", "dark", "auto"];
+}
+
+impl FromStr for Theme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "light" => Ok(Theme::Light),
+            "dark" => Ok(Theme::Dark),
+            "auto" => Ok(Theme::Auto),
+            unknown => Err(format!("unknown theme: {}", unknown)),
+        }
+    }
+}
+
+/// How the stacks of several input files are combined into one flame graph.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "kebab-case")
+)]
+pub enum MultiInput {
+    /// The stacks of all files are added up, as if they were one file.
+    #[default]
+    Merge,
+
+    /// The stacks of each file are put under a root frame named after the file, so that each
+    /// file is a tower of its own (see [`crate::merge::Input::prefix`]). Files with the same name
+    /// get a number after it, like `perf (2)`.
+    PerFile,
+
+    /// A differential flame graph of the change from the first file to the second (see
+    /// [`crate::differential`]), which needs exactly two files.
+    #[cfg_attr(feature = "config", serde(rename = "diff"))]
+    Differential,
+}
+
+impl MultiInput {
+    /// The names of the ways to combine input files.
+    pub const VARIANTS: &'static [&'static str] = &["merge", "per-file", "diff"];
+}
+
+impl FromStr for MultiInput {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "merge" => Ok(MultiInput::Merge),
+            "per-file" => Ok(MultiInput::PerFile),
+            "diff" => Ok(MultiInput::Differential),
+            unknown => Err(format!("unknown way to combine inputs: {}", unknown)),
+        }
+    }
+}
+
+/// What the colors of the frames of a flame graph show.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum ColorMode {
+    /// Frames are colored by their palette, or by how their samples changed in differential
+    /// flame graphs.
+    #[default]
+    Palette,
+
+    /// Each line of input has a weight after its sample count, like `main;read 120 3400`, and
+    /// frames are colored on a gradient by their weight per sample. The sample counts decide the
+    /// widths of the frames as usual.
+    ///
+    /// This shows a second metric along with the first, like the instructions per cycle of
+    /// frames that are as wide as their cycles, or the cache misses per sample. A legend below
+    /// the frames says what the colors range over.
+    Weight,
+}
+
+impl ColorMode {
+    /// The names of the color modes.
+    pub const VARIANTS: &'static [&'static str] = &["palette", "weight"];
+}
+
+impl FromStr for ColorMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "palette" => Ok(ColorMode::Palette),
+            "weight" => Ok(ColorMode::Weight),
+            unknown => Err(format!("unknown color mode: {}", unknown)),
+        }
+    }
+}
+
+/// The unit of the sample counts of a flame graph.
+#[derive(Debug, Clone, Eq, PartialEq, Default)]
+pub enum CountUnit {
+    /// Plain counts, labelled with [`Options::count_name`].
+    #[default]
+    Samples,
+
+    /// Bytes, shown in B, KiB, MiB, GiB or TiB.
+    Bytes,
+
+    /// Nanoseconds, shown in ns, µs, ms or s.
+    Nanoseconds,
+
+    /// Plain counts, labelled with the given unit.
+    Custom(String),
+}
+
+impl CountUnit {
+    /// The names of the count units that aren't custom.
+    pub const VARIANTS: &'static [&'static str] = &["samples", "bytes", "nanoseconds"];
+}
+
+impl FromStr for CountUnit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "" => Err("count unit cannot be empty".to_string()),
+            "samples" => Ok(CountUnit::Samples),
+            "bytes" => Ok(CountUnit::Bytes),
+            "nanoseconds" | "ns" => Ok(CountUnit::Nanoseconds),
+            unit => Ok(CountUnit::Custom(unit.to_string())),
+        }
+    }
+}
+
+#[cfg(feature = "config")]
+impl serde::Serialize for CountUnit {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(match self {
+            CountUnit::Samples => "samples",
+            CountUnit::Bytes => "bytes",
+            CountUnit::Nanoseconds => "nanoseconds",
+            CountUnit::Custom(unit) => unit,
+        })
+    }
+}
+
+#[cfg(feature = "config")]
+impl<'de> serde::Deserialize<'de> for CountUnit {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        crate::config::deserialize_from_str(deserializer)
+    }
+}
+
 struct Rectangle {
     x1_samples: usize,
     x1_pct: f64,
@@ -389,22 +835,240 @@ impl Rectangle {
 /// flame graph uses the difference between the two sample counts to show how the sample counts for
 /// each stack has changed between the first and second profiling.
 ///
-/// The resulting flame graph will be written out to `writer` in SVG format.
+/// If the lines start with a [`Header`], its units are used as the `count_name`, and its event and
+/// duration as the `subtitle`, unless those options were changed from their defaults.
+///
+/// The resulting flame graph will be written out to `writer` in the format of [`Options::format`].
 ///
 /// [differential flame graph]: http://www.brendangregg.com/blog/2014-11-09/differential-flame-graphs.html
-#[allow(clippy::cognitive_complexity)]
 pub fn from_lines<'a, I, W>(opt: &mut Options<'_>, lines: I, writer: W) -> quick_xml::Result<()>
+where
+    I: IntoIterator<Item = &'a str>,
+    W: Write,
+{
+    render(opt, lines, writer, None)
+}
+
+/// Produce a flame graph from folded stack lines in memory, and return it as a string, without
+/// files or a writer, like in a WebAssembly module that gets its input from JavaScript.
+///
+/// PNG flame graphs aren't text, so they're an error of kind `Unsupported`.
+///
+/// See [`from_lines`] for the expected format of each line.
+pub fn from_bytes(opt: &mut Options<'_>, folded: &[u8]) -> quick_xml::Result<String> {
+    if opt.format == Format::Png {
+        return Err(Error::UnsupportedFormat(
+            "PNG flame graphs can't be returned as strings".to_string(),
+        )
+        .into());
+    }
+    let mut output = Vec::new();
+    from_reader(opt, folded, &mut output)?;
+    String::from_utf8(output)
+        .map_err(|e| quick_xml::Error::Io(io::Error::new(io::ErrorKind::InvalidData, e)))
+}
+
+/// Configure how [`render_embeddable`] embeds a flame graph in a web page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct EmbedOptions {
+    /// The id of the root `<svg>` element, and the prefix of the ids of the elements in it.
+    ///
+    /// This must be unique on the page, and must be a valid CSS identifier.
+    ///
+    /// Default is `"flamegraph"`.
+    pub id_prefix: String,
+
+    /// Include the script that lets users zoom into and search the flame graph.
+    ///
+    /// Default is `false`.
+    pub javascript: bool,
+}
+
+impl Default for EmbedOptions {
+    fn default() -> Self {
+        EmbedOptions {
+            id_prefix: "flamegraph".to_string(),
+            javascript: false,
+        }
+    }
+}
+
+/// Produce a flame graph from an iterator over folded stack lines, as an SVG fragment to inline
+/// in an HTML page.
+///
+/// Unlike [`from_lines`], the fragment has no XML declaration or doctype, is as wide as the
+/// element it's in (keeping its aspect ratio), and only has JavaScript if
+/// [`EmbedOptions::javascript`] is set. Its styles only apply to itself, so several flame graphs
+/// with different id prefixes can be on one page.
+///
+/// The following can be styled from the page, with `p` being [`EmbedOptions::id_prefix`]:
+///
+///  - `svg.inferno-flamegraph`, or `#p`: the root element.
+///  - `#p-title` and `#p-subtitle`: the title and subtitle.
+///  - `#p-details`: the details of the frame under the mouse.
+///  - `#p-unzoom`, `#p-search` and `#p-matched`: the reset zoom and search buttons, and the
+///    matched percentage.
+///  - `#p-frames > g`: the frames, each a `<rect>` and a `<text>` with a `<title>` tooltip.
+///  - `#p-background`: the background gradient.
+///
+/// See [`from_lines`] for the expected format of each line.
+pub fn render_embeddable<'a, I, W>(
+    opt: &mut Options<'_>,
+    embed: &EmbedOptions,
+    lines: I,
+    writer: W,
+) -> quick_xml::Result<()>
+where
+    I: IntoIterator<Item = &'a str>,
+    W: Write,
+{
+    render(opt, lines, writer, Some(embed))
+}
+
+/// Produce a flame graph from an iterator over folded stack lines, as a PNG image.
+///
+/// This is the same as [`from_lines`] with [`Format::Png`]: the SVG flame graph is rasterized
+/// without a browser, as wide as [`Options::image_width`] and with frames as high as
+/// [`Options::frame_height`]. Text is drawn with the fonts installed on the system.
+///
+/// Only available with the `png` feature.
+#[cfg(feature = "png")]
+pub fn render_png<'a, I, W>(opt: &mut Options<'_>, lines: I, writer: W) -> quick_xml::Result<()>
+where
+    I: IntoIterator<Item = &'a str>,
+    W: Write,
+{
+    let format = mem::replace(&mut opt.format, Format::Png);
+    let result = render(opt, lines, writer, None);
+    opt.format = format;
+    result
+}
+
+fn render<'a, I, W>(
+    opt: &mut Options<'_>,
+    lines: I,
+    writer: W,
+    embed: Option<&EmbedOptions>,
+) -> quick_xml::Result<()>
+where
+    I: IntoIterator<Item = &'a str>,
+    W: Write,
+{
+    if opt.format == Format::Png && embed.is_none() {
+        opt.format = Format::Svg;
+        let embed_folded = opt.embed_folded.take();
+        let mut svg = Vec::new();
+        let result = render(opt, lines, &mut svg, None);
+        opt.format = Format::Png;
+        opt.embed_folded = embed_folded;
+        result?;
+        return png::write(&svg, writer).map_err(quick_xml::Error::Io);
+    }
+
+    let mut lines = lines.into_iter().map(|line| line.trim()).peekable();
+    let mut comments = Vec::new();
+    while let Some(line) = lines.next_if(|line| line.starts_with("# ")) {
+        comments.push(line);
+    }
+
+    let encoding = match opt.embed_folded {
+        Some(encoding) if opt.format == Format::Svg => encoding,
+        _ => {
+            return with_header(opt, Header::parse(comments), |opt| {
+                render_lines(opt, lines, writer, embed, None)
+            })
+        }
+    };
+    let mut folded = comments.clone();
+    folded.extend(lines);
+    let embedded = EmbeddedFolded::new(&folded, encoding).map_err(quick_xml::Error::Io)?;
+    let lines = folded[comments.len()..].iter().copied();
+    with_header(opt, Header::parse(comments), |opt| {
+        render_lines(opt, lines, writer, embed, Some(&embedded))
+    })
+}
+
+// Fills in the labels `header` knows about while `render` runs, for this flame graph only.
+fn with_header<'a, T>(
+    opt: &mut Options<'a>,
+    header: Option<Header>,
+    render: impl FnOnce(&mut Options<'a>) -> T,
+) -> T {
+    let header = match header {
+        Some(header) => header,
+        None => return render(opt),
+    };
+    let mut count_name = None;
+    if opt.count_name == defaults::COUNT_NAME {
+        if let Some(ref units) = header.units {
+            count_name = Some(mem::replace(&mut opt.count_name, units.clone()));
+        }
+    }
+    let mut filled_subtitle = false;
+    if opt.subtitle.is_none() {
+        let duration = header
+            .duration()
+            .map(|duration| format!("{:.2} seconds", duration));
+        opt.subtitle = match (header.event, duration) {
+            (Some(event), Some(duration)) => Some(format!("{}, {}", event, duration)),
+            (event, duration) => event.or(duration),
+        };
+        filled_subtitle = opt.subtitle.is_some();
+    }
+
+    let result = render(opt);
+    if let Some(count_name) = count_name {
+        opt.count_name = count_name;
+    }
+    if filled_subtitle {
+        opt.subtitle = None;
+    }
+    result
+}
+
+fn render_lines<'a, I, W>(
+    opt: &mut Options<'_>,
+    lines: I,
+    writer: W,
+    embed: Option<&EmbedOptions>,
+    folded: Option<&EmbeddedFolded>,
+) -> quick_xml::Result<()>
+where
+    I: IntoIterator<Item = &'a str>,
+    W: Write,
+{
+    if !opt.fractional_samples {
+        return render_stacks(opt, lines, 0, writer, embed, folded);
+    }
+
+    // Sample counts become fixed-point numbers with this many fractional digits, so that
+    // merging frames stays exact.
+    let lines: Vec<&str> = lines.into_iter().collect();
+    let digits = merge::fraction_digits(&lines);
+    render_stacks(opt, lines, digits, writer, embed, folded)
+}
+
+#[allow(clippy::cognitive_complexity)]
+fn render_stacks<'a, I, W>(
+    opt: &mut Options<'_>,
+    lines: I,
+    digits: usize,
+    writer: W,
+    embed: Option<&EmbedOptions>,
+    folded: Option<&EmbeddedFolded>,
+) -> quick_xml::Result<()>
 where
     I: IntoIterator<Item = &'a str>,
     W: Write,
 {
     let mut reversed = StrStack::new();
+    let weighted = opt.color_mode == ColorMode::Weight;
     let lines = lines
         .into_iter()
-        .map(|line| line.trim())
         .filter(|line| !(line.is_empty() || line.starts_with("# ")));
 
-    let (mut frames, time, ignored, delta_max) = if opt.reverse_stack_order {
+    let (frames, time, ignored, delta_max) = if opt.reverse_stack_order {
         if opt.no_sort {
             warn!(
                 "Input lines are always sorted when `reverse_stack_order` is `true`. \
@@ -432,16 +1096,24 @@ where
             reversed.push(&stack);
         }
         let mut reversed: Vec<&str> = reversed.iter().collect();
-        reversed.sort_unstable();
-        merge::frames(reversed, false)?
+        merge::sort_lines(&mut reversed, opt.nthreads);
+        merge::frames_parallel(
+            &reversed,
+            false,
+            digits,
+            weighted,
+            opt.max_depth,
+            opt.nthreads,
+        )?
     } else if opt.flame_chart {
         // In flame chart mode, just reverse the data so time moves from left to right.
         let mut lines: Vec<&str> = lines.into_iter().collect();
         lines.reverse();
-        merge::frames(lines, true)?
+        merge::frames_parallel(&lines, true, digits, weighted, opt.max_depth, opt.nthreads)?
     } else if opt.no_sort {
         // Lines don't need sorting.
-        merge::frames(lines, false)?
+        let lines: Vec<&str> = lines.into_iter().collect();
+        merge::frames_parallel(&lines, false, digits, weighted, opt.max_depth, opt.nthreads)?
     } else {
         // Sort lines by default.
         let mut lines: Vec<&str> = if opt.base.is_empty() {
@@ -449,32 +1121,42 @@ where
         } else {
             lines
                 .into_iter()
-                .filter_map(|line| {
-                    let mut cursor = line.len();
-                    for symbol in line.rsplit(';') {
-                        cursor -= symbol.len();
-                        if opt.base.iter().any(|b| b == symbol) {
-                            break;
-                        }
-                        cursor = cursor.saturating_sub(1);
-                    }
-                    if cursor == 0 {
-                        None
-                    } else {
-                        Some(&line[cursor..])
-                    }
-                })
+                .filter_map(|line| rebase(line, &opt.base))
                 .collect()
         };
-        lines.sort_unstable();
-        merge::frames(lines, false)?
+        merge::sort_lines(&mut lines, opt.nthreads);
+        merge::frames_parallel(&lines, false, digits, weighted, opt.max_depth, opt.nthreads)?
     };
 
     if ignored != 0 {
         warn!("Ignored {} lines with invalid format", ignored);
     }
 
-    let mut buffer = StrStack::new();
+    let omitted = if opt.aggregate_pruned && opt.format != Format::Json {
+        omitted_runs(&frames, opt.min_width * time as f64 / 100.0)
+    } else {
+        Vec::new()
+    };
+    let omitted_names: Vec<_> = omitted.iter().map(OmittedRun::name).collect();
+    let mut frames = frames;
+    frames.extend(
+        omitted
+            .iter()
+            .zip(&omitted_names)
+            .map(|(run, name)| run.frame(name)),
+    );
+
+    if matches!(opt.format, Format::Html | Format::Json) && embed.is_none() {
+        if time == 0 {
+            error!("No stack counts found");
+            return Err(Error::parse("folded", "No stack counts found").into());
+        }
+        return match opt.format {
+            Format::Html => html::write(opt, frames, time, delta_max, digits, writer),
+            _ => json::write(opt, frames, digits, writer),
+        }
+        .map_err(quick_xml::Error::Io);
+    }
 
     // let's start writing the svg!
     let mut svg = if opt.pretty_xml {
@@ -484,32 +1166,16 @@ where
     };
 
     if time == 0 {
-        error!("No stack counts found");
-        // emit an error message SVG, for tools automating flamegraph use
-        let imageheight = opt.font_size * 5;
-        svg::write_header(&mut svg, imageheight, opt)?;
-        svg::write_str(
-            &mut svg,
-            &mut buffer,
-            svg::TextItem {
-                x: Dimension::Percent(50.0),
-                y: (opt.font_size * 2) as f64,
-                text: "ERROR: No valid input provided to flamegraph".into(),
-                extra: None,
-            },
-        )?;
-        svg.write_event(Event::End(BytesEnd::new("svg")))?;
-        svg.write_event(Event::Eof)?;
-        return Err(quick_xml::Error::Io(io::Error::new(
-            io::ErrorKind::InvalidData,
-            "No stack counts found",
-        )));
+        return write_no_stacks(&mut svg, opt, embed);
     }
 
-    let image_width = opt.image_width.unwrap_or(DEFAULT_IMAGE_WIDTH) as f64;
-    let timemax = time;
-    let widthpertime_pct = 100.0 / timemax as f64;
-    let minwidth_time = opt.min_width / widthpertime_pct;
+    let categories = if opt.category_band {
+        category_segments(&frames)
+    } else {
+        Vec::new()
+    };
+
+    let minwidth_time = opt.min_width / (100.0 / time as f64);
 
     // prune blocks that are too narrow
     let mut depthmax = 0;
@@ -522,53 +1188,188 @@ where
         }
     });
 
-    // draw canvas, and embed interactive JavaScript program
-    let imageheight = ((depthmax + 1) * opt.frame_height) + opt.ypad1() + opt.ypad2();
-    svg::write_header(&mut svg, imageheight, opt)?;
+    let mut painter =
+        FramePainter::new(opt, time, depthmax, delta_max, weight_max(&frames), digits);
+    painter.write_start(opt, &mut svg, embed)?;
+    for frame in frames {
+        painter.paint(opt, &mut svg, &frame)?;
+    }
+    painter.write_end(opt, svg, embed, &categories, folded)
+}
 
-    let (bgcolor1, bgcolor2) = color::bgcolor_for(opt.bgcolors, opt.colors);
-    let strokecolor = match opt.stroke_color {
-        StrokeColor::Color(c) => Some(c.to_string()),
-        StrokeColor::None => None,
-    };
-    let uicolor = opt.uicolor.to_string();
-    let style_options = StyleOptions {
-        imageheight,
-        bgcolor1,
-        bgcolor2,
-        uicolor,
-        strokecolor,
-    };
+// Cuts the frames of a line up to the last of the `base` symbols off, leaving out lines that
+// don't have any of them.
+fn rebase<'l>(line: &'l str, base: &[String]) -> Option<&'l str> {
+    let mut cursor = line.len();
+    for symbol in line.rsplit(';') {
+        cursor -= symbol.len();
+        if base.iter().any(|b| b == symbol) {
+            break;
+        }
+        cursor = cursor.saturating_sub(1);
+    }
+    if cursor == 0 {
+        None
+    } else {
+        Some(&line[cursor..])
+    }
+}
 
-    svg::write_prelude(&mut svg, &style_options, opt)?;
-
-    // Used when picking color parameters at random, when no option determines how to pick these
-    // parameters. We instantiate it here because it may be called once for each iteration in the
-    // frames loop.
-    let mut thread_rng = rand::thread_rng();
-
-    // structs to reuse across loops to avoid allocations
-    let mut cache_g = Event::Start(BytesStart::new("g"));
-    let mut cache_a = Event::Start(BytesStart::new("a"));
-    let mut cache_rect = Event::Empty(BytesStart::new("rect"));
-    let cache_g_end = Event::End(BytesEnd::new("g"));
-    let cache_a_end = Event::End(BytesEnd::new("a"));
-
-    // create frames container
-    let container_x = format!("{}", XPAD);
-    let container_width = format!("{}", image_width as usize - XPAD - XPAD);
-    svg.write_event(Event::Start(BytesStart::new("svg").with_attributes(vec![
-        ("id", "frames"),
-        ("x", &container_x),
-        ("width", &container_width),
-        ("total_samples", &format!("{}", timemax)),
-    ])))?;
-
-    // draw frames
-    let mut samples_txt_buffer = num_format::Buffer::default();
-    for frame in frames {
-        let x1_pct = frame.start_time as f64 * widthpertime_pct;
-        let x2_pct = frame.end_time as f64 * widthpertime_pct;
+// Emits an error message SVG when there are no stacks to plot, for tools automating flamegraph use.
+fn write_no_stacks<W: Write>(
+    svg: &mut Writer<W>,
+    opt: &Options<'_>,
+    embed: Option<&EmbedOptions>,
+) -> quick_xml::Result<()> {
+    error!("No stack counts found");
+    let imageheight = opt.font_size * 5;
+    svg::write_header(svg, imageheight, opt, embed)?;
+    svg::write_str(
+        svg,
+        &mut StrStack::new(),
+        svg::TextItem {
+            x: Dimension::Percent(50.0),
+            y: (opt.font_size * 2) as f64,
+            text: "ERROR: No valid input provided to flamegraph".into(),
+            extra: None,
+        },
+    )?;
+    svg.write_event(Event::End(BytesEnd::new("svg")))?;
+    svg.write_event(Event::Eof)?;
+    Err(Error::parse("folded", "No stack counts found").into())
+}
+
+// Draws the frames of an SVG flame graph one at a time, once the size of the flame graph is known,
+// so that they can be drawn as soon as they're found.
+struct FramePainter {
+    image_width: f64,
+    imageheight: usize,
+    timemax: usize,
+    widthpertime_pct: f64,
+    delta_max: usize,
+    weight_max: f64,
+    digits: usize,
+    container_x: String,
+    container_width: String,
+
+    // structs to reuse across frames to avoid allocations
+    buffer: StrStack,
+    samples_txt: String,
+    weight_txt: String,
+    cache_g: Event<'static>,
+    cache_a: Event<'static>,
+    cache_rect: Event<'static>,
+    cache_g_end: Event<'static>,
+    cache_a_end: Event<'static>,
+
+    // The classes of the colors, and the indices of the names, of the frames of compact flame
+    // graphs.
+    compact_colors: AHashMap<Color, usize>,
+    compact_names: AHashMap<String, usize>,
+}
+
+impl FramePainter {
+    // A painter for a flame graph of `timemax` samples whose deepest frame that's drawn is at
+    // `depthmax`.
+    fn new(
+        opt: &Options<'_>,
+        timemax: usize,
+        depthmax: usize,
+        delta_max: usize,
+        weight_max: f64,
+        digits: usize,
+    ) -> Self {
+        let image_width = opt.image_width.unwrap_or(DEFAULT_IMAGE_WIDTH) as f64;
+        FramePainter {
+            image_width,
+            imageheight: ((depthmax + 1) * opt.frame_height) + opt.ypad1() + opt.ypad2(),
+            timemax,
+            widthpertime_pct: 100.0 / timemax as f64,
+            delta_max,
+            weight_max,
+            digits,
+            container_x: format!("{}", XPAD),
+            container_width: format!("{}", image_width as usize - XPAD - XPAD),
+            buffer: StrStack::new(),
+            samples_txt: String::new(),
+            weight_txt: String::new(),
+            cache_g: Event::Start(BytesStart::new("g")),
+            cache_a: Event::Start(BytesStart::new("a")),
+            cache_rect: Event::Empty(BytesStart::new("rect")),
+            cache_g_end: Event::End(BytesEnd::new("g")),
+            cache_a_end: Event::End(BytesEnd::new("a")),
+            compact_colors: AHashMap::default(),
+            compact_names: AHashMap::default(),
+        }
+    }
+
+    // Writes everything that goes before the frames, and opens their container.
+    fn write_start<W: Write>(
+        &mut self,
+        opt: &Options<'_>,
+        svg: &mut Writer<W>,
+        embed: Option<&EmbedOptions>,
+    ) -> quick_xml::Result<()> {
+        // draw canvas, and embed interactive JavaScript program
+        svg::write_header(svg, self.imageheight, opt, embed)?;
+
+        let (bgcolor1, bgcolor2) = color::bgcolor_for(opt.bgcolors, opt.colors);
+        let strokecolor = match opt.stroke_color {
+            StrokeColor::Color(c) => Some(c.to_string()),
+            StrokeColor::None => None,
+        };
+        let style_options = StyleOptions {
+            imageheight: self.imageheight,
+            bgcolor1,
+            bgcolor2,
+            uicolor: opt.uicolor.to_string(),
+            strokecolor,
+        };
+
+        svg::write_prelude(svg, &style_options, opt, embed)?;
+
+        if opt.color_mode == ColorMode::Weight {
+            write_weight(&mut self.weight_txt, opt, self.weight_max, self.digits);
+            svg::write_str(
+                svg,
+                &mut self.buffer,
+                svg::TextItem {
+                    x: Dimension::Percent(50.0),
+                    y: (self.imageheight - (opt.ypad2() / 2)) as f64,
+                    text: format!("Color: 0 to {}", self.weight_txt).into(),
+                    extra: vec![
+                        ("id", &*svg::element_id(embed, "legend")),
+                        ("fill", &style_options.uicolor),
+                    ],
+                },
+            )?;
+        }
+
+        // create frames container
+        svg.write_event(Event::Start(BytesStart::new("svg").with_attributes(vec![
+            ("id", &*svg::element_id(embed, "frames")),
+            ("x", &self.container_x),
+            ("width", &self.container_width),
+            ("total_samples", &format!("{}", self.timemax)),
+        ])))?;
+        Ok(())
+    }
+
+    // Draws one frame.
+    fn paint<W: Write>(
+        &mut self,
+        opt: &mut Options<'_>,
+        svg: &mut Writer<W>,
+        frame: &merge::TimedFrame<'_>,
+    ) -> quick_xml::Result<()> {
+        let image_width = self.image_width;
+        let imageheight = self.imageheight;
+        let timemax = self.timemax;
+        let digits = self.digits;
+        let buffer = &mut self.buffer;
+
+        let x1_pct = frame.start_time as f64 * self.widthpertime_pct;
+        let x2_pct = frame.end_time as f64 * self.widthpertime_pct;
 
         let (y1, y2) = match opt.direction {
             Direction::Straight => {
@@ -601,27 +1402,32 @@ where
         //     `sprintf "%.0f", 3.5` produces "4"
         let samples = ((frame.end_time - frame.start_time) as f64 * opt.factor).round() as usize;
 
-        // add thousands separators to `samples`
-        let _ = samples_txt_buffer.write_formatted(&samples, &Locale::en);
-        let samples_txt = samples_txt_buffer.as_str();
+        write_count(&mut self.samples_txt, opt, samples, digits);
+        let samples_txt = &self.samples_txt;
 
-        let info = if frame.location.function.is_empty() && frame.location.depth == 0 {
-            write!(buffer, "all ({} {}, 100%)", samples_txt, opt.count_name)
+        // Compact flame graphs leave out titles, which their script makes from the names of the
+        // frames instead, unless frame attributes set them.
+        let info = if opt.compact {
+            buffer.push("")
+        } else if frame.location.function.is_empty() && frame.location.depth == 0 {
+            write!(buffer, "all ({}, 100%)", samples_txt)
         } else {
             let pct = (100 * samples) as f64 / (timemax as f64 * opt.factor);
             let function = deannotate(frame.location.function);
             match frame.delta {
-                None => write!(
-                    buffer,
-                    "{} ({} {}, {:.2}%)",
-                    function, samples_txt, opt.count_name, pct
-                ),
+                None => match weight_rate(frame) {
+                    Some(rate) => {
+                        write_weight(&mut self.weight_txt, opt, rate, digits);
+                        write!(
+                            buffer,
+                            "{} ({}, {:.2}%; {})",
+                            function, samples_txt, pct, self.weight_txt
+                        )
+                    }
+                    None => write!(buffer, "{} ({}, {:.2}%)", function, samples_txt, pct),
+                },
                 // Special case delta == 0 so we don't format percentage with a + sign.
-                Some(0) => write!(
-                    buffer,
-                    "{} ({} {}, {:.2}%; 0.00%)",
-                    function, samples_txt, opt.count_name, pct,
-                ),
+                Some(0) => write!(buffer, "{} ({}, {:.2}%; 0.00%)", function, samples_txt, pct,),
                 Some(mut delta) => {
                     if opt.negate_differentials {
                         delta = -delta;
@@ -629,8 +1435,8 @@ where
                     let delta_pct = (100 * delta) as f64 / (timemax as f64 * opt.factor);
                     write!(
                         buffer,
-                        "{} ({} {}, {:.2}%; {:+.2}%)",
-                        function, samples_txt, opt.count_name, pct, delta_pct
+                        "{} ({}, {:.2}%; {:+.2}%)",
+                        function, samples_txt, pct, delta_pct
                     )
                 }
             }
@@ -638,56 +1444,57 @@ where
 
         let (has_href, title) = write_container_start(
             opt,
-            &mut svg,
-            &mut cache_a,
-            &mut cache_g,
-            &frame,
+            svg,
+            &mut self.cache_a,
+            &mut self.cache_g,
+            frame,
             &buffer[info],
         )?;
 
-        svg.write_event(Event::Start(BytesStart::new("title")))?;
-        svg.write_event(Event::Text(BytesText::new(title)))?;
-        svg.write_event(Event::End(BytesEnd::new("title")))?;
+        if !title.is_empty() {
+            svg.write_event(Event::Start(BytesStart::new("title")))?;
+            svg.write_event(Event::Text(BytesText::new(title)))?;
+            svg.write_event(Event::End(BytesEnd::new("title")))?;
+        }
 
         // select the color of the rectangle
-        let color = if frame.location.function == "--" {
-            color::VDGREY
-        } else if frame.location.function == "-" {
-            color::DGREY
-        } else if opt.color_diffusion {
-            // We want to visually highlight high priority regions for
-            // optimization: wider frames are redder. Typically when optimizing,
-            // a frame that is 50% of width is high priority, so it seems wrong
-            // to give it half the saturation of 100%. So we use sqrt to make
-            // the red dropoff less linear.
-            color::color_scale((((x2_pct - x1_pct) / 100.0).sqrt() * 2000.0) as isize, 2000)
-        } else if let Some(mut delta) = frame.delta {
-            if opt.negate_differentials {
-                delta = -delta;
-            }
-            color::color_scale(delta, delta_max)
-        } else if let Some(ref mut palette_map) = opt.palette_map {
-            let colors = opt.colors;
-            let hash = opt.hash;
-            let deterministic = opt.deterministic;
-            palette_map.find_color_for(frame.location.function, |name| {
-                color::color(colors, hash, deterministic, name, &mut thread_rng)
-            })
+        let color = frame_color(
+            opt,
+            frame,
+            x2_pct - x1_pct,
+            self.delta_max,
+            self.weight_max,
+            rand::thread_rng(),
+        );
+        if opt.compact {
+            let ncolors = self.compact_colors.len();
+            let class = *self.compact_colors.entry(color).or_insert(ncolors);
+            let function = deannotate(frame.location.function);
+            let name = match self.compact_names.get(function) {
+                Some(&name) => name,
+                None => {
+                    let name = self.compact_names.len();
+                    self.compact_names.insert(function.to_string(), name);
+                    name
+                }
+            };
+            compact_rectangle(svg, buffer, &rect, class, name, &mut self.cache_rect)?;
         } else {
-            color::color(
-                opt.colors,
-                opt.hash,
-                opt.deterministic,
-                frame.location.function,
-                &mut thread_rng,
-            )
-        };
-        filled_rectangle(&mut svg, &mut buffer, &rect, color, &mut cache_rect)?;
+            filled_rectangle(svg, buffer, &rect, color, &mut self.cache_rect)?;
+        }
 
         let fitchars = (rect.width_pct()
             / (100.0 * opt.font_size as f64 * opt.font_width / image_width))
             .trunc() as usize;
-        let text: svg::TextArgument<'_> = if fitchars >= 3 {
+        let text: svg::TextArgument<'_> = if let Some(ref metrics) = opt.font_metrics {
+            let f = deannotate(frame.location.function);
+            let space = rect.width_pct() * image_width / (100.0 * opt.font_size as f64);
+            match metrics.fit(f, space) {
+                Some(fit) if fit.len() == f.len() => f.into(),
+                Some(fit) => write!(buffer, "{}..", fit).into(),
+                None => "".into(),
+            }
+        } else if fitchars >= 3 {
             // room for one char plus two dots
             let f = deannotate(frame.location.function);
 
@@ -710,32 +1517,109 @@ where
             "".into()
         };
 
-        // write the text
-        svg::write_str(
-            &mut svg,
-            &mut buffer,
-            svg::TextItem {
-                x: Dimension::Percent(rect.x1_pct + 100.0 * 3.0 / image_width),
-                y: 3.0 + (rect.y1 + rect.y2) as f64 / 2.0,
-                text,
-                extra: None,
-            },
-        )?;
+        // write the text, which compact flame graphs leave out when there's no room for it
+        if !(opt.compact && matches!(text, svg::TextArgument::String(ref s) if s.is_empty())) {
+            svg::write_str(
+                svg,
+                buffer,
+                svg::TextItem {
+                    x: Dimension::Percent(rect.x1_pct + 100.0 * 3.0 / image_width),
+                    y: 3.0 + (rect.y1 + rect.y2) as f64 / 2.0,
+                    text,
+                    extra: None,
+                },
+            )?;
+        }
 
         buffer.clear();
         if has_href {
-            svg.write_event(&cache_a_end)?;
+            svg.write_event(&self.cache_a_end)
         } else {
-            svg.write_event(&cache_g_end)?;
+            svg.write_event(&self.cache_g_end)
         }
     }
 
-    svg.write_event(Event::End(BytesEnd::new("svg")))?;
-    svg.write_event(Event::End(BytesEnd::new("svg")))?;
-    svg.write_event(Event::Eof)?;
+    // Closes the container of the frames, and writes everything that goes after them.
+    fn write_end<W: Write>(
+        mut self,
+        opt: &Options<'_>,
+        mut svg: Writer<W>,
+        embed: Option<&EmbedOptions>,
+        categories: &[(usize, usize, &str)],
+        folded: Option<&EmbeddedFolded>,
+    ) -> quick_xml::Result<()> {
+        svg.write_event(Event::End(BytesEnd::new("svg")))?;
+
+        if opt.compact {
+            svg::write_compact_tables(
+                &mut svg,
+                opt,
+                embed,
+                self.compact_colors,
+                self.compact_names,
+                self.digits,
+            )?;
+        }
+
+        if !categories.is_empty() {
+            let y = match opt.direction {
+                Direction::Straight => self.imageheight - opt.ypad2() + FRAMEPAD,
+                Direction::Inverted => opt.ypad1() - CATEGORY_BAND,
+            };
+            svg.write_event(Event::Start(BytesStart::new("svg").with_attributes(vec![
+                ("id", &*svg::element_id(embed, "categories")),
+                ("x", &self.container_x),
+                ("width", &self.container_width),
+            ])))?;
+            for &(start_time, end_time, category) in categories {
+                let rect = Rectangle {
+                    x1_pct: start_time as f64 * self.widthpertime_pct,
+                    x1_samples: start_time,
+                    y1: y,
+                    x2_pct: end_time as f64 * self.widthpertime_pct,
+                    x2_samples: end_time,
+                    y2: y + CATEGORY_BAND - FRAMEPAD,
+                };
+                let samples = ((end_time - start_time) as f64 * opt.factor).round() as usize;
+                write_count(&mut self.samples_txt, opt, samples, self.digits);
+                let info = write!(
+                    self.buffer,
+                    "{} ({}, {:.2}%)",
+                    category,
+                    self.samples_txt,
+                    rect.width_pct()
+                );
+
+                svg.write_event(Event::Start(BytesStart::new("g")))?;
+                svg.write_event(Event::Start(BytesStart::new("title")))?;
+                svg.write_event(Event::Text(BytesText::new(&self.buffer[info])))?;
+                svg.write_event(Event::End(BytesEnd::new("title")))?;
+                let color = color::category_color(category);
+                filled_rectangle(
+                    &mut svg,
+                    &mut self.buffer,
+                    &rect,
+                    color,
+                    &mut self.cache_rect,
+                )?;
+                svg.write_event(&self.cache_g_end)?;
+                self.buffer.clear();
+            }
+            svg.write_event(Event::End(BytesEnd::new("svg")))?;
+        }
+
+        if let Some(embed) = embed.filter(|embed| embed.javascript) {
+            svg::write_embedded_script(&mut svg, opt, embed)?;
+        }
+        if let Some(folded) = folded {
+            folded.write(&mut svg, embed)?;
+        }
+        svg.write_event(Event::End(BytesEnd::new("svg")))?;
+        svg.write_event(Event::Eof)?;
 
-    svg.into_inner().flush()?;
-    Ok(())
+        svg.into_inner().flush()?;
+        Ok(())
+    }
 }
 
 #[cfg(feature = "nameattr")]
@@ -751,12 +1635,20 @@ fn write_container_start<'a, W: Write>(
         .func_frameattrs
         .frameattrs_for_func(frame.location.function);
 
+    let link = frame_link(opt, frame);
+
     let mut has_href = false;
     if let Some(frame_attributes) = frame_attributes {
         if frame_attributes.attrs.contains_key("xlink:href") {
             write_container_attributes(cache_a, frame_attributes);
             svg.write_event(cache_a)?;
             has_href = true;
+        } else if let Some(ref url) = link {
+            write_container_attributes(cache_a, frame_attributes);
+            let target = !frame_attributes.attrs.contains_key("target");
+            push_link_attributes(cache_a, url, target);
+            svg.write_event(cache_a)?;
+            has_href = true;
         } else {
             write_container_attributes(cache_g, frame_attributes);
             svg.write_event(cache_g)?;
@@ -764,6 +1656,13 @@ fn write_container_start<'a, W: Write>(
         if let Some(ref t) = frame_attributes.title {
             title = t.as_str();
         }
+    } else if let Some(ref url) = link {
+        if let Event::Start(ref mut c) = cache_a {
+            c.clear_attributes();
+        }
+        push_link_attributes(cache_a, url, true);
+        svg.write_event(cache_a)?;
+        has_href = true;
     } else if let Event::Start(ref mut c) = cache_g {
         c.clear_attributes();
         svg.write_event(cache_g)?;
@@ -774,13 +1673,21 @@ fn write_container_start<'a, W: Write>(
 
 #[cfg(not(feature = "nameattr"))]
 fn write_container_start<'a, W: Write>(
-    _opt: &Options<'_>,
+    opt: &Options<'_>,
     svg: &mut Writer<W>,
-    _cache_a: &mut Event<'_>,
+    cache_a: &mut Event<'_>,
     cache_g: &mut Event<'_>,
-    _frame: &merge::TimedFrame<'_>,
+    frame: &merge::TimedFrame<'_>,
     title: &'a str,
 ) -> quick_xml::Result<(bool, &'a str)> {
+    if let Some(url) = frame_link(opt, frame) {
+        if let Event::Start(ref mut c) = cache_a {
+            c.clear_attributes();
+        }
+        push_link_attributes(cache_a, &url, true);
+        svg.write_event(&cache_a)?;
+        return Ok((true, title));
+    }
     if let Event::Start(ref mut c) = cache_g {
         c.clear_attributes();
         svg.write_event(&cache_g)?;
@@ -789,6 +1696,28 @@ fn write_container_start<'a, W: Write>(
     Ok((false, title))
 }
 
+// The URL of a frame from `Options::frame_links`, if it has one.
+fn frame_link(opt: &Options<'_>, frame: &merge::TimedFrame<'_>) -> Option<String> {
+    let links = opt.frame_links.as_ref()?;
+    if matches!(frame.location.function, "" | "--" | "-") {
+        return None;
+    }
+    links.url_for(deannotate(frame.location.function))
+}
+
+// Links a container, which has to be an `a`, to `url`, opening it outside of the flame graph
+// unless `target` is `false`.
+fn push_link_attributes(event: &mut Event<'_>, url: &str, target: bool) {
+    if let Event::Start(ref mut c) = event {
+        c.push_attribute(("xlink:href", url));
+        if target {
+            c.push_attribute(("target", "_top"));
+        }
+    } else {
+        unreachable!("cache wrapper was of wrong type: {:?}", event);
+    }
+}
+
 /// Writes attributes to the container, container could be g or a
 #[cfg(feature = "nameattr")]
 fn write_container_attributes(event: &mut Event<'_>, frame_attributes: &FrameAttrs) {
@@ -809,7 +1738,7 @@ fn write_container_attributes(event: &mut Event<'_>, frame_attributes: &FrameAtt
 ///
 /// See [`from_lines`] for the expected format of each line.
 ///
-/// The resulting flame graph will be written out to `writer` in SVG format.
+/// The resulting flame graph will be written out to `writer` in the format of [`Options::format`].
 pub fn from_reader<R, W>(opt: &mut Options<'_>, reader: R, writer: W) -> quick_xml::Result<()>
 where
     R: Read,
@@ -822,7 +1751,7 @@ where
 ///
 /// See [`from_lines`] for the expected format of each line.
 ///
-/// The resulting flame graph will be written out to `writer` in SVG format.
+/// The resulting flame graph will be written out to `writer` in the format of [`Options::format`].
 pub fn from_readers<R, W>(opt: &mut Options<'_>, readers: R, writer: W) -> quick_xml::Result<()>
 where
     R: IntoIterator,
@@ -841,18 +1770,29 @@ where
 /// Produce a flame graph from files that contain folded stack lines
 /// and write the result to provided `writer`.
 ///
-/// If files is empty, STDIN will be used as input.
+/// If files is empty, STDIN will be used as input. Several files are combined as
+/// [`Options::multi_input`] says; unless they're merged, they can't include STDIN (`-`).
+/// Compressed input is decompressed first, like by [`Collapse::collapse_file`].
+///
+///   [`Collapse::collapse_file`]: crate::collapse::Collapse::collapse_file
 pub fn from_files<W: Write>(
     opt: &mut Options<'_>,
     files: &[PathBuf],
     writer: W,
 ) -> quick_xml::Result<()> {
-    if files.is_empty() || files.len() == 1 && files[0].to_str() == Some("-") {
+    let separate = match opt.multi_input {
+        MultiInput::Merge => false,
+        MultiInput::PerFile => !files.is_empty(),
+        MultiInput::Differential => true,
+    };
+    if separate {
+        from_separate_files(opt, files, writer)
+    } else if files.is_empty() || files.len() == 1 && files[0].to_str() == Some("-") {
         let stdin = io::stdin();
         let r = BufReader::with_capacity(128 * 1024, stdin.lock());
-        from_reader(opt, r, writer)
+        from_reader(opt, common::decompress(r)?, writer)
     } else if files.len() == 1 {
-        let r = File::open(&files[0]).map_err(quick_xml::Error::Io)?;
+        let r = common::open(&files[0]).map_err(quick_xml::Error::Io)?;
         from_reader(opt, r, writer)
     } else {
         let stdin = io::stdin();
@@ -862,11 +1802,11 @@ pub fn from_files<W: Write>(
             if infile.to_str() == Some("-") {
                 if !stdin_added {
                     let r = BufReader::with_capacity(128 * 1024, stdin.lock());
-                    readers.push(Box::new(r));
+                    readers.push(Box::new(common::decompress(r)?));
                     stdin_added = true;
                 }
             } else {
-                let r = File::open(infile).map_err(quick_xml::Error::Io)?;
+                let r = common::open(infile).map_err(quick_xml::Error::Io)?;
                 readers.push(Box::new(r));
             }
         }
@@ -875,15 +1815,160 @@ pub fn from_files<W: Write>(
     }
 }
 
+// Combines the stacks of files that aren't simply merged, as `opt.multi_input` says.
+fn from_separate_files<W: Write>(
+    opt: &mut Options<'_>,
+    files: &[PathBuf],
+    writer: W,
+) -> quick_xml::Result<()> {
+    let mut folded = Vec::new();
+    match opt.multi_input {
+        MultiInput::Differential if files.len() != 2 => {
+            Err(io::Error::from(Error::InvalidOptions(format!(
+                "differential flame graphs need two input files, but {} were given",
+                files.len()
+            ))))
+        }
+        MultiInput::Differential => {
+            crate::differential::from_files(Default::default(), &files[0], &files[1], &mut folded)
+        }
+        _ => {
+            // Files with the same name, like `run1/perf.folded` and `run2/perf.folded`, are told
+            // apart by a number after the name, like `perf (2)`.
+            let mut prefixes = AHashSet::new();
+            let inputs: Vec<_> = files
+                .iter()
+                .map(|path| {
+                    let name = match path.file_stem() {
+                        Some(stem) => stem.to_string_lossy().replace(';', "_"),
+                        None => path.display().to_string(),
+                    };
+                    let mut prefix = name.clone();
+                    let mut n = 1;
+                    while !prefixes.insert(prefix.clone()) {
+                        n += 1;
+                        prefix = format!("{} ({})", name, n);
+                    }
+                    let input = crate::merge::Input {
+                        prefix: Some(prefix),
+                        ..Default::default()
+                    };
+                    (path, input)
+                })
+                .collect();
+            crate::merge::from_files(&inputs, &mut folded)
+        }
+    }
+    .map_err(quick_xml::Error::Io)?;
+    from_reader(opt, &folded[..], writer)
+}
+
+// Finds the parts of the flame graph that stacks of each category take up, from the category
+// annotations of their leaf frames, merging neighboring parts of the same category.
+fn category_segments<'a>(frames: &[merge::TimedFrame<'a>]) -> Vec<(usize, usize, &'a str)> {
+    let mut segments: Vec<(usize, usize, &str)> = frames
+        .iter()
+        .filter_map(|frame| {
+            let (_, category) = folded::split_category(frame.location.function);
+            Some((frame.start_time, frame.end_time, category?))
+        })
+        .collect();
+    segments.sort_unstable_by_key(|&(start_time, _, _)| start_time);
+    let mut merged: Vec<(usize, usize, &str)> = Vec::with_capacity(segments.len());
+    for (start_time, end_time, category) in segments {
+        match merged.last_mut() {
+            Some(last) if last.1 == start_time && last.2 == category => last.1 = end_time,
+            _ => merged.push((start_time, end_time, category)),
+        }
+    }
+    merged
+}
+
 fn deannotate(f: &str) -> &str {
+    if let (f, Some(_)) = folded::split_category(f) {
+        return deannotate(f);
+    }
+    split_annotation(f).0
+}
+
+// Splits the `_[k]`-style annotation, if any, off a frame.
+fn split_annotation(f: &str) -> (&str, Option<&str>) {
     if f.ends_with(']') {
         if let Some(ai) = f.rfind("_[") {
             if f[ai..].len() == 4 && "kwij".contains(&f[ai + 2..ai + 3]) {
-                return &f[..ai];
+                return (&f[..ai], Some(&f[ai + 2..ai + 3]));
+            }
+        }
+    }
+    (f, None)
+}
+
+// Selects the color of a frame that takes up `width_pct` percent of the flame graph.
+fn frame_color(
+    opt: &mut Options<'_>,
+    frame: &merge::TimedFrame<'_>,
+    width_pct: f64,
+    delta_max: usize,
+    weight_max: f64,
+    mut thread_rng: impl FnMut() -> f32,
+) -> Color {
+    if let Some(ref color_fn) = opt.color_fn {
+        if !matches!(frame.location.function, "--" | "-") {
+            let (function, category) = folded::split_category(frame.location.function);
+            let (name, annotation) = split_annotation(function);
+            let info = FrameInfo {
+                name,
+                annotation,
+                category,
+                depth: frame.location.depth,
+                delta: frame.delta.map(|delta| {
+                    if opt.negate_differentials {
+                        -delta
+                    } else {
+                        delta
+                    }
+                }),
+            };
+            if let Some(color) = color_fn.color(&info) {
+                return color;
             }
         }
     }
-    f
+
+    if frame.location.function == "--" {
+        color::VDGREY
+    } else if frame.location.function == "-" {
+        color::DGREY
+    } else if let Some(rate) = weight_rate(frame) {
+        color::weight_scale(rate, weight_max)
+    } else if opt.color_diffusion {
+        // We want to visually highlight high priority regions for
+        // optimization: wider frames are redder. Typically when optimizing,
+        // a frame that is 50% of width is high priority, so it seems wrong
+        // to give it half the saturation of 100%. So we use sqrt to make
+        // the red dropoff less linear.
+        color::color_scale(((width_pct / 100.0).sqrt() * 2000.0) as isize, 2000)
+    } else if let Some(mut delta) = frame.delta {
+        if opt.negate_differentials {
+            delta = -delta;
+        }
+        color::color_scale(delta, delta_max)
+    } else if let Some(ref mut palette_map) = opt.palette_map {
+        let colors = opt.colors;
+        let hash = opt.hash;
+        let deterministic = opt.deterministic;
+        palette_map.find_color_for(frame.location.function, |name| {
+            color::color(colors, hash, deterministic, name, &mut thread_rng)
+        })
+    } else {
+        color::color(
+            opt.colors,
+            opt.hash,
+            opt.deterministic,
+            frame.location.function,
+            &mut thread_rng,
+        )
+    }
 }
 
 fn filled_rectangle<W: Write>(
@@ -919,10 +2004,216 @@ fn filled_rectangle<W: Write>(
     svg.write_event(cache_rect)
 }
 
+// Writes the rectangle of a frame of a compact flame graph, which has the class of its color and
+// the index of its name instead of its color and title.
+fn compact_rectangle<W: Write>(
+    svg: &mut Writer<W>,
+    buffer: &mut StrStack,
+    rect: &Rectangle,
+    class: usize,
+    name: usize,
+    cache_rect: &mut Event<'_>,
+) -> quick_xml::Result<()> {
+    let x = write!(buffer, "{:.2}%", rect.x1_pct);
+    let y = write_usize(buffer, rect.y1);
+    let width = write!(buffer, "{:.2}%", rect.width_pct());
+    let height = write_usize(buffer, rect.height());
+    let class = write!(buffer, "c{}", class);
+    let x_samples = write_usize(buffer, rect.x1_samples);
+    let width_samples = write_usize(buffer, rect.x2_samples - rect.x1_samples);
+    let name = write_usize(buffer, name);
+
+    if let Event::Empty(bytes_start) = cache_rect {
+        bytes_start.clear_attributes();
+        bytes_start.extend_attributes(args!(
+            "x" => &buffer[x],
+            "y" => &buffer[y],
+            "width" => &buffer[width],
+            "height" => &buffer[height],
+            "class" => &buffer[class],
+            "fg:x" => &buffer[x_samples],
+            "fg:w" => &buffer[width_samples],
+            "fg:n" => &buffer[name]
+        ));
+    } else {
+        unreachable!("cache wrapper was of wrong type: {:?}", cache_rect);
+    }
+    svg.write_event(cache_rect)
+}
+
 fn write_usize(buffer: &mut StrStack, value: usize) -> usize {
     buffer.push(itoa::Buffer::new().format(value))
 }
 
+// The weight of a frame per sample, in weighted flame graphs.
+fn weight_rate(frame: &merge::TimedFrame<'_>) -> Option<f64> {
+    let width = frame.end_time - frame.start_time;
+    frame
+        .weight
+        .filter(|_| width != 0)
+        .map(|weight| weight / width as f64)
+}
+
+// The largest weight per sample of the frames, which gets the strongest color.
+fn weight_max(frames: &[merge::TimedFrame<'_>]) -> f64 {
+    frames.iter().filter_map(weight_rate).fold(0.0, f64::max)
+}
+
+/// Frames next to each other under the same frame that are all left out for being too narrow.
+struct OmittedRun {
+    depth: usize,
+    start_time: usize,
+    end_time: usize,
+    count: usize,
+    delta: Option<isize>,
+    weight: Option<f64>,
+}
+
+impl OmittedRun {
+    fn name(&self) -> String {
+        if self.count == 1 {
+            "[1 frame omitted]".to_string()
+        } else {
+            format!("[{} frames omitted]", self.count)
+        }
+    }
+
+    fn frame<'a>(&self, name: &'a str) -> merge::TimedFrame<'a> {
+        merge::TimedFrame {
+            location: merge::Frame {
+                function: name,
+                depth: self.depth,
+            },
+            start_time: self.start_time,
+            end_time: self.end_time,
+            delta: self.delta,
+            weight: self.weight,
+        }
+    }
+}
+
+// Finds the runs of frames narrower than `minwidth_time` whose parents aren't, which are the
+// frames that are left out along with everything above them.
+fn omitted_runs(frames: &[merge::TimedFrame<'_>], minwidth_time: f64) -> Vec<OmittedRun> {
+    let narrow = |frame: &merge::TimedFrame<'_>| {
+        ((frame.end_time - frame.start_time) as f64) < minwidth_time
+    };
+
+    // The frames at each depth, in order, to find the parents of narrow frames.
+    let mut by_depth: Vec<Vec<&merge::TimedFrame<'_>>> = Vec::new();
+    for frame in frames {
+        let depth = frame.location.depth;
+        if by_depth.len() <= depth {
+            by_depth.resize_with(depth + 1, Vec::new);
+        }
+        by_depth[depth].push(frame);
+    }
+    for level in &mut by_depth {
+        level.sort_unstable_by_key(|frame| frame.start_time);
+    }
+
+    // The narrow frames with wide parents, along with the starts of their parents.
+    let mut tops: Vec<(&merge::TimedFrame<'_>, usize)> = Vec::new();
+    for (depth, level) in by_depth.iter().enumerate().skip(1) {
+        let parents = &by_depth[depth - 1];
+        for &frame in level.iter().filter(|frame| narrow(frame)) {
+            let i = parents.partition_point(|parent| parent.start_time <= frame.start_time);
+            if let Some(parent) = i.checked_sub(1).map(|i| parents[i]) {
+                if !narrow(parent) {
+                    tops.push((frame, parent.start_time));
+                }
+            }
+        }
+    }
+
+    let mut runs: Vec<OmittedRun> = Vec::new();
+    let mut last_parent = None;
+    for (frame, parent) in tops {
+        let depth = frame.location.depth;
+        match runs.last_mut() {
+            Some(run)
+                if run.depth == depth
+                    && last_parent == Some(parent)
+                    && run.end_time == frame.start_time =>
+            {
+                run.end_time = frame.end_time;
+                run.count += 1;
+                if let Some(delta) = frame.delta {
+                    run.delta = Some(run.delta.unwrap_or(0) + delta);
+                }
+                if let Some(weight) = frame.weight {
+                    run.weight = Some(run.weight.unwrap_or(0.0) + weight);
+                }
+            }
+            _ => runs.push(OmittedRun {
+                depth,
+                start_time: frame.start_time,
+                end_time: frame.end_time,
+                count: 1,
+                delta: frame.delta,
+                weight: frame.weight,
+            }),
+        }
+        last_parent = Some(parent);
+    }
+    runs
+}
+
+// The unit of counts for scripts (`plain`, `bytes` or `nanoseconds`), and their label.
+fn count_unit_names<'o>(opt: &'o Options<'_>) -> (&'static str, &'o str) {
+    match opt.count_unit {
+        CountUnit::Samples => ("plain", opt.count_name.as_str()),
+        CountUnit::Bytes => ("bytes", "B"),
+        CountUnit::Nanoseconds => ("nanoseconds", "ns"),
+        CountUnit::Custom(ref unit) => ("plain", unit.as_str()),
+    }
+}
+
+// Writes a weight per sample, like `2.50 instructions/cycles`.
+fn write_weight(weight_txt: &mut String, opt: &Options<'_>, rate: f64, digits: usize) {
+    let (_, label) = count_unit_names(opt);
+    let rate = rate * 10usize.pow(digits as u32) as f64 / opt.factor;
+    weight_txt.clear();
+    weight_txt.push_str(&format!("{:.2} {}/{}", rate, opt.weight_name, label));
+}
+
+// Formats a sample count with `digits` fixed-point fractional digits, and thousands separators,
+// followed by its unit.
+fn write_count(count_txt: &mut String, opt: &Options<'_>, samples: usize, digits: usize) {
+    let scale = 10usize.pow(digits as u32);
+    let (steps, units): (f64, &[&str]) = match opt.count_unit {
+        CountUnit::Bytes => (1024.0, &["B", "KiB", "MiB", "GiB", "TiB"]),
+        CountUnit::Nanoseconds => (1000.0, &["ns", "µs", "ms", "s"]),
+        CountUnit::Samples | CountUnit::Custom(_) => (f64::INFINITY, &[]),
+    };
+
+    count_txt.clear();
+    let mut value = samples as f64 / scale as f64;
+    if value >= steps {
+        // Show the count in the largest unit it's at least one of.
+        let mut unit = 0;
+        while value >= steps && unit + 1 < units.len() {
+            value /= steps;
+            unit += 1;
+        }
+        count_txt.push_str(&format!("{:.2} {}", value, units[unit]));
+        return;
+    }
+
+    let mut whole = num_format::Buffer::default();
+    let _ = whole.write_formatted(&(samples / scale), &Locale::en);
+    count_txt.push_str(whole.as_str());
+    if digits != 0 {
+        count_txt.push_str(&format!(".{:0width$}", samples % scale, width = digits));
+    }
+    count_txt.push(' ');
+    count_txt.push_str(match opt.count_unit {
+        CountUnit::Samples => opt.count_name.as_str(),
+        CountUnit::Custom(ref unit) => unit.as_str(),
+        CountUnit::Bytes | CountUnit::Nanoseconds => units[0],
+    });
+}
+
 #[cfg(test)]
 mod tests {
     use super::{Direction, Options};
@@ -0,0 +1,51 @@
+use std::env;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{self, Command};
+
+use super::Format;
+
+/// The path of a temporary file for a flame graph of the given format, named after this process.
+///
+/// This is where a flame graph can go to be opened with [`open_in_browser`] when there's nowhere
+/// else to write it.
+pub fn temp_path(format: Format) -> PathBuf {
+    env::temp_dir().join(format!(
+        "inferno-flamegraph-{}.{}",
+        process::id(),
+        format.extension()
+    ))
+}
+
+/// Open the file at `path`, like a flame graph, with the browser in the `BROWSER` environment
+/// variable, or else the platform's default program for it.
+///
+/// This waits for the launcher, but not for the browser to close.
+pub fn open_in_browser<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    let path = path.as_ref();
+    let mut command = match env::var_os("BROWSER").filter(|browser| !browser.is_empty()) {
+        Some(browser) => Command::new(browser),
+        None if cfg!(target_os = "windows") => {
+            let mut command = Command::new("cmd");
+            // The first quoted argument of start is the title of the window.
+            command.args(["/C", "start", ""]);
+            command
+        }
+        None if cfg!(target_os = "macos") => Command::new("open"),
+        None => Command::new("xdg-open"),
+    };
+    let status = command.arg(path).status().map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("failed to open {}: {}", path.display(), e),
+        )
+    })?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("failed to open {}: {}", path.display(), status),
+        ))
+    }
+}
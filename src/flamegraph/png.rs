@@ -0,0 +1,33 @@
+use std::io::{self, Write};
+
+#[cfg(feature = "png")]
+use resvg::{tiny_skia, usvg};
+
+#[cfg(not(feature = "png"))]
+use crate::Error;
+
+// Rasterizes the SVG of a flame graph to a PNG image of the same size.
+#[cfg(feature = "png")]
+pub(super) fn write<W: Write>(svg: &[u8], mut writer: W) -> io::Result<()> {
+    let mut options = usvg::Options::default();
+    options.fontdb_mut().load_system_fonts();
+    let tree = usvg::Tree::from_data(svg, &options)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let size = tree.size().to_int_size();
+    let mut pixmap = tiny_skia::Pixmap::new(size.width(), size.height()).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "The flame graph is too large to rasterize",
+        )
+    })?;
+    resvg::render(&tree, tiny_skia::Transform::default(), &mut pixmap.as_mut());
+    let png = pixmap
+        .encode_png()
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    writer.write_all(&png)
+}
+
+#[cfg(not(feature = "png"))]
+pub(super) fn write<W: Write>(_: &[u8], _: W) -> io::Result<()> {
+    Err(Error::UnsupportedFormat("PNG output needs inferno's `png` feature".to_string()).into())
+}
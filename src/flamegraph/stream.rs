@@ -0,0 +1,507 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use log::warn;
+use quick_xml::Writer;
+use str_stack::StrStack;
+
+use super::merge::{self, Frame, TimedFrame};
+use super::{ColorMode, FramePainter, MultiInput, Options};
+use crate::collapse::common;
+use crate::folded::Header;
+use crate::Error;
+
+const READER_CAPACITY: usize = 128 * 1024;
+
+// What each line costs on top of its text while it's being sorted: its place in the `StrStack`
+// and in the sorted order.
+const LINE_OVERHEAD: usize = 3 * std::mem::size_of::<usize>();
+
+// Tells apart the sorted runs of flame graphs that are streamed at the same time.
+static RUNS: AtomicUsize = AtomicUsize::new(0);
+
+/// Configure how [`from_reader_streaming`] bounds its memory use.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct StreamOptions {
+    /// About how many bytes of folded stack lines are sorted in memory at once.
+    ///
+    /// Inputs larger than this are sorted in runs that are written to temporary files, and then
+    /// merged.
+    ///
+    /// Default is 256 MiB.
+    pub memory_limit: usize,
+
+    /// The directory the sorted runs are written to.
+    ///
+    /// Default is `None`, which is the system's temporary directory.
+    pub temp_dir: Option<PathBuf>,
+}
+
+impl Default for StreamOptions {
+    fn default() -> Self {
+        StreamOptions {
+            memory_limit: 256 * 1024 * 1024,
+            temp_dir: None,
+        }
+    }
+}
+
+/// Produce an SVG flame graph from a reader of folded stack lines, with bounded memory.
+///
+/// Unlike [`from_reader`](super::from_reader), which keeps all of the stacks and frames in memory
+/// while it plots them, this sorts the stacks in runs of at most
+/// [`StreamOptions::memory_limit`] bytes that are written to temporary files, then merges the
+/// runs twice: once to measure the flame graph, and once to draw each frame as soon as it ends.
+/// Only the frames of one stack are in memory at a time, so very large profiles can be plotted
+/// on machines with modest RAM. The flame graph is the same as the one `from_reader` draws.
+///
+/// Lines are always sorted, so [`Options::no_sort`] is ignored. Only [`Format::Svg`] is
+/// supported; other formats are errors of kind `Unsupported`. [`Options::reverse_stack_order`],
+/// [`Options::flame_chart`], [`Options::aggregate_pruned`], [`Options::category_band`] and
+/// [`ColorMode::Weight`] need all of the stacks at once, so they are errors of kind
+/// `InvalidInput`.
+///
+/// See [`from_lines`](super::from_lines) for the expected format of each line.
+///
+/// [`Format::Svg`]: super::Format::Svg
+pub fn from_reader_streaming<R, W>(
+    opt: &mut Options<'_>,
+    stream: &StreamOptions,
+    reader: R,
+    writer: W,
+) -> quick_xml::Result<()>
+where
+    R: Read,
+    W: Write,
+{
+    if opt.format != super::Format::Svg {
+        return Err(
+            Error::UnsupportedFormat("only SVG flame graphs can be streamed".to_string()).into(),
+        );
+    }
+    let unsupported = [
+        (opt.reverse_stack_order, "reversed stacks"),
+        (opt.flame_chart, "flame charts"),
+        (opt.aggregate_pruned, "aggregated pruned frames"),
+        (opt.category_band, "category bands"),
+        (opt.color_mode == ColorMode::Weight, "weighted colors"),
+        (opt.embed_folded.is_some(), "embedded folded stacks"),
+    ];
+    if let Some((_, name)) = unsupported.iter().find(|(set, _)| *set) {
+        return Err(Error::InvalidOptions(format!("{} can't be streamed", name)).into());
+    }
+
+    let (comments, sorted, digits) = sort(opt, stream, reader).map_err(quick_xml::Error::Io)?;
+    let header = Header::parse(comments.iter().map(String::as_str));
+    super::with_header(opt, header, |opt| plot(opt, &sorted, digits, writer))
+}
+
+/// Produce an SVG flame graph from files of folded stack lines, with bounded memory.
+///
+/// If files is empty, STDIN will be used as input. The stacks of several files are merged; other
+/// [`Options::multi_input`] modes are errors of kind `InvalidInput`.
+///
+/// See [`from_reader_streaming`] for how memory is bounded.
+pub fn from_files_streaming<W: Write>(
+    opt: &mut Options<'_>,
+    stream: &StreamOptions,
+    files: &[PathBuf],
+    writer: W,
+) -> quick_xml::Result<()> {
+    let separate = match opt.multi_input {
+        MultiInput::Merge => false,
+        MultiInput::PerFile => !files.is_empty(),
+        MultiInput::Differential => true,
+    };
+    if separate {
+        return Err(Error::InvalidOptions("only merged inputs can be streamed".to_string()).into());
+    }
+
+    let stdin = io::stdin();
+    if files.is_empty() {
+        return from_reader_streaming(opt, stream, stdin.lock(), writer);
+    }
+    let mut reader: Box<dyn Read> = Box::new(io::empty());
+    let mut stdin_added = false;
+    for infile in files {
+        if infile.to_str() == Some("-") {
+            if !stdin_added {
+                reader = Box::new(reader.chain(stdin.lock()));
+                stdin_added = true;
+            }
+        } else {
+            let file = common::open(infile).map_err(quick_xml::Error::Io)?;
+            reader = Box::new(reader.chain(file));
+        }
+    }
+    from_reader_streaming(opt, stream, reader, writer)
+}
+
+// Sorts the stacks of `reader`, and finds its header comments and how many fractional digits
+// its sample counts need.
+fn sort<R: Read>(
+    opt: &Options<'_>,
+    stream: &StreamOptions,
+    reader: R,
+) -> io::Result<(Vec<String>, Sorted, usize)> {
+    let mut reader = BufReader::with_capacity(READER_CAPACITY, reader);
+    let mut comments = Vec::new();
+    let mut digits = 0;
+    let mut chunk = StrStack::new();
+    let mut chunk_size = 0;
+    let mut runs = Runs::default();
+    let mut seen_stack = false;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            break;
+        }
+        let trimmed = line.trim();
+        if trimmed.starts_with("# ") {
+            // Only the comments before the first stack make up the header.
+            if !seen_stack {
+                comments.push(trimmed.to_string());
+            }
+            continue;
+        }
+        if trimmed.is_empty() {
+            continue;
+        }
+        seen_stack = true;
+        let trimmed = if opt.base.is_empty() {
+            trimmed
+        } else {
+            match super::rebase(trimmed, &opt.base) {
+                Some(rebased) => rebased,
+                None => continue,
+            }
+        };
+        if opt.fractional_samples {
+            digits = std::cmp::max(digits, merge::fraction_digits(&[trimmed]));
+        }
+
+        chunk.push(trimmed);
+        chunk_size += trimmed.len() + LINE_OVERHEAD;
+        if chunk_size >= stream.memory_limit {
+            runs.write(stream, &chunk)?;
+            chunk.clear();
+            chunk_size = 0;
+        }
+    }
+
+    let sorted = if runs.paths.is_empty() {
+        // Everything fit in memory, so there's no need for temporary files.
+        let order = sorted_order(&chunk);
+        Sorted::Memory(chunk, order)
+    } else {
+        if chunk.len() != 0 {
+            runs.write(stream, &chunk)?;
+        }
+        Sorted::Runs(runs)
+    };
+    Ok((comments, sorted, digits))
+}
+
+// Measures the flame graph in one pass over the sorted stacks, and draws it in another.
+fn plot<W: Write>(
+    opt: &mut Options<'_>,
+    sorted: &Sorted,
+    digits: usize,
+    writer: W,
+) -> quick_xml::Result<()> {
+    // The widest frame at each depth, to find the deepest frame that isn't pruned.
+    let mut widest: Vec<usize> = Vec::new();
+    let mut measure = |frame: TimedFrame<'_>| {
+        let depth = frame.location.depth;
+        if widest.len() <= depth {
+            widest.resize(depth + 1, 0);
+        }
+        widest[depth] = std::cmp::max(widest[depth], frame.end_time - frame.start_time);
+        Ok(())
+    };
+    let mut walk = Walk::new(opt, digits);
+    sorted.for_each(|line| walk.line(line, &mut measure))?;
+    walk.finish(&mut measure)?;
+
+    if walk.stripped_fractional_samples {
+        merge::warn_stripped_fractional_samples();
+    }
+    if walk.ignored != 0 {
+        warn!("Ignored {} lines with invalid format", walk.ignored);
+    }
+
+    let mut svg = if opt.pretty_xml {
+        Writer::new_with_indent(writer, b' ', 4)
+    } else {
+        Writer::new(writer)
+    };
+
+    let time = walk.time;
+    if time == 0 {
+        return super::write_no_stacks(&mut svg, opt, None);
+    }
+
+    // prune blocks that are too narrow
+    let minwidth_time = opt.min_width / (100.0 / time as f64);
+    let depthmax = widest
+        .iter()
+        .rposition(|&width| width as f64 >= minwidth_time)
+        .unwrap_or(0);
+
+    let mut painter = FramePainter::new(opt, time, depthmax, walk.delta_max, 0.0, digits);
+    painter.write_start(opt, &mut svg, None)?;
+
+    let mut walk = Walk::new(opt, digits);
+    let mut paint = |frame: TimedFrame<'_>| {
+        if ((frame.end_time - frame.start_time) as f64) < minwidth_time {
+            Ok(())
+        } else {
+            painter.paint(opt, &mut svg, &frame)
+        }
+    };
+    sorted.for_each(|line| walk.line(line, &mut paint))?;
+    walk.finish(&mut paint)?;
+
+    painter.write_end(opt, svg, None, &[], None)
+}
+
+// The sorted stacks, which can be gone through more than once.
+enum Sorted {
+    // The stacks, and the order they sort in.
+    Memory(StrStack, Vec<usize>),
+    Runs(Runs),
+}
+
+impl Sorted {
+    fn for_each<F>(&self, mut f: F) -> quick_xml::Result<()>
+    where
+        F: FnMut(&str) -> quick_xml::Result<()>,
+    {
+        match self {
+            Sorted::Memory(lines, order) => {
+                for &i in order {
+                    f(&lines[i])?;
+                }
+            }
+            Sorted::Runs(runs) => {
+                for line in runs.merge().map_err(quick_xml::Error::Io)? {
+                    f(&line.map_err(quick_xml::Error::Io)?)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn sorted_order(lines: &StrStack) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..lines.len()).collect();
+    order.sort_unstable_by(|&a, &b| lines[a].cmp(&lines[b]));
+    order
+}
+
+// Sorted runs of stacks in temporary files, which are removed when the runs are dropped.
+#[derive(Default)]
+struct Runs {
+    paths: Vec<PathBuf>,
+}
+
+impl Runs {
+    // Writes the stacks of `chunk` to a new run, sorted.
+    fn write(&mut self, stream: &StreamOptions, chunk: &StrStack) -> io::Result<()> {
+        let dir = stream.temp_dir.clone().unwrap_or_else(env::temp_dir);
+        let path = dir.join(format!(
+            "inferno-stream-{}-{}.folded",
+            process::id(),
+            RUNS.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        self.paths.push(path);
+
+        let mut writer = BufWriter::new(file);
+        for i in sorted_order(chunk) {
+            writer.write_all(chunk[i].as_bytes())?;
+            writer.write_all(b"\n")?;
+        }
+        writer.flush()
+    }
+
+    // Merges the runs into one sorted sequence of stacks.
+    fn merge(&self) -> io::Result<Merge> {
+        let mut merge = Merge {
+            runs: Vec::with_capacity(self.paths.len()),
+            heads: BinaryHeap::with_capacity(self.paths.len()),
+        };
+        for path in &self.paths {
+            let mut run = BufReader::new(File::open(path)?).lines();
+            if let Some(line) = run.next() {
+                merge.heads.push(Reverse((line?, merge.runs.len())));
+            }
+            merge.runs.push(run);
+        }
+        Ok(merge)
+    }
+}
+
+impl Drop for Runs {
+    fn drop(&mut self) {
+        for path in &self.paths {
+            if let Err(e) = fs::remove_file(path) {
+                warn!("Couldn't remove {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+// The stacks of several sorted runs, in order.
+struct Merge {
+    runs: Vec<io::Lines<BufReader<File>>>,
+    // The next stack of each run that has any left, and the index of the run.
+    heads: BinaryHeap<Reverse<(String, usize)>>,
+}
+
+impl Iterator for Merge {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Reverse((line, i)) = self.heads.pop()?;
+        match self.runs[i].next() {
+            Some(Ok(next)) => self.heads.push(Reverse((next, i))),
+            Some(Err(e)) => return Some(Err(e)),
+            None => {}
+        }
+        Some(Ok(line))
+    }
+}
+
+// A frame that hasn't ended yet.
+struct OpenFrame {
+    function: String,
+    start_time: usize,
+    delta: Option<isize>,
+}
+
+// Finds the frames of sorted stacks like `merge::frames`, but hands each one over as soon as it
+// ends instead of collecting them. The frames that are still open are the frames of the last
+// stack, so they're kept by depth.
+struct Walk {
+    open: Vec<OpenFrame>,
+    time: usize,
+    ignored: usize,
+    delta: Option<isize>,
+    delta_max: usize,
+    digits: usize,
+    max_depth: Option<usize>,
+    stripped_fractional_samples: bool,
+}
+
+impl Walk {
+    fn new(opt: &Options<'_>, digits: usize) -> Self {
+        Walk {
+            open: Vec::new(),
+            time: 0,
+            ignored: 0,
+            delta: None,
+            delta_max: 1,
+            digits,
+            max_depth: opt.max_depth,
+            stripped_fractional_samples: false,
+        }
+    }
+
+    // Goes on to the next stack, ending the frames of the last stack that this one doesn't have.
+    fn line<F>(&mut self, line: &str, end: F) -> quick_xml::Result<()>
+    where
+        F: FnMut(TimedFrame<'_>) -> quick_xml::Result<()>,
+    {
+        let mut line = line;
+        let nsamples = match merge::parse_nsamples(
+            &mut line,
+            self.digits,
+            &mut self.stripped_fractional_samples,
+        ) {
+            Some(samples) => samples,
+            None => {
+                self.ignored += 1;
+                return Ok(());
+            }
+        };
+        // See if there's also a differential column present
+        self.delta = merge::parse_nsamples(
+            &mut line,
+            self.digits,
+            &mut self.stripped_fractional_samples,
+        )
+        .map(|original_samples| nsamples as isize - original_samples as isize);
+        if let Some(delta) = self.delta {
+            self.delta_max = std::cmp::max(delta.unsigned_abs(), self.delta_max);
+        }
+        if line.is_empty() {
+            self.ignored += 1;
+            return Ok(());
+        }
+
+        let mut this = merge::stack_frames(line, self.max_depth).peekable();
+        let mut shared_depth = 0;
+        while shared_depth < self.open.len()
+            && this.peek() == Some(&self.open[shared_depth].function.as_str())
+        {
+            this.next();
+            shared_depth += 1;
+        }
+        self.end(shared_depth, end)?;
+
+        while let Some(function) = this.next() {
+            let is_last = this.peek().is_none();
+            self.open.push(OpenFrame {
+                function: function.to_string(),
+                start_time: self.time,
+                delta: match self.delta {
+                    Some(_) if !is_last => Some(0),
+                    d => d,
+                },
+            });
+        }
+        self.time += nsamples;
+        Ok(())
+    }
+
+    // Ends the frames of the last stack.
+    fn finish<F>(&mut self, end: F) -> quick_xml::Result<()>
+    where
+        F: FnMut(TimedFrame<'_>) -> quick_xml::Result<()>,
+    {
+        self.end(0, end)
+    }
+
+    // Ends the open frames from `depth` on, from the shallowest to the deepest.
+    fn end<F>(&mut self, depth: usize, mut end: F) -> quick_xml::Result<()>
+    where
+        F: FnMut(TimedFrame<'_>) -> quick_xml::Result<()>,
+    {
+        for (i, frame) in self.open[depth..].iter().enumerate() {
+            end(TimedFrame {
+                location: Frame {
+                    function: &frame.function,
+                    depth: depth + i,
+                },
+                start_time: frame.start_time,
+                end_time: self.time,
+                delta: frame.delta,
+                weight: None,
+            })?;
+        }
+        self.open.truncate(depth);
+        Ok(())
+    }
+}
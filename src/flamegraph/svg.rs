@@ -3,11 +3,14 @@ use std::cell::RefCell;
 use std::io::prelude::*;
 use std::iter;
 
+use ahash::AHashMap;
 use quick_xml::events::{BytesCData, BytesDecl, BytesEnd, BytesStart, BytesText, Event};
 use quick_xml::Writer;
 use str_stack::StrStack;
 
-use super::{Direction, Options, TextTruncateDirection};
+use super::color::Color;
+use super::json::write_json_string;
+use super::{Direction, EmbedOptions, Options, TextTruncateDirection, Theme};
 
 /// The generic font families should not have quotes around them in the CSS.
 const GENERIC_FONT_FAMILIES: &[&str] = &["cursive", "fantasy", "monospace", "serif", "sans-serif"];
@@ -55,17 +58,39 @@ pub(super) struct StyleOptions<'a> {
     pub(super) strokecolor: Option<String>,
 }
 
+/// The id of an element, prefixed when the flame graph is embedded.
+pub(super) fn element_id<'a>(embed: Option<&EmbedOptions>, name: &'a str) -> Cow<'a, str> {
+    match embed {
+        Some(embed) => Cow::Owned(format!("{}-{}", embed.id_prefix, name)),
+        None => Cow::Borrowed(name),
+    }
+}
+
 pub(super) fn write_header<W>(
     svg: &mut Writer<W>,
     imageheight: usize,
     opt: &Options<'_>,
+    embed: Option<&EmbedOptions>,
 ) -> quick_xml::Result<()>
 where
     W: Write,
 {
+    let imagewidth = opt.image_width.unwrap_or(super::DEFAULT_IMAGE_WIDTH);
+    if let Some(embed) = embed {
+        // A fragment scales to the width of its container, keeping its aspect ratio.
+        return svg.write_event(Event::Start(BytesStart::new("svg").with_attributes(vec![
+            ("id", embed.id_prefix.as_str()),
+            ("class", "inferno-flamegraph"),
+            ("width", "100%"),
+            ("viewBox", &*format!("0 0 {} {}", imagewidth, imageheight)),
+            ("xmlns", "http://www.w3.org/2000/svg"),
+            ("xmlns:xlink", "http://www.w3.org/1999/xlink"),
+            ("xmlns:fg", "http://github.com/jonhoo/inferno"),
+        ])));
+    }
+
     svg.write_event(Event::Decl(BytesDecl::new("1.0", None, Some("no"))))?;
     svg.write_event(Event::DocType(BytesText::from_escaped(r#"svg PUBLIC "-//W3C//DTD SVG 1.1//EN" "http://www.w3.org/Graphics/SVG/1.1/DTD/svg11.dtd""#)))?;
-    let imagewidth = opt.image_width.unwrap_or(super::DEFAULT_IMAGE_WIDTH);
     svg.write_event(Event::Start(BytesStart::new("svg").with_attributes(vec![
         ("version", "1.1"),
         ("width", &*format!("{}", imagewidth)),
@@ -91,15 +116,22 @@ pub(super) fn write_prelude<W>(
     svg: &mut Writer<W>,
     style_options: &StyleOptions,
     opt: &Options<'_>,
+    embed: Option<&EmbedOptions>,
 ) -> quick_xml::Result<()>
 where
     W: Write,
 {
+    let background = element_id(embed, "background");
     svg.write_event(Event::Start(BytesStart::new("defs")))?;
-    svg.write_event(Event::Start(BytesStart::from_content(
-        r#"linearGradient id="background" y1="0" y2="1" x1="0" x2="0""#,
-        "linearGradient".len(),
-    )))?;
+    svg.write_event(Event::Start(
+        BytesStart::new("linearGradient").with_attributes(vec![
+            ("id", &*background),
+            ("y1", "0"),
+            ("y2", "1"),
+            ("x1", "0"),
+            ("x2", "0"),
+        ]),
+    ))?;
     svg.write_event(Event::Empty(BytesStart::new("stop").with_attributes(
         iter::once(("stop-color", &*style_options.bgcolor1)).chain(iter::once(("offset", "5%"))),
     )))?;
@@ -120,61 +152,81 @@ where
     };
 
     let titlesize = &opt.font_size + 5;
+    // Embedded flame graphs scope their rules to their own root element and ids.
+    let scope = match embed {
+        Some(embed) => format!("#{} ", embed.id_prefix),
+        None => String::new(),
+    };
     svg.write_event(Event::Text(BytesText::from_escaped(&format!(
         "
-text {{ font-family:{}; font-size:{}px }}
-#title {{ text-anchor:middle; font-size:{}px; }}
+{}text {{ font-family:{}; font-size:{}px }}
+#{} {{ text-anchor:middle; font-size:{}px; }}
 ",
-        font_type, &opt.font_size, titlesize,
+        scope,
+        font_type,
+        &opt.font_size,
+        element_id(embed, "title"),
+        titlesize,
     ))))?;
     if let Some(strokecolor) = &style_options.strokecolor {
         svg.write_event(Event::Text(BytesText::from_escaped(&format!(
-            "#frames > g > rect {{ stroke:{}; stroke-width:1; }}\n",
+            "#{} > g > rect {{ stroke:{}; stroke-width:1; }}\n",
+            element_id(embed, "frames"),
             strokecolor
         ))))?;
     }
-    svg.write_event(Event::Text(BytesText::from_escaped(include_str!(
-        "flamegraph.css"
-    ))))?;
+    let css = include_str!("flamegraph.css");
+    match embed {
+        Some(embed) => svg.write_event(Event::Text(BytesText::from_escaped(scoped_css(
+            css,
+            &embed.id_prefix,
+        ))))?,
+        None => svg.write_event(Event::Text(BytesText::from_escaped(css)))?,
+    }
+    if opt.theme != Theme::Light {
+        let css = include_str!("flamegraph-dark.css");
+        let mut css = match embed {
+            Some(embed) => scoped_css(css, &embed.id_prefix),
+            None => css.to_string(),
+        };
+        if opt.theme == Theme::Auto {
+            css = format!("@media (prefers-color-scheme: dark) {{\n{}}}\n", css);
+        }
+        svg.write_event(Event::Text(BytesText::from_escaped(&css)))?;
+    }
     svg.write_event(Event::End(BytesEnd::new("style")))?;
 
-    svg.write_event(Event::Start(
-        BytesStart::new("script").with_attributes(iter::once(("type", "text/ecmascript"))),
-    ))?;
-    svg.write_event(Event::CData(BytesCData::new(&format!(
-        "
-        var nametype = {};
-        var fontsize = {};
-        var fontwidth = {};
-        var xpad = {};
-        var inverted = {};
-        var searchcolor = '{}';
-        var fluiddrawing = {};
-        var truncate_text_right = {};\n    ",
-        enquote('\'', &opt.name_type),
-        opt.font_size,
-        opt.font_width,
-        super::XPAD,
-        opt.direction == Direction::Inverted,
-        opt.search_color,
-        opt.image_width.is_none(),
-        opt.text_truncate_direction == TextTruncateDirection::Right
-    ))))?;
-    if !opt.no_javascript {
-        svg.write_event(Event::CData(BytesCData::new(include_str!("flamegraph.js"))))?;
+    // Embedded flame graphs run their script after their frames are written instead, since
+    // there's no load event for them.
+    if embed.is_none() {
+        svg.write_event(Event::Start(
+            BytesStart::new("script").with_attributes(iter::once(("type", "text/ecmascript"))),
+        ))?;
+        svg.write_event(Event::CData(BytesCData::new(script_variables(
+            opt,
+            opt.image_width.is_none(),
+        ))))?;
+        if !opt.no_javascript {
+            svg.write_event(Event::CData(BytesCData::new(include_str!("flamegraph.js"))))?;
+        }
+        svg.write_event(Event::End(BytesEnd::new("script")))?;
     }
-    svg.write_event(Event::End(BytesEnd::new("script")))?;
 
     svg.write_event(Event::Empty(BytesStart::new("rect").with_attributes(vec![
         ("x", "0"),
         ("y", "0"),
         ("width", "100%"),
         ("height", &*format!("{}", style_options.imageheight)),
-        ("fill", "url(#background)"),
+        ("fill", &*format!("url(#{})", background)),
     ])))?;
 
     // We don't care too much about allocating just for the prelude
     let mut buf = StrStack::new();
+    let title_id = element_id(embed, "title");
+    let details_id = element_id(embed, "details");
+    let unzoom_id = element_id(embed, "unzoom");
+    let search_id = element_id(embed, "search");
+    let matched_id = element_id(embed, "matched");
     write_str(
         svg,
         &mut buf,
@@ -182,7 +234,7 @@ text {{ font-family:{}; font-size:{}px }}
             x: Dimension::Percent(50.0),
             y: (opt.font_size * 2) as f64,
             text: (&*opt.title).into(),
-            extra: vec![("id", "title"), ("fill", &style_options.uicolor)],
+            extra: vec![("id", &*title_id), ("fill", &style_options.uicolor)],
         },
     )?;
 
@@ -194,7 +246,7 @@ text {{ font-family:{}; font-size:{}px }}
                 x: Dimension::Percent(50.0),
                 y: (opt.font_size * 4) as f64,
                 text: (&**subtitle).into(),
-                extra: vec![("id", "subtitle")],
+                extra: vec![("id", &*element_id(embed, "subtitle"))],
             },
         )?;
     }
@@ -213,7 +265,7 @@ text {{ font-family:{}; font-size:{}px }}
                 opt.ypad1() - opt.font_size
             } as f64,
             text: " ".into(),
-            extra: vec![("id", "details"), ("fill", &style_options.uicolor)],
+            extra: vec![("id", &*details_id), ("fill", &style_options.uicolor)],
         },
     )?;
 
@@ -225,7 +277,7 @@ text {{ font-family:{}; font-size:{}px }}
             y: (opt.font_size * 2) as f64,
             text: "Reset Zoom".into(),
             extra: vec![
-                ("id", "unzoom"),
+                ("id", &*unzoom_id),
                 ("class", "hide"),
                 ("fill", &style_options.uicolor),
             ],
@@ -239,7 +291,7 @@ text {{ font-family:{}; font-size:{}px }}
             x: Dimension::Pixels(image_width as usize - super::XPAD),
             y: (opt.font_size * 2) as f64,
             text: "Search".into(),
-            extra: vec![("id", "search"), ("fill", &style_options.uicolor)],
+            extra: vec![("id", &*search_id), ("fill", &style_options.uicolor)],
         },
     )?;
 
@@ -250,13 +302,141 @@ text {{ font-family:{}; font-size:{}px }}
             x: Dimension::Pixels(image_width as usize - super::XPAD),
             y: (style_options.imageheight - (opt.ypad2() / 2)) as f64,
             text: " ".into(),
-            extra: vec![("id", "matched"), ("fill", &style_options.uicolor)],
+            extra: vec![("id", &*matched_id), ("fill", &style_options.uicolor)],
         },
     )?;
 
     Ok(())
 }
 
+/// Writes the script of an embedded flame graph, which has to come after its frames.
+pub(super) fn write_embedded_script<W>(
+    svg: &mut Writer<W>,
+    opt: &Options<'_>,
+    embed: &EmbedOptions,
+) -> quick_xml::Result<()>
+where
+    W: Write,
+{
+    // The script is wrapped in a function so that several flame graphs can be on one page.
+    svg.write_event(Event::Start(
+        BytesStart::new("script").with_attributes(iter::once(("type", "text/ecmascript"))),
+    ))?;
+    svg.write_event(Event::CData(BytesCData::new(format!(
+        "(function() {{{}{}\ninit({{ target: document.getElementById({}) }});\n}})();",
+        script_variables(opt, false),
+        include_str!("flamegraph.js"),
+        enquote('\'', &embed.id_prefix)
+    ))))?;
+    svg.write_event(Event::End(BytesEnd::new("script")))
+}
+
+/// Writes the classes of the colors, and the names, that the frames of a compact flame graph
+/// refer to, along with how their script shows their counts.
+pub(super) fn write_compact_tables<W>(
+    svg: &mut Writer<W>,
+    opt: &Options<'_>,
+    embed: Option<&EmbedOptions>,
+    colors: AHashMap<Color, usize>,
+    names: AHashMap<String, usize>,
+    digits: usize,
+) -> quick_xml::Result<()>
+where
+    W: Write,
+{
+    let scope = match embed {
+        Some(embed) => format!("#{} ", embed.id_prefix),
+        None => String::new(),
+    };
+    let mut colors: Vec<_> = colors.into_iter().collect();
+    colors.sort_unstable_by_key(|&(_, class)| class);
+    let mut css = String::from("\n");
+    for (color, class) in colors {
+        css.push_str(&format!(
+            "{}.c{} {{ fill:rgb({},{},{}) }}\n",
+            scope, class, color.r, color.g, color.b
+        ));
+    }
+    svg.write_event(Event::Start(
+        BytesStart::new("style").with_attributes(iter::once(("type", "text/css"))),
+    ))?;
+    svg.write_event(Event::Text(BytesText::from_escaped(&css)))?;
+    svg.write_event(Event::End(BytesEnd::new("style")))?;
+
+    let (unit, label) = super::count_unit_names(opt);
+    let mut names: Vec<_> = names.into_iter().collect();
+    names.sort_unstable_by_key(|&(_, i)| i);
+    let mut data = Vec::new();
+    data.extend_from_slice(br#"{"names":["#);
+    for (i, (name, _)) in names.into_iter().enumerate() {
+        if i > 0 {
+            data.push(b',');
+        }
+        write_json_string(&mut data, &name)?;
+    }
+    write!(
+        data,
+        r#"],"factor":{},"digits":{},"unit":"{}","label":"#,
+        opt.factor, digits, unit
+    )?;
+    write_json_string(&mut data, label)?;
+    data.push(b'}');
+
+    // Names are escaped so that they can't end the section.
+    let id = element_id(embed, "compact");
+    svg.write_event(Event::Start(
+        BytesStart::new("script").with_attributes(vec![("type", "application/json"), ("id", &*id)]),
+    ))?;
+    svg.write_event(Event::CData(BytesCData::new(
+        std::str::from_utf8(&data).expect("names are valid UTF-8"),
+    )))?;
+    svg.write_event(Event::End(BytesEnd::new("script")))
+}
+
+fn script_variables(opt: &Options<'_>, fluiddrawing: bool) -> String {
+    format!(
+        "
+        var nametype = {};
+        var fontsize = {};
+        var fontwidth = {};
+        var xpad = {};
+        var inverted = {};
+        var searchcolor = '{}';
+        var fluiddrawing = {};
+        var truncate_text_right = {};\n    ",
+        enquote('\'', &opt.name_type),
+        opt.font_size,
+        opt.font_width,
+        super::XPAD,
+        opt.direction == Direction::Inverted,
+        opt.search_color,
+        fluiddrawing,
+        opt.text_truncate_direction == TextTruncateDirection::Right
+    )
+}
+
+// Scopes the rules of flamegraph.css to an embedded flame graph.
+fn scoped_css(css: &str, prefix: &str) -> String {
+    let mut css = css.to_string();
+    for id in [
+        "background",
+        "details",
+        "legend",
+        "matched",
+        "search",
+        "subtitle",
+        "title",
+        "unzoom",
+        "frames",
+    ] {
+        css = css.replace(&format!("#{}", id), &format!("#{}-{}", prefix, id));
+    }
+    for class in [".hide", ".parent"] {
+        css = css.replace(class, &format!("#{} {}", prefix, class));
+    }
+    css
+}
+
 pub(super) fn write_str<'a, W, I>(
     svg: &mut Writer<W>,
     buf: &mut StrStack,
@@ -0,0 +1,202 @@
+use std::ops::Index;
+use std::slice;
+
+use super::merge;
+use crate::folded;
+
+/// The merged frames of a flame graph, as an owned tree.
+///
+/// The frames are stored in pre-order: every frame comes right after its parent, or after the
+/// descendants of its previous sibling, and siblings are in the order they're drawn in. So
+/// [`FrameTree::iter`] visits parents before their children, and the descendants of a frame
+/// come right after it (see [`FrameTree::descendants`]).
+///
+/// Frames are identified by their index in the tree, [`FrameNode::id`], and the tree can be
+/// indexed by it. The first frame is the root, which has an empty name and covers all stacks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameTree {
+    nodes: Vec<FrameNode>,
+}
+
+/// A frame of a [`FrameTree`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct FrameNode {
+    /// The index of this frame in its tree.
+    pub id: usize,
+
+    /// The name of the function, which is empty for the root frame.
+    pub name: String,
+
+    /// The number of frames between this one and the root, which has depth 0.
+    pub depth: usize,
+
+    /// The samples of the stacks this frame is in, including those of its callees.
+    pub inclusive: usize,
+
+    /// The samples of the stacks this frame is the leaf of.
+    pub exclusive: usize,
+
+    /// For differentials, the change in the inclusive samples of this frame between the first
+    /// and second sample counts.
+    pub delta: Option<isize>,
+
+    /// The id of the parent of this frame, unless this is the root.
+    pub parent: Option<usize>,
+
+    /// The ids of the children of this frame, in the order they're drawn in.
+    pub children: Vec<usize>,
+
+    // The id after the last descendant of this frame.
+    end: usize,
+}
+
+/// Merge folded stack lines into a [`FrameTree`], the way a flame graph merges them.
+///
+/// See [`super::from_lines`] for the expected format of each line. Comments and lines without
+/// a sample count are skipped, and fractional sample counts are truncated.
+pub fn build_tree<'a, I>(lines: I) -> FrameTree
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let mut lines: Vec<&str> = lines
+        .into_iter()
+        .map(str::trim)
+        .filter(|line| !(line.is_empty() || folded::is_comment(line)))
+        .collect();
+    lines.sort_unstable();
+    let (mut frames, time, _, _) =
+        merge::frames(lines, false, 0, false, None).expect("sorted lines are always merged");
+    // In this order, every frame comes after its parent, and before its later siblings.
+    frames.sort_unstable_by_key(|frame| (frame.start_time, frame.location.depth));
+
+    let mut nodes: Vec<FrameNode> = Vec::with_capacity(std::cmp::max(frames.len(), 1));
+    if frames.is_empty() {
+        nodes.push(FrameNode::new(0, String::new(), 0, time, None, None));
+    }
+    // The ids of the frames from the root to the last one.
+    let mut path: Vec<usize> = Vec::new();
+    for frame in frames {
+        let depth = frame.location.depth;
+        path.truncate(depth);
+        let id = nodes.len();
+        let parent = path.last().copied();
+        let inclusive = frame.end_time - frame.start_time;
+        if let Some(parent) = parent {
+            nodes[parent].children.push(id);
+            nodes[parent].exclusive -= inclusive;
+        }
+        nodes.push(FrameNode::new(
+            id,
+            frame.location.function.to_string(),
+            depth,
+            inclusive,
+            frame.delta,
+            parent,
+        ));
+        path.push(id);
+    }
+
+    // The descendants of a frame end where those of its last child do.
+    for id in (0..nodes.len()).rev() {
+        let end = match nodes[id].children.last() {
+            Some(&last) => nodes[last].end,
+            None => id + 1,
+        };
+        nodes[id].end = end;
+    }
+
+    FrameTree { nodes }
+}
+
+impl FrameNode {
+    fn new(
+        id: usize,
+        name: String,
+        depth: usize,
+        inclusive: usize,
+        delta: Option<isize>,
+        parent: Option<usize>,
+    ) -> Self {
+        FrameNode {
+            id,
+            name,
+            depth,
+            inclusive,
+            exclusive: inclusive,
+            delta,
+            parent,
+            children: Vec::new(),
+            end: id + 1,
+        }
+    }
+}
+
+impl FrameTree {
+    /// The root frame, which covers all stacks.
+    pub fn root(&self) -> &FrameNode {
+        &self.nodes[0]
+    }
+
+    /// The frame with the given id, if there is one.
+    pub fn get(&self, id: usize) -> Option<&FrameNode> {
+        self.nodes.get(id)
+    }
+
+    /// The number of frames in the tree, including the root.
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Whether the tree has only its root frame, because there were no stacks.
+    pub fn is_empty(&self) -> bool {
+        self.nodes.len() == 1
+    }
+
+    /// All frames, parents before their children.
+    pub fn iter(&self) -> slice::Iter<'_, FrameNode> {
+        self.nodes.iter()
+    }
+
+    /// The parent of a frame, unless it's the root.
+    pub fn parent(&self, node: &FrameNode) -> Option<&FrameNode> {
+        node.parent.map(|id| &self.nodes[id])
+    }
+
+    /// The children of a frame, in the order they're drawn in.
+    pub fn children<'t>(&'t self, node: &'t FrameNode) -> impl Iterator<Item = &'t FrameNode> {
+        node.children.iter().map(move |&id| &self.nodes[id])
+    }
+
+    /// The descendants of a frame, parents before their children.
+    pub fn descendants(&self, node: &FrameNode) -> &[FrameNode] {
+        &self.nodes[node.id + 1..node.end]
+    }
+
+    /// The frames from the root to the given frame, including both.
+    pub fn ancestry(&self, node: &FrameNode) -> Vec<&FrameNode> {
+        let mut ancestry = vec![&self.nodes[node.id]];
+        while let Some(parent) = self.parent(ancestry[ancestry.len() - 1]) {
+            ancestry.push(parent);
+        }
+        ancestry.reverse();
+        ancestry
+    }
+}
+
+impl Index<usize> for FrameTree {
+    type Output = FrameNode;
+
+    fn index(&self, id: usize) -> &FrameNode {
+        &self.nodes[id]
+    }
+}
+
+impl<'t> IntoIterator for &'t FrameTree {
+    type Item = &'t FrameNode;
+    type IntoIter = slice::Iter<'t, FrameNode>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
@@ -0,0 +1,90 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// Call `on_change` whenever one of the files at `inputs` changes, until it fails.
+///
+/// The modification times and sizes of the inputs are checked every `interval`, and a file that
+/// appears or disappears counts as a change too. `on_change` isn't called for the inputs as they
+/// are when watching starts, so the first flame graph should be written before.
+///
+/// This only returns the error of `on_change`. It's meant to go with [`write_atomically`], so
+/// that whatever shows the flame graph never sees it half-written:
+///
+/// ```no_run
+/// use std::path::PathBuf;
+/// use std::time::Duration;
+///
+/// use inferno::flamegraph::{self, Options};
+///
+/// fn render(options: &mut Options<'_>) -> quick_xml::Result<()> {
+///     flamegraph::write_atomically("flamegraph.svg", |writer| {
+///         flamegraph::from_files(options, &[PathBuf::from("stacks.folded")], writer)
+///     })
+/// }
+///
+/// let mut options = Options::default();
+/// render(&mut options)?;
+/// flamegraph::watch(&["stacks.folded"], Duration::from_millis(500), || {
+///     if let Err(e) = render(&mut options) {
+///         eprintln!("{}", e);
+///     }
+///     Ok(())
+/// })?;
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn watch<P, F>(inputs: &[P], interval: Duration, mut on_change: F) -> io::Result<()>
+where
+    P: AsRef<Path>,
+    F: FnMut() -> io::Result<()>,
+{
+    // Sizes are compared too, for file systems whose modification times are coarse.
+    let modified = |inputs: &[P]| -> Vec<Option<(SystemTime, u64)>> {
+        inputs
+            .iter()
+            .map(|path| {
+                let metadata = fs::metadata(path).ok()?;
+                Some((metadata.modified().ok()?, metadata.len()))
+            })
+            .collect()
+    };
+    let mut last = modified(inputs);
+    loop {
+        thread::sleep(interval);
+        let current = modified(inputs);
+        if current != last {
+            last = current;
+            on_change()?;
+        }
+    }
+}
+
+/// Write the file at `path` with `write`, like a flame graph, so that it's replaced all at once.
+///
+/// The file is written next to `path` first, and then renamed to it. If `write` fails, the file
+/// at `path` is left as it was.
+pub fn write_atomically<P, F>(path: P, write: F) -> quick_xml::Result<()>
+where
+    P: AsRef<Path>,
+    F: FnOnce(&mut BufWriter<File>) -> quick_xml::Result<()>,
+{
+    let path = path.as_ref();
+    let mut file_name = path.file_name().unwrap_or(path.as_os_str()).to_owned();
+    file_name.push(".tmp");
+    let temp = path.with_file_name(file_name);
+
+    let result = File::create(&temp)
+        .map(BufWriter::new)
+        .map_err(quick_xml::Error::Io)
+        .and_then(|mut writer| {
+            write(&mut writer)?;
+            writer.flush().map_err(quick_xml::Error::Io)
+        })
+        .and_then(|()| fs::rename(&temp, path).map_err(quick_xml::Error::Io));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
@@ -0,0 +1,282 @@
+use std::io::{self, prelude::*};
+
+use log::warn;
+
+/// The version of the folded format that [`Header::write`] writes.
+pub const FORMAT_VERSION: u32 = 1;
+
+// The key of the first header line, which says that the lines after it are a header.
+const VERSION_KEY: &str = "inferno-folded";
+
+// The start of the annotation of the category of a stack.
+const CATEGORY_PREFIX: &str = "_[c:";
+
+/// The metadata of a folded stack file, written as a block of comment lines before the stacks.
+///
+/// A header looks like this:
+///
+/// ```text
+/// # inferno-folded: 1
+/// # tool: inferno-collapse-perf 0.12.0
+/// # event: cycles
+/// # units: samples
+/// # period: 1000000
+/// # start: 4794564.109216
+/// # end: 4794570.891370
+/// ```
+///
+/// The first line gives the version of the format, and every other line is optional. Since lines
+/// that start with `# ` are comments to [`crate::flamegraph`] and the other tools, and are
+/// ignored by the original Perl scripts too, folded files with a header can be read by tools
+/// that don't know about it. Keys that aren't known are ignored, so that newer versions of the
+/// format can add keys.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct Header {
+    /// The version of the format.
+    ///
+    /// Default is [`FORMAT_VERSION`].
+    pub version: u32,
+
+    /// The tool, and its version, that wrote the file, like `inferno-collapse-perf 0.12.0`.
+    ///
+    /// Default is `None`.
+    pub tool: Option<String>,
+
+    /// The name of the event the profile recorded, like `cycles` or `cpu-clock`.
+    ///
+    /// Default is `None`.
+    pub event: Option<String>,
+
+    /// What the counts of the stacks are in, like `samples`, `microseconds` or `bytes`.
+    ///
+    /// Default is `None`.
+    pub units: Option<String>,
+
+    /// The number of events that each sample stands for, like the `-c` of `perf record`.
+    ///
+    /// Profiles with different periods are scaled to the same period when they're compared with
+    /// [`crate::differential`].
+    ///
+    /// Default is `None`.
+    pub period: Option<f64>,
+
+    /// The time, in seconds, of the first sample of the profile.
+    ///
+    /// Default is `None`.
+    pub start: Option<f64>,
+
+    /// The time, in seconds, of the last sample of the profile.
+    ///
+    /// Default is `None`.
+    pub end: Option<f64>,
+}
+
+impl Default for Header {
+    fn default() -> Self {
+        Self {
+            version: FORMAT_VERSION,
+            tool: None,
+            event: None,
+            units: None,
+            period: None,
+            start: None,
+            end: None,
+        }
+    }
+}
+
+impl Header {
+    /// Create a header for the output of the given inferno tool, like `inferno-collapse-perf`,
+    /// with the version of this crate.
+    pub fn for_tool(tool: &str) -> Self {
+        Self {
+            tool: Some(format!("{} {}", tool, env!("CARGO_PKG_VERSION"))),
+            ..Self::default()
+        }
+    }
+
+    /// Parse the header at the start of the given folded stack lines.
+    ///
+    /// Parsing stops at the first line that isn't a comment. `None` is returned if the lines
+    /// don't start with a header.
+    pub fn parse<'a, I>(lines: I) -> Option<Self>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let mut lines = lines
+            .into_iter()
+            .map(str::trim)
+            .take_while(|line| line.starts_with('#'))
+            .filter_map(|line| line[1..].split_once(':'))
+            .map(|(key, value)| (key.trim(), value.trim()));
+
+        let version = match lines.next() {
+            Some((VERSION_KEY, version)) => version.parse().ok()?,
+            _ => return None,
+        };
+        if version > FORMAT_VERSION {
+            warn!(
+                "Folded format version {} is newer than the supported version {}; unknown \
+                 metadata will be ignored",
+                version, FORMAT_VERSION
+            );
+        }
+
+        let mut header = Header {
+            version,
+            ..Header::default()
+        };
+        for (key, value) in lines {
+            let value = Some(value).filter(|value| !value.is_empty());
+            match key {
+                "tool" => header.tool = value.map(str::to_string),
+                "event" => header.event = value.map(str::to_string),
+                "units" => header.units = value.map(str::to_string),
+                "period" => header.period = value.and_then(|value| value.parse().ok()),
+                "start" => header.start = value.and_then(|value| value.parse().ok()),
+                "end" => header.end = value.and_then(|value| value.parse().ok()),
+                _ => {}
+            }
+        }
+        Some(header)
+    }
+
+    /// The time, in seconds, between the first and the last sample of the profile, if the header
+    /// has both.
+    pub fn duration(&self) -> Option<f64> {
+        Some(self.end? - self.start?).filter(|duration| *duration >= 0.0)
+    }
+
+    /// Write the header as comment lines, to go before the folded stack lines.
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writeln!(writer, "# {}: {}", VERSION_KEY, self.version)?;
+        if let Some(ref tool) = self.tool {
+            writeln!(writer, "# tool: {}", tool)?;
+        }
+        if let Some(ref event) = self.event {
+            writeln!(writer, "# event: {}", event)?;
+        }
+        if let Some(ref units) = self.units {
+            writeln!(writer, "# units: {}", units)?;
+        }
+        if let Some(period) = self.period {
+            writeln!(writer, "# period: {}", period)?;
+        }
+        if let Some(start) = self.start {
+            writeln!(writer, "# start: {:.6}", start)?;
+        }
+        if let Some(end) = self.end {
+            writeln!(writer, "# end: {:.6}", end)?;
+        }
+        Ok(())
+    }
+}
+
+/// Whether a line of a folded stack file is a comment, like the lines of a [`Header`].
+pub fn is_comment(line: &str) -> bool {
+    line.trim_start().starts_with("# ")
+}
+
+/// Annotate the leaf frame of a folded stack with the category of the stack, like `gc`, `jit`,
+/// `kernel` or `app`.
+///
+/// The annotation is `_[c:NAME]`, following the `_[k]`-style annotations of frames, so
+/// `main;alloc;collect` becomes `main;alloc;collect_[c:gc]`. [`crate::flamegraph`] shows the
+/// categories as a band under the frames, and can color frames by them.
+pub fn annotate_category(stack: &mut String, category: &str) {
+    stack.push_str(CATEGORY_PREFIX);
+    stack.push_str(category);
+    stack.push(']');
+}
+
+/// The frame that stands for the frames cut from a stack by [`truncate_depth`].
+pub const TRUNCATED_FRAMES: &str = "...";
+
+/// Cut a folded stack (without its count) deeper than `max_depth` frames, replacing its deeper
+/// frames with one [`TRUNCATED_FRAMES`] frame, so `main;a;b;c` becomes `main;a;...` for a
+/// maximum depth of 2. Stacks always keep their root frame.
+pub fn truncate_depth(stack: &mut String, max_depth: usize) {
+    if let Some(cut) = depth_cut(stack, max_depth) {
+        stack.truncate(cut);
+        stack.push_str(TRUNCATED_FRAMES);
+    }
+}
+
+// Where a stack deeper than `max_depth` frames is cut, after the separator of its last kept
+// frame, if it is.
+pub(crate) fn depth_cut(stack: &str, max_depth: usize) -> Option<usize> {
+    let (i, _) = stack.match_indices(';').nth(max_depth.max(1) - 1)?;
+    Some(i + 1)
+}
+
+/// Split the category annotation off a frame, giving the frame without it, and the category if it
+/// had one.
+///
+/// See [`annotate_category`].
+pub fn split_category(frame: &str) -> (&str, Option<&str>) {
+    if let Some(annotated) = frame.strip_suffix(']') {
+        if let Some(i) = annotated.rfind(CATEGORY_PREFIX) {
+            let category = &annotated[i + CATEGORY_PREFIX.len()..];
+            if !category.is_empty() && !category.contains(['[', ']', ';']) {
+                return (&frame[..i], Some(category));
+            }
+        }
+    }
+    (frame, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_round_trip() {
+        let mut header = Header::for_tool("inferno-collapse-perf");
+        header.event = Some("cycles".to_string());
+        header.units = Some("samples".to_string());
+        header.period = Some(1000.0);
+        header.start = Some(10.5);
+        header.end = Some(12.25);
+
+        let mut written = Vec::new();
+        header.write(&mut written).unwrap();
+        let written = String::from_utf8(written).unwrap();
+        assert!(written.starts_with("# inferno-folded: 1\n# tool: inferno-collapse-perf "));
+        assert_eq!(Header::parse(written.lines()), Some(header.clone()));
+        assert_eq!(header.duration(), Some(1.75));
+    }
+
+    #[test]
+    fn header_parse() {
+        let lines = "# inferno-folded: 1\n# units: bytes\n# color: blue\nmain;foo 1\n# event: x";
+        let header = Header::parse(lines.lines()).unwrap();
+        assert_eq!(header.units.as_deref(), Some("bytes"));
+        assert_eq!(header.event, None);
+
+        assert_eq!(Header::parse("# units: bytes\nmain;foo 1".lines()), None);
+        assert_eq!(Header::parse("main;foo 1".lines()), None);
+    }
+
+    #[test]
+    fn categories() {
+        let mut stack = "main;collect".to_string();
+        annotate_category(&mut stack, "gc");
+        assert_eq!(stack, "main;collect_[c:gc]");
+        assert_eq!(split_category("collect_[c:gc]"), ("collect", Some("gc")));
+        assert_eq!(split_category("collect_[k]"), ("collect_[k]", None));
+        assert_eq!(split_category("collect_[c:]"), ("collect_[c:]", None));
+        assert_eq!(split_category("collect"), ("collect", None));
+    }
+
+    #[test]
+    fn truncate() {
+        let mut stack = "main;a;b;c".to_string();
+        truncate_depth(&mut stack, 2);
+        assert_eq!(stack, "main;a;...");
+        truncate_depth(&mut stack, 3);
+        assert_eq!(stack, "main;a;...");
+        truncate_depth(&mut stack, 0);
+        assert_eq!(stack, "main;...");
+    }
+}
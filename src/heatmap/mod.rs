@@ -0,0 +1,282 @@
+use std::fs::File;
+use std::io::{self, prelude::*};
+use std::path::Path;
+
+use log::warn;
+
+const READER_CAPACITY: usize = 128 * 1024;
+
+// The size of each cell, in pixels, and the room left for the title and axes around the cells.
+const CELL_WIDTH: usize = 12;
+const CELL_HEIGHT: usize = 8;
+const MARGIN_TOP: usize = 60;
+const MARGIN_LEFT: usize = 50;
+const MARGIN_BOTTOM: usize = 40;
+const MARGIN_RIGHT: usize = 20;
+
+/// Configure the heatmap.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct Options {
+    /// The number of rows that each second is divided into.
+    ///
+    /// Default is `50`, so each cell covers 20 milliseconds.
+    pub rows: usize,
+
+    /// The title of the heatmap.
+    ///
+    /// Default is `"Heatmap"`.
+    pub title: String,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            rows: 50,
+            title: "Heatmap".to_string(),
+        }
+    }
+}
+
+/// Render a subsecond-offset heatmap, as pioneered by [FlameScope], of the samples in the output
+/// of `perf script`.
+///
+/// Each column of the heatmap is a second of the profile, and each row of a column a part of
+/// that second, so patterns over time, like bursts or periodic work, show up as shapes. Darker
+/// cells had more samples.
+///
+/// Clicking a cell and then another one selects the time range between them, and shows it as the
+/// `--from` and `--to` options of `inferno-collapse-perf`, so a flame graph can be made of just
+/// that range. The times are those `perf script` prints, not the offsets shown on the axes.
+///
+///   [FlameScope]: https://github.com/Netflix/flamescope
+pub fn from_reader<R, W>(opt: &Options, reader: R, writer: W) -> io::Result<()>
+where
+    R: BufRead,
+    W: Write,
+{
+    let mut times = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        if let Some(time) = event_time(&line) {
+            times.push(time);
+        }
+    }
+    if times.is_empty() {
+        warn!("No timestamped samples found; use perf script with the time field");
+    }
+    write_heatmap(opt, &times, writer)
+}
+
+/// Render a heatmap of the samples in a file of `perf script` output, or STDIN if the path is
+/// `None` or `-`.
+///
+/// See [`from_reader`] for details.
+pub fn from_file<P, W>(opt: &Options, path: Option<P>, writer: W) -> io::Result<()>
+where
+    P: AsRef<Path>,
+    W: Write,
+{
+    match path {
+        Some(path) if path.as_ref().to_str() != Some("-") => {
+            let file = File::open(path)?;
+            from_reader(
+                opt,
+                io::BufReader::with_capacity(READER_CAPACITY, file),
+                writer,
+            )
+        }
+        _ => from_reader(opt, io::stdin().lock(), writer),
+    }
+}
+
+// Finds the time of an event line of `perf script`, like:
+//
+//     java 25607 4794564.109216: cycles:
+//     V8 WorkerThread 24636/25607 [000] 94564.109216: cycles:
+//
+// Stack lines are indented, so they are skipped.
+fn event_time(line: &str) -> Option<f64> {
+    if line.is_empty() || line.starts_with(char::is_whitespace) || line.starts_with('#') {
+        return None;
+    }
+    line.split_whitespace()
+        .filter_map(|word| word.strip_suffix(':'))
+        .find(|word| {
+            let (secs, frac) = word.split_once('.').unwrap_or(("", ""));
+            !secs.is_empty()
+                && !frac.is_empty()
+                && secs.bytes().all(|b| b.is_ascii_digit())
+                && frac.bytes().all(|b| b.is_ascii_digit())
+        })
+        .and_then(|time| time.parse().ok())
+}
+
+fn write_heatmap<W: Write>(opt: &Options, times: &[f64], mut writer: W) -> io::Result<()> {
+    let rows = opt.rows.max(1);
+    let start = times.iter().copied().fold(f64::INFINITY, f64::min).floor();
+    let end = times.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let columns = if times.is_empty() {
+        1
+    } else {
+        (end - start) as usize + 1
+    };
+
+    let mut counts = vec![0usize; columns * rows];
+    for &time in times {
+        let offset = time - start;
+        let column = (offset as usize).min(columns - 1);
+        let row = ((offset.fract() * rows as f64) as usize).min(rows - 1);
+        counts[column * rows + row] += 1;
+    }
+    let max = counts.iter().copied().max().unwrap_or(0).max(1);
+
+    let width = MARGIN_LEFT + columns * CELL_WIDTH + MARGIN_RIGHT;
+    let height = MARGIN_TOP + rows * CELL_HEIGHT + MARGIN_BOTTOM;
+    write!(
+        writer,
+        r#"<?xml version="1.0" standalone="no"?>
+<svg version="1.1" width="{width}" height="{height}" viewBox="0 0 {width} {height}" xmlns="http://www.w3.org/2000/svg">
+<style type="text/css">
+text {{ font-family: monospace; font-size: 12px; fill: rgb(0,0,0); }}
+#title {{ text-anchor: middle; font-size: 17px; }}
+rect.cell {{ stroke: rgb(255,255,255); stroke-width: 0.5; cursor: pointer; }}
+rect.cell.selected {{ stroke: rgb(0,0,255); stroke-width: 1.5; }}
+</style>
+<rect x="0" y="0" width="100%" height="100%" fill="rgb(255,255,255)"/>
+<text id="title" x="{middle}" y="24">{title}</text>
+<text id="range" x="{MARGIN_LEFT}" y="46">Click a cell, then another one, to select a time range</text>
+"#,
+        width = width,
+        height = height,
+        middle = width / 2,
+        title = escape(&opt.title),
+        MARGIN_LEFT = MARGIN_LEFT,
+    )?;
+
+    for column in 0..columns {
+        for row in 0..rows {
+            let count = counts[column * rows + row];
+            let from = start + column as f64 + row as f64 / rows as f64;
+            let to = from + 1.0 / rows as f64;
+            // White for no samples, and from light to dark red for more.
+            let (r, g, b) = if count == 0 {
+                (255, 255, 255)
+            } else {
+                let heat = count as f64 / max as f64;
+                (
+                    255 - (heat * 135.0) as u8,
+                    235 - (heat * 235.0) as u8,
+                    225 - (heat * 225.0) as u8,
+                )
+            };
+            writeln!(
+                writer,
+                r#"<rect class="cell" x="{}" y="{}" width="{}" height="{}" fill="rgb({},{},{})" data-from="{:.6}" data-to="{:.6}"><title>{:.3}s: {} samples</title></rect>"#,
+                MARGIN_LEFT + column * CELL_WIDTH,
+                MARGIN_TOP + row * CELL_HEIGHT,
+                CELL_WIDTH,
+                CELL_HEIGHT,
+                r,
+                g,
+                b,
+                from,
+                to,
+                from - start,
+                count
+            )?;
+        }
+    }
+
+    // Label every tenth second on the time axis, and the offsets within a second on the other.
+    let axis_y = MARGIN_TOP + rows * CELL_HEIGHT + 16;
+    for column in (0..columns).step_by(10) {
+        writeln!(
+            writer,
+            r#"<text x="{}" y="{}">{}s</text>"#,
+            MARGIN_LEFT + column * CELL_WIDTH,
+            axis_y,
+            column
+        )?;
+    }
+    for (row, label) in [(0, "0ms"), (rows / 2, "500ms"), (rows - 1, "1000ms")] {
+        writeln!(
+            writer,
+            r#"<text x="2" y="{}">{}</text>"#,
+            MARGIN_TOP + row * CELL_HEIGHT + CELL_HEIGHT,
+            label
+        )?;
+    }
+
+    writer.write_all(HEATMAP_SCRIPT.as_bytes())?;
+    writer.write_all(b"</svg>\n")
+}
+
+// Selects the range between two clicked cells, and shows it as options of inferno-collapse-perf.
+const HEATMAP_SCRIPT: &str = r#"<script type="text/ecmascript"><![CDATA[
+var cells = Array.prototype.slice.call(document.querySelectorAll("rect.cell"));
+var range = document.getElementById("range");
+var first = null;
+cells.forEach(function (cell) {
+    cell.addEventListener("click", function () {
+        if (first === null || first.to !== undefined) {
+            first = { from: parseFloat(cell.getAttribute("data-from")) };
+            first.end = parseFloat(cell.getAttribute("data-to"));
+            select(first.from, first.end);
+            range.textContent = "--from " + first.from.toFixed(6) + " (click another cell to end the range)";
+            return;
+        }
+        var a = parseFloat(cell.getAttribute("data-from"));
+        var b = parseFloat(cell.getAttribute("data-to"));
+        var from = Math.min(first.from, a);
+        var to = Math.max(first.end, b);
+        first.to = to;
+        select(from, to);
+        range.textContent = "inferno-collapse-perf --from " + from.toFixed(6) + " --to " + to.toFixed(6);
+    });
+});
+function select(from, to) {
+    cells.forEach(function (cell) {
+        var f = parseFloat(cell.getAttribute("data-from"));
+        if (f >= from - 1e-9 && f < to - 1e-9) {
+            cell.classList.add("selected");
+        } else {
+            cell.classList.remove("selected");
+        }
+    });
+}
+]]></script>
+"#;
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn event_times() {
+        assert_eq!(
+            event_time("java 25607 4794564.109216: cycles:"),
+            Some(4794564.109216)
+        );
+        assert_eq!(
+            event_time("V8 WorkerThread 24636/25607 [000] 94564.109216: cycles:"),
+            Some(94564.109216)
+        );
+        assert_eq!(
+            event_time("vote   913    72.176760:     257597 cycles:uppp:"),
+            Some(72.17676)
+        );
+        assert_eq!(
+            event_time("\t    ffffffff8106d9a2 do_syscall_64 ([kernel])"),
+            None
+        );
+        assert_eq!(event_time("java 25607 cycles:"), None);
+    }
+}
@@ -0,0 +1,150 @@
+use std::fs::File;
+use std::io::{self, prelude::*};
+use std::path::Path;
+
+use ahash::AHashSet;
+use log::warn;
+
+const READER_CAPACITY: usize = 128 * 1024;
+
+/// The annotation of frames that only off-CPU stacks go through, which
+/// [`MultiPalette::HotCold`](crate::flamegraph::color::MultiPalette::HotCold) colors cool.
+pub const OFF_CPU_ANNOTATION: &str = "_[o]";
+
+/// Configure how the on-CPU and off-CPU profiles are put together.
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub struct Options {
+    /// The frequency, in Hz, that the on-CPU profile was sampled at, like the `-F` of `perf
+    /// record`.
+    ///
+    /// Each on-CPU sample stands for `1 / frequency` seconds.
+    ///
+    /// Default is `99.0`.
+    pub frequency: f64,
+
+    /// The microseconds that each count of the off-CPU profile stands for.
+    ///
+    /// Off-CPU stacks of `inferno-collapse-perf --off-cpu` and most other off-CPU tools are
+    /// counted in microseconds already.
+    ///
+    /// Default is `1.0`.
+    pub off_cpu_scale: f64,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            frequency: 99.0,
+            off_cpu_scale: 1.0,
+        }
+    }
+}
+
+/// Put an on-CPU and an off-CPU profile together into a single hot/cold profile, counted in
+/// microseconds, so that both the time spent running and the time spent blocked can be read from
+/// one flame graph.
+///
+/// The readers are expected to contain folded stack lines with the following whitespace-separated
+/// fields:
+///
+///  - A semicolon-separated list of frame names (e.g., `main;foo;bar;baz`).
+///  - A sample count for the given stack, or the blocked time for off-CPU stacks.
+///
+/// The frames of off-CPU stacks that no on-CPU stack goes through, and the leaf frame of every
+/// off-CPU stack, are annotated with [`OFF_CPU_ANNOTATION`]. Frames that both profiles share are
+/// left as they are, so they merge and show the total time of each function. Render the output
+/// with the `hotcold` palette to color on-CPU frames warm and off-CPU frames cool.
+pub fn from_readers<R1, R2, W>(
+    opt: Options,
+    on_cpu: R1,
+    off_cpu: R2,
+    mut writer: W,
+) -> io::Result<()>
+where
+    R1: Read,
+    R2: Read,
+    W: Write,
+{
+    let on_cpu_input = read_stacks(on_cpu)?;
+    let off_cpu_input = read_stacks(off_cpu)?;
+    let on_cpu: Vec<_> = parse_stacks(&on_cpu_input).collect();
+
+    // Every stack that on-CPU stacks go through, including the partial ones.
+    let mut on_cpu_prefixes = AHashSet::default();
+    for &(stack, _) in &on_cpu {
+        on_cpu_prefixes.extend(stack.match_indices(';').map(|(i, _)| &stack[..i]));
+        on_cpu_prefixes.insert(stack);
+    }
+
+    let on_cpu_scale = 1_000_000.0 / opt.frequency;
+    for &(stack, count) in &on_cpu {
+        writeln!(writer, "{} {}", stack, (count * on_cpu_scale).round())?;
+    }
+
+    let mut annotated = String::new();
+    for (stack, count) in parse_stacks(&off_cpu_input) {
+        annotated.clear();
+        let mut off_cpu_only = false;
+        let mut frames = stack.split(';').peekable();
+        let mut end = 0;
+        while let Some(frame) = frames.next() {
+            end += frame.len();
+            off_cpu_only = off_cpu_only || !on_cpu_prefixes.contains(&stack[..end]);
+            if !annotated.is_empty() {
+                annotated.push(';');
+            }
+            annotated.push_str(frame);
+            if off_cpu_only || frames.peek().is_none() {
+                annotated.push_str(OFF_CPU_ANNOTATION);
+            }
+            end += 1;
+        }
+        writeln!(
+            writer,
+            "{} {}",
+            annotated,
+            (count * opt.off_cpu_scale).round()
+        )?;
+    }
+    Ok(())
+}
+
+/// Put an on-CPU and an off-CPU profile together into a single hot/cold profile.
+///
+/// See [`from_readers`] for the input and output formats.
+pub fn from_files<P1, P2, W>(opt: Options, on_cpu: P1, off_cpu: P2, writer: W) -> io::Result<()>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+    W: Write,
+{
+    let file1 = File::open(on_cpu)?;
+    let reader1 = io::BufReader::with_capacity(READER_CAPACITY, file1);
+    let file2 = File::open(off_cpu)?;
+    let reader2 = io::BufReader::with_capacity(READER_CAPACITY, file2);
+    from_readers(opt, reader1, reader2, writer)
+}
+
+fn read_stacks<R: Read>(mut reader: R) -> io::Result<String> {
+    let mut input = String::new();
+    reader.read_to_string(&mut input)?;
+    Ok(input)
+}
+
+// Parses the stacks and counts of folded lines, skipping the lines that can't be parsed.
+fn parse_stacks(input: &str) -> impl Iterator<Item = (&str, f64)> {
+    input.lines().filter_map(|line| {
+        let line = line.trim_end();
+        if line.is_empty() {
+            return None;
+        }
+        let parsed = line
+            .rsplit_once(' ')
+            .and_then(|(stack, count)| Some((stack.trim_end(), count.parse::<f64>().ok()?)));
+        if parsed.is_none() {
+            warn!("Unable to parse line: {}", line);
+        }
+        parsed
+    })
+}
@@ -0,0 +1,163 @@
+use std::fmt;
+use std::io::{self, prelude::*};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use log::warn;
+
+use crate::collapse::common;
+use crate::folded;
+
+/// What the frames of a leak flame graph are weighted by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Weight {
+    /// The bytes that are still allocated: allocated bytes minus freed bytes. Stacks that freed
+    /// everything they allocated are left out.
+    #[default]
+    Outstanding,
+
+    /// All allocated bytes, freed or not. Stacks that still hold bytes are flagged by their color.
+    Allocated,
+}
+
+impl Weight {
+    /// The valid set of weights (via `FromStr`).
+    pub const VARIANTS: &'static [&'static str] = &["outstanding", "allocated"];
+}
+
+impl FromStr for Weight {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "outstanding" => Ok(Weight::Outstanding),
+            "allocated" => Ok(Weight::Allocated),
+            unknown => Err(format!("unknown leak weight: {}", unknown)),
+        }
+    }
+}
+
+impl fmt::Display for Weight {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Weight::Outstanding => "outstanding",
+            Weight::Allocated => "allocated",
+        })
+    }
+}
+
+/// Configure leak flame graphs.
+#[derive(Debug, Clone, Copy, Default)]
+#[non_exhaustive]
+pub struct Options {
+    /// What frames are weighted by.
+    ///
+    /// Default is [`Weight::Outstanding`].
+    pub weight: Weight,
+}
+
+/// Turn folded stack lines with allocated and freed bytes into the input of a differential flame
+/// graph that shows which stacks still hold memory, and write it to `writer`.
+///
+/// Each line is expected to have the following whitespace-separated fields, as written by the
+/// collapsers of allocation profilers like heaptrack, DHAT or memtrace:
+///
+///  - A semicolon-separated list of frame names (e.g., `main;foo;bar;baz`).
+///  - The bytes allocated by the given stack.
+///  - The bytes of those allocations that were freed.
+///
+/// The output has the two sample counts of differential flame graphs, whose difference is the
+/// bytes each stack still holds, so [`crate::flamegraph`] colors frames redder the more
+/// outstanding bytes they hold, and leaves frames that hold none white. The widths of frames
+/// depend on the [`Weight`].
+///
+/// Stacks that freed more than they allocated, for instance because the profile started after
+/// some allocations were made, are counted as holding nothing.
+pub fn from_lines<'a, I, W>(opt: Options, lines: I, mut writer: W) -> io::Result<()>
+where
+    I: IntoIterator<Item = &'a str>,
+    W: Write,
+{
+    let mut ignored = 0;
+    let mut overfreed = 0;
+    for line in lines {
+        let line = line.trim();
+        if line.is_empty() || folded::is_comment(line) {
+            continue;
+        }
+        let parsed = line.rsplit_once(' ').and_then(|(rest, freed)| {
+            let (stack, allocated) = rest.trim_end().rsplit_once(' ')?;
+            Some((
+                stack.trim_end(),
+                allocated.parse::<u64>().ok()?,
+                freed.parse::<u64>().ok()?,
+            ))
+        });
+        let (stack, allocated, mut freed) = match parsed {
+            Some(parsed) => parsed,
+            None => {
+                ignored += 1;
+                continue;
+            }
+        };
+        if freed > allocated {
+            overfreed += 1;
+            freed = allocated;
+        }
+
+        let outstanding = allocated - freed;
+        match opt.weight {
+            Weight::Outstanding if outstanding == 0 => {}
+            Weight::Outstanding => writeln!(writer, "{} 0 {}", stack, outstanding)?,
+            Weight::Allocated => writeln!(writer, "{} {} {}", stack, freed, allocated)?,
+        }
+    }
+
+    if ignored != 0 {
+        warn!("Ignored {} lines with invalid format", ignored);
+    }
+    if overfreed != 0 {
+        warn!(
+            "{} stacks freed more bytes than they allocated, and are counted as holding nothing",
+            overfreed
+        );
+    }
+    Ok(())
+}
+
+/// Turn folded stack lines with allocated and freed bytes from a reader into the input of a leak
+/// flame graph.
+///
+/// See [`from_lines`] for the expected format of each line.
+pub fn from_reader<R, W>(opt: Options, mut reader: R, writer: W) -> io::Result<()>
+where
+    R: Read,
+    W: Write,
+{
+    let mut input = String::new();
+    reader.read_to_string(&mut input)?;
+    from_lines(opt, input.lines(), writer)
+}
+
+/// Turn folded stack lines with allocated and freed bytes from files into the input of a leak
+/// flame graph.
+///
+/// If files is empty, or is just `-`, STDIN will be used as input.
+pub fn from_files<W: Write>(opt: Options, files: &[PathBuf], writer: W) -> io::Result<()> {
+    let mut input = String::new();
+    if files.is_empty() {
+        io::stdin().lock().read_to_string(&mut input)?;
+    }
+    let mut stdin_added = false;
+    for infile in files {
+        if infile.to_str() == Some("-") {
+            if !stdin_added {
+                io::stdin().lock().read_to_string(&mut input)?;
+                stdin_added = true;
+            }
+        } else {
+            common::open(infile)?.read_to_string(&mut input)?;
+        }
+    }
+    from_lines(opt, input.lines(), writer)
+}
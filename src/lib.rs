@@ -121,12 +121,40 @@
 //! $ inferno-diff-folded folded2 folded1 | inferno-flamegraph --negate > diff1.svg
 //! ```
 //!
+//! ## Exporting to other profile viewers
+//!
+//! Folded stacks can also be handed to other tools through `inferno-export`, which converts them
+//! to other profile formats (see the [`export`] module). For example, to get a profile for
+//! `go tool pprof`:
+//!
+//! ```console
+//! $ inferno-export --format pprof stacks.folded > profile.pb.gz
+//! ```
+//!
+//! ## Symbolizing stacks
+//!
+//! Stacks recorded without symbols have frames like `libfoo.so+0x1a2b`. With the `symbolize`
+//! feature, `inferno-symbolize` rewrites them to function names, using the binaries in the given
+//! directories (see the [`symbolize`](https://docs.rs/inferno/latest/inferno/symbolize/) module):
+//!
+//! ```console
+//! $ inferno-symbolize --binary-dir ./build stacks.folded | inferno-flamegraph > flamegraph.svg
+//! ```
+//!
 //! # Feature flags
 //! All features below are enabled by default
 //! - `cli`: Also builds the `inferno` command-line tools
 //! - `multithreaded`: Enables multithreaded stack-collapsing
 //! - `nameattr`: Allows for adding customizing and adding attributes to the svg of [`flamegraph`]. See the `--nameattr` option for the flamegraph cli
 //!
+//! # WebAssembly
+//!
+//! The library builds for `wasm32-unknown-unknown` with `default-features = false` (the
+//! `nameattr` and `multithreaded` features work there too, but collapsing stays on one thread).
+//! There, files, processes and threads aren't available, so use the functions that work in
+//! memory, like [`render_bytes`], [`Collapse::collapse_bytes`](collapse::Collapse::collapse_bytes)
+//! and [`flamegraph::from_bytes`], which are easy to wrap with `wasm-bindgen`.
+//!
 //! # Development
 //!
 //! This crate was initially developed through [a series of live coding sessions]. If you want to
@@ -166,6 +194,19 @@
 ///   [crate-level documentation]: ../index.html
 pub mod collapse;
 
+/// Tools for anonymizing the frames of folded stack traces, so that profiles of proprietary code
+/// can be shared.
+pub mod anonymize;
+
+/// Loading the options of the tools from an `.inferno.toml` config file, so that options like the
+/// palette, title and minimum width of flame graphs can be shared instead of repeated in every
+/// script.
+///
+/// Only available with the `config` feature, which also makes the options of the flame graph,
+/// differential and collapse tools (de)serializable with Serde.
+#[cfg(feature = "config")]
+pub mod config;
+
 /// Tool for creating an output required to generate differential flame graphs.
 ///
 /// See the [crate-level documentation] for details.
@@ -173,9 +214,115 @@ pub mod collapse;
 ///   [crate-level documentation]: ../index.html
 pub mod differential;
 
+/// C functions for collapsing stacks and plotting flame graphs, so that programs in other languages
+/// can embed Inferno instead of running its binaries.
+///
+/// Only available with the `ffi` feature.
+#[cfg(feature = "ffi")]
+pub mod ffi;
+
+/// Tools for downsampling large folded stack traces to fewer samples or unique stacks.
+pub mod downsample;
+
+/// Tools for exporting folded stack traces to the formats of other profile viewers.
+///
+/// See the [crate-level documentation] for details.
+///
+///   [crate-level documentation]: ../index.html
+pub mod export;
+
+/// The metadata header of folded stack files, which says what the counts of the stacks mean.
+pub mod folded;
+
+/// Tools for putting on-CPU and off-CPU profiles together into hot/cold flame graphs.
+pub mod hotcold;
+
+/// Tools for rendering subsecond-offset heatmaps of the samples in a profile over time.
+pub mod heatmap;
+
+/// Tools for making leak flame graphs of the bytes that stacks allocated and didn't free.
+pub mod leak;
+
+/// Tools for merging several folded stack profiles into one, like those of the same program on
+/// several machines.
+pub mod merge;
+
+/// Tools for rewriting the frames of folded stack traces with a rules file, between collapsing and
+/// plotting.
+pub mod rewrite;
+
+/// Tools for rewriting address-only frames of folded stack traces to function names.
+///
+/// Only available with the `symbolize` feature.
+#[cfg(feature = "symbolize")]
+pub mod symbolize;
+
+#[cfg(any(feature = "inline", feature = "symbolize"))]
+mod dwarf;
+
+/// Tools for splitting folded stack traces by their root frames, like processes or threads.
+pub mod split;
+
+/// Tools for working with the symbols of stack frames.
+pub mod symbols;
+
 /// Tools for producing flame graphs from folded stack traces.
 ///
 /// See the [crate-level documentation] for details.
 ///
 ///   [crate-level documentation]: ../index.html
 pub mod flamegraph;
+
+mod error;
+pub use error::Error;
+
+use std::io;
+
+/// Produce a flame graph straight from the output of a profiler.
+///
+/// The format of the input is guessed as by [`collapse::guess`], the stacks are collapsed into an
+/// in-memory buffer, and that buffer is then plotted with [`flamegraph::from_reader`]. This is the
+/// same as piping `inferno-collapse-guess` into `inferno-flamegraph`, without the intermediate
+/// folded stack file.
+///
+/// Compressed input is decompressed like by
+/// [`Collapse::collapse_file`](collapse::Collapse::collapse_file). Errors from collapsing the input
+/// are returned as [`quick_xml::Error::Io`].
+pub fn render<R, W>(
+    reader: R,
+    writer: W,
+    collapse_options: collapse::guess::Options,
+    flamegraph_options: &mut flamegraph::Options<'_>,
+) -> quick_xml::Result<()>
+where
+    R: io::BufRead,
+    W: io::Write,
+{
+    use collapse::Collapse;
+
+    let reader = collapse::common::decompress(reader).map_err(quick_xml::Error::Io)?;
+    let mut folded = Vec::new();
+    collapse::guess::Folder::from(collapse_options)
+        .collapse(reader, &mut folded)
+        .map_err(quick_xml::Error::Io)?;
+    flamegraph::from_reader(flamegraph_options, &folded[..], writer)
+}
+
+/// Produce a flame graph straight from the output of a profiler in memory, and return it as a
+/// string, like [`render`] does to a writer.
+///
+/// This needs neither files nor threads, so it works in a WebAssembly module that gets its input
+/// from JavaScript. See also [`Collapse::collapse_bytes`](collapse::Collapse::collapse_bytes) and
+/// [`flamegraph::from_bytes`] for the two stages on their own.
+pub fn render_bytes(
+    input: &[u8],
+    collapse_options: collapse::guess::Options,
+    flamegraph_options: &mut flamegraph::Options<'_>,
+) -> quick_xml::Result<String> {
+    use collapse::Collapse;
+
+    let folded = collapse::guess::Folder::from(collapse_options)
+        .collapse_bytes(input)
+        .map_err(quick_xml::Error::Io)?;
+    flamegraph::from_bytes(flamegraph_options, folded.as_bytes())
+}
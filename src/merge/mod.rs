@@ -0,0 +1,115 @@
+use std::collections::BTreeMap;
+use std::io::{self, prelude::*};
+use std::path::Path;
+
+use log::warn;
+
+use crate::collapse::common;
+use crate::folded;
+
+const READER_CAPACITY: usize = 128 * 1024;
+
+/// Configure how the stacks of one of the profiles to merge are adjusted.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct Input {
+    /// The factor to multiply the counts of the profile by, like `0.5` for a profile that was
+    /// sampled at twice the frequency of the others.
+    ///
+    /// Default is `1.0`.
+    pub scale: f64,
+
+    /// A frame to put at the root of every stack of the profile, like the name of the machine it
+    /// was recorded on, so the profiles can be told apart in the merged flame graph.
+    ///
+    /// Default is `None`.
+    pub prefix: Option<String>,
+}
+
+impl Default for Input {
+    fn default() -> Self {
+        Self {
+            scale: 1.0,
+            prefix: None,
+        }
+    }
+}
+
+/// Merge several folded stack profiles into one, summing the counts of the stacks they share.
+///
+/// The readers are expected to contain folded stack lines with the following whitespace-separated
+/// fields:
+///
+///  - A semicolon-separated list of frame names (e.g., `main;foo;bar;baz`).
+///  - A sample count for the given stack.
+///
+/// The counts of each profile are scaled by its [`Input::scale`] before they're added up, and the
+/// merged counts are rounded to whole numbers. Stacks are written sorted, and comment lines, like
+/// those of a [`folded::Header`], are left out.
+pub fn from_readers<I, R, W>(inputs: I, mut writer: W) -> io::Result<()>
+where
+    I: IntoIterator<Item = (R, Input)>,
+    R: Read,
+    W: Write,
+{
+    let mut stacks: BTreeMap<String, f64> = BTreeMap::new();
+    let mut line = String::new();
+    for (reader, input) in inputs {
+        let mut reader = io::BufReader::with_capacity(READER_CAPACITY, reader);
+        loop {
+            line.clear();
+            if reader.read_line(&mut line)? == 0 {
+                break;
+            }
+            let trimmed = line.trim_end();
+            if trimmed.is_empty() || folded::is_comment(trimmed) {
+                continue;
+            }
+            let (stack, count) = match trimmed
+                .rsplit_once(' ')
+                .and_then(|(stack, count)| Some((stack.trim_end(), count.parse::<f64>().ok()?)))
+            {
+                Some(parsed) => parsed,
+                None => {
+                    warn!("Unable to parse line: {}", trimmed);
+                    continue;
+                }
+            };
+            let count = count * input.scale;
+            match input.prefix {
+                Some(ref prefix) => {
+                    *stacks.entry(format!("{};{}", prefix, stack)).or_default() += count;
+                }
+                None => match stacks.get_mut(stack) {
+                    Some(total) => *total += count,
+                    None => {
+                        stacks.insert(stack.to_string(), count);
+                    }
+                },
+            }
+        }
+    }
+
+    for (stack, count) in stacks {
+        let count = count.round();
+        if count > 0.0 {
+            writeln!(writer, "{} {}", stack, count)?;
+        }
+    }
+    Ok(())
+}
+
+/// Merge the folded stack profiles in the given files into one.
+///
+/// See [`from_readers`] for the input and output formats.
+pub fn from_files<P, W>(inputs: &[(P, Input)], writer: W) -> io::Result<()>
+where
+    P: AsRef<Path>,
+    W: Write,
+{
+    let mut readers = Vec::with_capacity(inputs.len());
+    for (path, input) in inputs {
+        readers.push((common::open(path.as_ref())?, input.clone()));
+    }
+    from_readers(readers, writer)
+}
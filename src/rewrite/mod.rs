@@ -0,0 +1,342 @@
+use std::borrow::Cow;
+use std::fs;
+use std::io::{self, prelude::*};
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+
+use crate::collapse::common;
+use crate::folded;
+
+/// A rule of a rules file.
+#[derive(Clone, Debug)]
+enum Rule {
+    /// Replace what the pattern matches in each frame.
+    Rename(Regex, String),
+    /// Leave out the frames the pattern matches.
+    Drop(Regex),
+    /// Merge runs of frames the pattern matches into their first frame, or into a frame with the
+    /// given name.
+    Merge(Regex, Option<String>),
+    /// Put a frame with the given name above the first frame the pattern matches.
+    Category(Regex, String),
+    /// Leave out the stacks that have no frame the pattern matches.
+    Include(Regex),
+    /// Leave out the stacks that have a frame the pattern matches.
+    Exclude(Regex),
+    /// Leave out the frames above the first frame the pattern matches, and the stacks that have
+    /// no such frame.
+    Root(Regex),
+    /// Append the given annotation, like `_[mine]`, to the frames the pattern matches.
+    Annotate(Regex, String),
+}
+
+/// Rules to rewrite the frames of folded stacks with, in the order they're applied in.
+///
+/// Rules files have a rule on each line, made of the rule's name, a regular expression and, for
+/// some rules, the rest of the line:
+///
+/// ```text
+/// # Lines that start with `#`, and empty lines, are ignored.
+/// rename ^std::sys::(\w+)::.* std::sys::$1
+/// drop ^__rust_begin_short_backtrace$
+/// merge ^tokio::runtime:: tokio runtime
+/// category ^serde_json:: [json]
+/// exclude ^pthread_cond_wait$
+/// root ^tokio::runtime::task::raw::poll$
+/// annotate ^my_company:: c:mine
+/// ```
+///
+///  - `rename REGEX REPLACEMENT` replaces what the expression matches in each frame, where the
+///    replacement can refer to groups like `$1`.
+///  - `drop REGEX` leaves out the frames the expression matches.
+///  - `merge REGEX [NAME]` merges runs of consecutive frames the expression matches into the
+///    first frame of the run, or into a frame named `NAME`.
+///  - `category REGEX NAME` puts a frame named `NAME` above the first frame of each stack the
+///    expression matches, so those frames are grouped under it.
+///  - `include REGEX` leaves out the stacks that have no frame the expression matches.
+///  - `exclude REGEX` leaves out the stacks that have a frame the expression matches.
+///  - `root REGEX` re-roots each stack at its first frame the expression matches, leaving out
+///    its callers, and leaves out the stacks that have no such frame, to show everything under a
+///    function.
+///  - `annotate REGEX NAME` annotates the frames the expression matches with `_[NAME]`, like the
+///    `_[k]` of kernel frames, unless they already end in it. Frames annotated with `c:NAME` are
+///    colored by their category with the `category` palette of [`crate::flamegraph`].
+///
+/// Unlike `grep` on the folded lines, `include` and `exclude` match each frame on its own, so
+/// `^` and `$` anchor to the start and end of frames, and the counts are never matched.
+///
+/// Expressions end at the first whitespace, so spaces in them are written as `\s` or `\x20`.
+#[derive(Clone, Debug, Default)]
+pub struct Rules {
+    rules: Vec<Rule>,
+}
+
+impl Rules {
+    /// Parse the rules of a rules file.
+    pub fn parse(rules: &str) -> io::Result<Self> {
+        let mut parsed = Vec::new();
+        for (i, line) in rules.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |message: String| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Invalid rule on line {}: {}", i + 1, message),
+                )
+            };
+
+            let (name, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let rest = rest.trim_start();
+            let (pattern, argument) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            let argument = argument.trim();
+            if pattern.is_empty() {
+                return Err(invalid(format!("{} needs a regular expression", name)));
+            }
+            let pattern = Regex::new(pattern).map_err(|e| invalid(e.to_string()))?;
+
+            let rule = match (name, argument) {
+                ("rename", argument) => Rule::Rename(pattern, argument.to_string()),
+                ("drop", "") => Rule::Drop(pattern),
+                ("include", "") => Rule::Include(pattern),
+                ("exclude", "") => Rule::Exclude(pattern),
+                ("root", "") => Rule::Root(pattern),
+                ("merge", "") => Rule::Merge(pattern, None),
+                ("merge", argument) => Rule::Merge(pattern, Some(argument.to_string())),
+                ("category", "") => return Err(invalid("category needs a name".to_string())),
+                ("category", argument) => Rule::Category(pattern, argument.to_string()),
+                ("annotate", "") => return Err(invalid("annotate needs a name".to_string())),
+                ("annotate", argument) => Rule::Annotate(pattern, format!("_[{}]", argument)),
+                ("drop" | "include" | "exclude" | "root", _) => {
+                    return Err(invalid(format!("{} takes no name", name)))
+                }
+                (unknown, _) => return Err(invalid(format!("unknown rule '{}'", unknown))),
+            };
+            parsed.push(rule);
+        }
+        Ok(Self { rules: parsed })
+    }
+
+    /// Read and parse a rules file.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    // Whether there are no rules, and stacks are left as they are.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    // Applies the rules to the frames of a stack, in order.
+    pub(crate) fn rewrite(&self, stack: &str) -> Vec<String> {
+        let mut frames: Vec<String> = stack.split(';').map(String::from).collect();
+        for rule in &self.rules {
+            match rule {
+                Rule::Rename(pattern, replacement) => {
+                    for frame in &mut frames {
+                        if let Cow::Owned(renamed) =
+                            pattern.replace_all(frame, replacement.as_str())
+                        {
+                            *frame = renamed;
+                        }
+                    }
+                }
+                Rule::Drop(pattern) => frames.retain(|frame| !pattern.is_match(frame)),
+                Rule::Merge(pattern, name) => {
+                    let mut merged: Vec<String> = Vec::with_capacity(frames.len());
+                    let mut in_run = false;
+                    for frame in frames {
+                        let matches = pattern.is_match(&frame);
+                        if matches && in_run {
+                            continue;
+                        }
+                        in_run = matches;
+                        match name {
+                            Some(name) if matches => merged.push(name.clone()),
+                            _ => merged.push(frame),
+                        }
+                    }
+                    frames = merged;
+                }
+                Rule::Category(pattern, name) => {
+                    if let Some(i) = frames.iter().position(|frame| pattern.is_match(frame)) {
+                        frames.insert(i, name.clone());
+                    }
+                }
+                Rule::Include(pattern) => {
+                    if !frames.iter().any(|frame| pattern.is_match(frame)) {
+                        frames.clear();
+                    }
+                }
+                Rule::Exclude(pattern) => {
+                    if frames.iter().any(|frame| pattern.is_match(frame)) {
+                        frames.clear();
+                    }
+                }
+                Rule::Annotate(pattern, annotation) => {
+                    for frame in &mut frames {
+                        if pattern.is_match(frame) && !frame.ends_with(annotation.as_str()) {
+                            frame.push_str(annotation);
+                        }
+                    }
+                }
+                Rule::Root(pattern) => {
+                    match frames.iter().position(|frame| pattern.is_match(frame)) {
+                        Some(i) => frames = frames.split_off(i),
+                        None => frames.clear(),
+                    }
+                }
+            }
+        }
+        frames
+    }
+}
+
+/// Rewrite the frames of folded stack lines with `rules`, and write the result to `writer`.
+///
+/// Each line is expected to have the following whitespace-separated fields:
+///
+///  - A semicolon-separated list of frame names (e.g., `main;foo;bar;baz`).
+///  - A sample count for the given stack, or two for differential inputs.
+///
+/// Lines are written in the order they're read in, so stacks that end up the same aren't merged.
+/// Stacks whose frames are all dropped are left out, and so are lines that don't end in a sample
+/// count. Comment lines, like a [`Header`](crate::folded::Header), are kept as they are.
+pub fn from_lines<'a, I, W>(rules: &Rules, lines: I, mut writer: W) -> io::Result<()>
+where
+    I: IntoIterator<Item = &'a str>,
+    W: Write,
+{
+    for line in lines {
+        let line = line.trim_end();
+        if folded::is_comment(line) {
+            writeln!(writer, "{}", line)?;
+            continue;
+        }
+        let (stack, counts) = match split_counts(line) {
+            Some(parts) => parts,
+            None => continue,
+        };
+        let frames = rules.rewrite(stack);
+        if frames.is_empty() {
+            continue;
+        }
+        writeln!(writer, "{}{}", frames.join(";"), counts)?;
+    }
+    Ok(())
+}
+
+/// Rewrite the frames of folded stack lines from a reader.
+///
+/// See [`from_lines`] for the expected format of each line.
+pub fn from_reader<R, W>(rules: &Rules, mut reader: R, writer: W) -> io::Result<()>
+where
+    R: Read,
+    W: Write,
+{
+    let mut input = String::new();
+    reader.read_to_string(&mut input)?;
+    from_lines(rules, input.lines(), writer)
+}
+
+/// Rewrite the frames of folded stack lines from files, and write the result to the provided
+/// `writer`.
+///
+/// If files is empty, or is just `-`, STDIN will be used as input.
+pub fn from_files<W: Write>(rules: &Rules, files: &[PathBuf], writer: W) -> io::Result<()> {
+    let mut input = String::new();
+    if files.is_empty() {
+        io::stdin().lock().read_to_string(&mut input)?;
+    }
+    let mut stdin_added = false;
+    for infile in files {
+        if infile.to_str() == Some("-") {
+            if !stdin_added {
+                io::stdin().lock().read_to_string(&mut input)?;
+                stdin_added = true;
+            }
+        } else {
+            common::open(infile)?.read_to_string(&mut input)?;
+        }
+    }
+    from_lines(rules, input.lines(), writer)
+}
+
+// Splits a folded line into its stack and its sample counts (with the space before them). There
+// are two counts in the lines of differential flame graphs.
+pub(crate) fn split_counts(line: &str) -> Option<(&str, &str)> {
+    let is_count = |s: &str| s.parse::<f64>().is_ok();
+    let (stack, last) = line.rsplit_once(' ')?;
+    if !is_count(last) {
+        return None;
+    }
+    let stack = match stack.rsplit_once(' ') {
+        Some((rest, count)) if is_count(count) => rest,
+        _ => stack,
+    };
+    Some((stack, &line[stack.len()..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rewrite(rules: &str, stack: &str) -> String {
+        Rules::parse(rules).unwrap().rewrite(stack).join(";")
+    }
+
+    #[test]
+    fn rename() {
+        assert_eq!(
+            rewrite(
+                r"rename ^std::sys::(\w+)::.* std::sys::$1",
+                "main;std::sys::unix::fs::read"
+            ),
+            "main;std::sys::unix"
+        );
+    }
+
+    #[test]
+    fn drop() {
+        assert_eq!(rewrite("drop ^__", "main;__wrap;foo;__inner"), "main;foo");
+    }
+
+    #[test]
+    fn merge() {
+        assert_eq!(
+            rewrite("merge ^tokio::", "main;tokio::a;tokio::b;work;tokio::c"),
+            "main;tokio::a;work;tokio::c"
+        );
+        assert_eq!(
+            rewrite(
+                "merge ^tokio:: tokio runtime",
+                "main;tokio::a;tokio::b;work"
+            ),
+            "main;tokio runtime;work"
+        );
+    }
+
+    #[test]
+    fn category() {
+        assert_eq!(
+            rewrite(
+                "category ^serde_json:: [json]",
+                "main;serde_json::a;serde_json::b"
+            ),
+            "main;[json];serde_json::a;serde_json::b"
+        );
+        assert_eq!(rewrite("category ^serde_json:: [json]", "main"), "main");
+    }
+
+    #[test]
+    fn invalid_rules() {
+        assert!(Rules::parse("rename").is_err());
+        assert!(Rules::parse("category ^foo").is_err());
+        assert!(Rules::parse("drop ^foo bar").is_err());
+        assert!(Rules::parse("explode ^foo").is_err());
+        assert!(Rules::parse("drop (").is_err());
+        assert!(Rules::parse("# just a comment\n\n").is_ok());
+    }
+}
@@ -0,0 +1,149 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, prelude::*};
+use std::path::PathBuf;
+
+use crate::folded;
+use crate::rewrite::split_counts;
+
+const READER_CAPACITY: usize = 128 * 1024;
+
+/// Configure how folded stacks are split.
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub struct Options {
+    /// How many frames at the root of each stack name the root that stacks are split by.
+    ///
+    /// With stacks that begin with a process frame and then a thread frame, `1` splits them by
+    /// process and `2` by thread.
+    ///
+    /// Default is `1`.
+    pub depth: usize,
+
+    /// Leave the root frames that stacks are split by out of the split stacks.
+    ///
+    /// Default is `false`.
+    pub strip_roots: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            depth: 1,
+            strip_roots: false,
+        }
+    }
+}
+
+/// Split folded stack lines by their root frames, and write the stacks of each root to a writer of
+/// its own.
+///
+/// `writer_for` is called with the root frames of a stack, joined by `;`, the first time a stack
+/// with those roots is seen. Stacks with fewer frames than [`Options::depth`] are split by all of
+/// their frames.
+///
+/// Each line is expected to have the following whitespace-separated fields:
+///
+///  - A semicolon-separated list of frame names (e.g., `main;foo;bar;baz`).
+///  - A sample count for the given stack, or two for differential inputs.
+///
+/// Comment lines that come before the stacks, like a [`Header`](crate::folded::Header), are
+/// copied to the top of every output.
+pub fn from_lines<'a, I, W, F>(opt: Options, lines: I, mut writer_for: F) -> io::Result<()>
+where
+    I: IntoIterator<Item = &'a str>,
+    W: Write,
+    F: FnMut(&str) -> io::Result<W>,
+{
+    let mut writers = HashMap::new();
+    let mut comments = Vec::new();
+    for line in lines {
+        let line = line.trim_end();
+        if line.is_empty() {
+            continue;
+        }
+        if folded::is_comment(line) {
+            comments.push(line);
+            continue;
+        }
+        let stack = split_counts(line).map_or(line, |(stack, _)| stack);
+        let split = stack
+            .match_indices(';')
+            .nth(opt.depth.max(1) - 1)
+            .map_or(stack.len(), |(i, _)| i);
+        let root = &stack[..split];
+        let writer = match writers.entry(root.to_string()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let mut writer = writer_for(root)?;
+                for comment in &comments {
+                    writeln!(writer, "{}", comment)?;
+                }
+                entry.insert(writer)
+            }
+        };
+        if !opt.strip_roots {
+            writeln!(writer, "{}", line)?;
+        } else if split < stack.len() {
+            writeln!(writer, "{}", &line[split + 1..])?;
+        }
+    }
+
+    for writer in writers.values_mut() {
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+/// Split folded stack lines from a reader.
+///
+/// See [`from_lines`] for how stacks are split.
+pub fn from_reader<R, W, F>(opt: Options, mut reader: R, writer_for: F) -> io::Result<()>
+where
+    R: Read,
+    W: Write,
+    F: FnMut(&str) -> io::Result<W>,
+{
+    let mut input = String::new();
+    reader.read_to_string(&mut input)?;
+    from_lines(opt, input.lines(), writer_for)
+}
+
+/// Split folded stack lines from files.
+///
+/// If files is empty, or is just `-`, STDIN will be used as input.
+pub fn from_files<W, F>(opt: Options, files: &[PathBuf], writer_for: F) -> io::Result<()>
+where
+    W: Write,
+    F: FnMut(&str) -> io::Result<W>,
+{
+    let mut input = String::new();
+    if files.is_empty() {
+        io::stdin().lock().read_to_string(&mut input)?;
+    }
+    let mut stdin_added = false;
+    for infile in files {
+        if infile.to_str() == Some("-") {
+            if !stdin_added {
+                io::stdin().lock().read_to_string(&mut input)?;
+                stdin_added = true;
+            }
+        } else {
+            let mut r = io::BufReader::with_capacity(READER_CAPACITY, File::open(infile)?);
+            r.read_to_string(&mut input)?;
+        }
+    }
+    from_lines(opt, input.lines(), writer_for)
+}
+
+/// Turn root frames, like `comm`, `comm-pid/tid` or `app;worker-1`, into a name that's safe to use
+/// as a file name on any platform.
+pub fn file_name(root: &str) -> String {
+    root.chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => c,
+            _ => '_',
+        })
+        .collect()
+}
@@ -0,0 +1,59 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use log::{info, warn};
+
+use super::Resolver;
+use crate::dwarf::Binary;
+
+/// Resolves offsets into executables and shared libraries using their DWARF debug info, or
+/// their symbol tables if they have none.
+pub(super) struct Binaries {
+    dirs: Vec<PathBuf>,
+    // Binaries are loaded lazily, and modules that can't be loaded are only tried once.
+    loaded: HashMap<String, Option<Binary>>,
+}
+
+impl Binaries {
+    pub(super) fn new(dirs: Vec<PathBuf>) -> Self {
+        Self {
+            dirs,
+            loaded: HashMap::new(),
+        }
+    }
+}
+
+/// Looks for `module` in each directory, first as a path relative to the directory (like a
+/// sysroot) and then by file name, before trying the module as a path itself.
+pub(super) fn find_binary(dirs: &[PathBuf], module: &str) -> Option<PathBuf> {
+    let relative = module.trim_start_matches('/');
+    let file_name = Path::new(module).file_name()?;
+    dirs.iter()
+        .flat_map(|dir| [dir.join(relative), dir.join(file_name)])
+        .chain(std::iter::once(PathBuf::from(module)))
+        .find(|path| path.is_file())
+}
+
+impl Resolver for Binaries {
+    fn resolve(&mut self, module: &str, offset: u64) -> Option<Vec<String>> {
+        if !self.loaded.contains_key(module) {
+            let binary = match find_binary(&self.dirs, module) {
+                Some(path) => match Binary::load(&path) {
+                    Ok(binary) => Some(binary),
+                    Err(e) => {
+                        warn!("Could not load symbols from {}: {}", path.display(), e);
+                        None
+                    }
+                },
+                None => {
+                    info!("Could not find a binary for module {}", module);
+                    None
+                }
+            };
+            self.loaded.insert(module.to_string(), binary);
+        }
+
+        let binary = self.loaded.get(module)?.as_ref()?;
+        Some(binary.functions_at_offset(offset))
+    }
+}
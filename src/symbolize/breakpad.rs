@@ -0,0 +1,260 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, prelude::*, BufReader};
+use std::path::{Path, PathBuf};
+
+use log::warn;
+
+use super::Resolver;
+
+/// A function in a Breakpad symbol file, with the functions inlined into it.
+#[derive(Debug, Default)]
+struct Function {
+    start: u64,
+    end: u64,
+    name: String,
+    inlines: Vec<Inline>,
+}
+
+/// A call inlined into a function, by its nesting depth, inline origin and address ranges.
+#[derive(Debug)]
+struct Inline {
+    depth: u32,
+    origin: u32,
+    ranges: Vec<(u64, u64)>,
+}
+
+/// The parts of a Breakpad symbol file needed to find function names.
+#[derive(Debug, Default)]
+struct SymbolFile {
+    functions: Vec<Function>,
+    publics: Vec<(u64, String)>,
+    inline_origins: HashMap<u32, String>,
+}
+
+impl SymbolFile {
+    fn parse<R: BufRead>(reader: R) -> io::Result<Self> {
+        let mut file = SymbolFile::default();
+        for line in reader.lines() {
+            let line = line?;
+            if let Some(record) = line.strip_prefix("FUNC ") {
+                let record = record.strip_prefix("m ").unwrap_or(record);
+                let mut fields = record.splitn(4, ' ');
+                let (start, size) = match (fields.next(), fields.next()) {
+                    (Some(start), Some(size)) => (hex(start)?, hex(size)?),
+                    _ => continue,
+                };
+                file.functions.push(Function {
+                    start,
+                    end: start + size,
+                    name: fields.nth(1).unwrap_or_default().to_string(),
+                    inlines: Vec::new(),
+                });
+            } else if let Some(record) = line.strip_prefix("PUBLIC ") {
+                let record = record.strip_prefix("m ").unwrap_or(record);
+                let mut fields = record.splitn(3, ' ');
+                if let (Some(address), Some(name)) = (fields.next(), fields.nth(1)) {
+                    file.publics.push((hex(address)?, name.to_string()));
+                }
+            } else if let Some(record) = line.strip_prefix("INLINE_ORIGIN ") {
+                if let Some((id, name)) = record.split_once(' ') {
+                    file.inline_origins.insert(decimal(id)?, name.to_string());
+                }
+            } else if let Some(record) = line.strip_prefix("INLINE ") {
+                // INLINE depth call_line call_file origin (address size)+
+                let fields: Vec<_> = record.split(' ').collect();
+                if fields.len() < 6 || fields.len() % 2 != 0 {
+                    continue;
+                }
+                let ranges = fields[4..]
+                    .chunks(2)
+                    .map(|range| {
+                        let start = hex(range[0])?;
+                        Ok((start, start + hex(range[1])?))
+                    })
+                    .collect::<io::Result<_>>()?;
+                if let Some(function) = file.functions.last_mut() {
+                    function.inlines.push(Inline {
+                        depth: decimal(fields[0])?,
+                        origin: decimal(fields[3])?,
+                        ranges,
+                    });
+                }
+            }
+        }
+        file.functions.sort_by_key(|function| function.start);
+        file.publics.sort();
+        Ok(file)
+    }
+
+    // Returns the functions at `offset`, outermost first.
+    fn functions(&self, offset: u64) -> Vec<String> {
+        let i = self.functions.partition_point(|f| f.start <= offset);
+        let function = i
+            .checked_sub(1)
+            .map(|i| &self.functions[i])
+            .filter(|function| offset < function.end);
+        let function = match function {
+            Some(function) => function,
+            None => {
+                // Public symbols have no size, so this is only a guess.
+                let i = self.publics.partition_point(|(start, _)| *start <= offset);
+                return i
+                    .checked_sub(1)
+                    .map(|i| vec![self.publics[i].1.clone()])
+                    .unwrap_or_default();
+            }
+        };
+
+        let mut inlines: Vec<_> = function
+            .inlines
+            .iter()
+            .filter(|inline| {
+                inline
+                    .ranges
+                    .iter()
+                    .any(|&(start, end)| start <= offset && offset < end)
+            })
+            .collect();
+        inlines.sort_by_key(|inline| inline.depth);
+
+        let mut names = vec![function.name.clone()];
+        names.extend(
+            inlines
+                .into_iter()
+                .filter_map(|inline| self.inline_origins.get(&inline.origin).cloned()),
+        );
+        names
+    }
+}
+
+/// Resolves offsets into modules using Breakpad symbol files.
+pub(super) struct BreakpadSymbols {
+    // Symbol files keyed by the lowercase file stem of the module they're for.
+    index: HashMap<String, PathBuf>,
+    loaded: HashMap<String, Option<SymbolFile>>,
+}
+
+impl BreakpadSymbols {
+    /// Indexes the symbol files at `paths`, which may be `.sym` files, directories of them, or
+    /// symbol stores laid out as `name/id/name.sym`.
+    pub(super) fn new(paths: &[PathBuf]) -> io::Result<Self> {
+        let mut symbols = Self {
+            index: HashMap::new(),
+            loaded: HashMap::new(),
+        };
+        for path in paths {
+            if path.is_dir() {
+                symbols.index_dir(path, 2)?;
+            } else {
+                symbols.index_file(path)?;
+            }
+        }
+        Ok(symbols)
+    }
+
+    fn index_dir(&mut self, dir: &Path, depth: usize) -> io::Result<()> {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                if depth > 0 {
+                    self.index_dir(&path, depth - 1)?;
+                }
+            } else if path.extension().is_some_and(|extension| extension == "sym") {
+                self.index_file(&path)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Indexes a symbol file by the module in its first line, like
+    // `MODULE Linux x86_64 6EDC6ACDB282125843FD59DA9C81BD830 libxul.so`.
+    fn index_file(&mut self, path: &Path) -> io::Result<()> {
+        let mut first_line = String::new();
+        BufReader::new(File::open(path)?).read_line(&mut first_line)?;
+        match first_line
+            .strip_prefix("MODULE ")
+            .and_then(|record| record.trim_end().splitn(4, ' ').nth(3))
+        {
+            Some(module) => {
+                self.index.insert(module_key(module), path.to_path_buf());
+            }
+            None => warn!("{} is not a Breakpad symbol file", path.display()),
+        }
+        Ok(())
+    }
+}
+
+impl Resolver for BreakpadSymbols {
+    fn resolve(&mut self, module: &str, offset: u64) -> Option<Vec<String>> {
+        let key = module_key(module);
+        let path = self.index.get(&key)?;
+        if !self.loaded.contains_key(&key) {
+            let file = File::open(path)
+                .and_then(|file| SymbolFile::parse(BufReader::new(file)))
+                .map_err(|e| warn!("Could not load symbols from {}: {}", path.display(), e))
+                .ok();
+            self.loaded.insert(key.clone(), file);
+        }
+        let file = self.loaded.get(&key)?.as_ref()?;
+        Some(file.functions(offset))
+    }
+}
+
+// Symbol files are for `xul.pdb` when modules are `xul.dll`, so they're matched by file stem.
+fn module_key(module: &str) -> String {
+    let file_name = module.rsplit(['/', '\\']).next().unwrap_or(module);
+    let stem = file_name
+        .rsplit_once('.')
+        .map_or(file_name, |(stem, _)| stem);
+    stem.to_lowercase()
+}
+
+fn hex(s: &str) -> io::Result<u64> {
+    u64::from_str_radix(s, 16).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn decimal(s: &str) -> io::Result<u32> {
+    s.parse()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SYMBOLS: &str = "\
+MODULE Linux x86_64 6EDC6ACDB282125843FD59DA9C81BD830 libapp.so
+FILE 0 src/main.c
+INLINE_ORIGIN 0 helper
+INLINE_ORIGIN 1 inner_helper
+FUNC 1000 40 0 main
+INLINE 0 12 0 0 1010 10
+INLINE 1 30 0 1 1014 4
+1000 10 10 0
+FUNC m 1080 20 0 run(int)
+PUBLIC 2000 0 exported
+";
+
+    #[test]
+    fn functions() {
+        let file = SymbolFile::parse(SYMBOLS.as_bytes()).unwrap();
+        assert_eq!(file.functions(0x1004), vec!["main"]);
+        assert_eq!(file.functions(0x1010), vec!["main", "helper"]);
+        assert_eq!(
+            file.functions(0x1016),
+            vec!["main", "helper", "inner_helper"]
+        );
+        assert_eq!(file.functions(0x1084), vec!["run(int)"]);
+        assert_eq!(file.functions(0x2010), vec!["exported"]);
+        assert!(file.functions(0x0fff).is_empty());
+    }
+
+    #[test]
+    fn module_keys() {
+        assert_eq!(module_key("xul.dll"), "xul");
+        assert_eq!(module_key("xul.pdb"), "xul");
+        assert_eq!(module_key("/usr/lib/libc.so.6"), "libc.so");
+        assert_eq!(module_key("firefox"), "firefox");
+    }
+}
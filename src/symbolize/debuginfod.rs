@@ -0,0 +1,123 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use addr2line::object::{self, Object};
+use log::{info, warn};
+
+use super::binary::find_binary;
+use super::Resolver;
+use crate::dwarf::Binary;
+
+/// Resolves offsets into stripped binaries using the debug info that debuginfod servers have for
+/// their build ids.
+pub(super) struct Debuginfod {
+    binary_dirs: Vec<PathBuf>,
+    urls: Vec<String>,
+    cache: PathBuf,
+    loaded: HashMap<String, Option<Binary>>,
+}
+
+impl Debuginfod {
+    pub(super) fn new(binary_dirs: Vec<PathBuf>, urls: &[String]) -> Self {
+        Self {
+            binary_dirs,
+            urls: urls
+                .iter()
+                .map(|url| url.trim_end_matches('/').to_string())
+                .collect(),
+            cache: cache_dir(),
+            loaded: HashMap::new(),
+        }
+    }
+
+    // Finds the debug info of a module whose binary has a build id but no debug info of its own.
+    fn find(&self, module: &str) -> Option<PathBuf> {
+        let binary = find_binary(&self.binary_dirs, module)?;
+        let data = fs::read(binary).ok()?;
+        let file = object::File::parse(&*data).ok()?;
+        if file.section_by_name(".debug_info").is_some() {
+            // Binaries with debug info are left to `Binaries`.
+            return None;
+        }
+        let build_id = hex(file.build_id().ok()??);
+
+        let path = self.cache.join(&build_id).join("debuginfo");
+        if path.is_file() {
+            return Some(path);
+        }
+        self.urls
+            .iter()
+            .find_map(|url| match download(url, &build_id, &path) {
+                Ok(()) => Some(path.clone()),
+                Err(e) => {
+                    info!(
+                        "Could not get debug info for {} from {}: {}",
+                        module, url, e
+                    );
+                    None
+                }
+            })
+    }
+}
+
+impl Resolver for Debuginfod {
+    fn resolve(&mut self, module: &str, offset: u64) -> Option<Vec<String>> {
+        if !self.loaded.contains_key(module) {
+            let binary = self
+                .find(module)
+                .and_then(|path| match Binary::load(&path) {
+                    Ok(binary) => Some(binary),
+                    Err(e) => {
+                        warn!("Could not load symbols from {}: {}", path.display(), e);
+                        None
+                    }
+                });
+            self.loaded.insert(module.to_string(), binary);
+        }
+
+        let binary = self.loaded.get(module)?.as_ref()?;
+        Some(binary.functions_at_offset(offset))
+    }
+}
+
+// Downloads the debug info with the given build id to `path`.
+fn download(url: &str, build_id: &str, path: &Path) -> io::Result<()> {
+    let url = format!("{}/buildid/{}/debuginfo", url, build_id);
+    info!("Downloading {}", url);
+    let response = ureq::get(&url)
+        .call()
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+    fs::create_dir_all(path.parent().expect("cache paths have parents"))?;
+    // Download next to the debug info first, so that an interrupted download isn't mistaken for it.
+    let partial = path.with_extension("partial");
+    io::copy(&mut response.into_reader(), &mut File::create(&partial)?)?;
+    fs::rename(&partial, path)
+}
+
+// The cache of debuginfod clients, shared with tools like gdb and perf.
+fn cache_dir() -> PathBuf {
+    if let Some(path) = std::env::var_os("DEBUGINFOD_CACHE_PATH") {
+        return PathBuf::from(path);
+    }
+    std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+        .unwrap_or_else(std::env::temp_dir)
+        .join("debuginfod_client")
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_ids() {
+        assert_eq!(hex(&[0x0a, 0xbc, 0x12, 0xff]), "0abc12ff");
+    }
+}
@@ -0,0 +1,314 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, prelude::*};
+use std::path::PathBuf;
+
+use log::info;
+
+mod binary;
+mod breakpad;
+#[cfg(feature = "debuginfod")]
+mod debuginfod;
+#[cfg(feature = "pdb")]
+mod pdb;
+#[cfg(feature = "sourcemap")]
+pub(crate) mod source_map;
+
+use self::binary::Binaries;
+use self::breakpad::BreakpadSymbols;
+#[cfg(feature = "sourcemap")]
+use self::source_map::SourceMaps;
+
+const READER_CAPACITY: usize = 128 * 1024;
+
+/// Configure the symbolization.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Options {
+    /// Directories to look for the binaries named in `module+0xOFFSET` frames in.
+    ///
+    /// Each module is looked for as a path relative to each directory (like a sysroot), then by
+    /// its file name in each directory, and then as a path of its own.
+    ///
+    /// Default is no directories.
+    pub binary_dirs: Vec<PathBuf>,
+
+    /// The binary that bare addresses, like `0x4011d6`, are offsets into.
+    ///
+    /// Default is `None`, which leaves bare addresses as they are.
+    pub executable: Option<PathBuf>,
+
+    /// Don't expand frames into the functions that were inlined into them; only keep the
+    /// function the code was inlined into.
+    ///
+    /// Default is `false`.
+    pub no_inline: bool,
+
+    /// Breakpad symbol files (`.sym`), directories of them, or symbol stores laid out as
+    /// `module/id/module.sym`, like those of crash reporters.
+    ///
+    /// Symbol files are matched to modules by the module name in their `MODULE` record, ignoring
+    /// the extension, so `xul.pdb` symbols are used for `xul.dll` frames. They're used before
+    /// any binaries or PDBs.
+    ///
+    /// Default is no symbol files.
+    pub breakpad_symbols: Vec<PathBuf>,
+
+    /// Source maps of minified JavaScript, or directories of `.map` files.
+    ///
+    /// Frames like `t.a.onClick (https://example.com/app.min.js:1:2345)` are rewritten to the
+    /// original function name and location when there's a source map for the script, matched by
+    /// its file name. Only available with the `sourcemap` feature.
+    ///
+    /// Default is no source maps.
+    #[cfg(feature = "sourcemap")]
+    pub source_maps: Vec<PathBuf>,
+
+    /// Where to find the PDBs of Windows modules, in the syntax of `_NT_SYMBOL_PATH`: `;`-separated
+    /// directories, and `srv*CACHE*URL` entries for symbol servers, like
+    /// `srv*C:\symbols*https://msdl.microsoft.com/download/symbols`.
+    ///
+    /// The PDBs are also looked for in [`Options::binary_dirs`]. Only available with the `pdb`
+    /// feature.
+    ///
+    /// Default is `""`.
+    #[cfg(feature = "pdb")]
+    pub symbol_path: String,
+
+    /// debuginfod servers to fetch the debug info of stripped binaries from, like
+    /// `https://debuginfod.elfutils.org/`.
+    ///
+    /// Binaries found in [`Options::binary_dirs`] (or at the path of the module) that have a
+    /// build id but no debug info are looked up by their build id. Downloads are kept in the
+    /// cache that other debuginfod clients use, `$DEBUGINFOD_CACHE_PATH` or
+    /// `~/.cache/debuginfod_client`. Only available with the `debuginfod` feature.
+    ///
+    /// Default is no servers.
+    #[cfg(feature = "debuginfod")]
+    pub debuginfod_urls: Vec<String>,
+}
+
+/// A source of function names for offsets into modules.
+trait Resolver {
+    /// Returns the functions at `offset` into `module`, outermost first, or `None` if the module
+    /// isn't known to this resolver.
+    fn resolve(&mut self, module: &str, offset: u64) -> Option<Vec<String>>;
+}
+
+/// Rewrites the address-only frames of folded stacks to function names.
+struct Symbolizer<'a> {
+    opt: &'a Options,
+    resolvers: Vec<Box<dyn Resolver>>,
+    #[cfg(feature = "sourcemap")]
+    source_maps: SourceMaps,
+    // Symbolized frames, or `None` for frames that couldn't be symbolized.
+    cache: HashMap<String, Option<String>>,
+}
+
+impl<'a> Symbolizer<'a> {
+    fn new(opt: &'a Options) -> io::Result<Self> {
+        let mut resolvers: Vec<Box<dyn Resolver>> = Vec::new();
+        if !opt.breakpad_symbols.is_empty() {
+            resolvers.push(Box::new(BreakpadSymbols::new(&opt.breakpad_symbols)?));
+        }
+        #[cfg(feature = "pdb")]
+        resolvers.push(Box::new(self::pdb::Pdbs::new(
+            opt.binary_dirs.clone(),
+            &opt.symbol_path,
+        )));
+        #[cfg(feature = "debuginfod")]
+        if !opt.debuginfod_urls.is_empty() {
+            resolvers.push(Box::new(self::debuginfod::Debuginfod::new(
+                opt.binary_dirs.clone(),
+                &opt.debuginfod_urls,
+            )));
+        }
+        resolvers.push(Box::new(Binaries::new(opt.binary_dirs.clone())));
+
+        Ok(Self {
+            opt,
+            resolvers,
+            #[cfg(feature = "sourcemap")]
+            source_maps: SourceMaps::load(&opt.source_maps)?,
+            cache: HashMap::new(),
+        })
+    }
+
+    fn symbolize<'f>(&mut self, frame: &'f str) -> Cow<'f, str> {
+        if let Some(symbolized) = self.cache.get(frame) {
+            return match symbolized {
+                Some(symbolized) => Cow::Owned(symbolized.clone()),
+                None => Cow::Borrowed(frame),
+            };
+        }
+
+        let symbolized = self.resolve(frame);
+        self.cache.insert(frame.to_string(), symbolized.clone());
+        match symbolized {
+            Some(symbolized) => Cow::Owned(symbolized),
+            None => Cow::Borrowed(frame),
+        }
+    }
+
+    fn resolve(&mut self, frame: &str) -> Option<String> {
+        #[cfg(feature = "sourcemap")]
+        if let Some(resolved) = self.source_maps.resolve(frame) {
+            return Some(resolved);
+        }
+
+        let opt = self.opt;
+        let (module, offset) = parse_frame(frame)?;
+        let executable;
+        let module = match module {
+            Some(module) => module,
+            None => {
+                executable = opt.executable.as_ref()?.to_string_lossy();
+                &executable
+            }
+        };
+
+        let functions = self.resolvers.iter_mut().find_map(|resolver| {
+            resolver
+                .resolve(module, offset)
+                .filter(|functions| !functions.is_empty())
+        })?;
+        if opt.no_inline {
+            functions.into_iter().next()
+        } else {
+            Some(functions.join(";"))
+        }
+    }
+}
+
+/// Symbolize the frames of folded stack lines, and write the result to `writer`.
+///
+/// Frames like `libfoo.so+0x1a2b`, an offset into a module, and `0x1a2b`, an offset into
+/// [`Options::executable`], are replaced by the function at that offset, and by the functions
+/// that were inlined there unless [`Options::no_inline`] is set. JavaScript frames are rewritten
+/// with the source maps in `Options::source_maps`. All other frames, including those of modules
+/// that can't be found, are left as they are.
+pub fn from_lines<'a, I, W>(opt: &Options, lines: I, mut writer: W) -> io::Result<()>
+where
+    I: IntoIterator<Item = &'a str>,
+    W: Write,
+{
+    let mut symbolizer = Symbolizer::new(opt)?;
+    for line in lines {
+        let (stack, counts) = match split_counts(line) {
+            Some(parts) => parts,
+            None => {
+                writeln!(writer, "{}", line)?;
+                continue;
+            }
+        };
+        for (i, frame) in stack.split(';').enumerate() {
+            if i > 0 {
+                writer.write_all(b";")?;
+            }
+            writer.write_all(symbolizer.symbolize(frame).as_bytes())?;
+        }
+        writeln!(writer, "{}", counts)?;
+    }
+
+    let symbolized = symbolizer.cache.values().filter(|s| s.is_some()).count();
+    info!(
+        "Symbolized {} of {} distinct frames",
+        symbolized,
+        symbolizer.cache.len()
+    );
+    Ok(())
+}
+
+/// Symbolize the frames of folded stack lines from a reader.
+///
+/// See [`from_lines`] for which frames are symbolized.
+pub fn from_reader<R, W>(opt: &Options, mut reader: R, writer: W) -> io::Result<()>
+where
+    R: Read,
+    W: Write,
+{
+    let mut input = String::new();
+    reader.read_to_string(&mut input)?;
+    from_lines(opt, input.lines(), writer)
+}
+
+/// Symbolize the frames of folded stack lines from files, and write the result to the provided
+/// `writer`.
+///
+/// If files is empty, or is just `-`, STDIN will be used as input.
+pub fn from_files<W: Write>(opt: &Options, files: &[PathBuf], writer: W) -> io::Result<()> {
+    let mut input = String::new();
+    if files.is_empty() {
+        io::stdin().lock().read_to_string(&mut input)?;
+    }
+    let mut stdin_added = false;
+    for infile in files {
+        if infile.to_str() == Some("-") {
+            if !stdin_added {
+                io::stdin().lock().read_to_string(&mut input)?;
+                stdin_added = true;
+            }
+        } else {
+            let mut r = io::BufReader::with_capacity(READER_CAPACITY, File::open(infile)?);
+            r.read_to_string(&mut input)?;
+        }
+    }
+    from_lines(opt, input.lines(), writer)
+}
+
+// Splits frames like `libfoo.so+0x1a2b` into the module and offset, and bare addresses like
+// `0x1a2b` into just the offset.
+fn parse_frame(frame: &str) -> Option<(Option<&str>, u64)> {
+    let (module, offset) = match frame.rfind("+0x") {
+        Some(0) => return None,
+        Some(i) => (Some(&frame[..i]), &frame[(i + 3)..]),
+        None => (None, frame.strip_prefix("0x")?),
+    };
+    let offset = u64::from_str_radix(offset, 16).ok()?;
+    Some((module, offset))
+}
+
+// Splits a folded line into its stack and its sample counts (with the space before them). There
+// are two counts in the lines of differential flame graphs.
+fn split_counts(line: &str) -> Option<(&str, &str)> {
+    let is_count = |s: &str| s.parse::<f64>().is_ok();
+    let (stack, last) = line.rsplit_once(' ')?;
+    if !is_count(last) {
+        return None;
+    }
+    let stack = match stack.rsplit_once(' ') {
+        Some((rest, count)) if is_count(count) => rest,
+        _ => stack,
+    };
+    Some((stack, &line[stack.len()..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frames() {
+        assert_eq!(
+            parse_frame("libfoo.so+0x1a2b"),
+            Some((Some("libfoo.so"), 0x1a2b))
+        );
+        assert_eq!(
+            parse_frame("/usr/lib/libc.so.6+0x29d90"),
+            Some((Some("/usr/lib/libc.so.6"), 0x29d90))
+        );
+        assert_eq!(parse_frame("0x4011d6"), Some((None, 0x4011d6)));
+        assert_eq!(parse_frame("main"), None);
+        assert_eq!(parse_frame("+0x10"), None);
+        assert_eq!(parse_frame("foo+0xzz"), None);
+    }
+
+    #[test]
+    fn counts() {
+        assert_eq!(split_counts("a;b 3"), Some(("a;b", " 3")));
+        assert_eq!(split_counts("a;b 3 4"), Some(("a;b", " 3 4")));
+        assert_eq!(split_counts("a;b 1.5"), Some(("a;b", " 1.5")));
+        assert_eq!(split_counts("a;b"), None);
+    }
+}
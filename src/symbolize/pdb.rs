@@ -0,0 +1,291 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use ::pdb::{FallibleIterator, SymbolData, PDB};
+use addr2line::object::{self, Object};
+use log::{info, warn};
+
+use super::binary::find_binary;
+use super::Resolver;
+
+/// Where to look for PDBs, parsed from a symbol path like `_NT_SYMBOL_PATH`.
+#[derive(Debug, Default)]
+struct SymbolPath {
+    // Directories with PDBs in them, either directly or in the layout of a symbol store.
+    dirs: Vec<PathBuf>,
+    // Symbol servers, with the directory to keep the PDBs they have in.
+    servers: Vec<(String, PathBuf)>,
+}
+
+impl SymbolPath {
+    // Parses `;`-separated entries that are either directories, or `srv*cache*url` entries that
+    // keep the PDBs of the server at `url` in the directory `cache`.
+    fn parse(symbol_path: &str) -> Self {
+        let mut parsed = SymbolPath::default();
+        for entry in symbol_path.split(';').filter(|entry| !entry.is_empty()) {
+            let parts: Vec<_> = entry.split('*').collect();
+            if !parts[0].eq_ignore_ascii_case("srv") && !parts[0].eq_ignore_ascii_case("cache") {
+                parsed.dirs.push(PathBuf::from(entry));
+                continue;
+            }
+
+            let (urls, dirs): (Vec<&str>, Vec<&str>) = parts[1..]
+                .iter()
+                .filter(|part| !part.is_empty())
+                .partition(|part| part.starts_with("http://") || part.starts_with("https://"));
+            let cache = dirs
+                .first()
+                .map(PathBuf::from)
+                .unwrap_or_else(|| std::env::temp_dir().join("inferno-symbols"));
+            parsed.dirs.extend(dirs.iter().map(PathBuf::from));
+            for url in urls {
+                parsed
+                    .servers
+                    .push((url.trim_end_matches('/').to_string(), cache.clone()));
+            }
+        }
+        parsed
+    }
+}
+
+/// The functions of a PDB, as their start and end offsets from the image base, sorted.
+struct Functions(Vec<(u32, u32, String)>);
+
+impl Functions {
+    fn load(path: &Path) -> Result<Self, ::pdb::Error> {
+        let mut pdb = PDB::open(File::open(path)?)?;
+        let address_map = pdb.address_map()?;
+        let mut functions = Vec::new();
+
+        // Procedures have sizes, so prefer them over public symbols.
+        let debug_information = pdb.debug_information()?;
+        let mut modules = debug_information.modules()?;
+        while let Some(module) = modules.next()? {
+            let info = match pdb.module_info(&module)? {
+                Some(info) => info,
+                None => continue,
+            };
+            let mut symbols = info.symbols()?;
+            while let Some(symbol) = symbols.next()? {
+                if let Ok(SymbolData::Procedure(procedure)) = symbol.parse() {
+                    if let Some(rva) = procedure.offset.to_rva(&address_map) {
+                        let name = procedure.name.to_string().into_owned();
+                        functions.push((rva.0, rva.0 + procedure.len.max(1), name));
+                    }
+                }
+            }
+        }
+
+        let global_symbols = pdb.global_symbols()?;
+        let mut symbols = global_symbols.iter();
+        while let Some(symbol) = symbols.next()? {
+            if let Ok(SymbolData::Public(public)) = symbol.parse() {
+                if !public.function {
+                    continue;
+                }
+                if let Some(rva) = public.offset.to_rva(&address_map) {
+                    let name = public.name.to_string().into_owned();
+                    // Public symbols have no size, so they end where the next function starts.
+                    functions.push((rva.0, u32::MAX, name));
+                }
+            }
+        }
+
+        functions.sort_by_key(|&(start, end, _)| (start, end));
+        functions.dedup_by_key(|(start, _, _)| *start);
+        for i in 1..functions.len() {
+            if functions[i - 1].1 == u32::MAX {
+                functions[i - 1].1 = functions[i].0;
+            }
+        }
+        Ok(Functions(functions))
+    }
+
+    fn function(&self, offset: u32) -> Option<&str> {
+        let i = self.0.partition_point(|(start, _, _)| *start <= offset);
+        let (_, end, name) = &self.0[i.checked_sub(1)?];
+        (offset < *end).then_some(name.as_str())
+    }
+}
+
+/// Resolves offsets into Windows modules using their PDBs, from local directories or symbol
+/// servers.
+pub(super) struct Pdbs {
+    binary_dirs: Vec<PathBuf>,
+    symbol_path: SymbolPath,
+    loaded: HashMap<String, Option<Functions>>,
+}
+
+impl Pdbs {
+    pub(super) fn new(binary_dirs: Vec<PathBuf>, symbol_path: &str) -> Self {
+        Self {
+            binary_dirs,
+            symbol_path: SymbolPath::parse(symbol_path),
+            loaded: HashMap::new(),
+        }
+    }
+
+    // Finds the PDB of a module. With the module's binary, its PDB is looked up by the name,
+    // GUID and age the binary refers to, locally and then on symbol servers. Without it, a PDB
+    // with the same name as the module is looked for locally.
+    fn find(&self, module: &str) -> Option<PathBuf> {
+        let dirs = || self.binary_dirs.iter().chain(&self.symbol_path.dirs);
+
+        let reference = find_binary(&self.binary_dirs, module).and_then(|binary| {
+            let data = fs::read(binary).ok()?;
+            let file = object::File::parse(&*data).ok()?;
+            let code_view = file.pdb_info().ok()??;
+            let path = String::from_utf8_lossy(code_view.path()).into_owned();
+            // The path is where the PDB was written when the module was built.
+            let name = path.rsplit(['\\', '/']).next()?.to_string();
+            Some((name, symbol_store_key(code_view.guid(), code_view.age())))
+        });
+        let (name, key) = match reference {
+            Some(reference) => reference,
+            None => {
+                let stem = Path::new(module).file_stem()?.to_string_lossy();
+                let name = format!("{}.pdb", stem);
+                return dirs()
+                    .map(|dir| dir.join(&name))
+                    .find(|path| path.is_file());
+            }
+        };
+
+        let local = dirs()
+            .flat_map(|dir| [dir.join(&name).join(&key).join(&name), dir.join(&name)])
+            .find(|path| path.is_file());
+        if local.is_some() {
+            return local;
+        }
+
+        self.symbol_path.servers.iter().find_map(|(url, cache)| {
+            match download(url, cache, &name, &key) {
+                Ok(path) => Some(path),
+                Err(e) => {
+                    info!("Could not get {} from {}: {}", name, url, e);
+                    None
+                }
+            }
+        })
+    }
+}
+
+impl Resolver for Pdbs {
+    fn resolve(&mut self, module: &str, offset: u64) -> Option<Vec<String>> {
+        let extension = Path::new(module).extension()?.to_string_lossy();
+        if !["dll", "exe", "sys"]
+            .iter()
+            .any(|windows| extension.eq_ignore_ascii_case(windows))
+        {
+            return None;
+        }
+
+        if !self.loaded.contains_key(module) {
+            let functions = self
+                .find(module)
+                .and_then(|path| match Functions::load(&path) {
+                    Ok(functions) => Some(functions),
+                    Err(e) => {
+                        warn!("Could not load symbols from {}: {}", path.display(), e);
+                        None
+                    }
+                });
+            self.loaded.insert(module.to_string(), functions);
+        }
+
+        let functions = self.loaded.get(module)?.as_ref()?;
+        let function = functions.function(u32::try_from(offset).ok()?)?;
+        Some(vec![function.to_string()])
+    }
+}
+
+// Downloads a PDB from a symbol server into the symbol store at `cache`.
+fn download(url: &str, cache: &Path, name: &str, key: &str) -> io::Result<PathBuf> {
+    let path = cache.join(name).join(key).join(name);
+    if path.is_file() {
+        return Ok(path);
+    }
+
+    let url = format!("{}/{}/{}/{}", url, name, key, name);
+    info!("Downloading {}", url);
+    let response = ureq::get(&url)
+        .call()
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+    fs::create_dir_all(path.parent().expect("symbol store paths have parents"))?;
+    // Download next to the PDB first, so that an interrupted download isn't mistaken for it.
+    let partial = path.with_extension("partial");
+    io::copy(&mut response.into_reader(), &mut File::create(&partial)?)?;
+    fs::rename(&partial, &path)?;
+    Ok(path)
+}
+
+// The directory symbol stores keep a PDB in, from the GUID and age of the PDB.
+fn symbol_store_key(guid: [u8; 16], age: u32) -> String {
+    // The first three fields of the GUID are little-endian.
+    format!(
+        "{:08X}{:04X}{:04X}{}{:X}",
+        u32::from_le_bytes([guid[0], guid[1], guid[2], guid[3]]),
+        u16::from_le_bytes([guid[4], guid[5]]),
+        u16::from_le_bytes([guid[6], guid[7]]),
+        guid[8..]
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect::<String>(),
+        age
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn symbol_paths() {
+        let parsed = SymbolPath::parse(
+            r"C:\symbols;srv*C:\cache*https://msdl.microsoft.com/download/symbols;srv*https://example.com/symbols/",
+        );
+        assert_eq!(
+            parsed.dirs,
+            vec![PathBuf::from(r"C:\symbols"), PathBuf::from(r"C:\cache")]
+        );
+        assert_eq!(
+            parsed.servers,
+            vec![
+                (
+                    "https://msdl.microsoft.com/download/symbols".to_string(),
+                    PathBuf::from(r"C:\cache")
+                ),
+                (
+                    "https://example.com/symbols".to_string(),
+                    std::env::temp_dir().join("inferno-symbols")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn symbol_store_keys() {
+        let guid = [
+            0x78, 0x56, 0x34, 0x12, 0xbc, 0x9a, 0xf0, 0xde, 0x01, 0x23, 0x45, 0x67, 0x89, 0xab,
+            0xcd, 0xef,
+        ];
+        assert_eq!(
+            symbol_store_key(guid, 0x1a),
+            "123456789ABCDEF00123456789ABCDEF1A"
+        );
+    }
+
+    #[test]
+    fn functions() {
+        let functions = Functions(vec![
+            (0x1000, 0x1010, "main".to_string()),
+            (0x1020, 0x1080, "foo".to_string()),
+        ]);
+        assert_eq!(functions.function(0x1004), Some("main"));
+        assert_eq!(functions.function(0x1018), None);
+        assert_eq!(functions.function(0x1020), Some("foo"));
+        assert_eq!(functions.function(0x0fff), None);
+    }
+}
@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+
+use ::sourcemap::SourceMap;
+
+/// Source maps of minified JavaScript, keyed by the file name of the script they map.
+pub(crate) struct SourceMaps {
+    maps: HashMap<String, SourceMap>,
+}
+
+impl SourceMaps {
+    /// Loads the source maps at `paths`, and the `.map` files in the directories among them.
+    pub(crate) fn load(paths: &[PathBuf]) -> io::Result<Self> {
+        let mut maps = HashMap::new();
+        for path in paths {
+            if path.is_dir() {
+                for entry in fs::read_dir(path)? {
+                    let path = entry?.path();
+                    if path.extension().is_some_and(|extension| extension == "map") {
+                        let (file, map) = load(&path)?;
+                        maps.insert(file, map);
+                    }
+                }
+            } else {
+                let (file, map) = load(path)?;
+                maps.insert(file, map);
+            }
+        }
+        Ok(Self { maps })
+    }
+
+    /// Rewrites frames like `t.a.onClick (https://example.com/app.min.js:1:2345)` to the
+    /// original function name and location, if there's a source map for the script.
+    pub(super) fn resolve(&self, frame: &str) -> Option<String> {
+        let location = parse_location(frame)?;
+        let (name, source) =
+            self.lookup(location.name, location.file, location.line, location.column)?;
+        Some(if location.parenthesized {
+            format!("{} ({})", name, source)
+        } else {
+            format!("{} {}", name, source)
+        })
+    }
+
+    /// Looks up the original function name and location, like `src/button.js:4:3`, of
+    /// function `name` at the 1-based `line` and `column` of the script at `file`.
+    pub(crate) fn lookup<'a>(
+        &'a self,
+        name: &'a str,
+        file: &str,
+        line: u32,
+        column: u32,
+    ) -> Option<(&'a str, String)> {
+        let map = self.maps.get(file_name(file))?;
+        // Lines and columns are 1-based in frames, and 0-based in source maps.
+        let token = map.lookup_token(line.checked_sub(1)?, column.checked_sub(1)?)?;
+        let name = token.get_name().unwrap_or(name);
+        let source = token.get_source()?;
+        Some((
+            name,
+            format!(
+                "{}:{}:{}",
+                source,
+                token.get_src_line() + 1,
+                token.get_src_col() + 1
+            ),
+        ))
+    }
+}
+
+// Loads a source map, and returns it with the file name of the script it maps.
+fn load(path: &Path) -> io::Result<(String, SourceMap)> {
+    let map = SourceMap::from_reader(BufReader::new(File::open(path)?)).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Invalid source map {}: {}", path.display(), e),
+        )
+    })?;
+    let file = match map.get_file() {
+        Some(file) => file_name(file).to_string(),
+        None => path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+    };
+    Ok((file, map))
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Location<'a> {
+    name: &'a str,
+    file: &'a str,
+    line: u32,
+    column: u32,
+    parenthesized: bool,
+}
+
+// Splits frames like `name (file:line:column)` and `name file:line:column` into their parts.
+fn parse_location(frame: &str) -> Option<Location<'_>> {
+    let (name, location, parenthesized) = match frame.strip_suffix(')') {
+        Some(rest) => {
+            let (name, location) = rest.rsplit_once(" (")?;
+            (name, location, true)
+        }
+        None => {
+            let (name, location) = frame.rsplit_once(' ')?;
+            (name, location, false)
+        }
+    };
+    let (location, column) = location.rsplit_once(':')?;
+    let (file, line) = location.rsplit_once(':')?;
+    Some(Location {
+        name,
+        file,
+        line: line.parse().ok()?,
+        column: column.parse().ok()?,
+        parenthesized,
+    })
+}
+
+// The file name of a path or URL, without any query or fragment.
+fn file_name(url: &str) -> &str {
+    let url = url.split(['?', '#']).next().unwrap_or(url);
+    url.rsplit('/').next().unwrap_or(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locations() {
+        assert_eq!(
+            parse_location("t.a.onClick (https://example.com/app.min.js:1:2345)"),
+            Some(Location {
+                name: "t.a.onClick",
+                file: "https://example.com/app.min.js",
+                line: 1,
+                column: 2345,
+                parenthesized: true,
+            })
+        );
+        assert_eq!(
+            parse_location("n app.min.js:3:17"),
+            Some(Location {
+                name: "n",
+                file: "app.min.js",
+                line: 3,
+                column: 17,
+                parenthesized: false,
+            })
+        );
+        assert_eq!(parse_location("main"), None);
+        assert_eq!(parse_location("foo (bar)"), None);
+    }
+
+    #[test]
+    fn file_names() {
+        assert_eq!(
+            file_name("https://example.com/js/app.min.js?v=3"),
+            "app.min.js"
+        );
+        assert_eq!(file_name("app.min.js"), "app.min.js");
+    }
+}
@@ -0,0 +1,435 @@
+use std::borrow::Cow;
+
+// The length of the hash suffix of legacy Rust symbols, like `::h0123456789abcdef`, without the
+// colons.
+const RUST_HASH_LENGTH: usize = 17;
+
+/// The manglings to demangle, and how.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+#[cfg_attr(
+    feature = "config",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+pub struct Options {
+    /// Demangle Rust symbols, both legacy (`_ZN...E`) and v0 (`_R...`) ones, and fix the ones
+    /// that profilers like `sample` and DTrace only partially demangle.
+    ///
+    /// Fully mangled symbols are only demangled with the `demangle-rust` feature.
+    ///
+    /// Default is `true`.
+    pub rust: bool,
+
+    /// Strip the hash suffixes of demangled Rust symbols, like `::h0123456789abcdef`, so that
+    /// symbols from different builds still merge.
+    ///
+    /// Default is `true`.
+    pub strip_hash: bool,
+
+    /// Demangle Itanium C++ symbols (`_Z...`), as used by GCC and Clang. Needs the `demangle-cpp`
+    /// feature.
+    ///
+    /// Default is `true`.
+    pub cpp: bool,
+
+    /// Demangle MSVC C++ symbols (`?...`). Needs the `demangle-msvc` feature.
+    ///
+    /// Default is `true`.
+    pub msvc: bool,
+
+    /// Demangle Swift symbols (`$s...`) to their qualified names, like `main.Hello.greet`.
+    /// Signatures are left out, and symbols that use substitutions are left as they are.
+    ///
+    /// Default is `true`.
+    pub swift: bool,
+
+    /// Leave the parameters out of demangled C++ and MSVC functions, so that overloads merge
+    /// into one frame.
+    ///
+    /// Default is `false`.
+    pub no_params: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            rust: true,
+            strip_hash: true,
+            cpp: true,
+            msvc: true,
+            swift: true,
+            no_params: false,
+        }
+    }
+}
+
+impl Options {
+    /// Options that leave all symbols as they are.
+    pub fn none() -> Self {
+        Self {
+            rust: false,
+            strip_hash: true,
+            cpp: false,
+            msvc: false,
+            swift: false,
+            no_params: false,
+        }
+    }
+}
+
+/// Demangles `symbol` if it's mangled in one of the manglings enabled in `opt`.
+///
+/// Symbols that don't look mangled, or that fail to demangle, are returned unchanged. The extra
+/// leading underscore of symbols on macOS is allowed.
+pub fn demangle<'a>(symbol: &'a str, opt: &Options) -> Cow<'a, str> {
+    #[cfg(feature = "demangle-rust")]
+    if opt.rust && (has_prefix(symbol, "_ZN") || has_prefix(symbol, "_R")) {
+        if let Ok(demangled) = rustc_demangle::try_demangle(symbol) {
+            // The alternate format omits the hash.
+            return Cow::Owned(if opt.strip_hash {
+                format!("{:#}", demangled)
+            } else {
+                demangled.to_string()
+            });
+        }
+    }
+
+    #[cfg(feature = "demangle-cpp")]
+    if opt.cpp && has_prefix(symbol, "_Z") {
+        let options = if opt.no_params {
+            cpp_demangle::DemangleOptions::new().no_params()
+        } else {
+            cpp_demangle::DemangleOptions::new()
+        };
+        if let Ok(demangled) = cpp_demangle::Symbol::new(symbol)
+            .map_err(|_| std::fmt::Error)
+            .and_then(|s| s.demangle(&options))
+        {
+            return Cow::Owned(demangled);
+        }
+    }
+
+    #[cfg(feature = "demangle-msvc")]
+    if opt.msvc && symbol.starts_with('?') {
+        let flags = if opt.no_params {
+            msvc_demangler::DemangleFlags::NAME_ONLY
+        } else {
+            msvc_demangler::DemangleFlags::llvm()
+        };
+        if let Ok(demangled) = msvc_demangler::demangle(symbol, flags) {
+            return Cow::Owned(demangled);
+        }
+    }
+
+    if opt.swift && (has_prefix(symbol, "$s") || has_prefix(symbol, "$S")) {
+        let mangled = &symbol[(symbol.find('$').expect("Swift symbols start with $") + 2)..];
+        if let Some(demangled) = demangle_swift(mangled) {
+            return Cow::Owned(demangled);
+        }
+    }
+
+    if opt.rust {
+        let fixed = fix_partially_demangled_rust_symbol(symbol);
+        if !opt.strip_hash && fixed != symbol {
+            // Fixed symbols always had a hash, which was stripped along with the `::` before it.
+            let hash = &symbol[symbol.len() - RUST_HASH_LENGTH..];
+            return Cow::Owned(format!("{}::{}", fixed, hash));
+        }
+        return fixed;
+    }
+
+    Cow::Borrowed(symbol)
+}
+
+// Whether `symbol` starts with `prefix`, or with the extra underscore of macOS and then `prefix`.
+fn has_prefix(symbol: &str, prefix: &str) -> bool {
+    symbol.starts_with(prefix)
+        || symbol
+            .strip_prefix('_')
+            .is_some_and(|symbol| symbol.starts_with(prefix))
+}
+
+// Demangles the context of a Swift symbol (after the `$s`), like `4main5HelloV5greetyyF`, to its
+// qualified name, like `main.Hello.greet`. The module is followed by the names of the types the
+// symbol is in, each followed by the kind of type it is, and then by the symbol's own name.
+fn demangle_swift(mangled: &str) -> Option<String> {
+    let mut rest = mangled;
+    let mut names = Vec::new();
+    loop {
+        let digits = rest.bytes().take_while(u8::is_ascii_digit).count();
+        // Identifiers that start with a 0 are made of substitutions.
+        if rest.starts_with('0') {
+            return None;
+        }
+        if digits == 0 {
+            // Symbols of types themselves, like their metadata accessors, end after the type.
+            if names.len() > 1 {
+                break;
+            }
+            return None;
+        }
+        let len: usize = rest[..digits].parse().ok()?;
+        names.push(rest.get(digits..(digits + len))?);
+        rest = &rest[(digits + len)..];
+
+        if names.len() == 1 {
+            // The module.
+            continue;
+        }
+        // Classes, structs, enums and protocols.
+        match rest.strip_prefix(['C', 'V', 'O', 'P']) {
+            Some(after_kind) => rest = after_kind,
+            None => break,
+        }
+    }
+    Some(names.join("."))
+}
+
+// Demangles partially demangled Rust symbols that were demangled incorrectly by profilers like
+// `sample` and `DTrace`.
+//
+// For example:
+//     `_$LT$grep_searcher..searcher..glue..ReadByLine$LT$$u27$s$C$$u20$M$C$$u20$R$C$$u20$S$GT$$GT$::run::h30ecedc997ad7e32`
+// becomes
+//     `<grep_searcher::searcher::glue::ReadByLine<'s, M, R, S>>::run`
+//
+// Non-Rust symobols, or Rust symbols that are already demangled, will be returned unchanged.
+//
+// Based on code in https://github.com/alexcrichton/rustc-demangle/blob/master/src/legacy.rs
+#[allow(clippy::cognitive_complexity)]
+fn fix_partially_demangled_rust_symbol(symbol: &str) -> Cow<'_, str> {
+    // Rust hashes are hex digits with an `h` prepended.
+    let is_rust_hash =
+        |s: &str| s.starts_with('h') && s[1..].chars().all(|c| c.is_ascii_hexdigit());
+
+    // If there's no trailing Rust hash just return the symbol as is.
+    if symbol.len() < RUST_HASH_LENGTH || !is_rust_hash(&symbol[symbol.len() - RUST_HASH_LENGTH..])
+    {
+        return Cow::Borrowed(symbol);
+    }
+
+    // Strip off trailing hash.
+    let mut rest = &symbol[..symbol.len() - RUST_HASH_LENGTH];
+
+    if rest.ends_with("::") {
+        rest = &rest[..rest.len() - 2];
+    }
+
+    if rest.starts_with("_$") {
+        rest = &rest[1..];
+    }
+
+    let mut demangled = String::new();
+
+    while !rest.is_empty() {
+        if rest.starts_with('.') {
+            if let Some('.') = rest[1..].chars().next() {
+                demangled.push_str("::");
+                rest = &rest[2..];
+            } else {
+                demangled.push('.');
+                rest = &rest[1..];
+            }
+        } else if rest.starts_with('$') {
+            macro_rules! demangle {
+                ($($pat:expr => $demangled:expr,)*) => ({
+                    $(if rest.starts_with($pat) {
+                        demangled.push_str($demangled);
+                        rest = &rest[$pat.len()..];
+                        } else)*
+                    {
+                        demangled.push_str(rest);
+                        break;
+                    }
+
+                })
+            }
+
+            demangle! {
+                "$SP$" => "@",
+                "$BP$" => "*",
+                "$RF$" => "&",
+                "$LT$" => "<",
+                "$GT$" => ">",
+                "$LP$" => "(",
+                "$RP$" => ")",
+                "$C$" => ",",
+                "$u7e$" => "~",
+                "$u20$" => " ",
+                "$u27$" => "'",
+                "$u3d$" => "=",
+                "$u5b$" => "[",
+                "$u5d$" => "]",
+                "$u7b$" => "{",
+                "$u7d$" => "}",
+                "$u3b$" => ";",
+                "$u2b$" => "+",
+                "$u21$" => "!",
+                "$u22$" => "\"",
+            }
+        } else {
+            let idx = match rest.char_indices().find(|&(_, c)| c == '$' || c == '.') {
+                None => rest.len(),
+                Some((i, _)) => i,
+            };
+            demangled.push_str(&rest[..idx]);
+            rest = &rest[idx..];
+        }
+    }
+
+    Cow::Owned(demangled)
+}
+
+#[cfg(test)]
+mod tests {
+    macro_rules! t {
+        ($a:expr, $b:expr) => {
+            assert!(ok($a, $b))
+        };
+    }
+
+    macro_rules! t_unchanged {
+        ($a:expr) => {
+            assert!(ok_unchanged($a))
+        };
+    }
+
+    fn ok(sym: &str, expected: &str) -> bool {
+        let result = super::fix_partially_demangled_rust_symbol(sym);
+        if result == expected {
+            true
+        } else {
+            println!("\n{}\n!=\n{}\n", result, expected);
+            false
+        }
+    }
+
+    fn ok_unchanged(sym: &str) -> bool {
+        let result = super::fix_partially_demangled_rust_symbol(sym);
+        if result == sym {
+            true
+        } else {
+            println!("{} should have been unchanged, but got {}", sym, result);
+            false
+        }
+    }
+
+    #[test]
+    fn fix_partially_demangled_rust_symbols() {
+        t!(
+            "std::sys::unix::fs::File::open::hb90e1c1c787080f0",
+            "std::sys::unix::fs::File::open"
+        );
+        t!("_$LT$std..fs..ReadDir$u20$as$u20$core..iter..traits..iterator..Iterator$GT$::next::hc14f1750ca79129b", "<std::fs::ReadDir as core::iter::traits::iterator::Iterator>::next");
+        t!("rg::search_parallel::_$u7b$$u7b$closure$u7d$$u7d$::_$u7b$$u7b$closure$u7d$$u7d$::h6e849b55a66fcd85", "rg::search_parallel::_{{closure}}::_{{closure}}");
+        t!(
+            "_$LT$F$u20$as$u20$alloc..boxed..FnBox$LT$A$GT$$GT$::call_box::h8612a2a83552fc2d",
+            "<F as alloc::boxed::FnBox<A>>::call_box"
+        );
+        t!(
+            "_$LT$$RF$std..fs..File$u20$as$u20$std..io..Read$GT$::read::h5d84059cf335c8e6",
+            "<&std::fs::File as std::io::Read>::read"
+        );
+        t!(
+            "_$LT$std..thread..JoinHandle$LT$T$GT$$GT$::join::hca6aa63e512626da",
+            "<std::thread::JoinHandle<T>>::join"
+        );
+        t!(
+            "std::sync::mpsc::shared::Packet$LT$T$GT$::recv::hfde2d9e28d13fd56",
+            "std::sync::mpsc::shared::Packet<T>::recv"
+        );
+        t!("crossbeam_utils::thread::ScopedThreadBuilder::spawn::_$u7b$$u7b$closure$u7d$$u7d$::h8fdc7d4f74c0da05", "crossbeam_utils::thread::ScopedThreadBuilder::spawn::_{{closure}}");
+    }
+
+    #[test]
+    fn demangle_mangled_symbols() {
+        let demangle = |sym| super::demangle(sym, &super::Options::default()).into_owned();
+        assert_eq!(
+            demangle("_ZN3std2rt19lang_start_internal17h540c897fe52ba9c5E"),
+            "std::rt::lang_start_internal"
+        );
+        assert_eq!(demangle("_RNvCs1234_7inferno4main"), "inferno::main");
+        assert_eq!(demangle("_ZN3foo3BarC2Ev"), "foo::Bar::Bar()");
+        assert_eq!(demangle("_Z3fooi"), "foo(int)");
+        assert_eq!(demangle("main"), "main");
+        assert_eq!(demangle("_Znot_really_mangled"), "_Znot_really_mangled");
+    }
+
+    #[test]
+    fn fix_partially_demangled_rust_symbol_on_fully_mangled_symbols() {
+        t_unchanged!("_ZN4testE");
+        t_unchanged!("_ZN4test1a2bcE");
+        t_unchanged!("_ZN7inferno10flamegraph5merge6frames17hacfe2d67301633c2E");
+        t_unchanged!("_ZN3std2rt19lang_start_internal17h540c897fe52ba9c5E");
+        t_unchanged!("_ZN116_$LT$core..str..pattern..CharSearcher$LT$$u27$a$GT$$u20$as$u20$core..str..pattern..ReverseSearcher$LT$$u27$a$GT$$GT$15next_match_back17h09d544049dd719bbE");
+        t_unchanged!("_ZN3std5panic12catch_unwind17h0562757d03ff60b3E");
+        t_unchanged!("_ZN3std9panicking3try17h9c1cbc5599e1efbfE");
+    }
+
+    #[test]
+    fn fix_partially_demangled_rust_symbol_on_fully_demangled_symbols() {
+        t_unchanged!("std::sys::unix::fs::File::open");
+        t_unchanged!("<F as alloc::boxed::FnBox<A>>::call_box");
+        t_unchanged!("<std::fs::ReadDir as core::iter::traits::iterator::Iterator>::next");
+        t_unchanged!("<rg::search::SearchWorker<W>>::search_impl");
+        t_unchanged!("<grep_searcher::searcher::glue::ReadByLine<'s, M, R, S>>::run");
+        t_unchanged!("<alloc::raw_vec::RawVec<T, A>>::reserve_internal");
+    }
+
+    #[test]
+    fn demangle_other_manglings() {
+        let opt = super::Options::default();
+        let demangle = |sym| super::demangle(sym, &opt).into_owned();
+        assert_eq!(demangle("__Z3fooi"), "foo(int)");
+        assert_eq!(demangle("?foo@@YAXH@Z"), "void __cdecl foo(int)");
+        assert_eq!(demangle("$s4main5HelloV5greetyyF"), "main.Hello.greet");
+        assert_eq!(demangle("_$s4main3add1a1bS2i_SitF"), "main.add");
+        assert_eq!(demangle("$s4main5HelloVMa"), "main.Hello");
+        assert_eq!(demangle("$sSS"), "$sSS");
+        assert_eq!(
+            demangle("std::sys::unix::fs::File::open::hb90e1c1c787080f0"),
+            "std::sys::unix::fs::File::open"
+        );
+    }
+
+    #[test]
+    fn demangle_options() {
+        let opt = super::Options {
+            no_params: true,
+            ..Default::default()
+        };
+        assert_eq!(super::demangle("_Z3fooi", &opt), "foo");
+        assert_eq!(super::demangle("?foo@@YAXH@Z", &opt), "foo");
+
+        let opt = super::Options::none();
+        assert_eq!(super::demangle("_Z3fooi", &opt), "_Z3fooi");
+        assert_eq!(
+            super::demangle("std::sys::unix::fs::File::open::hb90e1c1c787080f0", &opt),
+            "std::sys::unix::fs::File::open::hb90e1c1c787080f0"
+        );
+    }
+
+    #[test]
+    fn demangle_rust_hashes() {
+        let opt = super::Options {
+            strip_hash: false,
+            ..Default::default()
+        };
+        assert_eq!(
+            super::demangle("_ZN3std2rt19lang_start_internal17h540c897fe52ba9c5E", &opt),
+            "std::rt::lang_start_internal::h540c897fe52ba9c5"
+        );
+        assert_eq!(
+            super::demangle(
+                "_$LT$std..thread..JoinHandle$LT$T$GT$$GT$::join::hca6aa63e512626da",
+                &opt
+            ),
+            "<std::thread::JoinHandle<T>>::join::hca6aa63e512626da"
+        );
+        assert_eq!(
+            super::demangle("std::sys::unix::fs::File::open::hb90e1c1c787080f0", &opt),
+            "std::sys::unix::fs::File::open::hb90e1c1c787080f0"
+        );
+    }
+}
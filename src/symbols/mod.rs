@@ -0,0 +1,2 @@
+/// Demangling of the symbols of C++, Rust, MSVC and Swift functions, shared by the collapsers.
+pub mod demangle;
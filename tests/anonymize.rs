@@ -0,0 +1,67 @@
+use std::fs::File;
+use std::process::Command;
+
+use assert_cmd::cargo::CommandCargoExt;
+use inferno::anonymize::{self, Options};
+use pretty_assertions::assert_eq;
+
+fn anonymize(options: Options) -> String {
+    let mut output = Vec::new();
+    anonymize::from_reader(
+        options,
+        File::open("./tests/data/anonymize/stacks.txt").unwrap(),
+        &mut output,
+    )
+    .unwrap();
+    String::from_utf8(output).unwrap()
+}
+
+#[test]
+fn anonymize_hides_names_and_paths() {
+    let mut options = Options::default();
+    options.key = "secret".to_string();
+    options.keep = vec!["std::".to_string()];
+    let output = anonymize(options);
+
+    assert_eq!(output.lines().count(), 4);
+    for hidden in ["acme", "server", "query", "config", "home", "dev", "main"] {
+        assert!(!output.contains(hidden), "{} in {}", hidden, output);
+    }
+    assert!(output.contains(";std::rt::lang_start;"));
+    assert!(output.contains("_[i] 3\n"));
+    assert!(output.contains("_[k] 1\n"));
+    assert!(output.contains(".rs:42) 2\n"));
+}
+
+#[test]
+fn anonymize_aligns_with_the_same_key() {
+    let mut options = Options::default();
+    options.key = "secret".to_string();
+    assert_eq!(anonymize(options.clone()), anonymize(options.clone()));
+    options.key = "other".to_string();
+    let mut first = Options::default();
+    first.key = "secret".to_string();
+    assert_ne!(anonymize(first), anonymize(options));
+}
+
+#[test]
+fn anonymize_cli() {
+    let output = Command::cargo_bin("inferno-anonymize")
+        .unwrap()
+        .arg("--key")
+        .arg("secret")
+        .arg("--keep")
+        .arg("std::")
+        .arg("./tests/data/anonymize/stacks.txt")
+        .output()
+        .expect("failed to execute process");
+    assert!(output.status.success());
+
+    let mut options = Options::default();
+    options.key = "secret".to_string();
+    options.keep = vec!["std::".to_string()];
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        anonymize(options)
+    );
+}
@@ -0,0 +1,60 @@
+mod common;
+
+use std::fs;
+use std::io;
+
+use inferno::collapse::bpftrace::{Folder, Options};
+use inferno::collapse::Collapse;
+
+fn test_collapse_bpftrace(
+    test_file: &str,
+    expected_file: &str,
+    options: Options,
+) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+#[test]
+fn collapse_bpftrace_default() {
+    let test_file = "./tests/data/collapse-bpftrace/bpftrace.txt";
+    let result_file = "./tests/data/collapse-bpftrace/results/bpftrace-collapsed.txt";
+    test_collapse_bpftrace(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_bpftrace_annotate_kernel() {
+    let test_file = "./tests/data/collapse-bpftrace/bpftrace.txt";
+    let result_file = "./tests/data/collapse-bpftrace/results/bpftrace-kernel.txt";
+    let mut options = Options::default();
+    options.annotate_kernel = true;
+    test_collapse_bpftrace(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_bpftrace_bcc() {
+    let test_file = "./tests/data/collapse-bpftrace/bcc.txt";
+    let result_file = "./tests/data/collapse-bpftrace/results/bcc-collapsed.txt";
+    test_collapse_bpftrace(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_bpftrace_bcc_kernel_pid() {
+    let test_file = "./tests/data/collapse-bpftrace/bcc.txt";
+    let result_file = "./tests/data/collapse-bpftrace/results/bcc-kernel-pid.txt";
+    let mut options = Options::default();
+    options.annotate_kernel = true;
+    options.include_pid = true;
+    test_collapse_bpftrace(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_bpftrace_is_applicable() {
+    let mut folder = Folder::default();
+    for file in ["bpftrace.txt", "bcc.txt"] {
+        let input = fs::read_to_string(format!("./tests/data/collapse-bpftrace/{}", file)).unwrap();
+        assert_eq!(folder.is_applicable(&input), Some(true), "{}", file);
+    }
+    let folded =
+        fs::read_to_string("./tests/data/collapse-bpftrace/results/bcc-collapsed.txt").unwrap();
+    assert_eq!(folder.is_applicable(&folded), Some(false));
+}
@@ -0,0 +1,29 @@
+mod common;
+
+use std::io;
+
+use inferno::collapse::callgrind::{Folder, Options};
+
+fn test_collapse_callgrind(
+    test_file: &str,
+    expected_file: &str,
+    options: Options,
+) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+#[test]
+fn collapse_callgrind_default() {
+    let test_file = "./tests/data/collapse-callgrind/callgrind.out";
+    let result_file = "./tests/data/collapse-callgrind/results/callgrind-collapsed.txt";
+    test_collapse_callgrind(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_callgrind_event() {
+    let test_file = "./tests/data/collapse-callgrind/callgrind.out";
+    let result_file = "./tests/data/collapse-callgrind/results/callgrind-dr.txt";
+    let mut options = Options::default();
+    options.event = Some("Dr".to_string());
+    test_collapse_callgrind(test_file, result_file, options).unwrap()
+}
@@ -0,0 +1,42 @@
+mod common;
+
+use std::io;
+
+use inferno::collapse::cpuprofile::{Folder, Options};
+
+fn test_collapse_cpuprofile(
+    test_file: &str,
+    expected_file: &str,
+    options: Options,
+) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+#[test]
+fn collapse_cpuprofile_default() {
+    let test_file = "./tests/data/collapse-cpuprofile/app.cpuprofile";
+    let result_file = "./tests/data/collapse-cpuprofile/results/app-collapsed.txt";
+    test_collapse_cpuprofile(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_cpuprofile_count_samples() {
+    let test_file = "./tests/data/collapse-cpuprofile/app.cpuprofile";
+    let result_file = "./tests/data/collapse-cpuprofile/results/app-samples.txt";
+    let mut options = Options::default();
+    options.count_samples = true;
+    options.include_locations = true;
+    options.skip_idle = true;
+    test_collapse_cpuprofile(test_file, result_file, options).unwrap()
+}
+
+#[cfg(feature = "sourcemap")]
+#[test]
+fn collapse_cpuprofile_source_maps() {
+    let test_file = "./tests/data/collapse-cpuprofile/minified.cpuprofile";
+    let result_file = "./tests/data/collapse-cpuprofile/results/minified.txt";
+    let mut options = Options::default();
+    options.include_locations = true;
+    options.source_maps = vec!["./tests/data/symbolize/app.min.js.map".into()];
+    test_collapse_cpuprofile(test_file, result_file, options).unwrap()
+}
@@ -0,0 +1,25 @@
+mod common;
+
+use std::io;
+
+use inferno::collapse::d3::{Folder, Options};
+
+fn test_collapse_d3(test_file: &str, expected_file: &str, options: Options) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+#[test]
+fn collapse_d3_default() {
+    let test_file = "./tests/data/collapse-d3/stacks.json";
+    let result_file = "./tests/data/collapse-d3/results/stacks.txt";
+    test_collapse_d3(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_d3_keep_root() {
+    let test_file = "./tests/data/collapse-d3/stacks.json";
+    let result_file = "./tests/data/collapse-d3/results/stacks-keep-root.txt";
+    let mut options = Options::default();
+    options.keep_root = true;
+    test_collapse_d3(test_file, result_file, options).unwrap()
+}
@@ -5,7 +5,9 @@ use std::io::{self, BufReader, Cursor};
 use std::process::{Command, Stdio};
 
 use assert_cmd::cargo::CommandCargoExt;
-use inferno::collapse::dtrace::{Folder, Options};
+use inferno::collapse::dtrace::{DistributionWeight, Folder, ModuleNames, Options, TimeUnit};
+use inferno::collapse::Collapse;
+use inferno::folded::Header;
 use log::Level;
 use pretty_assertions::assert_eq;
 use testing_logger::CapturedLog;
@@ -91,6 +93,108 @@ fn collapse_dtrace_stack_ustack() {
     test_collapse_dtrace(test_file, result_file, Options::default()).unwrap()
 }
 
+#[test]
+fn collapse_dtrace_quantize() {
+    let test_file = "./tests/data/collapse-dtrace/quantize.txt";
+    let result_file = "./tests/data/collapse-dtrace/results/quantize.txt";
+    test_collapse_dtrace(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_dtrace_quantize_count() {
+    let test_file = "./tests/data/collapse-dtrace/quantize.txt";
+    let result_file = "./tests/data/collapse-dtrace/results/quantize-count.txt";
+    let mut options = Options::default();
+    options.distribution_weight = DistributionWeight::Count;
+    test_collapse_dtrace(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_dtrace_time_sum() {
+    let test_file = "./tests/data/collapse-dtrace/time-sum.txt";
+    let result_file = "./tests/data/collapse-dtrace/results/time-sum.txt";
+    let mut options = Options::default();
+    options.time_unit = Some(TimeUnit::Microseconds);
+    test_collapse_dtrace(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_dtrace_bytes_sum() {
+    let test_file = "./tests/data/collapse-dtrace/bytes-sum.txt";
+    let result_file = "./tests/data/collapse-dtrace/results/bytes-sum.txt";
+    test_collapse_dtrace(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_dtrace_header_units() {
+    for &n in &[1, 2] {
+        let mut options = Options::default();
+        options.header = true;
+        options.nthreads = n;
+        options.time_unit = Some(TimeUnit::Microseconds);
+        options.units = Some("bytes".to_string());
+        let mut folded = Vec::new();
+        Folder::from(options)
+            .collapse_file(
+                Some("./tests/data/collapse-dtrace/bytes-sum.txt"),
+                &mut folded,
+            )
+            .unwrap();
+        let folded = String::from_utf8(folded).unwrap();
+
+        let header = Header::parse(folded.lines()).unwrap();
+        assert!(header
+            .tool
+            .as_deref()
+            .unwrap()
+            .starts_with("inferno-collapse-dtrace "));
+        assert_eq!(header.units.as_deref(), Some("bytes"));
+    }
+}
+
+#[test]
+fn collapse_dtrace_module_names() {
+    let test_file = "./tests/data/collapse-dtrace/modules.txt";
+    for (module_names, suffix) in [
+        (ModuleNames::Prefix, ""),
+        (ModuleNames::Strip, "-strip"),
+        (ModuleNames::Annotate, "-annotate"),
+        (ModuleNames::Frame, "-frame"),
+    ] {
+        let result_file = format!("./tests/data/collapse-dtrace/results/modules{}.txt", suffix);
+        let mut options = Options::default();
+        options.module_names = module_names;
+        test_collapse_dtrace(test_file, &result_file, options).unwrap()
+    }
+}
+
+#[test]
+fn collapse_dtrace_pname() {
+    let test_file = "./tests/data/collapse-dtrace/pname.txt";
+    let result_file = "./tests/data/collapse-dtrace/results/pname.txt";
+    let mut options = Options::default();
+    options.include_pname = true;
+    test_collapse_dtrace(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_dtrace_tid() {
+    let test_file = "./tests/data/collapse-dtrace/pname.txt";
+    let result_file = "./tests/data/collapse-dtrace/results/pname-tid.txt";
+    let mut options = Options::default();
+    options.include_tid = true;
+    test_collapse_dtrace(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_dtrace_tid_without_keys() {
+    let test_file = "./tests/data/collapse-dtrace/stack-ustack.txt";
+    let result_file = "./tests/data/collapse-dtrace/results/stack-ustack.txt";
+    let mut options = Options::default();
+    options.include_tid = true;
+    test_collapse_dtrace(test_file, result_file, options).unwrap()
+}
+
 #[test]
 fn collapse_dtrace_should_log_warning_for_only_header_lines() {
     test_collapse_dtrace_logs(
@@ -0,0 +1,43 @@
+mod common;
+
+use std::io;
+
+use inferno::collapse::gecko::{Folder, Options};
+
+fn test_collapse_gecko(test_file: &str, expected_file: &str, options: Options) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+#[test]
+fn collapse_gecko_processed() {
+    let test_file = "./tests/data/collapse-gecko/processed.json";
+    let result_file = "./tests/data/collapse-gecko/results/processed-collapsed.txt";
+    test_collapse_gecko(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_gecko_include_threads() {
+    let test_file = "./tests/data/collapse-gecko/processed.json";
+    let result_file = "./tests/data/collapse-gecko/results/processed-threads.txt";
+    let mut options = Options::default();
+    options.include_threads = true;
+    test_collapse_gecko(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_gecko_select_thread() {
+    let test_file = "./tests/data/collapse-gecko/processed.json";
+    let result_file = "./tests/data/collapse-gecko/results/processed-worker.txt";
+    let mut options = Options::default();
+    options.threads = vec!["Worker".to_string()];
+    test_collapse_gecko(test_file, result_file, options.clone()).unwrap();
+    options.threads = vec!["101".to_string()];
+    test_collapse_gecko(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_gecko_raw() {
+    let test_file = "./tests/data/collapse-gecko/raw.json";
+    let result_file = "./tests/data/collapse-gecko/results/raw-collapsed.txt";
+    test_collapse_gecko(test_file, result_file, Options::default()).unwrap()
+}
@@ -5,7 +5,8 @@ use std::io::{self, BufReader, Cursor};
 use std::process::{Command, Stdio};
 
 use assert_cmd::cargo::CommandCargoExt;
-use inferno::collapse::guess::Folder;
+use inferno::collapse::callgrind;
+use inferno::collapse::guess::{Confidence, Folder, Format, Options};
 use log::Level;
 use pretty_assertions::assert_eq;
 use testing_logger::CapturedLog;
@@ -92,6 +93,104 @@ fn collapse_guess_vsprof() {
     test_collapse_guess(test_file, result_file, false).unwrap()
 }
 
+#[test]
+fn collapse_guess_assume() {
+    let test_file = "./tests/data/collapse-vsprof/CallTreeSummary.csv";
+    let result_file = "./tests/data/collapse-vsprof/results/vsprof-default.txt";
+
+    let mut options = Options::default();
+    options.assume = Some(Format::Vsprof);
+
+    common::test_collapse(Folder::from(options), test_file, result_file, false).unwrap()
+}
+
+#[test]
+fn collapse_guess_rank() {
+    let input = std::fs::read_to_string("./tests/data/collapse-dtrace/java.txt").unwrap();
+    let candidates = Folder::default().rank(&input);
+    assert_eq!(candidates[0].format, Format::Dtrace);
+    assert!(matches!(candidates[0].confidence, Confidence::Matched(_)));
+    assert!(candidates[1..]
+        .iter()
+        .all(|candidate| candidate.confidence == Confidence::Possible));
+}
+
+#[test]
+fn collapse_guess_callgrind() {
+    let test_file = "./tests/data/collapse-callgrind/callgrind.out";
+    let result_file = "./tests/data/collapse-callgrind/results/callgrind-collapsed.txt";
+
+    let input = std::fs::read_to_string(test_file).unwrap();
+    let candidates = Folder::default().rank(&input);
+    assert_eq!(candidates[0].format, Format::Custom("callgrind"));
+    assert!(matches!(candidates[0].confidence, Confidence::Matched(_)));
+
+    test_collapse_guess(test_file, result_file, false).unwrap()
+}
+
+#[test]
+fn collapse_guess_registered() {
+    let test_file = "./tests/data/collapse-callgrind/callgrind.out";
+    let result_file = "./tests/data/collapse-callgrind/results/callgrind-collapsed.txt";
+
+    let mut folder = Folder::default();
+    folder.register("valgrind", 0, callgrind::Folder::default);
+    let formats = folder.formats();
+    let position = |format| formats.iter().position(|&f| f == format).unwrap();
+    assert!(position(Format::Custom("valgrind")) < position(Format::Custom("callgrind")));
+
+    let input = std::fs::read_to_string(test_file).unwrap();
+    let candidates = folder.rank(&input);
+    assert_eq!(candidates[0].format, Format::Custom("valgrind"));
+    assert!(matches!(candidates[0].confidence, Confidence::Matched(_)));
+
+    common::test_collapse(folder, test_file, result_file, false).unwrap()
+}
+
+#[test]
+fn collapse_guess_registered_priority() {
+    let mut folder = Folder::default();
+    folder.register("low", -1, callgrind::Folder::default);
+    folder.register("high", 1, callgrind::Folder::default);
+    let formats = folder.formats();
+    assert_eq!(formats.first(), Some(&Format::Custom("high")));
+    assert_eq!(formats.last(), Some(&Format::Custom("low")));
+}
+
+#[test]
+#[cfg(unix)]
+fn collapse_guess_plugin() {
+    let test_file = "./tests/data/collapse-guess/plugin-format.txt";
+    let result_file = "./tests/data/collapse-guess/results/plugin-format.txt";
+
+    let mut options = Options::default();
+    options.plugins = vec!["./tests/data/collapse-guess/plugins/test-format.sh".into()];
+
+    common::test_collapse(Folder::from(options), test_file, result_file, false).unwrap()
+}
+
+#[test]
+#[cfg(unix)]
+fn collapse_guess_plugin_not_applicable_should_log_error() {
+    let mut options = Options::default();
+    options.plugins = vec!["./tests/data/collapse-guess/plugins/test-format.sh".into()];
+
+    common::test_collapse_logs(
+        Folder::from(options),
+        "./tests/data/collapse-guess/unknown-format.txt",
+        |captured_logs| {
+            let nerrors = captured_logs
+                .iter()
+                .filter(|log| {
+                    log.level == Level::Error
+                        && log.body == "No applicable collapse implementation found for input"
+                })
+                .count();
+            assert_eq!(nerrors, 1);
+        },
+    );
+}
+
 #[test]
 fn collapse_guess_unknown_format_should_log_error() {
     test_collapse_guess_logs(
@@ -162,3 +261,24 @@ fn collapse_guess_cli() {
     let expected = BufReader::new(File::open(expected_file).unwrap());
     common::compare_results(Cursor::new(output.stdout), expected, expected_file, true);
 }
+
+#[test]
+fn collapse_guess_cpuprofile() {
+    let test_file = "./tests/data/collapse-cpuprofile/app.cpuprofile";
+    let result_file = "./tests/data/collapse-cpuprofile/results/app-collapsed.txt";
+    test_collapse_guess(test_file, result_file, false).unwrap()
+}
+
+#[test]
+fn collapse_guess_wpa() {
+    let test_file = "./tests/data/collapse-wpa/tree.csv";
+    let result_file = "./tests/data/collapse-wpa/results/tree-collapsed.txt";
+    test_collapse_guess(test_file, result_file, false).unwrap()
+}
+
+#[test]
+fn collapse_guess_stackprof() {
+    let test_file = "./tests/data/collapse-stackprof/app.json";
+    let result_file = "./tests/data/collapse-stackprof/results/app-collapsed.txt";
+    test_collapse_guess(test_file, result_file, false).unwrap()
+}
@@ -0,0 +1,38 @@
+mod common;
+
+use std::io;
+
+use inferno::collapse::heaptrack::{Cost, Folder, Options};
+
+fn test_collapse_heaptrack(
+    test_file: &str,
+    expected_file: &str,
+    options: Options,
+) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+#[test]
+fn collapse_heaptrack_allocated() {
+    let test_file = "./tests/data/collapse-heaptrack/heaptrack.app.txt";
+    let result_file = "./tests/data/collapse-heaptrack/results/heaptrack-allocated.txt";
+    test_collapse_heaptrack(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_heaptrack_leaked() {
+    let test_file = "./tests/data/collapse-heaptrack/heaptrack.app.txt";
+    let result_file = "./tests/data/collapse-heaptrack/results/heaptrack-leaked.txt";
+    let mut options = Options::default();
+    options.cost = Cost::Leaked;
+    test_collapse_heaptrack(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_heaptrack_allocations() {
+    let test_file = "./tests/data/collapse-heaptrack/heaptrack.app.txt";
+    let result_file = "./tests/data/collapse-heaptrack/results/heaptrack-allocations.txt";
+    let mut options = Options::default();
+    options.cost = Cost::Allocations;
+    test_collapse_heaptrack(test_file, result_file, options).unwrap()
+}
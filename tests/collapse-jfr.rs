@@ -0,0 +1,35 @@
+mod common;
+
+use std::io;
+
+use inferno::collapse::jfr::{Event, Folder, Options};
+
+fn test_collapse_jfr(test_file: &str, expected_file: &str, options: Options) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+#[test]
+fn collapse_jfr_default() {
+    let test_file = "./tests/data/collapse-jfr/profile.jfr";
+    let result_file = "./tests/data/collapse-jfr/results/profile-collapsed.txt";
+    test_collapse_jfr(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_jfr_threads() {
+    let test_file = "./tests/data/collapse-jfr/profile.jfr";
+    let result_file = "./tests/data/collapse-jfr/results/profile-threads.txt";
+    let mut options = Options::default();
+    options.include_threads = true;
+    options.annotate = true;
+    test_collapse_jfr(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_jfr_alloc() {
+    let test_file = "./tests/data/collapse-jfr/profile.jfr";
+    let result_file = "./tests/data/collapse-jfr/results/profile-alloc.txt";
+    let mut options = Options::default();
+    options.event = Event::Alloc;
+    test_collapse_jfr(test_file, result_file, options).unwrap()
+}
@@ -0,0 +1,26 @@
+mod common;
+
+use std::io;
+
+use inferno::collapse::massif::{Folder, Options};
+
+fn test_collapse_massif(test_file: &str, expected_file: &str, options: Options) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+#[test]
+fn collapse_massif_peak() {
+    let test_file = "./tests/data/collapse-massif/massif.out";
+    let result_file = "./tests/data/collapse-massif/results/massif-collapsed.txt";
+    test_collapse_massif(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_massif_snapshot_locations() {
+    let test_file = "./tests/data/collapse-massif/massif.out";
+    let result_file = "./tests/data/collapse-massif/results/massif-snapshot-locations.txt";
+    let mut options = Options::default();
+    options.snapshot = Some(1);
+    options.include_locations = true;
+    test_collapse_massif(test_file, result_file, options).unwrap()
+}
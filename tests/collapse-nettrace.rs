@@ -0,0 +1,29 @@
+mod common;
+
+use std::io;
+
+use inferno::collapse::nettrace::{Folder, Options};
+
+fn test_collapse_nettrace(
+    test_file: &str,
+    expected_file: &str,
+    options: Options,
+) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+#[test]
+fn collapse_nettrace_default() {
+    let test_file = "./tests/data/collapse-nettrace/cpu.nettrace";
+    let result_file = "./tests/data/collapse-nettrace/results/cpu-collapsed.txt";
+    test_collapse_nettrace(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_nettrace_threads() {
+    let test_file = "./tests/data/collapse-nettrace/cpu.nettrace";
+    let result_file = "./tests/data/collapse-nettrace/results/cpu-threads.txt";
+    let mut options = Options::default();
+    options.include_threads = true;
+    test_collapse_nettrace(test_file, result_file, options).unwrap()
+}
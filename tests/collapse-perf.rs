@@ -1,12 +1,17 @@
 mod common;
 
-use std::fs::File;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
 use std::io::{self, BufReader, Cursor};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
+use std::rc::Rc;
 
 use assert_cmd::cargo::CommandCargoExt;
 use inferno::collapse::perf::{Folder, Options};
+use inferno::collapse::{CancelToken, Collapse};
+use inferno::folded::Header;
 use log::Level;
 use pretty_assertions::assert_eq;
 use testing_logger::CapturedLog;
@@ -55,6 +60,7 @@ fn options_from_vec(opt_vec: Vec<&str>) -> Options {
             "addrs" => options.include_addrs = true,
             "jit" => options.annotate_jit = true,
             "kernel" => options.annotate_kernel = true,
+            "samples" => options.period_weights = false,
             "all" => {
                 options.annotate_jit = true;
                 options.annotate_kernel = true;
@@ -231,10 +237,12 @@ collapse_perf_tests! {
     collapse_perf_no_events,
     collapse_perf_single_line_stacks,
     collapse_perf_single_event,
+    collapse_perf_single_event__samples,
     collapse_perf_go_stacks,
     collapse_perf_java_inline,
     collapse_perf_versioned_vmlinux__kernel,
-    collapse_perf_sourcepawn_jitdump__jit
+    collapse_perf_sourcepawn_jitdump__jit,
+    collapse_perf_mangled
 }
 
 #[test]
@@ -248,6 +256,185 @@ fn collapse_perf_example_perf_stacks() {
     .unwrap();
 }
 
+#[test]
+fn collapse_perf_jit_map() {
+    let mut options = Options::default();
+    options.jit_maps = vec![PathBuf::from("./tests/data/collapse-perf/perf-4242.map")];
+    test_collapse_perf(
+        "./tests/data/collapse-perf/jit-map.txt",
+        "./tests/data/collapse-perf/results/jit-map-collapsed.txt",
+        options,
+        false,
+    )
+    .unwrap();
+}
+
+#[test]
+fn collapse_perf_jit_map_dir() {
+    let mut options = Options::default();
+    options.annotate_jit = true;
+    options.jit_maps = vec![PathBuf::from("./tests/data/collapse-perf")];
+    test_collapse_perf(
+        "./tests/data/collapse-perf/jit-map-addrs.txt",
+        "./tests/data/collapse-perf/results/jit-map-addrs-collapsed.txt",
+        options,
+        false,
+    )
+    .unwrap();
+}
+
+#[test]
+fn collapse_perf_off_cpu() {
+    let mut options = Options::default();
+    options.off_cpu = true;
+    test_collapse_perf(
+        "./tests/data/collapse-perf/sched-switch.txt",
+        "./tests/data/collapse-perf/results/sched-switch-collapsed-off-cpu.txt",
+        options,
+        false,
+    )
+    .unwrap();
+}
+
+#[test]
+fn collapse_perf_time_range() {
+    let mut options = Options::default();
+    options.from = Some(100.5);
+    options.to = Some(102.0);
+    test_collapse_perf(
+        "./tests/data/collapse-perf/timestamps.txt",
+        "./tests/data/collapse-perf/results/timestamps-collapsed-range.txt",
+        options,
+        false,
+    )
+    .unwrap();
+}
+
+#[test]
+fn collapse_perf_max_depth() {
+    let mut options = Options::default();
+    options.max_depth = Some(2);
+    options.nthreads = 1;
+    let input = fs::read("./tests/data/collapse-perf/timestamps.txt").unwrap();
+    let mut folded = Vec::new();
+    Folder::from(options)
+        .collapse(&input[..], &mut folded)
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(folded).unwrap(),
+        "app;main 1\napp;main;... 4\n"
+    );
+}
+
+#[test]
+fn collapse_perf_chronological() {
+    let mut options = Options::default();
+    options.idle_gap = 1.0;
+    let input = fs::read("./tests/data/collapse-perf/timestamps.txt").unwrap();
+    let mut folded = Vec::new();
+    Folder::from(options)
+        .collapse_chronologically(&input[..], &mut folded)
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(folded).unwrap(),
+        "\
+app;main 500000000
+app;main;parse 740000000
+app;main;render 1010000000
+[idle] 640000000
+app;main;exit 1000000000
+"
+    );
+}
+
+#[test]
+fn collapse_perf_per_thread() {
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl io::Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let input = "\
+app 1000/1001 100.000001: 1 cycles:
+            555555550120 main+0x20 (/usr/bin/app)
+
+app 1000/1002 100.000002: 1 cycles:
+            555555550210 worker+0x10 (/usr/bin/app)
+
+app 1000/1002 100.000003: 1 cycles:
+            555555550210 worker+0x10 (/usr/bin/app)
+
+";
+    let mut options = Options::default();
+    options.include_tid = true;
+    options.nthreads = 1;
+
+    let mut outputs = BTreeMap::new();
+    Folder::from(options)
+        .collapse_per_thread(Cursor::new(input), |root| {
+            let buffer = SharedBuffer::default();
+            outputs.insert(root.to_string(), buffer.clone());
+            Ok(buffer)
+        })
+        .unwrap();
+
+    let outputs: Vec<_> = outputs
+        .into_iter()
+        .map(|(root, buffer)| (root, String::from_utf8(buffer.0.take()).unwrap()))
+        .collect();
+    assert_eq!(
+        outputs,
+        vec![
+            (
+                "app-1000/1001".to_string(),
+                "app-1000/1001;main 1\n".to_string()
+            ),
+            (
+                "app-1000/1002".to_string(),
+                "app-1000/1002;worker 2\n".to_string()
+            ),
+        ]
+    );
+}
+
+#[test]
+fn collapse_perf_output_dir_keeps_roots_with_the_same_file_name_apart() {
+    let dir = std::env::temp_dir().join(format!("inferno-collapse-perf-{}", std::process::id()));
+    let output = Command::cargo_bin("inferno-collapse-perf")
+        .unwrap()
+        .arg("--tid")
+        .arg("--output-dir")
+        .arg(&dir)
+        .arg("./tests/data/collapse-perf/same-file-name.txt")
+        .output()
+        .expect("failed to execute process");
+    assert!(output.status.success());
+
+    let mut outputs: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| fs::read_to_string(entry.unwrap().path()).unwrap())
+        .collect();
+    outputs.sort();
+    assert_eq!(
+        outputs,
+        vec![
+            "pool#1-1/2;main 1\n".to_string(),
+            "pool_1-1/2;worker 1\n".to_string(),
+        ]
+    );
+    assert!(dir.join("pool_1-1_2.folded").exists());
+    assert!(dir.join("pool_1-1_2-2.folded").exists());
+    fs::remove_dir_all(dir).unwrap();
+}
+
 #[test]
 fn collapse_perf_should_warn_about_empty_input_lines() {
     test_collapse_perf_logs(
@@ -318,3 +505,131 @@ fn collapse_perf_cli() {
     let expected = BufReader::new(File::open(expected_file).unwrap());
     common::compare_results(Cursor::new(output.stdout), expected, expected_file, true);
 }
+
+#[test]
+fn collapse_perf_header() {
+    for &n in &[1, 2] {
+        let mut options = Options::default();
+        options.header = true;
+        options.nthreads = n;
+        let mut folded = Vec::new();
+        Folder::from(options)
+            .collapse_file(
+                Some("./tests/data/collapse-perf/timestamps.txt"),
+                &mut folded,
+            )
+            .unwrap();
+        let folded = String::from_utf8(folded).unwrap();
+
+        let header = Header::parse(folded.lines()).unwrap();
+        assert!(header
+            .tool
+            .as_deref()
+            .unwrap()
+            .starts_with("inferno-collapse-perf "));
+        assert_eq!(header.event.as_deref(), Some("cycles"));
+        assert_eq!(header.units.as_deref(), Some("cycles"));
+        assert_eq!(header.start, Some(100.01));
+        assert_eq!(header.end, Some(102.9));
+        assert!(folded.lines().any(|line| line == "app;main;render 2"));
+    }
+}
+
+#[test]
+fn collapse_perf_bytes() {
+    let input = fs::read("./tests/data/collapse-perf/single-event.txt.gz").unwrap();
+    let expected =
+        fs::read_to_string("./tests/data/collapse-perf/results/single-event-collapsed.txt")
+            .unwrap();
+    let folded = Folder::from(Options::default())
+        .collapse_bytes(&input)
+        .unwrap();
+    assert_eq!(folded, expected);
+}
+
+#[test]
+fn collapse_perf_compressed_input() {
+    let result_file = "./tests/data/collapse-perf/results/single-event-collapsed.txt";
+    let mut test_files = vec!["./tests/data/collapse-perf/single-event.txt.gz"];
+    if cfg!(feature = "zstd") {
+        test_files.push("./tests/data/collapse-perf/single-event.txt.zst");
+    }
+    if cfg!(feature = "xz") {
+        test_files.push("./tests/data/collapse-perf/single-event.txt.xz");
+    }
+    for test_file in test_files {
+        test_collapse_perf(test_file, result_file, Options::default(), false).unwrap()
+    }
+}
+
+#[test]
+fn collapse_perf_progress() {
+    let test_file = "./tests/data/collapse-perf/go-stacks.txt";
+    let input = std::fs::read(test_file).unwrap();
+
+    let mut reported = Vec::new();
+    let mut output = Vec::new();
+    Folder::default()
+        .collapse_with_progress(
+            &input[..],
+            &mut output,
+            |read| reported.push(read),
+            &CancelToken::new(),
+        )
+        .unwrap();
+    assert!(!output.is_empty());
+    assert!(reported.windows(2).all(|pair| pair[0] <= pair[1]));
+    assert_eq!(reported.last(), Some(&(input.len() as u64)));
+}
+
+#[test]
+fn collapse_perf_cancelled() {
+    let test_file = "./tests/data/collapse-perf/go-stacks.txt";
+    let input = std::fs::read(test_file).unwrap();
+
+    let cancel = CancelToken::new();
+    cancel.clone().cancel();
+    let mut output = Vec::new();
+    let error = Folder::default()
+        .collapse_with_progress(&input[..], &mut output, |_| (), &cancel)
+        .unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::Other);
+    assert!(output.is_empty());
+}
+
+// Cancels the collapse once all of the input has been read.
+struct CancelAtEnd<'a> {
+    input: &'a [u8],
+    cancel: CancelToken,
+}
+
+impl io::Read for CancelAtEnd<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.input.read(buf)?;
+        if n == 0 {
+            self.cancel.cancel();
+        }
+        Ok(n)
+    }
+}
+
+#[test]
+fn collapse_perf_cancelled_after_reading() {
+    let test_file = "./tests/data/collapse-perf/go-stacks.txt";
+    let input = std::fs::read(test_file).unwrap();
+
+    // The threads are still folding stacks when the whole input has been read.
+    let cancel = CancelToken::new();
+    let reader = BufReader::new(CancelAtEnd {
+        input: &input[..],
+        cancel: cancel.clone(),
+    });
+    let mut options = Options::default();
+    options.nthreads = 2;
+    let mut output = Vec::new();
+    let error = Folder::from(options)
+        .collapse_with_progress(reader, &mut output, |_| (), &cancel)
+        .unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::Other);
+    assert!(output.is_empty());
+}
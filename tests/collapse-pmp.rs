@@ -0,0 +1,34 @@
+mod common;
+
+use std::io;
+
+use inferno::collapse::pmp::{Folder, Options};
+
+fn test_collapse_pmp(test_file: &str, expected_file: &str, options: Options) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+#[test]
+fn collapse_pmp_gdb() {
+    let test_file = "./tests/data/collapse-pmp/gdb.txt";
+    let result_file = "./tests/data/collapse-pmp/results/gdb-collapsed.txt";
+    test_collapse_pmp(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_pmp_gdb_threads() {
+    let test_file = "./tests/data/collapse-pmp/gdb.txt";
+    let result_file = "./tests/data/collapse-pmp/results/gdb-threads.txt";
+    let mut options = Options::default();
+    options.include_threads = true;
+    test_collapse_pmp(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_pmp_eu_stack_threads() {
+    let test_file = "./tests/data/collapse-pmp/eu-stack.txt";
+    let result_file = "./tests/data/collapse-pmp/results/eu-stack-threads.txt";
+    let mut options = Options::default();
+    options.include_threads = true;
+    test_collapse_pmp(test_file, result_file, options).unwrap()
+}
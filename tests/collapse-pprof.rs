@@ -0,0 +1,38 @@
+mod common;
+
+use std::io;
+
+use inferno::collapse::pprof::{Folder, Options};
+use inferno::collapse::Collapse;
+
+fn test_collapse_pprof(test_file: &str, expected_file: &str, options: Options) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+#[test]
+fn collapse_pprof_default() {
+    let test_file = "./tests/data/collapse-pprof/cpu.pb.gz";
+    let result_file = "./tests/data/collapse-pprof/results/cpu-collapsed.txt";
+    test_collapse_pprof(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_pprof_sample_type() {
+    let test_file = "./tests/data/collapse-pprof/cpu.pb.gz";
+    let result_file = "./tests/data/collapse-pprof/results/cpu-samples.txt";
+    let mut options = Options::default();
+    options.sample_type = Some("samples".to_string());
+    test_collapse_pprof(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_pprof_unknown_sample_type() {
+    let mut options = Options::default();
+    options.sample_type = Some("alloc_space".to_string());
+    let mut output = Vec::new();
+    let error = Folder::from(options)
+        .collapse_file(Some("./tests/data/collapse-pprof/cpu.pb.gz"), &mut output)
+        .unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    assert!(error.to_string().contains("samples, cpu"));
+}
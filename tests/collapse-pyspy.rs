@@ -0,0 +1,38 @@
+mod common;
+
+use std::io;
+
+use inferno::collapse::pyspy::{Folder, Options};
+
+fn test_collapse_pyspy(test_file: &str, expected_file: &str, options: Options) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+#[test]
+fn collapse_pyspy_default() {
+    let test_file = "./tests/data/collapse-pyspy/raw.txt";
+    let result_file = "./tests/data/collapse-pyspy/results/raw-collapsed.txt";
+    test_collapse_pyspy(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_pyspy_no_lines_no_threads() {
+    let test_file = "./tests/data/collapse-pyspy/raw.txt";
+    let result_file = "./tests/data/collapse-pyspy/results/raw-no-lines-no-threads.txt";
+    let mut options = Options::default();
+    options.no_lines = true;
+    options.no_threads = true;
+    options.no_processes = true;
+    test_collapse_pyspy(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_pyspy_status() {
+    let test_file = "./tests/data/collapse-pyspy/raw.txt";
+    let result_file = "./tests/data/collapse-pyspy/results/raw-status.txt";
+    let mut options = Options::default();
+    options.no_files = true;
+    options.skip_idle = true;
+    options.annotate_status = true;
+    test_collapse_pyspy(test_file, result_file, options).unwrap()
+}
@@ -6,6 +6,7 @@ use std::process::{Command, Stdio};
 
 use assert_cmd::cargo::CommandCargoExt;
 use inferno::collapse::recursive::{Folder, Options};
+use regex::Regex;
 
 fn test_collapse_recursive(
     test_file: &str,
@@ -27,6 +28,52 @@ fn collapse_recursive_basic() {
     test_collapse_recursive(test_file, result_file, Options::default()).unwrap()
 }
 
+#[test]
+fn collapse_recursive_annotate_depth() {
+    let test_file = "./tests/data/collapse-recursive/basic.txt";
+    let result_file = "./tests/data/collapse-recursive/results/basic-annotate-depth.txt";
+
+    let mut options = Options::default();
+    options.annotate_depth = true;
+
+    test_collapse_recursive(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_recursive_include() {
+    let test_file = "./tests/data/collapse-recursive/selective.txt";
+    let result_file = "./tests/data/collapse-recursive/results/selective-collapsed.txt";
+
+    let mut options = Options::default();
+    options.include = Some(Regex::new("^eval$").unwrap());
+
+    test_collapse_recursive(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_recursive_exclude() {
+    let test_file = "./tests/data/collapse-recursive/selective.txt";
+    let result_file = "./tests/data/collapse-recursive/results/selective-collapsed.txt";
+
+    let mut options = Options::default();
+    options.exclude = Some(Regex::new("^fib$").unwrap());
+
+    test_collapse_recursive(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_recursive_max_repeats() {
+    let test_file = "./tests/data/collapse-recursive/selective.txt";
+    let result_file = "./tests/data/collapse-recursive/results/selective-max-repeats.txt";
+
+    let mut options = Options::default();
+    options.annotate_depth = true;
+    options.max_repeats = 2;
+    options.include = Some(Regex::new("^eval$").unwrap());
+
+    test_collapse_recursive(test_file, result_file, options).unwrap()
+}
+
 #[test]
 fn collapse_recursive_cli() {
     let input_file = "./tests/data/collapse-recursive/basic.txt";
@@ -6,22 +6,30 @@ use std::process::{Command, Stdio};
 
 use assert_cmd::prelude::*;
 use inferno::collapse::sample::{Folder, Options};
+use inferno::collapse::Prefix;
 use log::Level;
 use pretty_assertions::assert_eq;
 use testing_logger::CapturedLog;
 
 fn test_collapse_sample(test_file: &str, expected_file: &str, options: Options) -> io::Result<()> {
-    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+    for &n in &[1, 2] {
+        let mut options = options.clone();
+        options.nthreads = n;
+        common::test_collapse(Folder::from(options), test_file, expected_file, false)?;
+    }
+    Ok(())
 }
 
 fn test_collapse_sample_error(test_file: &str, options: Options) -> io::Error {
     common::test_collapse_error(Folder::from(options), test_file)
 }
 
-fn test_collapse_sample_logs_with_options<F>(input_file: &str, asserter: F, options: Options)
+fn test_collapse_sample_logs_with_options<F>(input_file: &str, asserter: F, mut options: Options)
 where
     F: Fn(&Vec<CapturedLog>),
 {
+    // We must run log tests in a single thread to play nicely with `testing_logger`.
+    options.nthreads = 1;
     common::test_collapse_logs(Folder::from(options), input_file, asserter);
 }
 
@@ -50,6 +58,85 @@ fn collapse_sample_no_modules() {
     test_collapse_sample(test_file, result_file, options).unwrap()
 }
 
+#[test]
+fn collapse_sample_prefix() {
+    let test_file = "./tests/data/collapse-sample/threads.txt";
+    for (prefix, name) in [
+        (Prefix::None, "none"),
+        (Prefix::Process, "process"),
+        (Prefix::Thread, "thread"),
+    ] {
+        let result_file = format!(
+            "./tests/data/collapse-sample/results/threads-prefix-{}.txt",
+            name
+        );
+        let mut options = Options::default();
+        options.prefix = Some(prefix);
+        test_collapse_sample(test_file, &result_file, options).unwrap()
+    }
+}
+
+#[test]
+fn collapse_sample_threads_default() {
+    let test_file = "./tests/data/collapse-sample/threads.txt";
+    let result_file = "./tests/data/collapse-sample/results/threads-default.txt";
+    test_collapse_sample(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_sample_threads_names_and_states() {
+    let test_file = "./tests/data/collapse-sample/threads.txt";
+    let result_file = "./tests/data/collapse-sample/results/threads-names-states.txt";
+
+    let mut options = Options::default();
+    options.thread_names = true;
+    options.dispatch_queues = true;
+    options.annotate_states = true;
+
+    test_collapse_sample(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_sample_threads_with_the_same_name() {
+    let test_file = "./tests/data/collapse-sample/named-threads.txt";
+    let result_file = "./tests/data/collapse-sample/results/named-threads-merged.txt";
+
+    let mut options = Options::default();
+    options.thread_names = true;
+    options.annotate_states = true;
+
+    test_collapse_sample(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_sample_threads_with_the_same_name_without_states() {
+    let test_file = "./tests/data/collapse-sample/named-threads.txt";
+    let result_file = "./tests/data/collapse-sample/results/named-threads-merged-no-states.txt";
+
+    let mut options = Options::default();
+    options.thread_names = true;
+
+    test_collapse_sample(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_sample_unknown_frames_default() {
+    let test_file = "./tests/data/collapse-sample/unknown-frames.txt";
+    let result_file = "./tests/data/collapse-sample/results/unknown-frames-default.txt";
+    test_collapse_sample(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_sample_unknown_frames_resolved() {
+    let test_file = "./tests/data/collapse-sample/unknown-frames.txt";
+    let result_file = "./tests/data/collapse-sample/results/unknown-frames-resolved.txt";
+
+    let mut options = Options::default();
+    options.resolve_unknown = true;
+
+    test_collapse_sample(test_file, result_file, options).unwrap()
+}
+
 #[test]
 fn collapse_sample_should_log_warning_for_ending_before_call_graph_start() {
     test_collapse_sample_logs(
@@ -87,7 +174,7 @@ fn collapse_sample_should_return_error_for_no_four_spaces() {
     assert_eq!(error.kind(), io::ErrorKind::InvalidData);
     assert!(error
         .to_string()
-        .starts_with("Stack line doesn't start with 4 spaces"));
+        .starts_with("line 22: Stack line doesn't start with 4 spaces"));
 }
 
 #[test]
@@ -97,7 +184,7 @@ fn collapse_sample_should_return_error_for_odd_number_of_indent_chars() {
     assert_eq!(error.kind(), io::ErrorKind::InvalidData);
     assert!(error
         .to_string()
-        .starts_with("Odd number of indentation characters for line"));
+        .starts_with("line 22: Odd number of indentation characters for line"));
 }
 
 #[test]
@@ -107,7 +194,7 @@ fn collapse_sample_should_return_error_for_skipped_indent_level() {
     assert_eq!(error.kind(), io::ErrorKind::InvalidData);
     assert!(error
         .to_string()
-        .starts_with("Skipped indentation level at line"));
+        .starts_with("line 23: Skipped indentation level at line"));
 }
 
 #[test]
@@ -115,7 +202,9 @@ fn collapse_sample_should_return_error_for_invalid_samples_field() {
     let test_file = "./tests/data/collapse-sample/invalid-samples-field.txt";
     let error = test_collapse_sample_error(test_file, Options::default());
     assert_eq!(error.kind(), io::ErrorKind::InvalidData);
-    assert!(error.to_string().starts_with("Invalid samples field"));
+    assert!(error
+        .to_string()
+        .starts_with("line 22: Invalid samples field"));
 }
 
 #[test]
@@ -123,7 +212,9 @@ fn collapse_sample_should_return_error_for_bad_stack_line() {
     let test_file = "./tests/data/collapse-sample/bad-stack-line.txt";
     let error = test_collapse_sample_error(test_file, Options::default());
     assert_eq!(error.kind(), io::ErrorKind::InvalidData);
-    assert!(error.to_string().starts_with("Unable to parse stack line"));
+    assert!(error
+        .to_string()
+        .starts_with("line 22: Unable to parse stack line"));
 }
 
 #[test]
@@ -133,7 +224,7 @@ fn collapse_sample_should_return_error_for_stack_line_with_only_indent_chars() {
     assert_eq!(error.kind(), io::ErrorKind::InvalidData);
     assert!(error
         .to_string()
-        .starts_with("Found stack line with only indent characters"));
+        .starts_with("line 22: Found stack line with only indent characters"));
 }
 
 #[test]
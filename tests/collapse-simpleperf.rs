@@ -0,0 +1,32 @@
+mod common;
+
+use std::io;
+
+use inferno::collapse::simpleperf::{Folder, Options};
+
+fn test_collapse_simpleperf(
+    test_file: &str,
+    expected_file: &str,
+    options: Options,
+) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+#[test]
+fn collapse_simpleperf_default() {
+    let test_file = "./tests/data/collapse-simpleperf/report-sample.txt";
+    let result_file = "./tests/data/collapse-simpleperf/results/report-sample-collapsed.txt";
+    test_collapse_simpleperf(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_simpleperf_annotated() {
+    let test_file = "./tests/data/collapse-simpleperf/report-sample.txt";
+    let result_file = "./tests/data/collapse-simpleperf/results/report-sample-annotated.txt";
+    let mut options = Options::default();
+    options.annotate_jit = true;
+    options.annotate_kernel = true;
+    options.include_process = true;
+    options.count_samples = true;
+    test_collapse_simpleperf(test_file, result_file, options).unwrap()
+}
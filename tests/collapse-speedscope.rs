@@ -0,0 +1,47 @@
+mod common;
+
+use std::io;
+
+use inferno::collapse::speedscope::{Folder, Options};
+
+fn test_collapse_speedscope(
+    test_file: &str,
+    expected_file: &str,
+    options: Options,
+) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+#[test]
+fn collapse_speedscope_sampled() {
+    let test_file = "./tests/data/collapse-speedscope/profile.speedscope.json";
+    let result_file = "./tests/data/collapse-speedscope/results/profile-collapsed.txt";
+    test_collapse_speedscope(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_speedscope_count_samples() {
+    let test_file = "./tests/data/collapse-speedscope/profile.speedscope.json";
+    let result_file = "./tests/data/collapse-speedscope/results/profile-count-samples.txt";
+    let mut options = Options::default();
+    options.count_samples = true;
+    test_collapse_speedscope(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_speedscope_evented() {
+    let test_file = "./tests/data/collapse-speedscope/profile.speedscope.json";
+    let result_file = "./tests/data/collapse-speedscope/results/profile-worker.txt";
+    let mut options = Options::default();
+    options.profile = Some("worker".to_string());
+    test_collapse_speedscope(test_file, result_file, options.clone()).unwrap();
+    options.profile = Some("1".to_string());
+    test_collapse_speedscope(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_speedscope_fractional_weights() {
+    let test_file = "./tests/data/collapse-speedscope/fractional.speedscope.json";
+    let result_file = "./tests/data/collapse-speedscope/results/fractional-collapsed.txt";
+    test_collapse_speedscope(test_file, result_file, Options::default()).unwrap()
+}
@@ -0,0 +1,29 @@
+mod common;
+
+use std::io;
+
+use inferno::collapse::stackprof::{Folder, Options};
+
+fn test_collapse_stackprof(
+    test_file: &str,
+    expected_file: &str,
+    options: Options,
+) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+#[test]
+fn collapse_stackprof_default() {
+    let test_file = "./tests/data/collapse-stackprof/app.json";
+    let result_file = "./tests/data/collapse-stackprof/results/app-collapsed.txt";
+    test_collapse_stackprof(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_stackprof_locations() {
+    let test_file = "./tests/data/collapse-stackprof/app.json";
+    let result_file = "./tests/data/collapse-stackprof/results/app-locations.txt";
+    let mut options = Options::default();
+    options.include_locations = true;
+    test_collapse_stackprof(test_file, result_file, options).unwrap()
+}
@@ -0,0 +1,25 @@
+mod common;
+
+use std::io;
+
+use inferno::collapse::svg::{Folder, Options};
+
+fn test_collapse_svg(test_file: &str, expected_file: &str, options: Options) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+#[test]
+fn collapse_svg_default() {
+    let test_file = "./tests/data/collapse-svg/flamegraph.svg";
+    let result_file = "./tests/data/collapse-svg/results/flamegraph.txt";
+    test_collapse_svg(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_svg_keep_root() {
+    let test_file = "./tests/data/collapse-svg/flamegraph.svg";
+    let result_file = "./tests/data/collapse-svg/results/flamegraph-keep-root.txt";
+    let mut options = Options::default();
+    options.keep_root = true;
+    test_collapse_svg(test_file, result_file, options).unwrap()
+}
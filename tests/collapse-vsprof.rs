@@ -5,13 +5,13 @@ use std::io::{self, BufReader, Cursor};
 use std::process::{Command, Stdio};
 
 use assert_cmd::prelude::*;
-use inferno::collapse::vsprof::Folder;
+use inferno::collapse::vsprof::{Folder, ModuleNames, Options, Weight};
 use log::Level;
 use pretty_assertions::assert_eq;
 use testing_logger::CapturedLog;
 
-fn test_collapse_vsprof(test_file: &str, expected_file: &str) -> io::Result<()> {
-    common::test_collapse(Folder::default(), test_file, expected_file, false)
+fn test_collapse_vsprof(test_file: &str, expected_file: &str, options: Options) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
 }
 
 fn test_collapse_vsprof_error(test_file: &str) -> io::Error {
@@ -29,7 +29,147 @@ where
 fn collapse_vsprof_default() {
     let test_file = "./tests/data/collapse-vsprof/CallTreeSummary.csv";
     let result_file = "./tests/data/collapse-vsprof/results/sample-default.txt";
-    test_collapse_vsprof(test_file, result_file).unwrap()
+    test_collapse_vsprof(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_vsprof_caller_callee() {
+    let test_file = "./tests/data/collapse-vsprof/CallerCalleeSummary.csv";
+    let result_file = "./tests/data/collapse-vsprof/results/caller-callee-default.txt";
+    test_collapse_vsprof(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_vsprof_cpu_usage() {
+    let test_file = "./tests/data/collapse-vsprof/CpuUsage.csv";
+    let result_file = "./tests/data/collapse-vsprof/results/cpu-usage-default.txt";
+    test_collapse_vsprof(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_vsprof_utf16() {
+    let test_file = "./tests/data/collapse-vsprof/CallTreeSummaryUtf16.csv";
+    let result_file = "./tests/data/collapse-vsprof/results/sample-default.txt";
+    test_collapse_vsprof(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_vsprof_utf16_big_endian() {
+    let test_file = "./tests/data/collapse-vsprof/CpuUsageUtf16BE.csv";
+    let result_file = "./tests/data/collapse-vsprof/results/cpu-usage-default.txt";
+    test_collapse_vsprof(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_vsprof_inclusive_time() {
+    let test_file = "./tests/data/collapse-vsprof/CallTreeTimes.csv";
+    let result_file = "./tests/data/collapse-vsprof/results/times-inclusive.txt";
+
+    let mut options = Options::default();
+    options.weight = Weight::InclusiveTime;
+
+    test_collapse_vsprof(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_vsprof_exclusive_time() {
+    let test_file = "./tests/data/collapse-vsprof/CallTreeTimes.csv";
+    let result_file = "./tests/data/collapse-vsprof/results/times-exclusive.txt";
+
+    let mut options = Options::default();
+    options.weight = Weight::ExclusiveTime;
+
+    test_collapse_vsprof(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_vsprof_localized() {
+    let test_file = "./tests/data/collapse-vsprof/CallTreeLocalized.csv";
+    let result_file = "./tests/data/collapse-vsprof/results/times-exclusive.txt";
+
+    let mut options = Options::default();
+    options.weight = Weight::ExclusiveTime;
+
+    test_collapse_vsprof(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_vsprof_localized_other_columns() {
+    let test_file = "./tests/data/collapse-vsprof/CallTreeLocalizedColumns.csv";
+    let result_file = "./tests/data/collapse-vsprof/results/localized-columns-annotate.txt";
+
+    let mut options = Options::default();
+    options.module_names = ModuleNames::Annotate;
+
+    test_collapse_vsprof(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_vsprof_column_indices() {
+    let test_file = "./tests/data/collapse-vsprof/CallTreeCustomColumns.csv";
+    let result_file = "./tests/data/collapse-vsprof/results/custom-columns.txt";
+
+    let mut options = Options::default();
+    options.column_indices = Some([2, 1, 0]);
+
+    test_collapse_vsprof(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_vsprof_caller_callee_exclusive_time() {
+    let test_file = "./tests/data/collapse-vsprof/CallerCalleeSummary.csv";
+    let result_file = "./tests/data/collapse-vsprof/results/caller-callee-exclusive-time.txt";
+
+    let mut options = Options::default();
+    options.weight = Weight::ExclusiveTime;
+
+    test_collapse_vsprof(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_vsprof_modules_annotate() {
+    let test_file = "./tests/data/collapse-vsprof/CallTreeTimes.csv";
+    let result_file = "./tests/data/collapse-vsprof/results/modules-annotate.txt";
+
+    let mut options = Options::default();
+    options.module_names = ModuleNames::Annotate;
+
+    test_collapse_vsprof(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_vsprof_modules_frame() {
+    let test_file = "./tests/data/collapse-vsprof/CallTreeTimes.csv";
+    let result_file = "./tests/data/collapse-vsprof/results/modules-frame-exclusive-time.txt";
+
+    let mut options = Options::default();
+    options.module_names = ModuleNames::Frame;
+    options.weight = Weight::ExclusiveTime;
+
+    test_collapse_vsprof(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_vsprof_reordered_columns() {
+    let test_file = "./tests/data/collapse-vsprof/CallTreeReordered.csv";
+    let result_file = "./tests/data/collapse-vsprof/results/modules-frame-exclusive-time.txt";
+
+    let mut options = Options::default();
+    options.module_names = ModuleNames::Frame;
+    options.weight = Weight::ExclusiveTime;
+
+    test_collapse_vsprof(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_vsprof_without_time_columns() {
+    let test_file = "./tests/data/collapse-vsprof/CallTreeCalls.csv";
+    let result_file = "./tests/data/collapse-vsprof/results/modules-annotate.txt";
+
+    let mut options = Options::default();
+    options.module_names = ModuleNames::Annotate;
+
+    test_collapse_vsprof(test_file, result_file, options).unwrap()
 }
 
 #[test]
@@ -52,6 +192,28 @@ fn collapse_vsprof_should_log_warning_for_ending_before_call_graph_start() {
     );
 }
 
+#[test]
+fn collapse_vsprof_should_cap_the_stacks_of_shared_callees() {
+    // Every function calls the next one through two others, so there are 2^30 paths to the last.
+    test_collapse_vsprof_logs(
+        "./tests/data/collapse-vsprof/CallerCalleeDiamonds.csv",
+        |captured_logs| {
+            let nwarnings = captured_logs
+                .iter()
+                .filter(|log| {
+                    log.body.starts_with("The call graph has too many paths")
+                        && log.level == Level::Warn
+                })
+                .count();
+            assert_eq!(
+                nwarnings, 1,
+                "warning logged {} times, but should be logged exactly once",
+                nwarnings
+            );
+        },
+    );
+}
+
 #[test]
 fn collapse_vsprof_should_return_error_for_incorrect_header() {
     let test_file = "./tests/data/collapse-vsprof/incorrect-header.csv";
@@ -59,7 +221,21 @@ fn collapse_vsprof_should_return_error_for_incorrect_header() {
     assert_eq!(error.kind(), io::ErrorKind::InvalidData);
     assert!(error
         .to_string()
-        .starts_with("Expected first line to be header line"));
+        .starts_with("line 1: Expected first line to be header line"));
+}
+
+#[test]
+fn collapse_vsprof_should_return_error_for_missing_time_column() {
+    let test_file = "./tests/data/collapse-vsprof/CallTreeCalls.csv";
+
+    let mut options = Options::default();
+    options.weight = Weight::InclusiveTime;
+
+    let error = common::test_collapse_error(Folder::from(options), test_file);
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert!(error
+        .to_string()
+        .starts_with("line 1: Missing column 'Avg Elapsed Inclusive Time' in header"));
 }
 
 #[test]
@@ -69,7 +245,7 @@ fn collapse_vsprof_should_return_error_for_missing_function_name() {
     assert_eq!(error.kind(), io::ErrorKind::InvalidData);
     assert!(error
         .to_string()
-        .starts_with("Missing function name in line:"));
+        .starts_with("line 2: Missing function name in line:"));
 }
 
 #[test]
@@ -79,7 +255,7 @@ fn collapse_vsprof_should_return_error_for_invalid_function_name() {
     assert_eq!(error.kind(), io::ErrorKind::InvalidData);
     assert!(error
         .to_string()
-        .starts_with("Unable to parse function name from line:"));
+        .starts_with("line 3: Unable to parse function name from line:"));
 }
 
 #[test]
@@ -89,7 +265,18 @@ fn collapse_vsprof_should_return_error_for_invalid_depth() {
     assert_eq!(error.kind(), io::ErrorKind::InvalidData);
     assert!(error
         .to_string()
-        .starts_with("Unable to parse integer from"));
+        .starts_with("line 3: Unable to parse integer from"));
+}
+
+#[test]
+fn collapse_vsprof_should_return_error_for_wrongly_inferred_columns() {
+    // The first whole number, the number of calls, is taken to be the level.
+    let test_file = "./tests/data/collapse-vsprof/CallTreeCustomColumns.csv";
+    let error = test_collapse_vsprof_error(test_file);
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert!(error
+        .to_string()
+        .starts_with("line 4: Skipped indentation level at line"));
 }
 
 #[test]
@@ -99,7 +286,7 @@ fn collapse_vsprof_should_return_error_for_invalid_number_of_calls() {
     assert_eq!(error.kind(), io::ErrorKind::InvalidData);
     assert!(error
         .to_string()
-        .starts_with("Unable to parse integer from"));
+        .starts_with("line 3: Unable to parse integer from"));
 }
 
 #[test]
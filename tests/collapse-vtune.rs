@@ -5,23 +5,30 @@ use std::io::{self, BufReader, Cursor};
 use std::process::{Command, Stdio};
 
 use assert_cmd::prelude::*;
-use inferno::collapse::vtune::{Folder, Options};
+use inferno::collapse::vtune::{Folder, ModuleNames, Options};
 use log::Level;
 use pretty_assertions::assert_eq;
 use testing_logger::CapturedLog;
 
 fn test_collapse_vtune(test_file: &str, expected_file: &str, options: Options) -> io::Result<()> {
-    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+    for &n in &[1, 2] {
+        let mut options = options.clone();
+        options.nthreads = n;
+        common::test_collapse(Folder::from(options), test_file, expected_file, false)?;
+    }
+    Ok(())
 }
 
 fn test_collapse_vtune_error(test_file: &str, options: Options) -> io::Error {
     common::test_collapse_error(Folder::from(options), test_file)
 }
 
-fn test_collapse_vtune_logs_with_options<F>(input_file: &str, asserter: F, options: Options)
+fn test_collapse_vtune_logs_with_options<F>(input_file: &str, asserter: F, mut options: Options)
 where
     F: Fn(&Vec<CapturedLog>),
 {
+    // We must run log tests in a single thread to play nicely with `testing_logger`.
+    options.nthreads = 1;
     common::test_collapse_logs(Folder::from(options), input_file, asserter);
 }
 
@@ -50,6 +57,64 @@ fn collapse_vtune_no_modules() {
     test_collapse_vtune(test_file, result_file, options).unwrap()
 }
 
+#[test]
+fn collapse_vtune_module_names() {
+    let test_file = "./tests/data/collapse-vtune/modules.csv";
+    for (module_names, suffix) in [
+        (ModuleNames::Annotate, "annotate"),
+        (ModuleNames::Frame, "frame"),
+        (ModuleNames::Collapse, "collapse"),
+    ] {
+        let result_file = format!("./tests/data/collapse-vtune/results/modules-{}.txt", suffix);
+        let mut options = Options::default();
+        options.module_names = module_names;
+        test_collapse_vtune(test_file, &result_file, options).unwrap()
+    }
+}
+
+#[test]
+fn collapse_vtune_quoted_fields() {
+    let test_file = "./tests/data/collapse-vtune/quoted-fields.csv";
+    let result_file = "./tests/data/collapse-vtune/results/quoted-fields.txt";
+    test_collapse_vtune(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_vtune_events_default() {
+    let test_file = "./tests/data/collapse-vtune/events.csv";
+    let result_file = "./tests/data/collapse-vtune/results/events-default.txt";
+    test_collapse_vtune(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_vtune_events_weight_column() {
+    let test_file = "./tests/data/collapse-vtune/events.csv";
+    let result_file = "./tests/data/collapse-vtune/results/events-instructions-retired.txt";
+
+    let mut options = Options::default();
+    options.weight_column = Some("Instructions Retired".to_string());
+
+    test_collapse_vtune(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_vtune_threads_default() {
+    let test_file = "./tests/data/collapse-vtune/threads.csv";
+    let result_file = "./tests/data/collapse-vtune/results/threads-default.txt";
+    test_collapse_vtune(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_vtune_threads_group_roots() {
+    let test_file = "./tests/data/collapse-vtune/threads.csv";
+    let result_file = "./tests/data/collapse-vtune/results/threads-group-roots.txt";
+
+    let mut options = Options::default();
+    options.group_roots = true;
+
+    test_collapse_vtune(test_file, result_file, options).unwrap()
+}
+
 #[test]
 fn collapse_vtune_should_log_warning_for_ending_before_header() {
     test_collapse_vtune_logs(
@@ -75,7 +140,7 @@ fn collapse_vtune_should_return_error_for_skipped_indent_level() {
     assert_eq!(error.kind(), io::ErrorKind::InvalidData);
     assert!(error
         .to_string()
-        .starts_with("Skipped indentation level at line"));
+        .starts_with("line 7: Skipped indentation level at line"));
 }
 
 #[test]
@@ -85,7 +150,19 @@ fn collapse_vtune_should_return_error_for_invalid_time_field() {
     assert_eq!(error.kind(), io::ErrorKind::InvalidData);
     assert!(error
         .to_string()
-        .starts_with("Invalid `CPU Time:Self` field"));
+        .starts_with("line 2: Invalid `CPU Time:Self` field"));
+}
+
+#[test]
+fn collapse_vtune_should_return_error_for_missing_weight_column() {
+    let test_file = "./tests/data/collapse-vtune/vtune.csv";
+    let mut options = Options::default();
+    options.weight_column = Some("Clockticks".to_string());
+    let error = test_collapse_vtune_error(test_file, options);
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert!(error
+        .to_string()
+        .starts_with("line 1: Weight column `Clockticks` not found"));
 }
 
 #[test]
@@ -93,7 +170,27 @@ fn collapse_vtune_should_return_error_for_bad_stack_line() {
     let test_file = "./tests/data/collapse-vtune/bad-stack-line.csv";
     let error = test_collapse_vtune_error(test_file, Options::default());
     assert_eq!(error.kind(), io::ErrorKind::InvalidData);
-    assert!(error.to_string().starts_with("Unable to parse stack line"));
+    assert!(error
+        .to_string()
+        .starts_with("line 7: Unable to parse stack line"));
+}
+
+#[test]
+fn collapse_vtune_error_should_be_structured() {
+    let test_file = "./tests/data/collapse-vtune/bad-stack-line.csv";
+    let error = test_collapse_vtune_error(test_file, Options::default());
+    match inferno::Error::from(error) {
+        inferno::Error::Parse {
+            format,
+            line,
+            message,
+        } => {
+            assert_eq!(format, "vtune");
+            assert_eq!(line, Some(7));
+            assert!(message.starts_with("Unable to parse stack line"));
+        }
+        e => panic!("expected a parse error, got {:?}", e),
+    }
 }
 
 #[test]
@@ -0,0 +1,42 @@
+mod common;
+
+use std::io;
+
+use inferno::collapse::wpa::{Folder, Options};
+
+fn test_collapse_wpa(test_file: &str, expected_file: &str, options: Options) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+#[test]
+fn collapse_wpa_tree() {
+    let test_file = "./tests/data/collapse-wpa/tree.csv";
+    let result_file = "./tests/data/collapse-wpa/results/tree-collapsed.txt";
+    test_collapse_wpa(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_wpa_tree_weight_column() {
+    let test_file = "./tests/data/collapse-wpa/tree.csv";
+    let result_file = "./tests/data/collapse-wpa/results/tree-collapsed.txt";
+    let mut options = Options::default();
+    options.weight_column = Some("Weight (in view) (ms)".to_string());
+    test_collapse_wpa(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_wpa_process_no_modules() {
+    let test_file = "./tests/data/collapse-wpa/tree.csv";
+    let result_file = "./tests/data/collapse-wpa/results/tree-process-no-modules.txt";
+    let mut options = Options::default();
+    options.include_process = true;
+    options.no_modules = true;
+    test_collapse_wpa(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_wpa_flat() {
+    let test_file = "./tests/data/collapse-wpa/flat.csv";
+    let result_file = "./tests/data/collapse-wpa/results/flat-collapsed.txt";
+    test_collapse_wpa(test_file, result_file, Options::default()).unwrap()
+}
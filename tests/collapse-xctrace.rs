@@ -0,0 +1,101 @@
+mod common;
+
+use std::io;
+
+use inferno::collapse::xctrace::{Folder, Options};
+use inferno::collapse::Collapse;
+use inferno::folded::Header;
+
+fn test_collapse_xctrace(test_file: &str, expected_file: &str, options: Options) -> io::Result<()> {
+    common::test_collapse(Folder::from(options), test_file, expected_file, false)
+}
+
+#[test]
+fn collapse_xctrace_default() {
+    let test_file = "./tests/data/collapse-xctrace/time-profile.xml";
+    let result_file = "./tests/data/collapse-xctrace/results/time-profile.txt";
+    test_collapse_xctrace(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_xctrace_weights() {
+    let test_file = "./tests/data/collapse-xctrace/time-profile.xml";
+    let result_file = "./tests/data/collapse-xctrace/results/time-profile-weights.txt";
+    let mut options = Options::default();
+    options.weights = true;
+    test_collapse_xctrace(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_xctrace_header() {
+    let mut options = Options::default();
+    options.weights = true;
+    options.header = true;
+    let mut folded = Vec::new();
+    Folder::from(options)
+        .collapse_file(
+            Some("./tests/data/collapse-xctrace/time-profile.xml"),
+            &mut folded,
+        )
+        .unwrap();
+    let folded = String::from_utf8(folded).unwrap();
+
+    let header = Header::parse(folded.lines()).unwrap();
+    assert!(header
+        .tool
+        .as_deref()
+        .unwrap()
+        .starts_with("inferno-collapse-xctrace "));
+    assert_eq!(header.event.as_deref(), Some("time-profile"));
+    assert_eq!(header.units.as_deref(), Some("nanoseconds"));
+}
+
+#[test]
+fn collapse_xctrace_thread_states() {
+    let test_file = "./tests/data/collapse-xctrace/time-profile.xml";
+    let result_file = "./tests/data/collapse-xctrace/results/time-profile-thread-states.txt";
+    let mut options = Options::default();
+    options.thread_states = true;
+    options.threads = vec!["worker".to_string()];
+    test_collapse_xctrace(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_xctrace_allocations() {
+    let test_file = "./tests/data/collapse-xctrace/allocations.xml";
+    let result_file = "./tests/data/collapse-xctrace/results/allocations.txt";
+    test_collapse_xctrace(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_xctrace_allocations_live_weights() {
+    let test_file = "./tests/data/collapse-xctrace/allocations.xml";
+    let result_file = "./tests/data/collapse-xctrace/results/allocations-live-weights.txt";
+    let mut options = Options::default();
+    options.live = true;
+    options.weights = true;
+    test_collapse_xctrace(test_file, result_file, options).unwrap()
+}
+
+#[test]
+fn collapse_xctrace_signposts() {
+    let test_file = "./tests/data/collapse-xctrace/signposts.xml";
+    let result_file = "./tests/data/collapse-xctrace/results/signposts.txt";
+    test_collapse_xctrace(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_xctrace_runs_added_up() {
+    let test_file = "./tests/data/collapse-xctrace/two-runs.xml";
+    let result_file = "./tests/data/collapse-xctrace/results/two-runs.txt";
+    test_collapse_xctrace(test_file, result_file, Options::default()).unwrap()
+}
+
+#[test]
+fn collapse_xctrace_runs_kept_apart() {
+    let test_file = "./tests/data/collapse-xctrace/two-runs.xml";
+    let result_file = "./tests/data/collapse-xctrace/results/two-runs-separate.txt";
+    let mut options = Options::default();
+    options.runs = true;
+    test_collapse_xctrace(test_file, result_file, options).unwrap()
+}
@@ -0,0 +1,168 @@
+#![cfg(feature = "config")]
+
+use std::fs;
+use std::io;
+use std::process::Command;
+
+use assert_cmd::cargo::CommandCargoExt;
+use inferno::collapse::perf;
+use inferno::config::{Config, FILE_NAME};
+use inferno::differential;
+use inferno::flamegraph::color::{BackgroundColor, BasicPalette, MultiPalette, Palette};
+use inferno::flamegraph::{self, CountUnit, FrameLinks, MultiInput};
+use pretty_assertions::assert_eq;
+
+const CONFIG: &str = r##"
+[flamegraph]
+colors = "java"
+bgcolors = "#ffffff"
+title = "Checkout service"
+min_width = 0.5
+count_unit = "requests"
+
+[differential]
+normalize = true
+
+[collapse.perf]
+include_addrs = true
+
+[collapse.perf.demangle]
+no_params = true
+"##;
+
+#[test]
+fn config_fills_in_flamegraph_options() {
+    let config: Config = CONFIG.parse().unwrap();
+    let options = config.flamegraph(flamegraph::Options::default()).unwrap();
+
+    assert_eq!(options.colors, Palette::Multi(MultiPalette::Java));
+    assert_eq!(
+        options.bgcolors,
+        Some(BackgroundColor::Flat(flamegraph::color::Color {
+            r: 255,
+            g: 255,
+            b: 255
+        }))
+    );
+    assert_eq!(options.title, "Checkout service");
+    assert_eq!(options.min_width, 0.5);
+    assert_eq!(
+        options.count_unit,
+        CountUnit::Custom("requests".to_string())
+    );
+}
+
+#[test]
+fn config_is_overruled_by_options_that_are_not_defaults() {
+    let config: Config = CONFIG.parse().unwrap();
+    let mut options = flamegraph::Options::default();
+    options.title = "Payment service".to_string();
+    options.colors = Palette::Basic(BasicPalette::Mem);
+    options.frame_links = Some(FrameLinks::new("https://example.com/{func}"));
+    let options = config.flamegraph(options).unwrap();
+
+    assert_eq!(options.title, "Payment service");
+    assert_eq!(options.colors, Palette::Basic(BasicPalette::Mem));
+    assert_eq!(options.min_width, 0.5);
+    assert_eq!(
+        options.frame_links.unwrap().url_for("main").as_deref(),
+        Some("https://example.com/main")
+    );
+}
+
+#[test]
+fn config_fills_in_collapse_and_differential_options() {
+    let config: Config = CONFIG.parse().unwrap();
+
+    let options = config.collapse("perf", perf::Options::default()).unwrap();
+    assert!(options.include_addrs);
+    assert!(options.demangle.no_params);
+    assert!(options.demangle.rust);
+
+    let options = config
+        .differential(differential::Options::default())
+        .unwrap();
+    assert!(options.normalize);
+    assert!(!options.strip_hex);
+}
+
+#[test]
+fn config_without_a_table_keeps_the_options() {
+    let config: Config = "[flamegraph]\ntitle = \"Checkout service\"\n"
+        .parse()
+        .unwrap();
+    let mut options = perf::Options::default();
+    options.annotate_jit = true;
+    let options = config.collapse("perf", options).unwrap();
+    assert!(options.annotate_jit);
+    assert!(!options.include_addrs);
+}
+
+#[test]
+fn config_with_invalid_toml_is_a_parse_error() {
+    let error = "[flamegraph]\ntitle = \"Checkout service\"\ncolors = \n"
+        .parse::<Config>()
+        .unwrap_err();
+    match error {
+        inferno::Error::Parse { format, line, .. } => {
+            assert_eq!(format, "toml");
+            assert!(line.is_some());
+        }
+        e => panic!("expected a parse error, got {:?}", e),
+    }
+}
+
+#[test]
+fn config_with_invalid_options_is_an_error() {
+    let config: Config = "[flamegraph]\ncolors = \"plaid\"\n".parse().unwrap();
+    let error = config
+        .flamegraph(flamegraph::Options::default())
+        .unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    assert!(error.to_string().contains("unknown color palette: plaid"));
+}
+
+#[test]
+fn flamegraph_options_round_trip_through_serde() {
+    let mut options = flamegraph::Options::default();
+    options.colors = Palette::Multi(MultiPalette::HotCold);
+    options.count_unit = CountUnit::Bytes;
+    options.max_depth = Some(12);
+    options.multi_input = MultiInput::Differential;
+
+    let json = serde_json::to_string(&options).unwrap();
+    let options: flamegraph::Options<'_> = serde_json::from_str(&json).unwrap();
+    assert_eq!(options.colors, Palette::Multi(MultiPalette::HotCold));
+    assert_eq!(options.count_unit, CountUnit::Bytes);
+    assert_eq!(options.max_depth, Some(12));
+    assert_eq!(options.multi_input, MultiInput::Differential);
+}
+
+#[test]
+fn flamegraph_cli_reads_config_file() {
+    let dir = std::env::temp_dir().join(format!("inferno-config-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join(FILE_NAME), CONFIG).unwrap();
+    fs::write(dir.join("stacks.folded"), "main;work 1\n").unwrap();
+
+    let output = Command::cargo_bin("inferno-flamegraph")
+        .unwrap()
+        .current_dir(&dir)
+        .arg("stacks.folded")
+        .output()
+        .expect("failed to execute process");
+    let overruled = Command::cargo_bin("inferno-flamegraph")
+        .unwrap()
+        .current_dir(&dir)
+        .arg("--title")
+        .arg("Payment service")
+        .arg("stacks.folded")
+        .output()
+        .expect("failed to execute process");
+    fs::remove_dir_all(&dir).unwrap();
+
+    let svg = String::from_utf8(output.stdout).unwrap();
+    assert!(svg.contains(">Checkout service<"), "{}", svg);
+    let svg = String::from_utf8(overruled.stdout).unwrap();
+    assert!(svg.contains(">Payment service<"), "{}", svg);
+}
@@ -0,0 +1,7 @@
+#!/bin/sh
+# A collapser plugin for a made-up format: a "# test-format" line followed by folded stacks.
+if [ "$1" = "--check" ]; then
+    read -r first && [ "$first" = "# test-format" ]
+    exit
+fi
+sed 1d
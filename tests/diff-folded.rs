@@ -6,6 +6,8 @@ use std::process::Command;
 
 use assert_cmd::cargo::CommandCargoExt;
 use inferno::differential::{self, Options};
+use inferno::folded::Header;
+use inferno::rewrite::Rules;
 use log::Level;
 use pretty_assertions::assert_eq;
 use testing_logger::CapturedLog;
@@ -22,7 +24,7 @@ fn test_diff_folded(
             if e.kind() == io::ErrorKind::NotFound {
                 // be nice to the dev and make the file
                 let mut f = File::create(expected_result_file).unwrap();
-                differential::from_files(options, infile1, infile2, &mut f)?;
+                differential::from_files(options.clone(), infile1, infile2, &mut f)?;
                 fs::metadata(expected_result_file).unwrap()
             } else {
                 return Err(e);
@@ -113,6 +115,15 @@ fn diff_folded_default() {
     test_diff_folded(infile1, infile2, expected_result_file, Default::default()).unwrap();
 }
 
+#[test]
+fn diff_folded_compressed_input() {
+    let infile1 = "./tests/data/diff-folded/before.txt.gz";
+    let infile2 = "./tests/data/diff-folded/after.txt";
+    let expected_result_file = "./tests/data/diff-folded/results/default.txt";
+
+    test_diff_folded(infile1, infile2, expected_result_file, Default::default()).unwrap();
+}
+
 #[test]
 fn diff_folded_normalize() {
     let infile1 = "./tests/data/diff-folded/before.txt";
@@ -126,6 +137,32 @@ fn diff_folded_normalize() {
     test_diff_folded(infile1, infile2, expected_result_file, opt).unwrap();
 }
 
+#[test]
+fn diff_folded_percentages() {
+    let infile1 = "./tests/data/diff-folded/before.txt";
+    let infile2 = "./tests/data/diff-folded/after.txt";
+    let expected_result_file = "./tests/data/diff-folded/results/percentages.txt";
+
+    let opt = Options {
+        percentages: true,
+        ..Default::default()
+    };
+    test_diff_folded(infile1, infile2, expected_result_file, opt).unwrap();
+}
+
+#[test]
+fn diff_folded_rewrite_rules() {
+    let infile1 = "./tests/data/diff-folded/hashes-before.txt";
+    let infile2 = "./tests/data/diff-folded/hashes-after.txt";
+    let expected_result_file = "./tests/data/diff-folded/results/rewrite_rules.txt";
+
+    let opt = Options {
+        rules: Rules::from_file("./tests/data/diff-folded/hashes.rules").unwrap(),
+        ..Default::default()
+    };
+    test_diff_folded(infile1, infile2, expected_result_file, opt).unwrap();
+}
+
 #[test]
 fn diff_folded_strip_hex() {
     let infile1 = "./tests/data/diff-folded/before.txt";
@@ -206,3 +243,32 @@ fn diff_folded_cli() {
     let expected = BufReader::new(File::open(expected_file).unwrap());
     compare_results(Cursor::new(output.stdout), expected, expected_file);
 }
+
+#[test]
+fn diff_folded_header_periods() {
+    let mut output = Vec::new();
+    differential::from_files(
+        Options::default(),
+        "./tests/data/diff-folded/header/before.txt",
+        "./tests/data/diff-folded/header/after.txt",
+        &mut output,
+    )
+    .unwrap();
+    let output = String::from_utf8(output).unwrap();
+
+    // The first profile is scaled to the period of the second.
+    let mut stacks: Vec<_> = output
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .collect();
+    stacks.sort_unstable();
+    assert_eq!(stacks, ["main;bar 10 5", "main;foo 5 10"]);
+
+    let header = Header::parse(output.lines()).unwrap();
+    assert!(header
+        .tool
+        .as_deref()
+        .unwrap()
+        .starts_with("inferno-diff-folded "));
+    assert_eq!(header.period, Some(2000.0));
+}
@@ -0,0 +1,70 @@
+use std::fs::File;
+use std::process::Command;
+
+use assert_cmd::cargo::CommandCargoExt;
+use inferno::downsample::{self, Options};
+use pretty_assertions::assert_eq;
+
+fn downsample(options: Options) -> String {
+    let mut output = Vec::new();
+    downsample::from_reader(
+        options,
+        File::open("./tests/data/downsample/stacks.txt").unwrap(),
+        &mut output,
+    )
+    .unwrap();
+    String::from_utf8(output).unwrap()
+}
+
+fn total(folded: &str) -> usize {
+    folded
+        .lines()
+        .map(|line| line.rsplit_once(' ').unwrap().1.parse::<usize>().unwrap())
+        .sum()
+}
+
+#[test]
+fn downsample_samples() {
+    let mut options = Options::default();
+    options.samples = Some(100);
+    assert_eq!(
+        downsample(options),
+        std::fs::read_to_string("./tests/data/downsample/results/samples.txt").unwrap()
+    );
+}
+
+#[test]
+fn downsample_stacks() {
+    let mut options = Options::default();
+    options.stacks = Some(3);
+    for seed in 0..10 {
+        options.seed = seed;
+        let output = downsample(options);
+        assert_eq!(output.lines().count(), 3);
+        assert_eq!(total(&output), 1000);
+    }
+}
+
+#[test]
+fn downsample_stacks_is_deterministic() {
+    let mut options = Options::default();
+    options.stacks = Some(2);
+    options.seed = 42;
+    assert_eq!(downsample(options), downsample(options));
+}
+
+#[test]
+fn downsample_cli() {
+    let output = Command::cargo_bin("inferno-downsample")
+        .unwrap()
+        .arg("--samples")
+        .arg("100")
+        .arg("./tests/data/downsample/stacks.txt")
+        .output()
+        .expect("failed to execute process");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        std::fs::read_to_string("./tests/data/downsample/results/samples.txt").unwrap()
+    );
+}
@@ -0,0 +1,394 @@
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::process::Command;
+
+use assert_cmd::cargo::CommandCargoExt;
+use inferno::export::{self, Format, Options};
+use libflate::gzip::Decoder;
+use pretty_assertions::assert_eq;
+
+#[derive(Debug, PartialEq)]
+enum Value {
+    Varint(u64),
+    Bytes(Vec<u8>),
+}
+
+fn read_varint(buf: &mut &[u8]) -> u64 {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = buf[0];
+        *buf = &buf[1..];
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+// Splits a protobuf message into its fields.
+fn read_fields(mut buf: &[u8]) -> Vec<(u64, Value)> {
+    let mut fields = Vec::new();
+    while !buf.is_empty() {
+        let key = read_varint(&mut buf);
+        let value = match key & 0x7 {
+            0 => Value::Varint(read_varint(&mut buf)),
+            2 => {
+                let len = read_varint(&mut buf) as usize;
+                let (bytes, rest) = buf.split_at(len);
+                buf = rest;
+                Value::Bytes(bytes.to_vec())
+            }
+            wire_type => panic!("unexpected wire type {}", wire_type),
+        };
+        fields.push((key >> 3, value));
+    }
+    fields
+}
+
+fn read_packed(mut buf: &[u8]) -> Vec<u64> {
+    let mut values = Vec::new();
+    while !buf.is_empty() {
+        values.push(read_varint(&mut buf));
+    }
+    values
+}
+
+fn bytes(fields: &[(u64, Value)], field: u64) -> Vec<&[u8]> {
+    fields
+        .iter()
+        .filter(|(number, _)| *number == field)
+        .map(|(_, value)| match value {
+            Value::Bytes(bytes) => &bytes[..],
+            Value::Varint(_) => panic!("field {} is not length-delimited", field),
+        })
+        .collect()
+}
+
+fn decompress(profile: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::new();
+    Decoder::new(profile)
+        .unwrap()
+        .read_to_end(&mut decoded)
+        .unwrap();
+    decoded
+}
+
+fn export_pprof(input_file: &str, options: &Options) -> Vec<(u64, Value)> {
+    let mut profile = Vec::new();
+    export::from_reader(options, File::open(input_file).unwrap(), &mut profile).unwrap();
+    read_fields(&decompress(&profile))
+}
+
+#[test]
+fn export_pprof_string_table() {
+    let profile = export_pprof("./tests/data/export/stacks.txt", &Options::default());
+    let strings: Vec<_> = bytes(&profile, 6)
+        .into_iter()
+        .map(|string| String::from_utf8(string.to_vec()).unwrap())
+        .collect();
+    assert_eq!(
+        strings,
+        ["", "samples", "count", "main", "foo", "bar", "baz"]
+    );
+}
+
+#[test]
+fn export_pprof_samples() {
+    let profile = export_pprof("./tests/data/export/stacks.txt", &Options::default());
+    let samples: Vec<_> = bytes(&profile, 2)
+        .into_iter()
+        .map(|sample| {
+            let sample = read_fields(sample);
+            (
+                read_packed(bytes(&sample, 1)[0]),
+                read_packed(bytes(&sample, 2)[0]),
+            )
+        })
+        .collect();
+    assert_eq!(
+        samples,
+        [
+            (vec![3, 2, 1], vec![4]),
+            (vec![2, 1], vec![2]),
+            (vec![4, 1], vec![1]),
+        ]
+    );
+}
+
+#[test]
+fn export_pprof_sample_type() {
+    let mut options = Options::default();
+    options.sample_type = "cpu".to_string();
+    options.sample_unit = "nanoseconds".to_string();
+    let profile = export_pprof("./tests/data/export/stacks.txt", &options);
+
+    let sample_type = read_fields(bytes(&profile, 1)[0]);
+    assert_eq!(sample_type, [(1, Value::Varint(1)), (2, Value::Varint(2))]);
+    let strings = bytes(&profile, 6);
+    assert_eq!(strings[1], b"cpu");
+    assert_eq!(strings[2], b"nanoseconds");
+}
+
+fn test_export(input_file: &str, expected_file: &str, options: &Options) {
+    let mut output = Vec::new();
+    export::from_reader(options, File::open(input_file).unwrap(), &mut output).unwrap();
+    let expected = std::fs::read_to_string(expected_file)
+        .unwrap()
+        .replace("{version}", env!("CARGO_PKG_VERSION"));
+    assert_eq!(String::from_utf8(output).unwrap(), expected);
+}
+
+#[test]
+fn export_speedscope() {
+    let mut options = Options::default();
+    options.format = Format::Speedscope;
+    test_export(
+        "./tests/data/export/stacks.txt",
+        "./tests/data/export/results/speedscope.json",
+        &options,
+    );
+}
+
+#[test]
+fn export_trace_event() {
+    let mut options = Options::default();
+    options.format = Format::TraceEvent;
+    test_export(
+        "./tests/data/export/stacks.txt",
+        "./tests/data/export/results/trace-event.json",
+        &options,
+    );
+}
+
+#[test]
+fn export_trace_event_thread_roots() {
+    let mut options = Options::default();
+    options.format = Format::TraceEvent;
+    options.sample_unit = "milliseconds".to_string();
+    options.thread_roots = true;
+    test_export(
+        "./tests/data/export/threads.txt",
+        "./tests/data/export/results/trace-event-thread-roots.json",
+        &options,
+    );
+}
+
+#[test]
+fn export_dot() {
+    let mut options = Options::default();
+    options.format = Format::Dot;
+    test_export(
+        "./tests/data/export/stacks.txt",
+        "./tests/data/export/results/dot.dot",
+        &options,
+    );
+}
+
+#[test]
+fn export_dot_min_percent() {
+    let mut options = Options::default();
+    options.format = Format::Dot;
+    options.min_percent = 20.0;
+    test_export(
+        "./tests/data/export/stacks.txt",
+        "./tests/data/export/results/dot-min-percent.dot",
+        &options,
+    );
+}
+
+#[test]
+fn export_firefox() {
+    let mut options = Options::default();
+    options.format = Format::Firefox;
+    test_export(
+        "./tests/data/export/stacks.txt",
+        "./tests/data/export/results/firefox.json",
+        &options,
+    );
+}
+
+#[test]
+fn export_stats() {
+    let mut options = Options::default();
+    options.format = Format::Stats;
+    test_export(
+        "./tests/data/export/stacks.txt",
+        "./tests/data/export/results/stats.json",
+        &options,
+    );
+}
+
+#[test]
+fn export_csv() {
+    let mut options = Options::default();
+    options.format = Format::Csv;
+    test_export(
+        "./tests/data/export/modules.txt",
+        "./tests/data/export/results/csv.csv",
+        &options,
+    );
+}
+
+#[test]
+fn export_csv_per_function() {
+    let mut options = Options::default();
+    options.format = Format::Csv;
+    options.per_function = true;
+    test_export(
+        "./tests/data/export/modules.txt",
+        "./tests/data/export/results/csv-per-function.csv",
+        &options,
+    );
+}
+
+#[test]
+fn export_parquet() {
+    let mut options = Options::default();
+    options.format = Format::Parquet;
+    let mut output = Vec::new();
+    let result = export::from_reader(
+        &options,
+        File::open("./tests/data/export/modules.txt").unwrap(),
+        &mut output,
+    );
+    if cfg!(feature = "parquet") {
+        result.unwrap();
+        assert!(output.starts_with(b"PAR1"));
+        assert!(output.ends_with(b"PAR1"));
+    } else {
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::Unsupported);
+    }
+}
+
+#[test]
+fn export_arrow() {
+    for (format, magic) in [
+        (Format::Arrow, &b"\xff\xff\xff\xff"[..]),
+        (Format::Feather, b"ARROW1"),
+    ] {
+        let mut options = Options::default();
+        options.format = format;
+        let mut output = Vec::new();
+        let result = export::from_reader(
+            &options,
+            File::open("./tests/data/export/modules.txt").unwrap(),
+            &mut output,
+        );
+        if cfg!(feature = "arrow") {
+            result.unwrap();
+            assert!(output.starts_with(magic));
+        } else {
+            assert_eq!(result.unwrap_err().kind(), io::ErrorKind::Unsupported);
+        }
+    }
+}
+
+#[test]
+fn export_cli() {
+    let input_file = "./tests/data/export/stacks.txt";
+
+    let output = Command::cargo_bin("inferno-export")
+        .unwrap()
+        .arg("--format")
+        .arg(Format::Pprof.to_string())
+        .arg(input_file)
+        .output()
+        .expect("failed to execute process");
+
+    let mut expected = Vec::new();
+    export::from_reader(
+        &Options::default(),
+        BufReader::new(File::open(input_file).unwrap()),
+        &mut expected,
+    )
+    .unwrap();
+    assert_eq!(decompress(&output.stdout), decompress(&expected));
+}
+
+#[cfg(feature = "push")]
+#[test]
+fn export_push() {
+    use std::io::{BufRead, Write};
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let server = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line).unwrap();
+        let mut content_length = 0;
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header).unwrap();
+            if header.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap();
+                }
+            }
+        }
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).unwrap();
+        reader
+            .get_mut()
+            .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+            .unwrap();
+        (request_line, String::from_utf8(body).unwrap())
+    });
+
+    let mut options = export::push::Options::default();
+    options.url = url;
+    options.app_name = "app.cpu".to_string();
+    options.labels = vec![("env".to_string(), "test".to_string())];
+    export::push::from_lines(&options, "main;foo 1\nmain;bar 2\nmain;foo 3".lines()).unwrap();
+
+    let (request_line, body) = server.join().unwrap();
+    assert!(request_line.starts_with("POST /ingest?name=app.cpu%7Benv%3Dtest%7D&format=folded"));
+    assert_eq!(body, "main;foo 4\nmain;bar 2\n");
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn export_sqlite_round_trip() {
+    let database = std::env::temp_dir().join(format!("inferno-export-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&database);
+
+    let mut options = export::sqlite::Options::default();
+    options.name = "first".to_string();
+    let first = export::sqlite::from_files(
+        &options,
+        &["./tests/data/export/stacks.txt".into()],
+        &database,
+    )
+    .unwrap();
+    options.name = "second".to_string();
+    let second =
+        export::sqlite::from_lines(&options, "main;foo;bar 5\nmain;qux 1".lines(), &database)
+            .unwrap();
+    assert_ne!(first, second);
+
+    let mut folded = Vec::new();
+    export::sqlite::to_folded(&database, "first", &mut folded).unwrap();
+    assert_eq!(
+        String::from_utf8(folded).unwrap(),
+        "main;foo;bar 4\nmain;foo 2\nmain;baz 1\n"
+    );
+
+    let mut folded = Vec::new();
+    export::sqlite::to_folded(&database, "second", &mut folded).unwrap();
+    assert_eq!(
+        String::from_utf8(folded).unwrap(),
+        "main;foo;bar 5\nmain;qux 1\n"
+    );
+
+    let error = export::sqlite::to_folded(&database, "third", Vec::new()).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::NotFound);
+
+    std::fs::remove_file(&database).unwrap();
+}
@@ -0,0 +1,65 @@
+#![cfg(feature = "ffi")]
+
+use std::ffi::CString;
+use std::{ptr, slice};
+
+use inferno::ffi::{self, InfernoStatus};
+
+// Calls an FFI function with the input, and returns its status and output.
+fn call<F>(input: &[u8], f: F) -> (InfernoStatus, String)
+where
+    F: FnOnce(*const u8, usize, *mut *mut u8, *mut usize) -> InfernoStatus,
+{
+    let mut out = ptr::null_mut();
+    let mut out_len = 0;
+    let status = f(input.as_ptr(), input.len(), &mut out, &mut out_len);
+    if out.is_null() {
+        return (status, String::new());
+    }
+    let output = unsafe { String::from_utf8_lossy(slice::from_raw_parts(out, out_len)) };
+    let output = output.into_owned();
+    unsafe { ffi::inferno_free(out, out_len) };
+    (status, output)
+}
+
+#[test]
+fn ffi_collapse_and_plot() {
+    let input = std::fs::read("./tests/data/collapse-perf/single-event.txt.gz").unwrap();
+    let (status, folded) = call(&input, |input, len, out, out_len| unsafe {
+        ffi::inferno_collapse_perf(input, len, out, out_len)
+    });
+    assert_eq!(status, InfernoStatus::Ok);
+    let expected =
+        std::fs::read_to_string("./tests/data/collapse-perf/results/single-event-collapsed.txt")
+            .unwrap();
+    assert_eq!(folded, expected);
+
+    let title = CString::new("From C").unwrap();
+    let format = CString::new("svg").unwrap();
+    let (status, svg) = call(folded.as_bytes(), |input, len, out, out_len| unsafe {
+        ffi::inferno_flamegraph(input, len, title.as_ptr(), format.as_ptr(), out, out_len)
+    });
+    assert_eq!(status, InfernoStatus::Ok);
+    assert!(svg.starts_with("<?xml"));
+    assert!(svg.contains(">From C</text>"));
+}
+
+#[test]
+fn ffi_errors() {
+    let (status, _) = call(b"", |_, len, out, out_len| unsafe {
+        ffi::inferno_collapse_perf(ptr::null(), len + 1, out, out_len)
+    });
+    assert_eq!(status, InfernoStatus::InvalidArgument);
+
+    let format = CString::new("gif").unwrap();
+    let (status, _) = call(b"main 1\n", |input, len, out, out_len| unsafe {
+        ffi::inferno_flamegraph(input, len, ptr::null(), format.as_ptr(), out, out_len)
+    });
+    assert_eq!(status, InfernoStatus::InvalidArgument);
+
+    let (status, output) = call(b"{not json", |input, len, out, out_len| unsafe {
+        ffi::inferno_collapse_speedscope(input, len, out, out_len)
+    });
+    assert_ne!(status, InfernoStatus::Ok);
+    assert!(output.is_empty());
+}
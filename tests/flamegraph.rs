@@ -5,10 +5,17 @@ use std::io::{self, BufRead, BufReader, Cursor};
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
 
 use assert_cmd::cargo::CommandCargoExt;
-use inferno::flamegraph::color::{BackgroundColor, PaletteMap};
-use inferno::flamegraph::{self, Direction, Options, Palette, TextTruncateDirection};
+use inferno::flamegraph::color::{
+    BackgroundColor, Color, ColorFn, FrameInfo, MultiPalette, PaletteMap,
+};
+use inferno::flamegraph::{
+    self, ColorMode, Direction, FoldedEncoding, FontMetrics, Format, FrameLinks, MultiInput,
+    Options, Palette, TextTruncateDirection, Theme,
+};
 use log::Level;
 use pretty_assertions::assert_eq;
 use testing_logger::CapturedLog;
@@ -375,6 +382,443 @@ fn flamegraph_should_not_warn_about_fractional_sample_with_tricky_stack() {
     );
 }
 
+#[test]
+fn flamegraph_fractional_samples() {
+    let input =
+        fs::read_to_string("./tests/data/flamegraph/fractional-samples/fractional.txt").unwrap();
+    let mut options = Options::default();
+    options.fractional_samples = true;
+    options.no_javascript = true;
+
+    testing_logger::setup();
+    let mut svg = Vec::new();
+    flamegraph::from_lines(&mut options, input.lines(), &mut svg).unwrap();
+    let svg = String::from_utf8(svg).unwrap();
+
+    assert!(svg.contains("all (334.618 samples, 100%)"));
+    assert!(svg.contains("main (274.321 samples, 81.98%)"));
+    assert!(svg.contains("[unknown] (2.567 samples, 0.77%)"));
+    testing_logger::validate(|captured_logs| {
+        assert!(
+            captured_logs.iter().all(|log| log.level != Level::Warn),
+            "no fractional samples should have been truncated"
+        );
+    });
+}
+
+#[test]
+fn flamegraph_count_unit() {
+    let render = |unit: &str, lines: &[&str]| {
+        let mut options = Options::default();
+        options.count_unit = unit.parse().unwrap();
+        options.no_javascript = true;
+        let mut svg = Vec::new();
+        flamegraph::from_lines(&mut options, lines.iter().copied(), &mut svg).unwrap();
+        String::from_utf8(svg).unwrap()
+    };
+
+    let svg = render("bytes", &["main;alloc 1536", "main;free 512"]);
+    assert!(svg.contains("all (2.00 KiB, 100%)"));
+    assert!(svg.contains("alloc (1.50 KiB, 75.00%)"));
+    assert!(svg.contains("free (512 B, 25.00%)"));
+
+    let svg = render("nanoseconds", &["main;sleep 2500000", "main;work 750"]);
+    assert!(svg.contains("sleep (2.50 ms, 99.97%)"));
+    assert!(svg.contains("work (750 ns, 0.03%)"));
+
+    let svg = render("allocations", &["main;alloc 12345"]);
+    assert!(svg.contains("alloc (12,345 allocations, 100.00%)"));
+}
+
+#[test]
+fn flamegraph_color_mode_weight() {
+    let mut options = Options::default();
+    options.color_mode = ColorMode::Weight;
+    options.weight_name = "misses".to_string();
+    options.no_javascript = true;
+    let mut svg = Vec::new();
+    flamegraph::from_lines(
+        &mut options,
+        ["main;fast 100 50", "main;slow 100 300", "main;unweighted 5"]
+            .iter()
+            .copied(),
+        &mut svg,
+    )
+    .unwrap();
+    let svg = String::from_utf8(svg).unwrap();
+
+    // Widths come from the counts, and lines without a weight are left out.
+    assert!(svg.contains("all (200 samples, 100%)"));
+    assert!(!svg.contains("unweighted"));
+    assert!(svg.contains("main (200 samples, 100.00%; 1.75 misses/samples)"));
+    assert!(svg.contains("fast (100 samples, 50.00%; 0.50 misses/samples)"));
+    assert!(svg.contains("slow (100 samples, 50.00%; 3.00 misses/samples)"));
+    assert!(svg.contains("Color: 0 to 3.00 misses/samples"));
+    // The frame with the most weight per sample gets the end of the gradient.
+    assert!(svg.contains(r#"fill="rgb(215,48,39)""#));
+}
+
+#[test]
+fn flamegraph_theme() {
+    let render = |theme: Theme| {
+        let mut options = Options::default();
+        options.theme = theme;
+        options.no_javascript = true;
+        let mut svg = Vec::new();
+        flamegraph::from_lines(&mut options, ["main;work 1"].iter().copied(), &mut svg).unwrap();
+        String::from_utf8(svg).unwrap()
+    };
+
+    let light = render(Theme::Light);
+    assert!(!light.contains("#1e1e1e"));
+
+    let dark = render(Theme::Dark);
+    assert!(dark.contains("#background stop { stop-color:#1e1e1e; }"));
+    assert!(!dark.contains("prefers-color-scheme"));
+
+    let auto = render(Theme::Auto);
+    assert!(auto.contains("@media (prefers-color-scheme: dark) {\n#background stop"));
+}
+
+#[test]
+fn flamegraph_frame_links() {
+    let mut options = Options::default();
+    options.frame_links = Some(
+        FrameLinks::new("https://src.example/{1}?q={func}")
+            .with_pattern("^([a-z]+)::")
+            .unwrap(),
+    );
+    options.no_javascript = true;
+    let mut svg = Vec::new();
+    flamegraph::from_lines(
+        &mut options,
+        ["main;tokio::spawn_[k] 1", "main;work 1"].iter().copied(),
+        &mut svg,
+    )
+    .unwrap();
+    let svg = String::from_utf8(svg).unwrap();
+
+    // Only the frames the pattern matches are linked, by their names without annotations.
+    assert!(svg.contains(
+        r#"<a xlink:href="https://src.example/tokio?q=tokio%3A%3Aspawn" target="_top">"#
+    ));
+    assert_eq!(svg.matches("<a ").count(), 1);
+}
+
+#[test]
+fn flamegraph_font_metrics() {
+    let narrow = "i".repeat(60);
+    let wide = "W".repeat(60);
+    let lines = [format!("main;{} 1", narrow), format!("main;{} 1", wide)];
+    let render = |font_metrics: Option<FontMetrics>| {
+        let mut options = Options::default();
+        options.font_metrics = font_metrics;
+        options.no_javascript = true;
+        let mut svg = Vec::new();
+        flamegraph::from_lines(&mut options, lines.iter().map(|l| l.as_str()), &mut svg).unwrap();
+        String::from_utf8(svg).unwrap()
+    };
+
+    // Every character is assumed to be as wide by default, so both names fit.
+    let svg = render(None);
+    assert!(svg.contains(&format!(">{}</text>", narrow)));
+    assert!(svg.contains(&format!(">{}</text>", wide)));
+
+    // Half of the flame graph is 50 font sizes wide, which fits 52 Ws of Arial and two dots.
+    let svg = render(FontMetrics::for_font("Arial"));
+    assert!(svg.contains(&format!(">{}</text>", narrow)));
+    assert!(svg.contains(&format!(">{}..</text>", "W".repeat(52))));
+}
+
+#[test]
+fn flamegraph_aggregate_pruned() {
+    let lines = [
+        "main;big 100",
+        "main;tiny_a 1",
+        "main;tiny_b 1",
+        "main;tiny_c;leaf 1",
+    ];
+    let render = |aggregate_pruned: bool| {
+        let mut options = Options::default();
+        options.min_width = 2.0;
+        options.aggregate_pruned = aggregate_pruned;
+        options.no_javascript = true;
+        let mut svg = Vec::new();
+        flamegraph::from_lines(&mut options, lines, &mut svg).unwrap();
+        String::from_utf8(svg).unwrap()
+    };
+
+    let svg = render(false);
+    assert!(!svg.contains("tiny_a"));
+    assert!(!svg.contains("omitted"));
+
+    let svg = render(true);
+    assert!(!svg.contains("tiny_a"));
+    assert!(svg.contains("<title>[3 frames omitted] (3 samples, 2.91%)</title>"));
+}
+
+#[test]
+fn flamegraph_max_depth() {
+    let mut options = Options::default();
+    options.max_depth = Some(2);
+    options.no_javascript = true;
+    let mut svg = Vec::new();
+    flamegraph::from_lines(
+        &mut options,
+        ["main;a;b;c;d 2", "main;a;b;e 3", "main;x 1"],
+        &mut svg,
+    )
+    .unwrap();
+    let svg = String::from_utf8(svg).unwrap();
+    assert!(svg.contains("<title>... (5 samples, 83.33%)</title>"));
+    assert!(svg.contains("<title>x (1 samples, 16.67%)</title>"));
+    assert!(!svg.contains("<title>b ("));
+}
+
+#[test]
+fn flamegraph_compact() {
+    // Like in real profiles, the same functions show up in many stacks.
+    let lines: Vec<_> = (0..500)
+        .map(|i| {
+            format!(
+                "main;worker;handle_{};parse_{};read_{} {}",
+                i / 50,
+                i / 5 % 10,
+                i % 5,
+                i + 1
+            )
+        })
+        .collect();
+    let render = |compact: bool| {
+        let mut options = Options::default();
+        options.compact = compact;
+        options.hash = true;
+        options.no_javascript = true;
+        let mut svg = Vec::new();
+        flamegraph::from_lines(&mut options, lines.iter().map(|l| l.as_str()), &mut svg).unwrap();
+        String::from_utf8(svg).unwrap()
+    };
+
+    let full = render(false);
+    let compact = render(true);
+    assert!(compact.contains(r#"class="c0""#));
+    assert!(compact.contains(r#"fg:n="0""#));
+    assert!(compact.contains(r#"<script type="application/json" id="compact">"#));
+    assert!(compact.contains(r#"{"names":["#));
+    assert!(compact.contains(r#""handle_9""#));
+    assert!(!compact.contains("<title>main"));
+    // Frames get their color from their class instead. The text elements of the title, details
+    // and search still have a fill of their own.
+    let rects = |svg: &str| -> Vec<String> {
+        svg.split('<')
+            .filter(|element| element.starts_with("rect "))
+            .map(str::to_string)
+            .collect()
+    };
+    assert!(rects(&full).iter().any(|rect| rect.contains("fill=\"rgb(")));
+    assert!(rects(&compact).len() > 200);
+    assert!(rects(&compact)
+        .iter()
+        .all(|rect| !rect.contains("fill=\"rgb(")));
+    assert!(compact.len() < full.len() * 2 / 3);
+}
+
+#[test]
+fn flamegraph_html() {
+    let mut options = Options::default();
+    options.format = Format::Html;
+    options.title = "<b>Flame</b> Graph".to_string();
+    options.no_javascript = true;
+    let mut html = Vec::new();
+    flamegraph::from_lines(
+        &mut options,
+        ["main;foo 3", "main;foo;bar 2", "main;baz</script> 5"],
+        &mut html,
+    )
+    .unwrap();
+    let html = String::from_utf8(html).unwrap();
+
+    assert!(html.starts_with("<!DOCTYPE html>"));
+    assert!(html.contains("<title>&lt;b&gt;Flame&lt;/b&gt; Graph</title>"));
+    let data = html
+        .split(r#"<script type="application/json" id="flamegraph-data">"#)
+        .nth(1)
+        .unwrap();
+    let data = &data[..data.find("</script>").unwrap()];
+    let data: serde_json::Value = serde_json::from_str(data).unwrap();
+
+    assert_eq!(data["total"], 10);
+    let names: Vec<_> = data["name"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|i| {
+            data["names"][i.as_u64().unwrap() as usize]
+                .as_str()
+                .unwrap()
+        })
+        .collect();
+    assert_eq!(names, ["", "main", "baz</script>", "foo", "bar"]);
+    assert_eq!(data["depth"], serde_json::json!([0, 1, 2, 2, 3]));
+    assert_eq!(data["width"], serde_json::json!([10, 10, 5, 5, 2]));
+    assert!(!html.contains("http-equiv"));
+}
+
+#[test]
+fn flamegraph_html_auto_reload() {
+    let mut options = Options::default();
+    options.format = Format::Html;
+    options.auto_reload = Some(2);
+    let mut html = Vec::new();
+    flamegraph::from_lines(&mut options, ["main;foo 3"], &mut html).unwrap();
+    let html = String::from_utf8(html).unwrap();
+    assert!(html.contains(r#"<meta http-equiv="refresh" content="2">"#));
+}
+
+#[test]
+fn flamegraph_write_atomically() {
+    let path = std::env::temp_dir().join(format!("inferno-atomic-{}.svg", std::process::id()));
+    let mut options = Options::default();
+    flamegraph::write_atomically(&path, |writer| {
+        flamegraph::from_lines(&mut options, ["main;foo 3"], writer)
+    })
+    .unwrap();
+    let written = fs::read_to_string(&path).unwrap();
+    assert!(written.contains("<title>foo (3 samples, 100.00%)</title>"));
+
+    // A flame graph that fails leaves the file as it was.
+    let result = flamegraph::write_atomically(&path, |writer| {
+        flamegraph::from_lines(&mut options, ["main;foo 3"], &mut *writer)?;
+        Err(quick_xml::Error::Io(io::Error::new(
+            io::ErrorKind::Other,
+            "no",
+        )))
+    });
+    let unchanged = fs::read_to_string(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    assert!(result.is_err());
+    assert_eq!(unchanged, written);
+}
+
+#[test]
+fn flamegraph_png() {
+    let mut options = Options::default();
+    options.format = Format::Png;
+    options.image_width = Some(300);
+    let mut png = Vec::new();
+    let result = flamegraph::from_lines(&mut options, ["main;foo 3", "main;bar 2"], &mut png);
+
+    if cfg!(feature = "png") {
+        result.unwrap();
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+        // The width of the image is the first field of the IHDR chunk.
+        assert_eq!(png[16..20], 300u32.to_be_bytes());
+    } else {
+        assert!(result.is_err());
+    }
+}
+
+#[test]
+fn flamegraph_json() {
+    let render = |lines: &[&str]| {
+        let mut options = Options::default();
+        options.format = Format::Json;
+        let mut json = Vec::new();
+        flamegraph::from_lines(&mut options, lines.iter().copied(), &mut json).unwrap();
+        serde_json::from_slice::<serde_json::Value>(&json).unwrap()
+    };
+
+    let tree = render(&["main;foo 3", "main;foo;bar 2", "main;baz 5"]);
+    assert_eq!(
+        tree,
+        serde_json::json!({"name": "all", "value": 10, "children": [
+            {"name": "main", "value": 10, "children": [
+                {"name": "baz", "value": 5, "children": []},
+                {"name": "foo", "value": 5, "children": [
+                    {"name": "bar", "value": 2, "children": []},
+                ]},
+            ]},
+        ]})
+    );
+
+    let tree = render(&["main;foo 1 3", "main;bar 4 2"]);
+    assert_eq!(tree["children"][0]["children"][0]["name"], "bar");
+    assert_eq!(tree["children"][0]["children"][0]["delta"], -2);
+    assert_eq!(tree["children"][0]["children"][1]["delta"], 2);
+}
+
+#[test]
+fn flamegraph_build_tree() {
+    let tree = flamegraph::build_tree(vec![
+        "# version: 1",
+        "main;foo 3",
+        "main;foo;bar 2",
+        "main;baz;foo 4",
+        "main 1",
+    ]);
+
+    let names: Vec<_> = tree.iter().map(|node| node.name.as_str()).collect();
+    assert_eq!(names, ["", "main", "baz", "foo", "foo", "bar"]);
+    assert_eq!(tree.root().inclusive, 10);
+    assert_eq!(tree.root().exclusive, 0);
+
+    let main = &tree[1];
+    assert_eq!((main.inclusive, main.exclusive, main.depth), (10, 1, 1));
+    let children: Vec<_> = tree.children(main).map(|node| node.name.as_str()).collect();
+    assert_eq!(children, ["baz", "foo"]);
+    assert_eq!(tree.descendants(main).len(), 4);
+
+    let bar = tree.iter().find(|node| node.name == "bar").unwrap();
+    assert_eq!((bar.inclusive, bar.exclusive), (2, 2));
+    let foo = tree.parent(bar).unwrap();
+    assert_eq!((foo.inclusive, foo.exclusive), (5, 3));
+    let ancestry: Vec<_> = tree.ancestry(bar).iter().map(|node| node.id).collect();
+    assert_eq!(ancestry, [0, 1, 4, 5]);
+
+    // The self samples of each function, summed over the frames it has.
+    let mut foo_self = 0;
+    for node in &tree {
+        if node.name == "foo" {
+            foo_self += node.exclusive;
+        }
+    }
+    assert_eq!(foo_self, 7);
+}
+
+#[test]
+fn flamegraph_color_fn() {
+    let mut options = Options::default();
+    options.no_javascript = true;
+    options.color_fn = Some(ColorFn::new(|frame: &FrameInfo<'_>| {
+        if frame.name.starts_with("team_a::") {
+            Some(Color { r: 1, g: 2, b: 3 })
+        } else if frame.annotation == Some("k") {
+            Some(Color { r: 4, g: 5, b: 6 })
+        } else {
+            None
+        }
+    }));
+    let mut svg = Vec::new();
+    flamegraph::from_lines(
+        &mut options,
+        [
+            "main;team_a::parse 2",
+            "main;team_a::parse;read_[k] 1",
+            "main;team_b::send 3",
+        ],
+        &mut svg,
+    )
+    .unwrap();
+    let svg = String::from_utf8(svg).unwrap();
+
+    assert_eq!(svg.matches(r#"fill="rgb(1,2,3)""#).count(), 1);
+    assert_eq!(svg.matches(r#"fill="rgb(4,5,6)""#).count(), 1);
+    // The other frames still get colors from the palette; black is the color of the text.
+    let frame_colors = svg.matches(r#"fill="rgb("#).count();
+    let text_colors = svg.matches(r#"fill="rgb(0,0,0)""#).count();
+    assert_eq!(frame_colors - text_colors, 5);
+}
+
 fn load_palette_map_file(palette_file: &str) -> PaletteMap {
     let path = Path::new(palette_file);
     PaletteMap::load_from_file_or_empty(&path).unwrap()
@@ -473,6 +917,51 @@ fn flamegraph_unsorted_multiple_input_files() {
     test_flamegraph_multiple_files(input_files, expected_result_file, options).unwrap();
 }
 
+#[test]
+fn flamegraph_multi_input() {
+    let input_files: Vec<PathBuf> = vec![
+        "./tests/data/flamegraph/multiple-inputs/perf-vertx-stacks-01-collapsed-all-unsorted-1.txt"
+            .into(),
+        "./tests/data/flamegraph/multiple-inputs/perf-vertx-stacks-01-collapsed-all-unsorted-2.txt"
+            .into(),
+    ];
+    let render = |multi_input: MultiInput, files: &[PathBuf]| {
+        let mut options = Options::default();
+        options.multi_input = multi_input;
+        options.no_javascript = true;
+        let mut svg = Vec::new();
+        flamegraph::from_files(&mut options, files, &mut svg)
+            .map(|()| String::from_utf8(svg).unwrap())
+    };
+
+    let svg = render(MultiInput::PerFile, &input_files).unwrap();
+    assert!(svg.contains("<title>perf-vertx-stacks-01-collapsed-all-unsorted-1 ("));
+    assert!(svg.contains("<title>perf-vertx-stacks-01-collapsed-all-unsorted-2 ("));
+
+    let svg = render(MultiInput::Differential, &input_files).unwrap();
+    assert!(svg.contains("<title>java ("));
+    assert!(svg.contains("%; +"));
+
+    assert!(render(MultiInput::Differential, &input_files[..1]).is_err());
+}
+
+#[test]
+fn flamegraph_multi_input_per_file_with_the_same_name() {
+    let input_files: Vec<PathBuf> = vec![
+        "./tests/data/flamegraph/base/flames.txt".into(),
+        "./tests/data/flamegraph/flamechart/flames.txt".into(),
+    ];
+    let mut options = Options::default();
+    options.multi_input = MultiInput::PerFile;
+    options.no_javascript = true;
+    let mut svg = Vec::new();
+    flamegraph::from_files(&mut options, &input_files, &mut svg).unwrap();
+    let svg = String::from_utf8(svg).unwrap();
+    // One tower is `flames`, the other is `flames (2)`.
+    assert_eq!(svg.matches("<title>flames (").count(), 2);
+    assert_eq!(svg.matches("<title>flames (2) (").count(), 1);
+}
+
 #[test]
 fn flamegraph_should_prune_narrow_blocks() {
     let input_file = "./tests/data/flamegraph/narrow-blocks/narrow-blocks.txt";
@@ -780,6 +1269,34 @@ fn flamegraph_no_sort_should_return_error_on_unsorted_input() {
     assert!(test_flamegraph(input_file, expected_result_file, options).is_err());
 }
 
+#[test]
+fn flamegraph_unsorted_input_error_should_have_line_number() {
+    let input = "a;b 1\na;c 1\na;b 2\n";
+    let mut options = flamegraph::Options::default();
+    options.no_sort = true;
+
+    let error = flamegraph::from_reader(&mut options, input.as_bytes(), io::sink()).unwrap_err();
+    match inferno::Error::from(error) {
+        inferno::Error::Parse { format, line, .. } => {
+            assert_eq!(format, "folded");
+            assert_eq!(line, Some(3));
+        }
+        e => panic!("expected a parse error, got {:?}", e),
+    }
+}
+
+#[test]
+fn flamegraph_png_to_string_should_be_unsupported() {
+    let mut options = flamegraph::Options::default();
+    options.format = flamegraph::Format::Png;
+
+    let error = flamegraph::from_bytes(&mut options, b"a;b 1\n").unwrap_err();
+    assert!(matches!(
+        inferno::Error::from(error),
+        inferno::Error::UnsupportedFormat(_)
+    ));
+}
+
 #[test]
 fn flamegraph_reversed_stack_ordering() {
     let input_file =
@@ -907,6 +1424,58 @@ fn flamegraph_cli() {
     compare_results(Cursor::new(output.stdout), expected, expected_file);
 }
 
+// The "browser" prints the file it's given, which is the flame graph in a temporary file.
+#[cfg(unix)]
+#[test]
+fn flamegraph_cli_open() {
+    let output = Command::cargo_bin("inferno-flamegraph")
+        .unwrap()
+        .args(["--open", "--format", "json"])
+        .arg("./flamegraph/test/results/perf-vertx-stacks-01-collapsed-all.txt")
+        .env("BROWSER", "cat")
+        .output()
+        .expect("failed to execute process");
+    assert!(output.status.success());
+    let json = String::from_utf8(output.stdout).unwrap();
+    assert!(json.starts_with(r#"{"name":"all""#));
+}
+
+#[test]
+fn flamegraph_cli_watch() {
+    let dir = std::env::temp_dir().join(format!("inferno-flamegraph-watch-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("stacks.folded");
+    let output = dir.join("flamegraph.svg");
+    fs::write(&input, "main;before 1\n").unwrap();
+
+    let mut child = Command::cargo_bin("inferno-flamegraph")
+        .unwrap()
+        .arg("--watch")
+        .arg("--output")
+        .arg(&output)
+        .arg(&input)
+        .spawn()
+        .expect("failed to execute process");
+    let written = |name: &str| {
+        (0..100).any(|_| {
+            if fs::read_to_string(&output).is_ok_and(|svg| svg.contains(name)) {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(100));
+            false
+        })
+    };
+    let first = written("<title>before");
+    fs::write(&input, "main;after 1\nmain;later 2\n").unwrap();
+    let second = written("<title>later");
+    child.kill().unwrap();
+    child.wait().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(first);
+    assert!(second);
+}
+
 #[test]
 fn flamegraph_colors_truncate_right() {
     let input_file = "./flamegraph/test/results/perf-java-stacks-01-collapsed-all.txt";
@@ -946,6 +1515,18 @@ fn flamegraph_base_symbol() {
     test_flamegraph(input_file, expected_result_file, opts).unwrap();
 }
 
+#[test]
+fn flamegraph_compressed_input() {
+    let input_file = "./tests/data/flamegraph/base/flames.txt.gz";
+    let expected_result_file = "./tests/data/flamegraph/base/single-base.svg";
+
+    let mut opts = flamegraph::Options::default();
+    opts.title = flamegraph::defaults::CHART_TITLE.to_owned();
+    opts.base = vec!["Final".to_string()];
+
+    test_flamegraph(input_file, expected_result_file, opts).unwrap();
+}
+
 #[test]
 fn flamegraph_multiple_base_symbol() {
     let input_file = "./tests/data/flamegraph/base/flames.txt";
@@ -965,3 +1546,209 @@ fn flamegraph_austin() {
     let opts = flamegraph::Options::default();
     test_flamegraph(input_file, expected_result_file, opts).unwrap();
 }
+
+fn render_embeddable(embed: &flamegraph::EmbedOptions) -> String {
+    let input = fs::read_to_string("./tests/data/flamegraph/austin/flames.txt").unwrap();
+    let mut options = flamegraph::Options::default();
+    options.hash = true;
+    let mut output = Vec::new();
+    flamegraph::render_embeddable(&mut options, embed, input.lines(), &mut output).unwrap();
+    String::from_utf8(output).unwrap()
+}
+
+#[test]
+fn flamegraph_embeddable() {
+    let mut embed = flamegraph::EmbedOptions::default();
+    embed.id_prefix = "cpu".to_string();
+    let svg = render_embeddable(&embed);
+
+    assert!(svg.starts_with(r#"<svg id="cpu" class="inferno-flamegraph" width="100%" viewBox="#));
+    assert!(svg.ends_with("</svg>"));
+    for id in [
+        "title",
+        "details",
+        "unzoom",
+        "search",
+        "matched",
+        "frames",
+        "background",
+    ] {
+        assert!(
+            svg.contains(&format!(r#"id="cpu-{}""#, id)),
+            "no cpu-{}",
+            id
+        );
+        assert!(
+            !svg.contains(&format!(r#"id="{}""#, id)),
+            "unprefixed {}",
+            id
+        );
+    }
+    assert!(svg.contains("#cpu-frames > *:hover"));
+    assert!(svg.contains("#cpu .hide"));
+    assert!(!svg.contains("<?xml"));
+    assert!(!svg.contains("<script"));
+}
+
+#[test]
+fn flamegraph_embeddable_javascript() {
+    let mut embed = flamegraph::EmbedOptions::default();
+    embed.javascript = true;
+    let svg = render_embeddable(&embed);
+
+    let frames = svg.find(r#"id="flamegraph-frames""#).unwrap();
+    let script = svg.find("<script").unwrap();
+    assert!(frames < script);
+    assert!(svg.contains("init({ target: document.getElementById('flamegraph') });"));
+    assert!(!svg.contains("onload"));
+}
+
+#[test]
+fn flamegraph_header() {
+    let input = fs::read_to_string("./tests/data/flamegraph/header/allocations.txt").unwrap();
+    let mut options = Options::default();
+    let mut svg = Vec::new();
+    flamegraph::from_lines(&mut options, input.lines(), &mut svg).unwrap();
+    let svg = String::from_utf8(svg).unwrap();
+
+    assert!(svg.contains("run (5 faults, 62.50%)"));
+    assert!(svg.contains("page-faults, 2.50 seconds"));
+    // The labels are only filled in for this flame graph.
+    assert_eq!(options.count_name, "samples");
+    assert_eq!(options.subtitle, None);
+
+    let mut options = Options::default();
+    options.count_name = "faults/s".to_string();
+    options.subtitle = Some("Demo".to_string());
+    let mut svg = Vec::new();
+    flamegraph::from_lines(&mut options, input.lines(), &mut svg).unwrap();
+    let svg = String::from_utf8(svg).unwrap();
+    assert!(svg.contains("run (5 faults/s, 62.50%)"));
+    assert!(!svg.contains("page-faults"));
+}
+
+#[test]
+fn flamegraph_category_band() {
+    let input = fs::read_to_string("./tests/data/flamegraph/category/stacks.txt").unwrap();
+    let mut options = Options::default();
+    options.category_band = true;
+    options.colors = Palette::Multi(MultiPalette::Category);
+    let mut svg = Vec::new();
+    flamegraph::from_lines(&mut options, input.lines(), &mut svg).unwrap();
+    let svg = String::from_utf8(svg).unwrap();
+
+    assert!(svg.contains("id=\"categories\""));
+    assert!(svg.contains("<title>gc (3 samples, 25.00%)</title>"));
+    assert!(svg.contains("<title>app (6 samples, 50.00%)</title>"));
+    assert!(!svg.contains("_[c:"));
+}
+
+#[test]
+fn flamegraph_streaming() {
+    let inputs = [
+        "./tests/data/flamegraph/unsorted-input/perf-vertx-stacks-01-collapsed-all-unsorted.txt",
+        "./tests/data/flamegraph/differential/perf-cycles-instructions-01-collapsed-all-diff.txt",
+        "./tests/data/flamegraph/header/allocations.txt",
+    ];
+    for input in inputs {
+        let folded = fs::read(input).unwrap();
+        let mut options = Options::default();
+        options.hash = true;
+        let mut expected = Vec::new();
+        flamegraph::from_reader(&mut options, &folded[..], &mut expected).unwrap();
+        let expected = String::from_utf8(expected).unwrap();
+
+        // Sort the stacks in memory, and in runs of a few lines in temporary files.
+        for memory_limit in [usize::MAX, 256] {
+            let mut stream = flamegraph::StreamOptions::default();
+            stream.memory_limit = memory_limit;
+            let mut svg = Vec::new();
+            flamegraph::from_reader_streaming(&mut options, &stream, &folded[..], &mut svg)
+                .unwrap();
+            assert_eq!(String::from_utf8(svg).unwrap(), expected, "{}", input);
+        }
+    }
+}
+
+#[test]
+fn flamegraph_streaming_unsupported_options() {
+    let stream = flamegraph::StreamOptions::default();
+    let mut options = Options::default();
+    options.flame_chart = true;
+    let error =
+        flamegraph::from_reader_streaming(&mut options, &stream, &b"a;b 1\n"[..], io::sink())
+            .unwrap_err();
+    assert!(matches!(
+        inferno::Error::from(error),
+        inferno::Error::InvalidOptions(_)
+    ));
+
+    let mut options = Options::default();
+    options.format = Format::Html;
+    let error =
+        flamegraph::from_reader_streaming(&mut options, &stream, &b"a;b 1\n"[..], io::sink())
+            .unwrap_err();
+    assert!(matches!(
+        inferno::Error::from(error),
+        inferno::Error::UnsupportedFormat(_)
+    ));
+}
+
+#[test]
+fn flamegraph_embed_folded() {
+    let input = fs::read_to_string("./tests/data/flamegraph/header/allocations.txt").unwrap();
+    let folded: String = input
+        .lines()
+        .map(|line| line.trim().to_string() + "\n")
+        .collect();
+
+    for encoding in [FoldedEncoding::Gzip, FoldedEncoding::Text] {
+        for pretty_xml in [false, true] {
+            let mut options = Options::default();
+            options.embed_folded = Some(encoding);
+            options.pretty_xml = pretty_xml;
+            let mut svg = Vec::new();
+            flamegraph::from_reader(&mut options, input.as_bytes(), &mut svg).unwrap();
+            assert!(String::from_utf8_lossy(&svg).contains(r#"<metadata id="folded">"#));
+
+            let mut extracted = Vec::new();
+            flamegraph::extract_folded(&svg[..], &mut extracted).unwrap();
+            assert_eq!(String::from_utf8(extracted).unwrap(), folded);
+        }
+    }
+
+    // Flame graphs without embedded stacks have nothing to extract.
+    let svg = flamegraph::from_bytes(&mut Options::default(), input.as_bytes()).unwrap();
+    assert!(!svg.contains("<metadata"));
+    let error = flamegraph::extract_folded(svg.as_bytes(), io::sink()).unwrap_err();
+    assert!(matches!(
+        inferno::Error::from(error),
+        inferno::Error::Parse { .. }
+    ));
+}
+
+#[test]
+fn flamegraph_embed_folded_cli() {
+    let input_file = "./tests/data/flamegraph/header/allocations.txt";
+    let output = Command::cargo_bin("inferno-flamegraph")
+        .unwrap()
+        .arg("--embed-folded")
+        .arg(input_file)
+        .output()
+        .expect("failed to execute process");
+    assert!(output.status.success());
+
+    let mut child = Command::cargo_bin("inferno-extract-folded")
+        .unwrap()
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Failed to spawn child process");
+    let stdin = child.stdin.as_mut().expect("Failed to open stdin");
+    io::Write::write_all(stdin, &output.stdout).unwrap();
+    let extracted = child.wait_with_output().expect("Failed to read stdout");
+    assert!(extracted.status.success());
+
+    let input = fs::read_to_string(input_file).unwrap();
+    assert_eq!(String::from_utf8(extracted.stdout).unwrap(), input);
+}
@@ -0,0 +1,48 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::process::Command;
+
+use assert_cmd::cargo::CommandCargoExt;
+use inferno::heatmap::{self, Options};
+
+fn heatmap(options: &Options) -> String {
+    let mut output = Vec::new();
+    heatmap::from_reader(
+        options,
+        BufReader::new(File::open("./tests/data/heatmap/perf-timestamps.txt").unwrap()),
+        &mut output,
+    )
+    .unwrap();
+    String::from_utf8(output).unwrap()
+}
+
+#[test]
+fn heatmap_cells() {
+    let mut options = Options::default();
+    options.rows = 10;
+    let svg = heatmap(&options);
+
+    // Three seconds of ten rows each.
+    assert_eq!(svg.matches(r#"<rect class="cell""#).count(), 30);
+    assert!(svg.contains(r#"data-from="100.000000" data-to="100.100000"><title>0.000s: 1 samples"#));
+    assert!(svg.contains(r#"data-from="100.500000" data-to="100.600000"><title>0.500s: 1 samples"#));
+    assert!(svg.contains(r#"data-from="101.200000" data-to="101.300000"><title>1.200s: 2 samples"#));
+    assert!(svg.contains(r#"data-from="102.900000" data-to="103.000000"><title>2.900s: 1 samples"#));
+    assert_eq!(svg.matches(": 0 samples").count(), 26);
+}
+
+#[test]
+fn heatmap_cli() {
+    let output = Command::cargo_bin("inferno-heatmap")
+        .unwrap()
+        .arg("--title")
+        .arg("Timeline <app>")
+        .arg("./tests/data/heatmap/perf-timestamps.txt")
+        .output()
+        .expect("failed to execute process");
+    assert!(output.status.success());
+    let svg = String::from_utf8(output.stdout).unwrap();
+    assert!(svg.starts_with("<?xml"));
+    assert!(svg.contains("Timeline &lt;app&gt;"));
+    assert_eq!(svg.matches(r#"<rect class="cell""#).count(), 150);
+}
@@ -0,0 +1,54 @@
+use std::fs::File;
+use std::process::Command;
+
+use assert_cmd::cargo::CommandCargoExt;
+use inferno::flamegraph::{self, color::MultiPalette, Palette};
+use inferno::hotcold::{self, Options};
+use pretty_assertions::assert_eq;
+
+#[test]
+fn hotcold_merges_profiles() {
+    let mut output = Vec::new();
+    hotcold::from_readers(
+        Options::default(),
+        File::open("./tests/data/hotcold/on-cpu.txt").unwrap(),
+        File::open("./tests/data/hotcold/off-cpu.txt").unwrap(),
+        &mut output,
+    )
+    .unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        std::fs::read_to_string("./tests/data/hotcold/results/hotcold.txt").unwrap()
+    );
+}
+
+#[test]
+fn hotcold_palette() {
+    let mut options = flamegraph::Options::default();
+    options.colors = Palette::Multi(MultiPalette::HotCold);
+    options.hash = true;
+    let mut svg = Vec::new();
+    flamegraph::from_files(
+        &mut options,
+        &["./tests/data/hotcold/results/hotcold.txt".into()],
+        &mut svg,
+    )
+    .unwrap();
+    let svg = String::from_utf8(svg).unwrap();
+    assert!(svg.contains("schedule_[o]"));
+}
+
+#[test]
+fn hotcold_cli() {
+    let output = Command::cargo_bin("inferno-hotcold")
+        .unwrap()
+        .arg("./tests/data/hotcold/on-cpu.txt")
+        .arg("./tests/data/hotcold/off-cpu.txt")
+        .output()
+        .expect("failed to execute process");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        std::fs::read_to_string("./tests/data/hotcold/results/hotcold.txt").unwrap()
+    );
+}
@@ -0,0 +1,142 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::process::Command;
+
+use assert_cmd::cargo::CommandCargoExt;
+use inferno::collapse::guess;
+use inferno::flamegraph;
+use pretty_assertions::assert_eq;
+
+#[test]
+fn inferno_runs_tools() {
+    let input = "./tests/data/merge-folded/host1.txt";
+    let output = Command::cargo_bin("inferno")
+        .unwrap()
+        .args(["merge-folded", "--prefix", "host1", input])
+        .output()
+        .expect("failed to execute process");
+    let expected = Command::cargo_bin("inferno-merge-folded")
+        .unwrap()
+        .args(["--prefix", "host1", input])
+        .output()
+        .expect("failed to execute process");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        String::from_utf8(expected.stdout).unwrap()
+    );
+
+    let output = Command::cargo_bin("inferno")
+        .unwrap()
+        .args(["collapse", "perf", "--no-such-flag"])
+        .output()
+        .expect("failed to execute process");
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn inferno_render() {
+    let output = Command::cargo_bin("inferno")
+        .unwrap()
+        .args(["render", "--title", "Rendered"])
+        .arg("./tests/data/collapse-perf/timestamps.txt")
+        .output()
+        .expect("failed to execute process");
+    assert!(output.status.success());
+    let svg = String::from_utf8(output.stdout).unwrap();
+    assert!(svg.starts_with("<?xml"));
+    assert!(svg.contains(">Rendered</text>"));
+    assert!(svg.contains("<title>parse (1 "));
+}
+
+#[test]
+fn inferno_collapse_diff_and_flamegraph() {
+    let input = "./tests/data/collapse-perf/single-event.txt.gz";
+    let output = Command::cargo_bin("inferno")
+        .unwrap()
+        .args(["collapse", "perf", input])
+        .output()
+        .expect("failed to execute process");
+    assert!(output.status.success());
+    let expected =
+        std::fs::read_to_string("./tests/data/collapse-perf/results/single-event-collapsed.txt")
+            .unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), expected);
+
+    let output = Command::cargo_bin("inferno")
+        .unwrap()
+        .args(["diff", "--normalize"])
+        .arg("./tests/data/diff-folded/before.txt.gz")
+        .arg("./tests/data/diff-folded/after.txt")
+        .output()
+        .expect("failed to execute process");
+    assert!(output.status.success());
+    // The stacks of a differential aren't written in any particular order.
+    let sorted = |s: &str| {
+        let mut lines: Vec<_> = s.lines().map(str::to_string).collect();
+        lines.sort();
+        lines
+    };
+    let expected =
+        std::fs::read_to_string("./tests/data/diff-folded/results/normalize.txt").unwrap();
+    assert_eq!(
+        sorted(&String::from_utf8(output.stdout).unwrap()),
+        sorted(&expected)
+    );
+
+    let output = Command::cargo_bin("inferno")
+        .unwrap()
+        .args(["flamegraph", "--title", "Plotted", "--colors", "java"])
+        .arg("./tests/data/flamegraph/base/flames.txt.gz")
+        .output()
+        .expect("failed to execute process");
+    assert!(output.status.success());
+    let svg = String::from_utf8(output.stdout).unwrap();
+    assert!(svg.starts_with("<?xml"));
+    assert!(svg.contains(">Plotted</text>"));
+}
+
+#[test]
+fn inferno_render_compressed() {
+    let output = Command::cargo_bin("inferno")
+        .unwrap()
+        .arg("render")
+        .arg("./tests/data/collapse-perf/single-event.txt.gz")
+        .output()
+        .expect("failed to execute process");
+    assert!(output.status.success());
+    let svg = String::from_utf8(output.stdout).unwrap();
+    assert!(svg.starts_with("<?xml"));
+    assert!(svg.contains("<title>boa_cli ("));
+}
+
+#[test]
+fn render_bytes_from_profiler_output() {
+    let input = std::fs::read("./tests/data/collapse-perf/timestamps.txt").unwrap();
+    let mut options = flamegraph::Options::default();
+    let svg = inferno::render_bytes(&input, guess::Options::default(), &mut options).unwrap();
+    assert!(svg.starts_with("<?xml"));
+    assert!(svg.contains("<title>parse (1 "));
+
+    options.format = flamegraph::Format::Png;
+    assert!(inferno::render_bytes(&input, guess::Options::default(), &mut options).is_err());
+}
+
+#[test]
+fn render_from_profiler_output() {
+    let input = File::open("./tests/data/collapse-perf/timestamps.txt").unwrap();
+    let mut options = flamegraph::Options::default();
+    options.title = "Rendered".to_string();
+    let mut svg = Vec::new();
+    inferno::render(
+        BufReader::new(input),
+        &mut svg,
+        guess::Options::default(),
+        &mut options,
+    )
+    .unwrap();
+    let svg = String::from_utf8(svg).unwrap();
+    assert!(svg.starts_with("<?xml"));
+    assert!(svg.contains(">Rendered</text>"));
+    assert!(svg.contains("<title>parse (1 "));
+}
@@ -0,0 +1,50 @@
+use std::fs::File;
+use std::process::Command;
+
+use assert_cmd::cargo::CommandCargoExt;
+use inferno::leak::{self, Options, Weight};
+use pretty_assertions::assert_eq;
+
+fn test_leak(weight: Weight, expected_file: &str) {
+    let mut options = Options::default();
+    options.weight = weight;
+    let mut output = Vec::new();
+    leak::from_reader(
+        options,
+        File::open("./tests/data/leak/allocations.txt").unwrap(),
+        &mut output,
+    )
+    .unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        std::fs::read_to_string(expected_file).unwrap()
+    );
+}
+
+#[test]
+fn leak_outstanding() {
+    test_leak(
+        Weight::Outstanding,
+        "./tests/data/leak/results/outstanding.txt",
+    );
+}
+
+#[test]
+fn leak_allocated() {
+    test_leak(Weight::Allocated, "./tests/data/leak/results/allocated.txt");
+}
+
+#[test]
+fn flamegraph_leaks_cli() {
+    let output = Command::cargo_bin("inferno-flamegraph")
+        .unwrap()
+        .arg("--leaks")
+        .arg("outstanding")
+        .arg("./tests/data/leak/allocations.txt")
+        .output()
+        .expect("failed to execute process");
+    assert!(output.status.success());
+    let svg = String::from_utf8(output.stdout).unwrap();
+    assert!(svg.contains("cache::insert (49,152 bytes"));
+    assert!(!svg.contains("load_config"));
+}
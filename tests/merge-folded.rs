@@ -0,0 +1,81 @@
+use std::fs::File;
+use std::process::Command;
+
+use assert_cmd::cargo::CommandCargoExt;
+use inferno::merge::{self, Input};
+use pretty_assertions::assert_eq;
+
+#[test]
+fn merge_folded_sums_counts() {
+    let mut output = Vec::new();
+    merge::from_readers(
+        vec![
+            (
+                File::open("./tests/data/merge-folded/host1.txt").unwrap(),
+                Input::default(),
+            ),
+            (
+                File::open("./tests/data/merge-folded/host2.txt").unwrap(),
+                Input::default(),
+            ),
+        ],
+        &mut output,
+    )
+    .unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        std::fs::read_to_string("./tests/data/merge-folded/results/merged.txt").unwrap()
+    );
+}
+
+#[test]
+fn merge_folded_scale_and_prefix() {
+    let mut host1 = Input::default();
+    host1.prefix = Some("host1".to_string());
+    let mut host2 = Input::default();
+    host2.prefix = Some("host2".to_string());
+    host2.scale = 2.0;
+
+    let mut output = Vec::new();
+    merge::from_files(
+        &[
+            ("./tests/data/merge-folded/host1.txt", host1),
+            ("./tests/data/merge-folded/host2.txt", host2),
+        ],
+        &mut output,
+    )
+    .unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        std::fs::read_to_string("./tests/data/merge-folded/results/prefixed.txt").unwrap()
+    );
+}
+
+#[test]
+fn merge_folded_cli() {
+    let output = Command::cargo_bin("inferno-merge-folded")
+        .unwrap()
+        .args(["--prefix", "host1", "--prefix", "host2"])
+        .args(["--scale", "1", "--scale", "2"])
+        .arg("./tests/data/merge-folded/host1.txt")
+        .arg("./tests/data/merge-folded/host2.txt")
+        .output()
+        .expect("failed to execute process");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        std::fs::read_to_string("./tests/data/merge-folded/results/prefixed.txt").unwrap()
+    );
+}
+
+#[test]
+fn merge_folded_cli_mismatched_scales() {
+    let output = Command::cargo_bin("inferno-merge-folded")
+        .unwrap()
+        .args(["--scale", "2"])
+        .arg("./tests/data/merge-folded/host1.txt")
+        .arg("./tests/data/merge-folded/host2.txt")
+        .output()
+        .expect("failed to execute process");
+    assert!(!output.status.success());
+}
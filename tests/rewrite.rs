@@ -0,0 +1,113 @@
+use std::fs::File;
+use std::process::Command;
+
+use assert_cmd::cargo::CommandCargoExt;
+use inferno::rewrite::{self, Rules};
+use pretty_assertions::assert_eq;
+
+#[test]
+fn rewrite_rules() {
+    let rules = Rules::from_file("./tests/data/rewrite/rules.txt").unwrap();
+
+    let mut output = Vec::new();
+    rewrite::from_reader(
+        &rules,
+        File::open("./tests/data/rewrite/stacks.txt").unwrap(),
+        &mut output,
+    )
+    .unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        std::fs::read_to_string("./tests/data/rewrite/results/stacks.txt").unwrap()
+    );
+}
+
+#[test]
+fn rewrite_rules_include_exclude() {
+    let rules = Rules::from_file("./tests/data/rewrite/filter.txt").unwrap();
+
+    let mut output = Vec::new();
+    rewrite::from_reader(
+        &rules,
+        File::open("./tests/data/rewrite/filter-stacks.txt").unwrap(),
+        &mut output,
+    )
+    .unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        std::fs::read_to_string("./tests/data/rewrite/results/filter-stacks.txt").unwrap()
+    );
+}
+
+#[test]
+fn rewrite_rules_root() {
+    let rules = Rules::from_file("./tests/data/rewrite/root.txt").unwrap();
+
+    let mut output = Vec::new();
+    rewrite::from_reader(
+        &rules,
+        File::open("./tests/data/rewrite/root-stacks.txt").unwrap(),
+        &mut output,
+    )
+    .unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        std::fs::read_to_string("./tests/data/rewrite/results/root-stacks.txt").unwrap()
+    );
+}
+
+#[test]
+fn rewrite_rules_annotate() {
+    let rules = Rules::from_file("./tests/data/rewrite/annotate.txt").unwrap();
+
+    let mut output = Vec::new();
+    rewrite::from_reader(
+        &rules,
+        File::open("./tests/data/rewrite/annotate-stacks.txt").unwrap(),
+        &mut output,
+    )
+    .unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        std::fs::read_to_string("./tests/data/rewrite/results/annotate-stacks.txt").unwrap()
+    );
+}
+
+#[test]
+fn rewrite_rules_errors_have_line_numbers() {
+    let error = Rules::parse("# comment\ndrop ^ok\nexplode ^foo").unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    assert!(error.to_string().contains("line 3"), "{}", error);
+}
+
+#[test]
+fn flamegraph_rewrite_rules_cli() {
+    let output = Command::cargo_bin("inferno-flamegraph")
+        .unwrap()
+        .arg("--rewrite-rules")
+        .arg("./tests/data/rewrite/rules.txt")
+        .arg("./tests/data/rewrite/stacks.txt")
+        .output()
+        .expect("failed to execute process");
+    assert!(output.status.success());
+    let svg = String::from_utf8(output.stdout).unwrap();
+    assert!(svg.contains("tokio runtime"));
+    assert!(svg.contains("[json]"));
+    assert!(!svg.contains("lang_start"));
+}
+
+#[test]
+fn flamegraph_root_cli() {
+    let output = Command::cargo_bin("inferno-flamegraph")
+        .unwrap()
+        .arg("--root")
+        .arg("^tokio::runtime::task::raw::poll$")
+        .arg("./tests/data/rewrite/root-stacks.txt")
+        .output()
+        .expect("failed to execute process");
+    assert!(output.status.success());
+    let svg = String::from_utf8(output.stdout).unwrap();
+    assert!(svg.contains("app::render"));
+    assert!(!svg.contains("block_on"));
+    assert!(!svg.contains("tokio::runtime::park"));
+}
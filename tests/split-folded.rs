@@ -0,0 +1,102 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io;
+use std::process::Command;
+use std::rc::Rc;
+
+use assert_cmd::cargo::CommandCargoExt;
+use inferno::split::{self, Options};
+use pretty_assertions::assert_eq;
+
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl io::Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn split(options: Options) -> Vec<(String, String)> {
+    let mut outputs = BTreeMap::new();
+    split::from_reader(
+        options,
+        File::open("./tests/data/split-folded/stacks.txt").unwrap(),
+        |root| {
+            let buffer = SharedBuffer::default();
+            outputs.insert(root.to_string(), buffer.clone());
+            Ok(buffer)
+        },
+    )
+    .unwrap();
+    outputs
+        .into_iter()
+        .map(|(root, buffer)| (root, String::from_utf8(buffer.0.take()).unwrap()))
+        .collect()
+}
+
+#[test]
+fn split_by_process() {
+    assert_eq!(
+        split(Options::default()),
+        vec![
+            (
+                "app-1000".to_string(),
+                "app-1000;main;worker 1000/1002;work 5\n\
+                 app-1000;main;run 2\n\
+                 app-1000;worker 1000/1002;idle 1\n"
+                    .to_string()
+            ),
+            ("db-2000".to_string(), "db-2000;query 7\n".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn split_by_thread_without_roots() {
+    let mut options = Options::default();
+    options.depth = 2;
+    options.strip_roots = true;
+    assert_eq!(
+        split(options),
+        vec![
+            (
+                "app-1000;main".to_string(),
+                "worker 1000/1002;work 5\nrun 2\n".to_string()
+            ),
+            (
+                "app-1000;worker 1000/1002".to_string(),
+                "idle 1\n".to_string()
+            ),
+            ("db-2000;query".to_string(), String::new()),
+        ]
+    );
+}
+
+#[test]
+fn split_folded_cli() {
+    let dir = std::env::temp_dir().join(format!("inferno-split-folded-{}", std::process::id()));
+    let output = Command::cargo_bin("inferno-split-folded")
+        .unwrap()
+        .arg("--output-dir")
+        .arg(&dir)
+        .arg("--svg")
+        .arg("./tests/data/split-folded/stacks.txt")
+        .output()
+        .expect("failed to execute process");
+    assert!(output.status.success());
+
+    assert_eq!(
+        fs::read_to_string(dir.join("db-2000.folded")).unwrap(),
+        "db-2000;query 7\n"
+    );
+    assert!(fs::read_to_string(dir.join("app-1000.svg"))
+        .unwrap()
+        .contains("worker 1000/1002"));
+    fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,77 @@
+#![cfg(feature = "symbolize")]
+
+use std::fs::File;
+use std::process::Command;
+
+use assert_cmd::cargo::CommandCargoExt;
+use inferno::symbolize::{self, Options};
+use pretty_assertions::assert_eq;
+
+#[test]
+fn symbolize_leaves_unresolved_frames() {
+    let input_file = "./tests/data/symbolize/unresolved.txt";
+    let mut options = Options::default();
+    options.binary_dirs = vec!["./tests/data/symbolize".into()];
+    options.executable = Some("./tests/data/symbolize/missing".into());
+
+    let mut output = Vec::new();
+    symbolize::from_reader(&options, File::open(input_file).unwrap(), &mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        std::fs::read_to_string(input_file).unwrap()
+    );
+}
+
+#[test]
+fn symbolize_cli() {
+    let input_file = "./tests/data/symbolize/unresolved.txt";
+    let output = Command::cargo_bin("inferno-symbolize")
+        .unwrap()
+        .arg("--binary-dir")
+        .arg("./tests/data/symbolize")
+        .arg(input_file)
+        .output()
+        .expect("failed to execute process");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        std::fs::read_to_string(input_file).unwrap()
+    );
+}
+
+#[cfg(feature = "sourcemap")]
+#[test]
+fn symbolize_source_maps() {
+    let mut options = Options::default();
+    options.source_maps = vec!["./tests/data/symbolize/app.min.js.map".into()];
+
+    let mut output = Vec::new();
+    symbolize::from_reader(
+        &options,
+        File::open("./tests/data/symbolize/minified.txt").unwrap(),
+        &mut output,
+    )
+    .unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        std::fs::read_to_string("./tests/data/symbolize/results/minified.txt").unwrap()
+    );
+}
+
+#[test]
+fn symbolize_breakpad_symbols() {
+    let mut options = Options::default();
+    options.breakpad_symbols = vec!["./tests/data/symbolize/breakpad".into()];
+
+    let mut output = Vec::new();
+    symbolize::from_reader(
+        &options,
+        File::open("./tests/data/symbolize/breakpad.txt").unwrap(),
+        &mut output,
+    )
+    .unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        std::fs::read_to_string("./tests/data/symbolize/results/breakpad.txt").unwrap()
+    );
+}